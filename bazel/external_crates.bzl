@@ -184,6 +184,9 @@ def external_crates_repository(name, static_openssl, cargo_lockfile, lockfile):
             "bitflags": crate.spec(
                 version = "^1.2.1",
             ),
+            "brotli": crate.spec(
+                version = "^3.3.4",
+            ),
             "bs58": crate.spec(
                 version = "0.4.0",
             ),
@@ -1253,6 +1256,9 @@ def external_crates_repository(name, static_openssl, cargo_lockfile, lockfile):
             "x509-parser": crate.spec(
                 version = "^0.12.0",
             ),
+            "xz2": crate.spec(
+                version = "^0.1.7",
+            ),
             "yansi": crate.spec(
                 version = "^0.5.0",
             ),
@@ -1262,6 +1268,12 @@ def external_crates_repository(name, static_openssl, cargo_lockfile, lockfile):
                     "zeroize_derive",
                 ],
             ),
+            "zstd": crate.spec(
+                version = "^0.12.1",
+                features = [
+                    "zstdmt",
+                ],
+            ),
         },
         splicing_config = splicing_config(
             resolver_version = "2",
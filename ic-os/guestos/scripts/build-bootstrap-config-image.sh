@@ -111,6 +111,13 @@ options may be specified:
     The overrides struct (report length, sampling interval) for the onchain observability config. To be used by system tests
 
     Be sure to properly quote the string.
+
+  --replica_config_overrides file
+    A file of overrides of the replica config, one environment variable
+    of the form IC_<SECTION>__<FIELD>=<value> per line, e.g.:
+      IC_HTTP_HANDLER__REQUEST_TIMEOUT_SECONDS=600
+    The variables are set in the environment of the replica, overriding
+    the values of the generated ic.json5. To be used by system tests only.
 EOF
 }
 
@@ -131,6 +138,7 @@ function build_ic_bootstrap_tar() {
     local MALICIOUS_BEHAVIOR
     local BITCOIND_ADDR
     local ONCHAIN_OBSERVABILITY_OVERRIDES
+    local REPLICA_CONFIG_OVERRIDES
 
     while true; do
         if [ $# == 0 ]; then
@@ -194,6 +202,9 @@ function build_ic_bootstrap_tar() {
             --onchain_observability_overrides)
                 ONCHAIN_OBSERVABILITY_OVERRIDES="$2"
                 ;;
+            --replica_config_overrides)
+                REPLICA_CONFIG_OVERRIDES="$2"
+                ;;
             *)
                 echo "Unrecognized option: $1"
                 usage
@@ -248,6 +259,9 @@ EOF
     if [ "${ONCHAIN_OBSERVABILITY_OVERRIDES}" != "" ]; then
         echo ${ONCHAIN_OBSERVABILITY_OVERRIDES} >"${BOOTSTRAP_TMPDIR}/onchain_observability_overrides.json"
     fi
+    if [ "${REPLICA_CONFIG_OVERRIDES}" != "" ]; then
+        cp "${REPLICA_CONFIG_OVERRIDES}" "${BOOTSTRAP_TMPDIR}/replica_config_overrides.env"
+    fi
     if [ "${IC_CRYPTO}" != "" ]; then
         cp -r "${IC_CRYPTO}" "${BOOTSTRAP_TMPDIR}/ic_crypto"
     fi
@@ -14,7 +14,7 @@
 //! no possibility to inject purging (or any other deletion) of artifacts
 //! between the pool update and the backup.
 
-use ic_config::artifact_pool::BACKUP_GROUP_SIZE;
+use ic_backup_spool::{VersionSpool, CUP_FILE_NAME};
 use ic_interfaces::{
     consensus_pool::{ConsensusPool, HeightRange},
     time_source::TimeSource,
@@ -630,14 +630,12 @@ impl BackupArtifact {
             ),
             RandomTape(artifact) => (artifact.height(), "random_tape.bin".to_string()),
             RandomBeacon(artifact) => (artifact.height(), "random_beacon.bin".to_string()),
-            CatchUpPackage(artifact) => (artifact.height(), "catch_up_package.bin".to_string()),
+            CatchUpPackage(artifact) => (artifact.height(), CUP_FILE_NAME.to_string()),
         };
         // We group heights by directories to avoid running into any kind of unexpected
         // FS inode limitations. Each group directory will contain at most
-        // `BACKUP_GROUP_SIZE` heights.
-        let group_key = (height.get() / BACKUP_GROUP_SIZE) * BACKUP_GROUP_SIZE;
-        let path_with_height = path.join(group_key.to_string()).join(height.to_string());
-        (path_with_height, file_name)
+        // `ic_backup_spool::BUCKET_SIZE` heights.
+        (VersionSpool::new(path).height_dir(height), file_name)
     }
 }
 
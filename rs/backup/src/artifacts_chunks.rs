@@ -0,0 +1,159 @@
+use crate::compression::unpack;
+use ic_backup_spool::{bucket, VersionSpool};
+use ic_http_utils::file_downloader::compute_sha256_hex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{copy, create_dir_all, read_dir, rename, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const CHUNKS_MANIFEST_FILE: &str = "chunks_manifest.json";
+
+/// A bundle in the cold storage holding the artifacts of a range of heights
+/// of one replica version. The heights are packed by whole buckets, in the
+/// layout of the spool.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArtifactsChunk {
+    pub replica_version: String,
+    pub first_height: u64,
+    pub last_height: u64,
+    /// The name of the bundle in the artifacts directory of the cold storage.
+    pub file_name: String,
+    /// SHA-256 of the bundle.
+    pub checksum: String,
+}
+
+/// Maps the height ranges of a subnet to the chunks holding their artifacts,
+/// so that the artifacts of a height can be restored without the rest of
+/// the replica version. Stored next to the bundles in the cold storage.
+/// Bundles of whole replica versions, packed before chunking was configured,
+/// are not listed.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunksManifest {
+    pub chunks: Vec<ArtifactsChunk>,
+}
+
+impl ChunksManifest {
+    fn path(artifacts_dir: &Path) -> PathBuf {
+        artifacts_dir.join(CHUNKS_MANIFEST_FILE)
+    }
+
+    /// Loads the manifest, which is empty if the file doesn't exist.
+    pub fn load(artifacts_dir: &Path) -> Result<Self, String> {
+        let path = Self::path(artifacts_dir);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let file = File::open(&path)
+            .map_err(|err| format!("Error opening chunks manifest {:?}: {:?}", path, err))?;
+        serde_json::from_reader(file)
+            .map_err(|err| format!("Error parsing chunks manifest {:?}: {:?}", path, err))
+    }
+
+    /// Saves the manifest, replacing the file atomically so that a restore
+    /// never reads a partial one.
+    pub fn save(&self, artifacts_dir: &Path) -> Result<(), String> {
+        let path = Self::path(artifacts_dir);
+        let tmp_path = path.with_extension("json.tmp");
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|err| format!("Error serializing chunks manifest: {:?}", err))?;
+        let mut file = File::create(&tmp_path)
+            .map_err(|err| format!("Error creating chunks manifest: {:?}", err))?;
+        file.write_all(json.as_bytes())
+            .and_then(|_| file.sync_all())
+            .map_err(|err| format!("Error writing chunks manifest: {:?}", err))?;
+        rename(&tmp_path, &path)
+            .map_err(|err| format!("Error replacing chunks manifest {:?}: {:?}", path, err))
+    }
+
+    /// The chunk holding the artifacts at `height`.
+    pub fn find(&self, height: u64) -> Option<&ArtifactsChunk> {
+        self.chunks
+            .iter()
+            .find(|chunk| chunk.first_height <= height && height <= chunk.last_height)
+    }
+}
+
+/// Consecutive buckets of a replica version's spool, packed into one chunk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkPlan {
+    pub buckets: Vec<u64>,
+    pub first_height: u64,
+    pub last_height: u64,
+}
+
+/// Groups the buckets of `spool` into chunks of at least `target_size_bytes`
+/// (uncompressed), except for the last one. A bucket is never split.
+pub fn plan_chunks(spool: &VersionSpool, target_size_bytes: u64) -> io::Result<Vec<ChunkPlan>> {
+    let mut bucket_heights = BTreeMap::new();
+    for height in spool.heights()? {
+        let (first, last) = bucket_heights
+            .entry(bucket(height))
+            .or_insert((height.get(), height.get()));
+        *first = (*first).min(height.get());
+        *last = (*last).max(height.get());
+    }
+
+    let mut chunks = Vec::new();
+    let mut current: Option<ChunkPlan> = None;
+    let mut current_size_bytes = 0;
+    for (bucket, (first_height, last_height)) in bucket_heights {
+        let chunk = current.get_or_insert_with(|| ChunkPlan {
+            buckets: Vec::new(),
+            first_height,
+            last_height,
+        });
+        chunk.buckets.push(bucket);
+        chunk.last_height = last_height;
+        current_size_bytes += dir_size(&spool.path().join(bucket.to_string()))?;
+        if current_size_bytes >= target_size_bytes {
+            chunks.extend(current.take());
+            current_size_bytes = 0;
+        }
+    }
+    chunks.extend(current);
+    Ok(chunks)
+}
+
+fn dir_size(dir: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
+
+/// Copies the bundle of `chunk` from `artifacts_dir` to `download_dir`,
+/// checks it against its checksum and unpacks it into `spool_dir`, the spool
+/// of the subnet.
+pub fn restore_chunk(
+    artifacts_dir: &Path,
+    chunk: &ArtifactsChunk,
+    download_dir: &Path,
+    spool_dir: &Path,
+) -> Result<(), String> {
+    create_dir_all(download_dir)
+        .map_err(|err| format!("Error creating {:?}: {:?}", download_dir, err))?;
+    create_dir_all(spool_dir)
+        .map_err(|err| format!("Error creating {:?}: {:?}", spool_dir, err))?;
+    let bundle = artifacts_dir.join(&chunk.file_name);
+    let downloaded = download_dir.join(&chunk.file_name);
+    copy(&bundle, &downloaded).map_err(|err| format!("Error copying {:?}: {:?}", bundle, err))?;
+
+    let checksum = compute_sha256_hex(&downloaded)
+        .map_err(|err| format!("Error hashing {:?}: {:?}", downloaded, err))?;
+    if checksum != chunk.checksum {
+        return Err(format!(
+            "Checksum mismatch of {:?}: expected {}, got {}",
+            bundle, chunk.checksum, checksum
+        ));
+    }
+
+    unpack(&downloaded, spool_dir)
+}
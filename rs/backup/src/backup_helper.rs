@@ -1,29 +1,71 @@
+use crate::artifacts_chunks::{plan_chunks, ArtifactsChunk, ChunksManifest};
+use crate::compression::{unpack, PackStats};
+use crate::config::{
+    ArtifactsCompression, CompressionAlgorithm, IpPreference, LogRotation, ReplayBudget,
+    ReplaySharding,
+};
+use crate::cup_verification::{write_public_key_pem, CupChecks, CupVerdict, VERIFICATION_FAILED};
+use crate::log_rotation::{rotate_logs, LogIndexEntry};
+use crate::node_retention::{listed_buckets, listed_heights, unrecoverable_gap};
 use crate::notification_client::NotificationClient;
-use crate::util::{block_on, sleep_secs};
-use ic_recovery::command_helper::exec_cmd;
-use ic_recovery::file_sync_helper::download_binary;
+use crate::pinned_heights::PinnedHeights;
+use crate::replay_cli::ReplayCli;
+use crate::replay_manifest::ReplayManifest;
+use crate::replay_sharding::{shard_end_height, ReplayShard, ShardResult, SharedDir};
+use crate::spool_manifest::BucketManifest;
+use crate::subnet_state::{SubnetState, UnavailableBinaries, VersionSource};
+use crate::util::{block_on, sleep_secs_blocking, Cancellation};
+use ic_backup_spool::{bucket, SubnetSpool, VersionSpool};
+use ic_http_utils::file_downloader::compute_sha256_hex;
+use ic_recovery::command_helper::{
+    exec_cmd_with_input_and_timeout, exec_cmd_with_timeout, OutputStream,
+};
+use ic_recovery::file_sync_helper::{download_binary, fetch_with_quorum};
 use ic_registry_client::client::{RegistryClient, RegistryClientImpl};
+use ic_registry_client_helpers::crypto::CryptoRegistry;
 use ic_registry_client_helpers::node::NodeRegistry;
-use ic_registry_client_helpers::subnet::SubnetRegistry;
-use ic_types::{ReplicaVersion, SubnetId};
+use ic_registry_client_helpers::subnet::{SubnetListRegistry, SubnetRegistry};
+use ic_types::{Height, NodeId, ReplicaVersion, SubnetId};
 
 use chrono::{DateTime, Utc};
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 use slog::{debug, error, info, warn, Logger};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::ffi::OsStr;
-use std::fs::{create_dir_all, read_dir, remove_dir_all, DirEntry, File};
+use std::fs::{
+    copy, create_dir_all, read_dir, read_to_string, remove_dir_all, remove_file, rename, DirEntry,
+    File,
+};
 use std::io::Write;
 use std::net::IpAddr;
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 const RETRIES_RSYNC_HOST: u64 = 5;
 const RETRIES_BINARY_DOWNLOAD: u64 = 3;
-const BUCKET_SIZE: u64 = 10000;
+// Once the binaries of a replica version failed to download for this long, the
+// version is reported as blocked, e.g. because it was removed from the CDN.
+const BINARIES_UNAVAILABLE_ALERT_HOURS: i64 = 24;
+// The number of nodes the config is fetched from; a majority of them must agree.
+const CONFIG_SOURCE_NODES: usize = 3;
+// rsync from a host is limited to 5 minutes by `--time-limit`, plus the ssh connection.
+const TIMEOUT_RSYNC_HOST: Duration = Duration::from_secs(10 * 60);
+const TIMEOUT_REPLAY: Duration = Duration::from_secs(24 * 60 * 60);
+const TIMEOUT_CUP_VERIFICATION: Duration = Duration::from_secs(60);
+const TIMEOUT_REPLAY_HELP: Duration = Duration::from_secs(60);
+const TIMEOUT_DISK_STATS: Duration = Duration::from_secs(60);
+// Moving, packing and copying states and artifacts on the local disks.
+const TIMEOUT_LOCAL_FILE_OPS: Duration = Duration::from_secs(6 * 60 * 60);
+// A worker's claim on a shard expires if it didn't finish the replay in time.
+const SHARD_LEASE: Duration = Duration::from_secs(25 * 60 * 60);
+const CHECKSUM_EXTENSION: &str = "sha256";
+// ic-replay asks for confirmation before replaying until a height, on stdin,
+// which isn't a terminal when the backup runs as a service.
+const REPLAY_UNTIL_HEIGHT_CONSENT: &[u8] = b"y\n";
 
 pub struct BackupHelper {
     pub subnet_id: SubnetId,
@@ -37,17 +79,72 @@ pub struct BackupHelper {
     pub disk_threshold_warn: u32,
     pub cold_storage_dir: PathBuf,
     pub versions_hot: usize,
+    /// The uncompressed size of the chunks the artifacts of a replica version
+    /// are packed into. If not set, they are packed into a single bundle.
+    pub artifacts_chunk_size_bytes: Option<u64>,
+    pub artifacts_compression: ArtifactsCompression,
     pub artifacts_guard: Mutex<bool>,
+    /// Serializes the updates of the subnet state file by the sync and the
+    /// replay.
+    pub subnet_state_guard: Mutex<bool>,
+    /// Held for the whole replay, so that the subnet isn't retired while its
+    /// artifacts are being replayed.
+    pub replay_guard: Mutex<bool>,
     pub daily_replays: usize,
     pub do_cold_storage: bool,
     pub thread_id: u32,
     pub blacklisted_nodes: Arc<Vec<IpAddr>>,
+    pub ip_preference: IpPreference,
+    pub node_address_overrides: Arc<BTreeMap<String, Vec<IpAddr>>>,
+    pub log_rotation: LogRotation,
+    pub replay_sharding: Option<ReplaySharding>,
+    pub replay_budget: ReplayBudget,
+    pub cup_wait_timeout: Duration,
+    /// Whether the synced CUPs are verified before their heights are
+    /// replayed.
+    pub verify_cups: bool,
+    pub cup_checks: Mutex<CupChecks>,
+    /// The capabilities of the ic-replay of the replica versions probed so
+    /// far, `None` if a version can't replay from the spool.
+    pub replay_clis: Mutex<BTreeMap<ReplicaVersion, Option<ReplayCli>>>,
+    /// Whether the subnet was deleted from the registry and its backup was
+    /// wrapped up, see [BackupHelper::retire].
+    pub retired: AtomicBool,
+    pub cancellation: Cancellation,
     pub log: Logger,
 }
 
+/// The addresses a node can be reached at, the preferred one first. Never
+/// empty.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeAddresses {
+    pub node_id: NodeId,
+    pub addrs: Vec<IpAddr>,
+}
+
+impl NodeAddresses {
+    pub fn preferred(&self) -> IpAddr {
+        self.addrs[0]
+    }
+}
+
+/// A packed artifacts directory in the cold storage, named
+/// `<timestamp>_<top_height>_<replica_version>.<extension>`, with the
+/// extension of its [CompressionAlgorithm].
+struct ArtifactsBundle {
+    path: PathBuf,
+    top_height: u64,
+    replica_version: ReplicaVersion,
+}
+
 enum ReplayResult {
     Done,
     UpgradeRequired(ReplicaVersion),
+    /// The CUP to start from wasn't synced in time.
+    Skipped,
+    /// The replay reached its [ReplayBudget], the remaining heights are
+    /// replayed in the next period.
+    Interrupted,
 }
 
 enum DiskStats {
@@ -64,7 +161,7 @@ impl BackupHelper {
         self.binary_dir(replica_version).join(executable)
     }
 
-    fn logs_dir(&self) -> PathBuf {
+    pub fn logs_dir(&self) -> PathBuf {
         create_if_not_exists(self.root_dir.join("logs"))
     }
 
@@ -76,6 +173,11 @@ impl BackupHelper {
         self.spool_root_dir().join(self.subnet_id.to_string())
     }
 
+    fn spool_manifests_dir(&self) -> PathBuf {
+        self.root_dir
+            .join(format!("spool_manifests/{}", self.subnet_id))
+    }
+
     fn local_store_dir(&self) -> PathBuf {
         self.root_dir.join("ic_registry_local_store")
     }
@@ -118,37 +220,36 @@ impl BackupHelper {
         )
     }
 
+    fn verification_dir(&self) -> PathBuf {
+        self.root_dir
+            .join(format!("verification/{}", self.subnet_id))
+    }
+
+    fn rejected_dir(&self) -> PathBuf {
+        self.root_dir.join(format!("rejected/{}", self.subnet_id))
+    }
+
+    fn subnet_public_key_file(&self) -> PathBuf {
+        self.work_dir().join("subnet_public_key.pem")
+    }
+
     fn trash_dir(&self) -> PathBuf {
         create_if_not_exists(self.root_dir.join("trash"))
     }
 
+    /// Logs the output lines of a command run by this thread.
+    fn log_output(&self) -> impl FnMut(OutputStream, &str) + '_ {
+        move |stream, line| match stream {
+            OutputStream::Stdout => debug!(self.log, "[#{}] {}", self.thread_id, line),
+            OutputStream::Stderr => warn!(self.log, "[#{}] {}", self.thread_id, line),
+        }
+    }
+
     fn username(&self) -> String {
         "backup".to_string()
     }
 
-    fn download_binaries(
-        &self,
-        replica_version: &ReplicaVersion,
-        start_height: u64,
-    ) -> Result<(), String> {
-        debug!(
-            self.log,
-            "[#{}] Check if there are new artifacts.", self.thread_id
-        );
-
-        let cup_file = format!(
-            "{}/{}/{}/catch_up_package.bin",
-            replica_version,
-            start_height - start_height % 10000,
-            start_height
-        );
-        // Make sure that the CUP from this replica version and at this height is
-        // already synced from the node.
-        // That way it is guaranteed that the node is running the new replica version and
-        // has the latest version of the ic.json5 file.
-        while !self.spool_dir().join(cup_file.as_str()).exists() {
-            sleep_secs(30);
-        }
+    fn download_binaries(&self, replica_version: &ReplicaVersion) -> Result<(), String> {
         debug!(
             self.log,
             "[#{}] Start downloading binaries.", self.thread_id
@@ -164,16 +265,11 @@ impl BackupHelper {
 
         if !self.ic_config_file_local(replica_version).exists() {
             // collect nodes from which we will fetch the config
-            match self.collect_nodes(1) {
+            match self.collect_nodes(CONFIG_SOURCE_NODES) {
+                Ok(nodes) if nodes.is_empty() => Err("Error getting first node.".to_string()),
                 Ok(nodes) => {
-                    // fetch the ic.json5 file from the first node
-                    // TODO: fetch from another f nodes and compare them
-                    if let Some(node_ip) = nodes.get(0) {
-                        self.rsync_config(node_ip, replica_version);
-                        Ok(())
-                    } else {
-                        Err("Error getting first node.".to_string())
-                    }
+                    self.rsync_config(&nodes, replica_version, &self.binary_dir(replica_version));
+                    Ok(())
                 }
                 Err(e) => Err(format!("Error fetching subnet node list: {:?}", e)),
             }
@@ -182,6 +278,138 @@ impl BackupHelper {
         }
     }
 
+    /// Compares the ic.json5 of `replica_version` the subnet is replayed with
+    /// to the one on the nodes, if they run that version, and re-fetches it
+    /// if the nodes changed it, e.g. after a reconfiguration of the subnet,
+    /// so that the replay doesn't silently run with stale parameters.
+    fn check_ic_config(&self, replica_version: &ReplicaVersion) -> Result<(), String> {
+        let local_config = self.ic_config_file_local(replica_version);
+        if !local_config.exists() {
+            return Ok(());
+        }
+        let registry_version = self.registry_client.get_latest_version();
+        match self
+            .registry_client
+            .get_subnet_record(self.subnet_id, registry_version)
+        {
+            Ok(Some(record)) if record.replica_version_id == replica_version.to_string() => {}
+            // The nodes run the config of another version.
+            _ => return Ok(()),
+        }
+        let local_hash = compute_sha256_hex(&local_config)
+            .map_err(|err| format!("Error hashing {:?}: {:?}", local_config, err))?;
+
+        let nodes = self.collect_nodes(CONFIG_SOURCE_NODES)?;
+        let check_dir = self.work_dir().join("ic_config_check");
+        if check_dir.exists() {
+            remove_dir_all(&check_dir)
+                .map_err(|err| format!("Error deleting {:?}: {:?}", check_dir, err))?;
+        }
+        create_dir_all(&check_dir)
+            .map_err(|err| format!("Error creating {:?}: {:?}", check_dir, err))?;
+        self.rsync_config(&nodes, replica_version, &check_dir);
+        let fetched_config = check_dir.join("ic.json5");
+        if !fetched_config.exists() {
+            return Err("Couldn't fetch ic.json5 from the nodes".to_string());
+        }
+        let nodes_hash = compute_sha256_hex(&fetched_config)
+            .map_err(|err| format!("Error hashing {:?}: {:?}", fetched_config, err))?;
+
+        if nodes_hash != local_hash {
+            {
+                // The config is shared with the other subnets.
+                let _guard = self
+                    .downloads_guard
+                    .lock()
+                    .expect("downloads mutex lock failed");
+                rename(&fetched_config, &local_config)
+                    .map_err(|err| format!("Error replacing {:?}: {:?}", local_config, err))?;
+            }
+            self.notification_client.report_warning_slack(format!(
+                "The ic.json5 of version {} changed on the nodes ({} -> {}), replaying with the new one",
+                replica_version, local_hash, nodes_hash
+            ));
+        }
+        let _guard = self
+            .subnet_state_guard
+            .lock()
+            .expect("subnet state mutex lock failed");
+        let mut state = SubnetState::load(&self.root_dir, self.subnet_id)?;
+        if state.ic_config_hashes.get(&replica_version.to_string()) != Some(&nodes_hash) {
+            state
+                .ic_config_hashes
+                .insert(replica_version.to_string(), nodes_hash);
+            state.save(&self.root_dir, self.subnet_id)?;
+        }
+        Ok(())
+    }
+
+    /// The number of heights between two CUPs of the subnet, i.e. its DKG
+    /// interval length plus one, as per the latest registry version.
+    fn cup_interval(&self) -> Result<u64, String> {
+        let registry_version = self.registry_client.get_latest_version();
+        match self
+            .registry_client
+            .get_dkg_interval_length(self.subnet_id, registry_version)
+        {
+            Ok(Some(length)) => Ok(length.get() + 1),
+            Ok(None) => Err(format!(
+                "No DKG interval length of subnet {} in the registry",
+                self.subnet_id
+            )),
+            Err(err) => Err(format!("Error reading the DKG interval length: {:?}", err)),
+        }
+    }
+
+    /// Waits for the CUP of `replica_version` at `start_height` to be synced
+    /// from the nodes. That way it is guaranteed that the nodes are running
+    /// the new replica version and have the latest version of the ic.json5
+    /// file. Returns `false` if the CUP didn't show up within the timeout.
+    fn wait_for_cup(
+        &self,
+        replica_version: &ReplicaVersion,
+        start_height: u64,
+    ) -> Result<bool, String> {
+        let cup_file = SubnetSpool::new(self.spool_dir())
+            .version(replica_version)
+            .cup_path(Height::from(start_height));
+        if cup_file.exists() {
+            return Ok(true);
+        }
+        debug!(
+            self.log,
+            "[#{}] Waiting for the CUP at height {} of version {}",
+            self.thread_id,
+            start_height,
+            replica_version
+        );
+        let started = Instant::now();
+        let since = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.notification_client.push_metrics_cup_wait_since(since);
+        let result = loop {
+            if cup_file.exists() {
+                break Ok(true);
+            }
+            if started.elapsed() >= self.cup_wait_timeout {
+                self.notification_client.report_warning_slack(format!(
+                    "The CUP at height {} of version {} wasn't synced within {} minutes, skipping the replay",
+                    start_height,
+                    replica_version,
+                    self.cup_wait_timeout.as_secs() / 60
+                ));
+                break Ok(false);
+            }
+            if let Err(err) = sleep_secs_blocking(30, &self.cancellation) {
+                break Err(err);
+            }
+        };
+        self.notification_client.push_metrics_cup_wait_since(0);
+        result
+    }
+
     fn download_binary(
         &self,
         binary_name: &str,
@@ -198,24 +426,122 @@ impl BackupHelper {
                 self.binary_dir(replica_version),
             ));
             if res.is_ok() {
+                if let Err(err) = self.clear_unavailable_binaries(replica_version) {
+                    warn!(
+                        self.log,
+                        "[#{}] Error clearing the unavailable binaries: {}", self.thread_id, err
+                    );
+                }
                 return Ok(());
             }
             warn!(
                 self.log,
                 "Error while downloading {}: {:?}", binary_name, res
             );
-            sleep_secs(10);
+            sleep_secs_blocking(10, &self.cancellation)?;
         }
         // Without the binaries we can't replay...
-        self.notification_client
-            .report_failure_slack(format!("Couldn't download: {}", binary_name));
+        if let Err(err) = self.track_unavailable_binary(binary_name, replica_version) {
+            warn!(
+                self.log,
+                "[#{}] Error tracking the unavailable binaries: {}", self.thread_id, err
+            );
+            self.notification_client
+                .report_failure_slack(format!("Couldn't download: {}", binary_name));
+        }
         Err(format!(
             "Binary {} is required for the replica {}",
             binary_name, replica_version
         ))
     }
 
-    fn rsync_spool(&self, node_ip: &IpAddr) -> bool {
+    /// Records that `binary_name` of `replica_version` couldn't be downloaded.
+    /// Once the downloads of the version keep failing for
+    /// [BINARIES_UNAVAILABLE_ALERT_HOURS], the version is blocked with a
+    /// distinct alert, instead of reporting the same failure every period.
+    fn track_unavailable_binary(
+        &self,
+        binary_name: &str,
+        replica_version: &ReplicaVersion,
+    ) -> Result<(), String> {
+        let _guard = self
+            .subnet_state_guard
+            .lock()
+            .expect("subnet state mutex lock failed");
+        let mut state = SubnetState::load(&self.root_dir, self.subnet_id)?;
+        let now = Utc::now();
+        let unavailable = state
+            .unavailable_binaries
+            .entry(replica_version.to_string())
+            .or_insert_with(|| UnavailableBinaries {
+                binary: binary_name.to_string(),
+                failing_since: now.to_rfc3339(),
+                blocked: false,
+            });
+        unavailable.binary = binary_name.to_string();
+        let failing_since = unavailable.failing_since.clone();
+        let failing_for = DateTime::parse_from_rfc3339(&failing_since)
+            .map(|since| now.signed_duration_since(since))
+            .map_err(|err| format!("Error parsing {}: {:?}", failing_since, err))?;
+        let was_blocked = unavailable.blocked;
+        unavailable.blocked =
+            failing_for >= chrono::Duration::hours(BINARIES_UNAVAILABLE_ALERT_HOURS);
+        let blocked = unavailable.blocked;
+        state.save(&self.root_dir, self.subnet_id)?;
+
+        if !blocked {
+            self.notification_client
+                .report_failure_slack(format!("Couldn't download: {}", binary_name));
+        } else if !was_blocked {
+            let heights = match SubnetSpool::new(self.spool_dir())
+                .version(replica_version)
+                .heights()
+            {
+                Ok(heights) if !heights.is_empty() => {
+                    format!("heights {}..{}", heights[0], heights[heights.len() - 1])
+                }
+                _ => "no heights in the spool yet".to_string(),
+            };
+            self.notification_client.report_failure_slack(format!(
+                "🚫 Replica version *{}* is blocked: {} couldn't be downloaded since {}, {} can't be replayed until its binaries are published again",
+                replica_version, binary_name, failing_since, heights
+            ));
+        } else {
+            warn!(
+                self.log,
+                "[#{}] Replica version {} is still blocked, {} couldn't be downloaded",
+                self.thread_id,
+                replica_version,
+                binary_name
+            );
+        }
+        Ok(())
+    }
+
+    /// Forgets the failed downloads of `replica_version`, now that one
+    /// succeeded.
+    fn clear_unavailable_binaries(&self, replica_version: &ReplicaVersion) -> Result<(), String> {
+        let _guard = self
+            .subnet_state_guard
+            .lock()
+            .expect("subnet state mutex lock failed");
+        let mut state = SubnetState::load(&self.root_dir, self.subnet_id)?;
+        let unavailable = state
+            .unavailable_binaries
+            .remove(&replica_version.to_string());
+        if let Some(unavailable) = unavailable {
+            state.save(&self.root_dir, self.subnet_id)?;
+            if unavailable.blocked {
+                self.notification_client.message_slack(format!(
+                    "✅ The binaries of replica version *{}* are available again",
+                    replica_version
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn rsync_spool(&self, node: &NodeAddresses) -> bool {
         let _guard = self
             .artifacts_guard
             .lock()
@@ -223,64 +549,185 @@ impl BackupHelper {
         info!(
             self.log,
             "Sync backup data from the node: {} for subnet_id: {}",
-            node_ip,
+            node.node_id,
             self.subnet_id.to_string()
         );
-        let remote_dir = format!(
-            "{}@[{}]:/var/lib/ic/backup/{}/",
-            self.username(),
-            node_ip,
-            self.subnet_id
-        );
         for _ in 0..RETRIES_RSYNC_HOST {
-            match self.rsync_remote_cmd(
-                remote_dir.clone(),
-                &self.spool_dir().into_os_string(),
-                &["-qam", "--append-verify"],
-            ) {
-                Ok(_) => return true,
-                Err(e) => warn!(
-                    self.log,
-                    "Problem syncing backup directory with host: {} : {}", node_ip, e
-                ),
+            // fall back to the other addresses of the node if one isn't reachable
+            for node_ip in &node.addrs {
+                let remote_dir = format!(
+                    "{}@{}:/var/lib/ic/backup/{}/",
+                    self.username(),
+                    remote_host(node_ip),
+                    self.subnet_id
+                );
+                match self.rsync_remote_cmd(
+                    remote_dir,
+                    &self.spool_dir().into_os_string(),
+                    &["-qam", "--append-verify"],
+                ) {
+                    Ok(_) => return true,
+                    Err(e) => warn!(
+                        self.log,
+                        "Problem syncing backup directory with host: {} : {}", node_ip, e
+                    ),
+                }
+            }
+            if sleep_secs_blocking(60, &self.cancellation).is_err() {
+                break;
             }
-            sleep_secs(60);
         }
-        warn!(self.log, "Didn't sync at all with host: {}", node_ip);
+        warn!(self.log, "Didn't sync at all with node: {}", node.node_id);
         false
     }
 
-    fn rsync_config(&self, node_ip: &IpAddr, replica_version: &ReplicaVersion) {
+    /// Fetches ic.json5 from all given nodes and keeps the version served by
+    /// the majority of them.
+    fn rsync_config(
+        &self,
+        nodes: &[NodeAddresses],
+        replica_version: &ReplicaVersion,
+        config_dir: &Path,
+    ) {
+        // the quorum is computed over the preferred address of each node
+        let hosts: Vec<IpAddr> = nodes.iter().map(NodeAddresses::preferred).collect();
         info!(
             self.log,
-            "[#{}] Sync ic.json5 from the node: {} for replica: {} and subnet_id: {}",
+            "[#{}] Sync ic.json5 from the nodes: {:?} for replica: {} and subnet_id: {}",
             self.thread_id,
-            node_ip,
+            hosts,
             replica_version,
             self.subnet_id.to_string()
         );
-        let remote_dir = format!(
-            "{}@[{}]:/run/ic-node/config/ic.json5",
-            self.username(),
-            node_ip
-        );
-        for _ in 0..RETRIES_RSYNC_HOST {
-            match self.rsync_remote_cmd(
+        let fetch = |host: IpAddr, dir: &Path| {
+            let addrs = nodes
+                .iter()
+                .find(|node| node.preferred() == host)
+                .map_or_else(|| vec![host], |node| node.addrs.clone());
+            for _ in 0..RETRIES_RSYNC_HOST {
+                for node_ip in &addrs {
+                    let remote_dir = format!(
+                        "{}@{}:/run/ic-node/config/ic.json5",
+                        self.username(),
+                        remote_host(node_ip)
+                    );
+                    match self.rsync_remote_cmd(remote_dir, dir.as_os_str(), &["-q"]) {
+                        Ok(_) => return Ok(()),
+                        Err(e) => warn!(
+                            self.log,
+                            "Problem syncing config from host: {} : {}", node_ip, e
+                        ),
+                    }
+                }
+                sleep_secs_blocking(60, &self.cancellation)?;
+            }
+            Err(format!("Didn't sync any config from host: {}", host))
+        };
+        match fetch_with_quorum(&self.log, &hosts, hosts.len() / 2 + 1, config_dir, fetch) {
+            Ok(report) if !report.divergent_hosts.is_empty() => self
+                .notification_client
+                .report_warning_slack(format!("Nodes served diverging ic.json5 files: {}", report)),
+            Ok(_) => {}
+            Err(e) => {
+                warn!(self.log, "Didn't sync any config: {}", e);
+                self.notification_client
+                    .report_failure_slack("Couldn't pull ic.json5 from the nodes!".to_string());
+            }
+        }
+    }
+
+    /// Returns the lowest height the node still keeps in its backup
+    /// directory, or `None` if it keeps none.
+    fn node_lowest_height(&self, node: &NodeAddresses) -> Result<Option<u64>, String> {
+        let mut last_error = String::new();
+        // fall back to the other addresses of the node if one isn't reachable
+        for node_ip in &node.addrs {
+            let remote_dir = format!(
+                "{}@{}:/var/lib/ic/backup/{}/",
+                self.username(),
+                remote_host(node_ip),
+                self.subnet_id
+            );
+            // Only the replica version and bucket directories are listed.
+            let listing = match self.rsync_list_cmd(
                 remote_dir.clone(),
-                &self.ic_config_file_local(replica_version).into_os_string(),
-                &["-q"],
+                &["-r", "--include=/*/", "--include=/*/*/", "--exclude=*"],
             ) {
-                Ok(_) => return,
-                Err(e) => warn!(
-                    self.log,
-                    "Problem syncing config from host: {} : {}", node_ip, e
-                ),
+                Ok(listing) => listing,
+                Err(e) => {
+                    last_error = e;
+                    continue;
+                }
+            };
+            // The lowest bucket may have been emptied by the pruning.
+            for (_, bucket_dir) in listed_buckets(&listing) {
+                let listing = self.rsync_list_cmd(format!("{}{}/", remote_dir, bucket_dir), &[])?;
+                if let Some(height) = listed_heights(&listing).first() {
+                    return Ok(Some(*height));
+                }
             }
-            sleep_secs(60);
+            return Ok(None);
         }
-        warn!(self.log, "Didn't sync any config from host: {}", node_ip);
-        self.notification_client
-            .report_failure_slack("Couldn't pull ic.json5 from the nodes!".to_string());
+        Err(last_error)
+    }
+
+    /// Raises a distinct alert if none of the nodes keeps the heights
+    /// following `spool_top_height` anymore, given the lowest height each of
+    /// them keeps. Every gap is alerted once and recorded in the subnet
+    /// state.
+    fn reconcile_node_retention(
+        &self,
+        spool_top_height: u64,
+        lowest_node_heights: &[u64],
+    ) -> Result<(), String> {
+        let gap = match unrecoverable_gap(spool_top_height, lowest_node_heights) {
+            Some(gap) => gap,
+            None => return Ok(()),
+        };
+        let _guard = self
+            .subnet_state_guard
+            .lock()
+            .expect("subnet state mutex lock failed");
+        let mut state = SubnetState::load(&self.root_dir, self.subnet_id)?;
+        let is_new =
+            state.record_unrecoverable_gap(*gap.start(), *gap.end(), Utc::now().to_rfc3339());
+        state.save(&self.root_dir, self.subnet_id)?;
+        if is_new {
+            self.notification_client.report_failure_slack(format!(
+                "🕳️ Unrecoverable gap: heights {}..{} were pruned on all nodes before they were synced, the subnet can't be replayed past height {} without a manual recovery",
+                gap.start(),
+                gap.end(),
+                spool_top_height
+            ));
+        } else {
+            warn!(
+                self.log,
+                "[#{}] The nodes still miss the heights {}..{}",
+                self.thread_id,
+                gap.start(),
+                gap.end()
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the entries of the remote directory listed by
+    /// `rsync --list-only`.
+    fn rsync_list_cmd(&self, remote_dir: String, arguments: &[&str]) -> Result<String, String> {
+        let mut cmd = Command::new("rsync");
+        cmd.arg("-e");
+        cmd.arg(format!(
+            "ssh -o StrictHostKeyChecking=no -i {}",
+            self.ssh_private_key
+        ));
+        cmd.arg("--timeout=60").arg("--list-only");
+        cmd.args(arguments);
+        cmd.arg(remote_dir);
+        debug!(self.log, "Will execute: {:?}", cmd);
+
+        exec_cmd_with_timeout(&mut cmd, TIMEOUT_RSYNC_HOST, self.log_output())
+            .map(|output| output.stdout)
+            .map_err(|e| format!("Error: {}", e))
     }
 
     fn rsync_remote_cmd(
@@ -301,27 +748,586 @@ impl BackupHelper {
         cmd.arg("--min-size=1").arg(remote_dir).arg(local_dir);
         debug!(self.log, "Will execute: {:?}", cmd);
 
-        if let Err(e) = exec_cmd(&mut cmd) {
+        if let Err(e) = exec_cmd_with_timeout(&mut cmd, TIMEOUT_RSYNC_HOST, self.log_output()) {
             Err(format!("Error: {}", e))
         } else {
             Ok(())
         }
     }
 
-    pub fn sync_files(&self, nodes: &[IpAddr]) {
+    pub fn sync_files(&self, nodes: &[NodeAddresses]) {
+        // The retention of the nodes is compared with the spool before the
+        // sync, as the nodes may prune further while it's running.
+        let spool_top_height = self.retrieve_spool_top_height();
+        let lowest_node_heights: Vec<u64> = nodes
+            .iter()
+            .filter_map(|node| match self.node_lowest_height(node) {
+                Ok(height) => {
+                    info!(
+                        self.log,
+                        "[#{}] Node {} keeps the heights from {:?}, the spool ends at {}",
+                        self.thread_id,
+                        node.node_id,
+                        height,
+                        spool_top_height
+                    );
+                    height
+                }
+                Err(e) => {
+                    warn!(
+                        self.log,
+                        "Problem listing the backup directory of node: {} : {}", node.node_id, e
+                    );
+                    None
+                }
+            })
+            .collect();
         let start_time = Instant::now();
         let total_succeeded: usize = nodes
             .iter()
             .map(|node| self.rsync_spool(node) as usize)
             .sum();
+        if self.cancellation.is_cancelled() {
+            return;
+        }
         if 2 * total_succeeded >= nodes.len() {
             let duration = start_time.elapsed();
             let minutes = duration.as_secs() / 60;
             self.notification_client.push_metrics_sync_time(minutes);
+            if let Err(err) = self.track_spool_versions() {
+                error!(
+                    self.log,
+                    "[#{}] Error tracking the replica versions of the spool: {}",
+                    self.thread_id,
+                    err
+                );
+            }
         } else {
             self.notification_client
                 .report_failure_slack("Couldn't pull artifacts from the nodes!".to_string());
         }
+        // Whatever was synced, also from a minority of the nodes, is verified
+        // before it's replayed.
+        if self.verify_cups {
+            if let Err(err) = self.verify_synced_cups() {
+                error!(
+                    self.log,
+                    "[#{}] Error verifying the synced CUPs: {}", self.thread_id, err
+                );
+            }
+        }
+        if let Err(err) = self.update_spool_manifests() {
+            error!(
+                self.log,
+                "[#{}] Error updating the spool manifests: {}", self.thread_id, err
+            );
+        }
+        if let Err(err) = self.reconcile_node_retention(spool_top_height, &lowest_node_heights) {
+            error!(
+                self.log,
+                "[#{}] Error reconciling the retention of the nodes: {}", self.thread_id, err
+            );
+        }
+    }
+
+    /// Adds the artifacts synced since the last update to the manifests of
+    /// their buckets and records the roots of the manifests in the subnet
+    /// state. A bucket whose manifest can't be updated keeps its previous
+    /// root, so that the next verification catches the problem.
+    fn update_spool_manifests(&self) -> Result<(), String> {
+        let spool_dir = self.spool_dir();
+        if !spool_dir.exists() {
+            return Ok(());
+        }
+        let _guard = self
+            .artifacts_guard
+            .lock()
+            .expect("artifacts mutex lock failed");
+        let versions = SubnetSpool::new(&spool_dir)
+            .versions()
+            .map_err(|err| format!("Error listing {:?}: {:?}", spool_dir, err))?;
+        let manifests_dir = self.spool_manifests_dir();
+        let mut roots = BTreeMap::new();
+        let mut failed = Vec::new();
+        for version_spool in versions {
+            let replica_version = match version_spool.replica_version() {
+                Some(replica_version) => replica_version.to_string(),
+                None => continue,
+            };
+            for bucket_dir in collect_only_dirs(&version_spool.path().to_path_buf())? {
+                let bucket = height_from_dir_entry_radix(&bucket_dir, 10);
+                let key = format!("{}/{}", replica_version, bucket);
+                let path = BucketManifest::path(&manifests_dir, &replica_version, bucket);
+                let mut manifest = BucketManifest::load(&path)?;
+                match manifest.update(&bucket_dir.path()) {
+                    Ok(true) => manifest.save(&path)?,
+                    Ok(false) => {}
+                    Err(err) => {
+                        failed.push((key, err));
+                        continue;
+                    }
+                }
+                roots.insert(key, manifest.root_hash);
+            }
+        }
+
+        let _state_guard = self
+            .subnet_state_guard
+            .lock()
+            .expect("subnet state mutex lock failed");
+        let mut state = SubnetState::load(&self.root_dir, self.subnet_id)?;
+        for (key, err) in &failed {
+            if let Some(root) = state.spool_roots.get(key) {
+                roots.insert(key.clone(), root.clone());
+            }
+            self.notification_client.report_failure_slack(format!(
+                "Integrity violation in bucket {} of the spool: {}",
+                key, err
+            ));
+        }
+        if state.spool_roots != roots {
+            state.spool_roots = roots;
+            state.save(&self.root_dir, self.subnet_id)?;
+        }
+        Ok(())
+    }
+
+    /// Hashes the files of the buckets of the spool version at `version_dir`
+    /// from the bucket of `from_height` up again, and returns the problems
+    /// found, by bucket directory. Buckets without a recorded root haven't
+    /// been added to a manifest yet and are skipped.
+    fn verify_spool_buckets(
+        &self,
+        version_dir: &Path,
+        from_height: u64,
+    ) -> Result<Vec<(PathBuf, Vec<String>)>, String> {
+        let replica_version = match version_dir.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => return Ok(Vec::new()),
+        };
+        let roots = {
+            let _guard = self
+                .subnet_state_guard
+                .lock()
+                .expect("subnet state mutex lock failed");
+            SubnetState::load(&self.root_dir, self.subnet_id)?.spool_roots
+        };
+        let manifests_dir = self.spool_manifests_dir();
+        let from_bucket = bucket(Height::from(from_height));
+        let mut corrupted = Vec::new();
+        for bucket_dir in collect_only_dirs(&version_dir.to_path_buf())? {
+            let bucket = height_from_dir_entry_radix(&bucket_dir, 10);
+            if bucket < from_bucket {
+                continue;
+            }
+            let root = match roots.get(&format!("{}/{}", replica_version, bucket)) {
+                Some(root) => root,
+                None => continue,
+            };
+            let path = BucketManifest::path(&manifests_dir, &replica_version, bucket);
+            let problems = BucketManifest::load(&path)?.verify(&bucket_dir.path(), root);
+            if !problems.is_empty() {
+                corrupted.push((bucket_dir.path(), problems));
+            }
+        }
+        Ok(corrupted)
+    }
+
+    /// Verifies the buckets of the spool that are still to be replayed
+    /// against their manifests. Corrupted buckets are quarantined, so that
+    /// they are synced again from the nodes, and false is returned.
+    fn verify_spool_before_replay(&self) -> Result<bool, String> {
+        let spool_dir = self.spool_dir();
+        if !spool_dir.exists() {
+            return Ok(true);
+        }
+        let _guard = self
+            .artifacts_guard
+            .lock()
+            .expect("artifacts mutex lock failed");
+        let versions = SubnetSpool::new(&spool_dir)
+            .versions()
+            .map_err(|err| format!("Error listing {:?}: {:?}", spool_dir, err))?;
+        let start_height = self.last_state_checkpoint();
+        let mut intact = true;
+        for version_spool in versions {
+            let replica_version = match version_spool.replica_version() {
+                Some(replica_version) => replica_version,
+                None => continue,
+            };
+            for (bucket_dir, problems) in
+                self.verify_spool_buckets(version_spool.path(), start_height)?
+            {
+                intact = false;
+                let rejected = self.quarantine_bucket(&replica_version, &bucket_dir)?;
+                self.forget_spool_manifest(&replica_version, &bucket_dir)?;
+                self.notification_client.report_failure_slack(format!(
+                    "Rejected the artifacts of bucket {:?} of version {}, moved to {:?}: they don't match their manifest: {}",
+                    bucket_dir.file_name().unwrap_or_default(),
+                    replica_version,
+                    rejected,
+                    problems.join("; ")
+                ));
+            }
+        }
+        Ok(intact)
+    }
+
+    /// Removes the manifest of the bucket at `bucket_dir` and its root, after
+    /// the bucket left the spool.
+    fn forget_spool_manifest(
+        &self,
+        replica_version: &ReplicaVersion,
+        bucket_dir: &Path,
+    ) -> Result<(), String> {
+        let bucket = bucket_dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.parse::<u64>().ok())
+            .ok_or_else(|| format!("Not a bucket directory: {:?}", bucket_dir))?;
+        let path = BucketManifest::path(
+            &self.spool_manifests_dir(),
+            &replica_version.to_string(),
+            bucket,
+        );
+        if path.exists() {
+            remove_file(&path).map_err(|err| format!("Error deleting {:?}: {:?}", path, err))?;
+        }
+        let _guard = self
+            .subnet_state_guard
+            .lock()
+            .expect("subnet state mutex lock failed");
+        let mut state = SubnetState::load(&self.root_dir, self.subnet_id)?;
+        if state
+            .spool_roots
+            .remove(&format!("{}/{}", replica_version, bucket))
+            .is_some()
+        {
+            state.save(&self.root_dir, self.subnet_id)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the manifests of all buckets of `replica_version` and their
+    /// roots, after the version left the spool.
+    fn forget_spool_manifests_of_version(&self, replica_version: &str) -> Result<(), String> {
+        let dir = self.spool_manifests_dir().join(replica_version);
+        if dir.exists() {
+            remove_dir_all(&dir).map_err(|err| format!("Error deleting {:?}: {:?}", dir, err))?;
+        }
+        let _guard = self
+            .subnet_state_guard
+            .lock()
+            .expect("subnet state mutex lock failed");
+        let mut state = SubnetState::load(&self.root_dir, self.subnet_id)?;
+        let prefix = format!("{}/", replica_version);
+        let before = state.spool_roots.len();
+        state.spool_roots.retain(|key, _| !key.starts_with(&prefix));
+        if state.spool_roots.len() != before {
+            state.save(&self.root_dir, self.subnet_id)?;
+        }
+        Ok(())
+    }
+
+    /// Verifies the CUPs synced since the last check against the threshold
+    /// signing key of the subnet in the registry. The bucket of a CUP failing
+    /// the verification is moved to the rejected directory, from where it
+    /// can be inspected, and is synced again from the nodes. If the nodes
+    /// serve the same CUP again, its bucket is deleted right away. A CUP that
+    /// can't be verified is alerted on and stays unchecked, which holds back
+    /// the replay.
+    fn verify_synced_cups(&self) -> Result<(), String> {
+        let spool_dir = self.spool_dir();
+        if !spool_dir.exists() {
+            return Ok(());
+        }
+        let versions = SubnetSpool::new(&spool_dir)
+            .versions()
+            .map_err(|err| format!("Error listing {:?}: {:?}", spool_dir, err))?;
+        let registry_version = self.registry_client.get_latest_version();
+        let key = match self
+            .registry_client
+            .get_threshold_signing_public_key_for_subnet(self.subnet_id, registry_version)
+        {
+            Ok(Some(key)) => key,
+            other => {
+                return Err(format!(
+                    "no threshold signing public key found in the registry for subnet_id={}: {:?}",
+                    self.subnet_id, other
+                ))
+            }
+        };
+        let key_file = self.subnet_public_key_file();
+        write_public_key_pem(key, &key_file)?;
+
+        let mut checks = self
+            .cup_checks
+            .lock()
+            .expect("cup checks mutex lock failed");
+        for version_spool in versions {
+            let replica_version = match version_spool.replica_version() {
+                Some(replica_version) => replica_version,
+                None => continue,
+            };
+            let cup_heights = version_spool
+                .cup_heights()
+                .map_err(|err| format!("Error listing {:?}: {:?}", version_spool.path(), err))?;
+            for height in cup_heights {
+                let cup_path = version_spool.cup_path(height);
+                if checks.is_checked(&cup_path) {
+                    continue;
+                }
+                let bucket = bucket(height);
+                let bucket_dir = version_spool.path().join(bucket.to_string());
+                let cup_hash = compute_sha256_hex(&cup_path)
+                    .map_err(|err| format!("Error hashing {:?}: {:?}", cup_path, err))?;
+                if checks.is_rejected(&cup_hash) {
+                    remove_dir_all(&bucket_dir)
+                        .map_err(|err| format!("Error deleting {:?}: {:?}", bucket_dir, err))?;
+                    self.forget_spool_manifest(&replica_version, &bucket_dir)?;
+                    checks.forget_dir(&bucket_dir);
+                    warn!(
+                        self.log,
+                        "[#{}] Deleted the artifacts of bucket {} of version {}: the CUP at height {} was rejected before",
+                        self.thread_id,
+                        bucket,
+                        replica_version,
+                        height
+                    );
+                    continue;
+                }
+                match self.verify_cup(&replica_version, &cup_path, &key_file) {
+                    CupVerdict::Valid => checks.accept(cup_path),
+                    CupVerdict::Invalid(reason) => {
+                        let rejected = self.quarantine_bucket(&replica_version, &bucket_dir)?;
+                        self.forget_spool_manifest(&replica_version, &bucket_dir)?;
+                        checks.forget_dir(&bucket_dir);
+                        checks.reject(cup_hash);
+                        self.notification_client.report_failure_slack(format!(
+                            "Rejected the artifacts of bucket {} of version {}, moved to {:?}: the CUP at height {} failed the verification: {}",
+                            bucket, replica_version, rejected, height, reason
+                        ));
+                    }
+                    CupVerdict::Unverifiable(err) => {
+                        let message = format!(
+                            "Couldn't verify the CUP at height {} with the ic-replay of version {}, holding back the replay: {}",
+                            height, replica_version, err
+                        );
+                        if checks.report_unverifiable(cup_path) {
+                            self.notification_client.report_failure_slack(message);
+                        } else {
+                            warn!(self.log, "[#{}] {}", self.thread_id, message);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn verify_cup(
+        &self,
+        replica_version: &ReplicaVersion,
+        cup_path: &Path,
+        key_file: &Path,
+    ) -> CupVerdict {
+        {
+            let _guard = self
+                .downloads_guard
+                .lock()
+                .expect("downloads mutex lock failed");
+            if let Err(err) = self.download_binary("ic-replay", replica_version) {
+                return CupVerdict::Unverifiable(err);
+            }
+        }
+        match self.replay_cli(replica_version) {
+            Ok(cli) if cli.verify_subnet_cup => {}
+            Ok(_) => {
+                return CupVerdict::Unverifiable(format!(
+                    "the ic-replay of version {} can't verify CUPs",
+                    replica_version
+                ))
+            }
+            Err(err) => return CupVerdict::Unverifiable(err),
+        }
+        let mut cmd = Command::new(self.binary_file("ic-replay", replica_version));
+        cmd.arg("verify-subnet-cup").arg(cup_path).arg(key_file);
+        debug!(self.log, "Will execute: {:?}", cmd);
+        let mut failure = None;
+        let mut log_output = self.log_output();
+        let on_line = |stream: OutputStream, line: &str| {
+            if line.starts_with(VERIFICATION_FAILED) {
+                failure = Some(line.to_string());
+            }
+            log_output(stream, line);
+        };
+        match exec_cmd_with_timeout(&mut cmd, TIMEOUT_CUP_VERIFICATION, on_line) {
+            Ok(_) => CupVerdict::Valid,
+            Err(err) => match failure {
+                Some(reason) => CupVerdict::Invalid(reason),
+                None => CupVerdict::Unverifiable(err.to_string()),
+            },
+        }
+    }
+
+    /// Returns what the downloaded ic-replay of `replica_version` supports,
+    /// probing its help once per version. Alerts the first time a version is
+    /// found unable to replay from the spool.
+    fn replay_cli(&self, replica_version: &ReplicaVersion) -> Result<ReplayCli, String> {
+        let mut replay_clis = self
+            .replay_clis
+            .lock()
+            .expect("replay CLIs mutex lock failed");
+        let cli = match replay_clis.get(replica_version) {
+            Some(cli) => cli.clone(),
+            None => {
+                let mut cmd = Command::new(self.binary_file("ic-replay", replica_version));
+                cmd.arg("--help");
+                let output = exec_cmd_with_timeout(&mut cmd, TIMEOUT_REPLAY_HELP, |_, _| {})
+                    .map_err(|err| format!("Error probing the ic-replay CLI: {:?}", err))?;
+                let cli = ReplayCli::from_help(&output.stdout);
+                if cli.is_none() {
+                    self.notification_client
+                        .report_failure_slack(ReplayCli::unsupported(&replica_version.to_string()));
+                }
+                replay_clis.insert(replica_version.clone(), cli.clone());
+                cli
+            }
+        };
+        cli.ok_or_else(|| ReplayCli::unsupported(&replica_version.to_string()))
+    }
+
+    /// Moves the bucket at `bucket_dir` of the spool of `replica_version` to
+    /// the rejected directory and returns its new location.
+    fn quarantine_bucket(
+        &self,
+        replica_version: &ReplicaVersion,
+        bucket_dir: &Path,
+    ) -> Result<PathBuf, String> {
+        let version_dir =
+            create_if_not_exists(self.rejected_dir().join(replica_version.to_string()));
+        let bucket_name = bucket_dir
+            .file_name()
+            .ok_or_else(|| format!("Not a bucket directory: {:?}", bucket_dir))?;
+        let rejected_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let target = version_dir.join(format!("{}_{}", bucket_name.to_string_lossy(), rejected_at));
+        rename(bucket_dir, &target)
+            .map_err(|err| format!("Error moving {:?} to {:?}: {:?}", bucket_dir, target, err))?;
+        Ok(target)
+    }
+
+    /// Whether the spool holds CUPs that weren't verified yet.
+    fn has_unchecked_cups(&self) -> Result<bool, String> {
+        let spool_dir = self.spool_dir();
+        if !spool_dir.exists() {
+            return Ok(false);
+        }
+        let versions = SubnetSpool::new(&spool_dir)
+            .versions()
+            .map_err(|err| format!("Error listing {:?}: {:?}", spool_dir, err))?;
+        let checks = self
+            .cup_checks
+            .lock()
+            .expect("cup checks mutex lock failed");
+        for version_spool in versions {
+            if version_spool.replica_version().is_none() {
+                continue;
+            }
+            let cup_heights = version_spool
+                .cup_heights()
+                .map_err(|err| format!("Error listing {:?}: {:?}", version_spool.path(), err))?;
+            if cup_heights
+                .into_iter()
+                .any(|height| !checks.is_checked(&version_spool.cup_path(height)))
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Records the replica versions that appeared in the spool since the last
+    /// sync, at the lowest height synced for them. The versions found when
+    /// nothing is tracked yet are recorded without a notification.
+    fn track_spool_versions(&self) -> Result<(), String> {
+        let spool_dir = self.spool_dir();
+        if !spool_dir.exists() {
+            return Ok(());
+        }
+        let versions = SubnetSpool::new(&spool_dir)
+            .versions()
+            .map_err(|err| format!("Error listing {:?}: {:?}", spool_dir, err))?;
+        let _guard = self
+            .subnet_state_guard
+            .lock()
+            .expect("subnet state mutex lock failed");
+        let mut state = SubnetState::load(&self.root_dir, self.subnet_id)?;
+        let notify = !state.version_transitions.is_empty();
+        let mut changed = false;
+        for version_spool in versions {
+            let replica_version = match version_spool.replica_version() {
+                Some(replica_version) => replica_version,
+                None => continue,
+            };
+            if state.knows_version(&replica_version) {
+                continue;
+            }
+            let heights = version_spool
+                .heights()
+                .map_err(|err| format!("Error listing {:?}: {:?}", version_spool.path(), err))?;
+            if let Some(height) = heights.first() {
+                self.record_version(
+                    &mut state,
+                    &replica_version,
+                    height.get(),
+                    VersionSource::Spool,
+                    notify,
+                );
+                changed = true;
+            }
+        }
+        if changed {
+            state.save(&self.root_dir, self.subnet_id)?;
+        }
+        Ok(())
+    }
+
+    /// Records an upgrade to `replica_version` detected by the replay at
+    /// `height`.
+    fn track_upgrade(&self, replica_version: &ReplicaVersion, height: u64) -> Result<(), String> {
+        let _guard = self
+            .subnet_state_guard
+            .lock()
+            .expect("subnet state mutex lock failed");
+        let mut state = SubnetState::load(&self.root_dir, self.subnet_id)?;
+        self.record_version(
+            &mut state,
+            replica_version,
+            height,
+            VersionSource::Replay,
+            true,
+        );
+        state.save(&self.root_dir, self.subnet_id)
+    }
+
+    fn record_version(
+        &self,
+        state: &mut SubnetState,
+        replica_version: &ReplicaVersion,
+        height: u64,
+        source: VersionSource,
+        notify: bool,
+    ) {
+        if state.record_version(replica_version, height, Utc::now().to_rfc3339(), source) && notify
+        {
+            self.notification_client.message_slack(format!(
+                "🆕 New replica version *{}* observed at height *{}*",
+                replica_version, height
+            ));
+        }
     }
 
     pub fn create_spool_dir(&self) {
@@ -330,72 +1336,250 @@ impl BackupHelper {
         }
     }
 
-    pub fn collect_nodes(&self, num_nodes: usize) -> Result<Vec<IpAddr>, String> {
+    pub fn collect_nodes(&self, num_nodes: usize) -> Result<Vec<NodeAddresses>, String> {
         let mut shuf_nodes = self.collect_all_subnet_nodes()?;
         shuf_nodes.shuffle(&mut thread_rng());
         Ok(shuf_nodes
-            .iter()
-            .filter(|ip| !self.blacklisted_nodes.contains(ip))
+            .into_iter()
+            .filter(|node| {
+                !node
+                    .addrs
+                    .iter()
+                    .any(|ip| self.blacklisted_nodes.contains(ip))
+            })
             .take(num_nodes)
-            .cloned()
             .collect::<Vec<_>>())
     }
 
-    fn collect_all_subnet_nodes(&self) -> Result<Vec<IpAddr>, String> {
+    /// Returns the addresses of all nodes of the subnet, from the overrides in
+    /// the config or else from their registered endpoints.
+    fn collect_all_subnet_nodes(&self) -> Result<Vec<NodeAddresses>, String> {
         let subnet_id = self.subnet_id;
         let version = self.registry_client.get_latest_version();
-        let result = match self
+        let node_ids = match self
             .registry_client
             .get_node_ids_on_subnet(subnet_id, version)
         {
-            Ok(Some(node_ids)) => Ok(node_ids
-                .into_iter()
-                .filter_map(|node_id| {
-                    self.registry_client
-                        .get_transport_info(node_id, version)
-                        .unwrap_or_default()
-                })
-                .collect::<Vec<_>>()),
-            other => Err(format!(
-                "no node ids found in the registry for subnet_id={}: {:?}",
-                subnet_id, other
-            )),
-        }?;
-        result
+            Ok(Some(node_ids)) => node_ids,
+            other => {
+                return Err(format!(
+                    "no node ids found in the registry for subnet_id={}: {:?}",
+                    subnet_id, other
+                ))
+            }
+        };
+        Ok(node_ids
             .into_iter()
-            .filter_map(|node_record| {
-                node_record.http.map(|http| {
-                    http.ip_addr.parse().map_err(|err| {
-                        format!("couldn't parse ip address from the registry: {:?}", err)
-                    })
-                })
+            .filter_map(|node_id| {
+                let addrs = match self.node_address_overrides.get(&node_id.to_string()) {
+                    Some(addrs) => addrs.clone(),
+                    None => {
+                        let node_record = self
+                            .registry_client
+                            .get_transport_info(node_id, version)
+                            .unwrap_or_default()?;
+                        node_record
+                            .http
+                            .iter()
+                            .chain(node_record.public_api.iter())
+                            .chain(node_record.private_api.iter())
+                            .chain(node_record.xnet.iter())
+                            .chain(node_record.xnet_api.iter())
+                            .filter_map(|endpoint| endpoint.ip_addr.parse().ok())
+                            .collect()
+                    }
+                };
+                let addrs = self.order_by_preference(addrs);
+                if addrs.is_empty() {
+                    warn!(self.log, "No usable address of node {}", node_id);
+                    return None;
+                }
+                Some(NodeAddresses { node_id, addrs })
             })
-            .collect()
+            .collect())
+    }
+
+    /// Deduplicates the addresses and moves those of the preferred family to
+    /// the front, keeping their order otherwise.
+    fn order_by_preference(&self, addrs: Vec<IpAddr>) -> Vec<IpAddr> {
+        let mut unique = Vec::new();
+        for addr in addrs {
+            if !unique.contains(&addr) {
+                unique.push(addr);
+            }
+        }
+        unique.sort_by_key(|addr| !self.ip_preference.prefers(addr));
+        unique
+    }
+
+    /// Returns whether the subnet is missing from the subnet list of the
+    /// latest registry version. Returns false if the list can't be read, e.g.
+    /// before the registry was fetched.
+    pub fn is_deleted_from_registry(&self) -> bool {
+        let version = self.registry_client.get_latest_version();
+        match self.registry_client.get_subnet_ids(version) {
+            Ok(Some(subnet_ids)) => !subnet_ids.contains(&self.subnet_id),
+            _ => false,
+        }
+    }
+
+    pub fn is_retired(&self) -> bool {
+        self.retired.load(Ordering::SeqCst)
+    }
+
+    /// Wraps up the backup of the subnet after it was deleted from the
+    /// registry: waits for a replay in progress to finish, moves all
+    /// remaining artifacts, and the states archived up to their heights, to
+    /// the cold storage. Then records the subnet as retired, so that it's no
+    /// longer synced nor replayed, also after a restart.
+    pub fn retire(&self) -> Result<(), String> {
+        let _replay = self.replay_guard.lock().expect("replay mutex lock failed");
+        self.move_to_cold_storage(0)?;
+
+        let _guard = self
+            .subnet_state_guard
+            .lock()
+            .expect("subnet state mutex lock failed");
+        let mut state = SubnetState::load(&self.root_dir, self.subnet_id)?;
+        state.retired_at = Some(Utc::now().to_rfc3339());
+        state.save(&self.root_dir, self.subnet_id)?;
+        self.retired.store(true, Ordering::SeqCst);
+        Ok(())
     }
 
     pub fn last_state_checkpoint(&self) -> u64 {
         last_checkpoint(&self.state_dir())
     }
 
-    pub fn replay(&self) {
+    pub fn replay(&self) {
+        let _replay = self.replay_guard.lock().expect("replay mutex lock failed");
+        // The subnet may have been retired while waiting for the guard.
+        if self.is_retired() {
+            return;
+        }
+        // Workers replay the spool of the primary, which verifies it.
+        let is_worker = self
+            .replay_sharding
+            .as_ref()
+            .map_or(false, |sharding| sharding.primary_spool_dir.is_some());
+        if self.verify_cups && !is_worker {
+            match self.has_unchecked_cups() {
+                Ok(false) => {}
+                Ok(true) => {
+                    info!(
+                        self.log,
+                        "[#{}] Postponing the replay until the synced CUPs are verified",
+                        self.thread_id
+                    );
+                    return;
+                }
+                Err(err) => {
+                    error!(
+                        self.log,
+                        "[#{}] Error checking the verification of the CUPs: {}",
+                        self.thread_id,
+                        err
+                    );
+                    return;
+                }
+            }
+        }
+        if !is_worker {
+            match self.verify_spool_before_replay() {
+                Ok(true) => {}
+                Ok(false) => {
+                    info!(
+                        self.log,
+                        "[#{}] Postponing the replay until the rejected buckets are synced again",
+                        self.thread_id
+                    );
+                    return;
+                }
+                Err(err) => {
+                    error!(
+                        self.log,
+                        "[#{}] Error verifying the spool manifests: {}", self.thread_id, err
+                    );
+                    return;
+                }
+            }
+        }
+        if let Some(sharding) = &self.replay_sharding {
+            let result = match &sharding.primary_spool_dir {
+                Some(primary_spool_dir) => self.replay_shard(sharding, primary_spool_dir),
+                None => self.coordinate_shards(sharding),
+            };
+            if let Err(err) = result {
+                error!(
+                    self.log,
+                    "[#{}] Error in the sharded replay: {}", self.thread_id, err
+                );
+                self.notification_client
+                    .report_failure_slack(format!("Sharded replay failed: {}", err));
+            }
+            return;
+        }
+
         let start_height = self.last_state_checkpoint();
         let start_time = Instant::now();
+        let until_height = match self.replay_budget.max_heights_per_replay {
+            Some(_) => match self.cup_interval() {
+                Ok(cup_interval) => self.replay_budget.until_height(start_height, cup_interval),
+                Err(err) => {
+                    warn!(
+                        self.log,
+                        "[#{}] Replaying without a height budget: {}", self.thread_id, err
+                    );
+                    None
+                }
+            },
+            None => None,
+        };
+        let deadline = self
+            .replay_budget
+            .max_duration()
+            .map(|duration| start_time + duration);
+        let mut interrupted = false;
         let mut current_replica_version =
             retrieve_replica_version_last_replayed(&self.log, self.spool_dir(), self.state_dir())
                 .unwrap_or_else(|| self.initial_replica_version.clone());
+        if let Err(err) = self.check_ic_config(&current_replica_version) {
+            warn!(
+                self.log,
+                "[#{}] Error checking the ic.json5 of version {}: {}",
+                self.thread_id,
+                current_replica_version,
+                err
+            );
+        }
 
         // replay the current version once, but if there is upgrade do it again
         loop {
-            match self.replay_current_version(&current_replica_version) {
+            match self.replay_current_version(&current_replica_version, until_height, deadline) {
                 Ok(ReplayResult::UpgradeRequired(upgrade_version)) => {
                     // replayed the current version, but if there is upgrade try to do it again
                     self.notification_client.message_slack(format!(
                         "Replica version upgrade detected (current: {} new: {}): upgrading the ic-replay tool to retry... 🤞",
                         current_replica_version, upgrade_version
                     ));
+                    if let Err(err) =
+                        self.track_upgrade(&upgrade_version, self.last_state_checkpoint())
+                    {
+                        error!(
+                            self.log,
+                            "[#{}] Error tracking the replica version upgrade: {}",
+                            self.thread_id,
+                            err
+                        );
+                    }
                     current_replica_version = upgrade_version;
                 }
-                Ok(_) => break,
+                Ok(ReplayResult::Skipped) => return,
+                Ok(ReplayResult::Done) => break,
+                Ok(ReplayResult::Interrupted) => {
+                    interrupted = true;
+                    break;
+                }
                 Err(err) => {
                     error!(self.log, "[#{}] Error replaying: {}", self.thread_id, err);
                     break;
@@ -403,9 +1587,20 @@ impl BackupHelper {
             }
         }
 
+        if self.cancellation.is_cancelled() {
+            return;
+        }
         let finish_height = self.last_state_checkpoint();
         if finish_height > start_height {
             debug!(self.log, "[#{}] Replay was successful!", self.thread_id);
+            if interrupted {
+                info!(
+                    self.log,
+                    "[#{}] The replay reached its budget at height {}, it continues in the next period",
+                    self.thread_id,
+                    finish_height
+                );
+            }
 
             if self.archive_state(finish_height).is_ok() {
                 self.notification_client.message_slack(format!(
@@ -426,11 +1621,317 @@ impl BackupHelper {
         }
     }
 
+    /// On the primary: archives the state published by the worker that
+    /// replayed the current shard, and plans the next one.
+    fn coordinate_shards(&self, sharding: &ReplaySharding) -> Result<(), String> {
+        let shared = SharedDir::new(&sharding.shared_dir, &self.subnet_id.to_string());
+        if let Some(shard) = shared.update_shard(|shard| shard.clone())? {
+            match &shard.result {
+                None => {
+                    debug!(
+                        self.log,
+                        "[#{}] Waiting for the replay of heights {}..{} by {:?}",
+                        self.thread_id,
+                        shard.start_height,
+                        shard.end_height,
+                        shard.worker
+                    );
+                    return Ok(());
+                }
+                Some(result) => {
+                    self.collect_shard(&shared, &shard, result)?;
+                    shared.update_shard(|current| *current = None)?;
+                }
+            }
+        }
+
+        let start_height = self.last_state_checkpoint();
+        let replica_version =
+            retrieve_replica_version_last_replayed(&self.log, self.spool_dir(), self.state_dir())
+                .unwrap_or_else(|| self.initial_replica_version.clone());
+        let top_height = SubnetSpool::new(self.spool_dir())
+            .version(&replica_version)
+            .top_height()
+            .get();
+        let end_height = match shard_end_height(
+            start_height,
+            sharding.range_heights,
+            self.cup_interval()?,
+            top_height,
+        ) {
+            Some(end_height) => end_height,
+            None => {
+                debug!(
+                    self.log,
+                    "[#{}] No complete DKG interval to replay after {}",
+                    self.thread_id,
+                    start_height
+                );
+                return Ok(());
+            }
+        };
+
+        // the workers fetch the config and the starting state from the shared directory
+        if !self.wait_for_cup(&replica_version, start_height)? {
+            return Ok(());
+        }
+        self.download_binaries(&replica_version)?;
+        let config_file = shared.config_file(replica_version.as_ref());
+        if let Some(dir) = config_file.parent() {
+            create_dir_all(dir).map_err(|err| format!("Error creating {:?}: {:?}", dir, err))?;
+        }
+        copy(self.ic_config_file_local(&replica_version), &config_file)
+            .map_err(|err| format!("Error publishing the config: {:?}", err))?;
+        let state_dir = shared.state_dir(start_height);
+        if !state_dir.exists() {
+            self.publish_state(&self.data_dir(), &state_dir)?;
+        }
+
+        let shard = ReplayShard::new(start_height, end_height, replica_version.to_string());
+        shared.update_shard(|current| *current = Some(shard))?;
+        info!(
+            self.log,
+            "[#{}] Planned the replay of heights {}..{} with version {}",
+            self.thread_id,
+            start_height,
+            end_height,
+            replica_version
+        );
+        Ok(())
+    }
+
+    /// Moves the state published for `shard` into the data directory of the
+    /// primary and archives it.
+    fn collect_shard(
+        &self,
+        shared: &SharedDir,
+        shard: &ReplayShard,
+        result: &ShardResult,
+    ) -> Result<(), String> {
+        if result.reached_height <= self.last_state_checkpoint() {
+            return Err(format!(
+                "Worker {:?} published the state at height {}, which isn't newer than ours",
+                shard.worker, result.reached_height
+            ));
+        }
+        let published = shared.state_dir(result.reached_height).join("ic_state");
+        let mut cmd = Command::new("rsync");
+        cmd.arg("-a")
+            .arg("--delete")
+            .arg(published.join("."))
+            .arg(self.state_dir());
+        debug!(self.log, "[#{}] Will execute: {:?}", self.thread_id, cmd);
+        exec_cmd_with_timeout(&mut cmd, TIMEOUT_LOCAL_FILE_OPS, self.log_output())
+            .map_err(|err| format!("Error copying the published state: {:?}", err))?;
+
+        self.archive_state(result.reached_height)?;
+        self.notification_client.message_slack(format!(
+            "✅ Successfully restored the state at height *{}* (replayed by {})",
+            result.reached_height,
+            shard.worker.as_deref().unwrap_or("unknown")
+        ));
+        self.notification_client
+            .push_metrics_replay_time(result.duration_secs / 60);
+        self.notification_client
+            .push_metrics_restored_height(result.reached_height);
+
+        // the reached state is the start of the next shard
+        let start_dir = shared.state_dir(shard.start_height);
+        if start_dir.exists() {
+            remove_dir_all(&start_dir)
+                .map_err(|err| format!("Error deleting {:?}: {:?}", start_dir, err))?;
+        }
+        Ok(())
+    }
+
+    /// On a worker: claims the current shard of the subnet if it is
+    /// available, replays it and publishes the resulting state.
+    fn replay_shard(
+        &self,
+        sharding: &ReplaySharding,
+        primary_spool_dir: &Path,
+    ) -> Result<(), String> {
+        let shared = SharedDir::new(&sharding.shared_dir, &self.subnet_id.to_string());
+        let worker = self.notification_client.backup_instance.clone();
+        let claimed = shared.update_shard(|shard| match shard {
+            Some(shard) if shard.is_claimable(SHARD_LEASE) => {
+                shard.claim(&worker);
+                Some(shard.clone())
+            }
+            _ => None,
+        })?;
+        let shard = match claimed {
+            Some(shard) => shard,
+            None => return Ok(()),
+        };
+        info!(
+            self.log,
+            "[#{}] Claimed the replay of heights {}..{}",
+            self.thread_id,
+            shard.start_height,
+            shard.end_height
+        );
+
+        let result = self.replay_claimed_shard(&shared, &shard, primary_spool_dir);
+        // on failure, release the claim so that another worker can retry
+        shared.update_shard(|current| {
+            if let Some(current) = current.as_mut() {
+                if current.is_claimed_by(&worker) && current.start_height == shard.start_height {
+                    match &result {
+                        Ok(result) => current.result = Some(result.clone()),
+                        Err(_) => current.worker = None,
+                    }
+                }
+            }
+        })?;
+        let result = result?;
+        info!(
+            self.log,
+            "[#{}] Replayed heights {}..{} in {} minutes",
+            self.thread_id,
+            shard.start_height,
+            result.reached_height,
+            result.duration_secs / 60
+        );
+        Ok(())
+    }
+
+    fn replay_claimed_shard(
+        &self,
+        shared: &SharedDir,
+        shard: &ReplayShard,
+        primary_spool_dir: &Path,
+    ) -> Result<ShardResult, String> {
+        let start_time = Instant::now();
+        let replica_version =
+            ReplicaVersion::try_from(shard.replica_version.as_str()).map_err(|e| e.to_string())?;
+
+        let data_dir = self.data_dir();
+        if data_dir.exists() {
+            remove_dir_all(&data_dir)
+                .map_err(|err| format!("Error deleting {:?}: {:?}", data_dir, err))?;
+        }
+        create_dir_all(&data_dir)
+            .map_err(|err| format!("Error creating {:?}: {:?}", data_dir, err))?;
+        let mut cmd = Command::new("rsync");
+        cmd.arg("-a")
+            .arg(shared.state_dir(shard.start_height).join("."))
+            .arg(&data_dir);
+        debug!(self.log, "[#{}] Will execute: {:?}", self.thread_id, cmd);
+        exec_cmd_with_timeout(&mut cmd, TIMEOUT_LOCAL_FILE_OPS, self.log_output())
+            .map_err(|err| format!("Error copying the published state: {:?}", err))?;
+
+        {
+            let _guard = self
+                .downloads_guard
+                .lock()
+                .expect("downloads mutex lock failed");
+            self.download_binary("ic-replay", &replica_version)?;
+            self.download_binary("sandbox_launcher", &replica_version)?;
+            self.download_binary("canister_sandbox", &replica_version)?;
+        }
+        copy(
+            shared.config_file(&shard.replica_version),
+            self.ic_config_file_local(&replica_version),
+        )
+        .map_err(|err| format!("Error copying the published config: {:?}", err))?;
+        let cli = self.replay_cli(&replica_version)?;
+        if !cli.replay_until_height {
+            return Err(format!(
+                "The ic-replay of version {} can't stop at a height, which the sharded replay needs, replay it manually",
+                replica_version
+            ));
+        }
+
+        let mut cmd = Command::new(self.binary_file("ic-replay", &replica_version));
+        cmd.arg("--data-root")
+            .arg(&data_dir)
+            .arg("--subnet-id")
+            .arg(&self.subnet_id.to_string())
+            .arg("--replay-until-height")
+            .arg(shard.end_height.to_string())
+            .arg(&self.ic_config_file_local(&replica_version))
+            .arg(cli.restore_subcommand)
+            .arg(&self.local_store_dir())
+            .arg(primary_spool_dir)
+            .arg(&shard.replica_version)
+            .arg(shard.start_height.to_string());
+        debug!(self.log, "[#{}] Will execute: {:?}", self.thread_id, cmd);
+        // The replay prints a line per height, log only its warnings.
+        let log_stderr = |stream: OutputStream, line: &str| {
+            if stream == OutputStream::Stderr {
+                warn!(self.log, "[#{}] {}", self.thread_id, line);
+            }
+        };
+        let output = exec_cmd_with_input_and_timeout(
+            &mut cmd,
+            REPLAY_UNTIL_HEIGHT_CONSENT,
+            TIMEOUT_REPLAY,
+            log_stderr,
+        )
+        .map_err(|err| format!("Error replaying: {:?}", err))?;
+        let upgrade_version = self.check_upgrade_request(output.stdout);
+
+        // Only an upgrade stops the replay before the end of the shard.
+        let reached_height = self.last_state_checkpoint();
+        if reached_height <= shard.start_height {
+            return Err(format!(
+                "No progress replaying from height {}",
+                shard.start_height
+            ));
+        }
+        if upgrade_version.is_none() && reached_height != shard.end_height {
+            return Err(format!(
+                "The replay of heights {}..{} stopped at height {}",
+                shard.start_height, shard.end_height, reached_height
+            ));
+        }
+        self.publish_state(&data_dir, &shared.state_dir(reached_height))?;
+        Ok(ShardResult {
+            reached_height,
+            upgrade_version,
+            duration_secs: start_time.elapsed().as_secs(),
+        })
+    }
+
+    /// Copies the data directory to `target` in the shared directory, such
+    /// that `target` only appears once complete.
+    fn publish_state(&self, data_dir: &Path, target: &Path) -> Result<(), String> {
+        let tmp_dir = target.with_extension("tmp");
+        if tmp_dir.exists() {
+            remove_dir_all(&tmp_dir)
+                .map_err(|err| format!("Error deleting {:?}: {:?}", tmp_dir, err))?;
+        }
+        create_dir_all(&tmp_dir)
+            .map_err(|err| format!("Error creating {:?}: {:?}", tmp_dir, err))?;
+        let mut cmd = Command::new("rsync");
+        cmd.arg("-a");
+        for dir in &self.excluded_dirs {
+            cmd.arg("--exclude").arg(dir);
+        }
+        cmd.arg(data_dir.join(".")).arg(&tmp_dir);
+        debug!(self.log, "[#{}] Will execute: {:?}", self.thread_id, cmd);
+        exec_cmd_with_timeout(&mut cmd, TIMEOUT_LOCAL_FILE_OPS, self.log_output())
+            .map_err(|err| format!("Error publishing the state: {:?}", err))?;
+        rename(&tmp_dir, target).map_err(|err| format!("Error publishing the state: {:?}", err))
+    }
+
+    /// Replays the spool of `replica_version` from the last checkpoint, up to
+    /// `until_height` and until `deadline`, if set.
     fn replay_current_version(
         &self,
         replica_version: &ReplicaVersion,
+        until_height: Option<u64>,
+        deadline: Option<Instant>,
     ) -> Result<ReplayResult, String> {
         let start_height = self.last_state_checkpoint();
+        let timeout = match deadline {
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+            None => TIMEOUT_REPLAY,
+        };
+        if until_height.map_or(false, |height| start_height >= height) || timeout.is_zero() {
+            return Ok(ReplayResult::Interrupted);
+        }
         info!(
             self.log,
             "[#{}] Replaying from height #{} of subnet {:?} with version {}",
@@ -439,39 +1940,123 @@ impl BackupHelper {
             self.subnet_id,
             replica_version
         );
-        self.download_binaries(replica_version, start_height)?;
+        if !self.wait_for_cup(replica_version, start_height)? {
+            return Ok(ReplayResult::Skipped);
+        }
+        self.download_binaries(replica_version)?;
         debug!(self.log, "[#{}] Binaries are downloaded.", self.thread_id);
+        let cli = self.replay_cli(replica_version)?;
 
-        let ic_admin = self.binary_file("ic-replay", replica_version);
-        let mut cmd = Command::new(ic_admin);
+        let ic_replay = self.binary_file("ic-replay", replica_version);
+        let mut cmd = Command::new(&ic_replay);
         cmd.arg("--data-root")
             .arg(&self.data_dir())
             .arg("--subnet-id")
-            .arg(&self.subnet_id.to_string())
-            .arg(&self.ic_config_file_local(replica_version))
-            .arg("restore-from-backup")
+            .arg(&self.subnet_id.to_string());
+        match until_height {
+            Some(height) if cli.replay_until_height => {
+                cmd.arg("--replay-until-height").arg(height.to_string());
+            }
+            Some(_) => warn!(
+                self.log,
+                "[#{}] The ic-replay of version {} can't stop at a height, replaying all heights",
+                self.thread_id,
+                replica_version
+            ),
+            None => {}
+        }
+        cmd.arg(&self.ic_config_file_local(replica_version))
+            .arg(cli.restore_subcommand)
             .arg(&self.local_store_dir())
             .arg(&self.spool_root_dir())
             .arg(&replica_version.to_string())
-            .arg(start_height.to_string())
-            .stdout(Stdio::piped());
+            .arg(start_height.to_string());
         debug!(self.log, "[#{}] Will execute: {:?}", self.thread_id, cmd);
-        match exec_cmd(&mut cmd) {
+        // The replay prints a line per height, log only its warnings.
+        let log_stderr = |stream: OutputStream, line: &str| {
+            if stream == OutputStream::Stderr {
+                warn!(self.log, "[#{}] {}", self.thread_id, line);
+            }
+        };
+        let started_at = Utc::now();
+        let timeout = timeout.min(TIMEOUT_REPLAY);
+        let result = match until_height {
+            Some(_) if cli.replay_until_height => exec_cmd_with_input_and_timeout(
+                &mut cmd,
+                REPLAY_UNTIL_HEIGHT_CONSENT,
+                timeout,
+                log_stderr,
+            ),
+            _ => exec_cmd_with_timeout(&mut cmd, timeout, log_stderr),
+        };
+        match result {
+            // The state manager only ever exposes complete checkpoints, so the
+            // interrupted replay leaves the state of the last one it reached.
+            Err(_) if deadline.map_or(false, |deadline| Instant::now() >= deadline) => {
+                info!(
+                    self.log,
+                    "[#{}] Interrupted the replay at its time budget, reached height {}",
+                    self.thread_id,
+                    self.last_state_checkpoint()
+                );
+                Ok(ReplayResult::Interrupted)
+            }
             Err(e) => {
                 error!(self.log, "[#{}] Error: {}", self.thread_id, e.to_string());
                 Err(e.to_string())
             }
-            Ok(Some(stdout)) => {
+            Ok(output) if !output.stdout.is_empty() => {
+                info!(
+                    self.log,
+                    "[#{}] Replay finished in {:?}", self.thread_id, output.duration
+                );
+                let stdout = output.stdout;
                 let timestamp = Utc::now().timestamp();
                 let log_file_name = format!(
                     "{}_{:010}_{:012}.log",
                     self.subnet_id, timestamp, start_height
                 );
-                let mut file = File::create(self.logs_dir().join(log_file_name))
+                let log_file = self.logs_dir().join(log_file_name);
+                let mut file = File::create(&log_file)
                     .map_err(|err| format!("Error creating log file: {:?}", err))?;
                 file.write_all(stdout.as_bytes())
                     .map_err(|err| format!("Error writing log file: {:?}", err))?;
 
+                let end_height = self.last_state_checkpoint();
+                let files = [
+                    ic_replay.clone(),
+                    self.binary_file("sandbox_launcher", replica_version),
+                    self.binary_file("canister_sandbox", replica_version),
+                    self.ic_config_file_local(replica_version),
+                ];
+                let manifest = ReplayManifest::new(
+                    self.subnet_id,
+                    replica_version.to_string(),
+                    &files,
+                    &cmd,
+                    start_height,
+                    end_height,
+                    started_at,
+                    output.duration,
+                    log_file.clone(),
+                )
+                .and_then(|manifest| manifest.save(&ReplayManifest::path_for_log(&log_file)));
+                if let Err(err) = manifest {
+                    warn!(
+                        self.log,
+                        "[#{}] Replay manifest not written: {}", self.thread_id, err
+                    );
+                }
+                let entry = LogIndexEntry::new(log_file, start_height, end_height);
+                if let Err(err) =
+                    rotate_logs(&self.logs_dir(), self.subnet_id, entry, &self.log_rotation)
+                {
+                    warn!(
+                        self.log,
+                        "[#{}] Replay logs not rotated: {}", self.thread_id, err
+                    );
+                }
+
                 if let Some(upgrade_version) = self.check_upgrade_request(stdout) {
                     debug!(
                         self.log,
@@ -481,6 +2066,15 @@ impl BackupHelper {
                     Ok(ReplayResult::UpgradeRequired(
                         ReplicaVersion::try_from(upgrade_version).map_err(|e| e.to_string())?,
                     ))
+                } else if until_height.map_or(false, |height| end_height >= height) {
+                    debug!(
+                        self.log,
+                        "[#{}] Stopped the replay at its height budget: #{}!",
+                        self.thread_id,
+                        end_height
+                    );
+
+                    Ok(ReplayResult::Interrupted)
                 } else {
                     debug!(
                         self.log,
@@ -492,7 +2086,7 @@ impl BackupHelper {
                     Ok(ReplayResult::Done)
                 }
             }
-            Ok(None) => {
+            Ok(_) => {
                 error!(
                     self.log,
                     "[#{}] No output from the replay process!", self.thread_id
@@ -507,7 +2101,7 @@ impl BackupHelper {
         let spool_dirs = collect_spool_dirs(&self.log, self.spool_dir());
         for spool_dir in spool_dirs {
             if into_replica_version(&self.log, &spool_dir).is_some() {
-                let (top_height, _) = fetch_top_height(&spool_dir);
+                let top_height = VersionSpool::new(spool_dir.path()).top_height().get();
                 if spool_top_height < top_height {
                     spool_top_height = top_height;
                 }
@@ -538,11 +2132,10 @@ impl BackupHelper {
             DiskStats::Space => "-k",
         });
         cmd.arg(&self.root_dir);
-        match exec_cmd(&mut cmd) {
-            Ok(str) => {
+        match exec_cmd_with_timeout(&mut cmd, TIMEOUT_DISK_STATS, |_, _| {}) {
+            Ok(output) => {
+                let str = output.stdout;
                 if let Some(val) = str
-                    .as_ref()
-                    .unwrap_or(&"".to_string())
                     .lines()
                     .next_back()
                     .unwrap_or_default()
@@ -589,7 +2182,7 @@ impl BackupHelper {
         }
         cmd.arg(state_dir).arg(&archive_last_dir);
         debug!(self.log, "[#{}] Will execute: {:?}", self.thread_id, cmd);
-        if let Err(e) = exec_cmd(&mut cmd) {
+        if let Err(e) = exec_cmd_with_timeout(&mut cmd, TIMEOUT_LOCAL_FILE_OPS, self.log_output()) {
             error!(self.log, "Error: {}", e);
             self.notification_client
                 .report_failure_slack("Couldn't archive the replayed state!".to_string());
@@ -652,6 +2245,13 @@ impl BackupHelper {
     }
 
     pub fn do_move_cold_storage(&self) -> Result<(), String> {
+        self.move_to_cold_storage(self.versions_hot)
+    }
+
+    /// Moves the artifacts of all but the `versions_hot` latest replica
+    /// versions, and the states archived up to their heights, to the cold
+    /// storage.
+    fn move_to_cold_storage(&self, versions_hot: usize) -> Result<(), String> {
         let guard = self
             .artifacts_guard
             .lock()
@@ -666,8 +2266,8 @@ impl BackupHelper {
         let spool_dirs = collect_only_dirs(&self.spool_dir())?;
         let mut dir_heights = BTreeMap::new();
         spool_dirs.iter().for_each(|replica_version_dir| {
-            let (top_height, replica_version_path) = fetch_top_height(replica_version_dir);
-            dir_heights.insert(top_height, replica_version_path);
+            let spool = VersionSpool::new(replica_version_dir.path());
+            dir_heights.insert(spool.top_height().get(), spool.path().to_path_buf());
         });
         if spool_dirs.len() != dir_heights.len() {
             error!(
@@ -678,7 +2278,7 @@ impl BackupHelper {
             )
         }
         let mut max_height: u64 = 0;
-        let to_clean = dir_heights.len() - self.versions_hot;
+        let to_clean = dir_heights.len() - versions_hot;
         let work_dir = self.work_dir();
         for (height, dir) in dir_heights.iter().take(to_clean) {
             info!(
@@ -686,15 +2286,34 @@ impl BackupHelper {
                 "Artifact directory: {:?} needs to be moved to the cold storage", dir
             );
             max_height = max_height.max(*height);
+            if self.do_cold_storage {
+                let corrupted = self.verify_spool_buckets(dir, 0)?;
+                if !corrupted.is_empty() {
+                    let problems: Vec<String> = corrupted
+                        .into_iter()
+                        .flat_map(|(_, problems)| problems)
+                        .collect();
+                    return Err(format!(
+                        "The artifacts in {:?} don't match their manifests, not moving them to the cold storage: {}",
+                        dir,
+                        problems.join("; ")
+                    ));
+                }
+            }
             // move artifact dir(s)
             let mut cmd = Command::new("mv");
             cmd.arg(dir).arg(&work_dir);
             debug!(self.log, "Will execute: {:?}", cmd);
-            exec_cmd(&mut cmd).map_err(|err| format!("Error moving artifacts: {:?}", err))?;
+            exec_cmd_with_timeout(&mut cmd, TIMEOUT_LOCAL_FILE_OPS, self.log_output())
+                .map_err(|err| format!("Error moving artifacts: {:?}", err))?;
+            if let Some(replica_version) = dir.file_name() {
+                self.forget_spool_manifests_of_version(&replica_version.to_string_lossy())?;
+            }
         }
         // we have moved all the artifacts from the spool directory, so don't need the mutex guard anymore
         drop(guard);
 
+        let mut pack_stats = PackStats::default();
         if self.do_cold_storage {
             // process moved artifact dirs
             let cold_storage_artifacts_dir = self.cold_storage_artifacts_dir();
@@ -704,6 +2323,8 @@ impl BackupHelper {
                 .into_string()
                 .expect("work directory is missing or invalid");
             let pack_dirs = collect_only_dirs(&work_dir)?;
+            let mut chunks_manifest = ChunksManifest::load(&cold_storage_artifacts_dir)?;
+            let extension = self.artifacts_compression.algorithm.extension();
             for pack_dir in pack_dirs {
                 let replica_version = pack_dir
                     .file_name()
@@ -711,24 +2332,59 @@ impl BackupHelper {
                     .expect("replica version entry in work directory is missing or invalid");
                 debug!(self.log, "Packing artifacts of {}", replica_version);
                 let timestamp = Utc::now().timestamp();
-                let (top_height, _) = fetch_top_height(&pack_dir);
-                let packed_file = format!(
-                    "{}/{:010}_{:012}_{}.tgz",
-                    work_dir_str, timestamp, top_height, replica_version
-                );
-                let mut cmd = Command::new("tar");
-                cmd.arg("czvf");
-                cmd.arg(&packed_file);
-                cmd.arg("-C").arg(&work_dir);
-                cmd.arg(&replica_version);
-                debug!(self.log, "Will execute: {:?}", cmd);
-                exec_cmd(&mut cmd).map_err(|err| format!("Error packing artifacts: {:?}", err))?;
-
-                info!(self.log, "Copy packed file of {}", replica_version);
-                let mut cmd2 = Command::new("cp");
-                cmd2.arg(packed_file).arg(&cold_storage_artifacts_dir);
-                debug!(self.log, "Will execute: {:?}", cmd2);
-                exec_cmd(&mut cmd2).map_err(|err| format!("Error copying artifacts: {:?}", err))?;
+                let spool = VersionSpool::new(pack_dir.path());
+                let chunk_size_bytes = match self.artifacts_chunk_size_bytes {
+                    Some(chunk_size_bytes) => chunk_size_bytes,
+                    None => {
+                        let packed_file = format!(
+                            "{}/{:010}_{:012}_{}.{}",
+                            work_dir_str,
+                            timestamp,
+                            spool.top_height().get(),
+                            replica_version,
+                            extension
+                        );
+                        self.pack_artifacts(
+                            &work_dir,
+                            &packed_file,
+                            &[replica_version],
+                            &cold_storage_artifacts_dir,
+                            &mut pack_stats,
+                        )?;
+                        continue;
+                    }
+                };
+
+                let chunks = plan_chunks(&spool, chunk_size_bytes)
+                    .map_err(|err| format!("Error planning chunks of {:?}: {:?}", spool, err))?;
+                for chunk in chunks {
+                    // named like a bundle of the whole version, with the last
+                    // height of the chunk as its top height
+                    let file_name = format!(
+                        "{:010}_{:012}_{}.{}",
+                        timestamp, chunk.last_height, replica_version, extension
+                    );
+                    let entries: Vec<String> = chunk
+                        .buckets
+                        .iter()
+                        .map(|bucket| format!("{}/{}", replica_version, bucket))
+                        .collect();
+                    let checksum = self.pack_artifacts(
+                        &work_dir,
+                        &format!("{}/{}", work_dir_str, file_name),
+                        &entries,
+                        &cold_storage_artifacts_dir,
+                        &mut pack_stats,
+                    )?;
+                    chunks_manifest.chunks.push(ArtifactsChunk {
+                        replica_version: replica_version.clone(),
+                        first_height: chunk.first_height,
+                        last_height: chunk.last_height,
+                        file_name,
+                        checksum,
+                    });
+                }
+                chunks_manifest.save(&cold_storage_artifacts_dir)?;
             }
         }
 
@@ -744,12 +2400,18 @@ impl BackupHelper {
             max_height
         );
 
-        // clean up the archive directory now
+        // clean up the archive directory now, except for the pinned states
+        let pinned_heights = PinnedHeights::load(&self.root_dir)?.heights(self.subnet_id);
         let archive_dirs = collect_only_dirs(&self.archive_dir())?;
         let mut old_state_dirs = BTreeMap::new();
         archive_dirs.iter().for_each(|state_dir| {
             let height = height_from_dir_entry_radix(state_dir, 10);
-            if height <= max_height {
+            if height > max_height {
+                return;
+            }
+            if pinned_heights.contains(&height) {
+                info!(self.log, "Keeping the pinned state at height {}", height);
+            } else {
                 old_state_dirs.insert(height, state_dir.path());
             }
         });
@@ -762,7 +2424,8 @@ impl BackupHelper {
                 cmd.arg("-a");
                 cmd.arg(dir.1).arg(self.cold_storage_states_dir());
                 debug!(self.log, "Will execute: {:?}", cmd);
-                exec_cmd(&mut cmd).map_err(|err| format!("Error copying states: {:?}", err))?;
+                exec_cmd_with_timeout(&mut cmd, TIMEOUT_LOCAL_FILE_OPS, self.log_output())
+                    .map_err(|err| format!("Error copying states: {:?}", err))?;
                 // skip some of the states if we replay more than one per day
                 if self.daily_replays > 1 {
                     // one element is consumed in the next() call above, and one in the nth(), hence the substract 2
@@ -777,7 +2440,8 @@ impl BackupHelper {
             let mut cmd = Command::new("mv");
             cmd.arg(dir.1).arg(&trash_dir);
             debug!(self.log, "Will execute: {:?}", cmd);
-            exec_cmd(&mut cmd).map_err(|err| format!("Error moving artifacts: {:?}", err))?;
+            exec_cmd_with_timeout(&mut cmd, TIMEOUT_LOCAL_FILE_OPS, self.log_output())
+                .map_err(|err| format!("Error moving artifacts: {:?}", err))?;
         }
 
         remove_dir_all(trash_dir).map_err(|err| format!("Error deleting trashdir: {:?}", err))?;
@@ -790,10 +2454,17 @@ impl BackupHelper {
         } else {
             "Cleaned up"
         };
-        self.notification_client.message_slack(format!(
+        let mut message = format!(
             "✅ {} artifacts of subnet {:?} and states up to height *{}*, saved {}% of space and {}% of inodes.",
             action_text, self.subnet_id, max_height, old_space - new_space, old_inodes - new_inodes
-        ));
+        );
+        if pack_stats.uncompressed_bytes > 0 {
+            message.push_str(&format!(
+                " Artifacts: {}.",
+                pack_stats.summary(self.artifacts_compression.algorithm)
+            ));
+        }
+        self.notification_client.message_slack(message);
         debug!(
             self.log,
             "Finished moving old artifacts and states of subnet {:?} to the cold storage",
@@ -801,6 +2472,289 @@ impl BackupHelper {
         );
         Ok(())
     }
+
+    /// Packs the `entries` of `work_dir` into `packed_file`, records its
+    /// checksum next to it and copies both to `cold_storage_artifacts_dir`.
+    /// Returns the checksum, and adds the sizes and the duration of the
+    /// packing to `pack_stats`.
+    fn pack_artifacts(
+        &self,
+        work_dir: &Path,
+        packed_file: &str,
+        entries: &[String],
+        cold_storage_artifacts_dir: &Path,
+        pack_stats: &mut PackStats,
+    ) -> Result<String, String> {
+        debug!(
+            self.log,
+            "Packing {:?} into {} with {:?}", entries, packed_file, self.artifacts_compression
+        );
+        let stats = self
+            .artifacts_compression
+            .pack(work_dir, entries, Path::new(packed_file))?;
+        info!(
+            self.log,
+            "Packed {}: {}",
+            packed_file,
+            stats.summary(self.artifacts_compression.algorithm)
+        );
+        pack_stats.add(&stats);
+
+        // the checksum allows to verify the copy in the cold storage later
+        let checksum = compute_sha256_hex(Path::new(packed_file))
+            .map_err(|err| format!("Error hashing packed artifacts: {:?}", err))?;
+        let checksum_file = checksum_path(Path::new(packed_file));
+        let mut file = File::create(&checksum_file)
+            .map_err(|err| format!("Error creating checksum file: {:?}", err))?;
+        file.write_all(format!("{}\n", checksum).as_bytes())
+            .map_err(|err| format!("Error writing checksum: {:?}", err))?;
+
+        info!(self.log, "Copy packed file {}", packed_file);
+        let mut cmd2 = Command::new("cp");
+        cmd2.arg(packed_file)
+            .arg(checksum_file)
+            .arg(cold_storage_artifacts_dir);
+        debug!(self.log, "Will execute: {:?}", cmd2);
+        exec_cmd_with_timeout(&mut cmd2, TIMEOUT_LOCAL_FILE_OPS, self.log_output())
+            .map_err(|err| format!("Error copying artifacts: {:?}", err))?;
+        Ok(checksum)
+    }
+
+    /// Restores a random artifacts bundle and a random state of the subnet
+    /// from the cold storage to a scratch directory and checks their
+    /// integrity. With `replay`, the restored state is also advanced by
+    /// replaying the artifacts following it. Returns a summary of what was
+    /// verified.
+    pub fn verify_cold_storage(&self, replay: bool) -> Result<String, String> {
+        let scratch_dir = self.verification_dir();
+        if scratch_dir.exists() {
+            remove_dir_all(&scratch_dir)
+                .map_err(|err| format!("Error cleaning the scratch directory: {:?}", err))?;
+        }
+        info!(
+            self.log,
+            "Verifying the cold storage of subnet {:?} in {:?}", self.subnet_id, scratch_dir
+        );
+        let result = self.verify_cold_storage_in(&scratch_dir, replay);
+        if let Err(err) = remove_dir_all(&scratch_dir) {
+            warn!(self.log, "Error deleting {:?}: {:?}", scratch_dir, err);
+        }
+        result
+    }
+
+    fn verify_cold_storage_in(&self, scratch_dir: &Path, replay: bool) -> Result<String, String> {
+        let spool_root_dir = scratch_dir.join("spool");
+        let spool_dir = spool_root_dir.join(self.subnet_id.to_string());
+        let data_dir = scratch_dir.join("data");
+
+        let bundles = self.cold_storage_bundles()?;
+        let states = collect_only_dirs(&self.cold_storage_states_dir())?;
+        let mut rng = thread_rng();
+        let bundle = bundles
+            .choose(&mut rng)
+            .ok_or("No artifacts in the cold storage")?;
+        let state = states
+            .choose(&mut rng)
+            .ok_or("No states in the cold storage")?;
+
+        self.verify_artifacts_bundle(bundle, &spool_dir)?;
+        let state_height = self.verify_archived_state(&state.path(), &data_dir)?;
+        let mut summary = format!(
+            "artifacts of version {} up to height {}, state at height {}",
+            bundle.replica_version, bundle.top_height, state_height
+        );
+        if !replay {
+            return Ok(summary);
+        }
+
+        // the artifacts following the state are in the next bundle
+        let next = bundles
+            .iter()
+            .filter(|b| b.top_height > state_height)
+            .min_by_key(|b| b.top_height);
+        match next {
+            Some(next) => {
+                if next.path != bundle.path {
+                    self.verify_artifacts_bundle(next, &spool_dir)?;
+                }
+                let height = self.verify_replay(next, state_height, &data_dir, &spool_root_dir)?;
+                summary.push_str(&format!(", replayed up to height {}", height));
+            }
+            None => summary.push_str(", no artifacts to replay on top of the state"),
+        }
+        Ok(summary)
+    }
+
+    fn cold_storage_bundles(&self) -> Result<Vec<ArtifactsBundle>, String> {
+        let dir = self.cold_storage_artifacts_dir();
+        Ok(read_dir(&dir)
+            .map_err(|e| format!("Error reading directory {dir:?}: {e}"))?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| CompressionAlgorithm::of_bundle(path).is_some())
+            .filter_map(|path| {
+                let stem = path.file_stem()?.to_str()?.to_string();
+                let mut parts = stem.splitn(3, '_');
+                let _timestamp = parts.next()?;
+                let top_height = parts.next()?.parse().ok()?;
+                let replica_version = ReplicaVersion::try_from(parts.next()?).ok()?;
+                Some(ArtifactsBundle {
+                    path,
+                    top_height,
+                    replica_version,
+                })
+            })
+            .collect())
+    }
+
+    /// Checks the bundle against its recorded checksum, unpacks it into
+    /// `spool_dir` and checks that it contains the artifacts up to the height
+    /// in its name.
+    fn verify_artifacts_bundle(
+        &self,
+        bundle: &ArtifactsBundle,
+        spool_dir: &Path,
+    ) -> Result<(), String> {
+        let checksum_file = checksum_path(&bundle.path);
+        if checksum_file.exists() {
+            let expected = read_to_string(&checksum_file)
+                .map_err(|err| format!("Error reading {:?}: {:?}", checksum_file, err))?;
+            let actual = compute_sha256_hex(&bundle.path)
+                .map_err(|err| format!("Error hashing {:?}: {:?}", bundle.path, err))?;
+            if expected.trim() != actual {
+                return Err(format!(
+                    "Checksum mismatch of {:?}: expected {}, got {}",
+                    bundle.path,
+                    expected.trim(),
+                    actual
+                ));
+            }
+        } else {
+            // bundles packed before checksums were recorded
+            warn!(self.log, "No checksum recorded for {:?}", bundle.path);
+        }
+
+        create_dir_all(spool_dir)
+            .map_err(|err| format!("Error creating {:?}: {:?}", spool_dir, err))?;
+        debug!(self.log, "Unpacking {:?} into {:?}", bundle.path, spool_dir);
+        unpack(&bundle.path, spool_dir)?;
+
+        let top_height = VersionSpool::new(spool_dir.join(bundle.replica_version.to_string()))
+            .top_height()
+            .get();
+        if top_height != bundle.top_height {
+            return Err(format!(
+                "Unpacked {:?} has top height {} instead of {}",
+                bundle.path, top_height, bundle.top_height
+            ));
+        }
+        Ok(())
+    }
+
+    /// Copies the archived state into `data_dir` and checks that it contains a
+    /// non-empty checkpoint, whose height is returned.
+    fn verify_archived_state(&self, state_dir: &Path, data_dir: &Path) -> Result<u64, String> {
+        create_dir_all(data_dir)
+            .map_err(|err| format!("Error creating {:?}: {:?}", data_dir, err))?;
+        let mut cmd = Command::new("rsync");
+        cmd.arg("-a").arg(state_dir.join(".")).arg(data_dir);
+        debug!(self.log, "Will execute: {:?}", cmd);
+        exec_cmd_with_timeout(&mut cmd, TIMEOUT_LOCAL_FILE_OPS, self.log_output())
+            .map_err(|err| format!("Error copying {:?}: {:?}", state_dir, err))?;
+
+        let state_dir = data_dir.join("ic_state");
+        let height = last_checkpoint(&state_dir);
+        if height == 0 {
+            return Err(format!("No checkpoint in the state at {:?}", state_dir));
+        }
+        let checkpoints_dir = state_dir.join("checkpoints");
+        let non_empty = read_dir(&checkpoints_dir)
+            .map_err(|e| format!("Error reading directory {checkpoints_dir:?}: {e}"))?
+            .flatten()
+            .filter(|entry| height_from_dir_entry(entry) == height)
+            .any(|entry| {
+                read_dir(entry.path())
+                    .map(|mut files| files.next().is_some())
+                    .unwrap_or(false)
+            });
+        if !non_empty {
+            return Err(format!(
+                "Checkpoint at height {} of the state at {:?} is empty",
+                height, state_dir
+            ));
+        }
+        Ok(height)
+    }
+
+    /// Replays the unpacked artifacts of the bundle on top of the state at
+    /// `start_height` and returns the height of the resulting checkpoint.
+    fn verify_replay(
+        &self,
+        bundle: &ArtifactsBundle,
+        start_height: u64,
+        data_dir: &Path,
+        spool_root_dir: &Path,
+    ) -> Result<u64, String> {
+        let replica_version = &bundle.replica_version;
+        {
+            let _guard = self
+                .downloads_guard
+                .lock()
+                .expect("downloads mutex lock failed");
+            self.download_binary("ic-replay", replica_version)?;
+            self.download_binary("sandbox_launcher", replica_version)?;
+            self.download_binary("canister_sandbox", replica_version)?;
+        }
+        let ic_config = self.ic_config_file_local(replica_version);
+        if !ic_config.exists() {
+            return Err(format!("Missing the config of version {}", replica_version));
+        }
+        let cli = self.replay_cli(replica_version)?;
+
+        let mut cmd = Command::new(self.binary_file("ic-replay", replica_version));
+        cmd.arg("--data-root")
+            .arg(data_dir)
+            .arg("--subnet-id")
+            .arg(&self.subnet_id.to_string())
+            .arg(&ic_config)
+            .arg(cli.restore_subcommand)
+            .arg(&self.local_store_dir())
+            .arg(spool_root_dir)
+            .arg(&replica_version.to_string())
+            .arg(start_height.to_string());
+        debug!(self.log, "Will execute: {:?}", cmd);
+        // The replay prints a line per height, log only its warnings.
+        let log_stderr = |stream: OutputStream, line: &str| {
+            if stream == OutputStream::Stderr {
+                warn!(self.log, "{}", line);
+            }
+        };
+        exec_cmd_with_timeout(&mut cmd, TIMEOUT_REPLAY, log_stderr)
+            .map_err(|err| format!("Error replaying: {:?}", err))?;
+
+        let height = last_checkpoint(&data_dir.join("ic_state"));
+        if height <= start_height {
+            return Err(format!(
+                "No progress replaying from height {} with version {}",
+                start_height, replica_version
+            ));
+        }
+        Ok(height)
+    }
+}
+
+/// The host part of an rsync or ssh destination.
+fn remote_host(addr: &IpAddr) -> String {
+    match addr {
+        IpAddr::V4(addr) => addr.to_string(),
+        IpAddr::V6(addr) => format!("[{}]", addr),
+    }
+}
+
+fn checksum_path(file: &Path) -> PathBuf {
+    let mut path = file.as_os_str().to_os_string();
+    path.push(format!(".{}", CHECKSUM_EXTENSION));
+    PathBuf::from(path)
 }
 
 fn into_replica_version(log: &Logger, spool_dir: &DirEntry) -> Option<ReplicaVersion> {
@@ -826,20 +2780,6 @@ fn collect_only_dirs(path: &PathBuf) -> Result<Vec<DirEntry>, String> {
         .collect())
 }
 
-fn fetch_top_height(replica_version_dir: &DirEntry) -> (u64, PathBuf) {
-    let replica_version_path = replica_version_dir.path();
-    let height_bucket = last_dir_height(&replica_version_path, 10);
-    let top_height = last_dir_height(&replica_version_path.join(format!("{}", height_bucket)), 10);
-    (top_height, replica_version_path)
-}
-
-fn is_height_in_spool(replica_version_dir: &DirEntry, height: u64) -> bool {
-    let replica_version_path = replica_version_dir.path();
-    let height_bucket = height / BUCKET_SIZE * BUCKET_SIZE;
-    let path = replica_version_path.join(format!("{}/{}", height_bucket, height));
-    path.exists()
-}
-
 fn height_from_dir_entry_radix(filename: &DirEntry, radix: u32) -> u64 {
     let height = filename
         .path()
@@ -872,6 +2812,14 @@ fn last_checkpoint(dir: &Path) -> u64 {
     last_dir_height(&dir.join("checkpoints"), 16)
 }
 
+/// The heights of the states in an archive directory.
+pub fn archived_heights(archive_dir: &PathBuf) -> Result<BTreeSet<u64>, String> {
+    Ok(collect_only_dirs(archive_dir)?
+        .iter()
+        .map(|state_dir| height_from_dir_entry_radix(state_dir, 10))
+        .collect())
+}
+
 fn create_if_not_exists(dir: PathBuf) -> PathBuf {
     if !dir.exists() {
         create_dir_all(&dir).unwrap_or_else(|e| panic!("Failure creating directory {dir:?}: {e}"));
@@ -908,8 +2856,9 @@ pub fn retrieve_replica_version_last_replayed(
     let spool_dirs = collect_spool_dirs(log, spool_dir);
     for spool_dir in spool_dirs {
         let replica_version = into_replica_version(log, &spool_dir);
-        if is_height_in_spool(&spool_dir, last_checkpoint) && replica_version.is_some() {
-            let (top_height, _) = fetch_top_height(&spool_dir);
+        let spool = VersionSpool::new(spool_dir.path());
+        if spool.contains_height(Height::from(last_checkpoint)) && replica_version.is_some() {
+            let top_height = spool.top_height().get();
             if max_height < top_height {
                 max_height = top_height;
                 current_replica_version = replica_version;
@@ -1,33 +1,42 @@
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashSet},
     fs, io,
     path::PathBuf,
     process::Command,
     str::FromStr,
-    sync::{Arc, Mutex},
-    thread,
-    time::{Duration, Instant},
+    sync::{atomic::AtomicBool, Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+use ic_config::Secret;
 use ic_crypto_utils_threshold_sig_der::parse_threshold_sig_key;
 use ic_logger::ReplicaLogger;
 use ic_recovery::command_helper::exec_cmd;
 use ic_registry_client::client::RegistryClientImpl;
-use ic_registry_local_store::LocalStoreImpl;
+use ic_registry_local_store::{LocalStoreCertifiedTimeReader, LocalStoreImpl};
 use ic_registry_replicator::RegistryReplicator;
 use ic_types::{PrincipalId, ReplicaVersion, SubnetId};
 use slog::{error, info, Logger};
-use tokio::runtime::Handle;
+use tokio::task::{spawn_blocking, JoinHandle};
 
 use crate::{
-    backup_helper::retrieve_replica_version_last_replayed,
-    util::{block_on, sleep_secs},
+    artifacts_chunks::{restore_chunk, ChunksManifest},
+    backup_helper::{archived_heights, retrieve_replica_version_last_replayed},
+    util::{block_on, sleep_secs, Cancellation},
 };
 use crate::{
     backup_helper::BackupHelper,
     cmd::BackupArgs,
-    config::{ColdStorage, Config, SubnetConfig},
+    config::{ColdStorage, Config, ReplayBudget, SubnetConfig},
+    cup_verification::CupChecks,
+    daemon_lock::DaemonLock,
+    log_rotation::LogIndex,
+    metrics_textfile::MetricsTextfile,
     notification_client::NotificationClient,
+    pinned_heights::PinnedHeights,
+    replay_manifest::ReplayManifest,
+    spool_import::import_spool,
+    subnet_state::{SubnetState, VersionSource},
 };
 
 const DEFAULT_SYNC_NODES: usize = 5;
@@ -37,6 +46,9 @@ const DEFAULT_VERSIONS_HOT: usize = 2;
 const SECONDS_IN_DAY: u64 = 24u64 * 60 * 60;
 const COLD_STORAGE_PERIOD: u64 = 60 * 60; // each hour
 const PERIODIC_METRICS_PUSH_PERIOD: u64 = 5 * 60; // each 5 min
+const REGISTRY_STALENESS_WARNING: Duration = Duration::from_secs(30 * 60);
+const DEFAULT_SPOOL_STALL_ALERT_MINS: u64 = 60;
+const DEFAULT_CUP_WAIT_TIMEOUT_MINS: u64 = 120;
 
 struct SubnetBackup {
     pub nodes_syncing: usize,
@@ -45,6 +57,33 @@ struct SubnetBackup {
     pub backup_helper: BackupHelper,
 }
 
+/// Tracks the top height of a subnet's spool over time, to notice when the
+/// nodes stop delivering new artifacts.
+struct SpoolTip {
+    height: u64,
+    advanced_at: Instant,
+    alerted: bool,
+}
+
+impl SpoolTip {
+    fn new(height: u64) -> Self {
+        Self {
+            height,
+            advanced_at: Instant::now(),
+            alerted: false,
+        }
+    }
+
+    /// Records the current top `height` and returns for how long it hasn't
+    /// advanced.
+    fn observe(&mut self, height: u64) -> Duration {
+        if height > self.height {
+            *self = Self::new(height);
+        }
+        self.advanced_at.elapsed()
+    }
+}
+
 pub struct BackupManager {
     pub version: u32,
     pub root_dir: PathBuf,
@@ -52,19 +91,34 @@ pub struct BackupManager {
     pub registry_client: Arc<RegistryClientImpl>,
     pub registry_replicator: Arc<RegistryReplicator>,
     subnet_backups: Vec<SubnetBackup>,
+    spool_stall_alert: Option<Duration>,
+    verification_period: Option<Duration>,
+    verification_replay: bool,
+    metrics_textfile: Option<Arc<MetricsTextfile>>,
+    cancellation: Cancellation,
+    _daemon_lock: DaemonLock,
     pub log: Logger,
 }
 
 impl BackupManager {
-    pub fn new(log: Logger, args: BackupArgs, rt: &Handle) -> Self {
+    /// Must be called from a blocking context on the runtime of the daemon.
+    pub fn new(log: Logger, args: BackupArgs, cancellation: Cancellation) -> Self {
         let config = Config::load_config(args.config_file).expect("Config file can't be loaded");
         // verification that all is initialized with the init command
         if config.subnets.is_empty() {
             panic!("No subnets are configured for backup")
         }
+        let daemon_lock = match DaemonLock::acquire(&config.root_dir) {
+            Ok(lock) => lock,
+            Err(err) => panic!("{}", err),
+        };
         let ColdStorage {
             cold_storage_dir,
             versions_hot,
+            verification_period_hours,
+            verification_replay,
+            artifacts_chunk_size_mb,
+            artifacts_compression,
         } = match config.cold_storage {
             Some(cs) => cs,
             None => panic!("Cold storage and cleanup are not configured"),
@@ -88,19 +142,19 @@ impl BackupManager {
         ));
         let nns_public_key =
             parse_threshold_sig_key(&config.nns_pem).expect("Missing NNS public key");
-        let nns_urls = vec![config.nns_url.expect("Missing NNS Url")];
+        let nns_urls = config.all_nns_urls();
+        info!(
+            log,
+            "Fetching the registry from NNS endpoints: {:?}", nns_urls
+        );
         let reg_replicator2 = registry_replicator.clone();
 
         info!(log.clone(), "Starting the registry replicator");
-        block_on(async {
-            rt.spawn(async move {
-                reg_replicator2
-                    .start_polling(nns_urls, Some(nns_public_key))
-                    .await
-                    .expect("Failed to start registry replicator");
-            })
-            .await
-            .expect("Task spawned in Tokio executor panicked")
+        block_on(async move {
+            reg_replicator2
+                .start_polling(nns_urls, Some(nns_public_key))
+                .await
+                .expect("Failed to start registry replicator");
         });
         info!(log.clone(), "Fetch and start polling");
         if let Err(err) = registry_client.fetch_and_start_polling() {
@@ -115,11 +169,30 @@ impl BackupManager {
         let downloads = Arc::new(Mutex::new(true));
         let disk_threshold_warn = config.disk_threshold_warn;
         let blacklisted = Arc::new(config.blacklisted_nodes.unwrap_or_default());
+        let node_address_overrides = Arc::new(config.node_address_overrides);
+        let spool_stall_alert = match config
+            .spool_stall_alert_mins
+            .unwrap_or(DEFAULT_SPOOL_STALL_ALERT_MINS)
+        {
+            0 => None,
+            mins => Some(Duration::from_secs(mins * 60)),
+        };
+        let cup_wait_timeout = Duration::from_secs(
+            config
+                .cup_wait_timeout_mins
+                .unwrap_or(DEFAULT_CUP_WAIT_TIMEOUT_MINS)
+                * 60,
+        );
+
+        let metrics_textfile = config
+            .metrics_textfile
+            .map(|path| Arc::new(MetricsTextfile::new(path)));
 
         for s in config.subnets {
             let notification_client = NotificationClient {
                 push_metrics: config.push_metrics,
                 metrics_urls: config.metrics_urls.clone(),
+                metrics_textfile: metrics_textfile.clone(),
                 network_name: config.network_name.clone(),
                 backup_instance: config.backup_instance.clone(),
                 slack_token: config.slack_token.clone(),
@@ -142,11 +215,29 @@ impl BackupManager {
                 disk_threshold_warn,
                 cold_storage_dir: cold_storage_dir.clone(),
                 versions_hot,
+                artifacts_chunk_size_bytes: artifacts_chunk_size_mb.map(|mb| mb * 1024 * 1024),
+                artifacts_compression: artifacts_compression.clone(),
                 artifacts_guard: Mutex::new(true),
+                subnet_state_guard: Mutex::new(true),
+                replay_guard: Mutex::new(true),
                 daily_replays,
                 do_cold_storage,
                 thread_id: s.thread_id,
                 blacklisted_nodes: blacklisted.clone(),
+                ip_preference: config.ip_preference,
+                node_address_overrides: node_address_overrides.clone(),
+                log_rotation: config.log_rotation.clone(),
+                replay_sharding: s.replay_sharding,
+                replay_budget: s.replay_budget,
+                cup_wait_timeout,
+                verify_cups: config.verify_cups,
+                cup_checks: Mutex::new(CupChecks::default()),
+                replay_clis: Mutex::new(BTreeMap::new()),
+                retired: AtomicBool::new(
+                    SubnetState::load(&config.root_dir, s.subnet_id)
+                        .map_or(false, |state| state.retired_at.is_some()),
+                ),
+                cancellation: cancellation.clone(),
                 log: log.clone(),
             };
             let sync_period = std::time::Duration::from_secs(s.sync_period_secs);
@@ -165,6 +256,15 @@ impl BackupManager {
             registry_client,
             registry_replicator, // it will be used as a background task, so keep it
             subnet_backups: backups,
+            spool_stall_alert,
+            verification_period: match verification_period_hours {
+                0 => None,
+                hours => Some(Duration::from_secs(hours * 60 * 60)),
+            },
+            verification_replay,
+            metrics_textfile,
+            cancellation,
+            _daemon_lock: daemon_lock,
             log,
         }
     }
@@ -178,14 +278,277 @@ impl BackupManager {
         println!("{}", replica_version)
     }
 
+    pub fn show_replay(config_file: PathBuf, subnet_id: SubnetId, height: u64) {
+        let config = Config::load_config(config_file).expect("Config file can't be loaded");
+        let logs_dir = config.root_dir.join("logs");
+        let indexed = LogIndex::load(&logs_dir, subnet_id)
+            .ok()
+            .and_then(|index| index.find(height).cloned());
+        let manifest = match indexed {
+            Some(entry) => ReplayManifest::load(&entry.manifest_file),
+            None => ReplayManifest::find(&logs_dir, subnet_id, height),
+        };
+        match manifest {
+            Ok(manifest) => {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&manifest)
+                        .expect("Replay manifest can't be serialized")
+                );
+                println!("\nTo reproduce:\n{}", manifest.shell_command());
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    /// Restores the chunk of the artifacts of a subnet holding `height` from
+    /// the cold storage into `<target_dir>/spool/<subnet_id>`.
+    pub fn restore(config_file: PathBuf, subnet_id: SubnetId, height: u64, target_dir: PathBuf) {
+        let config = Config::load_config(config_file).expect("Config file can't be loaded");
+        let cold_storage = config.cold_storage.expect("Cold storage is not configured");
+        let artifacts_dir = cold_storage
+            .cold_storage_dir
+            .join(format!("{}/artifacts", subnet_id));
+        let manifest =
+            ChunksManifest::load(&artifacts_dir).expect("Chunks manifest can't be loaded");
+        let chunk = match manifest.find(height) {
+            Some(chunk) => chunk,
+            None => {
+                eprintln!(
+                    "No chunk in {:?} holds the artifacts at height {}",
+                    artifacts_dir, height
+                );
+                std::process::exit(1);
+            }
+        };
+        let spool_dir = target_dir.join(format!("spool/{}", subnet_id));
+        match restore_chunk(
+            &artifacts_dir,
+            chunk,
+            &target_dir.join("downloads"),
+            &spool_dir,
+        ) {
+            Ok(()) => println!(
+                "Restored the artifacts of version {} at heights {} to {} into {:?}",
+                chunk.replica_version, chunk.first_height, chunk.last_height, spool_dir
+            ),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    /// Imports a manual copy of the backup directory of a node into the
+    /// spool of a subnet, see [crate::spool_import].
+    pub fn import(config_file: PathBuf, subnet_id: SubnetId, src_dir: PathBuf) {
+        let config = Config::load_config(config_file).expect("Config file can't be loaded");
+        if !config.subnets.iter().any(|s| s.subnet_id == subnet_id) {
+            eprintln!("Subnet {} is not configured for backup", subnet_id);
+            std::process::exit(1);
+        }
+        // the daemon holds the lock while it runs, so it can't sync into the
+        // spool while we import into it
+        let _daemon_lock = match DaemonLock::acquire(&config.root_dir) {
+            Ok(lock) => lock,
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        };
+        match import_spool(&config.root_dir, subnet_id, &src_dir) {
+            Ok(report) => {
+                println!(
+                    "Imported {} files at {} heights into {} buckets, skipped {} files already in the spool",
+                    report.imported_files,
+                    report.heights,
+                    report.buckets.len(),
+                    report.duplicate_files
+                );
+                if !report.conflicts.is_empty() {
+                    println!(
+                        "Kept the files of the spool over {} conflicting files:",
+                        report.conflicts.len()
+                    );
+                    report
+                        .conflicts
+                        .iter()
+                        .for_each(|conflict| println!("  {}", conflict));
+                }
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    pub fn pin(config_file: PathBuf, subnet_id: SubnetId, height: u64) {
+        let config = Config::load_config(config_file).expect("Config file can't be loaded");
+        let state_dir = config
+            .root_dir
+            .join(format!("archive/{}/{}", subnet_id, height));
+        if !state_dir.exists() {
+            eprintln!("There is no archived state at {:?}", state_dir);
+            std::process::exit(1);
+        }
+        let mut pinned_heights =
+            PinnedHeights::load(&config.root_dir).expect("Pinned heights can't be loaded");
+        if pinned_heights.pin(subnet_id, height) {
+            pinned_heights
+                .save(&config.root_dir)
+                .expect("Pinned heights couldn't be saved");
+            println!("Pinned height {} of subnet {}", height, subnet_id);
+        } else {
+            println!(
+                "Height {} of subnet {} is already pinned",
+                height, subnet_id
+            );
+        }
+    }
+
+    pub fn unpin(config_file: PathBuf, subnet_id: SubnetId, height: u64) {
+        let config = Config::load_config(config_file).expect("Config file can't be loaded");
+        let mut pinned_heights =
+            PinnedHeights::load(&config.root_dir).expect("Pinned heights can't be loaded");
+        if pinned_heights.unpin(subnet_id, height) {
+            pinned_heights
+                .save(&config.root_dir)
+                .expect("Pinned heights couldn't be saved");
+            println!("Unpinned height {} of subnet {}", height, subnet_id);
+        } else {
+            println!("Height {} of subnet {} is not pinned", height, subnet_id);
+        }
+    }
+
+    /// Resumes the backup of a subnet that was retired, e.g. because it was
+    /// wrongly found to be deleted from the registry. Takes effect when the
+    /// backup is restarted.
+    pub fn unretire(config_file: PathBuf, subnet_id: SubnetId) {
+        let config = Config::load_config(config_file).expect("Config file can't be loaded");
+        let mut state =
+            SubnetState::load(&config.root_dir, subnet_id).expect("Subnet state can't be loaded");
+        if state.retired_at.take().is_none() {
+            println!("Subnet {} is not retired", subnet_id);
+            return;
+        }
+        state
+            .save(&config.root_dir, subnet_id)
+            .expect("Subnet state couldn't be saved");
+        println!(
+            "Unretired subnet {}, its backup resumes when ic-backup is restarted",
+            subnet_id
+        );
+    }
+
+    pub fn status(config_file: PathBuf) {
+        let config = Config::load_config(config_file).expect("Config file can't be loaded");
+        let pinned_heights =
+            PinnedHeights::load(&config.root_dir).expect("Pinned heights can't be loaded");
+        for subnet in &config.subnets {
+            let archive_dir = config
+                .root_dir
+                .join(format!("archive/{}", subnet.subnet_id));
+            let archived = archived_heights(&archive_dir).unwrap_or_default();
+            println!("Subnet {}", subnet.subnet_id);
+            let state = SubnetState::load(&config.root_dir, subnet.subnet_id).unwrap_or_default();
+            if let Some(retired_at) = &state.retired_at {
+                println!("  retired at {}, deleted from the registry", retired_at);
+            }
+            for (replica_version, unavailable) in &state.unavailable_binaries {
+                if unavailable.blocked {
+                    println!(
+                        "  blocked replica version {}: {} unavailable since {}",
+                        replica_version, unavailable.binary, unavailable.failing_since
+                    );
+                }
+            }
+            for gap in &state.unrecoverable_gaps {
+                println!(
+                    "  unrecoverable gap: heights {}..{}, detected at {}",
+                    gap.from_height, gap.to_height, gap.detected_at
+                );
+            }
+            println!(
+                "  archived states: {} (latest height: {})",
+                archived.len(),
+                archived
+                    .iter()
+                    .last()
+                    .map_or("none".to_string(), |height| height.to_string())
+            );
+            let pinned = pinned_heights.heights(subnet.subnet_id);
+            if pinned.is_empty() {
+                println!("  pinned heights: none");
+            } else {
+                println!(
+                    "  pinned heights: {}",
+                    pinned
+                        .iter()
+                        .map(|height| height.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+        }
+    }
+
+    pub fn versions(config_file: PathBuf, subnet_id: SubnetId) {
+        let config = Config::load_config(config_file).expect("Config file can't be loaded");
+        let state =
+            SubnetState::load(&config.root_dir, subnet_id).expect("Subnet state can't be loaded");
+        if state.version_transitions.is_empty() {
+            println!("No replica versions observed for subnet {}", subnet_id);
+            return;
+        }
+        for transition in &state.version_transitions {
+            let source = match transition.source {
+                VersionSource::Spool => "spool",
+                VersionSource::Replay => "replay",
+            };
+            println!(
+                "{} from height {} (first seen in the {} at {})",
+                transition.replica_version, transition.height, source, transition.observed_at
+            );
+        }
+    }
+
     pub fn upgrade(log: Logger, config_file: PathBuf) {
-        let config = Config::load_config(config_file.clone()).expect("Config file can't be loaded");
+        let mut config =
+            Config::load_config(config_file.clone()).expect("Config file can't be loaded");
+        config.nns_urls = config.all_nns_urls();
+        config.nns_url = None;
         config
             .save_config(config_file)
             .expect("Config file couldn't be saved");
         info!(log, "Configuration updated...");
     }
 
+    /// Prints all problems of the config file, and exits with an error if
+    /// there are any.
+    pub fn check_config(config_file: PathBuf) {
+        let config = match Config::parse_config(&config_file) {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        };
+        let problems = config.problems();
+        if problems.is_empty() {
+            println!("{:?} is valid", config_file);
+            return;
+        }
+        for problem in &problems {
+            eprintln!("{}", problem);
+        }
+        eprintln!("{} problem(s) found in {:?}", problems.len(), config_file);
+        std::process::exit(1);
+    }
+
     pub fn init(log: Logger, config_file: PathBuf) {
         let config = BackupManager::init_config(config_file);
         BackupManager::init_copy_states(log, config);
@@ -273,13 +636,15 @@ impl BackupManager {
                 replay_period_secs,
                 thread_id,
                 disable_cold_storage: false,
+                replay_sharding: None,
+                replay_budget: ReplayBudget::default(),
             })
         }
 
         println!("Enter the Slack token:");
         let mut slack_token = String::new();
         let _ = stdin.read_line(&mut slack_token);
-        config.slack_token = slack_token.trim().to_string();
+        config.slack_token = Secret::new(slack_token.trim().to_string());
 
         let cold_storage_dir = loop {
             println!("Enter the directory for the cold storage:");
@@ -314,6 +679,10 @@ impl BackupManager {
         config.cold_storage = Some(ColdStorage {
             cold_storage_dir,
             versions_hot,
+            verification_period_hours: 0,
+            verification_replay: false,
+            artifacts_chunk_size_mb: None,
+            artifacts_compression: Default::default(),
         });
 
         config
@@ -365,15 +734,35 @@ impl BackupManager {
         }
     }
 
-    pub fn do_backups(self: Arc<BackupManager>) {
+    /// Returns the time since the local registry was last certified by the
+    /// NNS.
+    fn registry_staleness(&self) -> Duration {
+        let certified = Duration::from_nanos(
+            self.local_store
+                .read_certified_time()
+                .as_nanos_since_unix_epoch(),
+        );
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .saturating_sub(certified)
+    }
+
+    /// Spawns the sync, replay and cold storage tasks and pushes the progress
+    /// metrics until the daemon is cancelled. Then waits for the tasks to
+    /// wrap up. The syncs and replays themselves, including their retry waits,
+    /// each hold a thread of the blocking pool while they run.
+    pub async fn do_backups(self: Arc<BackupManager>) {
         let size = self.subnet_backups.len();
+        let mut tasks: Vec<JoinHandle<()>> = Vec::new();
 
         for i in 0..size {
             // should we sync the subnet
-            if self.subnet_backups[i].sync_period >= Duration::from_secs(1) {
+            if self.subnet_backups[i].sync_period >= Duration::from_secs(1)
+                && !self.subnet_backups[i].backup_helper.is_retired()
+            {
                 self.subnet_backups[i].backup_helper.create_spool_dir();
-                let m = self.clone();
-                thread::spawn(move || sync_subnet(m, i));
+                tasks.push(tokio::spawn(sync_subnet(self.clone(), i)));
             }
         }
 
@@ -384,56 +773,176 @@ impl BackupManager {
                 let id = self.subnet_backups[i].backup_helper.thread_id;
                 if !thread_ids.contains(&id) {
                     thread_ids.insert(id);
-                    let m = self.clone();
-                    thread::spawn(move || replay_subnets(m, id));
+                    tasks.push(tokio::spawn(replay_subnets(self.clone(), id)));
                 }
             }
         }
 
-        let m = self.clone();
-        thread::spawn(move || cold_store(m));
+        tasks.push(tokio::spawn(cold_store(self.clone())));
 
+        if let Some(period) = self.verification_period {
+            tasks.push(tokio::spawn(verify_cold_storage(self.clone(), period)));
+        }
+
+        let mut registry_stale = false;
+        let m = self.clone();
+        let mut spool_tips: Vec<SpoolTip> = spawn_blocking(move || {
+            m.subnet_backups
+                .iter()
+                .map(|b| SpoolTip::new(b.backup_helper.retrieve_spool_top_height()))
+                .collect()
+        })
+        .await
+        .expect("Collecting the spool heights panicked");
         loop {
-            let mut progress = Vec::new();
-            for i in 0..size {
-                let b = &self.subnet_backups[i].backup_helper;
-                let last_block = b.retrieve_spool_top_height();
-                let last_cp = b.last_state_checkpoint();
-                let subnet = &b.subnet_id.to_string()[..5];
-                progress.push(format!("{}: {}/{}", subnet, last_cp, last_block));
+            let m = self.clone();
+            (spool_tips, registry_stale) = spawn_blocking(move || {
+                let registry_stale = m.push_progress(&mut spool_tips, registry_stale);
+                (spool_tips, registry_stale)
+            })
+            .await
+            .expect("Pushing the progress metrics panicked");
 
-                b.notification_client.push_metrics_synced_height(last_block);
-                b.notification_client.push_metrics_restored_height(last_cp);
+            if sleep_secs(PERIODIC_METRICS_PUSH_PERIOD, &self.cancellation)
+                .await
+                .is_err()
+            {
+                break;
             }
-            info!(self.log, "Replay/Sync - {}", progress.join(", "));
+        }
 
-            sleep_secs(PERIODIC_METRICS_PUSH_PERIOD);
+        info!(self.log, "Waiting for the backup tasks to stop...");
+        for task in tasks {
+            if let Err(err) = task.await {
+                error!(self.log, "Backup task failed: {}", err);
+            }
         }
     }
+
+    /// Pushes the sync and replay progress of all subnets, and alerts on a
+    /// stale registry or stalled spools. Then writes the metrics textfile, if
+    /// configured. Returns whether the registry is
+    /// stale, given whether it was `registry_stale` on the previous push.
+    fn push_progress(&self, spool_tips: &mut [SpoolTip], registry_stale: bool) -> bool {
+        let staleness = self.registry_staleness();
+        if let Some(b) = self.subnet_backups.first() {
+            let b = &b.backup_helper;
+            b.notification_client
+                .push_metrics_registry_staleness(staleness.as_secs());
+            if staleness > REGISTRY_STALENESS_WARNING && !registry_stale {
+                b.notification_client.report_warning_slack(format!(
+                    "Registry was last certified {} minutes ago, the NNS endpoints might be unreachable",
+                    staleness.as_secs() / 60
+                ));
+            }
+        }
+
+        let mut progress = Vec::new();
+        for (i, subnet_backup) in self.subnet_backups.iter().enumerate() {
+            let b = &subnet_backup.backup_helper;
+            let last_block = b.retrieve_spool_top_height();
+            let last_cp = b.last_state_checkpoint();
+            let subnet = &b.subnet_id.to_string()[..5];
+            progress.push(format!("{}: {}/{}", subnet, last_cp, last_block));
+
+            b.notification_client.push_metrics_synced_height(last_block);
+            b.notification_client.push_metrics_restored_height(last_cp);
+
+            // only subnets being synced are expected to advance
+            if subnet_backup.sync_period < Duration::from_secs(1) || b.is_retired() {
+                continue;
+            }
+            let tip = &mut spool_tips[i];
+            let stalled = tip.observe(last_block);
+            b.notification_client
+                .push_metrics_spool_stalled_time(stalled.as_secs() / 60);
+            if let Some(threshold) = self.spool_stall_alert {
+                if stalled >= threshold && !tip.alerted {
+                    b.notification_client.report_failure_slack(format!(
+                        "Spool top height {} hasn't advanced in {} minutes, the nodes might have stopped backing up",
+                        last_block,
+                        stalled.as_secs() / 60
+                    ));
+                    tip.alerted = true;
+                }
+            }
+        }
+        info!(self.log, "Replay/Sync - {}", progress.join(", "));
+
+        if let Some(textfile) = &self.metrics_textfile {
+            if let Err(err) = textfile.write() {
+                error!(self.log, "Error writing the metrics textfile: {}", err);
+            }
+        }
+
+        staleness > REGISTRY_STALENESS_WARNING
+    }
 }
 
-fn sync_subnet(m: Arc<BackupManager>, i: usize) {
+async fn sync_subnet(m: Arc<BackupManager>, i: usize) {
     let b = &m.subnet_backups[i];
     let subnet_id = &b.backup_helper.subnet_id;
-    info!(m.log, "Spawned sync for subnet {:?} thread...", subnet_id);
+    info!(m.log, "Spawned sync for subnet {:?} task...", subnet_id);
     let mut sync_last_time = Instant::now() - b.sync_period;
     loop {
         if sync_last_time.elapsed() > b.sync_period {
-            match b.backup_helper.collect_nodes(b.nodes_syncing) {
-                Ok(nodes) => {
-                    sync_last_time = Instant::now();
-                    b.backup_helper.sync_files(&nodes);
+            let m = m.clone();
+            let synced = spawn_blocking(move || {
+                let b = &m.subnet_backups[i];
+                if b.backup_helper.is_deleted_from_registry() {
+                    // retried after the sync period if it failed
+                    retire_subnet(&m, &b.backup_helper);
+                    return true;
                 }
-                Err(e) => error!(m.log, "Error fetching subnet node list: {:?}", e),
+                match b.backup_helper.collect_nodes(b.nodes_syncing) {
+                    Ok(nodes) => {
+                        b.backup_helper.sync_files(&nodes);
+                        true
+                    }
+                    Err(e) => {
+                        error!(m.log, "Error fetching subnet node list: {:?}", e);
+                        false
+                    }
+                }
+            })
+            .await
+            .expect("Sync task panicked");
+            if synced {
+                sync_last_time = Instant::now();
             }
         }
 
-        sleep_secs(30);
+        if b.backup_helper.is_retired() || sleep_secs(30, &m.cancellation).await.is_err() {
+            break;
+        }
+    }
+    info!(m.log, "Stopped sync for subnet {:?}", subnet_id);
+}
+
+/// Wraps up the backup of a subnet that was deleted from the registry, see
+/// [BackupHelper::retire].
+fn retire_subnet(m: &BackupManager, b: &BackupHelper) {
+    info!(
+        m.log,
+        "Subnet {} was deleted from the registry, retiring its backup", b.subnet_id
+    );
+    match b.retire() {
+        Ok(()) => b.notification_client.message_slack(
+            "🏁 Subnet was deleted from the registry, moved its remaining artifacts to the cold storage and stopped its backup".to_string(),
+        ),
+        Err(err) => {
+            let msg = format!(
+                "Error retiring the backup of subnet {}, deleted from the registry: {}",
+                b.subnet_id, err
+            );
+            error!(m.log, "{}", msg);
+            b.notification_client.report_failure_slack(msg);
+        }
     }
 }
 
-fn replay_subnets(m: Arc<BackupManager>, thread_id: u32) {
-    info!(m.log, "Spawned replay for ID {thread_id} thread...");
+async fn replay_subnets(m: Arc<BackupManager>, thread_id: u32) {
+    info!(m.log, "Spawned replay for ID {thread_id} task...");
     let size = m.subnet_backups.len();
     let mut replay_last_time = Vec::new();
     m.subnet_backups
@@ -442,58 +951,118 @@ fn replay_subnets(m: Arc<BackupManager>, thread_id: u32) {
     loop {
         for (i, it) in replay_last_time.iter_mut().enumerate().take(size) {
             let b = &m.subnet_backups[i];
-            if b.backup_helper.thread_id != thread_id {
+            if b.backup_helper.thread_id != thread_id
+                || b.backup_helper.is_retired()
+                || m.cancellation.is_cancelled()
+            {
                 continue;
             }
             if it.elapsed() > b.replay_period {
                 *it = Instant::now();
-                b.backup_helper.replay();
+                let m = m.clone();
+                spawn_blocking(move || m.subnet_backups[i].backup_helper.replay())
+                    .await
+                    .expect("Replay task panicked");
             }
         }
 
-        sleep_secs(30);
+        if sleep_secs(30, &m.cancellation).await.is_err() {
+            break;
+        }
     }
+    info!(m.log, "Stopped replay for ID {thread_id}");
 }
 
-fn cold_store(m: Arc<BackupManager>) {
-    info!(m.log, "Spawned cold storage thread...");
-    let size = m.subnet_backups.len();
+async fn cold_store(m: Arc<BackupManager>) {
+    info!(m.log, "Spawned cold storage task...");
     loop {
-        for i in 0..size {
-            let b = &m.subnet_backups[i];
+        let bm = m.clone();
+        spawn_blocking(move || {
+            for b in &bm.subnet_backups {
+                if bm.cancellation.is_cancelled() {
+                    return;
+                }
+                cold_store_subnet(&bm, &b.backup_helper);
+            }
+        })
+        .await
+        .expect("Cold storage task panicked");
 
-            // announce the current version of the ic-backup on each cold storage check
-            b.backup_helper
-                .notification_client
-                .push_metrics_version(m.version);
+        if sleep_secs(COLD_STORAGE_PERIOD, &m.cancellation)
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+    info!(m.log, "Stopped cold storage");
+}
 
-            let subnet_id = &b.backup_helper.subnet_id;
-            match b.backup_helper.need_cold_storage_move() {
-                Ok(need) => {
-                    if !need {
-                        continue;
-                    }
-                }
-                Err(e) => {
-                    error!(
-                        m.log,
-                        "Error checking for cold store on subnet {}: {:?}", subnet_id, e
-                    );
+fn cold_store_subnet(m: &BackupManager, b: &BackupHelper) {
+    // announce the current version of the ic-backup on each cold storage check
+    b.notification_client.push_metrics_version(m.version);
+
+    let subnet_id = &b.subnet_id;
+    match b.need_cold_storage_move() {
+        Ok(need) => {
+            if !need {
+                return;
+            }
+        }
+        Err(e) => {
+            error!(
+                m.log,
+                "Error checking for cold store on subnet {}: {:?}", subnet_id, e
+            );
+            return;
+        }
+    };
+    if let Err(err) = b.do_move_cold_storage() {
+        let msg = format!(
+            "Error moving to cold storage for subnet {}: {:?}",
+            subnet_id, err
+        );
+        error!(m.log, "{}", msg);
+        b.notification_client.report_failure_slack(msg);
+    }
+}
+
+async fn verify_cold_storage(m: Arc<BackupManager>, period: Duration) {
+    info!(m.log, "Spawned cold storage verification task...");
+    while sleep_secs(period.as_secs(), &m.cancellation).await.is_ok() {
+        let bm = m.clone();
+        spawn_blocking(move || {
+            for b in &bm.subnet_backups {
+                let b = &b.backup_helper;
+                if !b.do_cold_storage || bm.cancellation.is_cancelled() {
                     continue;
                 }
-            };
-            if let Err(err) = b.backup_helper.do_move_cold_storage() {
-                let msg = format!(
-                    "Error moving to cold storage for subnet {}: {:?}",
-                    subnet_id, err
-                );
-                error!(m.log, "{}", msg);
-                b.backup_helper
-                    .notification_client
-                    .report_failure_slack(msg);
+                verify_cold_storage_of_subnet(&bm, b);
             }
-        }
+        })
+        .await
+        .expect("Cold storage verification task panicked");
+    }
+    info!(m.log, "Stopped cold storage verification");
+}
 
-        sleep_secs(COLD_STORAGE_PERIOD);
+fn verify_cold_storage_of_subnet(m: &BackupManager, b: &BackupHelper) {
+    match b.verify_cold_storage(m.verification_replay) {
+        Ok(summary) => {
+            b.notification_client
+                .push_metrics_cold_storage_verification(true);
+            b.notification_client
+                .message_slack(format!("✅ Verified the cold storage: {}", summary));
+        }
+        Err(err) => {
+            error!(
+                m.log,
+                "Error verifying the cold storage of subnet {}: {}", b.subnet_id, err
+            );
+            b.notification_client
+                .push_metrics_cold_storage_verification(false);
+            b.notification_client
+                .report_failure_slack(format!("Verification of the cold storage failed: {}", err));
+        }
     }
 }
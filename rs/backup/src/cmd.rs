@@ -37,9 +37,71 @@ pub enum SubCommand {
     Init,
     /// Upgrade the backup config file
     Upgrade,
+    /// Check the config file and print all problems found
+    CheckConfig,
     /// Get current replica version of a subnet
     GetReplicaVersion {
         /// The ID of the target subnet
         subnet_id: ClapSubnetId,
     },
+    /// Print the manifest of the replay that restored the state of a subnet at
+    /// a height, to reproduce it
+    ShowReplay {
+        /// The ID of the target subnet
+        subnet_id: ClapSubnetId,
+        /// The height of the restored state
+        height: u64,
+    },
+    /// Keep the archived state of a subnet at a height from being moved to
+    /// the cold storage or to the trash
+    Pin {
+        /// The ID of the target subnet
+        subnet_id: ClapSubnetId,
+        /// The height of the archived state
+        height: u64,
+    },
+    /// Let the archived state of a subnet at a height be moved to the cold
+    /// storage again
+    Unpin {
+        /// The ID of the target subnet
+        subnet_id: ClapSubnetId,
+        /// The height of the archived state
+        height: u64,
+    },
+    /// Resume the backup of a subnet that was retired after it was found to
+    /// be deleted from the registry. Restart the backup afterwards
+    Unretire {
+        /// The ID of the target subnet
+        subnet_id: ClapSubnetId,
+    },
+    /// Print the archived and pinned heights of the configured subnets
+    Status,
+    /// Print the replica version transitions observed for a subnet
+    Versions {
+        /// The ID of the target subnet
+        subnet_id: ClapSubnetId,
+    },
+    /// Restore the artifacts of a subnet around a height from the cold storage,
+    /// if they were packed in chunks
+    Restore {
+        /// The ID of the target subnet
+        subnet_id: ClapSubnetId,
+        /// The height whose chunk of artifacts is restored
+        #[clap(long)]
+        height: u64,
+        /// The directory the artifacts are restored into, in the layout of
+        /// the spool
+        #[clap(long)]
+        target_dir: PathBuf,
+    },
+    /// Import a manual copy of the backup directory of a node
+    /// (/var/lib/ic/backup) into the spool of a subnet, so that its artifacts
+    /// are replayed. Refuses to run while the backup daemon is running
+    Import {
+        /// The directory holding the copy
+        src_dir: PathBuf,
+        /// The ID of the subnet the artifacts belong to
+        #[clap(long)]
+        subnet: ClapSubnetId,
+    },
 }
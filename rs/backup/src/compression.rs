@@ -0,0 +1,231 @@
+//! Packing of the artifacts for the cold storage into compressed tarballs.
+//! The algorithm of a bundle is given by its extension, so that bundles
+//! packed with different settings can be restored side by side.
+use crate::config::{ArtifactsCompression, CompressionAlgorithm};
+use flate2::{read::GzDecoder, write::GzEncoder};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use xz2::read::XzDecoder;
+use xz2::stream::{Check, MtStreamBuilder};
+use xz2::write::XzEncoder;
+
+const DEFAULT_GZIP_LEVEL: u32 = 6;
+const DEFAULT_ZSTD_LEVEL: i32 = 3;
+const DEFAULT_XZ_LEVEL: u32 = 6;
+
+/// The sizes and the duration of packing a bundle.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PackStats {
+    /// The size of the tarball before the compression.
+    pub uncompressed_bytes: u64,
+    pub compressed_bytes: u64,
+    pub duration: Duration,
+}
+
+impl PackStats {
+    pub fn add(&mut self, other: &PackStats) {
+        self.uncompressed_bytes += other.uncompressed_bytes;
+        self.compressed_bytes += other.compressed_bytes;
+        self.duration += other.duration;
+    }
+
+    /// A line for the notifications, e.g. `1024 MiB packed into 256 MiB
+    /// (ratio 4.00) at 50.0 MiB/s with zstd`.
+    pub fn summary(&self, algorithm: CompressionAlgorithm) -> String {
+        let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+        let ratio = if self.compressed_bytes > 0 {
+            self.uncompressed_bytes as f64 / self.compressed_bytes as f64
+        } else {
+            0.0
+        };
+        let secs = self.duration.as_secs_f64();
+        let throughput = if secs > 0.0 {
+            mib(self.uncompressed_bytes) / secs
+        } else {
+            0.0
+        };
+        format!(
+            "{:.0} MiB packed into {:.0} MiB (ratio {:.2}) at {:.1} MiB/s with {}",
+            mib(self.uncompressed_bytes),
+            mib(self.compressed_bytes),
+            ratio,
+            throughput,
+            algorithm.name()
+        )
+    }
+}
+
+impl CompressionAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompressionAlgorithm::Gzip => "gzip",
+            CompressionAlgorithm::Zstd => "zstd",
+            CompressionAlgorithm::Xz => "xz",
+        }
+    }
+
+    /// The extension of the bundles, as recognized by `tar -a`.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CompressionAlgorithm::Gzip => "tgz",
+            CompressionAlgorithm::Zstd => "tzst",
+            CompressionAlgorithm::Xz => "txz",
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        [
+            CompressionAlgorithm::Gzip,
+            CompressionAlgorithm::Zstd,
+            CompressionAlgorithm::Xz,
+        ]
+        .into_iter()
+        .find(|algorithm| algorithm.extension() == extension)
+    }
+
+    /// The algorithm of the bundle at `path`, by its extension.
+    pub fn of_bundle(path: &Path) -> Option<Self> {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(Self::from_extension)
+    }
+}
+
+impl ArtifactsCompression {
+    pub fn validate(&self) -> Result<(), String> {
+        let (min, max) = match self.algorithm {
+            CompressionAlgorithm::Gzip | CompressionAlgorithm::Xz => (0, 9),
+            CompressionAlgorithm::Zstd => (1, 22),
+        };
+        match self.level {
+            Some(level) if level < min || level > max => Err(format!(
+                "The {} compression level must be between {} and {}, got {}",
+                self.algorithm.name(),
+                min,
+                max,
+                level
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn threads(&self) -> u32 {
+        self.threads.unwrap_or_else(|| {
+            std::thread::available_parallelism().map_or(1, |threads| threads.get() as u32)
+        })
+    }
+
+    /// Packs the `entries` of `work_dir`, given by their relative paths, into
+    /// `packed_file`, which is synced to the disk.
+    pub fn pack(
+        &self,
+        work_dir: &Path,
+        entries: &[String],
+        packed_file: &Path,
+    ) -> Result<PackStats, String> {
+        let start_time = Instant::now();
+        let file = File::create(packed_file)
+            .map_err(|err| format!("Error creating {:?}: {:?}", packed_file, err))?;
+        let result = match self.algorithm {
+            CompressionAlgorithm::Gzip => {
+                let level = self.level.map_or(DEFAULT_GZIP_LEVEL, |level| level as u32);
+                let encoder = GzEncoder::new(file, flate2::Compression::new(level));
+                append_entries(encoder, work_dir, entries)
+                    .and_then(|(encoder, size)| Ok((encoder.finish()?, size)))
+            }
+            CompressionAlgorithm::Zstd => {
+                let level = self.level.unwrap_or(DEFAULT_ZSTD_LEVEL);
+                zstd::Encoder::new(file, level)
+                    .and_then(|mut encoder| {
+                        encoder.multithread(self.threads())?;
+                        append_entries(encoder, work_dir, entries)
+                    })
+                    .and_then(|(encoder, size)| Ok((encoder.finish()?, size)))
+            }
+            CompressionAlgorithm::Xz => {
+                let level = self.level.map_or(DEFAULT_XZ_LEVEL, |level| level as u32);
+                MtStreamBuilder::new()
+                    .preset(level)
+                    .threads(self.threads())
+                    .check(Check::Crc64)
+                    .encoder()
+                    .map_err(io::Error::from)
+                    .and_then(|stream| {
+                        append_entries(XzEncoder::new_stream(file, stream), work_dir, entries)
+                    })
+                    .and_then(|(encoder, size)| Ok((encoder.finish()?, size)))
+            }
+        };
+        let (file, uncompressed_bytes) =
+            result.map_err(|err| format!("Error packing {:?}: {:?}", packed_file, err))?;
+        file.sync_all()
+            .map_err(|err| format!("Error writing {:?}: {:?}", packed_file, err))?;
+        let compressed_bytes = file
+            .metadata()
+            .map_err(|err| format!("Error reading {:?}: {:?}", packed_file, err))?
+            .len();
+        Ok(PackStats {
+            uncompressed_bytes,
+            compressed_bytes,
+            duration: start_time.elapsed(),
+        })
+    }
+}
+
+/// Unpacks the bundle at `packed_file` into `target_dir`, decompressing it
+/// according to its extension.
+pub fn unpack(packed_file: &Path, target_dir: &Path) -> Result<(), String> {
+    let algorithm = CompressionAlgorithm::of_bundle(packed_file)
+        .ok_or_else(|| format!("Unknown compression of {:?}", packed_file))?;
+    let file = File::open(packed_file)
+        .map_err(|err| format!("Error opening {:?}: {:?}", packed_file, err))?;
+    let reader = BufReader::new(file);
+    let decoder: Box<dyn Read> = match algorithm {
+        CompressionAlgorithm::Gzip => Box::new(GzDecoder::new(reader)),
+        CompressionAlgorithm::Zstd => Box::new(
+            zstd::Decoder::with_buffer(reader)
+                .map_err(|err| format!("Error opening {:?}: {:?}", packed_file, err))?,
+        ),
+        CompressionAlgorithm::Xz => Box::new(XzDecoder::new(reader)),
+    };
+    tar::Archive::new(decoder)
+        .unpack(target_dir)
+        .map_err(|err| format!("Error unpacking {:?}: {:?}", packed_file, err))
+}
+
+/// Writes the tarball of the `entries` of `work_dir` to `writer` and returns
+/// the writer and the size of the tarball.
+fn append_entries<W: Write>(
+    writer: W,
+    work_dir: &Path,
+    entries: &[String],
+) -> io::Result<(W, u64)> {
+    let mut builder = tar::Builder::new(CountingWriter {
+        inner: writer,
+        count: 0,
+    });
+    for entry in entries {
+        builder.append_dir_all(entry, work_dir.join(entry))?;
+    }
+    let counting = builder.into_inner()?;
+    Ok((counting.inner, counting.count))
+}
+
+struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
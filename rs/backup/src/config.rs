@@ -1,7 +1,15 @@
-use ic_config::{ConfigSource, ConfigValidate};
+use ic_config::{ConfigSource, ConfigValidate, Secret};
 use ic_types::{ReplicaVersion, SubnetId};
 use serde::{Deserialize, Serialize};
-use std::{fs::File, io::Write, net::IpAddr, path::PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    fs::File,
+    io::Write,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use url::Url;
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -16,57 +24,436 @@ pub struct SubnetConfig {
     pub sync_period_secs: u64,
     pub replay_period_secs: u64,
     pub thread_id: u32,
+    /// Keeps the archived states and artifacts of the subnet on the hot
+    /// storage. Defaults to false.
+    #[serde(default)]
     pub disable_cold_storage: bool,
+    /// Replays the subnet on worker hosts instead of this one.
+    #[serde(default)]
+    pub replay_sharding: Option<ReplaySharding>,
+    #[serde(default)]
+    pub replay_budget: ReplayBudget,
+}
+
+/// Limits on a single replay of a subnet, such that a long replay doesn't
+/// block the backup of the subnet for hours. Once a limit is reached, the
+/// replay stops at a checkpoint, the state reached so far is archived, and
+/// the next replay continues from there. The sharded replay is limited by
+/// [ReplaySharding::range_heights] instead.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayBudget {
+    /// The maximal number of heights replayed at once, rounded up to the next
+    /// CUP height. Ignored for replica versions whose ic-replay can't stop at
+    /// a height.
+    #[serde(default)]
+    pub max_heights_per_replay: Option<u64>,
+    /// Minutes after which the replay is interrupted. It then keeps the state
+    /// of the last checkpoint it reached.
+    #[serde(default)]
+    pub max_replay_duration_mins: Option<u64>,
+}
+
+impl ReplayBudget {
+    /// The height at which a replay from `start_height` stops: the first CUP
+    /// height after `max_heights_per_replay` heights, where the replay leaves a
+    /// checkpoint the next one can start from. `cup_interval` is the number of
+    /// heights between two CUPs, i.e. the DKG interval length plus one.
+    pub fn until_height(&self, start_height: u64, cup_interval: u64) -> Option<u64> {
+        let cup_interval = cup_interval.max(1);
+        self.max_heights_per_replay.map(|heights| {
+            let height = start_height + heights;
+            (height + cup_interval - 1) / cup_interval * cup_interval
+        })
+    }
+
+    pub fn max_duration(&self) -> Option<Duration> {
+        self.max_replay_duration_mins
+            .map(|mins| Duration::from_secs(mins * 60))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_heights_per_replay == Some(0) || self.max_replay_duration_mins == Some(0) {
+            return Err("The limits of the replay budget must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// The replay of a subnet by worker hosts, see [crate::replay_sharding].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplaySharding {
+    /// Directory shared by the primary and the workers.
+    pub shared_dir: PathBuf,
+    /// The number of heights a worker replays at once, rounded up to the next
+    /// CUP height.
+    pub range_heights: u64,
+    /// Set on workers only: the spool root of the primary, mounted read-only.
+    pub primary_spool_dir: Option<PathBuf>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ColdStorage {
     pub cold_storage_dir: PathBuf,
     pub versions_hot: usize,
+    /// Hours between verifications of the cold storage, 0 disables them.
+    #[serde(default)]
+    pub verification_period_hours: u64,
+    /// Whether a verification also replays the restored artifacts on top of
+    /// the restored state.
+    #[serde(default)]
+    pub verification_replay: bool,
+    /// If set, the artifacts of a replica version are packed into chunks of
+    /// consecutive heights of about this size (uncompressed), which can be
+    /// restored individually, instead of into a single bundle.
+    #[serde(default)]
+    pub artifacts_chunk_size_mb: Option<u64>,
+    #[serde(default)]
+    pub artifacts_compression: ArtifactsCompression,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionAlgorithm {
+    #[default]
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+/// The compression of the artifacts packed for the cold storage, see
+/// [crate::compression].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArtifactsCompression {
+    #[serde(default)]
+    pub algorithm: CompressionAlgorithm,
+    /// The compression level, 0-9 for gzip and xz, 1-22 for zstd. Defaults to
+    /// 6 for gzip and xz and to 3 for zstd.
+    #[serde(default)]
+    pub level: Option<i32>,
+    /// The threads zstd and xz compress on. Defaults to the number of cores.
+    #[serde(default)]
+    pub threads: Option<u32>,
+}
+
+/// The address family tried first when a node has addresses of both.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpPreference {
+    #[default]
+    Ipv6,
+    Ipv4,
+}
+
+impl IpPreference {
+    pub fn prefers(&self, addr: &IpAddr) -> bool {
+        match self {
+            IpPreference::Ipv6 => addr.is_ipv6(),
+            IpPreference::Ipv4 => addr.is_ipv4(),
+        }
+    }
+}
+
+/// Limits on the replay logs kept per subnet.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogRotation {
+    pub max_logs: usize,
+    pub max_size_mb: u64,
+}
+
+impl Default for LogRotation {
+    fn default() -> Self {
+        Self {
+            max_logs: 1000,
+            max_size_mb: 1024,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Config {
     pub version: u32,
+    /// Defaults to false.
+    #[serde(default)]
     pub push_metrics: bool,
+    #[serde(default)]
     pub metrics_urls: Vec<Url>,
     pub network_name: String,
     pub backup_instance: String,
+    /// Deprecated, moved to `nns_urls` on upgrade.
     pub nns_url: Option<Url>,
+    /// The NNS endpoints the registry is fetched from. The registry replicator
+    /// picks one at random for each query, and falls back to them when the
+    /// NNS nodes from the registry stop responding.
+    #[serde(default)]
+    pub nns_urls: Vec<Url>,
     pub nns_pem: PathBuf,
     pub root_dir: PathBuf,
+    /// Directories of the nodes' state that aren't synced. Defaults to none.
+    #[serde(default)]
     pub excluded_dirs: Vec<String>,
     pub ssh_private_key: PathBuf,
     pub disk_threshold_warn: u32,
-    pub slack_token: String,
+    pub slack_token: Secret<String>,
     pub cold_storage: Option<ColdStorage>,
     pub blacklisted_nodes: Option<Vec<IpAddr>>,
+    #[serde(default)]
+    pub ip_preference: IpPreference,
+    /// Addresses used for the given node ids instead of those in the
+    /// registry, e.g. in test environments the nodes aren't reachable at their
+    /// registered addresses.
+    #[serde(default)]
+    pub node_address_overrides: BTreeMap<String, Vec<IpAddr>>,
+    /// Minutes after which an alert is raised if the top height of a subnet's
+    /// spool hasn't advanced. Defaults to 60, 0 disables the alert.
+    #[serde(default)]
+    pub spool_stall_alert_mins: Option<u64>,
+    /// Minutes the replay of a subnet waits for the CUP of a new replica
+    /// version to be synced, after which a warning is raised and the replay
+    /// is skipped until the next period. Defaults to 120.
+    #[serde(default)]
+    pub cup_wait_timeout_mins: Option<u64>,
+    #[serde(default)]
+    pub log_rotation: LogRotation,
+    /// Verifies the signatures of the synced CUPs before their heights are
+    /// replayed, see [crate::cup_verification].
+    #[serde(default)]
+    pub verify_cups: bool,
+    /// The Prometheus textfile the gauges of all subnets are written to after
+    /// each round of progress metrics, e.g. in the directory of the textfile
+    /// collector of node_exporter. Written in addition to the pushed metrics.
+    #[serde(default)]
+    pub metrics_textfile: Option<PathBuf>,
     pub subnets: Vec<SubnetConfig>,
 }
 
+/// A problem with a single field of the config.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigProblem {
+    /// The path of the field, e.g. `subnets[0].sync_period_secs`.
+    pub field: String,
+    pub message: String,
+}
+
+impl ConfigProblem {
+    fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
 impl ConfigValidate for Config {
     fn validate(self) -> Result<Self, String> {
-        if self.nns_url.is_none() {
-            return Err("NNS Url is required!".to_string());
+        let problems = self.problems();
+        if problems.is_empty() {
+            return Ok(self);
+        }
+        let problems: Vec<String> = problems
+            .iter()
+            .map(|problem| format!("  {}", problem))
+            .collect();
+        Err(format!("Invalid backup config:\n{}", problems.join("\n")))
+    }
+}
+
+/// Checks that `path` is a directory, or that it can be created because its
+/// closest existing ancestor is one.
+fn check_dir_creatable(path: &Path) -> Result<(), String> {
+    if path.exists() {
+        if !path.is_dir() {
+            return Err(format!("{:?} is not a directory", path));
+        }
+        return Ok(());
+    }
+    // The last ancestor of a relative path is empty, i.e. the working dir.
+    let ancestor = path
+        .ancestors()
+        .skip(1)
+        .map(|ancestor| {
+            if ancestor.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                ancestor
+            }
+        })
+        .find(|ancestor| ancestor.exists());
+    match ancestor {
+        Some(ancestor) if !ancestor.is_dir() => Err(format!(
+            "{:?} can't be created, {:?} is not a directory",
+            path, ancestor
+        )),
+        Some(ancestor)
+            if ancestor
+                .metadata()
+                .map(|metadata| metadata.permissions().readonly())
+                .unwrap_or(true) =>
+        {
+            Err(format!(
+                "{:?} can't be created, {:?} is read-only",
+                path, ancestor
+            ))
+        }
+        Some(_) => Ok(()),
+        None => Err(format!("{:?} can't be created", path)),
+    }
+}
+
+fn check_file_exists(path: &Path) -> Result<(), String> {
+    if !path.is_file() {
+        return Err(format!("Missing file {:?}", path));
+    }
+    Ok(())
+}
+
+impl Config {
+    /// Returns the configured NNS endpoints, including the deprecated
+    /// `nns_url`.
+    pub fn all_nns_urls(&self) -> Vec<Url> {
+        let mut urls = self.nns_urls.clone();
+        if let Some(url) = &self.nns_url {
+            if !urls.contains(url) {
+                urls.push(url.clone());
+            }
         }
-        if !self.ssh_private_key.exists() {
-            return Err(format!(
-                "Missing ssh credentials file: {:?}",
-                self.ssh_private_key
-            ));
+        urls
+    }
+
+    /// Returns all problems of the config. The config is only loaded if there
+    /// are none.
+    pub fn problems(&self) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+        let mut check = |field: &str, result: Result<(), String>| {
+            if let Err(message) = result {
+                problems.push(ConfigProblem::new(field, message));
+            }
+        };
+
+        if self.all_nns_urls().is_empty() {
+            check(
+                "nns_urls",
+                Err("At least one NNS Url is required".to_string()),
+            );
         }
+        check("nns_pem", check_file_exists(&self.nns_pem));
+        check("ssh_private_key", check_file_exists(&self.ssh_private_key));
+        check("root_dir", check_dir_creatable(&self.root_dir));
         if self.disk_threshold_warn > 100 {
-            return Err("Disk threshhold warning value is > 100".to_string());
+            check(
+                "disk_threshold_warn",
+                Err(format!(
+                    "Must be a percentage, got {}",
+                    self.disk_threshold_warn
+                )),
+            );
+        }
+        if self.push_metrics && self.metrics_urls.is_empty() {
+            check(
+                "metrics_urls",
+                Err("At least one Url is required to push metrics".to_string()),
+            );
         }
+        if self.log_rotation.max_logs == 0 || self.log_rotation.max_size_mb == 0 {
+            check(
+                "log_rotation",
+                Err("The limits of the log rotation must be positive".to_string()),
+            );
+        }
+        if let Some(textfile) = &self.metrics_textfile {
+            let dir = textfile.parent().unwrap_or_else(|| Path::new("."));
+            if !dir.is_dir() {
+                check(
+                    "metrics_textfile",
+                    Err(format!("Missing directory {:?}", dir)),
+                );
+            }
+        }
+        if let Some(cold_storage) = &self.cold_storage {
+            check(
+                "cold_storage.cold_storage_dir",
+                check_dir_creatable(&cold_storage.cold_storage_dir),
+            );
+            if cold_storage.versions_hot == 0 {
+                check(
+                    "cold_storage.versions_hot",
+                    Err("At least one replica version must be kept hot".to_string()),
+                );
+            }
+            if cold_storage.artifacts_chunk_size_mb == Some(0) {
+                check(
+                    "cold_storage.artifacts_chunk_size_mb",
+                    Err("The chunk size must be positive".to_string()),
+                );
+            }
+            check(
+                "cold_storage.artifacts_compression",
+                cold_storage.artifacts_compression.validate(),
+            );
+        }
+
         // we accept no subnets in the config at the initial stage only
-        if self.subnets.is_empty() && self.slack_token != "<INSERT SLACK TOKEN>" {
-            return Err("No subnet configured for backup!".to_string());
+        if self.subnets.is_empty() && self.slack_token.expose() != "<INSERT SLACK TOKEN>" {
+            check(
+                "subnets",
+                Err("No subnet configured for backup".to_string()),
+            );
         }
-        Ok(self)
+        let mut subnet_ids = BTreeSet::new();
+        let mut thread_ids = BTreeSet::new();
+        for (i, subnet) in self.subnets.iter().enumerate() {
+            let field = |name: &str| format!("subnets[{}].{}", i, name);
+            if !subnet_ids.insert(subnet.subnet_id) {
+                check(
+                    &field("subnet_id"),
+                    Err(format!("Subnet {} is configured twice", subnet.subnet_id)),
+                );
+            }
+            if !thread_ids.insert(subnet.thread_id) {
+                check(
+                    &field("thread_id"),
+                    Err(format!("Thread {} is used twice", subnet.thread_id)),
+                );
+            }
+            for (name, value) in [
+                ("nodes_syncing", subnet.nodes_syncing as u64),
+                ("sync_period_secs", subnet.sync_period_secs),
+                ("replay_period_secs", subnet.replay_period_secs),
+            ] {
+                if value == 0 {
+                    check(&field(name), Err("Must be positive".to_string()));
+                }
+            }
+            check(&field("replay_budget"), subnet.replay_budget.validate());
+            if let Some(sharding) = &subnet.replay_sharding {
+                check(
+                    &field("replay_sharding.shared_dir"),
+                    check_dir_creatable(&sharding.shared_dir),
+                );
+                if sharding.range_heights == 0 {
+                    check(
+                        &field("replay_sharding.range_heights"),
+                        Err("Must be positive".to_string()),
+                    );
+                }
+            }
+        }
+        problems
+    }
+
+    /// Parses the config file without validating it, see [Config::problems].
+    pub fn parse_config(config_path: &Path) -> Result<Config, String> {
+        let json = std::fs::read_to_string(config_path)
+            .map_err(|err| format!("Error reading config file {:?}: {}", config_path, err))?;
+        json5::from_str(&json)
+            .map_err(|err| format!("Error parsing config file {:?}: {}", config_path, err))
     }
-}
 
-impl Config {
     pub fn load_config(config_path: PathBuf) -> Result<Config, String> {
         let config: Config = ConfigSource::File(config_path)
             .load()
@@ -82,3 +469,23 @@ impl Config {
             .map_err(|err| format!("Error writing config: {:?}", err))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_budget_stops_at_a_cup_height() {
+        let budget = ReplayBudget {
+            max_heights_per_replay: Some(1000),
+            max_replay_duration_mins: None,
+        };
+        // a budget of a multiple of the interval
+        assert_eq!(budget.until_height(500, 500), Some(1500));
+        // a budget rounded up to the next CUP height
+        assert_eq!(budget.until_height(500, 300), Some(1500));
+        assert_eq!(budget.until_height(600, 300), Some(1800));
+        // no limit without a budget of heights
+        assert_eq!(ReplayBudget::default().until_height(500, 500), None);
+    }
+}
@@ -0,0 +1,94 @@
+//! Verification of the CUPs synced to the spool, before the heights they
+//! belong to are replayed. The signature of each CUP is checked by
+//! `ic-replay verify-subnet-cup` against the threshold signing key of the
+//! subnet in the registry. The bucket of a CUP failing the check is moved out
+//! of the spool, so that it's synced again from the nodes. A CUP that can't
+//! be checked is alerted on and holds back the replay, instead of being
+//! trusted.
+
+use ic_crypto_utils_threshold_sig_der::public_key_to_der;
+use ic_types::crypto::threshold_sig::ThresholdSigPublicKey;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// The line `ic-replay verify-subnet-cup` prints if the signature of the CUP
+/// is invalid, as opposed to failing for another reason.
+pub const VERIFICATION_FAILED: &str = "CUP signature verification failed";
+
+/// The outcome of the verification of a CUP.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CupVerdict {
+    Valid,
+    /// The signature of the CUP is invalid, or the CUP is corrupted.
+    Invalid(String),
+    /// The verification couldn't be run, e.g. the `ic-replay` of the version
+    /// doesn't support it.
+    Unverifiable(String),
+}
+
+/// The outcome of the checks of the CUPs of a subnet since the start of the
+/// backup.
+#[derive(Debug, Default)]
+pub struct CupChecks {
+    /// The paths of the CUPs in the spool that were verified.
+    checked: BTreeSet<PathBuf>,
+    /// The SHA-256 hashes of the contents of the CUPs that failed the
+    /// verification. The nodes may keep serving such a CUP, which is then
+    /// dropped from the spool without being quarantined or alerted on again.
+    rejected: BTreeSet<String>,
+    /// The paths of the CUPs in the spool that couldn't be verified, which
+    /// were alerted on.
+    unverifiable: BTreeSet<PathBuf>,
+}
+
+impl CupChecks {
+    pub fn is_checked(&self, cup_path: &Path) -> bool {
+        self.checked.contains(cup_path)
+    }
+
+    pub fn accept(&mut self, cup_path: PathBuf) {
+        self.unverifiable.remove(&cup_path);
+        self.checked.insert(cup_path);
+    }
+
+    /// Whether a CUP with the SHA-256 hash `cup_hash` failed the verification
+    /// before.
+    pub fn is_rejected(&self, cup_hash: &str) -> bool {
+        self.rejected.contains(cup_hash)
+    }
+
+    /// Records the rejection of the CUP with the SHA-256 hash `cup_hash`.
+    pub fn reject(&mut self, cup_hash: String) {
+        self.rejected.insert(cup_hash);
+    }
+
+    /// Records that the CUP at `cup_path` couldn't be verified, returns
+    /// whether this is the first time.
+    pub fn report_unverifiable(&mut self, cup_path: PathBuf) -> bool {
+        self.unverifiable.insert(cup_path)
+    }
+
+    /// Forgets the CUPs below `dir`, e.g. after their bucket was moved.
+    pub fn forget_dir(&mut self, dir: &Path) {
+        self.checked.retain(|path| !path.starts_with(dir));
+        self.unverifiable.retain(|path| !path.starts_with(dir));
+    }
+}
+
+/// Writes `key` to `path` in the PEM format expected by `ic-replay`.
+pub fn write_public_key_pem(key: ThresholdSigPublicKey, path: &Path) -> Result<(), String> {
+    let der_bytes = public_key_to_der(&key.into_bytes())
+        .map_err(|err| format!("Error encoding the subnet public key: {:?}", err))?;
+    let mut pem = String::from("-----BEGIN PUBLIC KEY-----\n");
+    for chunk in base64::encode(&der_bytes).as_bytes().chunks(64) {
+        pem.push_str(&String::from_utf8_lossy(chunk));
+        pem.push('\n');
+    }
+    pem.push_str("-----END PUBLIC KEY-----\n");
+    let mut file =
+        File::create(path).map_err(|err| format!("Error creating {:?}: {:?}", path, err))?;
+    file.write_all(pem.as_bytes())
+        .map_err(|err| format!("Error writing {:?}: {:?}", path, err))
+}
@@ -0,0 +1,39 @@
+use std::fs::{File, OpenOptions};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+const DAEMON_LOCK_FILE: &str = "backup.lock";
+
+/// Exclusive lock on the root directory, held by the backup daemon for its
+/// whole lifetime and by the commands that write into the spool behind its
+/// back, e.g. `backup import`. The lock is an `flock` on a file in the root
+/// directory, so the kernel releases it when the holder exits, even if it
+/// crashes.
+pub struct DaemonLock {
+    _file: File,
+}
+
+impl DaemonLock {
+    /// Takes the lock without waiting, failing if another process holds it.
+    pub fn acquire(root_dir: &Path) -> Result<Self, String> {
+        let path = root_dir.join(DAEMON_LOCK_FILE);
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(&path)
+            .map_err(|err| format!("Error opening lock file {:?}: {:?}", path, err))?;
+        // SAFETY: the file descriptor is valid as long as `file` is alive.
+        let res = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+        if res != 0 {
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::EWOULDBLOCK) {
+                return Err(format!(
+                    "The backup in {:?} is locked by another process, is the backup daemon running?",
+                    root_dir
+                ));
+            }
+            return Err(format!("Error locking {:?}: {:?}", path, err));
+        }
+        Ok(Self { _file: file })
+    }
+}
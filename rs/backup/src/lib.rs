@@ -1,6 +1,20 @@
+pub mod artifacts_chunks;
 pub mod backup_helper;
 pub mod backup_manager;
 pub mod cmd;
+pub mod compression;
 pub mod config;
+pub mod cup_verification;
+pub mod daemon_lock;
+pub mod log_rotation;
+pub mod metrics_textfile;
+pub mod node_retention;
 pub mod notification_client;
+pub mod pinned_heights;
+pub mod replay_cli;
+pub mod replay_manifest;
+pub mod replay_sharding;
+pub mod spool_import;
+pub mod spool_manifest;
+pub mod subnet_state;
 pub mod util;
@@ -0,0 +1,213 @@
+//! Rotation of the replay logs of a subnet. The log of the latest replay is
+//! kept as is, older ones are gzip-compressed, and the oldest ones are removed
+//! together with their manifests once the subnet exceeds the configured number
+//! or total size of logs. An index file per subnet maps the replayed heights
+//! to the log files, for the `show-replay` command.
+use crate::config::LogRotation;
+use crate::replay_manifest::ReplayManifest;
+use flate2::{write::GzEncoder, Compression};
+use ic_types::SubnetId;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::fs::{read_dir, remove_file, rename, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const INDEX_FILE_SUFFIX: &str = "logs_index.json";
+const LOG_EXTENSION: &str = "log";
+const COMPRESSED_EXTENSION: &str = "gz";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogIndexEntry {
+    pub log_file: PathBuf,
+    pub manifest_file: PathBuf,
+    pub start_height: u64,
+    pub end_height: u64,
+    pub size_bytes: u64,
+}
+
+impl LogIndexEntry {
+    pub fn new(log_file: PathBuf, start_height: u64, end_height: u64) -> Self {
+        Self {
+            manifest_file: ReplayManifest::path_for_log(&log_file),
+            size_bytes: file_size(&log_file),
+            log_file,
+            start_height,
+            end_height,
+        }
+    }
+
+    fn is_compressed(&self) -> bool {
+        self.log_file.extension() == Some(COMPRESSED_EXTENSION.as_ref())
+    }
+
+    /// Replaces the log file by its compressed version, and points the
+    /// manifest to it.
+    fn compress(&mut self) -> Result<(), String> {
+        if !self.log_file.exists() {
+            self.size_bytes = 0;
+            return Ok(());
+        }
+        let mut compressed = OsString::from(self.log_file.as_os_str());
+        compressed.push(format!(".{}", COMPRESSED_EXTENSION));
+        let compressed = PathBuf::from(compressed);
+
+        let mut log = File::open(&self.log_file)
+            .map_err(|err| format!("Error opening {:?}: {:?}", self.log_file, err))?;
+        let file = File::create(&compressed)
+            .map_err(|err| format!("Error creating {:?}: {:?}", compressed, err))?;
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        io::copy(&mut log, &mut encoder)
+            .and_then(|_| encoder.finish())
+            .map_err(|err| format!("Error compressing {:?}: {:?}", self.log_file, err))?;
+        remove_if_exists(&self.log_file)?;
+        self.log_file = compressed;
+        self.size_bytes = file_size(&self.log_file);
+
+        if self.manifest_file.exists() {
+            let mut manifest = ReplayManifest::load(&self.manifest_file)?;
+            manifest.log_file = self.log_file.clone();
+            manifest.save(&self.manifest_file)?;
+        }
+        Ok(())
+    }
+
+    fn remove(&self) -> Result<(), String> {
+        remove_if_exists(&self.log_file)?;
+        remove_if_exists(&self.manifest_file)
+    }
+}
+
+/// The replay logs of a subnet, oldest first.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogIndex {
+    pub entries: Vec<LogIndexEntry>,
+}
+
+impl LogIndex {
+    pub fn path(logs_dir: &Path, subnet_id: SubnetId) -> PathBuf {
+        logs_dir.join(format!("{}_{}", subnet_id, INDEX_FILE_SUFFIX))
+    }
+
+    /// Loads the index of the subnet, or builds it from the logs in
+    /// `logs_dir` if there is none yet.
+    pub fn load(logs_dir: &Path, subnet_id: SubnetId) -> Result<Self, String> {
+        let path = Self::path(logs_dir, subnet_id);
+        if !path.exists() {
+            return Self::rebuild(logs_dir, subnet_id);
+        }
+        let file =
+            File::open(&path).map_err(|err| format!("Error opening {:?}: {:?}", path, err))?;
+        serde_json::from_reader(file).map_err(|err| format!("Error parsing {:?}: {:?}", path, err))
+    }
+
+    /// Indexes the logs of the subnet written before there was an index. Their
+    /// names are `<subnet_id>_<timestamp>_<start_height>.log`, so they are
+    /// ordered by the time of the replay.
+    fn rebuild(logs_dir: &Path, subnet_id: SubnetId) -> Result<Self, String> {
+        let prefix = format!("{}_", subnet_id);
+        let entries =
+            read_dir(logs_dir).map_err(|err| format!("Error reading {:?}: {:?}", logs_dir, err))?;
+        let mut log_files: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension() == Some(LOG_EXTENSION.as_ref())
+                    && path
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .map_or(false, |name| name.starts_with(&prefix))
+            })
+            .collect();
+        log_files.sort();
+
+        let entries = log_files
+            .into_iter()
+            .map(|log_file| {
+                let start_height = log_file
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.rsplit('_').next())
+                    .and_then(|height| height.parse().ok())
+                    .unwrap_or_default();
+                let end_height = ReplayManifest::load(&ReplayManifest::path_for_log(&log_file))
+                    .map_or(start_height, |manifest| manifest.end_height);
+                LogIndexEntry::new(log_file, start_height, end_height)
+            })
+            .collect();
+        Ok(Self { entries })
+    }
+
+    pub fn save(&self, logs_dir: &Path, subnet_id: SubnetId) -> Result<(), String> {
+        let path = Self::path(logs_dir, subnet_id);
+        let tmp_path = path.with_extension("tmp");
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|err| format!("Error serializing log index: {:?}", err))?;
+        let mut file = File::create(&tmp_path)
+            .map_err(|err| format!("Error creating log index: {:?}", err))?;
+        file.write_all(json.as_bytes())
+            .map_err(|err| format!("Error writing log index: {:?}", err))?;
+        rename(&tmp_path, &path).map_err(|err| format!("Error replacing log index: {:?}", err))
+    }
+
+    /// Finds the log of the replay that restored the state at `height`, or
+    /// else of the latest replay whose heights include `height`.
+    pub fn find(&self, height: u64) -> Option<&LogIndexEntry> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|e| e.end_height == height)
+            .or_else(|| {
+                self.entries
+                    .iter()
+                    .rev()
+                    .find(|e| e.start_height <= height && height <= e.end_height)
+            })
+    }
+
+    fn total_size_bytes(&self) -> u64 {
+        self.entries.iter().map(|e| e.size_bytes).sum()
+    }
+}
+
+/// Adds the log of the latest replay of the subnet to its index, compresses
+/// the older logs and removes the oldest ones beyond the limits. The latest
+/// log is always kept.
+pub fn rotate_logs(
+    logs_dir: &Path,
+    subnet_id: SubnetId,
+    latest: LogIndexEntry,
+    limits: &LogRotation,
+) -> Result<(), String> {
+    let mut index = LogIndex::load(logs_dir, subnet_id)?;
+    index.entries.retain(|e| e.log_file != latest.log_file);
+    index.entries.push(latest);
+
+    let older = index.entries.len() - 1;
+    for entry in index.entries[..older].iter_mut() {
+        if !entry.is_compressed() {
+            entry.compress()?;
+        }
+    }
+
+    let max_size_bytes = limits.max_size_mb * 1024 * 1024;
+    while index.entries.len() > 1
+        && (index.entries.len() > limits.max_logs || index.total_size_bytes() > max_size_bytes)
+    {
+        index.entries.remove(0).remove()?;
+    }
+    index.save(logs_dir, subnet_id)
+}
+
+fn file_size(path: &Path) -> u64 {
+    path.metadata().map_or(0, |metadata| metadata.len())
+}
+
+fn remove_if_exists(path: &Path) -> Result<(), String> {
+    match remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => {
+            Err(format!("Error removing {:?}: {:?}", path, err))
+        }
+        _ => Ok(()),
+    }
+}
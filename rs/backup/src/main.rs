@@ -2,10 +2,11 @@ use clap::Parser;
 use ic_backup::{
     backup_manager::BackupManager,
     cmd::{BackupArgs, SubCommand},
+    util::Cancellation,
 };
-use slog::{o, Drain};
+use slog::{info, o, Drain};
 use std::sync::Arc;
-use tokio::runtime::Handle;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::task::spawn_blocking;
 
 // Here is an example config file:
@@ -14,7 +15,10 @@ use tokio::task::spawn_blocking;
 //     "version": 5,
 //     "push_metrics": true,
 //     "backup_instance": "zh1-spm34",
-//     "nns_url": "https://smallXYZ.testnet.dfinity.network",
+//     "nns_urls": [
+//         "https://smallXYZ.testnet.dfinity.network",
+//         "https://smallXYZ-2.testnet.dfinity.network"
+//     ],
 //     "nns_pem": "ic_public_key.pem",
 //     "root_dir": "./backup",
 //     "excluded_dirs": [
@@ -71,20 +75,78 @@ async fn main() {
     let drain = slog_async::Async::new(filter).build().fuse();
     let log = slog::Logger::root(drain, o!());
 
-    let rt = Handle::current();
-    spawn_blocking(move || {
-        match args.subcmd {
-            Some(SubCommand::Init) => BackupManager::init(log, args.config_file),
-            Some(SubCommand::Upgrade) => BackupManager::upgrade(log, args.config_file),
-            Some(SubCommand::GetReplicaVersion { subnet_id }) => {
-                BackupManager::get_version(log, args.config_file, subnet_id.0)
-            }
-            _ => {
-                let bm = BackupManager::new(log, args, &rt);
-                Arc::new(bm).do_backups();
-            }
-        };
-    })
-    .await
+    match args.subcmd {
+        Some(SubCommand::Init) => {
+            spawn_blocking(move || BackupManager::init(log, args.config_file)).await
+        }
+        Some(SubCommand::Upgrade) => {
+            spawn_blocking(move || BackupManager::upgrade(log, args.config_file)).await
+        }
+        Some(SubCommand::CheckConfig) => {
+            spawn_blocking(move || BackupManager::check_config(args.config_file)).await
+        }
+        Some(SubCommand::GetReplicaVersion { subnet_id }) => {
+            spawn_blocking(move || BackupManager::get_version(log, args.config_file, subnet_id.0))
+                .await
+        }
+        Some(SubCommand::ShowReplay { subnet_id, height }) => {
+            spawn_blocking(move || {
+                BackupManager::show_replay(args.config_file, subnet_id.0, height)
+            })
+            .await
+        }
+        Some(SubCommand::Pin { subnet_id, height }) => {
+            spawn_blocking(move || BackupManager::pin(args.config_file, subnet_id.0, height)).await
+        }
+        Some(SubCommand::Unpin { subnet_id, height }) => {
+            spawn_blocking(move || BackupManager::unpin(args.config_file, subnet_id.0, height))
+                .await
+        }
+        Some(SubCommand::Restore {
+            subnet_id,
+            height,
+            target_dir,
+        }) => {
+            spawn_blocking(move || {
+                BackupManager::restore(args.config_file, subnet_id.0, height, target_dir)
+            })
+            .await
+        }
+        Some(SubCommand::Import { src_dir, subnet }) => {
+            spawn_blocking(move || BackupManager::import(args.config_file, subnet.0, src_dir)).await
+        }
+        Some(SubCommand::Unretire { subnet_id }) => {
+            spawn_blocking(move || BackupManager::unretire(args.config_file, subnet_id.0)).await
+        }
+        Some(SubCommand::Status) => {
+            spawn_blocking(move || BackupManager::status(args.config_file)).await
+        }
+        Some(SubCommand::Versions { subnet_id }) => {
+            spawn_blocking(move || BackupManager::versions(args.config_file, subnet_id.0)).await
+        }
+        _ => {
+            let (cancel, cancellation) = Cancellation::new();
+            let bm_log = log.clone();
+            let bm = spawn_blocking(move || BackupManager::new(bm_log, args, cancellation))
+                .await
+                .expect("Blocking task panicked");
+            tokio::spawn(async move {
+                shutdown_signal().await;
+                info!(log, "Shutting down, stopping the backup tasks...");
+                let _ = cancel.send(true);
+            });
+            Arc::new(bm).do_backups().await;
+            Ok(())
+        }
+    }
     .expect("Blocking task panicked")
 }
+
+/// Completes on SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    let mut sigterm = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = sigterm.recv() => {}
+    }
+}
@@ -0,0 +1,98 @@
+//! The gauges pushed by the notification clients of all subnets, kept in
+//! memory and written to a Prometheus textfile after each round of progress
+//! metrics, for the textfile collector of node_exporter. This way hosts
+//! without access to the push gateway are observable too.
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::{rename, File};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+const TMP_EXTENSION: &str = "tmp";
+
+#[derive(Default)]
+struct Gauge {
+    help: String,
+    /// The values of the gauge, by their labels.
+    samples: BTreeMap<String, String>,
+}
+
+pub struct MetricsTextfile {
+    path: PathBuf,
+    gauges: Mutex<BTreeMap<String, Gauge>>,
+}
+
+impl MetricsTextfile {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            gauges: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Records the gauges of `message`, in the text format pushed to the
+    /// push gateway, adding the label `ic_subnet="<subnet>"` to their
+    /// samples. A sample replaces the previous value of the same labels.
+    pub fn record(&self, message: &str, subnet: &str) {
+        let mut gauges = self.gauges.lock().expect("Metrics textfile lock poisoned");
+        for line in message.lines() {
+            if let Some(help) = line.strip_prefix("# HELP ") {
+                if let Some((name, help)) = help.split_once(' ') {
+                    gauges.entry(name.to_string()).or_default().help = help.to_string();
+                }
+                continue;
+            }
+            if line.starts_with('#') {
+                continue;
+            }
+            let (series, value) = match line.rsplit_once(' ') {
+                Some(sample) => sample,
+                None => continue,
+            };
+            let (name, labels) = match series.split_once('{') {
+                Some((name, labels)) => (name, labels.trim_end_matches('}')),
+                None => (series, ""),
+            };
+            let labels = if labels.is_empty() {
+                format!("ic_subnet=\"{}\"", subnet)
+            } else {
+                format!("{}, ic_subnet=\"{}\"", labels, subnet)
+            };
+            gauges
+                .entry(name.to_string())
+                .or_default()
+                .samples
+                .insert(labels, value.to_string());
+        }
+    }
+
+    fn render(&self) -> String {
+        let gauges = self.gauges.lock().expect("Metrics textfile lock poisoned");
+        let mut text = String::new();
+        for (name, gauge) in gauges.iter() {
+            text.push_str(&format!("# HELP {} {}\n", name, gauge.help));
+            text.push_str(&format!("# TYPE {} gauge\n", name));
+            for (labels, value) in &gauge.samples {
+                text.push_str(&format!("{}{{{}}} {}\n", name, labels, value));
+            }
+        }
+        text
+    }
+
+    /// Writes all gauges recorded so far to the textfile. The file is
+    /// replaced atomically, so that node_exporter never reads a partial one.
+    pub fn write(&self) -> Result<(), String> {
+        let mut tmp_path = OsString::from(self.path.as_os_str());
+        tmp_path.push(format!(".{}", TMP_EXTENSION));
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut file = File::create(&tmp_path)
+            .map_err(|err| format!("Error creating {:?}: {:?}", tmp_path, err))?;
+        file.write_all(self.render().as_bytes())
+            .and_then(|_| file.sync_all())
+            .map_err(|err| format!("Error writing {:?}: {:?}", tmp_path, err))?;
+        rename(&tmp_path, &self.path)
+            .map_err(|err| format!("Error moving {:?} to {:?}: {:?}", tmp_path, self.path, err))
+    }
+}
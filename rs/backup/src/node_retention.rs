@@ -0,0 +1,59 @@
+//! Reconciliation of the retention of the nodes' backup directories with the
+//! spool of the host.
+//!
+//! Nodes prune the heights of their backup directory (`/var/lib/ic/backup`)
+//! after a while. If the host doesn't sync them in time, e.g. because it was
+//! down for too long, they are lost. Before every sync, the lowest height each
+//! node still keeps is listed. Heights above the top of the spool that none of
+//! the nodes keeps anymore can't be synced, and thus not replayed.
+use std::ops::RangeInclusive;
+
+/// Returns the names of the entries listed by `rsync --list-only`, relative
+/// to the listed directory, e.g. `0.8.0/10000`. The directory itself (`.`) is
+/// skipped.
+fn listed_names(listing: &str) -> impl Iterator<Item = &str> {
+    listing
+        .lines()
+        .filter_map(|line| line.split_whitespace().last())
+        .filter(|name| *name != ".")
+}
+
+/// Returns the buckets `<replica version>/<bucket>` listed in a spool
+/// directory, lowest bucket first.
+pub fn listed_buckets(listing: &str) -> Vec<(u64, String)> {
+    let mut buckets: Vec<(u64, String)> = listed_names(listing)
+        .filter_map(|name| {
+            let (_, bucket) = name.split_once('/')?;
+            Some((bucket.parse().ok()?, name.to_string()))
+        })
+        .collect();
+    buckets.sort();
+    buckets
+}
+
+/// Returns the heights listed in a bucket directory in ascending order.
+pub fn listed_heights(listing: &str) -> Vec<u64> {
+    let mut heights: Vec<u64> = listed_names(listing)
+        .filter_map(|name| name.parse().ok())
+        .collect();
+    heights.sort_unstable();
+    heights
+}
+
+/// Returns the heights following `spool_top_height` that none of the nodes
+/// keeps, given the lowest height each of them keeps. There is no gap if
+/// the spool is still empty, or if no node could be listed.
+pub fn unrecoverable_gap(
+    spool_top_height: u64,
+    lowest_node_heights: &[u64],
+) -> Option<RangeInclusive<u64>> {
+    if spool_top_height == 0 {
+        return None;
+    }
+    let lowest = *lowest_node_heights.iter().min()?;
+    if lowest > spool_top_height + 1 {
+        Some(spool_top_height + 1..=lowest - 1)
+    } else {
+        None
+    }
+}
@@ -1,13 +1,17 @@
+use crate::metrics_textfile::MetricsTextfile;
 use crate::util::block_on;
+use ic_config::Secret;
 use slog::{error, info, Logger};
+use std::sync::Arc;
 use url::Url;
 
 pub struct NotificationClient {
     pub push_metrics: bool,
     pub metrics_urls: Vec<Url>,
+    pub metrics_textfile: Option<Arc<MetricsTextfile>>,
     pub network_name: String,
     pub backup_instance: String,
-    pub slack_token: String,
+    pub slack_token: Secret<String>,
     pub subnet: String,
     pub log: Logger,
 }
@@ -33,7 +37,7 @@ impl NotificationClient {
         info!(self.log, "{}", message);
         let url = format!(
             "https://hooks.slack.com/services/T43F9UHS5/B027BHAQ1HQ/{}",
-            self.slack_token
+            self.slack_token.expose()
         );
         let data_str = format!(
             "{{\"text\":\"[{}, *{}*] {}\"}}",
@@ -54,6 +58,9 @@ impl NotificationClient {
     }
 
     fn push_metrics(&self, message: String) {
+        if let Some(textfile) = &self.metrics_textfile {
+            textfile.record(&message, &self.subnet);
+        }
         if !self.push_metrics {
             return;
         }
@@ -121,6 +128,48 @@ impl NotificationClient {
         self.push_metrics(message)
     }
 
+    pub fn push_metrics_registry_staleness(&self, seconds: u64) {
+        let message = format!(
+            "# TYPE backup_registry_staleness_seconds gauge\n\
+            # HELP backup_registry_staleness_seconds The time since the registry of a backup pod was last certified by the NNS.\n\
+            backup_registry_staleness_seconds{{ic=\"{}\"}} {}\n",
+            self.network_name, seconds
+        );
+        self.push_metrics(message)
+    }
+
+    pub fn push_metrics_spool_stalled_time(&self, minutes: u64) {
+        let message = format!(
+            "# TYPE backup_spool_stalled_minutes gauge\n\
+            # HELP backup_spool_stalled_minutes The time since the top height of the spool of a backup pod last advanced.\n\
+            backup_spool_stalled_minutes{{ic=\"{}\"}} {}\n",
+            self.network_name, minutes
+        );
+        self.push_metrics(message)
+    }
+
+    /// `timestamp` is the time in seconds since the Unix epoch at which the
+    /// replay started waiting for a CUP, or 0 if it isn't waiting.
+    pub fn push_metrics_cup_wait_since(&self, timestamp: u64) {
+        let message = format!(
+            "# TYPE backup_waiting_for_cup_since_seconds gauge\n\
+            # HELP backup_waiting_for_cup_since_seconds The time since the Unix epoch at which the replay on a backup pod started waiting for a CUP, 0 if it isn't waiting.\n\
+            backup_waiting_for_cup_since_seconds{{ic=\"{}\"}} {}\n",
+            self.network_name, timestamp
+        );
+        self.push_metrics(message)
+    }
+
+    pub fn push_metrics_cold_storage_verification(&self, success: bool) {
+        let message = format!(
+            "# TYPE backup_cold_storage_verification_success gauge\n\
+            # HELP backup_cold_storage_verification_success Whether the last verification of the cold storage of a backup pod succeeded.\n\
+            backup_cold_storage_verification_success{{ic=\"{}\"}} {}\n",
+            self.network_name, success as u8
+        );
+        self.push_metrics(message)
+    }
+
     pub fn push_metrics_version(&self, version: u32) {
         let message = format!(
             "# TYPE backup_version_number gauge\n\
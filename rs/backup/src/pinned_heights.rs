@@ -0,0 +1,85 @@
+use ic_types::SubnetId;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{rename, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const PINNED_HEIGHTS_FILE: &str = "pinned_heights.json";
+
+/// Heights of archived states, per subnet, that are kept in the archive when
+/// the older states are moved to the cold storage or to the trash, e.g.
+/// because an incident investigation refers to them. Stored in the root
+/// directory, where `backup pin` and `backup unpin` edit it while the backup
+/// is running; it is re-read before each move to the cold storage.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PinnedHeights {
+    subnets: BTreeMap<String, BTreeSet<u64>>,
+}
+
+impl PinnedHeights {
+    fn path(root_dir: &Path) -> PathBuf {
+        root_dir.join(PINNED_HEIGHTS_FILE)
+    }
+
+    /// Loads the pinned heights, of which there are none if the file doesn't
+    /// exist.
+    pub fn load(root_dir: &Path) -> Result<Self, String> {
+        let path = Self::path(root_dir);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let file = File::open(&path)
+            .map_err(|err| format!("Error opening pinned heights {:?}: {:?}", path, err))?;
+        serde_json::from_reader(file)
+            .map_err(|err| format!("Error parsing pinned heights {:?}: {:?}", path, err))
+    }
+
+    /// Saves the pinned heights, replacing the file atomically so that a
+    /// concurrent `load` never sees a partial one.
+    pub fn save(&self, root_dir: &Path) -> Result<(), String> {
+        let path = Self::path(root_dir);
+        let tmp_path = path.with_extension("json.tmp");
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|err| format!("Error serializing pinned heights: {:?}", err))?;
+        let mut file = File::create(&tmp_path)
+            .map_err(|err| format!("Error creating pinned heights: {:?}", err))?;
+        file.write_all(json.as_bytes())
+            .and_then(|_| file.sync_all())
+            .map_err(|err| format!("Error writing pinned heights: {:?}", err))?;
+        rename(&tmp_path, &path)
+            .map_err(|err| format!("Error replacing pinned heights {:?}: {:?}", path, err))
+    }
+
+    /// Returns false if the height was already pinned.
+    pub fn pin(&mut self, subnet_id: SubnetId, height: u64) -> bool {
+        self.subnets
+            .entry(subnet_id.to_string())
+            .or_default()
+            .insert(height)
+    }
+
+    /// Returns false if the height wasn't pinned.
+    pub fn unpin(&mut self, subnet_id: SubnetId, height: u64) -> bool {
+        let subnet_id = subnet_id.to_string();
+        let removed = self
+            .subnets
+            .get_mut(&subnet_id)
+            .map_or(false, |heights| heights.remove(&height));
+        if self
+            .subnets
+            .get(&subnet_id)
+            .map_or(false, BTreeSet::is_empty)
+        {
+            self.subnets.remove(&subnet_id);
+        }
+        removed
+    }
+
+    pub fn heights(&self, subnet_id: SubnetId) -> BTreeSet<u64> {
+        self.subnets
+            .get(&subnet_id.to_string())
+            .cloned()
+            .unwrap_or_default()
+    }
+}
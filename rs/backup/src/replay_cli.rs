@@ -0,0 +1,63 @@
+//! The command line of ic-replay changed over the replica versions, e.g. the
+//! subcommand restoring the state from the spool was superseded by
+//! `restore-from-backup2`. Before the backup runs the ic-replay of a version,
+//! it probes `ic-replay --help` of that version for the subcommands and flags
+//! it relies on, instead of failing on an opaque error of the replay.
+use std::collections::BTreeSet;
+
+/// The subcommands restoring the state from the spool, most recent first.
+/// They all take the registry local store, the spool, the replica version and
+/// the start height as arguments.
+const RESTORE_SUBCOMMANDS: [&str; 2] = ["restore-from-backup2", "restore-from-backup"];
+const VERIFY_SUBNET_CUP_SUBCOMMAND: &str = "verify-subnet-cup";
+const REPLAY_UNTIL_HEIGHT_FLAG: &str = "--replay-until-height";
+
+/// What the ic-replay of a replica version supports.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplayCli {
+    /// The subcommand restoring the state from the spool.
+    pub restore_subcommand: &'static str,
+    /// Whether the replay can stop at a height, which the sharded replay
+    /// needs.
+    pub replay_until_height: bool,
+    /// Whether the signature of a CUP can be verified.
+    pub verify_subnet_cup: bool,
+}
+
+impl ReplayCli {
+    /// Reads the capabilities from the output of `ic-replay --help`. Returns
+    /// `None` if the state can't be restored from the spool with it at all.
+    pub fn from_help(help: &str) -> Option<Self> {
+        let subcommands = subcommands(help);
+        let restore_subcommand = RESTORE_SUBCOMMANDS
+            .iter()
+            .find(|subcommand| subcommands.contains(**subcommand))?;
+        Some(Self {
+            restore_subcommand,
+            replay_until_height: help.contains(REPLAY_UNTIL_HEIGHT_FLAG),
+            verify_subnet_cup: subcommands.contains(VERIFY_SUBNET_CUP_SUBCOMMAND),
+        })
+    }
+
+    /// The reason the state can't be restored with an ic-replay without any of
+    /// the known subcommands, for the operators.
+    pub fn unsupported(replica_version: &str) -> String {
+        format!(
+            "Replica version {} is not supported: its ic-replay has none of the subcommands {}, replay it manually",
+            replica_version,
+            RESTORE_SUBCOMMANDS.join(", ")
+        )
+    }
+}
+
+/// The names of the subcommands listed by the help of a clap command, under
+/// `SUBCOMMANDS:` or, in newer versions of clap, `Commands:`.
+fn subcommands(help: &str) -> BTreeSet<String> {
+    help.lines()
+        .skip_while(|line| !matches!(line.trim(), "SUBCOMMANDS:" | "Commands:"))
+        .skip(1)
+        .take_while(|line| line.is_empty() || line.starts_with(char::is_whitespace))
+        .filter_map(|line| line.split_whitespace().next())
+        .map(String::from)
+        .collect()
+}
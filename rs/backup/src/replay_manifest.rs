@@ -0,0 +1,160 @@
+use chrono::{DateTime, Utc};
+use ic_http_utils::file_downloader::compute_sha256_hex;
+use ic_types::SubnetId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{read_dir, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+const MANIFEST_EXTENSION: &str = "manifest.json";
+
+/// Inherited environment variables that influence the replay, and are
+/// therefore recorded in addition to those set explicitly on the command.
+const RECORDED_ENV_VARS: &[&str] = &["PATH", "LD_LIBRARY_PATH"];
+const RECORDED_ENV_PREFIX: &str = "RUST_";
+
+/// Everything needed to reproduce a run of ic-replay independently: the
+/// binaries and config used, the exact command line and environment, and the
+/// heights it replayed. Written next to the log of the run.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayManifest {
+    pub subnet_id: String,
+    pub replica_version: String,
+    /// SHA-256 of each binary and config file, by path.
+    pub file_hashes: BTreeMap<PathBuf, String>,
+    pub program: String,
+    pub args: Vec<String>,
+    pub env: BTreeMap<String, String>,
+    pub start_height: u64,
+    pub end_height: u64,
+    pub started_at: String,
+    pub duration_secs: u64,
+    pub log_file: PathBuf,
+}
+
+impl ReplayManifest {
+    /// Records the run of `cmd`, hashing `files` as they are now.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        subnet_id: SubnetId,
+        replica_version: String,
+        files: &[PathBuf],
+        cmd: &Command,
+        start_height: u64,
+        end_height: u64,
+        started_at: DateTime<Utc>,
+        duration: Duration,
+        log_file: PathBuf,
+    ) -> Result<Self, String> {
+        let mut file_hashes = BTreeMap::new();
+        for file in files {
+            let hash = compute_sha256_hex(file)
+                .map_err(|e| format!("Error hashing {:?}: {:?}", file, e))?;
+            file_hashes.insert(file.clone(), hash);
+        }
+
+        let mut env: BTreeMap<String, String> = std::env::vars()
+            .filter(|(key, _)| {
+                RECORDED_ENV_VARS.contains(&key.as_str()) || key.starts_with(RECORDED_ENV_PREFIX)
+            })
+            .collect();
+        for (key, value) in cmd.get_envs() {
+            let key = key.to_string_lossy().to_string();
+            match value {
+                Some(value) => env.insert(key, value.to_string_lossy().to_string()),
+                None => env.remove(&key),
+            };
+        }
+
+        Ok(Self {
+            subnet_id: subnet_id.to_string(),
+            replica_version,
+            file_hashes,
+            program: cmd.get_program().to_string_lossy().to_string(),
+            args: cmd
+                .get_args()
+                .map(|arg| arg.to_string_lossy().to_string())
+                .collect(),
+            env,
+            start_height,
+            end_height,
+            started_at: started_at.to_rfc3339(),
+            duration_secs: duration.as_secs(),
+            log_file,
+        })
+    }
+
+    /// The path of the manifest belonging to `log_file`.
+    pub fn path_for_log(log_file: &Path) -> PathBuf {
+        log_file.with_extension(MANIFEST_EXTENSION)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|err| format!("Error serializing replay manifest: {:?}", err))?;
+        let mut file = File::create(path)
+            .map_err(|err| format!("Error creating replay manifest: {:?}", err))?;
+        file.write_all(json.as_bytes())
+            .map_err(|err| format!("Error writing replay manifest: {:?}", err))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = File::open(path)
+            .map_err(|err| format!("Error opening replay manifest {:?}: {:?}", path, err))?;
+        serde_json::from_reader(file)
+            .map_err(|err| format!("Error parsing replay manifest {:?}: {:?}", path, err))
+    }
+
+    /// Finds the manifest of the run of the subnet that restored the state at
+    /// `height`, or else of the latest run whose heights include `height`.
+    pub fn find(logs_dir: &Path, subnet_id: SubnetId, height: u64) -> Result<Self, String> {
+        let subnet_id = subnet_id.to_string();
+        let mut manifests = Vec::new();
+        let entries =
+            read_dir(logs_dir).map_err(|err| format!("Error reading {:?}: {:?}", logs_dir, err))?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_manifest = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map_or(false, |name| {
+                    name.starts_with(&subnet_id) && name.ends_with(MANIFEST_EXTENSION)
+                });
+            if is_manifest {
+                manifests.push(Self::load(&path)?);
+            }
+        }
+        // Within a subnet, the log names are ordered by the time of the run.
+        manifests.sort_by(|a, b| a.log_file.cmp(&b.log_file));
+
+        let restored = manifests.iter().rev().find(|m| m.end_height == height);
+        let covering = || {
+            manifests
+                .iter()
+                .rev()
+                .find(|m| m.start_height <= height && height <= m.end_height)
+        };
+        restored
+            .or_else(covering)
+            .cloned()
+            .ok_or_else(|| format!("No replay of subnet {} covers height {}", subnet_id, height))
+    }
+
+    /// A shell command line reproducing the run.
+    pub fn shell_command(&self) -> String {
+        self.env
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, shell_quote(value)))
+            .chain(std::iter::once(shell_quote(&self.program)))
+            .chain(self.args.iter().map(|arg| shell_quote(arg)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
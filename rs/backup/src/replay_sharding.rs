@@ -0,0 +1,260 @@
+//! Coordination of the replay of a subnet between the host syncing its spool
+//! (the primary) and worker hosts that mount the spool read-only.
+//!
+//! The primary plans the next range of heights to replay, starting at its
+//! latest state, and publishes that state in a directory shared by all hosts.
+//! A worker claims the range, replays it from the published state, and
+//! publishes the resulting state, which the primary archives as if it had
+//! replayed the range itself. As each range starts from the state the previous
+//! one ended at, the ranges of a subnet are replayed one after the other, but
+//! the ranges of different subnets are replayed on different hosts in
+//! parallel, and the primary only syncs and archives.
+//!
+//! The shared directory of a subnet is laid out as:
+//!
+//! ```text
+//! <shared_dir>/<subnet_id>/shard.json          the current range
+//! <shared_dir>/<subnet_id>/shard.lock          held while updating shard.json
+//! <shared_dir>/<subnet_id>/states/<height>/    published data directories
+//! <shared_dir>/<subnet_id>/configs/<version>/  the ic.json5 of each version
+//! ```
+use serde::{Deserialize, Serialize};
+use std::fs::{create_dir_all, remove_file, rename, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SHARD_FILE: &str = "shard.json";
+const LOCK_FILE: &str = "shard.lock";
+const LOCK_RETRIES: u64 = 60;
+/// Locks are only held to update the shard file, so an older one was left
+/// behind by a host that crashed.
+const STALE_LOCK_AGE: Duration = Duration::from_secs(10 * 60);
+
+/// A range of heights of a subnet to be replayed by a worker.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayShard {
+    pub start_height: u64,
+    pub end_height: u64,
+    pub replica_version: String,
+    pub worker: Option<String>,
+    pub claimed_at_secs: u64,
+    pub result: Option<ShardResult>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardResult {
+    /// The height of the published state, which is below `end_height` if the
+    /// replay stopped at an upgrade.
+    pub reached_height: u64,
+    /// The version to replay the following heights with, if it changed.
+    pub upgrade_version: Option<String>,
+    pub duration_secs: u64,
+}
+
+impl ReplayShard {
+    pub fn new(start_height: u64, end_height: u64, replica_version: String) -> Self {
+        Self {
+            start_height,
+            end_height,
+            replica_version,
+            worker: None,
+            claimed_at_secs: 0,
+            result: None,
+        }
+    }
+
+    /// Whether a worker may claim the shard, because it isn't done and isn't
+    /// claimed, or the claim has expired.
+    pub fn is_claimable(&self, lease: Duration) -> bool {
+        self.result.is_none()
+            && (self.worker.is_none() || now_secs() > self.claimed_at_secs + lease.as_secs())
+    }
+
+    pub fn claim(&mut self, worker: &str) {
+        self.worker = Some(worker.to_string());
+        self.claimed_at_secs = now_secs();
+    }
+
+    pub fn is_claimed_by(&self, worker: &str) -> bool {
+        self.worker.as_deref() == Some(worker)
+    }
+}
+
+/// The end of the next shard starting at `start_height`, a CUP height, such
+/// that the replay of the shard stops at a CUP height, where the state is
+/// deterministic, and the next shard can start from. `cup_interval` is the
+/// number of heights between two CUPs, i.e. the DKG interval length plus one.
+/// The shard spans at least `range_heights`, rounded up to a multiple of
+/// `cup_interval`, or all the complete intervals up to `top_height`, the
+/// top height of the spool, if fewer. Returns `None` if the spool doesn't hold
+/// a complete interval after `start_height` yet.
+pub fn shard_end_height(
+    start_height: u64,
+    range_heights: u64,
+    cup_interval: u64,
+    top_height: u64,
+) -> Option<u64> {
+    let cup_interval = cup_interval.max(1);
+    let intervals = ((range_heights + cup_interval - 1) / cup_interval).max(1);
+    let available = top_height.saturating_sub(start_height) / cup_interval;
+    let intervals = intervals.min(available);
+    if intervals == 0 {
+        return None;
+    }
+    Some(start_height + intervals * cup_interval)
+}
+
+/// The shared directory of a subnet.
+pub struct SharedDir {
+    dir: PathBuf,
+}
+
+impl SharedDir {
+    pub fn new(shared_dir: &Path, subnet_id: &str) -> Self {
+        Self {
+            dir: shared_dir.join(subnet_id),
+        }
+    }
+
+    pub fn state_dir(&self, height: u64) -> PathBuf {
+        self.dir.join(format!("states/{}", height))
+    }
+
+    pub fn config_file(&self, replica_version: &str) -> PathBuf {
+        self.dir
+            .join(format!("configs/{}/ic.json5", replica_version))
+    }
+
+    /// Runs `f` on the current shard while holding the lock of the shard
+    /// file, and saves the shard it leaves behind.
+    pub fn update_shard<T>(
+        &self,
+        f: impl FnOnce(&mut Option<ReplayShard>) -> T,
+    ) -> Result<T, String> {
+        create_dir_all(&self.dir)
+            .map_err(|err| format!("Error creating {:?}: {:?}", self.dir, err))?;
+        let _lock = ShardLock::acquire(self.dir.join(LOCK_FILE))?;
+
+        let path = self.dir.join(SHARD_FILE);
+        let mut shard = if path.exists() {
+            let file =
+                File::open(&path).map_err(|err| format!("Error opening {:?}: {:?}", path, err))?;
+            serde_json::from_reader(file)
+                .map_err(|err| format!("Error parsing {:?}: {:?}", path, err))?
+        } else {
+            None
+        };
+        let result = f(&mut shard);
+
+        let tmp_path = path.with_extension("tmp");
+        let json = serde_json::to_string_pretty(&shard)
+            .map_err(|err| format!("Error serializing the shard: {:?}", err))?;
+        let mut file = File::create(&tmp_path)
+            .map_err(|err| format!("Error creating {:?}: {:?}", tmp_path, err))?;
+        file.write_all(json.as_bytes())
+            .map_err(|err| format!("Error writing {:?}: {:?}", tmp_path, err))?;
+        rename(&tmp_path, &path).map_err(|err| format!("Error replacing {:?}: {:?}", path, err))?;
+        Ok(result)
+    }
+}
+
+/// A lock file, created exclusively so that only one host holds it, and
+/// removed when dropped.
+struct ShardLock {
+    path: PathBuf,
+}
+
+impl ShardLock {
+    fn acquire(path: PathBuf) -> Result<Self, String> {
+        for _ in 0..LOCK_RETRIES {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(Self { path }),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    let stale = path
+                        .metadata()
+                        .and_then(|metadata| metadata.modified())
+                        .ok()
+                        .and_then(|modified| modified.elapsed().ok())
+                        .map_or(false, |age| age > STALE_LOCK_AGE);
+                    if stale {
+                        let _ = remove_file(&path);
+                    } else {
+                        std::thread::sleep(Duration::from_secs(1));
+                    }
+                }
+                Err(err) => return Err(format!("Error creating {:?}: {:?}", path, err)),
+            }
+        }
+        Err(format!("Couldn't acquire the lock {:?}", path))
+    }
+}
+
+impl Drop for ShardLock {
+    fn drop(&mut self) {
+        let _ = remove_file(&self.path);
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shard_ends_at_a_cup_height() {
+        // a range of a multiple of the interval
+        assert_eq!(shard_end_height(500, 1000, 500, 10_000), Some(1500));
+        // a range rounded up to the next CUP height
+        assert_eq!(shard_end_height(500, 700, 500, 10_000), Some(1500));
+        assert_eq!(shard_end_height(500, 1, 500, 10_000), Some(1000));
+    }
+
+    #[test]
+    fn shard_ends_at_the_last_complete_interval_in_the_spool() {
+        assert_eq!(shard_end_height(500, 2000, 500, 1700), Some(1500));
+        // exactly at the top height
+        assert_eq!(shard_end_height(500, 2000, 500, 1500), Some(1500));
+    }
+
+    #[test]
+    fn no_shard_without_a_complete_interval_in_the_spool() {
+        assert_eq!(shard_end_height(500, 1000, 500, 999), None);
+        assert_eq!(shard_end_height(500, 1000, 500, 500), None);
+        assert_eq!(shard_end_height(500, 1000, 500, 100), None);
+    }
+
+    #[test]
+    fn shard_with_a_zero_range_spans_one_interval() {
+        assert_eq!(shard_end_height(0, 0, 500, 10_000), Some(500));
+    }
+
+    #[test]
+    fn shard_is_claimable_until_claimed_or_done() {
+        let lease = Duration::from_secs(60);
+        let mut shard = ReplayShard::new(500, 1500, "version".to_string());
+        assert!(shard.is_claimable(lease));
+
+        shard.claim("worker-1");
+        assert!(shard.is_claimed_by("worker-1"));
+        assert!(!shard.is_claimed_by("worker-2"));
+        assert!(!shard.is_claimable(lease));
+
+        // the claim of a worker that didn't finish in time expires
+        shard.claimed_at_secs = now_secs() - 2 * lease.as_secs();
+        assert!(shard.is_claimable(lease));
+
+        shard.result = Some(ShardResult {
+            reached_height: 1500,
+            upgrade_version: None,
+            duration_secs: 60,
+        });
+        assert!(!shard.is_claimable(lease));
+    }
+}
@@ -0,0 +1,265 @@
+//! Import of ad-hoc copies of the backup directory of a node
+//! (`/var/lib/ic/backup`) into the spool of a subnet, so that the artifacts
+//! they hold can be replayed.
+//!
+//! The copy may contain the directory of the subnet or be that directory
+//! itself. Its version directories may hold the heights in buckets, as the
+//! spool does, or directly, as hand-made copies often do:
+//!
+//! ```text
+//! [<subnet_id>/]<replica version>/[<bucket>/]<height>/<artifact files>
+//! ```
+//!
+//! The whole copy is validated before anything is imported. Files already
+//! in the spool with the same content are skipped, files that differ from
+//! the ones in the spool are reported and left alone, and the manifests of
+//! the buckets that received files are updated.
+use crate::spool_manifest::BucketManifest;
+use crate::subnet_state::{SubnetState, VersionSource};
+use chrono::Utc;
+use ic_backup_spool::{bucket, SubnetSpool, BUCKET_SIZE};
+use ic_http_utils::file_downloader::compute_sha256_hex;
+use ic_types::{Height, ReplicaVersion, SubnetId};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fs::{copy, create_dir_all, read_dir, remove_dir_all, rename};
+use std::path::{Path, PathBuf};
+
+/// A height directory of the copy to import.
+struct SourceHeight {
+    replica_version: ReplicaVersion,
+    height: Height,
+    dir: PathBuf,
+    files: Vec<String>,
+}
+
+/// What an import did, printed by the CLI.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub heights: usize,
+    pub imported_files: usize,
+    /// Files skipped because the spool already holds them.
+    pub duplicate_files: usize,
+    /// Files skipped because the spool holds a different file of the same
+    /// name, with the reason.
+    pub conflicts: Vec<String>,
+    /// The buckets that received files, by replica version.
+    pub buckets: BTreeSet<(String, u64)>,
+}
+
+/// Imports the copy at `src_dir` into the spool of `subnet_id` under
+/// `root_dir`, and updates the manifests of the buckets that received files
+/// and their roots in the subnet state. The backup of the subnet must not be
+/// syncing meanwhile, which the caller ensures by holding the
+/// [`DaemonLock`](crate::daemon_lock::DaemonLock).
+pub fn import_spool(
+    root_dir: &Path,
+    subnet_id: SubnetId,
+    src_dir: &Path,
+) -> Result<ImportReport, String> {
+    let subnet_dir = src_dir.join(subnet_id.to_string());
+    let src_dir = if subnet_dir.is_dir() {
+        subnet_dir
+    } else {
+        src_dir.to_path_buf()
+    };
+    let heights = scan_copy(&src_dir)?;
+    if heights.is_empty() {
+        return Err(format!("No artifacts found in {:?}", src_dir));
+    }
+
+    let spool = SubnetSpool::new(root_dir.join("spool").join(subnet_id.to_string()));
+    let tmp_dir = root_dir.join(format!("import_tmp/{}", subnet_id));
+    create_dir_all(&tmp_dir).map_err(|err| format!("Error creating {:?}: {:?}", tmp_dir, err))?;
+    let mut report = ImportReport {
+        heights: heights.len(),
+        ..Default::default()
+    };
+    let mut lowest_heights = BTreeMap::new();
+    for source in &heights {
+        let height_dir = spool
+            .version(&source.replica_version)
+            .height_dir(source.height);
+        for file in &source.files {
+            let src = source.dir.join(file);
+            let dst = height_dir.join(file);
+            if dst.exists() {
+                match same_content(&src, &dst)? {
+                    true => report.duplicate_files += 1,
+                    false => report
+                        .conflicts
+                        .push(format!("{:?} differs from {:?} in the spool", src, dst)),
+                }
+                continue;
+            }
+            // copy next to the spool first, so that it never holds a partial file
+            let tmp = tmp_dir.join(file);
+            copy(&src, &tmp).map_err(|err| format!("Error copying {:?}: {:?}", src, err))?;
+            create_dir_all(&height_dir)
+                .map_err(|err| format!("Error creating {:?}: {:?}", height_dir, err))?;
+            rename(&tmp, &dst).map_err(|err| format!("Error moving {:?}: {:?}", tmp, err))?;
+            report.imported_files += 1;
+            report
+                .buckets
+                .insert((source.replica_version.to_string(), bucket(source.height)));
+            lowest_heights
+                .entry(source.replica_version.clone())
+                .or_insert(source.height);
+        }
+    }
+    remove_dir_all(&tmp_dir).map_err(|err| format!("Error deleting {:?}: {:?}", tmp_dir, err))?;
+
+    let manifests_dir = root_dir.join(format!("spool_manifests/{}", subnet_id));
+    let mut state = SubnetState::load(root_dir, subnet_id)?;
+    for (replica_version, bucket) in &report.buckets {
+        let bucket_dir = spool.path().join(replica_version).join(bucket.to_string());
+        let path = BucketManifest::path(&manifests_dir, replica_version, *bucket);
+        let mut manifest = BucketManifest::load(&path)?;
+        if manifest.update(&bucket_dir)? {
+            manifest.save(&path)?;
+        }
+        state.spool_roots.insert(
+            format!("{}/{}", replica_version, bucket),
+            manifest.root_hash,
+        );
+    }
+    // the heights are sorted, so the first one imported of a version is its lowest
+    for (replica_version, height) in lowest_heights {
+        state.record_version(
+            &replica_version,
+            height.get(),
+            Utc::now().to_rfc3339(),
+            VersionSource::Spool,
+        );
+    }
+    state.save(root_dir, subnet_id)?;
+    Ok(report)
+}
+
+/// Lists the height directories of the copy at `src_dir`, in the order of
+/// their versions and heights. Fails with all entries that don't fit the
+/// layout, before anything is imported.
+fn scan_copy(src_dir: &Path) -> Result<Vec<SourceHeight>, String> {
+    let mut heights = Vec::new();
+    let mut problems = Vec::new();
+    for (name, path) in list_dir(src_dir)? {
+        let replica_version = match ReplicaVersion::try_from(name.as_str()) {
+            Ok(replica_version) if path.is_dir() => replica_version,
+            _ => {
+                problems.push(format!("{:?} is not a replica version directory", path));
+                continue;
+            }
+        };
+        for (name, path) in list_dir(&path)? {
+            let number = match name.parse::<u64>() {
+                Ok(number) if path.is_dir() => number,
+                _ => {
+                    problems.push(format!("{:?} is neither a bucket nor a height", path));
+                    continue;
+                }
+            };
+            let entries = list_dir(&path)?;
+            // a bucket holds height directories, a height holds artifact files
+            if !entries.iter().any(|(_, entry)| entry.is_dir()) {
+                scan_height(
+                    &replica_version,
+                    number,
+                    path,
+                    entries,
+                    &mut heights,
+                    &mut problems,
+                );
+                continue;
+            }
+            if number % BUCKET_SIZE != 0 {
+                problems.push(format!(
+                    "{:?} is not a bucket of {} heights",
+                    path, BUCKET_SIZE
+                ));
+                continue;
+            }
+            for (name, path) in entries {
+                match name.parse::<u64>() {
+                    Ok(height) if path.is_dir() && bucket(Height::from(height)) == number => {
+                        let entries = list_dir(&path)?;
+                        scan_height(
+                            &replica_version,
+                            height,
+                            path,
+                            entries,
+                            &mut heights,
+                            &mut problems,
+                        );
+                    }
+                    _ => problems.push(format!("{:?} is not a height of its bucket", path)),
+                }
+            }
+        }
+    }
+    if !problems.is_empty() {
+        return Err(format!(
+            "{:?} doesn't have the layout of a backup directory: {}",
+            src_dir,
+            problems.join("; ")
+        ));
+    }
+    heights.sort_by(|a, b| (&a.replica_version, a.height).cmp(&(&b.replica_version, b.height)));
+    Ok(heights)
+}
+
+fn scan_height(
+    replica_version: &ReplicaVersion,
+    height: u64,
+    dir: PathBuf,
+    entries: Vec<(String, PathBuf)>,
+    heights: &mut Vec<SourceHeight>,
+    problems: &mut Vec<String>,
+) {
+    let mut files = Vec::new();
+    for (name, path) in entries {
+        if path.is_file() {
+            files.push(name);
+        } else {
+            problems.push(format!("{:?} is not an artifact file", path));
+        }
+    }
+    // an empty height directory carries nothing to import
+    if !files.is_empty() {
+        heights.push(SourceHeight {
+            replica_version: replica_version.clone(),
+            height: Height::from(height),
+            dir,
+            files,
+        });
+    }
+}
+
+/// Returns the entries of `dir` by name, sorted.
+fn list_dir(dir: &Path) -> Result<Vec<(String, PathBuf)>, String> {
+    let entries = read_dir(dir).map_err(|err| format!("Error listing {:?}: {:?}", dir, err))?;
+    let mut result = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|err| format!("Error listing {:?}: {:?}", dir, err))?;
+        result.push((
+            entry.file_name().to_string_lossy().to_string(),
+            entry.path(),
+        ));
+    }
+    result.sort();
+    Ok(result)
+}
+
+fn same_content(a: &Path, b: &Path) -> Result<bool, String> {
+    let size = |path: &Path| {
+        path.metadata()
+            .map(|metadata| metadata.len())
+            .map_err(|err| format!("Error reading {:?}: {:?}", path, err))
+    };
+    if size(a)? != size(b)? {
+        return Ok(false);
+    }
+    let sha256 = |path: &Path| {
+        compute_sha256_hex(path).map_err(|err| format!("Error hashing {:?}: {:?}", path, err))
+    };
+    Ok(sha256(a)? == sha256(b)?)
+}
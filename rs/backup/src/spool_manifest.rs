@@ -0,0 +1,235 @@
+//! Integrity protection of the spool against silent bitrot on the disks of
+//! the backup host. As artifacts land in a height bucket of the spool, the
+//! [BucketManifest] of the bucket records the size and the SHA-256 of each of
+//! its files, and the root of a Merkle tree over them, which is also stored in
+//! the subnet state file. Before the artifacts are replayed or packed for the
+//! cold storage, their files are hashed again and checked against the
+//! manifest.
+use ic_crypto_sha::Sha256;
+use ic_http_utils::file_downloader::compute_sha256_hex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_dir, rename, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+const MANIFEST_EXTENSION: &str = "json";
+
+/// A file of a bucket, as it was last hashed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BucketManifest {
+    /// The files of the bucket, by their path relative to the bucket.
+    pub files: BTreeMap<String, FileEntry>,
+    /// The root of the Merkle tree over the files, hex-encoded.
+    pub root_hash: String,
+}
+
+impl BucketManifest {
+    /// The manifest of a bucket is stored outside of the spool, so that the
+    /// sync with the nodes doesn't touch it.
+    pub fn path(manifests_dir: &Path, replica_version: &str, bucket: u64) -> PathBuf {
+        manifests_dir
+            .join(replica_version)
+            .join(format!("{}.{}", bucket, MANIFEST_EXTENSION))
+    }
+
+    /// Loads the manifest, which is empty if the file doesn't exist.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let file = File::open(path)
+            .map_err(|err| format!("Error opening spool manifest {:?}: {:?}", path, err))?;
+        serde_json::from_reader(file)
+            .map_err(|err| format!("Error parsing spool manifest {:?}: {:?}", path, err))
+    }
+
+    /// Saves the manifest, replacing the file atomically.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir).map_err(|err| format!("Error creating {:?}: {:?}", dir, err))?;
+        }
+        let tmp_path = path.with_extension("json.tmp");
+        let json = serde_json::to_string(self)
+            .map_err(|err| format!("Error serializing spool manifest: {:?}", err))?;
+        let mut file = File::create(&tmp_path)
+            .map_err(|err| format!("Error creating spool manifest: {:?}", err))?;
+        file.write_all(json.as_bytes())
+            .and_then(|_| file.sync_all())
+            .map_err(|err| format!("Error writing spool manifest: {:?}", err))?;
+        rename(&tmp_path, path)
+            .map_err(|err| format!("Error replacing spool manifest {:?}: {:?}", path, err))
+    }
+
+    /// Adds the files that landed in `bucket_dir` since the last update and
+    /// returns whether the manifest changed. The spool is append-only, so
+    /// only new files and files that grew, e.g. by a resumed transfer, are
+    /// hashed. A file that shrank, disappeared or whose bytes recorded before
+    /// changed is an integrity violation.
+    pub fn update(&mut self, bucket_dir: &Path) -> Result<bool, String> {
+        let files = list_files(bucket_dir)?;
+        if let Some(missing) = self.files.keys().find(|name| !files.contains_key(*name)) {
+            return Err(format!("{:?} disappeared from {:?}", missing, bucket_dir));
+        }
+        let mut changed = false;
+        for (name, size) in files {
+            match self.files.get(&name) {
+                Some(entry) if entry.size == size => continue,
+                Some(entry) if entry.size > size => {
+                    return Err(format!(
+                        "{:?} in {:?} shrank from {} to {} bytes",
+                        name, bucket_dir, entry.size, size
+                    ))
+                }
+                _ => {}
+            }
+            let path = bucket_dir.join(&name);
+            let sha256 = match self.files.get(&name) {
+                // The grown file must extend the bytes recorded before, or
+                // their corruption would be recorded with the new hash.
+                Some(entry) => {
+                    let (prefix_sha256, sha256) = hash_with_prefix(&path, entry.size)
+                        .map_err(|err| format!("Error hashing {:?}: {:?}", path, err))?;
+                    if prefix_sha256 != entry.sha256 {
+                        return Err(format!(
+                            "The first {} bytes of {:?} in {:?} have the SHA-256 {}, expected {}",
+                            entry.size, name, bucket_dir, prefix_sha256, entry.sha256
+                        ));
+                    }
+                    sha256
+                }
+                None => compute_sha256_hex(&path)
+                    .map_err(|err| format!("Error hashing {:?}: {:?}", path, err))?,
+            };
+            self.files.insert(name, FileEntry { size, sha256 });
+            changed = true;
+        }
+        if changed || self.root_hash.is_empty() {
+            self.root_hash = self.merkle_root();
+            changed = true;
+        }
+        Ok(changed)
+    }
+
+    /// Hashes the files of `bucket_dir` again and returns the problems found,
+    /// checking the manifest itself against the expected root hash first.
+    /// Files that landed or grew since the last update are not checked.
+    pub fn verify(&self, bucket_dir: &Path, expected_root_hash: &str) -> Vec<String> {
+        let root_hash = self.merkle_root();
+        if root_hash != expected_root_hash || root_hash != self.root_hash {
+            return vec![format!(
+                "the manifest of {:?} has the root hash {}, expected {}",
+                bucket_dir, root_hash, expected_root_hash
+            )];
+        }
+        self.files
+            .iter()
+            .filter_map(|(name, entry)| {
+                let path = bucket_dir.join(name);
+                match compute_sha256_hex(&path) {
+                    Ok(sha256) if sha256 == entry.sha256 => None,
+                    Ok(sha256) if entry.size == file_size(&path) => Some(format!(
+                        "{:?} has the SHA-256 {}, expected {}",
+                        path, sha256, entry.sha256
+                    )),
+                    // grew after the last update
+                    Ok(_) if entry.size < file_size(&path) => None,
+                    Ok(_) => Some(format!("{:?} shrank", path)),
+                    Err(err) => Some(format!("Error hashing {:?}: {:?}", path, err)),
+                }
+            })
+            .collect()
+    }
+
+    /// The root of the Merkle tree whose leaves are the hashes of the file
+    /// names and their SHA-256, in the order of the names.
+    pub fn merkle_root(&self) -> String {
+        let mut level: Vec<[u8; 32]> = self
+            .files
+            .iter()
+            .map(|(name, entry)| {
+                let mut hasher = Sha256::new();
+                hasher.write(name.as_bytes());
+                hasher.write(&[0]);
+                hasher.write(entry.sha256.as_bytes());
+                hasher.finish()
+            })
+            .collect();
+        if level.is_empty() {
+            return hex::encode(Sha256::hash(&[]));
+        }
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => {
+                        let mut hasher = Sha256::new();
+                        hasher.write(left);
+                        hasher.write(right);
+                        hasher.finish()
+                    }
+                    // an odd node is promoted to the next level
+                    [single] => *single,
+                    _ => unreachable!("chunks of 2"),
+                })
+                .collect();
+        }
+        hex::encode(level[0])
+    }
+}
+
+/// Returns the sizes of all files below `dir`, by their relative path.
+fn list_files(dir: &Path) -> Result<BTreeMap<String, u64>, String> {
+    let mut files = BTreeMap::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(current) = dirs.pop() {
+        let entries =
+            read_dir(&current).map_err(|err| format!("Error listing {:?}: {:?}", current, err))?;
+        for entry in entries {
+            let entry = entry.map_err(|err| format!("Error listing {:?}: {:?}", current, err))?;
+            let metadata = entry
+                .metadata()
+                .map_err(|err| format!("Error reading {:?}: {:?}", entry.path(), err))?;
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else if let Ok(name) = entry.path().strip_prefix(dir) {
+                files.insert(name.to_string_lossy().to_string(), metadata.len());
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Returns the hex-encoded SHA-256 of the first `prefix_size` bytes of the
+/// file at `path` and of the whole file, reading it once.
+fn hash_with_prefix(path: &Path, prefix_size: u64) -> std::io::Result<(String, String)> {
+    let mut file = File::open(path)?;
+    let mut prefix_hasher = Sha256::new();
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1 << 20];
+    let mut hashed = 0;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        let in_prefix = prefix_size.saturating_sub(hashed).min(read as u64) as usize;
+        prefix_hasher.write(&buffer[..in_prefix]);
+        hasher.write(&buffer[..read]);
+        hashed += read as u64;
+    }
+    Ok((
+        hex::encode(prefix_hasher.finish()),
+        hex::encode(hasher.finish()),
+    ))
+}
+
+fn file_size(path: &Path) -> u64 {
+    path.metadata().map(|metadata| metadata.len()).unwrap_or(0)
+}
@@ -0,0 +1,187 @@
+use ic_types::{ReplicaVersion, SubnetId};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, rename, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const SUBNET_STATE_DIR: &str = "subnet_state";
+
+/// Where a replica version of a subnet was first observed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VersionSource {
+    /// A version directory of the spool, synced from the nodes.
+    Spool,
+    /// An upgrade requested by ic-replay during the replay.
+    Replay,
+}
+
+/// The first observation of a replica version of a subnet.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionTransition {
+    pub replica_version: String,
+    /// The lowest height known to run the version, which is lowered if the
+    /// version is later observed at an earlier height.
+    pub height: u64,
+    pub observed_at: String,
+    pub source: VersionSource,
+}
+
+/// A replica version whose binaries couldn't be downloaded, such that its
+/// heights can't be replayed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnavailableBinaries {
+    /// The binary whose download failed last.
+    pub binary: String,
+    /// When the downloads of the version started failing.
+    pub failing_since: String,
+    /// Whether the downloads kept failing for so long that the version was
+    /// reported as blocked.
+    pub blocked: bool,
+}
+
+/// Heights that the nodes pruned before they were synced, such that they
+/// can only be recovered manually.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnrecoverableGap {
+    pub from_height: u64,
+    pub to_height: u64,
+    pub detected_at: String,
+}
+
+/// What the backup tracks about a subnet across restarts, stored in a file
+/// per subnet under the root directory.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubnetState {
+    /// The replica versions the subnet ran, ordered by height.
+    pub version_transitions: Vec<VersionTransition>,
+    /// The SHA-256 of the ic.json5 the subnet is replayed with, by replica
+    /// version.
+    #[serde(default)]
+    pub ic_config_hashes: BTreeMap<String, String>,
+    /// When the subnet was found to be deleted from the registry and its
+    /// backup was wrapped up. A retired subnet is no longer synced nor
+    /// replayed.
+    #[serde(default)]
+    pub retired_at: Option<String>,
+    /// The Merkle roots of the manifests of the spool buckets, by
+    /// `<replica version>/<bucket>`.
+    #[serde(default)]
+    pub spool_roots: BTreeMap<String, String>,
+    /// The replica versions whose binaries currently fail to download, by
+    /// replica version.
+    #[serde(default)]
+    pub unavailable_binaries: BTreeMap<String, UnavailableBinaries>,
+    /// The gaps in the heights synced from the nodes, in the order they were
+    /// detected.
+    #[serde(default)]
+    pub unrecoverable_gaps: Vec<UnrecoverableGap>,
+}
+
+impl SubnetState {
+    fn path(root_dir: &Path, subnet_id: SubnetId) -> PathBuf {
+        root_dir
+            .join(SUBNET_STATE_DIR)
+            .join(format!("{}.json", subnet_id))
+    }
+
+    /// Loads the state of the subnet, which is empty if the file doesn't
+    /// exist.
+    pub fn load(root_dir: &Path, subnet_id: SubnetId) -> Result<Self, String> {
+        let path = Self::path(root_dir, subnet_id);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let file = File::open(&path)
+            .map_err(|err| format!("Error opening subnet state {:?}: {:?}", path, err))?;
+        serde_json::from_reader(file)
+            .map_err(|err| format!("Error parsing subnet state {:?}: {:?}", path, err))
+    }
+
+    /// Saves the state of the subnet, replacing the file atomically so that
+    /// the CLI never reads a partial one.
+    pub fn save(&self, root_dir: &Path, subnet_id: SubnetId) -> Result<(), String> {
+        let path = Self::path(root_dir, subnet_id);
+        let dir = root_dir.join(SUBNET_STATE_DIR);
+        create_dir_all(&dir).map_err(|err| format!("Error creating {:?}: {:?}", dir, err))?;
+        let tmp_path = path.with_extension("json.tmp");
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|err| format!("Error serializing subnet state: {:?}", err))?;
+        let mut file = File::create(&tmp_path)
+            .map_err(|err| format!("Error creating subnet state: {:?}", err))?;
+        file.write_all(json.as_bytes())
+            .and_then(|_| file.sync_all())
+            .map_err(|err| format!("Error writing subnet state: {:?}", err))?;
+        rename(&tmp_path, &path)
+            .map_err(|err| format!("Error replacing subnet state {:?}: {:?}", path, err))
+    }
+
+    pub fn knows_version(&self, replica_version: &ReplicaVersion) -> bool {
+        self.version_transitions
+            .iter()
+            .any(|transition| transition.replica_version == replica_version.to_string())
+    }
+
+    /// Records the gap of heights `from_height..=to_height`, extending the
+    /// gap starting at the same height if it is known already. Returns true
+    /// if the gap wasn't known before.
+    pub fn record_unrecoverable_gap(
+        &mut self,
+        from_height: u64,
+        to_height: u64,
+        detected_at: String,
+    ) -> bool {
+        match self
+            .unrecoverable_gaps
+            .iter_mut()
+            .find(|gap| gap.from_height == from_height)
+        {
+            Some(gap) => {
+                gap.to_height = gap.to_height.max(to_height);
+                false
+            }
+            None => {
+                self.unrecoverable_gaps.push(UnrecoverableGap {
+                    from_height,
+                    to_height,
+                    detected_at,
+                });
+                true
+            }
+        }
+    }
+
+    /// Records that the subnet runs `replica_version` at `height`. Returns
+    /// true if the version wasn't observed before.
+    pub fn record_version(
+        &mut self,
+        replica_version: &ReplicaVersion,
+        height: u64,
+        observed_at: String,
+        source: VersionSource,
+    ) -> bool {
+        let replica_version = replica_version.to_string();
+        let is_new = match self
+            .version_transitions
+            .iter_mut()
+            .find(|transition| transition.replica_version == replica_version)
+        {
+            Some(transition) => {
+                transition.height = transition.height.min(height);
+                false
+            }
+            None => {
+                self.version_transitions.push(VersionTransition {
+                    replica_version,
+                    height,
+                    observed_at,
+                    source,
+                });
+                true
+            }
+        };
+        self.version_transitions
+            .sort_by_key(|transition| transition.height);
+        is_new
+    }
+}
@@ -1,16 +1,56 @@
 use ic_types::ReplicaVersion;
 use serde::{de::Error, Deserialize, Deserializer, Serializer};
-use std::future::Future;
-use tokio::runtime::Runtime;
+use std::{future::Future, time::Duration};
+use tokio::{runtime::Handle, sync::watch};
 
+/// Runs `f` to completion on the runtime of the daemon, which all its tasks
+/// share. Must be called from a blocking context, e.g., a task spawned with
+/// [tokio::task::spawn_blocking], and panics if called from an async task.
 pub fn block_on<F: Future>(f: F) -> F::Output {
-    let rt = Runtime::new().unwrap_or_else(|err| panic!("Could not create tokio runtime: {}", err));
-    rt.block_on(f)
+    Handle::current().block_on(f)
 }
 
-pub fn sleep_secs(secs: u64) {
-    let sleep_duration = std::time::Duration::from_secs(secs);
-    std::thread::sleep(sleep_duration);
+/// Signals the tasks of the daemon to stop. All clones observe the same
+/// signal, which is raised through the sender returned by [Cancellation::new].
+#[derive(Clone)]
+pub struct Cancellation(watch::Receiver<bool>);
+
+impl Cancellation {
+    pub fn new() -> (watch::Sender<bool>, Self) {
+        let (sender, receiver) = watch::channel(false);
+        (sender, Self(receiver))
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Completes once the signal is raised. Never completes if the sender is
+    /// dropped without raising it, as then nothing can cancel the tasks.
+    pub async fn cancelled(&self) {
+        let mut receiver = self.0.clone();
+        while !*receiver.borrow_and_update() {
+            if receiver.changed().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+/// Sleeps for `secs` seconds without blocking the thread. Returns an error if
+/// `cancellation` is signalled in the meantime.
+pub async fn sleep_secs(secs: u64, cancellation: &Cancellation) -> Result<(), String> {
+    tokio::select! {
+        _ = tokio::time::sleep(Duration::from_secs(secs)) => Ok(()),
+        _ = cancellation.cancelled() => Err("The backup was cancelled".to_string()),
+    }
+}
+
+/// Like [sleep_secs], but blocks the calling thread for the whole wait. For
+/// the waits of [crate::backup_helper::BackupHelper], whose work runs on the
+/// blocking pool of the runtime and keeps its thread while it waits.
+pub fn sleep_secs_blocking(secs: u64, cancellation: &Cancellation) -> Result<(), String> {
+    block_on(sleep_secs(secs, cancellation))
 }
 
 pub fn replica_from_string<'de, D>(deserializer: D) -> Result<ReplicaVersion, D::Error>
@@ -28,3 +68,24 @@ where
     let s = ver.to_string();
     serializer.serialize_str(&s)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sleeps_through_a_dropped_sender() {
+        let (sender, cancellation) = Cancellation::new();
+        drop(sender);
+        assert!(!cancellation.is_cancelled());
+        assert_eq!(sleep_secs(1, &cancellation).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn stops_sleeping_once_cancelled() {
+        let (sender, cancellation) = Cancellation::new();
+        sender.send(true).unwrap();
+        assert!(cancellation.is_cancelled());
+        assert!(sleep_secs(3600, &cancellation).await.is_err());
+    }
+}
@@ -0,0 +1,252 @@
+//! The layout of the backup spool, which replicas write their consensus
+//! artifacts to, and which ic-backup and ic-replay read them from.
+//!
+//! The artifacts of a subnet are grouped by the replica version that produced
+//! them, then by height buckets of [BUCKET_SIZE] heights, then by height:
+//!
+//! ```text
+//! <subnet spool>/<replica version>/<bucket>/<height>/<artifact files>
+//! ```
+use ic_types::{Height, ReplicaVersion};
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+/// The number of consecutive heights stored in one bucket directory.
+pub use ic_config::artifact_pool::BACKUP_GROUP_SIZE as BUCKET_SIZE;
+
+/// The file name of the CUP stored at a height.
+pub const CUP_FILE_NAME: &str = "catch_up_package.bin";
+
+/// Returns the first height of the bucket containing `height`.
+pub fn bucket(height: Height) -> u64 {
+    height.get() / BUCKET_SIZE * BUCKET_SIZE
+}
+
+/// The spool of a subnet, containing one [VersionSpool] per replica version.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SubnetSpool {
+    path: PathBuf,
+}
+
+impl SubnetSpool {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the spool of the given replica version, which may not exist
+    /// yet.
+    pub fn version(&self, replica_version: &ReplicaVersion) -> VersionSpool {
+        VersionSpool::new(self.path.join(replica_version.to_string()))
+    }
+
+    /// Returns the spools of all replica versions, or none if the spool
+    /// doesn't exist.
+    pub fn versions(&self) -> io::Result<Vec<VersionSpool>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let mut versions = Vec::new();
+        for entry in fs::read_dir(&self.path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                versions.push(VersionSpool::new(entry.path()));
+            }
+        }
+        Ok(versions)
+    }
+}
+
+/// The artifacts produced by one replica version.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionSpool {
+    path: PathBuf,
+}
+
+impl VersionSpool {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Parses the replica version from the name of the directory.
+    pub fn replica_version(&self) -> Option<ReplicaVersion> {
+        let name = self.path.file_name()?.to_str()?;
+        ReplicaVersion::try_from(name).ok()
+    }
+
+    /// Returns the directory of the artifacts at `height`.
+    pub fn height_dir(&self, height: Height) -> PathBuf {
+        self.path
+            .join(bucket(height).to_string())
+            .join(height.to_string())
+    }
+
+    /// Returns the path of the CUP at `height`.
+    pub fn cup_path(&self, height: Height) -> PathBuf {
+        self.height_dir(height).join(CUP_FILE_NAME)
+    }
+
+    pub fn contains_height(&self, height: Height) -> bool {
+        self.height_dir(height).exists()
+    }
+
+    pub fn contains_cup(&self, height: Height) -> bool {
+        self.cup_path(height).exists()
+    }
+
+    /// Returns the highest height in the highest bucket, or 0 if the spool
+    /// is empty. Entries that are not heights are ignored.
+    pub fn top_height(&self) -> Height {
+        let top_bucket = max_numeric_entry(&self.path);
+        Height::from(max_numeric_entry(&self.path.join(top_bucket.to_string())))
+    }
+
+    /// Returns all heights in ascending order. Fails if the spool contains
+    /// entries that are not heights.
+    pub fn heights(&self) -> io::Result<Vec<Height>> {
+        let mut heights = Vec::new();
+        for bucket in numeric_entries(&self.path)? {
+            for height in numeric_entries(&self.path.join(bucket.to_string()))? {
+                heights.push(Height::from(height));
+            }
+        }
+        heights.sort();
+        Ok(heights)
+    }
+
+    /// Returns the heights of all CUPs in ascending order.
+    pub fn cup_heights(&self) -> io::Result<Vec<Height>> {
+        Ok(self
+            .heights()?
+            .into_iter()
+            .filter(|height| self.contains_cup(*height))
+            .collect())
+    }
+
+    /// Returns the ranges of heights missing between the lowest and the
+    /// highest height.
+    pub fn gaps(&self) -> io::Result<Vec<RangeInclusive<Height>>> {
+        Ok(self
+            .heights()?
+            .windows(2)
+            .filter(|pair| pair[1].get() > pair[0].get() + 1)
+            .map(|pair| Height::from(pair[0].get() + 1)..=Height::from(pair[1].get() - 1))
+            .collect())
+    }
+}
+
+/// Returns the numeric names of all entries of `dir`, failing on others.
+fn numeric_entries(dir: &Path) -> io::Result<Vec<u64>> {
+    fs::read_dir(dir)?
+        .map(|entry| {
+            let name = entry?.file_name();
+            name.to_str()
+                .and_then(|name| name.parse().ok())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{:?} in {:?} is not a height", name, dir),
+                    )
+                })
+        })
+        .collect()
+}
+
+/// Returns the highest numeric name of the entries of `dir`, or 0.
+fn max_numeric_entry(dir: &Path) -> u64 {
+    fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+                .max()
+                .unwrap_or(0)
+        })
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn spool_with_heights(dir: &Path, heights: &[u64]) -> VersionSpool {
+        let spool = VersionSpool::new(dir.join("0.8.0"));
+        for height in heights {
+            fs::create_dir_all(spool.height_dir(Height::from(*height))).unwrap();
+        }
+        spool
+    }
+
+    #[test]
+    fn locates_heights_in_buckets() {
+        let spool = VersionSpool::new("/spool/0.8.0");
+        assert_eq!(
+            spool.height_dir(Height::from(12345)),
+            PathBuf::from("/spool/0.8.0/10000/12345")
+        );
+        assert_eq!(
+            spool.cup_path(Height::from(9999)),
+            PathBuf::from("/spool/0.8.0/0/9999/catch_up_package.bin")
+        );
+        assert_eq!(
+            spool.replica_version(),
+            Some(ReplicaVersion::try_from("0.8.0").unwrap())
+        );
+    }
+
+    #[test]
+    fn iterates_heights_and_detects_gaps() {
+        let tmp = tempdir().unwrap();
+        let spool = spool_with_heights(tmp.path(), &[9998, 9999, 10000, 10003, 20001]);
+        fs::write(spool.cup_path(Height::from(10000)), b"cup").unwrap();
+
+        assert_eq!(
+            spool.heights().unwrap(),
+            [9998, 9999, 10000, 10003, 20001].map(Height::from).to_vec()
+        );
+        assert_eq!(spool.top_height(), Height::from(20001));
+        assert!(spool.contains_height(Height::from(10003)));
+        assert!(!spool.contains_height(Height::from(10001)));
+        assert_eq!(spool.cup_heights().unwrap(), vec![Height::from(10000)]);
+        assert_eq!(
+            spool.gaps().unwrap(),
+            vec![
+                Height::from(10001)..=Height::from(10002),
+                Height::from(10004)..=Height::from(20000),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_entries_that_are_not_heights() {
+        let tmp = tempdir().unwrap();
+        let spool = spool_with_heights(tmp.path(), &[1]);
+        fs::create_dir_all(spool.path().join("0").join("tmp")).unwrap();
+
+        assert!(spool.heights().is_err());
+        assert_eq!(spool.top_height(), Height::from(1));
+    }
+
+    #[test]
+    fn lists_versions_of_a_subnet() {
+        let tmp = tempdir().unwrap();
+        let subnet = SubnetSpool::new(tmp.path().join("subnet"));
+        assert_eq!(subnet.versions().unwrap(), vec![]);
+
+        let version = ReplicaVersion::try_from("0.8.0").unwrap();
+        fs::create_dir_all(subnet.version(&version).path()).unwrap();
+        fs::write(subnet.path().join("file"), b"").unwrap();
+        assert_eq!(subnet.versions().unwrap(), vec![subnet.version(&version)]);
+    }
+}
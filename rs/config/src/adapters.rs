@@ -1,3 +1,4 @@
+use crate::validation::{FieldError, FieldErrors, Validate, ValidationError};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
@@ -17,3 +18,23 @@ pub struct AdaptersConfig {
 fn should_enable_onchain_observability_grpc_server_default() -> bool {
     false
 }
+
+impl Validate for AdaptersConfig {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = FieldErrors::default();
+        let uds_paths = [
+            ("bitcoin_mainnet_uds_path", &self.bitcoin_mainnet_uds_path),
+            ("bitcoin_testnet_uds_path", &self.bitcoin_testnet_uds_path),
+            ("https_outcalls_uds_path", &self.https_outcalls_uds_path),
+        ];
+        for (i, &(field, path)) in uds_paths.iter().enumerate() {
+            if let Some(&(other_field, _)) = uds_paths[..i]
+                .iter()
+                .find(|(_, other_path)| path.is_some() && *other_path == path)
+            {
+                errors.push(field, ValidationError::Duplicate { other_field });
+            }
+        }
+        errors.into_vec()
+    }
+}
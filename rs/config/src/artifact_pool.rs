@@ -1,3 +1,4 @@
+use crate::validation::{FieldError, FieldErrors, Validate};
 use ic_types::Height;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
@@ -48,6 +49,19 @@ impl ArtifactPoolTomlConfig {
     }
 }
 
+impl Validate for ArtifactPoolTomlConfig {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = FieldErrors::default();
+        errors.check_writable("consensus_pool_path", &self.consensus_pool_path);
+        errors.check_non_zero("ingress_pool_max_count", self.ingress_pool_max_count as u64);
+        errors.check_non_zero("ingress_pool_max_bytes", self.ingress_pool_max_bytes as u64);
+        if let Some(backup) = &self.backup {
+            errors.check_writable("backup", &backup.spool_path);
+        }
+        errors.into_vec()
+    }
+}
+
 /// Configuration of the consensus artifact backup.
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct BackupConfig {
@@ -0,0 +1,66 @@
+//! Tooling for replica config files.
+//!
+//! Usage:
+//!   ic-config schema
+//!     Prints the JSON Schema of the replica config file.
+//!   ic-config deprecations
+//!     Prints the deprecated fields of the replica config file, with the
+//!     fields replacing them, as JSON.
+//!   ic-config diff <OLD_CONFIG> <NEW_CONFIG>
+//!     Prints the semantic difference between two replica config files,
+//!     together with warnings about deprecated and unknown fields. Exits with
+//!     0 if the normalized configs are identical and 1 if they differ.
+//!
+//! Exits with 2 on errors.
+use ic_config::{
+    config_deprecations::DEPRECATED_FIELDS, config_diff::ConfigDiff, schema::config_schema,
+    ConfigSource,
+};
+use std::path::PathBuf;
+
+const USAGE: &str = "Usage:
+  ic-config schema
+  ic-config deprecations
+  ic-config diff <OLD_CONFIG> <NEW_CONFIG>";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["schema"] => schema(),
+        ["deprecations"] => deprecations(),
+        ["diff", old, new] => diff(
+            ConfigSource::File(PathBuf::from(old)),
+            ConfigSource::File(PathBuf::from(new)),
+        ),
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+}
+
+fn schema() {
+    let schema = serde_json::to_string_pretty(&config_schema())
+        .expect("Failed to serialize the config schema");
+    println!("{}", schema);
+}
+
+fn deprecations() {
+    let deprecations = serde_json::to_string_pretty(DEPRECATED_FIELDS)
+        .expect("Failed to serialize the deprecated config fields");
+    println!("{}", deprecations);
+}
+
+fn diff(old: ConfigSource, new: ConfigSource) {
+    match ConfigDiff::load(&old, &new) {
+        Ok(diff) => {
+            print!("{}", diff);
+            std::process::exit(if diff.is_empty() { 0 } else { 1 });
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    }
+}
@@ -6,7 +6,9 @@
 use crate::{
     adapters::AdaptersConfig,
     artifact_pool::ArtifactPoolTomlConfig,
+    config_deprecations::MigratedConfig,
     config_parser::{ConfigError, ConfigSource, ConfigValidate},
+    config_profile::ConfigProfile,
     consensus::ConsensusConfig,
     crypto::CryptoConfig,
     execution_environment::Config as HypervisorConfig,
@@ -18,12 +20,23 @@ use crate::{
     nns_registry_replicator::Config as NnsRegistryReplicatorConfig,
     registration::Config as RegistrationConfig,
     registry_client::Config as RegistryClientConfig,
+    secret,
     state_manager::Config as StateManagerConfig,
     transport::TransportConfig,
+    validation::{ConfigDefect, Validate},
 };
 use ic_types::malicious_behaviour::MaliciousBehaviour;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashSet, path::PathBuf};
+use serde_json::{json, Value};
+use std::{
+    collections::HashSet,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// The version of the format written by [Config::dump_effective]. It is
+/// increased whenever the layout of the dump, not of the config, changes.
+pub const EFFECTIVE_CONFIG_SCHEMA_VERSION: u32 = 1;
 
 /// The config struct for the replica.  Just consists of `Config`s for
 /// the components.
@@ -75,6 +88,9 @@ pub struct ConfigOptional {
     pub registration: Option<RegistrationConfig>,
     pub nns_registry_replicator: Option<NnsRegistryReplicatorConfig>,
     pub adapters_config: Option<AdaptersConfig>,
+    /// The profile setting the values the file leaves out. Only applied by
+    /// [crate::LayeredConfig].
+    pub profile: Option<ConfigProfile>,
 }
 
 impl Config {
@@ -127,14 +143,21 @@ impl Config {
     }
 
     /// Load [Config] from the given 'config_descr' where if a section is
-    /// omitted, its value is taken from the given 'default'.
+    /// omitted, its value is taken from the given 'default'. Deprecated fields
+    /// are migrated to their replacement.
     pub fn load_with_default(source: &ConfigSource, default: Config) -> Result<Self, ConfigError> {
-        let cfg = source.load::<ConfigOptional>()?;
+        let cfg = MigratedConfig::load(source)?.config;
+        Ok(Self::from_optional(cfg, default))
+    }
+
+    /// Build a [Config] from the sections present in 'cfg', taking omitted
+    /// sections from the given 'default'.
+    pub fn from_optional(cfg: ConfigOptional, default: Config) -> Self {
         let logger = cfg.logger.unwrap_or(default.logger);
         let orchestrator_logger = cfg.orchestrator_logger.unwrap_or_else(|| logger.clone());
         let csp_vault_logger = cfg.csp_vault_logger.unwrap_or_else(|| logger.clone());
 
-        Ok(Self {
+        Self {
             registry_client: cfg.registry_client.unwrap_or(default.registry_client),
             transport: cfg.transport.unwrap_or(default.transport),
             state_manager: cfg.state_manager.unwrap_or(default.state_manager),
@@ -157,7 +180,63 @@ impl Config {
                 .nns_registry_replicator
                 .unwrap_or(default.nns_registry_replicator),
             adapters_config: cfg.adapters_config.unwrap_or(default.adapters_config),
-        })
+        }
+    }
+
+    /// Validate all sections, returning every defect found.
+    pub fn validate_all(&self) -> Result<(), Vec<ConfigDefect>> {
+        let sections: [(&'static str, &dyn Validate); 10] = [
+            ("transport", &self.transport),
+            ("state_manager", &self.state_manager),
+            ("http_handler", &self.http_handler),
+            ("metrics", &self.metrics),
+            ("artifact_pool", &self.artifact_pool),
+            ("crypto", &self.crypto),
+            ("logger", &self.logger),
+            ("orchestrator_logger", &self.orchestrator_logger),
+            ("csp_vault_logger", &self.csp_vault_logger),
+            ("adapters_config", &self.adapters_config),
+        ];
+        let defects: Vec<ConfigDefect> = sections
+            .iter()
+            .flat_map(|&(section, config)| {
+                config
+                    .validate()
+                    .into_iter()
+                    .map(move |error| ConfigDefect {
+                        section,
+                        field: error.field,
+                        error: error.error,
+                    })
+            })
+            .collect();
+        if defects.is_empty() {
+            Ok(())
+        } else {
+            Err(defects)
+        }
+    }
+
+    /// Writes the config, with all defaults and overrides resolved, to `path`
+    /// as JSON of the form `{"schema_version": .., "config": {..}}`. Keys are
+    /// sorted and secrets are redacted, so that dumps of different replicas
+    /// or versions can be diffed and shared. The file is replaced atomically.
+    pub fn dump_effective(&self, path: &Path) -> std::io::Result<()> {
+        let config = secret::redacted(|| serde_json::to_value(self))?;
+        let dump = json!({
+            "schema_version": EFFECTIVE_CONFIG_SCHEMA_VERSION,
+            "config": sort_keys(config),
+        });
+
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut file = tempfile::NamedTempFile::new_in(dir)?;
+        serde_json::to_writer_pretty(&mut file, &dump)?;
+        file.write_all(b"\n")?;
+        file.persist(path).map_err(|err| err.error)?;
+        Ok(())
     }
 
     /// Load the Replica config from the given source
@@ -171,6 +250,25 @@ impl Config {
     }
 }
 
+/// Orders the keys of all objects in `value`, whatever the map
+/// implementation of `serde_json`.
+fn sort_keys(value: Value) -> Value {
+    match value {
+        Value::Object(object) => {
+            let mut entries: Vec<(String, Value)> = object.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, sort_keys(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(sort_keys).collect()),
+        value => value,
+    }
+}
+
 impl ConfigValidate for ConfigOptional {
     fn validate(self) -> Result<Self, String> {
         let mut same_uds_paths = false;
@@ -236,4 +334,36 @@ mod tests {
             "/tmp/ic_crypto"
         );
     }
+
+    #[test]
+    fn dump_effective_writes_versioned_resolved_config() {
+        let temp_dir = tempdir_deleted_at_end_of_scope().expect("Failed creating a temp dir.");
+        let config = Config::new(temp_dir.path().to_path_buf());
+        let dump_path = temp_dir.path().join("effective_config.json");
+
+        config
+            .dump_effective(&dump_path)
+            .expect("Failed dumping the effective config.");
+
+        let dump: Value = serde_json::from_str(
+            &std::fs::read_to_string(&dump_path).expect("Failed reading the dump."),
+        )
+        .expect("The dump is not JSON.");
+        assert_eq!(
+            dump["schema_version"],
+            json!(EFFECTIVE_CONFIG_SCHEMA_VERSION)
+        );
+        assert_eq!(
+            dump["config"]["state_manager"]["state_root"],
+            json!(temp_dir.path().join("state"))
+        );
+        let sections: Vec<&String> = dump["config"]
+            .as_object()
+            .expect("Expected the config sections.")
+            .keys()
+            .collect();
+        let mut sorted_sections = sections.clone();
+        sorted_sections.sort();
+        assert_eq!(sections, sorted_sections);
+    }
 }
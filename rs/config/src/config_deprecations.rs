@@ -0,0 +1,326 @@
+//! Evolution of the replica config schema without breaking existing
+//! `ic.json5` files.
+//!
+//! Fields that are renamed or no longer used are listed in
+//! [DEPRECATED_FIELDS]. Before a config file is deserialized, the value of a
+//! renamed field is moved to its replacement, and every deprecated field the
+//! file uses is reported as a [Deprecation], so that the replica can warn
+//! about it. The same applies to the paths of environment and command line
+//! overrides.
+
+use crate::{
+    config::ConfigOptional,
+    config_layers::ConfigOverride,
+    config_parser::{ConfigError, ConfigSource, ConfigValidate},
+};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// A field that is still accepted by the replica, but should no longer be
+/// used.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DeprecatedField {
+    /// The path of the field, e.g. `http_handler.listen_addr`.
+    pub field: &'static str,
+    /// The path of the field that replaces it, if any. The value of the
+    /// deprecated field must be accepted by the replacement.
+    pub replacement: Option<&'static str>,
+}
+
+/// The deprecated fields of the replica config. This list is printed by
+/// `ic-config deprecations`, so that deployment tooling can migrate the
+/// config files it generates.
+pub const DEPRECATED_FIELDS: &[DeprecatedField] = &[
+    DeprecatedField {
+        field: "http_handler.listen_addr",
+        replacement: Some("http_handler.listeners"),
+    },
+    DeprecatedField {
+        field: "hypervisor.create_funds_whitelist",
+        replacement: None,
+    },
+    DeprecatedField {
+        field: "logger.node_id",
+        replacement: None,
+    },
+    DeprecatedField {
+        field: "logger.dc_id",
+        replacement: None,
+    },
+    DeprecatedField {
+        field: "orchestrator_logger.node_id",
+        replacement: None,
+    },
+    DeprecatedField {
+        field: "orchestrator_logger.dc_id",
+        replacement: None,
+    },
+    DeprecatedField {
+        field: "csp_vault_logger.node_id",
+        replacement: None,
+    },
+    DeprecatedField {
+        field: "csp_vault_logger.dc_id",
+        replacement: None,
+    },
+];
+
+/// A field of a config file that should be removed or migrated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Deprecation {
+    /// The field was renamed to `replacement`. Its value is still accepted.
+    Renamed {
+        field: String,
+        replacement: &'static str,
+    },
+    /// The field is still accepted, but has no effect.
+    Unused { field: String },
+    /// The field is not part of the current schema and is ignored.
+    Unknown { field: String },
+}
+
+impl fmt::Display for Deprecation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Deprecation::Renamed { field, replacement } => {
+                write!(
+                    f,
+                    "'{}' is deprecated, use '{}' instead",
+                    field, replacement
+                )
+            }
+            Deprecation::Unused { field } => {
+                write!(f, "'{}' is deprecated and has no effect", field)
+            }
+            Deprecation::Unknown { field } => {
+                write!(f, "'{}' is not a known config field and is ignored", field)
+            }
+        }
+    }
+}
+
+impl DeprecatedField {
+    pub(crate) fn deprecation(&self) -> Deprecation {
+        let field = self.field.to_string();
+        match self.replacement {
+            Some(replacement) => Deprecation::Renamed { field, replacement },
+            None => Deprecation::Unused { field },
+        }
+    }
+}
+
+/// The sections of a config file, after its deprecated fields were migrated.
+pub struct MigratedConfig {
+    pub config: ConfigOptional,
+    /// The migrated contents of the file.
+    pub value: Value,
+    /// The deprecated fields used by the file.
+    pub deprecations: Vec<Deprecation>,
+}
+
+impl MigratedConfig {
+    /// Reads, migrates and validates the config of `source`. The config of
+    /// [ConfigSource::Default] is empty.
+    pub fn load(source: &ConfigSource) -> Result<Self, ConfigError> {
+        match source.read()? {
+            Some(cfg_str) => Self::parse(source, &cfg_str),
+            None => Ok(Self {
+                config: ConfigOptional::default(),
+                value: Value::Null,
+                deprecations: vec![],
+            }),
+        }
+    }
+
+    /// Migrates and validates `cfg_str`, which was read from `source`.
+    pub fn parse(source: &ConfigSource, cfg_str: &str) -> Result<Self, ConfigError> {
+        let parse_error = |message: String| ConfigError::ParseError {
+            source: source.clone(),
+            message,
+        };
+        let mut value =
+            json5::from_str::<Value>(cfg_str).map_err(|err| parse_error(err.to_string()))?;
+        let deprecations = migrate(&mut value).map_err(parse_error)?;
+        let config = serde_json::from_value::<ConfigOptional>(value.clone())
+            .map_err(|err| parse_error(err.to_string()))?
+            .validate()
+            .map_err(|message| ConfigError::ValidationError {
+                source: source.clone(),
+                message,
+            })?;
+        Ok(Self {
+            config,
+            value,
+            deprecations,
+        })
+    }
+}
+
+/// Moves the values of renamed fields to their replacement, and returns the
+/// deprecated fields used by `value`.
+pub fn migrate(value: &mut Value) -> Result<Vec<Deprecation>, String> {
+    let mut deprecations = Vec::new();
+    for deprecated in DEPRECATED_FIELDS {
+        let path: Vec<&str> = deprecated.field.split('.').collect();
+        let old_value = match deprecated.replacement {
+            Some(_) => remove(value, &path),
+            None => lookup(value, &path).cloned(),
+        };
+        let old_value = match old_value {
+            Some(old_value) => old_value,
+            None => continue,
+        };
+        if let Some(replacement) = deprecated.replacement {
+            let replacement_path: Vec<&str> = replacement.split('.').collect();
+            if lookup(value, &replacement_path).is_some() {
+                return Err(format!(
+                    "Both '{}' and its replacement '{}' are set, remove '{}'",
+                    deprecated.field, replacement, deprecated.field
+                ));
+            }
+            insert(value, &replacement_path, old_value)?;
+        }
+        deprecations.push(deprecated.deprecation());
+    }
+    Ok(deprecations)
+}
+
+/// Replaces a deprecated field at the beginning of the override's path by
+/// its replacement, and returns the deprecated field, if any.
+pub fn migrate_override(config_override: &mut ConfigOverride) -> Option<Deprecation> {
+    let deprecated = DEPRECATED_FIELDS.iter().find(|deprecated| {
+        let path: Vec<&str> = deprecated.field.split('.').collect();
+        path.len() <= config_override.path.len()
+            && path.iter().zip(&config_override.path).all(|(a, b)| a == b)
+    })?;
+    if let Some(replacement) = deprecated.replacement {
+        let depth = deprecated.field.split('.').count();
+        config_override
+            .path
+            .splice(..depth, replacement.split('.').map(|key| key.to_string()));
+    }
+    Some(deprecated.deprecation())
+}
+
+fn lookup<'a>(value: &'a Value, path: &[&str]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, key| value.get(key))
+}
+
+fn remove(mut value: &mut Value, path: &[&str]) -> Option<Value> {
+    let (key, parents) = path.split_last()?;
+    for parent in parents {
+        value = value.get_mut(parent)?;
+    }
+    value.as_object_mut()?.remove(*key)
+}
+
+/// Inserts `new_value` at `path`, creating the missing parents.
+fn insert(value: &mut Value, path: &[&str], new_value: Value) -> Result<(), String> {
+    let (key, parents) = path
+        .split_last()
+        .ok_or_else(|| "Empty config path".to_string())?;
+    let mut object: &mut Map<String, Value> = value
+        .as_object_mut()
+        .ok_or_else(|| "Config is not an object".to_string())?;
+    for parent in parents {
+        object = object
+            .entry(parent.to_string())
+            .or_insert_with(|| Value::Object(Map::new()))
+            .as_object_mut()
+            .ok_or_else(|| format!("'{}' is not an object", parent))?;
+    }
+    object.insert(key.to_string(), new_value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(cfg: &str) -> Result<MigratedConfig, ConfigError> {
+        MigratedConfig::parse(&ConfigSource::Literal(cfg.to_string()), cfg)
+    }
+
+    #[test]
+    fn moves_renamed_fields_to_their_replacement() {
+        let config = parse(
+            r#"{
+                http_handler: { listen_addr: "127.0.0.1:8080", max_tcp_connections: 10 },
+                logger: { node_id: 1 },
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.value["http_handler"],
+            json!({ "listeners": "127.0.0.1:8080", "max_tcp_connections": 10 })
+        );
+        assert_eq!(
+            config.config.http_handler.unwrap().tcp_listen_addr(),
+            Some("127.0.0.1:8080".parse().unwrap())
+        );
+        assert_eq!(
+            config.deprecations,
+            vec![
+                Deprecation::Renamed {
+                    field: "http_handler.listen_addr".to_string(),
+                    replacement: "http_handler.listeners",
+                },
+                Deprecation::Unused {
+                    field: "logger.node_id".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_a_renamed_field_together_with_its_replacement() {
+        let err = parse(
+            r#"{ http_handler: { listen_addr: "127.0.0.1:8080", listeners: ["127.0.0.1:8081"] } }"#,
+        )
+        .err()
+        .unwrap();
+        assert!(
+            err.to_string()
+                .contains("Both 'http_handler.listen_addr' and its replacement"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn migrates_the_paths_of_overrides() {
+        let mut config_override: ConfigOverride =
+            r#"http_handler.listen_addr=["[::1]:8080"]"#.parse().unwrap();
+        assert_eq!(
+            migrate_override(&mut config_override),
+            Some(Deprecation::Renamed {
+                field: "http_handler.listen_addr".to_string(),
+                replacement: "http_handler.listeners",
+            })
+        );
+        assert_eq!(config_override.path, vec!["http_handler", "listeners"]);
+
+        let mut config_override: ConfigOverride =
+            "http_handler.max_tcp_connections=10".parse().unwrap();
+        assert_eq!(migrate_override(&mut config_override), None);
+        assert_eq!(
+            config_override.path,
+            vec!["http_handler", "max_tcp_connections"]
+        );
+    }
+
+    #[test]
+    fn replacements_are_not_deprecated() {
+        for deprecated in DEPRECATED_FIELDS {
+            if let Some(replacement) = deprecated.replacement {
+                assert!(
+                    DEPRECATED_FIELDS.iter().all(|d| d.field != replacement),
+                    "{}",
+                    replacement
+                );
+            }
+        }
+    }
+}
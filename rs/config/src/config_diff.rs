@@ -0,0 +1,264 @@
+//! Semantic comparison of replica config files, e.g. of the `ic.json5` of
+//! two replica versions.
+//!
+//! Both files are normalized against the defaults of the current schema
+//! before they are compared, so that omitted sections, reordered fields and
+//! formatting do not show up as differences. Fields that were renamed, are
+//! no longer used, or are not part of the schema at all are reported as
+//! [Deprecation]s, since the replica silently ignores the latter.
+
+pub use crate::config_deprecations::Deprecation;
+use crate::{
+    config::Config,
+    config_deprecations::{MigratedConfig, DEPRECATED_FIELDS},
+    config_layers::collect_leaves,
+    config_parser::{ConfigError, ConfigSource},
+};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::PathBuf;
+
+/// The parent directory of the default paths, so that they are identical
+/// for both compared files.
+const NORMALIZATION_PARENT_DIR: &str = "/var/lib/ic/data";
+
+/// A config file, normalized against the defaults of the current schema.
+pub struct NormalizedConfig {
+    pub config: Config,
+    /// The value of every field of the normalized config, by path.
+    pub values: BTreeMap<String, Value>,
+    pub deprecations: Vec<Deprecation>,
+}
+
+impl NormalizedConfig {
+    pub fn load(source: &ConfigSource) -> Result<Self, ConfigError> {
+        let cfg_str = source.read()?.unwrap_or_else(|| "{}".to_string());
+        let raw: Value = json5::from_str(&cfg_str).map_err(|err| ConfigError::ParseError {
+            source: source.clone(),
+            message: err.to_string(),
+        })?;
+        let cfg = MigratedConfig::parse(source, &cfg_str)?.config;
+        let config =
+            Config::from_optional(cfg, Config::new(PathBuf::from(NORMALIZATION_PARENT_DIR)));
+
+        let normalized = serde_json::to_value(&config).map_err(|err| ConfigError::ParseError {
+            source: source.clone(),
+            message: err.to_string(),
+        })?;
+        let mut leaves = Vec::new();
+        collect_leaves(&normalized, String::new(), &mut leaves);
+
+        Ok(Self {
+            config,
+            values: leaves.into_iter().collect(),
+            deprecations: find_deprecations(&raw, &normalized),
+        })
+    }
+}
+
+fn find_deprecations(raw: &Value, normalized: &Value) -> Vec<Deprecation> {
+    let mut raw_leaves = Vec::new();
+    collect_leaves(raw, String::new(), &mut raw_leaves);
+
+    let mut deprecations = Vec::new();
+    let mut reported = BTreeSet::new();
+    for (path, value) in raw_leaves {
+        let deprecated = DEPRECATED_FIELDS.iter().find(|deprecated| {
+            path == deprecated.field || path.starts_with(&format!("{}.", deprecated.field))
+        });
+        if let Some(deprecated) = deprecated {
+            if reported.insert(deprecated.field.to_string()) {
+                deprecations.push(deprecated.deprecation());
+            }
+            continue;
+        }
+        if value.is_null() {
+            continue;
+        }
+        if let Some(field) = unknown_prefix(normalized, &path) {
+            if reported.insert(field.clone()) {
+                deprecations.push(Deprecation::Unknown { field });
+            }
+        }
+    }
+    deprecations
+}
+
+/// Returns the shortest prefix of `path` that does not exist in the
+/// normalized config. Fields with a custom representation, e.g. a listener
+/// written as an object, end in a non-object value and count as known.
+fn unknown_prefix(normalized: &Value, path: &str) -> Option<String> {
+    let mut value = normalized;
+    let mut prefix = String::new();
+    for key in path.split('.') {
+        if !prefix.is_empty() {
+            prefix.push('.');
+        }
+        prefix.push_str(key);
+        match value {
+            Value::Object(object) => match object.get(key) {
+                Some(child) => value = child,
+                None => return Some(prefix),
+            },
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// The difference in the value of a single field.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValueChange {
+    Added(Value),
+    Removed(Value),
+    Changed { old: Value, new: Value },
+}
+
+/// The semantic difference between two config files.
+pub struct ConfigDiff {
+    /// The changed fields, by path.
+    pub changes: BTreeMap<String, ValueChange>,
+    pub old_deprecations: Vec<Deprecation>,
+    pub new_deprecations: Vec<Deprecation>,
+}
+
+impl ConfigDiff {
+    pub fn new(old: &NormalizedConfig, new: &NormalizedConfig) -> Self {
+        let mut changes = BTreeMap::new();
+        for (path, old_value) in &old.values {
+            match new.values.get(path) {
+                None => {
+                    changes.insert(path.clone(), ValueChange::Removed(old_value.clone()));
+                }
+                Some(new_value) if new_value != old_value => {
+                    changes.insert(
+                        path.clone(),
+                        ValueChange::Changed {
+                            old: old_value.clone(),
+                            new: new_value.clone(),
+                        },
+                    );
+                }
+                Some(_) => {}
+            }
+        }
+        for (path, new_value) in &new.values {
+            if !old.values.contains_key(path) {
+                changes.insert(path.clone(), ValueChange::Added(new_value.clone()));
+            }
+        }
+        Self {
+            changes,
+            old_deprecations: old.deprecations.clone(),
+            new_deprecations: new.deprecations.clone(),
+        }
+    }
+
+    /// Loads, normalizes and compares the config files.
+    pub fn load(old: &ConfigSource, new: &ConfigSource) -> Result<Self, ConfigError> {
+        Ok(Self::new(
+            &NormalizedConfig::load(old)?,
+            &NormalizedConfig::load(new)?,
+        ))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl fmt::Display for ConfigDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (path, change) in &self.changes {
+            match change {
+                ValueChange::Added(value) => writeln!(f, "+ {} = {}", path, value)?,
+                ValueChange::Removed(value) => writeln!(f, "- {} = {}", path, value)?,
+                ValueChange::Changed { old, new } => {
+                    writeln!(f, "~ {} = {} -> {}", path, old, new)?
+                }
+            }
+        }
+        for (file, deprecations) in [
+            ("old", &self.old_deprecations),
+            ("new", &self.new_deprecations),
+        ] {
+            for deprecation in deprecations {
+                writeln!(f, "warning ({} config): {}", file, deprecation)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(cfg: &str) -> NormalizedConfig {
+        NormalizedConfig::load(&ConfigSource::Literal(cfg.to_string())).unwrap()
+    }
+
+    #[test]
+    fn equivalent_configs_have_no_differences() {
+        let old = load(r#"{ http_handler: { listen_addr: "127.0.0.1:8080" } }"#);
+        let new = load(
+            r#"{
+                // Comments, field order and omitted defaults do not matter.
+                http_handler: { max_tcp_connections: 20000, listeners: ["127.0.0.1:8080"] },
+            }"#,
+        );
+        let diff = ConfigDiff::new(&old, &new);
+        assert!(diff.is_empty(), "{}", diff);
+    }
+
+    #[test]
+    fn reports_changed_values() {
+        let old = load("{ http_handler: { max_tcp_connections: 10 } }");
+        let new = load("{ http_handler: { max_tcp_connections: 20 } }");
+        let diff = ConfigDiff::new(&old, &new);
+        assert_eq!(
+            diff.changes,
+            BTreeMap::from([(
+                "http_handler.max_tcp_connections".to_string(),
+                ValueChange::Changed {
+                    old: 10.into(),
+                    new: 20.into()
+                }
+            )])
+        );
+        assert_eq!(
+            diff.to_string(),
+            "~ http_handler.max_tcp_connections = 10 -> 20\n"
+        );
+    }
+
+    #[test]
+    fn reports_deprecated_and_unknown_fields() {
+        let config = load(
+            r#"{
+                http_handler: { listen_addr: "127.0.0.1:8080", no_such_field: 1 },
+                logger: { node_id: 1 },
+                no_such_section: { field: true },
+            }"#,
+        );
+        assert_eq!(
+            config.deprecations,
+            vec![
+                Deprecation::Renamed {
+                    field: "http_handler.listen_addr".to_string(),
+                    replacement: "http_handler.listeners",
+                },
+                Deprecation::Unknown {
+                    field: "http_handler.no_such_field".to_string(),
+                },
+                Deprecation::Unused {
+                    field: "logger.node_id".to_string(),
+                },
+                Deprecation::Unknown {
+                    field: "no_such_section".to_string(),
+                },
+            ]
+        );
+    }
+}
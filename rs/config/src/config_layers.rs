@@ -0,0 +1,633 @@
+//! Layered loading of the replica [Config].
+//!
+//! The effective configuration is assembled from the following layers, each
+//! overriding the previous one:
+//!
+//! 1. the hard-coded defaults,
+//! 2. the values of the [ConfigProfile] selected on the command line or by
+//!    the `profile` of the config file, where the file doesn't set them,
+//! 3. the sections present in the config file (see [ConfigSource]),
+//! 4. environment variables of the form `IC_<SECTION>__<FIELD>=<VALUE>`, e.g.
+//!    `IC_HTTP_HANDLER__MAX_TCP_CONNECTIONS=100`,
+//! 5. command line overrides of the form `<section>.<field>=<value>`, e.g.
+//!    `http_handler.max_tcp_connections=100`.
+//!
+//! The layer every value was taken from is tracked, so that the effective
+//! config and its provenance can be dumped at startup. Deprecated fields of
+//! the file and of the overrides are migrated, see [crate::config_deprecations].
+
+use crate::{
+    config::Config,
+    config_deprecations::{migrate_override, Deprecation, MigratedConfig},
+    config_parser::{ConfigError, ConfigSource},
+    config_profile::ConfigProfile,
+    secret,
+};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Prefix of the environment variables that override config values.
+pub const ENV_VAR_PREFIX: &str = "IC_";
+
+/// Separates the path components in environment variable names.
+const ENV_VAR_PATH_SEPARATOR: &str = "__";
+
+/// The layer a config value was taken from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigLayer {
+    /// The hard-coded default.
+    Default,
+    /// The config profile.
+    Profile(ConfigProfile),
+    /// The config file (or literal, or stdin).
+    Source(ConfigSource),
+    /// The environment variable with the given name.
+    Env(String),
+    /// A command line override.
+    Cli,
+}
+
+impl fmt::Display for ConfigLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigLayer::Default => write!(f, "default"),
+            ConfigLayer::Profile(profile) => write!(f, "profile {}", profile),
+            ConfigLayer::Source(source) => write!(f, "{}", source),
+            ConfigLayer::Env(name) => write!(f, "env {}", name),
+            ConfigLayer::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+/// An override of a single config value, e.g. `http_handler.port_file_path=/tmp/port`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigOverride {
+    /// The path of the overridden value, e.g. `["http_handler", "port_file_path"]`.
+    pub path: Vec<String>,
+    /// The new value. It is parsed as JSON5 unless the overridden value is a
+    /// string, in which case it is taken literally.
+    pub value: String,
+}
+
+impl FromStr for ConfigOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected <path>=<value>, got '{}'", s))?;
+        let path: Vec<String> = path.split('.').map(|key| key.trim().to_string()).collect();
+        if path.iter().any(|key| key.is_empty()) {
+            return Err(format!("Invalid config path in '{}'", s));
+        }
+        Ok(Self {
+            path,
+            value: value.to_string(),
+        })
+    }
+}
+
+impl ConfigOverride {
+    /// Returns the override specified by the environment variable `name`, if
+    /// it is of the form `IC_<SECTION>__<FIELD>`.
+    pub fn from_env_var(name: &str, value: &str) -> Option<Self> {
+        let path = name.strip_prefix(ENV_VAR_PREFIX)?;
+        if !path.contains(ENV_VAR_PATH_SEPARATOR) {
+            return None;
+        }
+        Some(Self {
+            path: path
+                .split(ENV_VAR_PATH_SEPARATOR)
+                .map(|key| key.to_lowercase())
+                .collect(),
+            value: value.to_string(),
+        })
+    }
+
+    /// The name of the environment variable specifying this override, i.e.,
+    /// the inverse of [ConfigOverride::from_env_var].
+    pub fn env_var_name(&self) -> String {
+        format!(
+            "{}{}",
+            ENV_VAR_PREFIX,
+            self.path
+                .iter()
+                .map(|key| key.to_uppercase())
+                .collect::<Vec<_>>()
+                .join(ENV_VAR_PATH_SEPARATOR)
+        )
+    }
+
+    fn path_string(&self) -> String {
+        self.path.join(".")
+    }
+}
+
+/// The effective replica [Config] together with the layer each of its values
+/// was taken from.
+#[derive(Clone, Debug)]
+pub struct LayeredConfig {
+    pub config: Config,
+    /// Maps config paths to the layer they were taken from. Values not
+    /// covered by any path (or a prefix thereof) are defaults.
+    provenance: BTreeMap<String, ConfigLayer>,
+    /// The deprecated fields used by the file and the overrides, which should
+    /// be migrated.
+    pub deprecations: Vec<Deprecation>,
+}
+
+impl LayeredConfig {
+    /// Loads the config layers on top of `default`. Only environment
+    /// variables (from `env`) naming a config section are considered. The
+    /// `profile` given on the command line takes precedence over the one of
+    /// the config file.
+    pub fn load(
+        source: &ConfigSource,
+        default: Config,
+        profile: Option<ConfigProfile>,
+        env: impl IntoIterator<Item = (String, String)>,
+        cli_overrides: &[ConfigOverride],
+    ) -> Result<Self, ConfigError> {
+        let MigratedConfig {
+            config: cfg,
+            value: file_value,
+            mut deprecations,
+        } = MigratedConfig::load(source)?;
+        let profile = profile.or(cfg.profile);
+        let mut provenance = BTreeMap::new();
+
+        let file_sections = to_value(&cfg)?;
+        if let Value::Object(sections) = &file_sections {
+            for (section, value) in sections {
+                if !value.is_null() {
+                    provenance.insert(section.clone(), ConfigLayer::Source(source.clone()));
+                }
+            }
+        }
+        // The orchestrator and CSP vault loggers fall back to the main logger.
+        if cfg.logger.is_some() {
+            for section in ["orchestrator_logger", "csp_vault_logger"] {
+                provenance
+                    .entry(section.to_string())
+                    .or_insert_with(|| ConfigLayer::Source(source.clone()));
+            }
+        }
+
+        let config = Config::from_optional(cfg, default);
+        let mut value = to_value(&config)?;
+
+        if let Some(profile) = profile {
+            for (path, profile_value) in profile.values() {
+                // The values of the file take precedence. `file_value` is
+                // migrated, so a deprecated field of the file, e.g.
+                // `http_handler.listen_addr`, is found under its replacement.
+                if lookup(&file_value, &path).is_some() {
+                    continue;
+                }
+                set_value(&mut value, &path, profile_value).map_err(|message| {
+                    ConfigError::OverrideError {
+                        layer: ConfigLayer::Profile(profile),
+                        message,
+                    }
+                })?;
+                provenance.insert(path.join("."), ConfigLayer::Profile(profile));
+            }
+        }
+
+        let mut env_overrides: Vec<(String, ConfigOverride)> = env
+            .into_iter()
+            .filter_map(|(name, raw)| ConfigOverride::from_env_var(&name, &raw).map(|o| (name, o)))
+            .filter(|(_, o)| value.get(&o.path[0]).is_some())
+            .collect();
+        env_overrides.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let overrides = env_overrides
+            .into_iter()
+            .map(|(name, o)| (ConfigLayer::Env(name), o))
+            .chain(cli_overrides.iter().map(|o| (ConfigLayer::Cli, o.clone())));
+        let mut applied = Vec::new();
+        for (layer, mut config_override) in overrides {
+            let renamed = match migrate_override(&mut config_override) {
+                Some(deprecation) => {
+                    let renamed = matches!(deprecation, Deprecation::Renamed { .. });
+                    if !deprecations.contains(&deprecation) {
+                        deprecations.push(deprecation);
+                    }
+                    renamed
+                }
+                None => false,
+            };
+            let new_value = apply_override(&mut value, &config_override).map_err(|message| {
+                ConfigError::OverrideError {
+                    layer: layer.clone(),
+                    message,
+                }
+            })?;
+            provenance.insert(config_override.path_string(), layer.clone());
+            applied.push((layer, config_override, new_value, renamed));
+        }
+
+        let config: Config =
+            serde_json::from_value(value).map_err(|err| ConfigError::ParseError {
+                source: source.clone(),
+                message: err.to_string(),
+            })?;
+
+        // Unknown fields are silently dropped by serde, so check that every
+        // override made it into the effective config.
+        let value = to_value(&config)?;
+        for (layer, config_override, new_value, renamed) in applied {
+            // The replacement of a renamed field may normalize its value, e.g.
+            // a single listener into a list of them.
+            let effective_value = lookup(&value, &config_override.path);
+            let known = if renamed {
+                effective_value.is_some()
+            } else {
+                effective_value == Some(&new_value)
+            };
+            if !known {
+                return Err(ConfigError::OverrideError {
+                    layer,
+                    message: format!("Unknown config field '{}'", config_override.path_string()),
+                });
+            }
+        }
+
+        Ok(Self {
+            config,
+            provenance,
+            deprecations,
+        })
+    }
+
+    /// Loads the replica config layers on top of the defaults for the given
+    /// `tmpdir`, reading overrides from the process environment. Exits the
+    /// process if the config cannot be loaded.
+    pub fn load_with_tmpdir(
+        source: ConfigSource,
+        tmpdir: PathBuf,
+        profile: Option<ConfigProfile>,
+        cli_overrides: &[ConfigOverride],
+    ) -> Self {
+        Self::load(
+            &source,
+            Config::new(tmpdir),
+            profile,
+            std::env::vars(),
+            cli_overrides,
+        )
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to load config:\n  {}", err);
+            std::process::exit(1);
+        })
+    }
+
+    /// Returns the layer the value at `path` (e.g. `http_handler.max_tcp_connections`)
+    /// was taken from.
+    pub fn layer_of(&self, path: &str) -> &ConfigLayer {
+        let mut prefix = path;
+        loop {
+            if let Some(layer) = self.provenance.get(prefix) {
+                return layer;
+            }
+            match prefix.rsplit_once('.') {
+                Some((parent, _)) => prefix = parent,
+                None => return &ConfigLayer::Default,
+            }
+        }
+    }
+
+    /// Renders every value of the effective config, one per line, together
+    /// with the layer it was taken from. Secrets are redacted.
+    pub fn dump(&self) -> String {
+        let mut leaves = Vec::new();
+        if let Ok(value) = secret::redacted(|| serde_json::to_value(&self.config)) {
+            collect_leaves(&value, String::new(), &mut leaves);
+        }
+        leaves
+            .into_iter()
+            .map(|(path, value)| format!("{} = {} ({})\n", path, value, self.layer_of(&path)))
+            .collect()
+    }
+}
+
+fn to_value<T: serde::Serialize>(value: &T) -> Result<Value, ConfigError> {
+    serde_json::to_value(value).map_err(|err| ConfigError::ParseError {
+        source: ConfigSource::Default,
+        message: err.to_string(),
+    })
+}
+
+fn lookup<'a>(value: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, key| value.get(key))
+}
+
+/// Sets the value at the override's path and returns the new value.
+fn apply_override(value: &mut Value, config_override: &ConfigOverride) -> Result<Value, String> {
+    let new_value = match lookup(value, &config_override.path) {
+        Some(Value::String(_)) => Value::String(config_override.value.clone()),
+        _ => json5::from_str::<Value>(&config_override.value)
+            .unwrap_or_else(|_| Value::String(config_override.value.clone())),
+    };
+    set_value(value, &config_override.path, new_value.clone())?;
+    Ok(new_value)
+}
+
+/// Sets the value at `path`, whose parents must exist.
+fn set_value(value: &mut Value, path: &[String], new_value: Value) -> Result<(), String> {
+    let (key, parents) = path
+        .split_last()
+        .ok_or_else(|| "Empty config path".to_string())?;
+    let mut object: &mut Map<String, Value> = value
+        .as_object_mut()
+        .ok_or_else(|| "Config is not an object".to_string())?;
+    for parent in parents {
+        object = object
+            .get_mut(parent)
+            .and_then(Value::as_object_mut)
+            .ok_or_else(|| format!("Unknown config section '{}'", path.join(".")))?;
+    }
+    object.insert(key.clone(), new_value);
+    Ok(())
+}
+
+pub(crate) fn collect_leaves(value: &Value, path: String, leaves: &mut Vec<(String, Value)>) {
+    match value {
+        Value::Object(object) if !object.is_empty() => {
+            for (key, value) in object {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                collect_leaves(value, path, leaves);
+            }
+        }
+        _ => leaves.push((path, value.clone())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::ConfigOptional, metrics::Exporter, validation::Validate};
+
+    fn load(
+        file: &str,
+        env: &[(&str, &str)],
+        cli_overrides: &[&str],
+    ) -> Result<LayeredConfig, ConfigError> {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let cli_overrides: Vec<ConfigOverride> =
+            cli_overrides.iter().map(|o| o.parse().unwrap()).collect();
+        LayeredConfig::load(
+            &ConfigSource::Literal(file.to_string()),
+            Config::new(tmpdir.path().to_path_buf()),
+            None,
+            env.iter()
+                .map(|(name, value)| (name.to_string(), value.to_string())),
+            &cli_overrides,
+        )
+    }
+
+    #[test]
+    fn later_layers_take_precedence() {
+        let config = load(
+            "{ http_handler: { max_tcp_connections: 10, request_timeout_seconds: 10 } }",
+            &[
+                ("IC_HTTP_HANDLER__MAX_TCP_CONNECTIONS", "20"),
+                ("IC_HTTP_HANDLER__REQUEST_TIMEOUT_SECONDS", "20"),
+            ],
+            &["http_handler.max_tcp_connections=30"],
+        )
+        .unwrap();
+
+        assert_eq!(config.config.http_handler.max_tcp_connections, 30);
+        assert_eq!(config.config.http_handler.request_timeout_seconds, 20);
+        assert_eq!(
+            config.layer_of("http_handler.max_tcp_connections"),
+            &ConfigLayer::Cli
+        );
+        assert_eq!(
+            config.layer_of("http_handler.request_timeout_seconds"),
+            &ConfigLayer::Env("IC_HTTP_HANDLER__REQUEST_TIMEOUT_SECONDS".to_string())
+        );
+        assert!(matches!(
+            config.layer_of("http_handler.connection_read_timeout_seconds"),
+            ConfigLayer::Source(_)
+        ));
+        assert_eq!(config.layer_of("metrics.exporter"), &ConfigLayer::Default);
+    }
+
+    #[test]
+    fn override_values_are_parsed_according_to_the_field_type() {
+        let config = load(
+            "{}",
+            &[],
+            &[
+                r#"http_handler.listeners=["127.0.0.1:1234"]"#,
+                "logger.node_id=42",
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            config.config.http_handler.tcp_listen_addr(),
+            Some("127.0.0.1:1234".parse().unwrap())
+        );
+        assert_eq!(config.config.logger.node_id, 42);
+    }
+
+    #[test]
+    fn env_var_name_is_the_inverse_of_from_env_var() {
+        let config_override: ConfigOverride =
+            "http_handler.request_timeout_seconds=20".parse().unwrap();
+        let name = config_override.env_var_name();
+        assert_eq!(name, "IC_HTTP_HANDLER__REQUEST_TIMEOUT_SECONDS");
+        assert_eq!(
+            ConfigOverride::from_env_var(&name, &config_override.value),
+            Some(config_override)
+        );
+    }
+
+    #[test]
+    fn unrelated_env_vars_are_ignored() {
+        assert!(load(
+            "{}",
+            &[("IC_SOME_TOOL__SETTING", "1"), ("IC_DEBUG", "1")],
+            &[]
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert!(matches!(
+            load("{}", &[("IC_HTTP_HANDLER__NO_SUCH_FIELD", "1")], &[]),
+            Err(ConfigError::OverrideError {
+                layer: ConfigLayer::Env(_),
+                ..
+            })
+        ));
+        assert!(matches!(
+            load("{}", &[], &["http_handler.max_tcp_connections.x=1"]),
+            Err(ConfigError::OverrideError {
+                layer: ConfigLayer::Cli,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn dump_lists_values_with_their_layer() {
+        let config = load("{}", &[], &["http_handler.max_tcp_connections=30"]).unwrap();
+        let dump = config.dump();
+        assert!(dump.contains("http_handler.max_tcp_connections = 30 (command line)\n"));
+        assert!(dump.contains("http_handler.request_timeout_seconds = 300 (default)\n"));
+    }
+
+    fn load_with_profile(
+        file: &str,
+        profile: Option<ConfigProfile>,
+        cli_overrides: &[&str],
+    ) -> LayeredConfig {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let cli_overrides: Vec<ConfigOverride> =
+            cli_overrides.iter().map(|o| o.parse().unwrap()).collect();
+        LayeredConfig::load(
+            &ConfigSource::Literal(file.to_string()),
+            Config::new(tmpdir.path().to_path_buf()),
+            profile,
+            std::iter::empty(),
+            &cli_overrides,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn profile_sets_the_values_the_file_leaves_out() {
+        let config = load_with_profile(
+            r#"{ profile: "local-dev", http_handler: { max_tcp_connections: 10 } }"#,
+            None,
+            &["http_handler.shutdown_grace_period_seconds=5"],
+        );
+
+        let profile = ConfigLayer::Profile(ConfigProfile::LocalDev);
+        assert_eq!(
+            config.config.http_handler.tcp_listen_addr(),
+            Some("127.0.0.1:8080".parse().unwrap())
+        );
+        assert_eq!(config.layer_of("http_handler.listeners"), &profile);
+        assert_eq!(
+            config.config.metrics.exporter,
+            Exporter::Http("127.0.0.1:9090".parse().unwrap())
+        );
+        assert_eq!(config.layer_of("metrics.exporter"), &profile);
+
+        assert_eq!(config.config.http_handler.max_tcp_connections, 10);
+        assert!(matches!(
+            config.layer_of("http_handler.max_tcp_connections"),
+            ConfigLayer::Source(_)
+        ));
+        assert_eq!(config.config.http_handler.shutdown_grace_period_seconds, 5);
+        assert_eq!(
+            config.layer_of("http_handler.shutdown_grace_period_seconds"),
+            &ConfigLayer::Cli
+        );
+        assert!(config
+            .dump()
+            .contains("http_handler.drain_connections_on_shutdown = false (profile local-dev)\n"));
+    }
+
+    #[test]
+    fn deprecated_fields_are_migrated_and_reported() {
+        let config = load_with_profile(
+            r#"{ profile: "local-dev", http_handler: { listen_addr: "[::1]:8080" } }"#,
+            None,
+            &["logger.dc_id=1"],
+        );
+        // The profile does not override the listener of the file.
+        assert_eq!(
+            config.config.http_handler.tcp_listen_addr(),
+            Some("[::1]:8080".parse().unwrap())
+        );
+        assert!(matches!(
+            config.layer_of("http_handler.listeners"),
+            ConfigLayer::Source(_)
+        ));
+        assert_eq!(
+            config.deprecations,
+            vec![
+                Deprecation::Renamed {
+                    field: "http_handler.listen_addr".to_string(),
+                    replacement: "http_handler.listeners",
+                },
+                Deprecation::Unused {
+                    field: "logger.dc_id".to_string(),
+                },
+            ]
+        );
+
+        let config = load_with_profile("{}", None, &["http_handler.listen_addr=127.0.0.1:1234"]);
+        assert_eq!(
+            config.config.http_handler.tcp_listen_addr(),
+            Some("127.0.0.1:1234".parse().unwrap())
+        );
+        assert_eq!(config.layer_of("http_handler.listeners"), &ConfigLayer::Cli);
+    }
+
+    #[test]
+    fn profile_of_the_command_line_takes_precedence() {
+        let config = load_with_profile(
+            r#"{ profile: "local-dev" }"#,
+            Some(ConfigProfile::Mainnet),
+            &[],
+        );
+        assert_eq!(
+            config.config.http_handler.tcp_listen_addr(),
+            Some("[::]:8080".parse().unwrap())
+        );
+        assert_eq!(config.config.http_handler.max_tcp_connections, 20_000);
+
+        let config = load_with_profile("{}", None, &[]);
+        assert_eq!(
+            config.layer_of("http_handler.listeners"),
+            &ConfigLayer::Default
+        );
+    }
+
+    #[test]
+    fn every_profile_yields_a_valid_config() {
+        for profile in ConfigProfile::ALL {
+            let config = load_with_profile("{}", Some(profile), &[]);
+            assert_eq!(config.config.http_handler.validate(), vec![], "{}", profile);
+            assert_eq!(config.config.metrics.validate(), vec![], "{}", profile);
+            assert_eq!(profile.name().parse(), Ok(profile));
+        }
+        assert!("dev".parse::<ConfigProfile>().is_err());
+        assert!(json5::from_str::<ConfigOptional>(r#"{ profile: "dev" }"#).is_err());
+    }
+
+    #[test]
+    fn parses_overrides() {
+        assert_eq!(
+            "a.b=c=d".parse::<ConfigOverride>(),
+            Ok(ConfigOverride {
+                path: vec!["a".to_string(), "b".to_string()],
+                value: "c=d".to_string(),
+            })
+        );
+        assert!("a.b".parse::<ConfigOverride>().is_err());
+        assert!("a..b=1".parse::<ConfigOverride>().is_err());
+        assert_eq!(
+            ConfigOverride::from_env_var("IC_STATE_MANAGER__STATE_ROOT", "/tmp"),
+            Some(ConfigOverride {
+                path: vec!["state_manager".to_string(), "state_root".to_string()],
+                value: "/tmp".to_string(),
+            })
+        );
+        assert_eq!(ConfigOverride::from_env_var("HOME", "/root"), None);
+    }
+}
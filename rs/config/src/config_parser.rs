@@ -1,3 +1,4 @@
+use crate::config_layers::ConfigLayer;
 use serde::de::DeserializeOwned;
 use std::fs;
 use std::path::PathBuf;
@@ -32,6 +33,8 @@ pub enum ConfigError {
         source: ConfigSource,
         message: String,
     },
+    /// Failed to apply an override from the environment or the command line.
+    OverrideError { layer: ConfigLayer, message: String },
 }
 
 /// Rules for validating the values of the Config struct
@@ -59,6 +62,13 @@ impl std::fmt::Display for ConfigError {
             Self::ValidationError { source, message } => {
                 write!(f, "Failed to validate config from {}: {}", source, message)
             }
+            Self::OverrideError { layer, message } => {
+                write!(
+                    f,
+                    "Failed to apply config override from {}: {}",
+                    layer, message
+                )
+            }
         }
     }
 }
@@ -78,8 +88,17 @@ impl ConfigSource {
     /// Loads a value from the provided config source.
     /// The source is expected to be a valid JSON5 document.
     pub fn load<T: DeserializeOwned + Default + ConfigValidate>(&self) -> Result<T, ConfigError> {
+        match self.read()? {
+            Some(cfg_str) => self.parse(&cfg_str),
+            None => Ok(Default::default()),
+        }
+    }
+
+    /// Reads the contents of the source, or returns `None` for
+    /// [ConfigSource::Default].
+    pub fn read(&self) -> Result<Option<String>, ConfigError> {
         let cfg_str = match &self {
-            ConfigSource::Default => return Ok(Default::default()),
+            ConfigSource::Default => return Ok(None),
             ConfigSource::Literal(literal) => literal.clone(),
 
             ConfigSource::StdIn => {
@@ -103,8 +122,15 @@ impl ConfigSource {
                 })?
             }
         };
+        Ok(Some(cfg_str))
+    }
 
-        let cfg = json5::from_str::<T>(&cfg_str).map_err(|err| ConfigError::ParseError {
+    /// Parses and validates `cfg_str`, which was read from this source.
+    pub fn parse<T: DeserializeOwned + ConfigValidate>(
+        &self,
+        cfg_str: &str,
+    ) -> Result<T, ConfigError> {
+        let cfg = json5::from_str::<T>(cfg_str).map_err(|err| ConfigError::ParseError {
             source: self.clone(),
             message: err.to_string(),
         })?;
@@ -0,0 +1,97 @@
+//! Named sets of config values for the usual deployments of a replica, so
+//! that e.g. a local replica is set up with `profile: "local-dev"` in
+//! `ic.json5` (or `--config-profile local-dev`) instead of a copy of some
+//! other setup's config.
+//!
+//! A profile only sets the values the config file leaves out: the values of
+//! the file, the environment and the command line take precedence, see
+//! [crate::config_layers].
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConfigProfile {
+    /// A node of a mainnet subnet.
+    Mainnet,
+    /// A node of a testnet, which logs more than mainnet nodes.
+    Testnet,
+    /// A replica run by a developer on their machine: it only listens on the
+    /// loopback interface and shuts down right away.
+    LocalDev,
+}
+
+impl ConfigProfile {
+    pub const ALL: [ConfigProfile; 3] = [
+        ConfigProfile::Mainnet,
+        ConfigProfile::Testnet,
+        ConfigProfile::LocalDev,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConfigProfile::Mainnet => "mainnet",
+            ConfigProfile::Testnet => "testnet",
+            ConfigProfile::LocalDev => "local-dev",
+        }
+    }
+
+    /// The values set by the profile, by config path, e.g.
+    /// `["http_handler", "max_tcp_connections"]`.
+    pub fn values(&self) -> Vec<(Vec<String>, Value)> {
+        let values = match self {
+            ConfigProfile::Mainnet => vec![
+                ("http_handler.listeners", json!(["[::]:8080"])),
+                ("metrics.exporter", json!({ "http": "[::]:9090" })),
+                ("logger.level", json!("info")),
+            ],
+            ConfigProfile::Testnet => vec![
+                ("http_handler.listeners", json!(["[::]:8080"])),
+                ("metrics.exporter", json!({ "http": "[::]:9090" })),
+                ("logger.level", json!("debug")),
+            ],
+            ConfigProfile::LocalDev => vec![
+                ("http_handler.listeners", json!(["127.0.0.1:8080"])),
+                ("http_handler.max_tcp_connections", json!(100)),
+                ("http_handler.shutdown_grace_period_seconds", json!(1)),
+                ("http_handler.drain_connections_on_shutdown", json!(false)),
+                (
+                    "http_handler.admin_access.allow",
+                    json!(["127.0.0.1/32", "::1/128"]),
+                ),
+                ("metrics.exporter", json!({ "http": "127.0.0.1:9090" })),
+                ("logger.level", json!("debug")),
+            ],
+        };
+        values
+            .into_iter()
+            .map(|(path, value)| (path.split('.').map(String::from).collect(), value))
+            .collect()
+    }
+}
+
+impl fmt::Display for ConfigProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for ConfigProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|profile| profile.name() == s)
+            .ok_or_else(|| {
+                format!(
+                    "Unknown config profile '{}', expected one of: {}",
+                    s,
+                    Self::ALL.map(|profile| profile.name()).join(", ")
+                )
+            })
+    }
+}
@@ -71,6 +71,15 @@ pub const SAMPLE_CONFIG: &str = r#"
     // retrieve its own subnet id from the registry.
     subnet_id: 0,
 
+    // The config profile setting the values this file leaves out. The
+    // replica's `--config-profile` takes precedence.
+    //
+    // Alternatives:
+    // - EXAMPLE: profile: "mainnet",
+    // - EXAMPLE: profile: "testnet",
+    // - EXAMPLE: profile: "local-dev",
+    //   Listen on the loopback interface only and shut down right away.
+
     // ============================================
     // Configuration of node transport
     // ============================================
@@ -166,8 +175,85 @@ pub const SAMPLE_CONFIG: &str = r#"
     // Configuration of the HTTPS endpoint.
     // ====================================
     http_handler: {
-        // The address to listen on.
-        listen_addr: "127.0.0.1:8080"
+        // The addresses to listen on: IPv4 or IPv6 socket addresses, which
+        // serve HTTP and HTTPS, or Unix domain sockets ("unix:/path"), which
+        // serve plain HTTP only.
+        //
+        // Alternatives:
+        // - EXAMPLE: listeners: ["127.0.0.1:8080", { address: "unix:/run/ic-node/http.sock", max_tcp_connections: 100, connection_read_timeout_seconds: 60 }],
+        //   A listener can override the connection limits.
+        // - EXAMPLE: listen_addr: "127.0.0.1:8080",
+        //   The former single address field is still accepted.
+        listeners: ["127.0.0.1:8080"],
+        // TLS settings for HTTPS connections, which are served on the same port.
+        tls: {
+            // Where the certificate comes from.
+            //
+            // Alternatives:
+            // - EXAMPLE: certificate: "registry",
+            //   Use the node's TLS certificate from the registry (TLS 1.3 only, no ALPN).
+            // - EXAMPLE: certificate: { files: { certificate_path: "/path/to/cert.pem", private_key_path: "/path/to/key.pem" } },
+            //   Use PEM files, which are reloaded when they change.
+            certificate: "registry",
+            // The minimum TLS version, "1.2" or "1.3".
+            min_tls_version: "1.3",
+            // The ALPN protocols offered to clients, e.g. ["h2", "http/1.1"].
+            alpn_protocols: [],
+            // How often to check the certificate files for changes.
+            certificate_reload_interval_seconds: 60,
+        },
+        // The maximum request body size in bytes per endpoint class. Larger
+        // requests are rejected with 413 Content Too Large. No limit may
+        // exceed 5242880, the largest ingress message of any subnet plus its
+        // envelope.
+        //
+        // Alternatives:
+        // - EXAMPLE: max_request_size_bytes: 5242880,
+        //   A single limit for all endpoint classes.
+        max_request_size_bytes: { call: 5242880, query: 5242880, read_state: 1048576, status: 4096 },
+        // How long in-flight requests are given to complete on shutdown.
+        shutdown_grace_period_seconds: 10,
+        // Whether to drain connections on shutdown, i.e. to stop accepting
+        // new connections and to close the existing ones once their in-flight
+        // requests are completed. If false, connections are aborted right away.
+        drain_connections_on_shutdown: true,
+        // The maximum number of requests processed concurrently for a single
+        // source IP address, across all of its connections. Requests above
+        // the limit are rejected with 429 Too Many Requests. Unlimited by
+        // default.
+        //
+        // EXAMPLE: max_streams_per_ip: 64,
+        //
+        // The Retry-After in seconds of 429 Too Many Requests responses to
+        // requests rejected by max_streams_per_ip or endpoint_limits.
+        overload_retry_after_seconds: 1,
+        // Concurrency and per source IP rate limits of the call, query and
+        // read_state endpoints. Requests exceeding a limit are rejected with
+        // 429 Too Many Requests. All limits are unset by default.
+        //
+        // EXAMPLE: endpoint_limits: { call: { max_concurrent_requests: 100, per_ip_requests_per_second: 10, per_ip_burst: 20 }, query: { max_concurrent_requests: 400 } },
+        //
+        // Compression of the responses of the status, query and read_state
+        // endpoints with gzip or br, negotiated with the Accept-Encoding
+        // header. Responses smaller than min_response_size_bytes are sent
+        // uncompressed. Disabled by default.
+        //
+        // EXAMPLE: compression: { encodings: ["br", "gzip"], min_response_size_bytes: 1024 },
+        //
+        // Tracing of a sample of the requests, exported as OTLP/HTTP JSON to
+        // exporter_endpoint, or logged if it is not set. Requests sampled by
+        // their caller through the W3C traceparent header are traced as
+        // well if propagate_trace_context is true, within the same sampling
+        // budget. Disabled by default.
+        //
+        // EXAMPLE: request_tracing: { sampled_requests_per_million: 1000, exporter_endpoint: "http://127.0.0.1:4318/v1/traces", propagate_trace_context: true },
+        //
+        // Access to the dashboard and the pprof endpoints by source IP
+        // address, as networks in CIDR notation. If allow is not empty, only
+        // addresses in it are permitted; addresses in deny are always
+        // rejected. Open to all addresses by default.
+        //
+        // EXAMPLE: admin_access: { allow: ["10.0.0.0/8", "2001:db8::/32"], deny: ["10.1.0.0/16"] },
     },
     // ==================================================
     // Configuration of the metrics collection subsystem.
@@ -4,6 +4,7 @@
 #![allow(clippy::redundant_closure)]
 #![allow(clippy::unit_arg)]
 
+use crate::validation::{FieldError, FieldErrors, Validate};
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::os::unix::fs::PermissionsExt;
@@ -70,6 +71,18 @@ impl Default for CryptoConfig {
     }
 }
 
+impl Validate for CryptoConfig {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = FieldErrors::default();
+        // With a remote vault, the crypto root is written by the vault
+        // process, which may run as a different user.
+        if self.csp_vault_type == CspVaultType::InReplica {
+            errors.check_writable("crypto_root", &self.crypto_root);
+        }
+        errors.into_vec()
+    }
+}
+
 impl CryptoConfig {
     /// Returns a new CryptoConfig with the given crypto_root path.
     pub fn new(crypto_root: PathBuf) -> Self {
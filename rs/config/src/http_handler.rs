@@ -1,21 +1,571 @@
-use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
+use crate::reloadable::Reloadable;
+use crate::secret::Secret;
+use crate::validation::{FieldError, FieldErrors, Validate, ValidationError};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
 use std::path::PathBuf;
+use std::str::FromStr;
+use url::Url;
 
 const DEFAULT_IP_ADDR: &str = "0.0.0.0";
 
 const DEFAULT_PORT: u16 = 8080u16;
 
+const UNIX_SOCKET_PREFIX: &str = "unix:";
+
+/// The largest request body any subnet accepts: the ingress message limit of
+/// the NNS subnet, 3.5 MiB, plus room for the request envelope.
+pub const MAX_SUBNET_INGRESS_BYTES: u64 = 5 * 1024 * 1024;
+
+/// An address the endpoint accepts connections on, written as
+/// `127.0.0.1:8080`, `[::1]:8080` or `unix:/path/to/socket`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ListenAddress {
+    /// An IPv4 or IPv6 socket address. Serves both HTTP and HTTPS.
+    Tcp(SocketAddr),
+    /// The path of a Unix domain socket, for local agents and tests. Serves
+    /// plain HTTP only.
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix(UNIX_SOCKET_PREFIX) {
+            Some("") => Err("Empty Unix domain socket path".to_string()),
+            Some(path) => Ok(ListenAddress::Unix(PathBuf::from(path))),
+            None => s
+                .parse()
+                .map(ListenAddress::Tcp)
+                .map_err(|err| format!("Invalid listen address '{}': {}", s, err)),
+        }
+    }
+}
+
+impl TryFrom<String> for ListenAddress {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddress::Tcp(addr) => write!(f, "{}", addr),
+            ListenAddress::Unix(path) => write!(f, "{}{}", UNIX_SOCKET_PREFIX, path.display()),
+        }
+    }
+}
+
+impl From<ListenAddress> for String {
+    fn from(address: ListenAddress) -> Self {
+        address.to_string()
+    }
+}
+
+/// An address to listen on, with optional overrides of the connection
+/// limits of the [Config]. A listener without overrides is written as just
+/// its address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "ListenerRepr", into = "ListenerRepr")]
+pub struct Listener {
+    pub address: ListenAddress,
+
+    /// Overrides `max_tcp_connections` for the connections of this listener.
+    pub max_tcp_connections: Option<usize>,
+
+    /// Overrides `connection_read_timeout_seconds` for the connections of
+    /// this listener.
+    pub connection_read_timeout_seconds: Option<u64>,
+
+    /// Whether the listener is the single address of the former
+    /// `listen_addr` field, i.e., was not written in a list. See
+    /// [Listener::tcp_bind_addr].
+    pub legacy_listen_addr: bool,
+}
+
+impl From<ListenAddress> for Listener {
+    fn from(address: ListenAddress) -> Self {
+        Self {
+            address,
+            max_tcp_connections: None,
+            connection_read_timeout_seconds: None,
+            legacy_listen_addr: false,
+        }
+    }
+}
+
+impl Listener {
+    /// The socket address to bind a TCP listener to, `None` for a Unix
+    /// domain socket.
+    ///
+    /// TODO(OR4-60): the address of the former `listen_addr` field is bound
+    /// as `[::]` on its port, so that both IPv4 and IPv6 connections are
+    /// accepted. This requires net.ipv6.bindv6only = 0. Revert this once we
+    /// have rolled out IPv6 in prometheus and ic_p8s_service_discovery.
+    /// Addresses written in a list are bound as they are.
+    pub fn tcp_bind_addr(&self) -> Option<SocketAddr> {
+        match &self.address {
+            ListenAddress::Tcp(addr) if self.legacy_listen_addr => Some(SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                addr.port(),
+            )),
+            ListenAddress::Tcp(addr) => Some(*addr),
+            ListenAddress::Unix(_) => None,
+        }
+    }
+}
+
+impl From<SocketAddr> for Listener {
+    fn from(addr: SocketAddr) -> Self {
+        ListenAddress::Tcp(addr).into()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum ListenerRepr {
+    Address(ListenAddress),
+    Full {
+        address: ListenAddress,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_tcp_connections: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        connection_read_timeout_seconds: Option<u64>,
+    },
+}
+
+impl From<ListenerRepr> for Listener {
+    fn from(repr: ListenerRepr) -> Self {
+        match repr {
+            ListenerRepr::Address(address) => address.into(),
+            ListenerRepr::Full {
+                address,
+                max_tcp_connections,
+                connection_read_timeout_seconds,
+            } => Self {
+                address,
+                max_tcp_connections,
+                connection_read_timeout_seconds,
+                legacy_listen_addr: false,
+            },
+        }
+    }
+}
+
+impl From<Listener> for ListenerRepr {
+    fn from(listener: Listener) -> Self {
+        match listener {
+            Listener {
+                address,
+                max_tcp_connections: None,
+                connection_read_timeout_seconds: None,
+                ..
+            } => ListenerRepr::Address(address),
+            Listener {
+                address,
+                max_tcp_connections,
+                connection_read_timeout_seconds,
+                ..
+            } => ListenerRepr::Full {
+                address,
+                max_tcp_connections,
+                connection_read_timeout_seconds,
+            },
+        }
+    }
+}
+
+/// Accepts a list of listeners as well as a single one, which is how the
+/// former `listen_addr` field was written.
+fn deserialize_listeners<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Listener>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(Listener),
+        Many(Vec<Listener>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(listener) => vec![Listener {
+            legacy_listen_addr: true,
+            ..listener
+        }],
+        OneOrMany::Many(listeners) => listeners,
+    })
+}
+
+/// Writes the listener of the former `listen_addr` field as a single one, so
+/// that it is read back as such.
+fn serialize_listeners<S: Serializer>(
+    listeners: &[Listener],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match listeners {
+        [listener] if listener.legacy_listen_addr => listener.serialize(serializer),
+        listeners => listeners.serialize(serializer),
+    }
+}
+
+/// Where the certificate of HTTPS connections comes from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TlsCertificateSource {
+    /// The node's TLS certificate from the registry, whose key is held by
+    /// the crypto component. Only TLS 1.3 without ALPN is supported.
+    Registry,
+    /// A PEM encoded certificate chain and private key, which are reloaded
+    /// when the files change.
+    Files {
+        certificate_path: PathBuf,
+        private_key_path: Secret<PathBuf>,
+    },
+}
+
+/// A TLS protocol version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+/// TLS settings of the HTTPS endpoint. HTTPS and plain HTTP are served on
+/// the same port, distinguished by the first byte of a connection.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    pub certificate: TlsCertificateSource,
+
+    /// The minimum TLS version accepted from clients.
+    pub min_tls_version: TlsVersion,
+
+    /// The ALPN protocols offered to clients in order of preference, e.g.
+    /// `["h2", "http/1.1"]`. If empty, ALPN is not used.
+    pub alpn_protocols: Vec<String>,
+
+    /// How often to check the certificate files for changes.
+    pub certificate_reload_interval_seconds: u64,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            certificate: TlsCertificateSource::Registry,
+            min_tls_version: TlsVersion::Tls13,
+            alpn_protocols: vec![],
+            certificate_reload_interval_seconds: 60,
+        }
+    }
+}
+
+/// Limits applied to the requests of one endpoint class. Requests exceeding
+/// a limit are rejected with `429 Too Many Requests`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EndpointLimits {
+    /// The maximum number of requests processed concurrently. Unlimited if
+    /// not set.
+    pub max_concurrent_requests: Option<usize>,
+
+    /// The sustained number of requests per second accepted from a single
+    /// source IP address. Unlimited if not set.
+    pub per_ip_requests_per_second: Option<u32>,
+
+    /// The number of requests a single source IP address can send in a burst
+    /// before `per_ip_requests_per_second` applies. Defaults to
+    /// `per_ip_requests_per_second` if not set, and must not be set without
+    /// it.
+    pub per_ip_burst: Option<u32>,
+}
+
+/// Limits per endpoint class. None are set by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EndpointLimitsConfig {
+    /// `/api/v2/canister/.../call`
+    pub call: EndpointLimits,
+    /// `/api/v2/canister/.../query`
+    pub query: EndpointLimits,
+    /// `/api/v2/canister/.../read_state`
+    pub read_state: EndpointLimits,
+}
+
+/// A content coding response bodies can be compressed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ContentEncoding {
+    #[serde(rename = "gzip")]
+    Gzip,
+    #[serde(rename = "br")]
+    Brotli,
+}
+
+impl ContentEncoding {
+    /// The token of the encoding in `Accept-Encoding` and `Content-Encoding`
+    /// headers.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Brotli => "br",
+        }
+    }
+}
+
+/// Compression of the response bodies of the status, query and read_state
+/// endpoints, negotiated with the `Accept-Encoding` header of the request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+    /// The encodings responses may be compressed with. If a client accepts
+    /// several with the same preference, the first one is used. Compression
+    /// is disabled if empty.
+    pub encodings: Vec<ContentEncoding>,
+
+    /// Response bodies smaller than this are sent uncompressed, as
+    /// compressing them saves little.
+    pub min_response_size_bytes: u64,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            encodings: vec![],
+            min_response_size_bytes: 1024, // 1KB
+        }
+    }
+}
+
+/// Tracing of HTTP requests, in the spirit of OpenTelemetry: a span is
+/// recorded for each sampled request, from routing until the response, and
+/// exported as OTLP/HTTP JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RequestTracingConfig {
+    /// How many requests out of a million are traced. Tracing is disabled if
+    /// 0.
+    pub sampled_requests_per_million: u32,
+
+    /// The OTLP/HTTP endpoint spans are posted to, e.g.
+    /// `http://127.0.0.1:4318/v1/traces`. Spans are logged if not set.
+    pub exporter_endpoint: Option<Url>,
+
+    /// If true, the W3C `traceparent` and `tracestate` headers of requests
+    /// are honored: a request whose caller sampled it is traced as part of
+    /// the caller's trace, as long as the traced requests stay within
+    /// `sampled_requests_per_million`. The headers are passed on to the
+    /// endpoint services with the span of the replica as parent. If false,
+    /// they are ignored and every trace starts at the replica. Disabled by
+    /// default, as the headers are chosen by untrusted callers.
+    pub propagate_trace_context: bool,
+
+    /// Spans are exported in batches of at most `export_batch_size`, at
+    /// least every `export_interval_seconds`. Spans exceeding a full batch
+    /// while an export is in progress are dropped.
+    pub export_batch_size: usize,
+    pub export_interval_seconds: u64,
+}
+
+impl Default for RequestTracingConfig {
+    fn default() -> Self {
+        Self {
+            sampled_requests_per_million: 0,
+            exporter_endpoint: None,
+            propagate_trace_context: false,
+            export_batch_size: 512,
+            export_interval_seconds: 5,
+        }
+    }
+}
+
+/// An IPv4 or IPv6 network in CIDR notation, e.g. `10.0.0.0/8` or
+/// `2001:db8::/32`. A single address is a network of its full length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNetwork {
+    /// Whether `ip` is in the network. IPv4-mapped IPv6 addresses, as seen by
+    /// listeners on `[::]`, are matched against IPv4 networks.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            IpAddr::V4(_) => ip,
+        };
+        match (self.address, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                prefix_matches(&network.octets(), &ip.octets(), self.prefix_len)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                prefix_matches(&network.octets(), &ip.octets(), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(network: &[u8], ip: &[u8], prefix_len: u8) -> bool {
+    let full_bytes = (prefix_len / 8) as usize;
+    let rest_bits = prefix_len % 8;
+    if network[..full_bytes] != ip[..full_bytes] {
+        return false;
+    }
+    if rest_bits == 0 {
+        return true;
+    }
+    let mask = 0xffu8 << (8 - rest_bits);
+    network[full_bytes] & mask == ip[full_bytes] & mask
+}
+
+impl FromStr for IpNetwork {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = match s.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (s, None),
+        };
+        let address: IpAddr = address
+            .parse()
+            .map_err(|err| format!("Invalid network '{}': {}", s, err))?;
+        let max_prefix_len = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix_len| *prefix_len <= max_prefix_len)
+                .ok_or_else(|| format!("Invalid prefix length in network '{}'", s))?,
+            None => max_prefix_len,
+        };
+        Ok(IpNetwork {
+            address,
+            prefix_len,
+        })
+    }
+}
+
+impl TryFrom<String> for IpNetwork {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+impl From<IpNetwork> for String {
+    fn from(network: IpNetwork) -> Self {
+        network.to_string()
+    }
+}
+
+/// Access to the administrative endpoints, the dashboard and the pprof
+/// endpoints, by the source IP address of the request. Requests that are
+/// not permitted are rejected with `403 Forbidden`. Requests received on a
+/// Unix domain socket are always permitted.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdminAccessConfig {
+    /// If not empty, only addresses in one of these networks are permitted.
+    pub allow: Vec<IpNetwork>,
+    /// Addresses in one of these networks are rejected, even if they are in
+    /// `allow`.
+    pub deny: Vec<IpNetwork>,
+}
+
+impl AdminAccessConfig {
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        (self.allow.is_empty() || self.allow.iter().any(|network| network.contains(ip)))
+            && !self.deny.iter().any(|network| network.contains(ip))
+    }
+}
+
+/// The maximum request body size in bytes per endpoint class. Requests with a
+/// larger body are rejected with
+/// [`413 Content Too Large`](https://developer.mozilla.org/en-US/docs/Web/HTTP/Status/413).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RequestSizeLimits {
+    /// `/api/v2/canister/.../call`
+    pub call: u64,
+    /// `/api/v2/canister/.../query`
+    pub query: u64,
+    /// `/api/v2/canister/.../read_state` and `/_/catch_up_package`
+    pub read_state: u64,
+    /// `/api/v2/status`
+    pub status: u64,
+}
+
+impl Default for RequestSizeLimits {
+    fn default() -> Self {
+        Self {
+            call: MAX_SUBNET_INGRESS_BYTES,
+            query: MAX_SUBNET_INGRESS_BYTES,
+            read_state: 1024 * 1024, // 1MB
+            status: 4 * 1024,        // 4KB
+        }
+    }
+}
+
+impl RequestSizeLimits {
+    /// The same limit for every endpoint class.
+    pub fn uniform(max_request_size_bytes: u64) -> Self {
+        Self {
+            call: max_request_size_bytes,
+            query: max_request_size_bytes,
+            read_state: max_request_size_bytes,
+            status: max_request_size_bytes,
+        }
+    }
+}
+
+/// Accepts a single limit for all endpoint classes as well, which is how
+/// `max_request_size_bytes` was written before it was split up.
+fn deserialize_request_size_limits<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<RequestSizeLimits, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum UniformOrPerClass {
+        Uniform(u64),
+        PerClass(RequestSizeLimits),
+    }
+    Ok(match UniformOrPerClass::deserialize(deserializer)? {
+        UniformOrPerClass::Uniform(limit) => RequestSizeLimits::uniform(limit),
+        UniformOrPerClass::PerClass(limits) => limits,
+    })
+}
+
 /// The internal configuration -- any historical warts from the external
 /// configuration are removed. Anything using this struct can trust that it
 /// has been validated.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Config {
-    /// IP address and port to listen on
-    pub listen_addr: SocketAddr,
+    /// The addresses to listen on. Also accepts the former `listen_addr`
+    /// field holding a single address.
+    #[serde(
+        alias = "listen_addr",
+        deserialize_with = "deserialize_listeners",
+        serialize_with = "serialize_listeners"
+    )]
+    pub listeners: Vec<Listener>,
 
-    /// The path to write the listening port to
+    /// The path to write the port of the first TCP listener to
     pub port_file_path: Option<PathBuf>,
 
     /// The endpoint can serve from at most 'max_tcp_connections'
@@ -27,14 +577,30 @@ pub struct Config {
     /// 'connection_read_timeout_seconds', then the connection is dropped.
     /// There is no point is setting a timeout on the write bytes since
     /// they are conditioned on the received requests.
+    /// Reloadable: applies to new connections.
     pub connection_read_timeout_seconds: u64,
 
     /// Per request timeout in seconds before the server replies with `504 Gateway Timeout`.
+    /// Reloadable: applies to new requests.
     pub request_timeout_seconds: u64,
 
     /// The `SETTINGS_MAX_CONCURRENT_STREAMS` option for HTTP2 connections.
+    /// Reloadable: applies to new connections.
     pub http_max_concurrent_streams: u32,
 
+    /// The maximum number of requests processed concurrently for a single
+    /// source IP address, across all of its connections. Bounds what a
+    /// single client can take of `http_max_concurrent_streams` by opening
+    /// many connections. Unlimited if not set.
+    /// Reloadable: applies to new requests.
+    pub max_streams_per_ip: Option<usize>,
+
+    /// The `Retry-After` header, in seconds, of the `429 Too Many Requests`
+    /// responses sent when a request is shed because of
+    /// `max_streams_per_ip` or `endpoint_limits`.
+    /// Reloadable: applies to new requests.
+    pub overload_retry_after_seconds: u64,
+
     /// The maximum time we should wait for a peeking the first bytes on a TCP
     /// connection. Effectively, if we can't read the first bytes within the
     /// timeout the connection is broken.
@@ -42,11 +608,28 @@ pub struct Config {
     /// - `ic_canister_client::agent::MAX_POLL_INTERVAL`,
     /// - `canister_test::canister::MAX_BACKOFF_INTERVAL`.
     /// See VER-1060 for details.
+    /// Reloadable: applies to new connections.
     pub max_tcp_peek_timeout_seconds: u64,
 
-    /// Request with body size bigger than `max_request_size_bytes` will be rejected
-    /// and [`413 Content Too Large`](https://developer.mozilla.org/en-US/docs/Web/HTTP/Status/413) will be returned to the user.
-    pub max_request_size_bytes: u64,
+    /// The maximum time for the TLS handshake of an HTTPS connection, after
+    /// its first bytes were peeked. Connections that don't complete the
+    /// handshake in time are dropped.
+    /// Reloadable: applies to new connections.
+    pub tls_handshake_timeout_seconds: u64,
+
+    /// The maximum time for receiving the headers of a request: of the first
+    /// request of a connection after the connection is set up, and of each
+    /// following HTTP/1 request. Connections that don't deliver them in time
+    /// are dropped, so that slow clients can't hold connections open by
+    /// trickling headers. The request body isn't covered, see
+    /// `max_request_receive_seconds`.
+    /// Reloadable: applies to new connections.
+    pub max_request_header_receive_seconds: u64,
+
+    /// The maximum request body size per endpoint class. Also accepts a
+    /// single limit for all classes.
+    #[serde(deserialize_with = "deserialize_request_size_limits")]
+    pub max_request_size_bytes: RequestSizeLimits,
 
     /// Delegation certificate requests with body size bigger than `max_delegation_certificate_size_bytes`
     /// will be rejected. For valid IC delegation certificates this is never the case since the size is always constant.
@@ -56,24 +639,570 @@ pub struct Config {
     /// `max_request_receive_seconds`, then the request will be rejected and
     /// [`408 Request Timeout`](https://developer.mozilla.org/en-US/docs/Web/HTTP/Status/408) will be returned to the user.
     pub max_request_receive_seconds: u64,
+
+    /// TLS settings for HTTPS connections.
+    pub tls: TlsConfig,
+
+    /// Concurrency and rate limits per endpoint class.
+    /// Reloadable: applies to new requests. Changing the limits of a class
+    /// resets its rate limiter.
+    pub endpoint_limits: EndpointLimitsConfig,
+
+    /// Compression of response bodies. Disabled by default.
+    /// Reloadable: applies to new requests.
+    pub compression: CompressionConfig,
+
+    /// On shutdown, in-flight requests are given at most
+    /// `shutdown_grace_period_seconds` to complete before the remaining
+    /// connections are aborted.
+    pub shutdown_grace_period_seconds: u64,
+
+    /// If true, connections are drained on shutdown: HTTP/1 connections are
+    /// closed after their in-flight request and HTTP/2 connections receive a
+    /// `GOAWAY` frame. If false, all connections are aborted right away.
+    pub drain_connections_on_shutdown: bool,
+
+    /// Tracing of HTTP requests. Disabled by default.
+    /// Reloadable: applies to new requests. The batching of exported spans
+    /// is only read at startup.
+    pub request_tracing: RequestTracingConfig,
+
+    /// Access to the dashboard and the pprof endpoints by source IP address.
+    /// Open to all addresses by default.
+    /// Reloadable: applies to new requests.
+    pub admin_access: AdminAccessConfig,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            listen_addr: SocketAddr::new(
-                DEFAULT_IP_ADDR.parse().expect("can't fail"),
-                DEFAULT_PORT,
-            ),
+            listeners: vec![Listener {
+                legacy_listen_addr: true,
+                ..SocketAddr::new(DEFAULT_IP_ADDR.parse().expect("can't fail"), DEFAULT_PORT).into()
+            }],
             port_file_path: None,
             max_tcp_connections: 20_000,
             connection_read_timeout_seconds: 1_200, // 20 min
             request_timeout_seconds: 300,           // 5 min
             http_max_concurrent_streams: 256,
+            max_streams_per_ip: None,
+            overload_retry_after_seconds: 1,
             max_tcp_peek_timeout_seconds: 11,
-            max_request_size_bytes: 5 * 1024 * 1024, // 5MB
+            tls_handshake_timeout_seconds: 10,
+            max_request_header_receive_seconds: 30,
+            max_request_size_bytes: RequestSizeLimits::default(),
             max_delegation_certificate_size_bytes: 1024 * 1024, // 1MB
-            max_request_receive_seconds: 300,        // 5 min
+            max_request_receive_seconds: 300,                   // 5 min
+            tls: TlsConfig::default(),
+            endpoint_limits: EndpointLimitsConfig::default(),
+            compression: CompressionConfig::default(),
+            shutdown_grace_period_seconds: 10,
+            drain_connections_on_shutdown: true,
+            request_tracing: RequestTracingConfig::default(),
+            admin_access: AdminAccessConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The address of the first TCP listener, which is the one registered
+    /// for the node and whose port is written to `port_file_path`.
+    pub fn tcp_listen_addr(&self) -> Option<SocketAddr> {
+        self.listeners
+            .iter()
+            .find_map(|listener| match listener.address {
+                ListenAddress::Tcp(addr) => Some(addr),
+                ListenAddress::Unix(_) => None,
+            })
+    }
+}
+
+impl Reloadable for Config {
+    const RELOADABLE_FIELDS: &'static [&'static str] = &[
+        "connection_read_timeout_seconds",
+        "request_timeout_seconds",
+        "http_max_concurrent_streams",
+        "max_streams_per_ip",
+        "overload_retry_after_seconds",
+        "max_tcp_peek_timeout_seconds",
+        "tls_handshake_timeout_seconds",
+        "max_request_header_receive_seconds",
+        "endpoint_limits",
+        "compression",
+        "request_tracing",
+        "admin_access",
+    ];
+}
+
+impl Validate for Config {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = FieldErrors::default();
+        if self.listeners.is_empty() {
+            errors.push("listeners", ValidationError::Zero);
+        }
+        for (i, listener) in self.listeners.iter().enumerate() {
+            match &listener.address {
+                // Port 0 picks an ephemeral port, which is only useful if it
+                // is written to the port file.
+                ListenAddress::Tcp(addr) => {
+                    if addr.port() == 0 && (i > 0 || self.port_file_path.is_none()) {
+                        errors.push("listeners", ValidationError::Zero);
+                    }
+                }
+                ListenAddress::Unix(path) => errors.check_writable("listeners", path),
+            }
+            if self.listeners[..i]
+                .iter()
+                .any(|other| other.address == listener.address)
+            {
+                errors.push(
+                    "listeners",
+                    ValidationError::Duplicate {
+                        other_field: "listeners",
+                    },
+                );
+            }
+            if listener.max_tcp_connections == Some(0)
+                || listener.connection_read_timeout_seconds == Some(0)
+            {
+                errors.push("listeners", ValidationError::Zero);
+            }
+        }
+        if let Some(port_file_path) = &self.port_file_path {
+            errors.check_writable("port_file_path", port_file_path);
+        }
+        errors.check_non_zero("max_tcp_connections", self.max_tcp_connections as u64);
+        errors.check_non_zero(
+            "connection_read_timeout_seconds",
+            self.connection_read_timeout_seconds,
+        );
+        errors.check_non_zero("request_timeout_seconds", self.request_timeout_seconds);
+        errors.check_not_exceeding(
+            "request_timeout_seconds",
+            self.request_timeout_seconds,
+            "connection_read_timeout_seconds",
+            self.connection_read_timeout_seconds,
+        );
+        errors.check_non_zero(
+            "http_max_concurrent_streams",
+            self.http_max_concurrent_streams as u64,
+        );
+        if self.max_streams_per_ip == Some(0) {
+            errors.push("max_streams_per_ip", ValidationError::Zero);
         }
+        errors.check_non_zero(
+            "max_tcp_peek_timeout_seconds",
+            self.max_tcp_peek_timeout_seconds,
+        );
+        errors.check_non_zero(
+            "tls_handshake_timeout_seconds",
+            self.tls_handshake_timeout_seconds,
+        );
+        errors.check_non_zero(
+            "max_request_header_receive_seconds",
+            self.max_request_header_receive_seconds,
+        );
+        for (field, limit) in [
+            (
+                "max_request_size_bytes.call",
+                self.max_request_size_bytes.call,
+            ),
+            (
+                "max_request_size_bytes.query",
+                self.max_request_size_bytes.query,
+            ),
+            (
+                "max_request_size_bytes.read_state",
+                self.max_request_size_bytes.read_state,
+            ),
+            (
+                "max_request_size_bytes.status",
+                self.max_request_size_bytes.status,
+            ),
+        ] {
+            errors.check_non_zero(field, limit);
+            if limit > MAX_SUBNET_INGRESS_BYTES {
+                errors.push(
+                    field,
+                    ValidationError::TooLarge {
+                        max: MAX_SUBNET_INGRESS_BYTES,
+                    },
+                );
+            }
+        }
+        errors.check_non_zero(
+            "max_request_receive_seconds",
+            self.max_request_receive_seconds,
+        );
+        errors.check_not_exceeding(
+            "max_request_receive_seconds",
+            self.max_request_receive_seconds,
+            "request_timeout_seconds",
+            self.request_timeout_seconds,
+        );
+        for (field, limits) in [
+            ("endpoint_limits.call", &self.endpoint_limits.call),
+            ("endpoint_limits.query", &self.endpoint_limits.query),
+            (
+                "endpoint_limits.read_state",
+                &self.endpoint_limits.read_state,
+            ),
+        ] {
+            if limits.max_concurrent_requests == Some(0)
+                || limits.per_ip_requests_per_second == Some(0)
+                || limits.per_ip_burst == Some(0)
+            {
+                errors.push(field, ValidationError::Zero);
+            }
+            if limits.per_ip_burst.is_some() && limits.per_ip_requests_per_second.is_none() {
+                errors.push(
+                    field,
+                    ValidationError::Unsupported {
+                        reason: "'per_ip_burst' requires 'per_ip_requests_per_second'",
+                    },
+                );
+            }
+        }
+        for (i, encoding) in self.compression.encodings.iter().enumerate() {
+            if self.compression.encodings[..i].contains(encoding) {
+                errors.push(
+                    "compression.encodings",
+                    ValidationError::Duplicate {
+                        other_field: "compression.encodings",
+                    },
+                );
+            }
+        }
+        if self.request_tracing.sampled_requests_per_million > 1_000_000 {
+            errors.push(
+                "request_tracing.sampled_requests_per_million",
+                ValidationError::TooLarge { max: 1_000_000 },
+            );
+        }
+        if let Some(endpoint) = &self.request_tracing.exporter_endpoint {
+            if endpoint.scheme() != "http" && endpoint.scheme() != "https" {
+                errors.push(
+                    "request_tracing.exporter_endpoint",
+                    ValidationError::Unsupported {
+                        reason: "spans are only exported over HTTP(S)",
+                    },
+                );
+            }
+        }
+        errors.check_non_zero(
+            "request_tracing.export_batch_size",
+            self.request_tracing.export_batch_size as u64,
+        );
+        errors.check_non_zero(
+            "request_tracing.export_interval_seconds",
+            self.request_tracing.export_interval_seconds,
+        );
+        if self.drain_connections_on_shutdown {
+            errors.check_non_zero(
+                "shutdown_grace_period_seconds",
+                self.shutdown_grace_period_seconds,
+            );
+        }
+        match &self.tls.certificate {
+            TlsCertificateSource::Registry => {
+                if self.tls.min_tls_version != TlsVersion::Tls13 {
+                    errors.push(
+                        "tls",
+                        ValidationError::Unsupported {
+                            reason: "the registry certificate only supports TLS 1.3",
+                        },
+                    );
+                }
+                if !self.tls.alpn_protocols.is_empty() {
+                    errors.push(
+                        "tls",
+                        ValidationError::Unsupported {
+                            reason: "ALPN requires a certificate from files",
+                        },
+                    );
+                }
+            }
+            TlsCertificateSource::Files { .. } => {
+                errors.check_non_zero("tls", self.tls.certificate_reload_interval_seconds)
+            }
+        }
+        errors.into_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(http_handler: &str) -> Config {
+        json5::from_str(http_handler).unwrap()
+    }
+
+    #[test]
+    fn accepts_the_former_single_listen_addr() {
+        let config = parse(r#"{ listen_addr: "[::1]:8080" }"#);
+        assert_eq!(
+            config.listeners,
+            vec![Listener {
+                legacy_listen_addr: true,
+                ..Listener::from("[::1]:8080".parse::<SocketAddr>().unwrap())
+            }]
+        );
+        // It is written back as a single address.
+        let serialized = serde_json::to_value(&config).unwrap();
+        assert_eq!(serialized["listeners"], serde_json::json!("[::1]:8080"));
+        assert_eq!(
+            serde_json::from_value::<Config>(serialized).unwrap(),
+            config
+        );
+    }
+
+    #[test]
+    fn binds_only_the_former_listen_addr_to_all_interfaces() {
+        let tcp_bind_addrs = |config: Config| -> Vec<_> {
+            config
+                .listeners
+                .iter()
+                .map(Listener::tcp_bind_addr)
+                .collect()
+        };
+        assert_eq!(
+            tcp_bind_addrs(parse(r#"{ listen_addr: "127.0.0.1:8080" }"#)),
+            vec![Some("[::]:8080".parse().unwrap())]
+        );
+        assert_eq!(
+            tcp_bind_addrs(Config::default()),
+            vec![Some("[::]:8080".parse().unwrap())]
+        );
+
+        // A single listener written in a list keeps its address.
+        assert_eq!(
+            tcp_bind_addrs(parse(r#"{ listeners: ["127.0.0.1:8080"] }"#)),
+            vec![Some("127.0.0.1:8080".parse().unwrap())]
+        );
+        assert_eq!(
+            tcp_bind_addrs(parse(
+                r#"{ listeners: ["127.0.0.1:8080", "unix:/run/ic/http.sock"] }"#
+            )),
+            vec![Some("127.0.0.1:8080".parse().unwrap()), None]
+        );
+    }
+
+    #[test]
+    fn parses_listeners_with_overrides() {
+        let config = parse(
+            r#"{
+                listeners: [
+                    "0.0.0.0:8080",
+                    { address: "unix:/run/ic/http.sock", max_tcp_connections: 10 },
+                ],
+            }"#,
+        );
+        assert_eq!(
+            config.listeners,
+            vec![
+                Listener::from("0.0.0.0:8080".parse::<SocketAddr>().unwrap()),
+                Listener {
+                    address: ListenAddress::Unix(PathBuf::from("/run/ic/http.sock")),
+                    max_tcp_connections: Some(10),
+                    connection_read_timeout_seconds: None,
+                    legacy_listen_addr: false,
+                },
+            ]
+        );
+        assert_eq!(
+            config.tcp_listen_addr(),
+            Some("0.0.0.0:8080".parse().unwrap())
+        );
+
+        // Listeners without overrides are written as just their address.
+        let serialized = serde_json::to_value(&config.listeners).unwrap();
+        assert_eq!(
+            serialized,
+            serde_json::json!([
+                "0.0.0.0:8080",
+                { "address": "unix:/run/ic/http.sock", "max_tcp_connections": 10 }
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_listen_addresses() {
+        assert!("unix:".parse::<ListenAddress>().is_err());
+        assert!("localhost".parse::<ListenAddress>().is_err());
+        assert!(json5::from_str::<Config>(r#"{ listeners: ["8080"] }"#).is_err());
+    }
+
+    #[test]
+    fn accepts_a_single_request_size_limit_for_all_endpoint_classes() {
+        let config = parse("{ max_request_size_bytes: 1024 }");
+        assert_eq!(
+            config.max_request_size_bytes,
+            RequestSizeLimits::uniform(1024)
+        );
+
+        let config = parse("{ max_request_size_bytes: { status: 128 } }");
+        assert_eq!(
+            config.max_request_size_bytes,
+            RequestSizeLimits {
+                status: 128,
+                ..RequestSizeLimits::default()
+            }
+        );
+    }
+
+    #[test]
+    fn parses_compression() {
+        let config = parse(r#"{ compression: { encodings: ["br", "gzip"] } }"#);
+        assert_eq!(
+            config.compression,
+            CompressionConfig {
+                encodings: vec![ContentEncoding::Brotli, ContentEncoding::Gzip],
+                ..CompressionConfig::default()
+            }
+        );
+        assert!(config.validate().is_empty());
+
+        let config = parse(r#"{ compression: { encodings: ["gzip", "gzip"] } }"#);
+        assert_eq!(
+            config.validate(),
+            vec![FieldError {
+                field: "compression.encodings",
+                error: ValidationError::Duplicate {
+                    other_field: "compression.encodings",
+                },
+            }]
+        );
+        assert!(json5::from_str::<Config>(r#"{ compression: { encodings: ["zstd"] } }"#).is_err());
+    }
+
+    #[test]
+    fn rejects_request_size_limits_above_the_subnet_ingress_limit() {
+        let config = Config {
+            max_request_size_bytes: RequestSizeLimits {
+                query: MAX_SUBNET_INGRESS_BYTES + 1,
+                status: 0,
+                ..RequestSizeLimits::default()
+            },
+            ..Config::default()
+        };
+        assert_eq!(
+            config.validate(),
+            vec![
+                FieldError {
+                    field: "max_request_size_bytes.query",
+                    error: ValidationError::TooLarge {
+                        max: MAX_SUBNET_INGRESS_BYTES
+                    },
+                },
+                FieldError {
+                    field: "max_request_size_bytes.status",
+                    error: ValidationError::Zero,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_a_per_ip_burst_without_a_per_ip_rate() {
+        let config = Config {
+            endpoint_limits: EndpointLimitsConfig {
+                call: EndpointLimits {
+                    per_ip_burst: Some(20),
+                    ..EndpointLimits::default()
+                },
+                query: EndpointLimits {
+                    per_ip_requests_per_second: Some(10),
+                    per_ip_burst: Some(20),
+                    ..EndpointLimits::default()
+                },
+                ..EndpointLimitsConfig::default()
+            },
+            ..Config::default()
+        };
+        assert_eq!(
+            config.validate(),
+            vec![FieldError {
+                field: "endpoint_limits.call",
+                error: ValidationError::Unsupported {
+                    reason: "'per_ip_burst' requires 'per_ip_requests_per_second'",
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_request_tracing() {
+        let config = parse(
+            r#"{
+                request_tracing: {
+                    sampled_requests_per_million: 1000,
+                    exporter_endpoint: "http://127.0.0.1:4318/v1/traces",
+                },
+            }"#,
+        );
+        assert_eq!(
+            config.request_tracing,
+            RequestTracingConfig {
+                sampled_requests_per_million: 1000,
+                exporter_endpoint: Some(Url::parse("http://127.0.0.1:4318/v1/traces").unwrap()),
+                ..RequestTracingConfig::default()
+            }
+        );
+        assert!(config.validate().is_empty());
+
+        let config = parse(
+            r#"{
+                request_tracing: {
+                    sampled_requests_per_million: 1000001,
+                    exporter_endpoint: "udp://127.0.0.1:4317",
+                },
+            }"#,
+        );
+        assert_eq!(
+            config.validate(),
+            vec![
+                FieldError {
+                    field: "request_tracing.sampled_requests_per_million",
+                    error: ValidationError::TooLarge { max: 1_000_000 },
+                },
+                FieldError {
+                    field: "request_tracing.exporter_endpoint",
+                    error: ValidationError::Unsupported {
+                        reason: "spans are only exported over HTTP(S)",
+                    },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_admin_access() {
+        let config = parse(
+            r#"{
+                admin_access: {
+                    allow: ["10.0.0.0/8", "2001:db8::/32", "192.168.1.1"],
+                    deny: ["10.1.0.0/16"],
+                },
+            }"#,
+        );
+        let permits = |ip: &str| config.admin_access.permits(ip.parse().unwrap());
+        assert!(permits("10.2.3.4"));
+        assert!(permits("::ffff:10.2.3.4"));
+        assert!(permits("2001:db8::1"));
+        assert!(permits("192.168.1.1"));
+        assert!(!permits("10.1.2.3"));
+        assert!(!permits("192.168.1.2"));
+        assert!(!permits("2001:db9::1"));
+        assert_eq!(
+            config.admin_access.allow[2].to_string(),
+            "192.168.1.1/32".to_string()
+        );
+
+        assert!(Config::default()
+            .admin_access
+            .permits("1.2.3.4".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<IpNetwork>().is_err());
+        assert!("10.0.0/8".parse::<IpNetwork>().is_err());
     }
 }
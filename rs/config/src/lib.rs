@@ -2,8 +2,14 @@
 //! This crate should be self-contained and should not depend on other IC crates.
 
 pub mod config;
+pub mod config_deprecations;
+pub mod config_diff;
+pub mod config_layers;
 pub mod config_parser;
+pub mod config_profile;
 pub mod config_sample;
+pub mod schema;
+pub mod secret;
 pub mod subnet_config;
 
 pub mod adapters;
@@ -21,9 +27,14 @@ pub mod metrics;
 pub mod nns_registry_replicator;
 pub mod registration;
 pub mod registry_client;
+pub mod reloadable;
 pub mod state_manager;
 pub mod transport;
+pub mod validation;
 
 pub use config::*;
+pub use config_layers::{ConfigLayer, ConfigOverride, LayeredConfig};
 pub use config_parser::*;
+pub use config_profile::ConfigProfile;
 pub use config_sample::*;
+pub use secret::Secret;
@@ -1,3 +1,4 @@
+use crate::validation::{FieldError, FieldErrors, Validate};
 use serde::{Deserialize, Serialize};
 use slog::Level;
 use std::collections::HashMap;
@@ -84,3 +85,13 @@ impl Default for Config {
         }
     }
 }
+
+impl Validate for Config {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = FieldErrors::default();
+        if let LogTarget::File(path) = &self.target {
+            errors.check_writable("target", path);
+        }
+        errors.into_vec()
+    }
+}
@@ -1,3 +1,4 @@
+use crate::validation::{FieldError, FieldErrors, Validate, ValidationError};
 use serde::{Deserialize, Serialize};
 use std::{net::SocketAddr, path::PathBuf};
 
@@ -46,3 +47,30 @@ pub struct Config {
     /// Per request timeout in seconds before the server replies with 504 Gateway Timeout.
     pub request_timeout_seconds: u64,
 }
+
+impl Validate for Config {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = FieldErrors::default();
+        match &self.exporter {
+            Exporter::Log => {}
+            Exporter::Http(addr) if addr.port() == 0 => {
+                errors.push("exporter", ValidationError::Zero)
+            }
+            Exporter::Http(_) => {}
+            Exporter::File(path) => errors.check_writable("exporter", path),
+        }
+        errors.check_non_zero("max_tcp_connections", self.max_tcp_connections as u64);
+        errors.check_non_zero(
+            "max_concurrent_requests",
+            self.max_concurrent_requests as u64,
+        );
+        errors.check_non_zero("request_timeout_seconds", self.request_timeout_seconds);
+        errors.check_not_exceeding(
+            "request_timeout_seconds",
+            self.request_timeout_seconds,
+            "connection_read_timeout_seconds",
+            self.connection_read_timeout_seconds,
+        );
+        errors.into_vec()
+    }
+}
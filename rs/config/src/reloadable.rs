@@ -0,0 +1,134 @@
+//! Config fields that can be changed while the replica is running, e.g. to
+//! tune timeouts and rate limits without restarting it.
+//!
+//! A section opts in by implementing [Reloadable], which names the fields
+//! that take effect at runtime. A reloaded section is only applied if it is
+//! valid and no other field changed, since those would silently keep their
+//! previous value until the next restart.
+
+use crate::validation::{FieldError, Validate};
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+
+/// A config section of which some fields can be changed at runtime.
+pub trait Reloadable: Clone + PartialEq + Serialize + Validate {
+    /// The fields that take effect without restarting the replica.
+    const RELOADABLE_FIELDS: &'static [&'static str];
+
+    /// Returns the fields that differ between `self` and `new`, but cannot
+    /// be changed at runtime.
+    fn non_reloadable_changes(&self, new: &Self) -> Vec<String> {
+        let (current, new) = match (serde_json::to_value(self), serde_json::to_value(new)) {
+            (Ok(Value::Object(current)), Ok(Value::Object(new))) => (current, new),
+            _ => return vec![String::new()],
+        };
+        let mut fields: Vec<String> = current
+            .keys()
+            .chain(new.keys())
+            .filter(|field| !Self::RELOADABLE_FIELDS.contains(&field.as_str()))
+            .filter(|field| current.get(*field) != new.get(*field))
+            .cloned()
+            .collect();
+        fields.sort();
+        fields.dedup();
+        fields
+    }
+}
+
+/// Why a reloaded config section was not applied.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReloadError {
+    /// The section has defects.
+    Invalid(Vec<FieldError>),
+    /// Fields changed that only take effect after a restart.
+    NotReloadable(Vec<String>),
+}
+
+impl fmt::Display for ReloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReloadError::Invalid(errors) => {
+                write!(f, "invalid config:")?;
+                for error in errors {
+                    write!(f, " '{}' {};", error.field, error.error)?;
+                }
+                Ok(())
+            }
+            ReloadError::NotReloadable(fields) => write!(
+                f,
+                "the fields {} cannot be changed without a restart",
+                fields.join(", ")
+            ),
+        }
+    }
+}
+
+/// Checks whether `new` can replace the `current` section at runtime.
+/// Returns whether anything changed.
+pub fn check_reload<T: Reloadable>(current: &T, new: &T) -> Result<bool, ReloadError> {
+    if current == new {
+        return Ok(false);
+    }
+    let errors = new.validate();
+    if !errors.is_empty() {
+        return Err(ReloadError::Invalid(errors));
+    }
+    let fields = current.non_reloadable_changes(new);
+    if !fields.is_empty() {
+        return Err(ReloadError::NotReloadable(fields));
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_handler::Config;
+    use crate::validation::ValidationError;
+
+    #[test]
+    fn reloadable_fields_exist() {
+        let config = serde_json::to_value(Config::default()).unwrap();
+        for field in Config::RELOADABLE_FIELDS {
+            assert!(config.get(field).is_some(), "unknown field {}", field);
+        }
+    }
+
+    #[test]
+    fn accepts_changes_of_reloadable_fields_only() {
+        let current = Config::default();
+        assert_eq!(check_reload(&current, &current.clone()), Ok(false));
+
+        let mut new = current.clone();
+        new.request_timeout_seconds = 60;
+        new.endpoint_limits.query.per_ip_requests_per_second = Some(10);
+        assert_eq!(check_reload(&current, &new), Ok(true));
+
+        new.max_tcp_connections = 10;
+        new.port_file_path = Some("/tmp/port".into());
+        assert_eq!(
+            check_reload(&current, &new),
+            Err(ReloadError::NotReloadable(vec![
+                "max_tcp_connections".to_string(),
+                "port_file_path".to_string()
+            ]))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_changes() {
+        let current = Config::default();
+        let new = Config {
+            request_timeout_seconds: 0,
+            ..current.clone()
+        };
+        assert_eq!(
+            check_reload(&current, &new),
+            Err(ReloadError::Invalid(vec![FieldError {
+                field: "request_timeout_seconds",
+                error: ValidationError::Zero,
+            }]))
+        );
+    }
+}
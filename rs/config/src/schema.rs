@@ -0,0 +1,632 @@
+//! A [JSON Schema](https://json-schema.org/) of the replica config file, so
+//! that deployment tooling can validate a generated `ic.json5` before the
+//! replica is started.
+//!
+//! The schema is derived from the `Deserialize` implementations of the config
+//! sections: the config is deserialized from a [Tracer], which records the
+//! struct fields, enum variants and primitive types that are requested instead
+//! of providing data. Thus, the schema cannot get out of sync with the config
+//! structs. Values that cannot be traced, e.g. untagged enums or strings that
+//! are parsed into addresses, are described as accepting any value.
+
+use crate::config::ConfigOptional;
+use serde::de::{
+    self, value::BorrowedStrDeserializer, value::StrDeserializer, DeserializeOwned,
+    DeserializeSeed, Deserializer, EnumAccess, MapAccess, SeqAccess, VariantAccess, Visitor,
+};
+use serde_json::{json, Map, Value};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::rc::Rc;
+
+const JSON_SCHEMA_DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+// Path components of values that are not struct fields or enum variants.
+const OPTION_CONTENT: &str = "?";
+const SEQ_ITEM: &str = "[]";
+const MAP_VALUE: &str = "{}";
+
+/// The location of a value in the traced type, made of struct field names,
+/// enum variant names and the markers above.
+type Path = Vec<String>;
+
+/// What the `Deserialize` implementation requested at a [Path].
+enum Node {
+    Any,
+    Primitive(Value),
+    Option,
+    Seq,
+    Tuple(usize),
+    Map,
+    Struct(&'static [&'static str]),
+    Enum(&'static [&'static str]),
+}
+
+#[derive(Default)]
+struct Trace {
+    nodes: BTreeMap<Path, Node>,
+    /// Struct fields that are omitted, and enum variants that are avoided,
+    /// because tracing them failed in a previous attempt.
+    skipped: BTreeSet<Path>,
+    /// Enum variants that were traced, so that the next attempt can trace
+    /// another one.
+    traced_variants: BTreeSet<Path>,
+    /// The innermost field or variant whose tracing failed in the current
+    /// attempt.
+    failed: Option<Path>,
+}
+
+#[derive(Debug)]
+struct TraceError(String);
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+impl de::Error for TraceError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        TraceError(msg.to_string())
+    }
+}
+
+/// A [Deserializer] that records the requested types at its path.
+#[derive(Clone)]
+struct Tracer {
+    trace: Rc<RefCell<Trace>>,
+    path: Path,
+}
+
+impl Tracer {
+    fn child(&self, key: &str) -> Self {
+        let mut path = self.path.clone();
+        path.push(key.to_string());
+        Self {
+            trace: Rc::clone(&self.trace),
+            path,
+        }
+    }
+
+    fn record(&self, node: Node) {
+        self.trace
+            .borrow_mut()
+            .nodes
+            .insert(self.path.clone(), node);
+    }
+
+    fn fail(&self) {
+        let mut trace = self.trace.borrow_mut();
+        if trace.failed.is_none() {
+            trace.failed = Some(self.path.clone());
+        }
+    }
+
+    fn is_skipped(&self) -> bool {
+        self.trace.borrow().skipped.contains(&self.path)
+    }
+
+    /// Prefers variants that were not traced yet, then variants whose
+    /// tracing did not fail.
+    fn choose_variant(&self, variants: &'static [&'static str]) -> Option<&'static str> {
+        let trace = self.trace.borrow();
+        let path_of = |variant: &&str| {
+            let mut path = self.path.clone();
+            path.push(variant.to_string());
+            path
+        };
+        variants
+            .iter()
+            .find(|variant| !trace.traced_variants.contains(&path_of(variant)))
+            .or_else(|| {
+                variants
+                    .iter()
+                    .find(|variant| !trace.skipped.contains(&path_of(variant)))
+            })
+            .or_else(|| variants.first())
+            .copied()
+    }
+}
+
+macro_rules! trace_primitives {
+    ($($method:ident => $visit:ident($($sample:expr)?), $schema:tt;)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+                self.record(Node::Primitive(json!($schema)));
+                visitor.$visit($($sample)?)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for Tracer {
+    type Error = TraceError;
+
+    trace_primitives! {
+        deserialize_bool => visit_bool(false), {"type": "boolean"};
+        deserialize_i8 => visit_i8(0), {"type": "integer"};
+        deserialize_i16 => visit_i16(0), {"type": "integer"};
+        deserialize_i32 => visit_i32(0), {"type": "integer"};
+        deserialize_i64 => visit_i64(0), {"type": "integer"};
+        deserialize_i128 => visit_i128(0), {"type": "integer"};
+        deserialize_u8 => visit_u8(0), {"type": "integer", "minimum": 0, "maximum": u8::MAX};
+        deserialize_u16 => visit_u16(0), {"type": "integer", "minimum": 0, "maximum": u16::MAX};
+        deserialize_u32 => visit_u32(0), {"type": "integer", "minimum": 0, "maximum": u32::MAX};
+        deserialize_u64 => visit_u64(0), {"type": "integer", "minimum": 0};
+        deserialize_u128 => visit_u128(0), {"type": "integer", "minimum": 0};
+        deserialize_f32 => visit_f32(0.0), {"type": "number"};
+        deserialize_f64 => visit_f64(0.0), {"type": "number"};
+        deserialize_char => visit_char('a'), {"type": "string", "minLength": 1, "maxLength": 1};
+        deserialize_str => visit_str(""), {"type": "string"};
+        deserialize_string => visit_string(String::new()), {"type": "string"};
+        deserialize_bytes => visit_bytes(&[]), {"type": "array", "items": {"type": "integer"}};
+        deserialize_byte_buf => visit_byte_buf(vec![]), {"type": "array", "items": {"type": "integer"}};
+        deserialize_unit => visit_unit(), {"type": "null"};
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.record(Node::Any);
+        visitor.visit_unit()
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.record(Node::Option);
+        visitor.visit_some(self.child(OPTION_CONTENT))
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.record(Node::Seq);
+        visitor.visit_seq(SeqTracer {
+            item: self.child(SEQ_ITEM),
+        })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        self.record(Node::Tuple(len));
+        visitor.visit_seq(TupleTracer {
+            tracer: self,
+            index: 0,
+            len,
+        })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.record(Node::Map);
+        visitor.visit_map(MapTracer {
+            value: self.child(MAP_VALUE),
+            done: false,
+        })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        self.record(Node::Struct(fields));
+        let pending_field = Rc::new(RefCell::new(None));
+        let result = visitor.visit_map(StructTracer {
+            tracer: self.clone(),
+            fields: fields.iter(),
+            pending_field: Rc::clone(&pending_field),
+        });
+        if result.is_err() {
+            // A field that was rejected before its value was requested, e.g.
+            // an alias of another field, is omitted next time. Otherwise the
+            // struct itself, e.g. a required field of it, is to blame.
+            match pending_field.borrow_mut().take() {
+                Some(field) => self.child(field).fail(),
+                None => self.fail(),
+            }
+        }
+        result
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        self.record(Node::Enum(variants));
+        let variant = self
+            .choose_variant(variants)
+            .ok_or_else(|| TraceError("enum without variants".to_string()))?;
+        visitor.visit_enum(EnumTracer {
+            variant: self.child(variant),
+            name: variant,
+        })
+    }
+}
+
+/// Traces the item type of a sequence and yields no items.
+struct SeqTracer {
+    item: Tracer,
+}
+
+impl<'de> SeqAccess<'de> for SeqTracer {
+    type Error = TraceError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, TraceError> {
+        // Items are never yielded, so whether tracing them fails does not matter.
+        let failed = self.item.trace.borrow_mut().failed.take();
+        let _ = seed.deserialize(self.item.clone());
+        self.item.trace.borrow_mut().failed = failed;
+        Ok(None)
+    }
+}
+
+struct TupleTracer {
+    tracer: Tracer,
+    index: usize,
+    len: usize,
+}
+
+impl<'de> SeqAccess<'de> for TupleTracer {
+    type Error = TraceError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, TraceError> {
+        if self.index == self.len {
+            return Ok(None);
+        }
+        let element = self.tracer.child(&self.index.to_string());
+        self.index += 1;
+        seed.deserialize(element).map(Some)
+    }
+}
+
+/// Traces the value type of a map by yielding a single entry.
+struct MapTracer {
+    value: Tracer,
+    done: bool,
+}
+
+impl<'de> MapAccess<'de> for MapTracer {
+    type Error = TraceError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, TraceError> {
+        if std::mem::replace(&mut self.done, true) {
+            return Ok(None);
+        }
+        // Keys that cannot be deserialized from a string end the map.
+        Ok(seed
+            .deserialize(StrDeserializer::<TraceError>::new(""))
+            .ok())
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, TraceError> {
+        seed.deserialize(self.value.clone())
+    }
+}
+
+/// Yields every field of a struct that is not skipped.
+struct StructTracer {
+    tracer: Tracer,
+    fields: std::slice::Iter<'static, &'static str>,
+    pending_field: Rc<RefCell<Option<&'static str>>>,
+}
+
+impl<'de> MapAccess<'de> for StructTracer {
+    type Error = TraceError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, TraceError> {
+        for field in self.fields.by_ref() {
+            if self.tracer.child(field).is_skipped() {
+                continue;
+            }
+            *self.pending_field.borrow_mut() = Some(field);
+            return seed
+                .deserialize(BorrowedStrDeserializer::<TraceError>::new(field))
+                .map(Some);
+        }
+        Ok(None)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, TraceError> {
+        let field = self
+            .pending_field
+            .borrow_mut()
+            .take()
+            .ok_or_else(|| TraceError("value requested before key".to_string()))?;
+        let field = self.tracer.child(field);
+        seed.deserialize(field.clone()).map_err(|err| {
+            field.fail();
+            err
+        })
+    }
+}
+
+struct EnumTracer {
+    variant: Tracer,
+    name: &'static str,
+}
+
+impl<'de> EnumAccess<'de> for EnumTracer {
+    type Error = TraceError;
+    type Variant = VariantTracer;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, VariantTracer), TraceError> {
+        let value = seed.deserialize(BorrowedStrDeserializer::<TraceError>::new(self.name))?;
+        self.variant
+            .trace
+            .borrow_mut()
+            .traced_variants
+            .insert(self.variant.path.clone());
+        Ok((
+            value,
+            VariantTracer {
+                variant: self.variant,
+            },
+        ))
+    }
+}
+
+struct VariantTracer {
+    variant: Tracer,
+}
+
+impl VariantTracer {
+    /// Avoids the variant next time, unless it was avoided already, in which
+    /// case the enclosing field is to blame.
+    fn fail<T>(&self, result: Result<T, TraceError>) -> Result<T, TraceError> {
+        if result.is_err() && !self.variant.is_skipped() {
+            self.variant.fail();
+        }
+        result
+    }
+}
+
+impl<'de> VariantAccess<'de> for VariantTracer {
+    type Error = TraceError;
+
+    fn unit_variant(self) -> Result<(), TraceError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, TraceError> {
+        self.fail(seed.deserialize(self.variant.clone()))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        self.fail(self.variant.clone().deserialize_tuple(len, visitor))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        self.fail(self.variant.clone().deserialize_struct("", fields, visitor))
+    }
+}
+
+/// Returns the JSON Schema of the values `T` can be deserialized from.
+pub fn schema_for<T: DeserializeOwned>() -> Result<Value, String> {
+    let trace = Rc::new(RefCell::new(Trace::default()));
+    // Every attempt that fails omits the failing field, and every attempt
+    // traces another variant of the enums it reaches, until nothing changes.
+    loop {
+        let traced_variants = trace.borrow().traced_variants.len();
+        let result = T::deserialize(Tracer {
+            trace: Rc::clone(&trace),
+            path: vec![],
+        });
+        let mut trace = trace.borrow_mut();
+        let mut progress = trace.traced_variants.len() > traced_variants;
+        if let Err(err) = result {
+            if let Some(failed) = trace.failed.take() {
+                progress |= trace.skipped.insert(failed);
+            }
+            if !progress {
+                return Err(err.to_string());
+            }
+        }
+        if !progress {
+            break;
+        }
+    }
+    let trace = trace.borrow();
+    Ok(build(&trace.nodes, &mut vec![]))
+}
+
+/// Returns the JSON Schema of the replica config file.
+pub fn config_schema() -> Value {
+    let mut schema = schema_for::<ConfigOptional>().expect("Failed to trace the replica config");
+    let object = schema.as_object_mut().expect("The config is a struct");
+    object.insert("$schema".to_string(), json!(JSON_SCHEMA_DIALECT));
+    object.insert("title".to_string(), json!("Replica config"));
+    schema
+}
+
+fn build(nodes: &BTreeMap<Path, Node>, path: &mut Path) -> Value {
+    let mut child = |key: &str| {
+        path.push(key.to_string());
+        let schema = build(nodes, path);
+        path.pop();
+        schema
+    };
+    match nodes.get(path.as_slice()) {
+        None | Some(Node::Any) => json!({}),
+        Some(Node::Primitive(schema)) => schema.clone(),
+        Some(Node::Option) => json!({ "anyOf": [child(OPTION_CONTENT), {"type": "null"}] }),
+        Some(Node::Seq) => json!({ "type": "array", "items": child(SEQ_ITEM) }),
+        Some(Node::Tuple(len)) => json!({
+            "type": "array",
+            "prefixItems": (0..*len).map(|i| child(&i.to_string())).collect::<Vec<_>>(),
+            "minItems": len,
+            "maxItems": len,
+        }),
+        Some(Node::Map) => json!({ "type": "object", "additionalProperties": child(MAP_VALUE) }),
+        Some(Node::Struct(fields)) => json!({
+            "type": "object",
+            "properties": fields
+                .iter()
+                .map(|field| (field.to_string(), child(field)))
+                .collect::<Map<_, _>>(),
+            "additionalProperties": false,
+        }),
+        // Unit variants are written as strings, variants with data as an
+        // object with a single field named after the variant.
+        Some(Node::Enum(variants)) => json!({
+            "anyOf": [
+                { "enum": variants },
+                {
+                    "type": "object",
+                    "properties": variants
+                        .iter()
+                        .map(|variant| (variant.to_string(), child(variant)))
+                        .collect::<Map<_, _>>(),
+                    "additionalProperties": false,
+                    "minProperties": 1,
+                    "maxProperties": 1,
+                },
+            ]
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    /// Returns the schema of the property `key` of an object described by
+    /// `schema`, looking into the alternatives of options and enums.
+    fn property<'a>(schema: &'a Value, key: &str) -> Option<&'a Value> {
+        if let Some(property) = schema.get("properties").and_then(|p| p.get(key)) {
+            return Some(property);
+        }
+        if let Some(value) = schema.get("additionalProperties").filter(|v| v.is_object()) {
+            return Some(value);
+        }
+        schema
+            .get("anyOf")?
+            .as_array()?
+            .iter()
+            .find_map(|alternative| property(alternative, key))
+    }
+
+    fn assert_described(schema: &Value, value: &Value, path: &str) {
+        let object = match value {
+            Value::Object(object) => object,
+            _ => return,
+        };
+        if schema == &json!({}) {
+            return;
+        }
+        for (key, value) in object {
+            let path = format!("{}.{}", path, key);
+            let property =
+                property(schema, key).unwrap_or_else(|| panic!("{} is not in the schema", path));
+            assert_described(property, value, &path);
+        }
+    }
+
+    #[test]
+    fn every_field_of_the_default_config_is_in_the_schema() {
+        let (config, _tmpdir) = Config::temp_config();
+        let schema = config_schema();
+        assert_described(&schema, &serde_json::to_value(&config).unwrap(), "config");
+    }
+
+    #[test]
+    fn schema_describes_field_types() {
+        let schema = config_schema();
+        let http_handler = property(&schema, "http_handler").unwrap();
+        assert_eq!(
+            property(http_handler, "max_tcp_connections").unwrap(),
+            &json!({"type": "integer", "minimum": 0})
+        );
+        assert_eq!(
+            http_handler["anyOf"][0]["additionalProperties"],
+            json!(false)
+        );
+
+        let logger = property(&schema, "logger").unwrap();
+        assert_eq!(
+            property(logger, "level").unwrap()["anyOf"][0],
+            json!({"enum": ["critical", "error", "warning", "info", "debug", "trace"]})
+        );
+
+        // Variants with data are traced, even if they fail to deserialize.
+        let metrics = property(&schema, "metrics").unwrap();
+        let exporter = property(metrics, "exporter").unwrap();
+        assert_eq!(
+            property(exporter, "http").unwrap(),
+            &json!({"type": "string"})
+        );
+        assert_eq!(
+            property(exporter, "file").unwrap(),
+            &json!({"type": "string"})
+        );
+    }
+}
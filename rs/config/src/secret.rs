@@ -0,0 +1,128 @@
+//! A wrapper for config values that must not end up in logs, such as
+//! tokens or the paths of private keys.
+//!
+//! A [Secret] is redacted by its `Debug` and `Display` implementations, and
+//! the wrapped value is only available through [Secret::expose]. It is
+//! (de)serialized as the wrapped value, so that config files and the merging
+//! of config layers are not affected, unless it is serialized within
+//! [redacted], e.g. to render the effective config.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cell::Cell;
+use std::fmt;
+
+/// What a redacted value is rendered as.
+pub const REDACTED: &str = "<redacted>";
+
+thread_local! {
+    static REDACT_SERIALIZATION: Cell<bool> = Cell::new(false);
+}
+
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// Returns the wrapped value. Callers must not log it.
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+
+    pub fn into_exposed(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T: Serialize> Serialize for Secret<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if REDACT_SERIALIZATION.with(Cell::get) {
+            serializer.serialize_str(REDACTED)
+        } else {
+            self.0.serialize(serializer)
+        }
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Secret<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Self)
+    }
+}
+
+/// Runs `f`, serializing every [Secret] as [REDACTED] on the current thread
+/// in the meantime.
+pub fn redacted<R>(f: impl FnOnce() -> R) -> R {
+    struct Reset(bool);
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            REDACT_SERIALIZATION.with(|redact| redact.set(self.0));
+        }
+    }
+    let _reset = Reset(REDACT_SERIALIZATION.with(|redact| redact.replace(true)));
+    f()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Section {
+        name: String,
+        token: Secret<String>,
+    }
+
+    #[test]
+    fn redacts_debug_and_display() {
+        let section = Section {
+            name: "backup".to_string(),
+            token: Secret::new("s3cr3t".to_string()),
+        };
+        let debug = format!("{:?}", section);
+        assert!(!debug.contains("s3cr3t"), "{}", debug);
+        assert!(debug.contains(REDACTED), "{}", debug);
+        assert_eq!(section.token.to_string(), REDACTED);
+        assert_eq!(section.token.expose(), "s3cr3t");
+    }
+
+    #[test]
+    fn serializes_the_value_unless_redacted() {
+        let section: Section = json5::from_str(r#"{ name: "backup", token: "s3cr3t" }"#).unwrap();
+        assert_eq!(section.token.expose(), "s3cr3t");
+        assert_eq!(
+            serde_json::to_value(&section).unwrap(),
+            serde_json::json!({ "name": "backup", "token": "s3cr3t" })
+        );
+        assert_eq!(
+            redacted(|| serde_json::to_value(&section).unwrap()),
+            serde_json::json!({ "name": "backup", "token": REDACTED })
+        );
+        // Redaction ends with the closure.
+        assert_eq!(
+            serde_json::to_value(Secret::new(PathBuf::from("/key.pem"))).unwrap(),
+            serde_json::json!("/key.pem")
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use crate::validation::{FieldError, FieldErrors, Validate};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
@@ -15,3 +16,11 @@ impl Config {
         self.state_root.clone()
     }
 }
+
+impl Validate for Config {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = FieldErrors::default();
+        errors.check_writable("state_root", &self.state_root);
+        errors.into_vec()
+    }
+}
@@ -1,3 +1,4 @@
+use crate::validation::{FieldError, FieldErrors, Validate};
 use serde::{Deserialize, Serialize};
 use std::default::Default;
 
@@ -26,3 +27,12 @@ impl Default for TransportConfig {
         }
     }
 }
+
+impl Validate for TransportConfig {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = FieldErrors::default();
+        errors.check_non_zero("send_queue_size", self.send_queue_size as u64);
+        errors.check_non_zero("max_streams", self.max_streams as u64);
+        errors.into_vec()
+    }
+}
@@ -0,0 +1,213 @@
+//! Validation of the sections of the replica [Config](crate::Config).
+//!
+//! Unlike [ConfigValidate](crate::ConfigValidate), which rejects a config
+//! file on the first problem while it is being parsed, [Validate] reports all
+//! defects of a section, so that an operator can fix them in one go.
+
+use std::fmt;
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+
+/// What is wrong with the value of a config field.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The value must not be zero.
+    Zero,
+    /// The value must not exceed the value of the field `limit_field` of the
+    /// same section.
+    Exceeds { limit_field: &'static str },
+    /// The value must not exceed `max`.
+    TooLarge { max: u64 },
+    /// Neither the path, nor the directory it would be created in, is
+    /// writable.
+    NotWritable { path: PathBuf },
+    /// The value is also used by the field `other_field` of the same section.
+    Duplicate { other_field: &'static str },
+    /// The value is not supported in combination with other settings.
+    Unsupported { reason: &'static str },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::Zero => write!(f, "must not be zero"),
+            ValidationError::Exceeds { limit_field } => {
+                write!(f, "must not exceed '{}'", limit_field)
+            }
+            ValidationError::TooLarge { max } => write!(f, "must not exceed {}", max),
+            ValidationError::NotWritable { path } => {
+                write!(f, "path '{}' is not writable", path.display())
+            }
+            ValidationError::Duplicate { other_field } => {
+                write!(f, "must differ from '{}'", other_field)
+            }
+            ValidationError::Unsupported { reason } => write!(f, "unsupported: {}", reason),
+        }
+    }
+}
+
+/// A defect of a single field of a config section.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub error: ValidationError,
+}
+
+/// A defect of a field of the replica config.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigDefect {
+    pub section: &'static str,
+    pub field: &'static str,
+    pub error: ValidationError,
+}
+
+impl fmt::Display for ConfigDefect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}: {}", self.section, self.field, self.error)
+    }
+}
+
+/// Checks the invariants of a config section that cannot be expressed by its
+/// type, e.g. relations between fields or the accessibility of paths.
+pub trait Validate {
+    /// Returns all defects of the section, or an empty vector if it is valid.
+    fn validate(&self) -> Vec<FieldError>;
+}
+
+/// Collects the [FieldError]s of a section.
+#[derive(Default)]
+pub struct FieldErrors(Vec<FieldError>);
+
+impl FieldErrors {
+    pub fn push(&mut self, field: &'static str, error: ValidationError) {
+        self.0.push(FieldError { field, error });
+    }
+
+    pub fn check_non_zero(&mut self, field: &'static str, value: u64) {
+        if value == 0 {
+            self.push(field, ValidationError::Zero);
+        }
+    }
+
+    pub fn check_not_exceeding(
+        &mut self,
+        field: &'static str,
+        value: u64,
+        limit_field: &'static str,
+        limit: u64,
+    ) {
+        if value > limit {
+            self.push(field, ValidationError::Exceeds { limit_field });
+        }
+    }
+
+    /// Checks that the file or directory at `path` is writable or, if it
+    /// does not exist yet, that it can be created.
+    pub fn check_writable(&mut self, field: &'static str, path: &Path) {
+        if !is_writable(path) {
+            self.push(
+                field,
+                ValidationError::NotWritable {
+                    path: path.to_path_buf(),
+                },
+            );
+        }
+    }
+
+    pub fn into_vec(self) -> Vec<FieldError> {
+        self.0
+    }
+}
+
+fn is_writable(path: &Path) -> bool {
+    if path.is_dir() {
+        return tempfile::tempfile_in(path).is_ok();
+    }
+    if path.exists() {
+        return OpenOptions::new().append(true).open(path).is_ok();
+    }
+    match path.ancestors().skip(1).find(|ancestor| ancestor.exists()) {
+        Some(existing) => existing.is_dir() && tempfile::tempfile_in(existing).is_ok(),
+        // A relative path without any existing ancestor is relative to the
+        // working directory.
+        None => tempfile::tempfile_in(".").is_ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn default_config_is_valid() {
+        let (config, _tmpdir) = Config::temp_config();
+        assert_eq!(config.validate_all(), Ok(()));
+    }
+
+    #[test]
+    fn reports_all_defects_with_section_and_field() {
+        let (mut config, _tmpdir) = Config::temp_config();
+        config.http_handler.max_tcp_connections = 0;
+        config.http_handler.request_timeout_seconds =
+            config.http_handler.connection_read_timeout_seconds + 1;
+        config.transport.max_streams = 0;
+
+        let defects = config.validate_all().unwrap_err();
+        assert_eq!(
+            defects,
+            vec![
+                ConfigDefect {
+                    section: "transport",
+                    field: "max_streams",
+                    error: ValidationError::Zero,
+                },
+                ConfigDefect {
+                    section: "http_handler",
+                    field: "max_tcp_connections",
+                    error: ValidationError::Zero,
+                },
+                ConfigDefect {
+                    section: "http_handler",
+                    field: "request_timeout_seconds",
+                    error: ValidationError::Exceeds {
+                        limit_field: "connection_read_timeout_seconds"
+                    },
+                },
+            ]
+        );
+        assert_eq!(
+            defects[2].to_string(),
+            "http_handler.request_timeout_seconds: must not exceed 'connection_read_timeout_seconds'"
+        );
+    }
+
+    #[test]
+    fn ephemeral_http_port_requires_port_file() {
+        let (mut config, tmpdir) = Config::temp_config();
+        config.http_handler.listeners = vec!["127.0.0.1:0".parse::<SocketAddr>().unwrap().into()];
+        assert_eq!(
+            config.http_handler.validate(),
+            vec![FieldError {
+                field: "listeners",
+                error: ValidationError::Zero,
+            }]
+        );
+
+        config.http_handler.port_file_path = Some(tmpdir.path().join("port"));
+        assert_eq!(config.http_handler.validate(), vec![]);
+    }
+
+    #[test]
+    fn detects_paths_that_are_not_writable() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let file = tmpdir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+
+        assert!(is_writable(&tmpdir.path().join("a/b/c")));
+        assert!(is_writable(&file));
+        // Nothing can be created below a regular file.
+        assert!(!is_writable(&file.join("child")));
+    }
+}
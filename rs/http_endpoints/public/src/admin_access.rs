@@ -0,0 +1,122 @@
+//! Restricts the administrative endpoints, the dashboard and the pprof
+//! endpoints, to the source IP addresses permitted by
+//! [`AdminAccessConfig`](ic_config::http_handler::AdminAccessConfig).
+//! Rejected requests get `403 Forbidden`, are logged and are counted in
+//! `replica_http_admin_access_rejections_total`.
+//! The lists are reloadable: they are read on every request.
+use crate::{
+    common::make_plaintext_response, endpoint_limits::PeerAddr, metrics::HttpHandlerMetrics,
+    types::ApiReqType,
+};
+use hyper::{Body, Request, Response, StatusCode};
+use ic_config::http_handler::Config;
+use ic_logger::{warn, ReplicaLogger};
+use tokio::sync::watch;
+
+const LOG_EVERY_N_SECONDS: i32 = 10;
+
+#[derive(Clone)]
+pub(crate) struct AdminAccess {
+    config: watch::Receiver<Config>,
+    metrics: HttpHandlerMetrics,
+    log: ReplicaLogger,
+}
+
+impl AdminAccess {
+    pub(crate) fn new(
+        config: watch::Receiver<Config>,
+        metrics: HttpHandlerMetrics,
+        log: ReplicaLogger,
+    ) -> Self {
+        Self {
+            config,
+            metrics,
+            log,
+        }
+    }
+
+    /// Returns the response to reject `request` to the administrative
+    /// endpoint `request_type` with, if its source IP address is not
+    /// permitted. Requests without a peer address, i.e. received on a Unix
+    /// domain socket, are always permitted.
+    pub(crate) fn check(
+        &self,
+        request: &Request<Body>,
+        request_type: ApiReqType,
+    ) -> Result<(), Response<Body>> {
+        let peer_addr = match request.extensions().get::<PeerAddr>() {
+            Some(PeerAddr(peer_addr)) => *peer_addr,
+            None => return Ok(()),
+        };
+        if self.config.borrow().admin_access.permits(peer_addr.ip()) {
+            return Ok(());
+        }
+        self.metrics
+            .admin_access_rejections_total
+            .with_label_values(&[request_type.into()])
+            .inc();
+        warn!(every_n_seconds => LOG_EVERY_N_SECONDS,
+            self.log,
+            "Rejected a request to {} from {}: the address is not permitted by admin_access",
+            request.uri().path(),
+            peer_addr
+        );
+        Err(make_plaintext_response(
+            StatusCode::FORBIDDEN,
+            "Access to this endpoint is not permitted from this address.".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_logger::replica_logger::no_op_logger;
+    use ic_metrics::MetricsRegistry;
+
+    fn request_from(peer_addr: &str) -> Request<Body> {
+        let mut request = Request::new(Body::empty());
+        request
+            .extensions_mut()
+            .insert(PeerAddr(peer_addr.parse().unwrap()));
+        request
+    }
+
+    #[test]
+    fn rejects_addresses_not_permitted() {
+        let mut config = Config::default();
+        config.admin_access.allow = vec!["10.0.0.0/8".parse().unwrap()];
+        config.admin_access.deny = vec!["10.1.0.0/16".parse().unwrap()];
+        let (sender, config) = watch::channel(config);
+        let metrics = HttpHandlerMetrics::new(&MetricsRegistry::new());
+        let admin_access = AdminAccess::new(config, metrics.clone(), no_op_logger());
+
+        assert!(admin_access
+            .check(&request_from("10.2.0.1:1000"), ApiReqType::Dashboard)
+            .is_ok());
+        let response = admin_access
+            .check(&request_from("10.1.0.1:1000"), ApiReqType::Dashboard)
+            .err()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(admin_access
+            .check(&request_from("[::1]:1000"), ApiReqType::PprofProfile)
+            .is_err());
+        // Unix domain socket clients are always permitted.
+        assert!(admin_access
+            .check(&Request::new(Body::empty()), ApiReqType::PprofProfile)
+            .is_ok());
+        assert_eq!(
+            metrics
+                .admin_access_rejections_total
+                .with_label_values(&["dashboard"])
+                .get(),
+            1
+        );
+
+        sender.send_modify(|config| config.admin_access = Default::default());
+        assert!(admin_access
+            .check(&request_from("[::1]:1000"), ApiReqType::PprofProfile)
+            .is_ok());
+    }
+}
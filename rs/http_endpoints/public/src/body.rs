@@ -17,10 +17,12 @@ pub(crate) struct BodyReceiverLayer {
 }
 
 impl BodyReceiverLayer {
-    pub(crate) fn new(config: &Config) -> Self {
+    /// Receives request bodies of at most `max_request_size_bytes`, the limit
+    /// of the endpoint class in [`Config::max_request_size_bytes`].
+    pub(crate) fn new(config: &Config, max_request_size_bytes: u64) -> Self {
         Self {
             max_request_receive_duration: Duration::from_secs(config.max_request_receive_seconds),
-            max_request_body_size: Byte::from_bytes(config.max_request_size_bytes.into()),
+            max_request_body_size: Byte::from_bytes(max_request_size_bytes.into()),
         }
     }
 }
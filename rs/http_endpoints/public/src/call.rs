@@ -71,7 +71,10 @@ impl CallService {
         }));
         BoxCloneService::new(
             ServiceBuilder::new()
-                .layer(BodyReceiverLayer::new(&config))
+                .layer(BodyReceiverLayer::new(
+                    &config,
+                    config.max_request_size_bytes.call,
+                ))
                 .service(base_service),
         )
     }
@@ -46,7 +46,10 @@ impl CatchUpPackageService {
 
         BoxCloneService::new(
             ServiceBuilder::new()
-                .layer(BodyReceiverLayer::new(&config))
+                .layer(BodyReceiverLayer::new(
+                    &config,
+                    config.max_request_size_bytes.read_state,
+                ))
                 .service(base_service),
         )
     }
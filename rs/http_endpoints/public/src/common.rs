@@ -60,6 +60,18 @@ pub(crate) fn make_plaintext_response(status: StatusCode, message: String) -> Re
     resp
 }
 
+/// A `429 Too Many Requests` response asking the client to retry after
+/// `retry_after_seconds`.
+pub(crate) fn make_overloaded_response(
+    message: String,
+    retry_after_seconds: u64,
+) -> Response<Body> {
+    let mut resp = make_plaintext_response(StatusCode::TOO_MANY_REQUESTS, message);
+    resp.headers_mut()
+        .insert(hyper::header::RETRY_AFTER, retry_after_seconds.into());
+    resp
+}
+
 /// Converts a user error into an HTTP response.
 ///
 /// We need this conversion because we validate user requests twice:
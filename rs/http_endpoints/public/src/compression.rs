@@ -0,0 +1,317 @@
+//! Compresses response bodies according to the [`CompressionConfig`], with
+//! the encoding the client prefers among the `Accept-Encoding` header of the
+//! request and the configured encodings. Only successful responses of at
+//! least `min_response_size_bytes` are compressed, and only if that makes
+//! them smaller. The bytes saved are counted in
+//! `replica_http_compression_saved_bytes_total`.
+//! The config is reloadable: it is read on every request.
+use crate::{
+    common::make_plaintext_response, metrics::HttpHandlerMetrics, types::ApiReqType,
+    EndpointService,
+};
+use flate2::{write::GzEncoder, Compression};
+use hyper::{
+    body::HttpBody,
+    header::{self, HeaderMap, HeaderValue},
+    Body, Request, Response, StatusCode,
+};
+use ic_config::http_handler::{CompressionConfig, Config, ContentEncoding};
+use std::{
+    io::Write,
+    task::{Context, Poll},
+};
+use tokio::sync::watch;
+use tower::{util::BoxCloneService, BoxError, Service};
+
+/// Brotli quality level, from 0 to 11. Higher levels are too slow for
+/// responses compressed on the fly.
+const BROTLI_QUALITY: u32 = 5;
+/// Base-2 logarithm of the brotli window size.
+const BROTLI_LG_WINDOW_SIZE: u32 = 22;
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
+#[derive(Clone)]
+pub(crate) struct CompressionService {
+    request_type: ApiReqType,
+    config: watch::Receiver<Config>,
+    metrics: HttpHandlerMetrics,
+    inner: EndpointService,
+}
+
+impl CompressionService {
+    /// Wraps `inner`, compressing its responses according to the current
+    /// config.
+    pub(crate) fn new_service(
+        config: watch::Receiver<Config>,
+        request_type: ApiReqType,
+        metrics: HttpHandlerMetrics,
+        inner: EndpointService,
+    ) -> EndpointService {
+        BoxCloneService::new(Self {
+            request_type,
+            config,
+            metrics,
+            inner,
+        })
+    }
+}
+
+impl Service<Request<Body>> for CompressionService {
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = <EndpointService as Service<Request<Body>>>::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let compression = self.config.borrow().compression.clone();
+        let encoding = negotiate(request.headers(), &compression.encodings);
+        let response = self.inner.call(request);
+        let request_type = self.request_type;
+        let metrics = self.metrics.clone();
+        Box::pin(async move {
+            let mut response = response.await?;
+            if compression.encodings.is_empty() {
+                return Ok(response);
+            }
+            // Caches must not serve a compressed response to clients that
+            // don't accept it.
+            response
+                .headers_mut()
+                .append(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+            Ok(match encoding {
+                Some(encoding) => {
+                    compress_response(response, encoding, &compression, request_type, &metrics)
+                        .await
+                }
+                None => response,
+            })
+        })
+    }
+}
+
+/// Returns the first of `encodings` with the highest quality value in the
+/// `Accept-Encoding` headers, or `None` if the client accepts none of them.
+fn negotiate(headers: &HeaderMap, encodings: &[ContentEncoding]) -> Option<ContentEncoding> {
+    let mut accepted = vec![];
+    for value in headers.get_all(header::ACCEPT_ENCODING) {
+        let value = match value.to_str() {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        for coding in value.split(',') {
+            let mut params = coding.split(';');
+            let name = params.next().unwrap_or_default().trim().to_lowercase();
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|quality| quality.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            accepted.push((name, quality));
+        }
+    }
+    let quality_of = |encoding: &ContentEncoding| {
+        let explicit = accepted
+            .iter()
+            .find(|(name, _)| name == encoding.as_str())
+            .or_else(|| accepted.iter().find(|(name, _)| name == "*"));
+        explicit.map_or(0.0, |(_, quality)| *quality)
+    };
+
+    let mut best: Option<(ContentEncoding, f32)> = None;
+    for encoding in encodings {
+        let quality = quality_of(encoding);
+        if quality > 0.0 && best.map_or(true, |(_, best_quality)| quality > best_quality) {
+            best = Some((*encoding, quality));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+async fn compress_response(
+    response: Response<Body>,
+    encoding: ContentEncoding,
+    compression: &CompressionConfig,
+    request_type: ApiReqType,
+    metrics: &HttpHandlerMetrics,
+) -> Response<Body> {
+    // The endpoints build their responses in memory, so the size is known.
+    let too_small = response
+        .body()
+        .size_hint()
+        .exact()
+        .map_or(true, |size| size < compression.min_response_size_bytes);
+    if too_small
+        || response.status() != StatusCode::OK
+        || response.headers().contains_key(header::CONTENT_ENCODING)
+    {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(err) => {
+            return make_plaintext_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to read the response body: {}", err),
+            )
+        }
+    };
+    let (body, compressed) = tokio::task::spawn_blocking(move || {
+        let compressed = encode(encoding, &body);
+        (body, compressed)
+    })
+    .await
+    .expect("Compressing a response panicked.");
+
+    match compressed {
+        Ok(compressed) if compressed.len() < body.len() => {
+            let labels: [&str; 2] = [request_type.into(), encoding.as_str()];
+            metrics
+                .compressed_responses_total
+                .with_label_values(&labels)
+                .inc();
+            metrics
+                .compression_saved_bytes_total
+                .with_label_values(&labels)
+                .inc_by((body.len() - compressed.len()) as u64);
+            parts.headers.insert(
+                header::CONTENT_ENCODING,
+                HeaderValue::from_static(encoding.as_str()),
+            );
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Response::from_parts(parts, Body::from(compressed))
+        }
+        _ => Response::from_parts(parts, Body::from(body)),
+    }
+}
+
+fn encode(encoding: ContentEncoding, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    match encoding {
+        ContentEncoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        ContentEncoding::Brotli => {
+            let mut encoder = brotli::CompressorWriter::new(
+                Vec::new(),
+                BROTLI_BUFFER_SIZE,
+                BROTLI_QUALITY,
+                BROTLI_LG_WINDOW_SIZE,
+            );
+            encoder.write_all(data)?;
+            Ok(encoder.into_inner())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use ic_metrics::MetricsRegistry;
+    use std::io::Read;
+    use tower::ServiceExt;
+
+    const GZIP_AND_BR: [ContentEncoding; 2] = [ContentEncoding::Gzip, ContentEncoding::Brotli];
+    const BR_AND_GZIP: [ContentEncoding; 2] = [ContentEncoding::Brotli, ContentEncoding::Gzip];
+
+    fn accept_encoding(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_ENCODING, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn negotiates_the_preferred_encoding() {
+        use ContentEncoding::*;
+
+        assert_eq!(negotiate(&HeaderMap::new(), &GZIP_AND_BR), None);
+        assert_eq!(negotiate(&accept_encoding("identity"), &GZIP_AND_BR), None);
+        assert_eq!(
+            negotiate(&accept_encoding("br"), &GZIP_AND_BR),
+            Some(Brotli)
+        );
+        // Equal qualities are resolved in the order of the config.
+        assert_eq!(
+            negotiate(&accept_encoding("gzip, deflate, br"), &GZIP_AND_BR),
+            Some(Gzip)
+        );
+        assert_eq!(
+            negotiate(&accept_encoding("gzip, deflate, br"), &BR_AND_GZIP),
+            Some(Brotli)
+        );
+        assert_eq!(
+            negotiate(&accept_encoding("br;q=0.5, GZIP"), &BR_AND_GZIP),
+            Some(Gzip)
+        );
+        assert_eq!(
+            negotiate(&accept_encoding("*;q=0.1, gzip;q=0"), &GZIP_AND_BR),
+            Some(Brotli)
+        );
+        assert_eq!(negotiate(&accept_encoding("gzip"), &[]), None);
+    }
+
+    #[tokio::test]
+    async fn compresses_large_responses() {
+        let (sender, config) = watch::channel(Config::default());
+        let metrics = HttpHandlerMetrics::new(&MetricsRegistry::new());
+        let service = CompressionService::new_service(
+            config,
+            ApiReqType::Query,
+            metrics.clone(),
+            BoxCloneService::new(tower::service_fn(|request: Request<Body>| async move {
+                let size = request.uri().path()[1..].parse().unwrap();
+                Ok::<_, BoxError>(Response::new(Body::from(vec![b'a'; size])))
+            })),
+        );
+        let call = |path: &str| {
+            let request = Request::get(path)
+                .header(header::ACCEPT_ENCODING, "gzip")
+                .body(Body::empty())
+                .unwrap();
+            service.clone().oneshot(request)
+        };
+
+        // Disabled by default.
+        let response = call("/4096").await.unwrap();
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        assert!(response.headers().get(header::VARY).is_none());
+
+        sender.send_modify(|config| config.compression.encodings = GZIP_AND_BR.to_vec());
+        let response = call("/4096").await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(response.headers()[header::VARY], "Accept-Encoding");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let mut decompressed = vec![];
+        GzDecoder::new(&body[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, vec![b'a'; 4096]);
+        assert_eq!(
+            metrics
+                .compression_saved_bytes_total
+                .with_label_values(&["query", "gzip"])
+                .get(),
+            (4096 - body.len()) as u64
+        );
+
+        // Below the threshold.
+        let response = call("/512").await.unwrap();
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(
+            hyper::body::to_bytes(response.into_body()).await.unwrap(),
+            vec![b'a'; 512]
+        );
+        assert_eq!(
+            metrics
+                .compressed_responses_total
+                .with_label_values(&["query", "gzip"])
+                .get(),
+            1
+        );
+    }
+}
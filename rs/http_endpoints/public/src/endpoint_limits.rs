@@ -0,0 +1,351 @@
+//! Enforces the [`EndpointLimits`] of an endpoint class: a cap on the number
+//! of concurrently processed requests and a token bucket per source IP
+//! address. Requests exceeding a limit are rejected with
+//! `429 Too Many Requests` and a `Retry-After` header, and counted in
+//! `replica_http_limit_hits_total`. IPv6 addresses share the bucket of their
+//! /64 prefix, which is usually assigned to a single host.
+//! The limits are reloadable: when they change, the limiters of the class are
+//! replaced, which resets the token buckets.
+use crate::{
+    common::make_overloaded_response, metrics::HttpHandlerMetrics, types::ApiReqType,
+    EndpointService,
+};
+use hyper::{Body, Request, Response};
+use ic_config::http_handler::{Config, EndpointLimits};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    sync::{watch, Semaphore},
+    time::Instant,
+};
+use tower::{util::BoxCloneService, BoxError, Service};
+
+/// The number of tracked source IP addresses. Once reached, the buckets of
+/// idle addresses are dropped, and addresses without a bucket share a single
+/// one until there is room again.
+const MAX_TRACKED_IPS: usize = 100_000;
+
+/// How often the buckets of idle addresses are dropped at most, so that a
+/// full map is not scanned on every request.
+const EVICTION_INTERVAL: Duration = Duration::from_secs(1);
+
+pub(crate) const LIMIT_CONCURRENCY: &str = "concurrency";
+pub(crate) const LIMIT_RATE: &str = "rate";
+
+/// The address of the peer a request was received from, attached to every
+/// request as an extension.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct PeerAddr(pub SocketAddr);
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn full(burst: f64, now: Instant) -> Self {
+        Self {
+            tokens: burst,
+            last_refill: now,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Buckets {
+    by_ip: HashMap<IpAddr, TokenBucket>,
+    /// Shared by the addresses that find no room in `by_ip`.
+    overflow: Option<TokenBucket>,
+    last_eviction: Option<Instant>,
+}
+
+struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<Buckets>,
+}
+
+/// The address whose bucket a request from `ip` takes its token from.
+fn bucket_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(ipv6) => match ipv6.to_ipv4_mapped() {
+            Some(ipv4) => IpAddr::V4(ipv4),
+            None => {
+                let prefix = u128::from(ipv6) & !((1u128 << 64) - 1);
+                IpAddr::V6(prefix.into())
+            }
+        },
+    }
+}
+
+impl RateLimiter {
+    fn new(requests_per_second: u32, burst: u32) -> Self {
+        Self {
+            rate: requests_per_second as f64,
+            burst: burst as f64,
+            buckets: Mutex::new(Buckets::default()),
+        }
+    }
+
+    /// Takes a token from the bucket of `ip`. Returns false if it is empty.
+    fn try_acquire(&self, ip: IpAddr, now: Instant) -> bool {
+        let ip = bucket_ip(ip);
+        let mut guard = self.buckets.lock().unwrap();
+        let buckets = &mut *guard;
+        let eviction_due = buckets.last_eviction.map_or(true, |last_eviction| {
+            now.duration_since(last_eviction) >= EVICTION_INTERVAL
+        });
+        if buckets.by_ip.len() >= MAX_TRACKED_IPS && eviction_due {
+            // Buckets that would be full again are indistinguishable from new ones.
+            let (rate, burst) = (self.rate, self.burst);
+            buckets.by_ip.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.last_refill).as_secs_f64() * rate < burst
+            });
+            buckets.last_eviction = Some(now);
+        }
+        let burst = self.burst;
+        let bucket = if buckets.by_ip.len() < MAX_TRACKED_IPS || buckets.by_ip.contains_key(&ip) {
+            buckets
+                .by_ip
+                .entry(ip)
+                .or_insert_with(|| TokenBucket::full(burst, now))
+        } else {
+            buckets
+                .overflow
+                .get_or_insert_with(|| TokenBucket::full(burst, now))
+        };
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.last_refill = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The limiters enforcing `limits`.
+struct Limiters {
+    limits: EndpointLimits,
+    concurrency: Option<Arc<Semaphore>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+impl Limiters {
+    fn new(limits: &EndpointLimits) -> Self {
+        Self {
+            limits: limits.clone(),
+            concurrency: limits
+                .max_concurrent_requests
+                .map(|max| Arc::new(Semaphore::new(max))),
+            rate_limiter: limits
+                .per_ip_requests_per_second
+                .map(|rate| Arc::new(RateLimiter::new(rate, limits.per_ip_burst.unwrap_or(rate)))),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct EndpointLimitService {
+    request_type: ApiReqType,
+    config: watch::Receiver<Config>,
+    select_limits: fn(&Config) -> &EndpointLimits,
+    limiters: Arc<Mutex<Limiters>>,
+    metrics: HttpHandlerMetrics,
+    inner: EndpointService,
+}
+
+impl EndpointLimitService {
+    /// Wraps `inner` with the limits `select_limits` picks from the current
+    /// config.
+    pub(crate) fn new_service(
+        config: watch::Receiver<Config>,
+        select_limits: fn(&Config) -> &EndpointLimits,
+        request_type: ApiReqType,
+        metrics: HttpHandlerMetrics,
+        inner: EndpointService,
+    ) -> EndpointService {
+        let limiters = Limiters::new(select_limits(&config.borrow()));
+        BoxCloneService::new(Self {
+            request_type,
+            config,
+            select_limits,
+            limiters: Arc::new(Mutex::new(limiters)),
+            metrics,
+            inner,
+        })
+    }
+
+    /// Returns the limiters of the current limits, replacing them if the
+    /// limits were reloaded.
+    fn current_limiters(&self) -> (Option<Arc<Semaphore>>, Option<Arc<RateLimiter>>) {
+        let config = self.config.borrow();
+        let limits = (self.select_limits)(&config);
+        let mut limiters = self.limiters.lock().unwrap();
+        if limiters.limits != *limits {
+            *limiters = Limiters::new(limits);
+        }
+        (limiters.concurrency.clone(), limiters.rate_limiter.clone())
+    }
+
+    fn reject(&self, limit: &'static str, message: &str) -> Response<Body> {
+        self.metrics
+            .limit_hits_total
+            .with_label_values(&[self.request_type.into(), limit])
+            .inc();
+        let retry_after_seconds = self.config.borrow().overload_retry_after_seconds;
+        make_overloaded_response(message.to_string(), retry_after_seconds)
+    }
+}
+
+impl Service<Request<Body>> for EndpointLimitService {
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = <EndpointService as Service<Request<Body>>>::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let (concurrency, rate_limiter) = self.current_limiters();
+        if let (Some(rate_limiter), Some(PeerAddr(peer_addr))) =
+            (&rate_limiter, request.extensions().get::<PeerAddr>())
+        {
+            if !rate_limiter.try_acquire(peer_addr.ip(), Instant::now()) {
+                let response = self.reject(LIMIT_RATE, "Request rate limit exceeded.");
+                return Box::pin(async move { Ok(response) });
+            }
+        }
+
+        let permit = match concurrency {
+            Some(semaphore) => match semaphore.try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => {
+                    let response = self.reject(LIMIT_CONCURRENCY, "Too many concurrent requests.");
+                    return Box::pin(async move { Ok(response) });
+                }
+            },
+            None => None,
+        };
+
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = response.await;
+            drop(permit);
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_metrics::MetricsRegistry;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn token_bucket_allows_bursts_and_refills() {
+        let rate_limiter = RateLimiter::new(2, 3);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other_ip: IpAddr = "10.0.0.2".parse().unwrap();
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(rate_limiter.try_acquire(ip, start));
+        }
+        assert!(!rate_limiter.try_acquire(ip, start));
+        // Buckets are per source IP.
+        assert!(rate_limiter.try_acquire(other_ip, start));
+
+        // Two tokens are added per second.
+        let later = start + Duration::from_millis(500);
+        assert!(rate_limiter.try_acquire(ip, later));
+        assert!(!rate_limiter.try_acquire(ip, later));
+
+        // The bucket never holds more than the burst size.
+        let much_later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(rate_limiter.try_acquire(ip, much_later));
+        }
+        assert!(!rate_limiter.try_acquire(ip, much_later));
+    }
+
+    #[test]
+    fn ipv6_addresses_share_the_bucket_of_their_prefix() {
+        let rate_limiter = RateLimiter::new(1, 1);
+        let start = Instant::now();
+
+        assert!(rate_limiter.try_acquire("2001:db8:0:1::1".parse().unwrap(), start));
+        assert!(!rate_limiter.try_acquire("2001:db8:0:1:ffff::2".parse().unwrap(), start));
+        assert!(rate_limiter.try_acquire("2001:db8:0:2::1".parse().unwrap(), start));
+        // IPv4-mapped addresses are limited like the IPv4 address.
+        assert!(rate_limiter.try_acquire("10.0.0.1".parse().unwrap(), start));
+        assert!(!rate_limiter.try_acquire("::ffff:10.0.0.1".parse().unwrap(), start));
+    }
+
+    #[test]
+    fn tracked_addresses_are_capped() {
+        let rate_limiter = RateLimiter::new(1, 1);
+        let start = Instant::now();
+        let ip = |n: u32| IpAddr::V4(Ipv4Addr::from(n));
+        for n in 0..MAX_TRACKED_IPS as u32 {
+            assert!(rate_limiter.try_acquire(ip(n), start));
+        }
+
+        // No bucket is idle, so new addresses share the overflow bucket.
+        let new_ip = ip(MAX_TRACKED_IPS as u32);
+        assert!(rate_limiter.try_acquire(new_ip, start));
+        assert!(!rate_limiter.try_acquire(ip(MAX_TRACKED_IPS as u32 + 1), start));
+        assert_eq!(
+            rate_limiter.buckets.lock().unwrap().by_ip.len(),
+            MAX_TRACKED_IPS
+        );
+
+        // Idle buckets are dropped, at most once per interval.
+        let later = start + Duration::from_millis(500);
+        assert!(!rate_limiter.try_acquire(ip(0), start));
+        assert!(!rate_limiter.try_acquire(new_ip, later));
+        let much_later = start + EVICTION_INTERVAL * 2;
+        assert!(rate_limiter.try_acquire(new_ip, much_later));
+        assert_eq!(rate_limiter.buckets.lock().unwrap().by_ip.len(), 1);
+    }
+
+    #[test]
+    fn replaces_limiters_when_limits_are_reloaded() {
+        let (sender, config) = watch::channel(Config::default());
+        let select_limits: fn(&Config) -> &EndpointLimits = |config| &config.endpoint_limits.query;
+        let limiters = Limiters::new(select_limits(&config.borrow()));
+        let service = EndpointLimitService {
+            request_type: ApiReqType::Query,
+            config,
+            select_limits,
+            limiters: Arc::new(Mutex::new(limiters)),
+            metrics: HttpHandlerMetrics::new(&MetricsRegistry::new()),
+            inner: BoxCloneService::new(tower::service_fn(|_: Request<Body>| async {
+                Ok::<_, BoxError>(Response::new(Body::empty()))
+            })),
+        };
+        assert!(service.current_limiters().0.is_none());
+
+        sender.send_modify(|config| config.endpoint_limits.query.max_concurrent_requests = Some(1));
+        let (concurrency, _) = service.current_limiters();
+        let concurrency = concurrency.unwrap();
+        assert_eq!(concurrency.available_permits(), 1);
+
+        // Limiters are only replaced when the limits change.
+        sender.send_modify(|config| config.request_timeout_seconds = 60);
+        assert!(Arc::ptr_eq(
+            &service.current_limiters().0.unwrap(),
+            &concurrency
+        ));
+    }
+}
@@ -5,35 +5,49 @@
 //! As much as possible the naming of structs in this module should match the
 //! naming used in the [Interface
 //! Specification](https://sdk.dfinity.org/docs/interface-spec/index.html)
+mod admin_access;
 mod body;
 mod call;
 mod catch_up_package;
 mod common;
+mod compression;
 mod dashboard;
+mod endpoint_limits;
 mod health_status_refresher;
 mod metrics;
 mod pprof;
 mod query;
 mod read_state;
+mod reload;
+mod request_tracing;
+mod shutdown;
 mod state_reader_executor;
 mod status;
+mod stream_limits;
+mod tls;
 mod types;
 mod validator_executor;
 
 use crate::{
+    admin_access::AdminAccess,
     call::CallService,
     catch_up_package::CatchUpPackageService,
     common::{
         get_cors_headers, get_root_threshold_public_key, make_plaintext_response,
         map_box_error_to_response,
     },
+    compression::CompressionService,
     dashboard::DashboardService,
+    endpoint_limits::{EndpointLimitService, PeerAddr},
     health_status_refresher::HealthStatusRefreshLayer,
     metrics::{LABEL_REQUEST_TYPE, LABEL_STATUS, REQUESTS_LABEL_NAMES, REQUESTS_NUM_LABELS},
     query::QueryService,
     read_state::ReadStateService,
+    request_tracing::RequestTracer,
+    shutdown::ShutdownSignal,
     state_reader_executor::StateReaderExecutor,
     status::StatusService,
+    stream_limits::StreamLimiter,
     types::*,
     validator_executor::ValidatorExecutor,
 };
@@ -46,7 +60,7 @@ use hyper::{
 use hyper_tls::HttpsConnector;
 use ic_async_utils::{receive_body, start_tcp_listener};
 use ic_certification::validate_subnet_delegation_certificate;
-use ic_config::http_handler::Config;
+use ic_config::http_handler::{Config, ListenAddress, Listener, TlsCertificateSource};
 use ic_crypto_tls_interfaces::TlsHandshake;
 use ic_crypto_tree_hash::{lookup_path, LabeledTree, Path};
 use ic_crypto_utils_threshold_sig_der::parse_threshold_sig_key_from_der;
@@ -77,20 +91,25 @@ use ic_types::{
 };
 use metrics::{HttpHandlerMetrics, LABEL_UNKNOWN};
 use rand::Rng;
+pub use reload::HttpConfigReloader;
+pub use shutdown::{DrainReport, HttpServerHandle};
 use std::{
     convert::{Infallible, TryFrom},
     io::Write,
     net::SocketAddr,
-    path::PathBuf,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
     str::FromStr,
     sync::{Arc, RwLock},
     time::Duration,
 };
 use tempfile::NamedTempFile;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::net::TcpStream;
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::sync::{watch, Notify};
 use tokio::time::{sleep, timeout, Instant};
 use tokio_io_timeout::TimeoutStream;
+use tokio_rustls::TlsAcceptor;
 use tower::{
     limit::GlobalConcurrencyLimitLayer, service_fn, util::BoxCloneService, BoxError, Service,
     ServiceBuilder, ServiceExt,
@@ -98,6 +117,9 @@ use tower::{
 
 const HTTP_DASHBOARD_URL_PATH: &str = "/_/dashboard";
 const CONTENT_TYPE_CBOR: &str = "application/cbor";
+/// The `Debug` output of the error hyper fails HTTP/1 connections with when
+/// they exceed `http1_header_read_timeout`, which names the kind of the error.
+const HYPER_HEADER_TIMEOUT_DEBUG: &str = "hyper::Error(HeaderTimeout)";
 
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) struct HttpError {
@@ -118,6 +140,9 @@ struct HttpHandler {
     status_service: EndpointService,
     read_state_service: EndpointService,
     health_status_refresher: HealthStatusRefreshLayer,
+    stream_limiter: StreamLimiter,
+    admin_access: AdminAccess,
+    request_tracer: RequestTracer,
 }
 
 // Crates a detached tokio blocking task that initializes the server (reading
@@ -215,6 +240,7 @@ fn create_port_file(path: PathBuf, port: u16) {
 }
 
 /// Creates HTTP server. The function returns only after a TCP listener is bound to a port.
+/// The returned handle shuts the server down gracefully and reloads its config.
 #[allow(clippy::too_many_arguments)]
 pub fn start_server(
     rt_handle: tokio::runtime::Handle,
@@ -235,17 +261,38 @@ pub fn start_server(
     consensus_pool_cache: Arc<dyn ConsensusPoolCache>,
     subnet_type: SubnetType,
     malicious_flags: MaliciousFlags,
-) {
-    let listen_addr = config.listen_addr;
+) -> HttpServerHandle {
     info!(log, "Starting HTTP server...");
 
     let _enter = rt_handle.enter();
-    // TODO(OR4-60): temporarily listen on [::] so that we accept both IPv4 and
-    // IPv6 connections. This requires net.ipv6.bindv6only = 0. Revert this once
-    // we have rolled out IPv6 in prometheus and ic_p8s_service_discovery.
-    let mut addr = "[::]:8080".parse::<SocketAddr>().unwrap();
-    addr.set_port(listen_addr.port());
-    let tcp_listener = start_tcp_listener(addr);
+    // Without an acceptor, HTTPS connections are served with the node's
+    // registry certificate through `tls_handshake`.
+    let tls_acceptor = match &config.tls.certificate {
+        TlsCertificateSource::Registry => None,
+        TlsCertificateSource::Files {
+            certificate_path,
+            private_key_path,
+        } => Some(tls::start_file_tls_acceptor(
+            &rt_handle,
+            log.clone(),
+            &config.tls,
+            certificate_path,
+            private_key_path.expose(),
+        )),
+    };
+    let bound_listeners: Vec<(Listener, BoundListener)> = config
+        .listeners
+        .iter()
+        .map(|listener| {
+            let bound_listener = match (listener.tcp_bind_addr(), &listener.address) {
+                (Some(addr), _) => BoundListener::Tcp(start_tcp_listener(addr)),
+                (None, ListenAddress::Unix(path)) => BoundListener::Unix(start_unix_listener(path)),
+                (None, ListenAddress::Tcp(_)) => unreachable!("TCP listeners have a bind address"),
+            };
+            info!(log, "Listening on {}", listener.address);
+            (listener.clone(), bound_listener)
+        })
+        .collect();
 
     if !AtomicCell::<ReplicaHealthStatus>::is_lock_free() {
         error!(log, "Replica health status uses locks instead of atomics.");
@@ -256,46 +303,84 @@ pub fn start_server(
     let health_status = Arc::new(AtomicCell::new(ReplicaHealthStatus::Starting));
     let state_reader_executor = StateReaderExecutor::new(state_reader);
     let validator_executor = ValidatorExecutor::new(ingress_verifier, log.clone());
-    let call_service = CallService::new_service(
-        config.clone(),
-        log.clone(),
+    let (config_reloader, reloaded_config) = HttpConfigReloader::new(config.clone());
+    let call_service = EndpointLimitService::new_service(
+        reloaded_config.clone(),
+        |config| &config.endpoint_limits.call,
+        ApiReqType::Call,
         metrics.clone(),
-        subnet_id,
-        Arc::clone(&registry_client),
-        validator_executor.clone(),
-        ingress_sender,
-        ingress_filter,
-        malicious_flags.clone(),
+        CallService::new_service(
+            config.clone(),
+            log.clone(),
+            metrics.clone(),
+            subnet_id,
+            Arc::clone(&registry_client),
+            validator_executor.clone(),
+            ingress_sender,
+            ingress_filter,
+            malicious_flags.clone(),
+        ),
     );
-    let query_service = QueryService::new_service(
-        config.clone(),
-        log.clone(),
+    let query_service = EndpointLimitService::new_service(
+        reloaded_config.clone(),
+        |config| &config.endpoint_limits.query,
+        ApiReqType::Query,
         metrics.clone(),
-        Arc::clone(&health_status),
-        Arc::clone(&delegation_from_nns),
-        validator_executor.clone(),
-        Arc::clone(&registry_client),
-        query_execution_service,
-        malicious_flags.clone(),
+        QueryService::new_service(
+            config.clone(),
+            log.clone(),
+            metrics.clone(),
+            Arc::clone(&health_status),
+            Arc::clone(&delegation_from_nns),
+            validator_executor.clone(),
+            Arc::clone(&registry_client),
+            query_execution_service,
+            malicious_flags.clone(),
+        ),
     );
-    let read_state_service = ReadStateService::new_service(
-        config.clone(),
-        log.clone(),
+    let read_state_service = EndpointLimitService::new_service(
+        reloaded_config.clone(),
+        |config| &config.endpoint_limits.read_state,
+        ApiReqType::ReadState,
         metrics.clone(),
-        Arc::clone(&health_status),
-        Arc::clone(&delegation_from_nns),
-        state_reader_executor.clone(),
-        validator_executor,
-        Arc::clone(&registry_client),
-        malicious_flags,
+        ReadStateService::new_service(
+            config.clone(),
+            log.clone(),
+            metrics.clone(),
+            Arc::clone(&health_status),
+            Arc::clone(&delegation_from_nns),
+            state_reader_executor.clone(),
+            validator_executor,
+            Arc::clone(&registry_client),
+            malicious_flags,
+        ),
     );
     let status_service = StatusService::new_service(
+        config.clone(),
         log.clone(),
         nns_subnet_id,
         Arc::clone(&registry_client),
         Arc::clone(&health_status),
         state_reader_executor.clone(),
     );
+    let query_service = CompressionService::new_service(
+        reloaded_config.clone(),
+        ApiReqType::Query,
+        metrics.clone(),
+        query_service,
+    );
+    let read_state_service = CompressionService::new_service(
+        reloaded_config.clone(),
+        ApiReqType::ReadState,
+        metrics.clone(),
+        read_state_service,
+    );
+    let status_service = CompressionService::new_service(
+        reloaded_config.clone(),
+        ApiReqType::Status,
+        metrics.clone(),
+        status_service,
+    );
     let dashboard_service =
         DashboardService::new_service(config.clone(), subnet_type, state_reader_executor.clone());
     let catchup_service = CatchUpPackageService::new_service(
@@ -333,49 +418,147 @@ pub fn start_server(
         dashboard_service,
         read_state_service,
         health_status_refresher,
+        stream_limiter: StreamLimiter::new(reloaded_config.clone(), metrics.clone()),
+        admin_access: AdminAccess::new(reloaded_config.clone(), metrics.clone(), log.clone()),
+        request_tracer: RequestTracer::new(&rt_handle, reloaded_config.clone(), log.clone()),
     };
-    let main_service = create_main_service(metrics.clone(), config.clone(), http_handler);
+    let main_service = create_main_service(metrics.clone(), reloaded_config.clone(), http_handler);
 
-    let port_file_path = config.port_file_path.clone();
-    // If addr == 0, then a random port will be assigned. In this case it
+    // If the port is 0, then a random port will be assigned. In this case it
     // is useful to report the randomly assigned port by writing it to a file.
-    let local_addr = tcp_listener.local_addr().unwrap();
-    if let Some(path) = port_file_path {
-        create_port_file(path, local_addr.port());
+    let first_tcp_listener =
+        bound_listeners
+            .iter()
+            .find_map(|(_, bound_listener)| match bound_listener {
+                BoundListener::Tcp(tcp_listener) => Some(tcp_listener),
+                BoundListener::Unix(_) => None,
+            });
+    if let (Some(path), Some(tcp_listener)) = (config.port_file_path.clone(), first_tcp_listener) {
+        create_port_file(path, tcp_listener.local_addr().unwrap().port());
+    }
+
+    let (server_handle, shutdown) =
+        shutdown::shutdown_channel(&config, config_reloader, log.clone());
+    for (listener, bound_listener) in bound_listeners {
+        match bound_listener {
+            BoundListener::Tcp(tcp_listener) => serve_tcp_listener(
+                &rt_handle,
+                log.clone(),
+                reloaded_config.clone(),
+                listener,
+                main_service.clone(),
+                tcp_listener,
+                tls_handshake.clone(),
+                tls_acceptor.clone(),
+                registry_client.clone(),
+                metrics.clone(),
+                shutdown.clone(),
+            ),
+            BoundListener::Unix(unix_listener) => serve_unix_listener(
+                &rt_handle,
+                log.clone(),
+                reloaded_config.clone(),
+                listener,
+                main_service.clone(),
+                unix_listener,
+                metrics.clone(),
+                shutdown.clone(),
+            ),
+        }
     }
+    server_handle
+}
+
+enum BoundListener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+fn start_unix_listener(path: &Path) -> UnixListener {
+    // A socket left behind by a previous run would make binding fail.
+    if std::fs::symlink_metadata(path).map_or(false, |metadata| metadata.file_type().is_socket()) {
+        std::fs::remove_file(path).unwrap_or_else(|err| {
+            panic!("Could not remove stale socket {}: {}", path.display(), err)
+        });
+    }
+    UnixListener::bind(path).unwrap_or_else(|err| {
+        panic!(
+            "Could not start Unix domain socket listener at {}: {}",
+            path.display(),
+            err
+        )
+    })
+}
 
+/// Applies the connection limits the listener overrides.
+fn config_for_listener(config: &Config, listener: &Listener) -> Config {
+    let mut config = config.clone();
+    if let Some(max_tcp_connections) = listener.max_tcp_connections {
+        config.max_tcp_connections = max_tcp_connections;
+    }
+    if let Some(connection_read_timeout_seconds) = listener.connection_read_timeout_seconds {
+        config.connection_read_timeout_seconds = connection_read_timeout_seconds;
+    }
+    config
+}
+
+#[allow(clippy::too_many_arguments)]
+fn serve_tcp_listener(
+    rt_handle: &tokio::runtime::Handle,
+    log: ReplicaLogger,
+    config: watch::Receiver<Config>,
+    listener: Listener,
+    main_service: BoxCloneService<Request<Body>, Response<Body>, Infallible>,
+    tcp_listener: TcpListener,
+    tls_handshake: Arc<dyn TlsHandshake + Send + Sync>,
+    tls_acceptor: Option<TlsAcceptor>,
+    registry_client: Arc<dyn RegistryClient>,
+    metrics: HttpHandlerMetrics,
+    shutdown: ShutdownSignal,
+) {
     let metrics_cl = metrics.clone();
     let log_cl = log.clone();
+    let shutdown_cl = shutdown.clone();
+    // The connection limit is not reloadable, the other settings are read
+    // when a connection is accepted.
+    let max_tcp_connections = config_for_listener(&config.borrow(), &listener).max_tcp_connections;
     let conn_svc = ServiceBuilder::new()
         .load_shed()
-        .layer(GlobalConcurrencyLimitLayer::new(config.max_tcp_connections))
+        .layer(GlobalConcurrencyLimitLayer::new(max_tcp_connections))
         .service_fn(move |tcp_stream: TcpStream| {
             handshake_and_serve_connection(
                 log_cl.clone(),
-                config.clone(),
+                config_for_listener(&config.borrow(), &listener),
                 main_service.clone(),
                 tcp_stream,
                 tls_handshake.clone(),
+                tls_acceptor.clone(),
                 registry_client.clone(),
                 metrics_cl.clone(),
+                shutdown_cl.clone(),
             )
         });
     let conn_svc = BoxCloneService::new(conn_svc);
-    rt_handle.clone().spawn(async move {
+    rt_handle.spawn(async move {
         loop {
-            match tcp_listener.accept().await {
+            let accepted = tokio::select! {
+                accepted = tcp_listener.accept() => accepted,
+                // Dropping the listener stops accepting connections.
+                _ = shutdown.clone().shutting_down() => break,
+            };
+            match accepted {
                 Ok((tcp_stream, _)) => {
                     metrics.connections_total.inc();
                     // Start recording connection setup duration.
                     let mut conn_svc = conn_svc.clone();
-                    tokio::spawn(async move {
+                    tokio::spawn(shutdown.clone().run_connection(async move {
                         let _ = conn_svc
                             .ready()
                             .await
                             .expect("The load shedder must always be ready.")
                             .call(tcp_stream)
                             .await;
-                    });
+                    }));
                 }
                 Err(err) => {
                     // Don't exit the loop on a connection error. We will want to
@@ -388,16 +571,100 @@ pub fn start_server(
     });
 }
 
-fn create_main_service(
+/// Serves plain HTTP on a Unix domain socket. The connections are limited
+/// like TCP connections.
+#[allow(clippy::too_many_arguments)]
+fn serve_unix_listener(
+    rt_handle: &tokio::runtime::Handle,
+    log: ReplicaLogger,
+    config: watch::Receiver<Config>,
+    listener: Listener,
+    main_service: BoxCloneService<Request<Body>, Response<Body>, Infallible>,
+    unix_listener: UnixListener,
     metrics: HttpHandlerMetrics,
+    shutdown: ShutdownSignal,
+) {
+    let metrics_cl = metrics.clone();
+    let log_cl = log.clone();
+    let shutdown_cl = shutdown.clone();
+    let max_tcp_connections = config_for_listener(&config.borrow(), &listener).max_tcp_connections;
+    let conn_svc = ServiceBuilder::new()
+        .load_shed()
+        .layer(GlobalConcurrencyLimitLayer::new(max_tcp_connections))
+        .service_fn(move |unix_stream: UnixStream| {
+            serve_unix_connection(
+                log_cl.clone(),
+                config_for_listener(&config.borrow(), &listener),
+                main_service.clone(),
+                unix_stream,
+                metrics_cl.clone(),
+                shutdown_cl.clone(),
+            )
+        });
+    let conn_svc = BoxCloneService::new(conn_svc);
+    rt_handle.spawn(async move {
+        loop {
+            let accepted = tokio::select! {
+                accepted = unix_listener.accept() => accepted,
+                _ = shutdown.clone().shutting_down() => break,
+            };
+            match accepted {
+                Ok((unix_stream, _)) => {
+                    metrics.connections_total.inc();
+                    let mut conn_svc = conn_svc.clone();
+                    tokio::spawn(shutdown.clone().run_connection(async move {
+                        let _ = conn_svc
+                            .ready()
+                            .await
+                            .expect("The load shedder must always be ready.")
+                            .call(unix_stream)
+                            .await;
+                    }));
+                }
+                Err(err) => {
+                    metrics.observe_connection_error(ConnectionError::Accept, Instant::now());
+                    error!(
+                        log,
+                        "Can't accept Unix domain socket connection, error = {}", err
+                    );
+                }
+            }
+        }
+    });
+}
+
+async fn serve_unix_connection(
+    log: ReplicaLogger,
     config: Config,
+    service: BoxCloneService<Request<Body>, Response<Body>, Infallible>,
+    unix_stream: UnixStream,
+    metrics: HttpHandlerMetrics,
+    shutdown: ShutdownSignal,
+) -> Result<(), Infallible> {
+    let connection_start_time = Instant::now();
+    metrics.observe_successful_connection_setup(AppLayer::Http, connection_start_time);
+    let connection_result =
+        serve_connection_with_read_timeout(unix_stream, service, &config, shutdown).await;
+    observe_connection_termination(
+        &log,
+        &metrics,
+        AppLayer::Http,
+        connection_start_time,
+        connection_result,
+    );
+    Ok(())
+}
+
+fn create_main_service(
+    metrics: HttpHandlerMetrics,
+    config: watch::Receiver<Config>,
     http_handler: HttpHandler,
 ) -> BoxCloneService<Request<Body>, Response<Body>, Infallible> {
     let health_status_refresher = http_handler.health_status_refresher.clone();
     let route_service = service_fn(move |req: RequestWithTimer| {
         let http_handler = http_handler.clone();
-        let config = config.clone();
-        async move { Ok::<_, Infallible>(make_router(http_handler, config, req).await) }
+        let request_timeout = Duration::from_secs(config.borrow().request_timeout_seconds);
+        async move { Ok::<_, Infallible>(make_router(http_handler, request_timeout, req).await) }
     });
 
     BoxCloneService::new(
@@ -433,13 +700,23 @@ async fn handshake_and_serve_connection(
     service: BoxCloneService<Request<Body>, Response<Body>, Infallible>,
     tcp_stream: TcpStream,
     tls_handshake: Arc<dyn TlsHandshake + Send + Sync>,
+    tls_acceptor: Option<TlsAcceptor>,
     registry_client: Arc<dyn RegistryClient>,
     metrics: HttpHandlerMetrics,
+    shutdown: ShutdownSignal,
 ) -> Result<(), Infallible> {
     let connection_start_time = Instant::now();
-    let mut http = Http::new();
-    http.http2_max_concurrent_streams(config.http_max_concurrent_streams);
-
+    // Attach the peer address to every request, so that endpoints can apply
+    // per source IP limits.
+    let service = match tcp_stream.peer_addr() {
+        Ok(peer_addr) => {
+            BoxCloneService::new(service.map_request(move |mut req: Request<Body>| {
+                req.extensions_mut().insert(PeerAddr(peer_addr));
+                req
+            }))
+        }
+        Err(_) => service,
+    };
     let mut b = [0_u8; 1];
     let app_layer = match timeout(
         Duration::from_secs(config.max_tcp_peek_timeout_seconds),
@@ -476,13 +753,46 @@ async fn handshake_and_serve_connection(
     let connection_result = match app_layer {
         AppLayer::Https => {
             let peer_addr = tcp_stream.peer_addr();
-            let tls_stream = match tls_handshake
-                .perform_tls_server_handshake_without_client_auth(
-                    tcp_stream,
-                    registry_client.get_latest_version(),
-                )
-                .await
+            let handshake = async {
+                match tls_acceptor {
+                    Some(tls_acceptor) => tls_acceptor
+                        .accept(tcp_stream)
+                        .await
+                        .map(|tls_stream| Box::new(tls_stream) as Box<dyn AsyncStream>)
+                        .map_err(|err| err.to_string()),
+                    None => tls_handshake
+                        .perform_tls_server_handshake_without_client_auth(
+                            tcp_stream,
+                            registry_client.get_latest_version(),
+                        )
+                        .await
+                        .map(|tls_stream| Box::new(tls_stream) as Box<dyn AsyncStream>)
+                        .map_err(|err| err.to_string()),
+                }
+            };
+            let handshake_result = match timeout(
+                Duration::from_secs(config.tls_handshake_timeout_seconds),
+                handshake,
+            )
+            .await
             {
+                Ok(handshake_result) => handshake_result,
+                Err(_) => {
+                    metrics.observe_connection_error(
+                        ConnectionError::TlsHandshakeTimeout,
+                        connection_start_time,
+                    );
+                    metrics.observe_slow_connection_drop(SlowConnectionTimeout::TlsHandshake);
+                    warn!(
+                        log,
+                        "TLS handshake timeout after {}s, peer_addr = {:?}",
+                        config.tls_handshake_timeout_seconds,
+                        peer_addr,
+                    );
+                    return Ok(());
+                }
+            };
+            let tls_stream = match handshake_result {
                 Err(err) => {
                     metrics.observe_connection_error(
                         ConnectionError::TlsHandshake,
@@ -497,26 +807,42 @@ async fn handshake_and_serve_connection(
                 Ok(tls_stream) => tls_stream,
             };
             metrics.observe_successful_connection_setup(app_layer, connection_start_time);
-            serve_connection_with_read_timeout(
-                tls_stream,
-                service,
-                config.connection_read_timeout_seconds,
-            )
-            .await
+            serve_connection_with_read_timeout(tls_stream, service, &config, shutdown).await
         }
         AppLayer::Http => {
             metrics.observe_successful_connection_setup(app_layer, connection_start_time);
-            serve_connection_with_read_timeout(
-                tcp_stream,
-                service,
-                config.connection_read_timeout_seconds,
-            )
-            .await
+            serve_connection_with_read_timeout(tcp_stream, service, &config, shutdown).await
         }
     };
 
+    observe_connection_termination(
+        &log,
+        &metrics,
+        app_layer,
+        connection_start_time,
+        connection_result,
+    );
+    Ok(())
+}
+
+fn observe_connection_termination(
+    log: &ReplicaLogger,
+    metrics: &HttpHandlerMetrics,
+    app_layer: AppLayer,
+    connection_start_time: Instant,
+    connection_result: Result<(), ServeError>,
+) {
     match connection_result {
-        Err(err) => {
+        Err(ServeError::RequestHeaderTimeout) => {
+            metrics.observe_abrupt_conn_termination(app_layer, connection_start_time);
+            metrics.observe_slow_connection_drop(SlowConnectionTimeout::RequestHeader);
+            warn!(
+                log,
+                "No request headers received within {:?}, dropping the connection",
+                connection_start_time.elapsed()
+            );
+        }
+        Err(ServeError::Hyper(err)) => {
             metrics.observe_abrupt_conn_termination(app_layer, connection_start_time);
             info!(
                 log,
@@ -527,19 +853,84 @@ async fn handshake_and_serve_connection(
         }
         Ok(()) => metrics.observe_graceful_conn_termination(app_layer, connection_start_time),
     }
-    Ok(())
 }
 
+/// Why serving a connection failed.
+enum ServeError {
+    Hyper(hyper::Error),
+    /// The headers of a request weren't received within
+    /// `max_request_header_receive_seconds`.
+    RequestHeaderTimeout,
+}
+
+/// A stream a connection can be served on, either plain TCP or TLS.
+trait AsyncStream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> AsyncStream for T {}
+
 async fn serve_connection_with_read_timeout<T: AsyncRead + AsyncWrite + 'static>(
     stream: T,
     metrics_svc: BoxCloneService<Request<Body>, Response<Body>, Infallible>,
-    connection_read_timeout_seconds: u64,
-) -> Result<(), hyper::Error> {
-    let http = Http::new();
+    config: &Config,
+    shutdown: ShutdownSignal,
+) -> Result<(), ServeError> {
+    let header_timeout = Duration::from_secs(config.max_request_header_receive_seconds);
+    // Notified as soon as hyper hands the first request to the service, i.e.
+    // once its headers are received. Until then, the connection may not even
+    // have sent the HTTP/2 preface.
+    let first_request = Arc::new(Notify::new());
+    let metrics_svc = {
+        let first_request = Arc::clone(&first_request);
+        BoxCloneService::new(metrics_svc.map_request(move |req: Request<Body>| {
+            first_request.notify_one();
+            req
+        }))
+    };
+    let mut http = Http::new();
+    http.http2_max_concurrent_streams(config.http_max_concurrent_streams);
+    // Re-armed by hyper for every request of HTTP/1 connections, so that a
+    // client can't trickle the headers of the requests after the first one
+    // either. The headers of an HTTP/2 stream arrive in frames, which are
+    // subject to `connection_read_timeout_seconds`.
+    http.http1_header_read_timeout(header_timeout);
     let mut stream = TimeoutStream::new(stream);
-    stream.set_read_timeout(Some(Duration::from_secs(connection_read_timeout_seconds)));
+    stream.set_read_timeout(Some(Duration::from_secs(
+        config.connection_read_timeout_seconds,
+    )));
     let stream = Box::pin(stream);
-    http.serve_connection(stream, metrics_svc).await
+    let connection = http.serve_connection(stream, metrics_svc);
+    tokio::pin!(connection);
+    let header_deadline = sleep(header_timeout);
+    tokio::select! {
+        result = connection.as_mut() => return result.map_err(into_serve_error),
+        _ = first_request.notified() => {}
+        _ = header_deadline => return Err(ServeError::RequestHeaderTimeout),
+        // Idle connections are closed gracefully right away.
+        _ = shutdown.clone().shutting_down() => {}
+    }
+    tokio::select! {
+        result = connection.as_mut() => return result.map_err(into_serve_error),
+        _ = shutdown.shutting_down() => {}
+    }
+    // Closes HTTP/1 connections after their in-flight request and sends a
+    // `GOAWAY` frame on HTTP/2 connections.
+    connection.as_mut().graceful_shutdown();
+    connection.await.map_err(into_serve_error)
+}
+
+fn into_serve_error(err: hyper::Error) -> ServeError {
+    if is_header_timeout(&err) {
+        ServeError::RequestHeaderTimeout
+    } else {
+        ServeError::Hyper(err)
+    }
+}
+
+/// Hyper 0.14 reports the header timeout of HTTP/1 connections with an error
+/// of its own kind, which `is_timeout` doesn't cover and which isn't exposed
+/// otherwise, so the kind is read from the `Debug` output of the error.
+fn is_header_timeout(err: &hyper::Error) -> bool {
+    err.is_timeout() || format!("{:?}", err) == HYPER_HEADER_TIMEOUT_DEBUG
 }
 
 type RequestWithTimer = (
@@ -553,9 +944,14 @@ type ResponseWithTimer = (
 
 async fn make_router(
     http_handler: HttpHandler,
-    config: Config,
+    request_timeout: Duration,
     (mut req, mut timer): RequestWithTimer,
 ) -> ResponseWithTimer {
+    // Accounted for until the response is returned.
+    let _stream = match http_handler.stream_limiter.try_start(&req) {
+        Ok(stream) => stream,
+        Err(response) => return (response, timer),
+    };
     let call_service = http_handler.call_service.clone();
     let query_service = http_handler.query_service.clone();
     let status_service = http_handler.status_service.clone();
@@ -563,7 +959,7 @@ async fn make_router(
     let dashboard_service = http_handler.dashboard_service.clone();
     let read_state_service = http_handler.read_state_service.clone();
 
-    let svc = match req.method().clone() {
+    let (svc, request_type) = match req.method().clone() {
         Method::POST => {
             // Check the content-type header
             if !req
@@ -589,23 +985,23 @@ async fn make_router(
 
             // Check the path
             let path = req.uri().path();
-            let (svc, effective_canister_id) =
+            let (svc, effective_canister_id, request_type) =
                 match *path.split('/').collect::<Vec<&str>>().as_slice() {
                     ["", "api", "v2", "canister", effective_canister_id, "call"] => {
-                        timer.set_label(LABEL_REQUEST_TYPE, ApiReqType::Call.into());
-                        (call_service, Some(effective_canister_id))
-                    }
-                    ["", "api", "v2", "canister", effective_canister_id, "query"] => {
-                        timer.set_label(LABEL_REQUEST_TYPE, ApiReqType::Query.into());
-                        (query_service, Some(effective_canister_id))
-                    }
-                    ["", "api", "v2", "canister", effective_canister_id, "read_state"] => {
-                        timer.set_label(LABEL_REQUEST_TYPE, ApiReqType::ReadState.into());
-                        (read_state_service, Some(effective_canister_id))
+                        (call_service, Some(effective_canister_id), ApiReqType::Call)
                     }
+                    ["", "api", "v2", "canister", effective_canister_id, "query"] => (
+                        query_service,
+                        Some(effective_canister_id),
+                        ApiReqType::Query,
+                    ),
+                    ["", "api", "v2", "canister", effective_canister_id, "read_state"] => (
+                        read_state_service,
+                        Some(effective_canister_id),
+                        ApiReqType::ReadState,
+                    ),
                     ["", "_", "catch_up_package"] => {
-                        timer.set_label(LABEL_REQUEST_TYPE, ApiReqType::CatchUpPackage.into());
-                        (catch_up_package_service, None)
+                        (catch_up_package_service, None, ApiReqType::CatchUpPackage)
                     }
                     _ => {
                         timer.set_label(LABEL_REQUEST_TYPE, ApiReqType::InvalidArgument.into());
@@ -618,6 +1014,7 @@ async fn make_router(
                         );
                     }
                 };
+            timer.set_label(LABEL_REQUEST_TYPE, request_type.into());
 
             // If url contains effective canister id we attach it to the request.
             if let Some(effective_canister_id) = effective_canister_id {
@@ -639,12 +1036,12 @@ async fn make_router(
                     }
                 }
             }
-            svc
+            (svc, request_type)
         }
         Method::GET => match req.uri().path() {
             "/api/v2/status" => {
                 timer.set_label(LABEL_REQUEST_TYPE, ApiReqType::Status.into());
-                status_service
+                (status_service, ApiReqType::Status)
             }
             "/" | "/_/" => {
                 timer.set_label(LABEL_REQUEST_TYPE, ApiReqType::RedirectToDashboard.into());
@@ -652,18 +1049,38 @@ async fn make_router(
             }
             HTTP_DASHBOARD_URL_PATH => {
                 timer.set_label(LABEL_REQUEST_TYPE, ApiReqType::Dashboard.into());
-                dashboard_service
+                if let Err(response) = http_handler.admin_access.check(&req, ApiReqType::Dashboard)
+                {
+                    return (response, timer);
+                }
+                (dashboard_service, ApiReqType::Dashboard)
             }
             "/_/pprof" => {
                 timer.set_label(LABEL_REQUEST_TYPE, ApiReqType::PprofHome.into());
+                if let Err(response) = http_handler.admin_access.check(&req, ApiReqType::PprofHome)
+                {
+                    return (response, timer);
+                }
                 return (pprof::home(), timer);
             }
             "/_/pprof/profile" => {
                 timer.set_label(LABEL_REQUEST_TYPE, ApiReqType::PprofProfile.into());
+                if let Err(response) = http_handler
+                    .admin_access
+                    .check(&req, ApiReqType::PprofProfile)
+                {
+                    return (response, timer);
+                }
                 return (pprof::cpu_profile(req.into_parts().0).await, timer);
             }
             "/_/pprof/flamegraph" => {
                 timer.set_label(LABEL_REQUEST_TYPE, ApiReqType::PprofFlamegraph.into());
+                if let Err(response) = http_handler
+                    .admin_access
+                    .check(&req, ApiReqType::PprofFlamegraph)
+                {
+                    return (response, timer);
+                }
                 return (pprof::cpu_flamegraph(req.into_parts().0).await, timer);
             }
             _ => {
@@ -695,20 +1112,22 @@ async fn make_router(
             );
         }
     };
+    let span = http_handler.request_tracer.start(&mut req, request_type);
     let mut svc_per_conn = ServiceBuilder::new()
         .load_shed()
-        .timeout(Duration::from_secs(config.request_timeout_seconds))
+        .timeout(request_timeout)
         .service(svc);
-    (
-        svc_per_conn
-            .ready()
-            .await
-            .expect("The load shedder must always be ready.")
-            .call(req)
-            .await
-            .unwrap_or_else(|err| map_box_error_to_response(err)),
-        timer,
-    )
+    let response = svc_per_conn
+        .ready()
+        .await
+        .expect("The load shedder must always be ready.")
+        .call(req)
+        .await
+        .unwrap_or_else(|err| map_box_error_to_response(err));
+    if let Some(span) = span {
+        span.finish(response.status());
+    }
+    (response, timer)
 }
 
 // Fetches a delegation from the NNS subnet to allow this subnet to issue
@@ -1063,4 +1482,44 @@ mod tests {
     fn test_replica_state_atomic() {
         assert!(AtomicCell::<ReplicaHealthStatus>::is_lock_free());
     }
+
+    // Guards the detection of the header timeout against changes of hyper.
+    #[tokio::test]
+    async fn test_recognizes_the_http1_header_timeout() {
+        use tokio::io::AsyncWriteExt;
+
+        let (mut client, server) = tokio::io::duplex(1024);
+        let mut http = Http::new();
+        http.http1_header_read_timeout(Duration::from_millis(100));
+        let connection = http.serve_connection(
+            server,
+            hyper::service::service_fn(|_: Request<Body>| async {
+                Ok::<_, Infallible>(Response::new(Body::empty()))
+            }),
+        );
+        client.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+
+        let err = connection.await.unwrap_err();
+        assert!(matches!(
+            into_serve_error(err),
+            ServeError::RequestHeaderTimeout
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_keeps_other_hyper_errors() {
+        use tokio::io::AsyncWriteExt;
+
+        let (mut client, server) = tokio::io::duplex(1024);
+        let connection = Http::new().serve_connection(
+            server,
+            hyper::service::service_fn(|_: Request<Body>| async {
+                Ok::<_, Infallible>(Response::new(Body::empty()))
+            }),
+        );
+        client.write_all(b"NOT HTTP\r\n\r\n").await.unwrap();
+
+        let err = connection.await.unwrap_err();
+        assert!(matches!(into_serve_error(err), ServeError::Hyper(_)));
+    }
 }
@@ -1,6 +1,6 @@
 use crate::types::*;
 use ic_metrics::{buckets::decimal_buckets, MetricsRegistry};
-use prometheus::{HistogramVec, IntCounter, IntCounterVec};
+use prometheus::{HistogramVec, IntCounter, IntCounterVec, IntGauge};
 use tokio::time::Instant;
 
 pub const LABEL_DETAIL: &str = "detail";
@@ -11,6 +11,9 @@ pub const LABEL_REQUEST_TYPE: &str = "request_type";
 pub const LABEL_STATUS: &str = "status";
 pub const LABEL_HEALTH_STATUS_BEFORE: &str = "before";
 pub const LABEL_HEALTH_STATUS_AFTER: &str = "after";
+pub const LABEL_LIMIT: &str = "limit";
+pub const LABEL_ENCODING: &str = "encoding";
+pub const LABEL_TIMEOUT: &str = "timeout";
 
 /// Placeholder used when we can't determine the approriate prometheus label.
 pub const LABEL_UNKNOWN: &str = "unknown";
@@ -30,6 +33,13 @@ pub(crate) struct HttpHandlerMetrics {
     pub(crate) response_body_size_bytes: HistogramVec,
     pub(crate) connections_total: IntCounter,
     pub(crate) health_status_transitions_total: IntCounterVec,
+    pub(crate) limit_hits_total: IntCounterVec,
+    pub(crate) streams_in_flight: IntGauge,
+    pub(crate) streams_per_ip_limit_hits_total: IntCounter,
+    pub(crate) compressed_responses_total: IntCounterVec,
+    pub(crate) compression_saved_bytes_total: IntCounterVec,
+    pub(crate) admin_access_rejections_total: IntCounterVec,
+    slow_connections_dropped_total: IntCounterVec,
     connection_setup_duration: HistogramVec,
     connection_duration: HistogramVec,
 }
@@ -80,6 +90,39 @@ impl HttpHandlerMetrics {
                 "Number of health status state transitions",
                 &[LABEL_HEALTH_STATUS_BEFORE,LABEL_HEALTH_STATUS_AFTER]
             ),
+            limit_hits_total: metrics_registry.int_counter_vec(
+                "replica_http_limit_hits_total",
+                "Number of requests rejected by an endpoint limit, by request type and limit (concurrency or rate).",
+                &[LABEL_REQUEST_TYPE, LABEL_LIMIT]
+            ),
+            streams_in_flight: metrics_registry.int_gauge(
+                "replica_http_streams_in_flight",
+                "Number of requests in flight over all connections."
+            ),
+            streams_per_ip_limit_hits_total: metrics_registry.int_counter(
+                "replica_http_streams_per_ip_limit_hits_total",
+                "Number of requests rejected because their source IP address had `max_streams_per_ip` requests in flight."
+            ),
+            compressed_responses_total: metrics_registry.int_counter_vec(
+                "replica_http_compressed_responses_total",
+                "Number of compressed responses, by request type and content encoding.",
+                &[LABEL_REQUEST_TYPE, LABEL_ENCODING]
+            ),
+            compression_saved_bytes_total: metrics_registry.int_counter_vec(
+                "replica_http_compression_saved_bytes_total",
+                "Response body bytes saved by compression, by request type and content encoding.",
+                &[LABEL_REQUEST_TYPE, LABEL_ENCODING]
+            ),
+            admin_access_rejections_total: metrics_registry.int_counter_vec(
+                "replica_http_admin_access_rejections_total",
+                "Number of requests to administrative endpoints rejected because their source IP address is not permitted by `admin_access`, by request type.",
+                &[LABEL_REQUEST_TYPE]
+            ),
+            slow_connections_dropped_total: metrics_registry.int_counter_vec(
+                "replica_http_slow_connections_dropped_total",
+                "Number of connections dropped because the client was too slow, by timeout (TLS handshake or request header).",
+                &[LABEL_TIMEOUT]
+            ),
             connection_setup_duration: metrics_registry.histogram_vec(
                 "replica_http_connection_setup_duration_seconds",
                 "HTTP connection setup durations, by status and detail (protocol on status=\"success\", error type on status=\"error\").",
@@ -104,6 +147,13 @@ impl HttpHandlerMetrics {
             .observe(start_time.elapsed().as_secs_f64());
     }
 
+    /// Counts a connection dropped because the client exceeded `timeout`.
+    pub(crate) fn observe_slow_connection_drop(&self, timeout: SlowConnectionTimeout) {
+        self.slow_connections_dropped_total
+            .with_label_values(&[timeout.into()])
+            .inc();
+    }
+
     /// Records the duration of a successful connection setup, by app layer
     /// (protocol).
     pub(crate) fn observe_successful_connection_setup(
@@ -67,7 +67,10 @@ impl QueryService {
         }));
         BoxCloneService::new(
             ServiceBuilder::new()
-                .layer(BodyReceiverLayer::new(&config))
+                .layer(BodyReceiverLayer::new(
+                    &config,
+                    config.max_request_size_bytes.query,
+                ))
                 .service(base_service),
         )
     }
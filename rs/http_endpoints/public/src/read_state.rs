@@ -82,7 +82,10 @@ impl ReadStateService {
         );
         BoxCloneService::new(
             ServiceBuilder::new()
-                .layer(BodyReceiverLayer::new(&config))
+                .layer(BodyReceiverLayer::new(
+                    &config,
+                    config.max_request_size_bytes.read_state,
+                ))
                 .service(base_service),
         )
     }
@@ -0,0 +1,62 @@
+//! Applies reloaded configs to the running HTTP server. Only the
+//! [reloadable fields](ic_config::reloadable::Reloadable::RELOADABLE_FIELDS)
+//! can change; the listeners and services observe them through a watch
+//! channel.
+use ic_config::{
+    http_handler::Config,
+    reloadable::{check_reload, ReloadError},
+};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Replaces the config of the HTTP server started by
+/// [`start_server`](crate::start_server).
+#[derive(Clone)]
+pub struct HttpConfigReloader {
+    config: Arc<watch::Sender<Config>>,
+}
+
+impl HttpConfigReloader {
+    pub(crate) fn new(config: Config) -> (Self, watch::Receiver<Config>) {
+        let (sender, receiver) = watch::channel(config);
+        (
+            Self {
+                config: Arc::new(sender),
+            },
+            receiver,
+        )
+    }
+
+    /// Applies `new`, if it is valid and only the reloadable fields changed.
+    /// Returns whether anything changed.
+    pub fn reload(&self, new: Config) -> Result<bool, ReloadError> {
+        let changed = check_reload(&*self.config.borrow(), &new)?;
+        if changed {
+            self.config.send_replace(new);
+        }
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_reloadable_changes_only() {
+        let (reloader, receiver) = HttpConfigReloader::new(Config::default());
+        let tuned = Config {
+            request_timeout_seconds: 60,
+            ..Config::default()
+        };
+        assert_eq!(reloader.reload(tuned.clone()), Ok(true));
+        assert_eq!(*receiver.borrow(), tuned);
+
+        let restart_required = Config {
+            max_tcp_connections: 1,
+            ..tuned.clone()
+        };
+        assert!(reloader.reload(restart_required).is_err());
+        assert_eq!(*receiver.borrow(), tuned);
+    }
+}
@@ -0,0 +1,524 @@
+//! Traces a sample of the requests according to the [`RequestTracingConfig`].
+//! A span is recorded from the routing of a request until its response, and
+//! exported in batches as [OTLP/HTTP JSON](https://opentelemetry.io/docs/specs/otlp/#otlphttp)
+//! by a background task, or logged if no exporter endpoint is configured.
+//!
+//! With `propagate_trace_context`, the W3C
+//! [trace context](https://www.w3.org/TR/trace-context/) of the caller is
+//! continued, and the `traceparent` header seen by the endpoint services
+//! names the span of the replica as parent. Requests sampled by their caller
+//! draw on the same budget as the ones sampled by the replica, so callers
+//! can't make the replica trace more than `sampled_requests_per_million`.
+//! The trace context of a traced request is also attached to it as an
+//! extension.
+//! The config is reloadable: it is read on every request and export.
+use crate::types::ApiReqType;
+use hyper::{
+    client::HttpConnector,
+    header::{self, HeaderValue},
+    Body, Client, Request, StatusCode,
+};
+use hyper_tls::HttpsConnector;
+use ic_config::http_handler::{Config, RequestTracingConfig};
+use ic_logger::{info, warn, ReplicaLogger};
+use rand::Rng;
+use serde_json::{json, Value};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::{mpsc, watch};
+use url::Url;
+
+const TRACEPARENT: &str = "traceparent";
+const SAMPLING_RESOLUTION: u32 = 1_000_000;
+/// How many traces the sampling budget can save up for a burst of requests
+/// sampled by their callers.
+const MAX_SAMPLING_BURST: u64 = 100;
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+/// `SPAN_KIND_SERVER` of the OTLP protobuf definitions.
+const SPAN_KIND_SERVER: u8 = 2;
+/// `STATUS_CODE_ERROR` of the OTLP protobuf definitions.
+const STATUS_CODE_ERROR: u8 = 2;
+
+/// The W3C trace context of a span.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// Parses a `traceparent` header. Returns `None` if it is invalid, in
+    /// which case the trace is restarted.
+    fn parse(traceparent: &str) -> Option<Self> {
+        let mut fields = traceparent.trim().split('-');
+        let version = fields.next()?;
+        let (trace_id, span_id, flags) = (fields.next()?, fields.next()?, fields.next()?);
+        // Later versions may append fields, version 00 has exactly four.
+        if version.len() != 2 || version == "ff" || (version == "00" && fields.next().is_some()) {
+            return None;
+        }
+        let mut context = Self {
+            trace_id: [0; 16],
+            span_id: [0; 8],
+            sampled: false,
+        };
+        hex::decode_to_slice(trace_id, &mut context.trace_id).ok()?;
+        hex::decode_to_slice(span_id, &mut context.span_id).ok()?;
+        if flags.len() != 2 || context.trace_id == [0; 16] || context.span_id == [0; 8] {
+            return None;
+        }
+        context.sampled = u8::from_str_radix(flags, 16).ok()? & 1 == 1;
+        Some(context)
+    }
+
+    fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            hex::encode(self.trace_id),
+            hex::encode(self.span_id),
+            self.sampled as u8
+        )
+    }
+}
+
+/// A finished span, as exported.
+#[derive(Debug)]
+pub(crate) struct Span {
+    context: TraceContext,
+    parent_span_id: Option<[u8; 8]>,
+    name: &'static str,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(&'static str, String)>,
+    status: StatusCode,
+}
+
+impl Span {
+    fn to_otlp_json(&self) -> Value {
+        let unix_nanos = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos()
+        };
+        let mut attributes: Vec<Value> = self
+            .attributes
+            .iter()
+            .map(|(key, value)| json!({ "key": key, "value": { "stringValue": value } }))
+            .collect();
+        attributes.push(json!({
+            "key": "http.status_code",
+            "value": { "intValue": self.status.as_u16().to_string() },
+        }));
+        let mut span = json!({
+            "traceId": hex::encode(self.context.trace_id),
+            "spanId": hex::encode(self.context.span_id),
+            "name": self.name,
+            "kind": SPAN_KIND_SERVER,
+            "startTimeUnixNano": unix_nanos(self.start).to_string(),
+            "endTimeUnixNano": unix_nanos(self.end).to_string(),
+            "attributes": attributes,
+        });
+        if let Some(parent_span_id) = self.parent_span_id {
+            span["parentSpanId"] = json!(hex::encode(parent_span_id));
+        }
+        if self.status.is_server_error() {
+            span["status"] = json!({ "code": STATUS_CODE_ERROR });
+        }
+        span
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Span {} trace_id = {}, span_id = {}, parent_span_id = {}, duration = {:?}, status = {}",
+            self.name,
+            hex::encode(self.context.trace_id),
+            hex::encode(self.context.span_id),
+            self.parent_span_id.map(hex::encode).unwrap_or_default(),
+            self.end.duration_since(self.start).unwrap_or_default(),
+            self.status.as_u16()
+        )?;
+        for (key, value) in &self.attributes {
+            write!(f, ", {} = {}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// The span of a request that is being traced. It is exported once
+/// [finished](ActiveSpan::finish).
+pub(crate) struct ActiveSpan {
+    span: Span,
+    spans: mpsc::Sender<Span>,
+}
+
+impl ActiveSpan {
+    pub(crate) fn finish(mut self, status: StatusCode) {
+        self.span.end = SystemTime::now();
+        self.span.status = status;
+        // The span is dropped if the exporter is behind.
+        let _ = self.spans.try_send(self.span);
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct RequestTracer {
+    config: watch::Receiver<Config>,
+    spans: mpsc::Sender<Span>,
+    budget: Arc<SamplingBudget>,
+}
+
+impl RequestTracer {
+    /// Starts the export of the spans on `rt_handle`.
+    pub(crate) fn new(
+        rt_handle: &tokio::runtime::Handle,
+        config: watch::Receiver<Config>,
+        log: ReplicaLogger,
+    ) -> Self {
+        let tracing_config = config.borrow().request_tracing.clone();
+        let (spans, receiver) = mpsc::channel(tracing_config.export_batch_size);
+        rt_handle.spawn(export_spans(
+            receiver,
+            config.clone(),
+            log,
+            tracing_config.export_batch_size,
+            Duration::from_secs(tracing_config.export_interval_seconds),
+        ));
+        Self {
+            config,
+            spans,
+            budget: Arc::default(),
+        }
+    }
+
+    /// Decides whether `request` is traced, and if so returns its span,
+    /// named after `request_type`.
+    pub(crate) fn start(
+        &self,
+        request: &mut Request<Body>,
+        request_type: ApiReqType,
+    ) -> Option<ActiveSpan> {
+        let config = self.config.borrow().request_tracing.clone();
+        let caller_context = if config.propagate_trace_context {
+            request
+                .headers()
+                .get(TRACEPARENT)
+                .and_then(|value| value.to_str().ok())
+                .and_then(TraceContext::parse)
+        } else {
+            None
+        };
+        let mut rng = rand::thread_rng();
+        if !is_sampled(&config, caller_context.as_ref(), &self.budget, &mut rng) {
+            return None;
+        }
+
+        let context = TraceContext {
+            trace_id: caller_context.map_or_else(|| rng.gen(), |context| context.trace_id),
+            span_id: rng.gen(),
+            sampled: true,
+        };
+        // The `tracestate` header of the caller is passed on as is.
+        if config.propagate_trace_context {
+            if let Ok(traceparent) = HeaderValue::from_str(&context.traceparent()) {
+                request.headers_mut().insert(TRACEPARENT, traceparent);
+            }
+        }
+        request.extensions_mut().insert(context);
+
+        let mut attributes = vec![
+            ("http.method", request.method().to_string()),
+            ("http.target", request.uri().path().to_string()),
+        ];
+        if let Some(user_agent) = request
+            .headers()
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+        {
+            attributes.push(("http.user_agent", user_agent.to_string()));
+        }
+        let now = SystemTime::now();
+        Some(ActiveSpan {
+            span: Span {
+                context,
+                parent_span_id: caller_context.map(|context| context.span_id),
+                name: request_type.into(),
+                start: now,
+                end: now,
+                attributes,
+                status: StatusCode::OK,
+            },
+            spans: self.spans.clone(),
+        })
+    }
+}
+
+/// The traces the sampling rate allows for, in millionths of a trace: every
+/// request adds `sampled_requests_per_million` and every traced request
+/// takes a million, up to a burst of [`MAX_SAMPLING_BURST`] traces.
+#[derive(Default)]
+struct SamplingBudget {
+    credits: AtomicU64,
+}
+
+impl SamplingBudget {
+    /// Accounts for a request, and takes a trace from the budget if `wanted`
+    /// and it is left. Returns whether the request is traced.
+    fn take(&self, sampled_requests_per_million: u32, wanted: bool) -> bool {
+        let cost = SAMPLING_RESOLUTION as u64;
+        let mut taken = false;
+        // The closure always returns `Some`, so the update can't fail.
+        let _ = self
+            .credits
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |credits| {
+                let credits =
+                    (credits + sampled_requests_per_million as u64).min(MAX_SAMPLING_BURST * cost);
+                taken = wanted && credits >= cost;
+                Some(if taken { credits - cost } else { credits })
+            });
+        taken
+    }
+}
+
+/// A request is traced if tracing is enabled, it is either sampled by the
+/// caller or by the sampling rate, and the sampling budget isn't exhausted.
+fn is_sampled(
+    config: &RequestTracingConfig,
+    caller_context: Option<&TraceContext>,
+    budget: &SamplingBudget,
+    rng: &mut impl Rng,
+) -> bool {
+    if config.sampled_requests_per_million == 0 {
+        return false;
+    }
+    let wanted = caller_context.map_or(false, |context| context.sampled)
+        || rng.gen_range(0..SAMPLING_RESOLUTION) < config.sampled_requests_per_million;
+    budget.take(config.sampled_requests_per_million, wanted)
+}
+
+async fn export_spans(
+    mut spans: mpsc::Receiver<Span>,
+    config: watch::Receiver<Config>,
+    log: ReplicaLogger,
+    batch_size: usize,
+    interval: Duration,
+) {
+    let client: Client<HttpsConnector<HttpConnector>, Body> =
+        Client::builder().build(HttpsConnector::new());
+    let mut interval = tokio::time::interval(interval);
+    let mut batch = Vec::with_capacity(batch_size);
+    loop {
+        tokio::select! {
+            span = spans.recv() => match span {
+                Some(span) => {
+                    batch.push(span);
+                    if batch.len() < batch_size {
+                        continue;
+                    }
+                }
+                None => break,
+            },
+            _ = interval.tick() => {}
+        }
+        if batch.is_empty() {
+            continue;
+        }
+        let batch = std::mem::replace(&mut batch, Vec::with_capacity(batch_size));
+        let endpoint = config.borrow().request_tracing.exporter_endpoint.clone();
+        match endpoint {
+            Some(endpoint) => {
+                if let Err(err) = post_spans(&client, &endpoint, &batch).await {
+                    warn!(
+                        log,
+                        "Failed to export {} spans to {}: {}",
+                        batch.len(),
+                        endpoint,
+                        err
+                    );
+                }
+            }
+            None => {
+                for span in &batch {
+                    info!(log, "{}", span);
+                }
+            }
+        }
+    }
+}
+
+fn otlp_json(spans: &[Span]) -> Value {
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    { "key": "service.name", "value": { "stringValue": "ic-replica" } },
+                ],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "ic-http-endpoints-public" },
+                "spans": spans.iter().map(Span::to_otlp_json).collect::<Vec<_>>(),
+            }],
+        }],
+    })
+}
+
+async fn post_spans(
+    client: &Client<HttpsConnector<HttpConnector>, Body>,
+    endpoint: &Url,
+    spans: &[Span],
+) -> Result<(), String> {
+    let body = serde_json::to_vec(&otlp_json(spans)).map_err(|err| err.to_string())?;
+    let request = Request::post(endpoint.as_str())
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .map_err(|err| err.to_string())?;
+    let response = tokio::time::timeout(EXPORT_TIMEOUT, client.request(request))
+        .await
+        .map_err(|_| format!("timed out after {:?}", EXPORT_TIMEOUT))?
+        .map_err(|err| err.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("the collector responded {}", response.status()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    const TRACEPARENT_EXAMPLE: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn parses_and_renders_traceparent() {
+        let context = TraceContext::parse(TRACEPARENT_EXAMPLE).unwrap();
+        assert!(context.sampled);
+        assert_eq!(hex::encode(context.span_id), "00f067aa0ba902b7");
+        assert_eq!(context.traceparent(), TRACEPARENT_EXAMPLE);
+
+        for invalid in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e47-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-1",
+        ] {
+            assert_eq!(TraceContext::parse(invalid), None, "{}", invalid);
+        }
+        // Fields appended by later versions are ignored.
+        assert!(TraceContext::parse(&format!("01{}-extra", &TRACEPARENT_EXAMPLE[2..])).is_some());
+    }
+
+    #[test]
+    fn samples_according_to_rate_and_caller() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let caller_sampled = TraceContext::parse(TRACEPARENT_EXAMPLE).unwrap();
+        let caller_not_sampled = TraceContext {
+            sampled: false,
+            ..caller_sampled
+        };
+        let config = |sampled_requests_per_million| RequestTracingConfig {
+            sampled_requests_per_million,
+            ..RequestTracingConfig::default()
+        };
+
+        let budget = SamplingBudget::default();
+        assert!(!is_sampled(
+            &config(0),
+            Some(&caller_sampled),
+            &budget,
+            &mut rng
+        ));
+        assert!(is_sampled(
+            &config(SAMPLING_RESOLUTION),
+            Some(&caller_not_sampled),
+            &budget,
+            &mut rng
+        ));
+
+        let budget = SamplingBudget::default();
+        let sampled = (0..10_000)
+            .filter(|_| is_sampled(&config(100_000), None, &budget, &mut rng))
+            .count();
+        assert!((800..1200).contains(&sampled), "{}", sampled);
+    }
+
+    #[test]
+    fn caller_sampled_requests_stay_within_the_budget() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let caller_sampled = TraceContext::parse(TRACEPARENT_EXAMPLE).unwrap();
+        let config = RequestTracingConfig {
+            sampled_requests_per_million: 100_000,
+            ..RequestTracingConfig::default()
+        };
+
+        // Every tenth request can be traced, however many callers sample.
+        let budget = SamplingBudget::default();
+        let sampled = (0..10_000)
+            .filter(|_| is_sampled(&config, Some(&caller_sampled), &budget, &mut rng))
+            .count();
+        assert_eq!(sampled, 1_000);
+
+        // Unused budget is saved up for a burst, but only up to a limit.
+        let budget = SamplingBudget::default();
+        for _ in 0..100_000 {
+            assert!(!budget.take(config.sampled_requests_per_million, false));
+        }
+        let burst = (0..1_000).take_while(|_| budget.take(0, true)).count();
+        assert_eq!(burst as u64, MAX_SAMPLING_BURST);
+    }
+
+    #[tokio::test]
+    async fn traces_requests_continuing_the_callers_trace() {
+        let config = Config {
+            request_tracing: RequestTracingConfig {
+                sampled_requests_per_million: 1,
+                propagate_trace_context: true,
+                ..RequestTracingConfig::default()
+            },
+            ..Config::default()
+        };
+        let (_sender, config) = watch::channel(config);
+        let (spans, mut receiver) = mpsc::channel(1);
+        let tracer = RequestTracer {
+            config,
+            spans,
+            budget: Arc::default(),
+        };
+        // A budget saved up by earlier requests.
+        for _ in 0..SAMPLING_RESOLUTION {
+            tracer.budget.take(1, false);
+        }
+        let mut request = Request::post("/api/v2/canister/aaaaa-aa/query")
+            .header(TRACEPARENT, TRACEPARENT_EXAMPLE)
+            .body(Body::empty())
+            .unwrap();
+
+        let span = tracer.start(&mut request, ApiReqType::Query).unwrap();
+        let context = *request.extensions().get::<TraceContext>().unwrap();
+        assert_eq!(
+            request.headers().get(TRACEPARENT).unwrap(),
+            &context.traceparent()
+        );
+        span.finish(StatusCode::SERVICE_UNAVAILABLE);
+
+        let span = receiver.recv().await.unwrap().to_otlp_json();
+        assert_eq!(span["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(span["parentSpanId"], "00f067aa0ba902b7");
+        assert_eq!(span["spanId"], hex::encode(context.span_id));
+        assert_eq!(span["name"], "query");
+        assert_eq!(span["status"]["code"], STATUS_CODE_ERROR);
+    }
+}
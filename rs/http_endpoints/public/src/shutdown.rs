@@ -0,0 +1,224 @@
+//! Graceful shutdown of the HTTP server. On shutdown, the listeners stop
+//! accepting connections and the open connections are drained: they are
+//! closed once their in-flight requests are completed. Connections that are
+//! still open after the grace period are aborted.
+use crate::reload::HttpConfigReloader;
+use ic_config::http_handler::Config;
+use ic_logger::{info, ReplicaLogger};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ServerState {
+    Running,
+    Draining,
+    Aborting,
+}
+
+/// The number of connections that were open when the shutdown started, by
+/// whether they completed within the grace period.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DrainReport {
+    pub drained: usize,
+    pub aborted: usize,
+}
+
+/// Shuts down the HTTP server started by [`start_server`](crate::start_server).
+pub struct HttpServerHandle {
+    state: watch::Sender<ServerState>,
+    open_connections: watch::Receiver<usize>,
+    grace_period: Duration,
+    drain_connections: bool,
+    config_reloader: HttpConfigReloader,
+    log: ReplicaLogger,
+}
+
+/// Lets the listeners and connections observe the shutdown.
+#[derive(Clone)]
+pub(crate) struct ShutdownSignal {
+    state: watch::Receiver<ServerState>,
+    open_connections: Arc<watch::Sender<usize>>,
+}
+
+/// Tracks an open connection until it is dropped.
+pub(crate) struct ConnectionGuard {
+    open_connections: Arc<watch::Sender<usize>>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.open_connections.send_modify(|open| *open -= 1);
+    }
+}
+
+pub(crate) fn shutdown_channel(
+    config: &Config,
+    config_reloader: HttpConfigReloader,
+    log: ReplicaLogger,
+) -> (HttpServerHandle, ShutdownSignal) {
+    let (state_sender, state) = watch::channel(ServerState::Running);
+    let (open_connections_sender, open_connections) = watch::channel(0);
+    (
+        HttpServerHandle {
+            state: state_sender,
+            open_connections,
+            grace_period: Duration::from_secs(config.shutdown_grace_period_seconds),
+            drain_connections: config.drain_connections_on_shutdown,
+            config_reloader,
+            log,
+        },
+        ShutdownSignal {
+            state,
+            open_connections: Arc::new(open_connections_sender),
+        },
+    )
+}
+
+impl ShutdownSignal {
+    async fn wait_for(&mut self, predicate: impl Fn(ServerState) -> bool) {
+        while !predicate(*self.state.borrow()) {
+            if self.state.changed().await.is_err() {
+                // The handle was dropped, so the server is never shut down.
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+
+    /// Completes when the listeners should stop accepting connections and
+    /// the open connections should be closed gracefully.
+    pub(crate) async fn shutting_down(mut self) {
+        self.wait_for(|state| state != ServerState::Running).await
+    }
+
+    /// Completes when the open connections should be aborted.
+    pub(crate) async fn aborting(mut self) {
+        self.wait_for(|state| state == ServerState::Aborting).await
+    }
+
+    fn track_connection(&self) -> ConnectionGuard {
+        self.open_connections.send_modify(|open| *open += 1);
+        ConnectionGuard {
+            open_connections: Arc::clone(&self.open_connections),
+        }
+    }
+
+    /// Tracks a newly accepted connection and runs `connection` until it
+    /// completes or the connections are aborted.
+    pub(crate) fn run_connection(
+        self,
+        connection: impl Future<Output = ()>,
+    ) -> impl Future<Output = ()> {
+        let guard = self.track_connection();
+        async move {
+            let _guard = guard;
+            tokio::select! {
+                _ = connection => {}
+                _ = self.aborting() => {}
+            }
+        }
+    }
+}
+
+impl HttpServerHandle {
+    /// Returns a handle to reload the config of the running server.
+    pub fn config_reloader(&self) -> HttpConfigReloader {
+        self.config_reloader.clone()
+    }
+
+    /// Stops accepting connections and drains the open ones. Returns once all
+    /// connections are closed.
+    pub async fn shutdown(mut self) -> DrainReport {
+        let open_at_start = *self.open_connections.borrow();
+        info!(
+            self.log,
+            "Shutting down the HTTP server with {} open connections", open_at_start
+        );
+        if self.drain_connections {
+            self.state.send_replace(ServerState::Draining);
+            let _ = tokio::time::timeout(self.grace_period, self.all_connections_closed()).await;
+        }
+        let aborted = *self.open_connections.borrow();
+        self.state.send_replace(ServerState::Aborting);
+        self.all_connections_closed().await;
+
+        let report = DrainReport {
+            drained: open_at_start.saturating_sub(aborted),
+            aborted,
+        };
+        info!(
+            self.log,
+            "HTTP server shut down: {} connections drained, {} aborted",
+            report.drained,
+            report.aborted
+        );
+        report
+    }
+
+    async fn all_connections_closed(&mut self) {
+        while *self.open_connections.borrow_and_update() > 0 {
+            if self.open_connections.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_logger::replica_logger::no_op_logger;
+
+    #[tokio::test]
+    async fn drains_idle_connections_and_aborts_the_rest() {
+        let config = Config {
+            shutdown_grace_period_seconds: 1,
+            ..Default::default()
+        };
+        let (config_reloader, _) = HttpConfigReloader::new(config.clone());
+        let (handle, signal) = shutdown_channel(&config, config_reloader, no_op_logger());
+        // Closes as soon as it is asked to.
+        let idle = tokio::spawn(
+            signal
+                .clone()
+                .run_connection(signal.clone().shutting_down()),
+        );
+        // Never completes on its own, e.g. a long running request.
+        let busy = tokio::spawn(signal.clone().run_connection(std::future::pending()));
+
+        assert_eq!(
+            handle.shutdown().await,
+            DrainReport {
+                drained: 1,
+                aborted: 1
+            }
+        );
+        idle.await.unwrap();
+        busy.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn aborts_all_connections_without_draining() {
+        let config = Config {
+            drain_connections_on_shutdown: false,
+            ..Default::default()
+        };
+        let (config_reloader, _) = HttpConfigReloader::new(config.clone());
+        let (handle, signal) = shutdown_channel(&config, config_reloader, no_op_logger());
+        let idle = tokio::spawn(
+            signal
+                .clone()
+                .run_connection(signal.clone().shutting_down()),
+        );
+
+        assert_eq!(
+            handle.shutdown().await,
+            DrainReport {
+                drained: 0,
+                aborted: 1
+            }
+        );
+        idle.await.unwrap();
+    }
+}
@@ -1,8 +1,11 @@
 //! Module that deals with requests to /api/v2/status
-use crate::{common, state_reader_executor::StateReaderExecutor, EndpointService};
+use crate::{
+    body::BodyReceiverLayer, common, state_reader_executor::StateReaderExecutor, EndpointService,
+};
 use crossbeam::atomic::AtomicCell;
 use http::Request;
 use hyper::{Body, Response};
+use ic_config::http_handler::Config;
 use ic_crypto_utils_threshold_sig_der::public_key_to_der;
 use ic_interfaces_registry::RegistryClient;
 use ic_logger::{warn, ReplicaLogger};
@@ -11,13 +14,13 @@ use ic_types::{
     replica_version::REPLICA_BINARY_HASH,
     ReplicaVersion, SubnetId,
 };
+use std::convert::Infallible;
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
 use tower::{
-    limit::concurrency::GlobalConcurrencyLimitLayer, util::BoxCloneService, BoxError, Service,
-    ServiceBuilder,
+    limit::concurrency::GlobalConcurrencyLimitLayer, util::BoxCloneService, Service, ServiceBuilder,
 };
 
 // TODO(NET-776)
@@ -36,6 +39,7 @@ pub(crate) struct StatusService {
 
 impl StatusService {
     pub(crate) fn new_service(
+        config: Config,
         log: ReplicaLogger,
         nns_subnet_id: SubnetId,
         registry_client: Arc<dyn RegistryClient>,
@@ -49,27 +53,35 @@ impl StatusService {
             replica_health_status,
             state_read_executor,
         };
-        BoxCloneService::new(
+        let base_service = BoxCloneService::new(
             ServiceBuilder::new()
                 .layer(GlobalConcurrencyLimitLayer::new(
                     MAX_STATUS_CONCURRENT_REQUESTS,
                 ))
                 .service(base_service),
+        );
+        BoxCloneService::new(
+            ServiceBuilder::new()
+                .layer(BodyReceiverLayer::new(
+                    &config,
+                    config.max_request_size_bytes.status,
+                ))
+                .service(base_service),
         )
     }
 }
 
-impl Service<Request<Body>> for StatusService {
+impl Service<Request<Vec<u8>>> for StatusService {
     type Response = Response<Body>;
-    type Error = BoxError;
+    type Error = Infallible;
     #[allow(clippy::type_complexity)]
-    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + Sync>>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
 
     fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         Poll::Ready(Ok(()))
     }
 
-    fn call(&mut self, _unused: Request<Body>) -> Self::Future {
+    fn call(&mut self, _unused: Request<Vec<u8>>) -> Self::Future {
         let log = self.log.clone();
         let nns_subnet_id = self.nns_subnet_id;
         let replica_health_status = self.replica_health_status.clone();
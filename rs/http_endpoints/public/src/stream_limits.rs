@@ -0,0 +1,144 @@
+//! Accounts for the requests in flight (HTTP/2 streams or HTTP/1 requests)
+//! per source IP address across all of its connections, and sheds the
+//! requests of an address exceeding `max_streams_per_ip` with
+//! `429 Too Many Requests`. Without this limit, a single client could use up
+//! `http_max_concurrent_streams` on each of many connections.
+//! The limit is reloadable: it is read on every request.
+use crate::{
+    common::make_overloaded_response, endpoint_limits::PeerAddr, metrics::HttpHandlerMetrics,
+};
+use hyper::{Body, Request, Response};
+use ic_config::http_handler::Config;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::watch;
+
+#[derive(Clone)]
+pub(crate) struct StreamLimiter {
+    config: watch::Receiver<Config>,
+    streams_per_ip: Arc<Mutex<HashMap<IpAddr, usize>>>,
+    metrics: HttpHandlerMetrics,
+}
+
+/// A request in flight, accounted for until dropped.
+pub(crate) struct Stream {
+    ip: Option<IpAddr>,
+    streams_per_ip: Arc<Mutex<HashMap<IpAddr, usize>>>,
+    metrics: HttpHandlerMetrics,
+}
+
+impl StreamLimiter {
+    pub(crate) fn new(config: watch::Receiver<Config>, metrics: HttpHandlerMetrics) -> Self {
+        Self {
+            config,
+            streams_per_ip: Arc::new(Mutex::new(HashMap::new())),
+            metrics,
+        }
+    }
+
+    /// Accounts for `request` until the returned [`Stream`] is dropped, or
+    /// returns the response to shed it with if its source IP address already
+    /// has `max_streams_per_ip` requests in flight. Requests without a peer
+    /// address, i.e. received on a Unix domain socket, are never shed.
+    pub(crate) fn try_start(&self, request: &Request<Body>) -> Result<Stream, Response<Body>> {
+        let ip = request
+            .extensions()
+            .get::<PeerAddr>()
+            .map(|PeerAddr(peer_addr)| peer_addr.ip());
+        if let Some(ip) = ip {
+            let (max_streams_per_ip, retry_after_seconds) = {
+                let config = self.config.borrow();
+                (
+                    config.max_streams_per_ip,
+                    config.overload_retry_after_seconds,
+                )
+            };
+            let mut streams_per_ip = self.streams_per_ip.lock().unwrap();
+            let streams = streams_per_ip.entry(ip).or_insert(0);
+            if max_streams_per_ip.map_or(false, |max| *streams >= max) {
+                self.metrics.streams_per_ip_limit_hits_total.inc();
+                return Err(make_overloaded_response(
+                    "Too many concurrent requests from this IP address.".to_string(),
+                    retry_after_seconds,
+                ));
+            }
+            *streams += 1;
+        }
+        self.metrics.streams_in_flight.inc();
+        Ok(Stream {
+            ip,
+            streams_per_ip: self.streams_per_ip.clone(),
+            metrics: self.metrics.clone(),
+        })
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        self.metrics.streams_in_flight.dec();
+        if let Some(ip) = self.ip {
+            let mut streams_per_ip = self.streams_per_ip.lock().unwrap();
+            if let Some(streams) = streams_per_ip.get_mut(&ip) {
+                *streams -= 1;
+                if *streams == 0 {
+                    streams_per_ip.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::{header, StatusCode};
+    use ic_metrics::MetricsRegistry;
+
+    fn request_from(peer_addr: &str) -> Request<Body> {
+        let mut request = Request::new(Body::empty());
+        request
+            .extensions_mut()
+            .insert(PeerAddr(peer_addr.parse().unwrap()));
+        request
+    }
+
+    #[test]
+    fn sheds_requests_above_the_limit_per_ip() {
+        let (sender, config) = watch::channel(Config {
+            max_streams_per_ip: Some(2),
+            overload_retry_after_seconds: 5,
+            ..Config::default()
+        });
+        let metrics = HttpHandlerMetrics::new(&MetricsRegistry::new());
+        let limiter = StreamLimiter::new(config, metrics.clone());
+
+        let first = limiter.try_start(&request_from("10.0.0.1:1000")).unwrap();
+        // From another connection of the same address.
+        let _second = limiter.try_start(&request_from("10.0.0.1:2000")).unwrap();
+        let response = limiter
+            .try_start(&request_from("10.0.0.1:3000"))
+            .err()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "5");
+        assert_eq!(metrics.streams_per_ip_limit_hits_total.get(), 1);
+
+        // Other addresses and Unix domain socket clients are not affected.
+        let _other = limiter.try_start(&request_from("10.0.0.2:1000")).unwrap();
+        let _unix = limiter.try_start(&Request::new(Body::empty())).unwrap();
+        assert_eq!(metrics.streams_in_flight.get(), 4);
+
+        drop(first);
+        let _third = limiter.try_start(&request_from("10.0.0.1:3000")).unwrap();
+
+        sender.send_modify(|config| config.max_streams_per_ip = None);
+        let _fourth = limiter.try_start(&request_from("10.0.0.1:4000")).unwrap();
+        assert_eq!(
+            limiter.streams_per_ip.lock().unwrap()[&"10.0.0.1".parse::<IpAddr>().unwrap()],
+            3
+        );
+    }
+}
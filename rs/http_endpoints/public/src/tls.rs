@@ -0,0 +1,243 @@
+//! Serves HTTPS with a certificate and private key loaded from PEM files (see
+//! [`TlsCertificateSource::Files`](ic_config::http_handler::TlsCertificateSource)).
+//! The files are polled for changes, so that a renewed certificate is picked
+//! up without restarting the replica.
+use ic_config::http_handler::{TlsConfig, TlsVersion};
+use ic_logger::{info, warn, ReplicaLogger};
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+use tokio_rustls::{
+    rustls::{
+        server::{ClientHello, ResolvesServerCert},
+        sign::{any_supported_type, CertifiedKey},
+        version::{TLS12, TLS13},
+        Certificate, PrivateKey, ServerConfig, SupportedProtocolVersion,
+    },
+    TlsAcceptor,
+};
+
+/// Resolves every handshake to the certificate loaded from files.
+pub(crate) struct FileCertResolver {
+    certificate_path: PathBuf,
+    private_key_path: PathBuf,
+    loaded: RwLock<LoadedKey>,
+}
+
+struct LoadedKey {
+    key: Arc<CertifiedKey>,
+    modified: [Option<SystemTime>; 2],
+}
+
+impl FileCertResolver {
+    pub(crate) fn new(certificate_path: &Path, private_key_path: &Path) -> Result<Self, String> {
+        let modified = modification_times(certificate_path, private_key_path);
+        let key = load_certified_key(certificate_path, private_key_path)?;
+        Ok(Self {
+            certificate_path: certificate_path.to_path_buf(),
+            private_key_path: private_key_path.to_path_buf(),
+            loaded: RwLock::new(LoadedKey {
+                key: Arc::new(key),
+                modified,
+            }),
+        })
+    }
+
+    /// Reloads the certificate if either file was modified since it was last
+    /// loaded. Returns whether the certificate was reloaded. If loading fails,
+    /// the previous certificate stays in use.
+    pub(crate) fn reload_if_changed(&self) -> Result<bool, String> {
+        let modified = modification_times(&self.certificate_path, &self.private_key_path);
+        if self.loaded.read().unwrap().modified == modified {
+            return Ok(false);
+        }
+        let key = load_certified_key(&self.certificate_path, &self.private_key_path)?;
+        *self.loaded.write().unwrap() = LoadedKey {
+            key: Arc::new(key),
+            modified,
+        };
+        Ok(true)
+    }
+}
+
+impl ResolvesServerCert for FileCertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(Arc::clone(&self.loaded.read().unwrap().key))
+    }
+}
+
+fn modification_times(certificate_path: &Path, private_key_path: &Path) -> [Option<SystemTime>; 2] {
+    [certificate_path, private_key_path]
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+}
+
+fn load_certified_key(
+    certificate_path: &Path,
+    private_key_path: &Path,
+) -> Result<CertifiedKey, String> {
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|err| format!("Failed to open {}: {}", path.display(), err))
+    };
+
+    let certificates = rustls_pemfile::certs(&mut open(certificate_path)?).map_err(|err| {
+        format!(
+            "Failed to read certificates from {}: {}",
+            certificate_path.display(),
+            err
+        )
+    })?;
+    if certificates.is_empty() {
+        return Err(format!(
+            "No certificate found in {}",
+            certificate_path.display()
+        ));
+    }
+
+    let private_key = rustls_pemfile::read_all(&mut open(private_key_path)?)
+        .map_err(|err| {
+            format!(
+                "Failed to read private key from {}: {}",
+                private_key_path.display(),
+                err
+            )
+        })?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(key),
+            _ => None,
+        })
+        .ok_or_else(|| format!("No private key found in {}", private_key_path.display()))?;
+    let signing_key = any_supported_type(&PrivateKey(private_key))
+        .map_err(|err| format!("Unsupported private key: {}", err))?;
+
+    Ok(CertifiedKey::new(
+        certificates.into_iter().map(Certificate).collect(),
+        signing_key,
+    ))
+}
+
+pub(crate) fn server_config(
+    tls_config: &TlsConfig,
+    cert_resolver: Arc<dyn ResolvesServerCert>,
+) -> Result<ServerConfig, String> {
+    let versions: &[&'static SupportedProtocolVersion] = match tls_config.min_tls_version {
+        TlsVersion::Tls12 => &[&TLS13, &TLS12],
+        TlsVersion::Tls13 => &[&TLS13],
+    };
+    let mut config = ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(versions)
+        .map_err(|err| err.to_string())?
+        .with_no_client_auth()
+        .with_cert_resolver(cert_resolver);
+    config.alpn_protocols = tls_config
+        .alpn_protocols
+        .iter()
+        .map(|protocol| protocol.as_bytes().to_vec())
+        .collect();
+    Ok(config)
+}
+
+/// Creates the acceptor for HTTPS connections using the certificate files and
+/// spawns a task that reloads them when they change.
+pub(crate) fn start_file_tls_acceptor(
+    rt_handle: &tokio::runtime::Handle,
+    log: ReplicaLogger,
+    tls_config: &TlsConfig,
+    certificate_path: &Path,
+    private_key_path: &Path,
+) -> TlsAcceptor {
+    let cert_resolver = Arc::new(
+        FileCertResolver::new(certificate_path, private_key_path)
+            .unwrap_or_else(|err| panic!("Failed to load the TLS certificate: {}", err)),
+    );
+    let config = server_config(tls_config, cert_resolver.clone())
+        .unwrap_or_else(|err| panic!("Invalid TLS config: {}", err));
+
+    let reload_interval = Duration::from_secs(tls_config.certificate_reload_interval_seconds);
+    rt_handle.spawn(async move {
+        let mut interval = tokio::time::interval(reload_interval);
+        loop {
+            interval.tick().await;
+            match cert_resolver.reload_if_changed() {
+                Ok(true) => info!(log, "Reloaded the TLS certificate."),
+                Ok(false) => {}
+                Err(err) => warn!(
+                    log,
+                    "Failed to reload the TLS certificate, keeping the previous one: {}", err
+                ),
+            }
+        }
+    });
+
+    TlsAcceptor::from(Arc::new(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_self_signed_certificate(certificate_path: &Path, private_key_path: &Path) {
+        let certificate = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+            .expect("Failed to generate certificate");
+        std::fs::write(certificate_path, certificate.serialize_pem().unwrap()).unwrap();
+        std::fs::write(private_key_path, certificate.serialize_private_key_pem()).unwrap();
+    }
+
+    #[test]
+    fn reloads_certificate_when_files_change() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let certificate_path = tmp_dir.path().join("cert.pem");
+        let private_key_path = tmp_dir.path().join("key.pem");
+        write_self_signed_certificate(&certificate_path, &private_key_path);
+
+        let resolver = FileCertResolver::new(&certificate_path, &private_key_path).unwrap();
+        let initial_certificate = resolver.loaded.read().unwrap().key.cert.clone();
+        assert_eq!(resolver.reload_if_changed(), Ok(false));
+
+        // Make sure the modification times differ even on coarse-grained file systems.
+        std::thread::sleep(Duration::from_millis(10));
+        write_self_signed_certificate(&certificate_path, &private_key_path);
+        assert_eq!(resolver.reload_if_changed(), Ok(true));
+        let reloaded_certificate = resolver.loaded.read().unwrap().key.cert.clone();
+        assert_ne!(reloaded_certificate, initial_certificate);
+
+        // A broken key does not replace the loaded certificate.
+        std::thread::sleep(Duration::from_millis(10));
+        std::fs::write(&private_key_path, "not a key").unwrap();
+        assert!(resolver.reload_if_changed().is_err());
+        assert_eq!(
+            resolver.loaded.read().unwrap().key.cert,
+            reloaded_certificate
+        );
+    }
+
+    #[test]
+    fn server_config_respects_alpn_protocols() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let certificate_path = tmp_dir.path().join("cert.pem");
+        let private_key_path = tmp_dir.path().join("key.pem");
+        write_self_signed_certificate(&certificate_path, &private_key_path);
+        let resolver = FileCertResolver::new(&certificate_path, &private_key_path).unwrap();
+
+        let tls_config = TlsConfig {
+            min_tls_version: TlsVersion::Tls12,
+            alpn_protocols: vec!["h2".to_string(), "http/1.1".to_string()],
+            ..TlsConfig::default()
+        };
+        let config = server_config(&tls_config, Arc::new(resolver)).unwrap();
+        assert_eq!(
+            config.alpn_protocols,
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        );
+    }
+}
@@ -40,6 +40,15 @@ pub(crate) enum ConnectionError {
     Accept,
     Peek,
     PeekTimeout,
+    TlsHandshakeTimeout,
+}
+
+/// The timeouts against slow clients that drop a connection.
+#[derive(Clone, Copy, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub(crate) enum SlowConnectionTimeout {
+    TlsHandshake,
+    RequestHeader,
 }
 
 #[cfg(test)]
@@ -86,5 +95,18 @@ mod tests {
             StaticStr::from(ConnectionError::PeekTimeout),
             "peek_timeout"
         );
+        assert_eq!(
+            StaticStr::from(ConnectionError::TlsHandshakeTimeout),
+            "tls_handshake_timeout"
+        );
+
+        assert_eq!(
+            StaticStr::from(SlowConnectionTimeout::TlsHandshake),
+            "tls_handshake"
+        );
+        assert_eq!(
+            StaticStr::from(SlowConnectionTimeout::RequestHeader),
+            "request_header"
+        );
     }
 }
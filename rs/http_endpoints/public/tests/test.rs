@@ -69,6 +69,7 @@ use std::{
     },
 };
 use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
     net::{TcpSocket, TcpStream},
     runtime::Runtime,
     time::{sleep, Duration},
@@ -164,7 +165,7 @@ fn test_healthy_behind() {
     let rt = Runtime::new().unwrap();
     let addr = get_free_localhost_socket_addr();
     let config = Config {
-        listen_addr: addr,
+        listeners: vec![addr.into()],
         ..Default::default()
     };
     let certified_state_height = Height::from(1);
@@ -323,7 +324,7 @@ fn test_unathorized_controller() {
     let rt = Runtime::new().unwrap();
     let addr = get_free_localhost_socket_addr();
     let config = Config {
-        listen_addr: addr,
+        listeners: vec![addr.into()],
         ..Default::default()
     };
 
@@ -388,7 +389,7 @@ fn test_unathorized_query() {
     let rt = Runtime::new().unwrap();
     let addr = get_free_localhost_socket_addr();
     let config = Config {
-        listen_addr: addr,
+        listeners: vec![addr.into()],
         ..Default::default()
     };
 
@@ -477,7 +478,7 @@ fn test_unathorized_call() {
     let rt = Runtime::new().unwrap();
     let addr = get_free_localhost_socket_addr();
     let config = Config {
-        listen_addr: addr,
+        listeners: vec![addr.into()],
         ..Default::default()
     };
 
@@ -586,7 +587,7 @@ async fn test_max_tcp_connections() {
     let rt_handle = tokio::runtime::Handle::current();
     let addr = get_free_localhost_socket_addr();
     let config = Config {
-        listen_addr: addr,
+        listeners: vec![addr.into()],
         max_tcp_connections: 50,
         ..Default::default()
     };
@@ -629,7 +630,7 @@ async fn test_connection_read_timeout() {
     let rt_handle = tokio::runtime::Handle::current();
     let addr = get_free_localhost_socket_addr();
     let config = Config {
-        listen_addr: addr,
+        listeners: vec![addr.into()],
         max_tcp_connections: 50,
         connection_read_timeout_seconds: 2,
         ..Default::default()
@@ -658,6 +659,123 @@ async fn test_connection_read_timeout() {
     assert!(request_sender.ready().await.err().unwrap().is_closed());
 }
 
+/// If the headers of the first request aren't received within
+/// 'max_request_header_receive_seconds', then the connection is dropped, even
+/// though the client keeps sending bytes.
+#[tokio::test]
+async fn test_request_header_receive_timeout() {
+    let rt_handle = tokio::runtime::Handle::current();
+    let addr = get_free_localhost_socket_addr();
+    let config = Config {
+        listeners: vec![addr.into()],
+        max_request_header_receive_seconds: 2,
+        ..Default::default()
+    };
+
+    let mock_state_manager = basic_state_manager_mock();
+    let mock_consensus_cache = basic_consensus_pool_cache();
+    let mock_registry_client = basic_registry_client();
+
+    // Start server
+    start_http_endpoint(
+        rt_handle.clone(),
+        config.clone(),
+        Arc::new(mock_state_manager),
+        Arc::new(mock_consensus_cache),
+        Arc::new(mock_registry_client),
+    );
+
+    // Complete requests are served as usual.
+    let (_request_sender, status_code) = create_conn_and_send_request(addr).await;
+    assert!(status_code == StatusCode::OK);
+
+    // Trickle the headers, one line per second.
+    let mut stream = TcpStream::connect(addr)
+        .await
+        .expect("tcp connection to server address failed");
+    let mut header_lines = ["GET /api/v2/status HTTP/1.1\r\n"]
+        .into_iter()
+        .chain(std::iter::repeat("X-Slow: 1\r\n"));
+    let mut buf = [0_u8; 1];
+    let start = std::time::Instant::now();
+    let closed = loop {
+        let line = header_lines.next().unwrap();
+        if stream.write_all(line.as_bytes()).await.is_err() {
+            break true;
+        }
+        match tokio::time::timeout(Duration::from_secs(1), stream.read(&mut buf)).await {
+            Ok(Ok(0)) | Ok(Err(_)) => break true,
+            Ok(Ok(_)) => panic!("Received a response to incomplete headers"),
+            Err(_) if start.elapsed() > Duration::from_secs(10) => break false,
+            Err(_) => {}
+        }
+    };
+    assert!(closed);
+}
+
+/// The headers of the requests following the first one of an HTTP/1
+/// connection are subject to 'max_request_header_receive_seconds' as well.
+#[tokio::test]
+async fn test_request_header_receive_timeout_applies_to_every_request() {
+    let rt_handle = tokio::runtime::Handle::current();
+    let addr = get_free_localhost_socket_addr();
+    let config = Config {
+        listeners: vec![addr.into()],
+        max_request_header_receive_seconds: 2,
+        ..Default::default()
+    };
+
+    let mock_state_manager = basic_state_manager_mock();
+    let mock_consensus_cache = basic_consensus_pool_cache();
+    let mock_registry_client = basic_registry_client();
+
+    // Start server
+    start_http_endpoint(
+        rt_handle.clone(),
+        config.clone(),
+        Arc::new(mock_state_manager),
+        Arc::new(mock_consensus_cache),
+        Arc::new(mock_registry_client),
+    );
+
+    // Send a complete request and drain its response.
+    let mut stream = TcpStream::connect(addr)
+        .await
+        .expect("tcp connection to server address failed");
+    stream
+        .write_all(b"GET /api/v2/status HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut buf = [0_u8; 4096];
+    let mut received = 0;
+    while let Ok(Ok(n)) =
+        tokio::time::timeout(Duration::from_millis(500), stream.read(&mut buf)).await
+    {
+        assert!(n > 0, "The connection was closed after the first request");
+        received += n;
+    }
+    assert!(received > 0);
+
+    // Trickle the headers of the second request, one line per second.
+    let mut header_lines = ["GET /api/v2/status HTTP/1.1\r\n"]
+        .into_iter()
+        .chain(std::iter::repeat("X-Slow: 1\r\n"));
+    let start = std::time::Instant::now();
+    let closed = loop {
+        let line = header_lines.next().unwrap();
+        if stream.write_all(line.as_bytes()).await.is_err() {
+            break true;
+        }
+        match tokio::time::timeout(Duration::from_secs(1), stream.read(&mut buf)).await {
+            Ok(Ok(0)) | Ok(Err(_)) => break true,
+            Ok(Ok(_)) => panic!("Received a response to incomplete headers"),
+            Err(_) if start.elapsed() > Duration::from_secs(10) => break false,
+            Err(_) => {}
+        }
+    };
+    assert!(closed);
+}
+
 /// If the downstream service is stuck return 504.
 #[test]
 fn test_request_timeout() {
@@ -665,7 +783,7 @@ fn test_request_timeout() {
     let addr = get_free_localhost_socket_addr();
     let request_timeout_seconds = 2;
     let config = Config {
-        listen_addr: addr,
+        listeners: vec![addr.into()],
         request_timeout_seconds,
         ..Default::default()
     };
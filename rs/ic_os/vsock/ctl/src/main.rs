@@ -0,0 +1,315 @@
+#![cfg(target_os = "linux")]
+
+//! A CLI for node operators to send vsock commands to the hostOS from the
+//! guestOS. Unlike vsock_guest, which is called by the orchestrator, it has
+//! a subcommand per command, prints human-readable output or, with `--json`,
+//! a single JSON object, and its exit code tells what went wrong.
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+use std::path::PathBuf;
+use std::process::exit;
+use vsock_lib::protocol::{
+    Command, HSMSerialData, HostMetrics, Payload, Response, StreamInfo, StreamSource, StreamTarget,
+    UpgradeData, UpgradeStage, UpgradeStatus, UPLOADED_UPGRADE_URL,
+};
+use vsock_lib::{download_from_host, send_command, upload_to_host};
+
+/// The command was sent, but the hostOS failed to execute it, or it could
+/// not be sent at all.
+const EXIT_COMMAND_FAILED: i32 = 1;
+// 2 is used by clap for invalid arguments.
+/// The hostOS answered with a payload the command doesn't return.
+const EXIT_UNEXPECTED_RESPONSE: i32 = 3;
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "vsock-ctl",
+    version = "1.0.0",
+    about = "Send commands to the hostOS over the vsock channel",
+    author = "DFINITY Stiftung (c) 2023"
+)]
+struct Cli {
+    /// Print the outcome as a JSON object
+    #[clap(long, global = true)]
+    json: bool,
+
+    /// The port of the vsock server of the hostOS
+    #[clap(long, global = true, default_value_t = 19090)]
+    port: u32,
+
+    #[clap(subcommand)]
+    command: CtlCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum CtlCommand {
+    /// Attach the HSM to the guest VM
+    AttachHsm {
+        /// The USB serial number of the HSM, required if the host has several HSMs
+        #[clap(long, value_name = "SERIAL")]
+        serial: Option<String>,
+    },
+    /// Detach the HSM from the guest VM
+    DetachHsm,
+    /// Apply a hostOS upgrade to the inactive boot slot and reboot the host into it
+    Upgrade {
+        /// The URL of the upgrade image
+        #[clap(long, value_name = "URL")]
+        url: String,
+        /// The SHA-256 of the upgrade image
+        #[clap(long, value_name = "HASH")]
+        hash: String,
+    },
+    /// Print the progress of the last upgrade and the state of the boot slots of the host
+    GetUpgradeStatus,
+    /// Print the usage of the CPU, memory, filesystems and network interfaces of the host
+    GetHostMetrics,
+    /// Download the whole console log of the guest VM, resuming an interrupted download into FILE
+    DownloadGuestConsole {
+        /// The file to download the log to
+        #[clap(long, value_name = "FILE")]
+        output: PathBuf,
+    },
+    /// Upload a hostOS upgrade image, to apply with `upgrade --url vsock:uploaded-upgrade-image`
+    UploadUpgradeImage {
+        /// The upgrade image
+        #[clap(long, value_name = "FILE")]
+        file: PathBuf,
+    },
+}
+
+impl CtlCommand {
+    fn name(&self) -> &'static str {
+        match self {
+            CtlCommand::AttachHsm { .. } => "attach-hsm",
+            CtlCommand::DetachHsm => "detach-hsm",
+            CtlCommand::Upgrade { .. } => "upgrade",
+            CtlCommand::GetUpgradeStatus => "get-upgrade-status",
+            CtlCommand::GetHostMetrics => "get-host-metrics",
+            CtlCommand::DownloadGuestConsole { .. } => "download-guest-console",
+            CtlCommand::UploadUpgradeImage { .. } => "upload-upgrade-image",
+        }
+    }
+
+    fn run(&self, port: u32) -> Response {
+        match self {
+            CtlCommand::DownloadGuestConsole { output } => {
+                download_from_host(StreamSource::GuestConsole, output, port)
+            }
+            CtlCommand::UploadUpgradeImage { file } => {
+                upload_to_host(file, StreamTarget::UpgradeImage, port)
+            }
+            command => send_command(command.to_command(), port),
+        }
+    }
+
+    /// The command sent for the commands that are not stream transfers.
+    fn to_command(&self) -> Command {
+        match self {
+            CtlCommand::AttachHsm {
+                serial: Some(serial),
+            } => Command::AttachHSMBySerial(HSMSerialData {
+                serial: serial.clone(),
+            }),
+            CtlCommand::AttachHsm { serial: None } => Command::AttachHSM,
+            CtlCommand::DetachHsm => Command::DetachHSM,
+            CtlCommand::Upgrade { url, hash } => Command::Upgrade(UpgradeData {
+                url: url.clone(),
+                target_hash: hash.clone(),
+            }),
+            CtlCommand::GetUpgradeStatus => Command::GetUpgradeStatus,
+            CtlCommand::GetHostMetrics => Command::GetHostMetrics,
+            CtlCommand::DownloadGuestConsole { .. } | CtlCommand::UploadUpgradeImage { .. } => {
+                unreachable!("stream transfers are not sent as a single command")
+            }
+        }
+    }
+
+    /// Whether the command answers with `payload` on success.
+    fn expects(&self, payload: &Payload) -> bool {
+        match self {
+            CtlCommand::Upgrade { .. } | CtlCommand::GetUpgradeStatus => {
+                matches!(payload, Payload::UpgradeStatus(_))
+            }
+            CtlCommand::GetHostMetrics => matches!(payload, Payload::HostMetrics(_)),
+            CtlCommand::DownloadGuestConsole { .. } | CtlCommand::UploadUpgradeImage { .. } => {
+                matches!(payload, Payload::StreamInfo(_))
+            }
+            _ => matches!(payload, Payload::NoPayload),
+        }
+    }
+}
+
+/// The outcome printed with `--json`.
+#[derive(Serialize)]
+struct Outcome<'a> {
+    command: &'a str,
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload: Option<&'a Payload>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let name = cli.command.name();
+    let result = cli.command.run(cli.port);
+
+    let (exit_code, error) = match &result {
+        Ok(payload) if cli.command.expects(payload) => (0, None),
+        Ok(payload) => (
+            EXIT_UNEXPECTED_RESPONSE,
+            Some(format!("Unexpected response from the hostOS: {}", payload)),
+        ),
+        Err(error) => (EXIT_COMMAND_FAILED, Some(error.clone())),
+    };
+
+    if cli.json {
+        let outcome = Outcome {
+            command: name,
+            success: exit_code == 0,
+            payload: result.as_ref().ok(),
+            error,
+        };
+        match serde_json::to_string(&outcome) {
+            Ok(json) => println!("{}", json),
+            Err(error) => {
+                eprintln!("Could not serialize the outcome: {}", error);
+                exit(EXIT_COMMAND_FAILED);
+            }
+        }
+    } else {
+        match (&result, error) {
+            (_, Some(error)) => eprintln!("{} failed: {}", name, error),
+            (Ok(Payload::HostMetrics(metrics)), None) => print_host_metrics(metrics),
+            (Ok(Payload::UpgradeStatus(status)), None) => print_upgrade_status(status),
+            (Ok(Payload::StreamInfo(info)), None) => print_stream_info(&cli.command, info),
+            (_, None) => println!("{}: done", name),
+        }
+    }
+    exit(exit_code);
+}
+
+fn print_upgrade_status(status: &UpgradeStatus) {
+    match &status.stage {
+        UpgradeStage::Idle => println!("No upgrade requested since the host booted"),
+        UpgradeStage::Downloading => println!("Downloading the upgrade image"),
+        UpgradeStage::Verifying => println!("Verifying the upgrade image"),
+        UpgradeStage::Installing => println!(
+            "Installing the upgrade image into slot {}",
+            status.target_slot
+        ),
+        UpgradeStage::Rebooting => println!(
+            "Upgrade installed into slot {}, rebooting",
+            status.target_slot
+        ),
+        UpgradeStage::Failed(error) => println!("Last upgrade failed: {}", error),
+    }
+    println!("  active slot:    {}", status.active_slot);
+    println!("  target slot:    {}", status.target_slot);
+    println!("  next boot slot: {}", status.next_boot_slot);
+    println!(
+        "  boot confirmed: {}",
+        if status.boot_confirmed { "yes" } else { "no" }
+    );
+}
+
+fn print_stream_info(command: &CtlCommand, info: &StreamInfo) {
+    let transferred = format_bytes(info.size_bytes - info.offset);
+    if info.offset > 0 {
+        println!(
+            "Transferred {}, resuming at {}",
+            transferred,
+            format_bytes(info.offset)
+        );
+    } else {
+        println!("Transferred {}", transferred);
+    }
+    println!("  size:   {}", format_bytes(info.size_bytes));
+    println!("  sha256: {}", info.sha256);
+    if let CtlCommand::UploadUpgradeImage { .. } = command {
+        println!(
+            "Apply it with: vsock-ctl upgrade --url {} --hash {}",
+            UPLOADED_UPGRADE_URL, info.sha256
+        );
+    }
+}
+
+fn print_host_metrics(metrics: &HostMetrics) {
+    let cpu = &metrics.cpu;
+    let [load_1, load_5, load_15] = cpu.load_averages_centi;
+    println!("CPU");
+    println!("  cores:          {}", cpu.cores);
+    println!(
+        "  load averages:  {:.2} {:.2} {:.2}",
+        load_1 as f64 / 100.0,
+        load_5 as f64 / 100.0,
+        load_15 as f64 / 100.0
+    );
+    let total_ticks = cpu.busy_ticks + cpu.idle_ticks;
+    if total_ticks > 0 {
+        println!(
+            "  busy:           {:.1}% since boot",
+            cpu.busy_ticks as f64 * 100.0 / total_ticks as f64
+        );
+    }
+
+    let memory = &metrics.memory;
+    println!("Memory");
+    println!(
+        "  used:           {} of {}",
+        format_bytes(memory.total_bytes.saturating_sub(memory.available_bytes)),
+        format_bytes(memory.total_bytes)
+    );
+    println!(
+        "  swap used:      {} of {}",
+        format_bytes(
+            memory
+                .swap_total_bytes
+                .saturating_sub(memory.swap_free_bytes)
+        ),
+        format_bytes(memory.swap_total_bytes)
+    );
+
+    println!("Filesystems");
+    for filesystem in &metrics.filesystems {
+        println!(
+            "  {:<24} {:>10} of {:>10} used ({} available) on {}",
+            filesystem.mount_point,
+            format_bytes(filesystem.used_bytes),
+            format_bytes(filesystem.size_bytes),
+            format_bytes(filesystem.available_bytes),
+            filesystem.device
+        );
+    }
+
+    println!("Network interfaces");
+    for interface in &metrics.network_interfaces {
+        println!(
+            "  {:<16} rx {:>10} ({} errors, {} dropped)  tx {:>10} ({} errors, {} dropped)",
+            interface.name,
+            format_bytes(interface.rx_bytes),
+            interface.rx_errors,
+            interface.rx_dropped,
+            format_bytes(interface.tx_bytes),
+            interface.tx_errors,
+            interface.tx_dropped
+        );
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
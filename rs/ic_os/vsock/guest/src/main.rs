@@ -1,7 +1,11 @@
 #![cfg(target_os = "linux")]
 
 use clap::{Args, Parser};
-use vsock_lib::protocol::{Command, NodeIdData, NotifyData, UpgradeData};
+use std::path::PathBuf;
+use vsock_lib::protocol::{
+    AttestationData, Command, DiskData, DiskSource, FirewallRulesetData, GuestConsoleData,
+    HSMSerialData, NodeIdData, NotifyData, Payload, UpgradeData,
+};
 use vsock_lib::send_command;
 fn main() -> Result<(), String> {
     let cli = Cli::parse();
@@ -10,7 +14,30 @@ fn main() -> Result<(), String> {
     let command = get_command(cli)?;
     let payload = send_command(command, port)?;
 
-    println!("RESPONSE: {}", payload);
+    match payload {
+        // The full hardware health and host metrics are printed as JSON so that they can be
+        // scraped by the guestOS monitoring, and so is the upgrade status to be polled.
+        Payload::HardwareHealth(hardware_health) => println!(
+            "{}",
+            serde_json::to_string(&hardware_health).map_err(|e| e.to_string())?
+        ),
+        Payload::HostMetrics(host_metrics) => println!(
+            "{}",
+            serde_json::to_string(&host_metrics).map_err(|e| e.to_string())?
+        ),
+        Payload::UpgradeStatus(upgrade_status) => println!(
+            "{}",
+            serde_json::to_string(&upgrade_status).map_err(|e| e.to_string())?
+        ),
+        // The attestation is printed as JSON to be included in the attestation of the node.
+        Payload::Attestation(attestation) => println!(
+            "{}",
+            serde_json::to_string(&attestation).map_err(|e| e.to_string())?
+        ),
+        // The console log is printed as is, to be read like the console itself.
+        Payload::GuestConsole(log) => print!("{}", log),
+        payload => println!("RESPONSE: {}", payload),
+    }
 
     Ok(())
 }
@@ -26,6 +53,10 @@ struct Cli {
     #[clap(long)]
     attach_hsm: bool,
 
+    /// The USB serial number of the HSM to attach, required if the host has several HSMs
+    #[clap(long, value_name = "SERIAL", requires = "attach_hsm")]
+    serial: Option<String>,
+
     /// Request hostOS to detach the HSM to to the guest VM
     #[clap(long)]
     detach_hsm: bool,
@@ -34,6 +65,47 @@ struct Cli {
     #[clap(long)]
     get_hostos_version: bool,
 
+    /// Request hostOS to return the health of its hardware (fans, temperatures, PSUs, disks)
+    #[clap(long)]
+    get_hardware_health: bool,
+
+    /// Request hostOS to return the usage of its CPU, memory, filesystems and network interfaces
+    #[clap(long)]
+    get_host_metrics: bool,
+
+    /// Request hostOS to return the progress of the last upgrade and the state of its boot slots
+    #[clap(long)]
+    get_upgrade_status: bool,
+
+    /// Request hostOS to return the last lines of the guestOS console log
+    #[clap(long)]
+    get_guest_console: bool,
+
+    /// The number of console log lines to return
+    #[clap(long, value_name = "N", default_value_t = 100)]
+    lines: u32,
+
+    /// Request hostOS to return a TPM quote over its measured boot state covering the given
+    /// hex encoded nonce
+    #[clap(long, value_name = "NONCE")]
+    get_attestation: Option<String>,
+
+    /// Request hostOS to replace its firewall allowlist by the ruleset in the given JSON file
+    #[clap(long, value_name = "RULESET_FILE")]
+    update_firewall: Option<PathBuf>,
+
+    /// Request hostOS to attach a disk to the guest VM, given as lvm:<VOLUME> or file:<NAME>
+    #[clap(long, value_name = "SOURCE", requires = "disk_size")]
+    attach_disk: Option<String>,
+
+    /// The size of the disk to attach, in bytes
+    #[clap(long, value_name = "BYTES")]
+    disk_size: Option<u64>,
+
+    /// Request hostOS to detach a disk from the guest VM, given as lvm:<VOLUME> or file:<NAME>
+    #[clap(long, value_name = "SOURCE")]
+    detach_disk: Option<String>,
+
     /// Request hostOS to set the node ID.
     #[clap(long, value_name = "NODE_ID")]
     set_node_id: Option<String>,
@@ -72,11 +144,41 @@ struct Upgrade {
 
 fn get_command(cli: Cli) -> Result<Command, String> {
     if cli.attach_hsm {
-        Ok(Command::AttachHSM)
+        match cli.serial {
+            Some(serial) => Ok(Command::AttachHSMBySerial(HSMSerialData { serial })),
+            None => Ok(Command::AttachHSM),
+        }
     } else if cli.detach_hsm {
         Ok(Command::DetachHSM)
     } else if cli.get_hostos_version {
         Ok(Command::GetHostOSVersion)
+    } else if cli.get_hardware_health {
+        Ok(Command::GetHardwareHealth)
+    } else if cli.get_host_metrics {
+        Ok(Command::GetHostMetrics)
+    } else if cli.get_upgrade_status {
+        Ok(Command::GetUpgradeStatus)
+    } else if cli.get_guest_console {
+        Ok(Command::GetGuestConsole(GuestConsoleData {
+            lines: cli.lines,
+        }))
+    } else if let Some(nonce) = cli.get_attestation {
+        Ok(Command::GetAttestation(AttestationData { nonce }))
+    } else if let Some(ruleset_file) = cli.update_firewall {
+        let ruleset = std::fs::read_to_string(&ruleset_file)
+            .map_err(|e| format!("Could not read {:?}: {}", ruleset_file, e))?;
+        let ruleset: FirewallRulesetData = serde_json::from_str(&ruleset)
+            .map_err(|e| format!("Could not parse {:?}: {}", ruleset_file, e))?;
+        Ok(Command::UpdateFirewall(ruleset))
+    } else if let Some(source) = cli.attach_disk {
+        Ok(Command::AttachDisk(DiskData {
+            source: parse_disk_source(&source)?,
+            size_bytes: cli
+                .disk_size
+                .ok_or("No size given for attach-disk command")?,
+        }))
+    } else if let Some(source) = cli.detach_disk {
+        Ok(Command::DetachDisk(parse_disk_source(&source)?))
     } else if let Some(node_id) = cli.set_node_id {
         Ok(Command::SetNodeId(NodeIdData { node_id }))
     } else if let Some(url) = cli.upgrade.upgrade {
@@ -94,3 +196,14 @@ fn get_command(cli: Cli) -> Result<Command, String> {
         Err("no command matched".into())
     }
 }
+
+fn parse_disk_source(source: &str) -> Result<DiskSource, String> {
+    match source.split_once(':') {
+        Some(("lvm", name)) => Ok(DiskSource::LvmVolume(name.to_string())),
+        Some(("file", name)) => Ok(DiskSource::RawFile(name.to_string())),
+        _ => Err(format!(
+            "Invalid disk source {}, expected lvm:<VOLUME> or file:<NAME>",
+            source
+        )),
+    }
+}
@@ -0,0 +1,912 @@
+//! End-to-end tests of the guest client and the host agent, connected over a
+//! Unix socket pair instead of vsock and backed by an in-memory host.
+use crate::guest::client::send_request_over_stream;
+use crate::guest::transfer::{download_over_stream, upload_over_stream};
+use crate::host::backend::{Backend, UsbDevice};
+use crate::host::mock_backend::{MockHost, MOCK_PCR_VALUE};
+use crate::host::server::process_connection;
+use crate::protocol::{
+    parse_response, read_control, AttestationData, BootSlot, Command, DiskData, DiskSource,
+    FirewallRule, FirewallRulesetData, GuestConsoleData, HSMSerialData, HostOSVsockVersion,
+    Payload, Request, Response, StreamInfo, StreamSource, StreamTarget, StreamUploadData,
+    UpgradeData, UpgradeStage, UpgradeStatus, VsockProtocol, UPLOADED_UPGRADE_URL,
+};
+use sha2::Digest;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+const GUEST_CID: u32 = 3;
+
+const NITROKEY: UsbDevice = UsbDevice {
+    bus_number: 1,
+    address: 4,
+    vendor_id: 8352,
+    product_id: 16944,
+    serial_number: None,
+};
+
+fn nitrokey(address: u8, serial: &str) -> UsbDevice {
+    UsbDevice {
+        address,
+        serial_number: Some(serial.to_string()),
+        ..NITROKEY
+    }
+}
+
+struct TestHost {
+    mock: MockHost,
+    backend: Arc<Backend>,
+    _tmp_dir: TempDir,
+}
+
+impl TestHost {
+    fn new() -> Self {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mock = MockHost::default();
+        let backend = Arc::new(mock.backend(tmp_dir.path()));
+        Self {
+            mock,
+            backend,
+            _tmp_dir: tmp_dir,
+        }
+    }
+
+    /// Sends the command from a guest with CID `sender_cid` to the host agent,
+    /// which sees a peer with CID `GUEST_CID`.
+    fn send_as(&self, sender_cid: u32, command: Command) -> Response {
+        let (mut guest_stream, mut host_stream) = UnixStream::pair().unwrap();
+        let backend = Arc::clone(&self.backend);
+        let host = std::thread::spawn(move || {
+            process_connection(&mut host_stream, Ok(GUEST_CID), &backend)
+        });
+
+        let request = Request {
+            guest_cid: sender_cid,
+            command,
+        };
+        let response = send_request_over_stream(&mut guest_stream, &request, &VsockProtocol::V1)?;
+        let _ = host.join().unwrap();
+
+        parse_response(&response, &VsockProtocol::V1)
+    }
+
+    fn send(&self, command: Command) -> Response {
+        self.send_as(GUEST_CID, command)
+    }
+
+    /// Polls the status of the upgrade running in the background until it
+    /// failed or rebooted the host, like the guest does after an upgrade.
+    fn await_upgrade(&self) -> UpgradeStatus {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let status = match self.send(Command::GetUpgradeStatus) {
+                Ok(Payload::UpgradeStatus(status)) => status,
+                response => panic!("Unexpected response: {:?}", response),
+            };
+            let done = match &status.stage {
+                UpgradeStage::Rebooting => self.mock.state.lock().unwrap().reboots > 0,
+                stage => !stage.is_in_progress(),
+            };
+            if done {
+                return status;
+            }
+            assert!(
+                Instant::now() < deadline,
+                "Upgrade stuck at {}",
+                status.stage
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Runs `transfer` on the guest end of a connection to the host agent.
+    fn transfer<F>(&self, transfer: F) -> Response
+    where
+        F: FnOnce(&mut UnixStream) -> Response,
+    {
+        let (mut guest_stream, mut host_stream) = UnixStream::pair().unwrap();
+        let backend = Arc::clone(&self.backend);
+        let host = std::thread::spawn(move || {
+            process_connection(&mut host_stream, Ok(GUEST_CID), &backend)
+        });
+
+        let response = transfer(&mut guest_stream);
+        drop(guest_stream);
+        let _ = host.join().unwrap();
+
+        response
+    }
+}
+
+/// A connection that breaks once `remaining` bytes were written to it.
+struct BreakingStream<'a> {
+    stream: &'a mut UnixStream,
+    remaining: usize,
+}
+
+impl Read for BreakingStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for BreakingStream<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.remaining == 0 {
+            return Err(Error::new(ErrorKind::BrokenPipe, "connection broke"));
+        }
+        let len = buf.len().min(self.remaining);
+        self.remaining -= len;
+        self.stream.write(&buf[..len])
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+fn sha256_hex(contents: &[u8]) -> String {
+    format!("{:x}", sha2::Sha256::digest(contents))
+}
+
+#[test]
+fn get_vsock_protocol() {
+    let host = TestHost::new();
+    assert_eq!(
+        host.send(Command::GetVsockProtocol),
+        Ok(Payload::HostOSVsockVersion(HostOSVsockVersion {
+            major: 1,
+            minor: 1,
+            patch: 0,
+        }))
+    );
+}
+
+#[test]
+fn rejects_request_with_wrong_sender_cid() {
+    let host = TestHost::new();
+    host.mock.add_usb_device(NITROKEY);
+
+    assert!(host.send_as(GUEST_CID + 1, Command::AttachHSM).is_err());
+    assert!(host.mock.attached_devices("guestos").is_empty());
+}
+
+#[test]
+fn attach_and_detach_hsm() {
+    let host = TestHost::new();
+    host.mock.add_usb_device(UsbDevice {
+        bus_number: 1,
+        address: 2,
+        vendor_id: 0x1d6b,
+        product_id: 0x0002,
+        serial_number: None,
+    });
+    host.mock.add_usb_device(NITROKEY);
+
+    assert_eq!(host.send(Command::AttachHSM), Ok(Payload::NoPayload));
+    let attached = host.mock.attached_devices("guestos");
+    assert_eq!(attached.len(), 1);
+    assert!(attached[0].contains("<address bus='1' port='1' device='4'/>"));
+
+    // Attaching twice is refused.
+    assert!(host.send(Command::AttachHSM).is_err());
+
+    assert_eq!(host.send(Command::DetachHSM), Ok(Payload::NoPayload));
+    assert!(host.mock.attached_devices("guestos").is_empty());
+    assert!(host.send(Command::DetachHSM).is_err());
+}
+
+#[test]
+fn attach_hsm_fails_without_hsm() {
+    let host = TestHost::new();
+    assert_eq!(
+        host.send(Command::AttachHSM),
+        Err("Could not get hsm info".to_string())
+    );
+}
+
+#[test]
+fn attach_hsm_requires_serial_with_multiple_hsms() {
+    let host = TestHost::new();
+    host.mock.add_usb_device(nitrokey(4, "NK001"));
+    host.mock.add_usb_device(nitrokey(5, "NK002"));
+
+    assert_eq!(
+        host.send(Command::AttachHSM),
+        Err("Found 2 HSM devices, select one by serial: NK001, NK002".to_string())
+    );
+    assert!(host.mock.attached_devices("guestos").is_empty());
+
+    assert_eq!(
+        host.send(Command::AttachHSMBySerial(HSMSerialData {
+            serial: "NK003".to_string()
+        })),
+        Err("No HSM device found with serial NK003".to_string())
+    );
+}
+
+#[test]
+fn attach_hsm_by_serial_and_detach_it() {
+    let host = TestHost::new();
+    host.mock.add_usb_device(nitrokey(4, "NK001"));
+    host.mock.add_usb_device(nitrokey(5, "NK002"));
+
+    assert_eq!(
+        host.send(Command::AttachHSMBySerial(HSMSerialData {
+            serial: "NK002".to_string()
+        })),
+        Ok(Payload::NoPayload)
+    );
+    let attached = host.mock.attached_devices("guestos");
+    assert_eq!(attached.len(), 1);
+    assert!(attached[0].contains("<address bus='1' port='1' device='5'/>"));
+
+    // Only one HSM can be attached at a time.
+    assert!(host
+        .send(Command::AttachHSMBySerial(HSMSerialData {
+            serial: "NK001".to_string()
+        }))
+        .is_err());
+
+    // Detaching targets the recorded HSM even though the host has two.
+    assert_eq!(host.send(Command::DetachHSM), Ok(Payload::NoPayload));
+    assert!(host.mock.attached_devices("guestos").is_empty());
+}
+
+#[test]
+fn upgrade_installs_verified_image_and_reboots() {
+    let host = TestHost::new();
+    let image = b"hostos upgrade image".to_vec();
+    host.mock
+        .add_upgrade_image("https://example.com/upgrade.tar.gz", image.clone());
+
+    // The upgrade runs in the background.
+    assert_eq!(
+        host.send(Command::Upgrade(UpgradeData {
+            url: "https://example.com/upgrade.tar.gz".to_string(),
+            target_hash: sha256_hex(&image),
+        })),
+        Ok(Payload::UpgradeStatus(UpgradeStatus {
+            stage: UpgradeStage::Downloading,
+            active_slot: BootSlot::A,
+            target_slot: BootSlot::B,
+            next_boot_slot: BootSlot::A,
+            boot_confirmed: true,
+        }))
+    );
+    assert_eq!(
+        host.await_upgrade(),
+        UpgradeStatus {
+            stage: UpgradeStage::Rebooting,
+            active_slot: BootSlot::A,
+            target_slot: BootSlot::B,
+            next_boot_slot: BootSlot::B,
+            boot_confirmed: false,
+        }
+    );
+
+    {
+        let state = host.mock.state.lock().unwrap();
+        assert_eq!(state.installed_images, vec![image.clone()]);
+        assert_eq!(state.reboots, 1);
+    }
+
+    // Until the upgraded HostOS confirmed its boot, the bootloader may fall
+    // back to the running slot, which must not be overwritten.
+    assert!(host
+        .send(Command::Upgrade(UpgradeData {
+            url: "https://example.com/upgrade.tar.gz".to_string(),
+            target_hash: sha256_hex(&image),
+        }))
+        .is_err());
+    assert_eq!(host.mock.state.lock().unwrap().installed_images.len(), 1);
+}
+
+#[test]
+fn upgrade_with_wrong_hash_is_not_installed() {
+    let host = TestHost::new();
+    host.mock.add_upgrade_image(
+        "https://example.com/upgrade.tar.gz",
+        b"tampered image".to_vec(),
+    );
+
+    assert!(host
+        .send(Command::Upgrade(UpgradeData {
+            url: "https://example.com/upgrade.tar.gz".to_string(),
+            target_hash: sha256_hex(b"hostos upgrade image"),
+        }))
+        .is_ok());
+
+    match host.await_upgrade() {
+        UpgradeStatus {
+            stage: UpgradeStage::Failed(error),
+            active_slot: BootSlot::A,
+            next_boot_slot: BootSlot::A,
+            boot_confirmed: true,
+            ..
+        } => assert!(error.contains("hash")),
+        status => panic!("Unexpected status: {:?}", status),
+    }
+    {
+        let state = host.mock.state.lock().unwrap();
+        assert!(state.installed_images.is_empty());
+        assert_eq!(state.reboots, 0);
+    }
+}
+
+#[test]
+fn upgrade_is_refused_while_another_one_runs() {
+    let host = TestHost::new();
+    host.backend.state.lock().unwrap().upgrade_stage = UpgradeStage::Installing;
+
+    assert_eq!(
+        host.send(Command::Upgrade(UpgradeData {
+            url: "https://example.com/upgrade.tar.gz".to_string(),
+            target_hash: sha256_hex(b"hostos upgrade image"),
+        })),
+        Err("An upgrade is already in progress: Installing".to_string())
+    );
+    assert!(host.mock.state.lock().unwrap().installed_images.is_empty());
+}
+
+#[test]
+fn get_upgrade_status_reports_boot_slots() {
+    let host = TestHost::new();
+    host.mock.set_grubenv("B", "failsafe_check");
+
+    assert_eq!(
+        host.send(Command::GetUpgradeStatus),
+        Ok(Payload::UpgradeStatus(UpgradeStatus {
+            stage: UpgradeStage::Idle,
+            active_slot: BootSlot::B,
+            target_slot: BootSlot::A,
+            next_boot_slot: BootSlot::A,
+            boot_confirmed: false,
+        }))
+    );
+}
+
+#[test]
+fn upgrade_fails_when_download_fails() {
+    let host = TestHost::new();
+    assert!(host
+        .send(Command::Upgrade(UpgradeData {
+            url: "https://example.com/missing.tar.gz".to_string(),
+            target_hash: sha256_hex(b""),
+        }))
+        .is_ok());
+    assert_eq!(
+        host.await_upgrade().stage,
+        UpgradeStage::Failed("Could not download url".to_string())
+    );
+}
+
+#[test]
+fn get_guest_console_returns_last_lines() {
+    let host = TestHost::new();
+    let log: String = (0..5000).map(|i| format!("console line {}\n", i)).collect();
+    std::fs::write(&host.backend.guest_console_log_path, log).unwrap();
+
+    assert_eq!(
+        host.send(Command::GetGuestConsole(GuestConsoleData { lines: 2 })),
+        Ok(Payload::GuestConsole(
+            "console line 4998\nconsole line 4999\n".to_string()
+        ))
+    );
+
+    // The log is capped, but responses larger than a single read still arrive.
+    match host.send(Command::GetGuestConsole(GuestConsoleData { lines: 5000 })) {
+        Ok(Payload::GuestConsole(tail)) => {
+            assert!(tail.len() > 4096 && tail.len() <= 32 * 1024);
+            assert!(tail.ends_with("console line 4999\n"));
+        }
+        response => panic!("Unexpected response: {:?}", response),
+    }
+}
+
+#[test]
+fn get_guest_console_fails_without_log() {
+    let host = TestHost::new();
+    assert!(host
+        .send(Command::GetGuestConsole(GuestConsoleData { lines: 10 }))
+        .is_err());
+}
+
+#[test]
+fn stream_download_of_guest_console_resumes_and_verifies() {
+    let host = TestHost::new();
+    // Far larger than the capped get-guest-console.
+    let log: String = (0..100_000)
+        .map(|i| format!("console line {}\n", i))
+        .collect();
+    std::fs::write(&host.backend.guest_console_log_path, &log).unwrap();
+    let guest_dir = tempfile::tempdir().unwrap();
+    let target = guest_dir.path().join("guestos-serial.log");
+
+    // The guest kept the beginning of an interrupted download.
+    std::fs::write(&target, &log[..300_000]).unwrap();
+    assert_eq!(
+        host.transfer(|stream| download_over_stream(
+            stream,
+            GUEST_CID,
+            StreamSource::GuestConsole,
+            &target
+        )),
+        Ok(Payload::StreamInfo(StreamInfo {
+            size_bytes: log.len() as u64,
+            sha256: sha256_hex(log.as_bytes()),
+            offset: 300_000,
+        }))
+    );
+    assert_eq!(std::fs::read_to_string(&target).unwrap(), log);
+
+    // A kept beginning that differs from the log is detected.
+    std::fs::write(&target, "not the console log").unwrap();
+    match host.transfer(|stream| {
+        download_over_stream(stream, GUEST_CID, StreamSource::GuestConsole, &target)
+    }) {
+        Err(error) => assert!(error.contains("do not match the expected hash")),
+        response => panic!("Unexpected response: {:?}", response),
+    }
+    assert!(!target.exists());
+}
+
+#[test]
+fn stream_upload_resumes_and_installs_uploaded_upgrade_image() {
+    let host = TestHost::new();
+    let image: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let guest_dir = tempfile::tempdir().unwrap();
+    let source = guest_dir.path().join("upgrade.tar.gz");
+    std::fs::write(&source, &image).unwrap();
+
+    // The connection breaks in the middle of the upload.
+    assert!(host
+        .transfer(|stream| upload_over_stream(
+            &mut BreakingStream {
+                stream,
+                remaining: 1024 * 1024,
+            },
+            GUEST_CID,
+            &source,
+            StreamTarget::UpgradeImage,
+        ))
+        .is_err());
+    assert!(!host.backend.upgrade_file_path.exists());
+
+    match host.transfer(|stream| {
+        upload_over_stream(stream, GUEST_CID, &source, StreamTarget::UpgradeImage)
+    }) {
+        Ok(Payload::StreamInfo(info)) => {
+            assert_eq!(info.size_bytes, image.len() as u64);
+            assert_eq!(info.sha256, sha256_hex(&image));
+            // Only the bytes the host did not keep were sent again.
+            assert!(info.offset > 0 && info.offset < 1024 * 1024);
+        }
+        response => panic!("Unexpected response: {:?}", response),
+    }
+    assert_eq!(
+        std::fs::read(&host.backend.upgrade_file_path).unwrap(),
+        image
+    );
+
+    assert!(matches!(
+        host.send(Command::Upgrade(UpgradeData {
+            url: UPLOADED_UPGRADE_URL.to_string(),
+            target_hash: sha256_hex(&image),
+        })),
+        Ok(Payload::UpgradeStatus(_))
+    ));
+    assert_eq!(host.await_upgrade().stage, UpgradeStage::Rebooting);
+    assert_eq!(
+        host.mock.state.lock().unwrap().installed_images,
+        vec![image]
+    );
+}
+
+#[test]
+fn stream_upload_rejects_wrong_sender_cid() {
+    let host = TestHost::new();
+    let guest_dir = tempfile::tempdir().unwrap();
+    let source = guest_dir.path().join("upgrade.tar.gz");
+    std::fs::write(&source, b"hostos upgrade image").unwrap();
+
+    assert!(host
+        .transfer(|stream| upload_over_stream(
+            stream,
+            GUEST_CID + 1,
+            &source,
+            StreamTarget::UpgradeImage
+        ))
+        .unwrap_err()
+        .contains("sender CID"));
+    assert!(!host.backend.upgrade_file_path.exists());
+}
+
+#[test]
+fn stream_upload_checks_available_space() {
+    let host = TestHost::new();
+    host.mock.state.lock().unwrap().available_disk_bytes = Some(1024 * 1024);
+    let image: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let guest_dir = tempfile::tempdir().unwrap();
+    let source = guest_dir.path().join("upgrade.tar.gz");
+    std::fs::write(&source, &image).unwrap();
+    let upload = |stream: &mut UnixStream| {
+        upload_over_stream(stream, GUEST_CID, &source, StreamTarget::UpgradeImage)
+    };
+
+    assert_eq!(
+        host.transfer(upload),
+        Err(format!(
+            "Upload of {} bytes exceeds the {} bytes available for {:?}",
+            image.len(),
+            1024 * 1024,
+            host.backend.upgrade_file_path
+        ))
+    );
+    assert!(!host.backend.upgrade_file_path.exists());
+
+    host.mock.state.lock().unwrap().available_disk_bytes = Some(image.len() as u64);
+    assert!(matches!(host.transfer(upload), Ok(Payload::StreamInfo(_))));
+    assert_eq!(
+        std::fs::read(&host.backend.upgrade_file_path).unwrap(),
+        image
+    );
+}
+
+#[test]
+fn stream_upload_excludes_other_uploads_and_upgrades() {
+    let host = TestHost::new();
+    let guest_dir = tempfile::tempdir().unwrap();
+    let source = guest_dir.path().join("upgrade.tar.gz");
+    std::fs::write(&source, b"hostos upgrade image").unwrap();
+    let upload = |stream: &mut UnixStream| {
+        upload_over_stream(stream, GUEST_CID, &source, StreamTarget::UpgradeImage)
+    };
+
+    // An upload would replace the image an upgrade verified and installs.
+    host.backend.state.lock().unwrap().upgrade_stage = UpgradeStage::Verifying;
+    assert_eq!(
+        host.transfer(upload),
+        Err("An upgrade is in progress: Verifying".to_string())
+    );
+    assert!(!host.backend.upgrade_file_path.exists());
+
+    // Uploads share the partial file, so they run one at a time, and an
+    // upgrade waits for the upload to end.
+    host.backend.state.lock().unwrap().upgrade_stage = UpgradeStage::Idle;
+    host.backend.state.lock().unwrap().upload_in_progress = true;
+    assert_eq!(
+        host.transfer(upload),
+        Err("Another upload is in progress".to_string())
+    );
+    assert_eq!(
+        host.send(Command::Upgrade(UpgradeData {
+            url: UPLOADED_UPGRADE_URL.to_string(),
+            target_hash: sha256_hex(b"hostos upgrade image"),
+        })),
+        Err("An upload of an upgrade image is in progress".to_string())
+    );
+
+    // A completed upload releases the upgrade file.
+    host.backend.state.lock().unwrap().upload_in_progress = false;
+    assert!(matches!(host.transfer(upload), Ok(Payload::StreamInfo(_))));
+    assert!(!host.backend.state.lock().unwrap().upload_in_progress);
+}
+
+#[test]
+fn stream_upload_rejects_uploads_over_the_limit() {
+    let host = TestHost::new();
+
+    // The guest would hash 5 GiB before sending the request, so it is
+    // written by hand.
+    let response = host.transfer(|stream| {
+        let request = Request {
+            guest_cid: GUEST_CID,
+            command: Command::StreamUpload(StreamUploadData {
+                target: StreamTarget::UpgradeImage,
+                size_bytes: 5 * GIB,
+                sha256: sha256_hex(b""),
+            }),
+        };
+        stream
+            .write_all(serde_json::to_string(&request).unwrap().as_bytes())
+            .unwrap();
+        read_control::<_, Response>(stream).unwrap()
+    });
+
+    assert_eq!(
+        response,
+        Err(format!(
+            "Uploads are limited to {} bytes, got {}",
+            4 * GIB,
+            5 * GIB
+        ))
+    );
+    assert!(!host.backend.upgrade_file_path.exists());
+}
+
+#[test]
+fn get_attestation_quotes_measured_boot_over_nonce() {
+    let host = TestHost::new();
+    let nonce = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+
+    match host.send(Command::GetAttestation(AttestationData {
+        nonce: nonce.to_string(),
+    })) {
+        Ok(Payload::Attestation(attestation)) => {
+            assert_eq!(attestation.tpm_quote.message, nonce);
+            assert_eq!(
+                attestation
+                    .tpm_quote
+                    .pcrs
+                    .iter()
+                    .map(|pcr| pcr.index)
+                    .collect::<Vec<_>>(),
+                (0..10).collect::<Vec<_>>()
+            );
+            assert!(attestation
+                .tpm_quote
+                .pcrs
+                .iter()
+                .all(|pcr| pcr.sha256 == MOCK_PCR_VALUE));
+        }
+        response => panic!("Unexpected response: {:?}", response),
+    }
+}
+
+#[test]
+fn get_attestation_validates_nonce_and_fails_without_tpm() {
+    let host = TestHost::new();
+    for nonce in ["", "abc", "not hex", "00".repeat(33).as_str()] {
+        assert!(host
+            .send(Command::GetAttestation(AttestationData {
+                nonce: nonce.to_string(),
+            }))
+            .is_err());
+    }
+
+    host.mock.state.lock().unwrap().no_tpm = true;
+    assert_eq!(
+        host.send(Command::GetAttestation(AttestationData {
+            nonce: "0a0b".to_string(),
+        })),
+        Err("ERROR: Could not load tcti".to_string())
+    );
+}
+
+fn ruleset(registry_version: u64, sources: &[&str]) -> FirewallRulesetData {
+    FirewallRulesetData {
+        registry_version,
+        rules: sources
+            .iter()
+            .map(|source| FirewallRule {
+                source: source.to_string(),
+                ports: vec![22, 9100],
+                comment: "node".to_string(),
+            })
+            .collect(),
+    }
+}
+
+fn audit_log(host: &TestHost) -> Vec<String> {
+    std::fs::read_to_string(&host.backend.firewall_audit_log_path)
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn update_firewall_applies_and_persists_ruleset() {
+    let host = TestHost::new();
+
+    assert_eq!(
+        host.send(Command::UpdateFirewall(ruleset(7, &["2001:db8::/64"]))),
+        Ok(Payload::NoPayload)
+    );
+
+    let scripts = host.mock.state.lock().unwrap().firewall_scripts.clone();
+    assert_eq!(scripts.len(), 1);
+    assert!(scripts[0].contains("ip6 saddr 2001:db8::/64 ct state { new } tcp dport { 22, 9100 }"));
+    assert_eq!(
+        std::fs::read_to_string(&host.backend.firewall_ruleset_path).unwrap(),
+        scripts[0]
+    );
+    let audit_log = audit_log(&host);
+    assert_eq!(audit_log.len(), 1);
+    assert!(audit_log[0].contains("registry_version=7 rules=1"));
+    assert!(audit_log[0].ends_with("applied"));
+}
+
+#[test]
+fn update_firewall_rejects_invalid_ruleset() {
+    let host = TestHost::new();
+
+    assert!(host
+        .send(Command::UpdateFirewall(ruleset(
+            7,
+            &["10.0.0.1", "0.0.0.0/0"]
+        )))
+        .is_err());
+
+    assert!(host.mock.state.lock().unwrap().firewall_scripts.is_empty());
+    assert!(!host.backend.firewall_ruleset_path.exists());
+    assert!(audit_log(&host)[0].contains("rejected: Invalid firewall rule"));
+}
+
+#[test]
+fn update_firewall_keeps_previous_ruleset_if_loading_fails() {
+    let host = TestHost::new();
+    assert_eq!(
+        host.send(Command::UpdateFirewall(ruleset(7, &["10.0.0.1"]))),
+        Ok(Payload::NoPayload)
+    );
+    let applied = std::fs::read_to_string(&host.backend.firewall_ruleset_path).unwrap();
+
+    host.mock.state.lock().unwrap().fail_firewall = true;
+    assert!(host
+        .send(Command::UpdateFirewall(ruleset(8, &["10.0.0.2"])))
+        .is_err());
+
+    assert_eq!(
+        std::fs::read_to_string(&host.backend.firewall_ruleset_path).unwrap(),
+        applied
+    );
+    let audit_log = audit_log(&host);
+    assert_eq!(audit_log.len(), 2);
+    assert!(audit_log[1].contains("registry_version=8 rules=1"));
+    assert!(audit_log[1].contains("rejected"));
+}
+
+const GIB: u64 = 1 << 30;
+
+fn lvm_volume(name: &str, size_bytes: u64) -> DiskData {
+    DiskData {
+        source: DiskSource::LvmVolume(name.to_string()),
+        size_bytes,
+    }
+}
+
+#[test]
+fn attach_and_detach_disks() {
+    let host = TestHost::new();
+    host.mock
+        .add_disk("/dev/hostlvm/guestos_extra_data", 100 * GIB);
+
+    assert_eq!(
+        host.send(Command::AttachDisk(lvm_volume(
+            "guestos_extra_data",
+            100 * GIB
+        ))),
+        Ok(Payload::NoPayload)
+    );
+    assert_eq!(
+        host.send(Command::AttachDisk(DiskData {
+            source: DiskSource::RawFile("scratch.img".to_string()),
+            size_bytes: 10 * GIB,
+        })),
+        Ok(Payload::NoPayload)
+    );
+
+    // The missing raw file was created, and both disks are in the persistent
+    // definition of the guest, on their own targets.
+    assert_eq!(
+        host.mock
+            .state
+            .lock()
+            .unwrap()
+            .disks
+            .get(std::path::Path::new(
+                "/var/lib/libvirt/images/guestos-disks/scratch.img"
+            )),
+        Some(&(10 * GIB))
+    );
+    let persistent = host.mock.persistent_devices("guestos");
+    assert_eq!(persistent.len(), 2);
+    assert!(persistent[0].contains("<source dev='/dev/hostlvm/guestos_extra_data'/>"));
+    assert!(persistent[0].contains("<target dev='vdb' bus='virtio'/>"));
+    assert!(persistent[1].contains("<target dev='vdc' bus='virtio'/>"));
+
+    // Attaching twice is refused.
+    assert!(host
+        .send(Command::AttachDisk(lvm_volume(
+            "guestos_extra_data",
+            100 * GIB
+        )))
+        .is_err());
+
+    assert_eq!(
+        host.send(Command::DetachDisk(DiskSource::LvmVolume(
+            "guestos_extra_data".to_string()
+        ))),
+        Ok(Payload::NoPayload)
+    );
+    let persistent = host.mock.persistent_devices("guestos");
+    assert_eq!(persistent.len(), 1);
+    assert!(persistent[0].contains("scratch.img"));
+    assert!(host
+        .send(Command::DetachDisk(DiskSource::LvmVolume(
+            "guestos_extra_data".to_string()
+        )))
+        .is_err());
+
+    // The freed target is reused.
+    assert_eq!(
+        host.send(Command::AttachDisk(lvm_volume(
+            "guestos_extra_data",
+            100 * GIB
+        ))),
+        Ok(Payload::NoPayload)
+    );
+    assert!(host.mock.persistent_devices("guestos")[1].contains("<target dev='vdb' bus='virtio'/>"));
+}
+
+#[test]
+fn attach_disk_validates_size() {
+    let host = TestHost::new();
+    host.mock
+        .add_disk("/dev/hostlvm/guestos_extra_data", 100 * GIB);
+
+    assert_eq!(
+        host.send(Command::AttachDisk(lvm_volume(
+            "guestos_extra_data",
+            50 * GIB
+        ))),
+        Err(format!(
+            "Disk lvm:guestos_extra_data has {} bytes, expected {}",
+            100 * GIB,
+            50 * GIB
+        ))
+    );
+    assert!(host
+        .send(Command::AttachDisk(lvm_volume("guestos_extra_data", 1024)))
+        .is_err());
+    // Missing logical volumes are not created.
+    assert!(host
+        .send(Command::AttachDisk(lvm_volume(
+            "guestos_extra_other",
+            100 * GIB
+        )))
+        .is_err());
+    assert!(host.mock.attached_devices("guestos").is_empty());
+}
+
+#[test]
+fn attach_disk_checks_available_space() {
+    let host = TestHost::new();
+    host.mock.state.lock().unwrap().available_disk_bytes = Some(20 * GIB);
+    let raw_file = |size_bytes| DiskData {
+        source: DiskSource::RawFile("scratch.img".to_string()),
+        size_bytes,
+    };
+
+    assert_eq!(
+        host.send(Command::AttachDisk(raw_file(30 * GIB))),
+        Err(format!(
+            "Disk size of {} bytes exceeds the {} bytes available for raw files",
+            30 * GIB,
+            20 * GIB
+        ))
+    );
+    assert!(host.mock.state.lock().unwrap().disks.is_empty());
+    assert!(host.mock.attached_devices("guestos").is_empty());
+
+    assert_eq!(
+        host.send(Command::AttachDisk(raw_file(20 * GIB))),
+        Ok(Payload::NoPayload)
+    );
+}
+
+#[test]
+fn attach_disk_refuses_host_volumes() {
+    let host = TestHost::new();
+    host.mock.add_disk("/dev/hostlvm/A_var", 100 * GIB);
+
+    assert!(host
+        .send(Command::AttachDisk(lvm_volume("A_var", 100 * GIB)))
+        .is_err());
+    assert!(host.mock.attached_devices("guestos").is_empty());
+}
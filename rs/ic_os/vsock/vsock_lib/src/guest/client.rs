@@ -1,11 +1,13 @@
 use crate::protocol::{
     get_v0_request_vec, parse_response, Command, Payload, Request, Response, VsockProtocol,
 };
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
 use vsock::{VsockStream, VMADDR_CID_HOST};
 
 // the length of HOSTOS_V0_VERSION matches the length of "real" hostOS versions, 64 characters
 const HOSTOS_V0_VERSION: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+// responses larger than that are truncated and fail to parse
+const MAX_RESPONSE_SIZE: usize = 256 * 1024;
 
 pub fn send_request_to_host_and_parse_response(
     request: &Request,
@@ -29,6 +31,15 @@ pub fn send_request_to_host(
 ) -> Result<String, String> {
     let mut stream = create_stream(port).map_err(|e| e.to_string())?;
 
+    send_request_over_stream(&mut stream, request, protocol_version)
+}
+
+/// Sends the request over an already established connection and returns the raw response.
+pub(crate) fn send_request_over_stream<S: Read + Write>(
+    stream: &mut S,
+    request: &Request,
+    protocol_version: &VsockProtocol,
+) -> Result<String, String> {
     match protocol_version {
         VsockProtocol::V0 => {
             let request_vec = get_v0_request_vec(request)?;
@@ -42,19 +53,31 @@ pub fn send_request_to_host(
         }
     };
 
-    read_response_from_host(&mut stream)
+    read_response_from_host(stream)
 }
 
-fn read_response_from_host(stream: &mut VsockStream) -> Result<String, String> {
+/// Reads the response until the host closes the connection.
+fn read_response_from_host<S: Read>(stream: &mut S) -> Result<String, String> {
+    let mut response = Vec::new();
     let mut buffer = [0; 4096];
-    let bytes_read = stream.read(&mut buffer).map_err(|e| e.to_string())?;
-    match std::str::from_utf8(&buffer[..bytes_read]) {
-        Ok(response_str) => Ok(response_str.to_string()),
-        Err(error) => Err(error.to_string()),
+    while response.len() < MAX_RESPONSE_SIZE {
+        match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(bytes_read) => response.extend_from_slice(&buffer[..bytes_read]),
+            // older hosts keep the connection open after responding
+            Err(error)
+                if !response.is_empty()
+                    && matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+            {
+                break
+            }
+            Err(error) => return Err(error.to_string()),
+        }
     }
+    String::from_utf8(response).map_err(|e| e.to_string())
 }
 
-fn create_stream(port: &u32) -> Result<VsockStream, std::io::Error> {
+pub(crate) fn create_stream(port: &u32) -> Result<VsockStream, std::io::Error> {
     let stream = VsockStream::connect_with_cid_port(VMADDR_CID_HOST, *port)?;
     stream.set_write_timeout(Some(std::time::Duration::from_secs(5)))?;
     stream.set_read_timeout(Some(std::time::Duration::from_secs(5)))?;
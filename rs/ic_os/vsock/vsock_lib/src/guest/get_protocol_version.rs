@@ -28,10 +28,7 @@ fn parse_protocol_response(response: &str) -> Result<VsockProtocol, String> {
             Payload::HostOSVsockVersion(hostos_vsock_version) => {
                 parse_v1_response_helper(hostos_vsock_version)
             }
-            Payload::HostOSVersion(_) => {
-                Err("Logical error. Received payload: HostOSVersion".to_string())
-            }
-            Payload::NoPayload => Err("Logical error. Received payload: NoPayload".to_string()),
+            payload => Err(format!("Logical error. Received payload: {}", payload)),
         }
     }
     fn parse_v0_response(payload: Payload) -> Result<VsockProtocol, String> {
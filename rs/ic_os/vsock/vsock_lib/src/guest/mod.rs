@@ -1,6 +1,8 @@
-mod client;
+pub(crate) mod client;
 mod get_protocol_version;
+pub(crate) mod transfer;
 use crate::protocol::{Command, Request, Response, VsockProtocol};
+pub use transfer::{download_from_host, upload_to_host};
 
 /// Send a command to the host vsock server
 pub fn send_command(command: Command, port: u32) -> Response {
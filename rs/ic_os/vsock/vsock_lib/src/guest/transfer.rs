@@ -0,0 +1,175 @@
+//! The guest side of the stream transfers, see [crate::protocol::send_stream].
+use crate::guest::client::{create_stream, send_request_to_host};
+use crate::protocol::{
+    parse_response, read_control, receive_stream, send_stream, sha256_hex, Command, Payload,
+    Request, Response, StreamDownloadData, StreamInfo, StreamSource, StreamTarget,
+    StreamUploadData, VsockProtocol,
+};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// How long to wait for the host, which may hash the whole transfer before
+/// it answers.
+const STREAM_READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Downloads `source` from the host into the file at `target`. If `target`
+/// holds the beginning of an interrupted download, only the rest of it is
+/// transferred. The file is removed if it does not match the hash the host
+/// announced.
+pub fn download_from_host(source: StreamSource, target: &Path, port: u32) -> Response {
+    let guest_cid = vsock::get_local_cid().map_err(|e| e.to_string())?;
+    check_host_supports_streams(&port, guest_cid)?;
+    let mut stream = create_stream(&port).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(STREAM_READ_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+
+    download_over_stream(&mut stream, guest_cid, source, target)
+}
+
+/// Uploads the file at `source` to `target` on the host. If an upload of the
+/// same contents was interrupted, only the bytes the host is missing are
+/// transferred.
+pub fn upload_to_host(source: &Path, target: StreamTarget, port: u32) -> Response {
+    let guest_cid = vsock::get_local_cid().map_err(|e| e.to_string())?;
+    check_host_supports_streams(&port, guest_cid)?;
+    let mut stream = create_stream(&port).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(STREAM_READ_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+
+    upload_over_stream(&mut stream, guest_cid, source, target)
+}
+
+/// Stream transfers were added in version 1.1.0 of the host vsock.
+fn check_host_supports_streams(port: &u32, guest_cid: u32) -> Result<(), String> {
+    let request = Request {
+        guest_cid,
+        command: Command::GetVsockProtocol,
+    };
+    let response = send_request_to_host(&request, port, &VsockProtocol::V1)?;
+    match parse_response(&response, &VsockProtocol::V1) {
+        Ok(Payload::HostOSVsockVersion(version)) if version.major == 1 && version.minor >= 1 => {
+            Ok(())
+        }
+        Ok(Payload::HostOSVsockVersion(version)) => Err(format!(
+            "The host vsock {} does not support stream transfers",
+            version
+        )),
+        _ => Err("The host vsock does not support stream transfers".to_string()),
+    }
+}
+
+pub(crate) fn download_over_stream<S: Read + Write>(
+    stream: &mut S,
+    guest_cid: u32,
+    source: StreamSource,
+    target: &Path,
+) -> Response {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(target)
+        .map_err(|err| format!("Could not open {:?}: {}", target, err))?;
+    let offset = file
+        .metadata()
+        .map_err(|err| format!("Could not read {:?}: {}", target, err))?
+        .len();
+
+    let info = start_transfer(
+        stream,
+        Request {
+            guest_cid,
+            command: Command::StreamDownload(StreamDownloadData { source, offset }),
+        },
+    )?;
+    if info.offset != offset {
+        return Err(format!(
+            "The host streams from offset {} instead of {}",
+            info.offset, offset
+        ));
+    }
+    let received = receive_stream(stream, &mut file, info.offset, info.size_bytes)
+        .map_err(|e| e.to_string())?;
+    if received != info.size_bytes {
+        return Err(format!(
+            "The download ended after {} of {} bytes",
+            received, info.size_bytes
+        ));
+    }
+
+    let sha256 = File::open(target)
+        .and_then(sha256_hex)
+        .map_err(|err| format!("Could not read {:?}: {}", target, err))?;
+    if sha256 != info.sha256 {
+        let _ = std::fs::remove_file(target);
+        return Err(format!(
+            "The downloaded bytes do not match the expected hash, removed {:?}.
+Expected hash: {}
+Computed hash: {}",
+            target, info.sha256, sha256
+        ));
+    }
+
+    Ok(Payload::StreamInfo(info))
+}
+
+pub(crate) fn upload_over_stream<S: Read + Write>(
+    stream: &mut S,
+    guest_cid: u32,
+    source: &Path,
+    target: StreamTarget,
+) -> Response {
+    let mut file =
+        File::open(source).map_err(|err| format!("Could not open {:?}: {}", source, err))?;
+    let size_bytes = file
+        .metadata()
+        .map_err(|err| format!("Could not read {:?}: {}", source, err))?
+        .len();
+    let sha256 =
+        sha256_hex(&mut file).map_err(|err| format!("Could not read {:?}: {}", source, err))?;
+
+    let info = start_transfer(
+        stream,
+        Request {
+            guest_cid,
+            command: Command::StreamUpload(StreamUploadData {
+                target,
+                size_bytes,
+                sha256,
+            }),
+        },
+    )?;
+    if info.offset > size_bytes {
+        return Err(format!(
+            "The host resumes the upload at offset {}, beyond the {} bytes of {:?}",
+            info.offset, size_bytes, source
+        ));
+    }
+    file.seek(SeekFrom::Start(info.offset))
+        .map_err(|err| format!("Could not read {:?}: {}", source, err))?;
+    send_stream(
+        stream,
+        &mut file.take(size_bytes - info.offset),
+        info.offset,
+    )
+    .map_err(|e| e.to_string())?;
+
+    read_control::<_, Response>(stream).map_err(|e| e.to_string())?
+}
+
+/// Sends the request starting a transfer and returns the transfer accepted
+/// by the host.
+fn start_transfer<S: Read + Write>(stream: &mut S, request: Request) -> Result<StreamInfo, String> {
+    let json_request = serde_json::to_string(&request).map_err(|e| e.to_string())?;
+    stream
+        .write_all(json_request.as_bytes())
+        .map_err(|e| e.to_string())?;
+
+    match read_control::<_, Response>(stream).map_err(|e| e.to_string())?? {
+        Payload::StreamInfo(info) => Ok(info),
+        payload => Err(format!("Unexpected response from the host: {}", payload)),
+    }
+}
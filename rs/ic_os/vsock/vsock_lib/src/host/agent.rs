@@ -1,22 +1,41 @@
+use crate::host::attestation::get_attestation;
+use crate::host::backend::Backend;
 use crate::host::command_utilities::handle_command_output;
+use crate::host::disks::{attach_disk, detach_disk};
+use crate::host::firewall::update_firewall;
+use crate::host::guest_console::get_guest_console;
+use crate::host::hardware_health::get_hardware_health;
+use crate::host::host_metrics::get_host_metrics;
 use crate::host::hsm::{attach_hsm, detach_hsm};
-use crate::protocol::{
-    Command, HostOSVsockVersion, NodeIdData, NotifyData, Payload, Response, UpgradeData,
-};
-use sha2::Digest;
+use crate::host::upgrade::{get_upgrade_status, upgrade_hostos};
+use crate::protocol::{Command, HostOSVsockVersion, NodeIdData, NotifyData, Payload, Response};
 use std::fs::OpenOptions;
-use std::io::{Read, Write};
+use std::io::Write;
+use std::sync::Arc;
 
-pub fn dispatch(command: &Command) -> Response {
+pub fn dispatch(command: &Command, backend: &Arc<Backend>) -> Response {
     use Command::*;
     match command {
-        AttachHSM => attach_hsm(),
-        DetachHSM => detach_hsm(),
+        AttachHSM => attach_hsm(backend, None),
+        AttachHSMBySerial(hsm_serial_data) => attach_hsm(backend, Some(&hsm_serial_data.serial)),
+        DetachHSM => detach_hsm(backend),
         SetNodeId(node_id) => set_node_id(node_id),
-        Upgrade(upgrade_data) => upgrade_hostos(upgrade_data),
+        Upgrade(upgrade_data) => upgrade_hostos(upgrade_data, backend),
+        GetUpgradeStatus => get_upgrade_status(backend),
         Notify(notify_data) => notify(notify_data),
         GetVsockProtocol => get_hostos_vsock_version(),
         GetHostOSVersion => get_hostos_version(),
+        GetHardwareHealth => get_hardware_health(),
+        GetHostMetrics => get_host_metrics(),
+        GetGuestConsole(guest_console_data) => get_guest_console(guest_console_data, backend),
+        UpdateFirewall(ruleset) => update_firewall(ruleset, backend),
+        AttachDisk(disk_data) => attach_disk(disk_data, backend),
+        DetachDisk(source) => detach_disk(source, backend),
+        GetAttestation(attestation_data) => get_attestation(attestation_data, backend),
+        // Served by the server, which streams the answer.
+        StreamDownload(_) | StreamUpload(_) => {
+            Err("Stream commands cannot be dispatched".to_string())
+        }
     }
 }
 
@@ -27,13 +46,9 @@ const HOSTOS_VERSION_FILE_PATH: &str = "/opt/ic/share/version.txt";
 const NODE_ID_FILE_PATH: &str = "/boot/config/node-id";
 const SETUP_HOSTNAME_FILE_PATH: &str = "/opt/ic/bin/setup-hostname.sh";
 
-// upgrade
-const UPGRADE_FILE_PATH: &str = "/tmp/upgrade.tar.gz";
-const INSTALL_UPGRADE_FILE_PATH: &str = "/opt/ic/bin/install-upgrade.sh";
-
 const VSOCK_VERSION: HostOSVsockVersion = HostOSVsockVersion {
     major: 1,
-    minor: 0,
+    minor: 1,
     patch: 0,
 };
 
@@ -98,93 +113,3 @@ fn notify(notify_data: &NotifyData) -> Response {
 
     Ok(Payload::NoPayload)
 }
-
-fn create_hostos_upgrade_file(upgrade_url: &str) -> Result<(), String> {
-    let response =
-        reqwest::blocking::get(upgrade_url).map_err(|_| "Could not download url".to_string())?;
-
-    let hostos_upgrade_contents = response
-        .bytes()
-        .map_err(|_| "Could not read downloaded contents".to_string())?;
-
-    let mut upgrade_file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .create(true)
-        .open(UPGRADE_FILE_PATH)
-        .map_err(|_| "Could not open upgrade file".to_string())?;
-    upgrade_file
-        .write_all(&hostos_upgrade_contents)
-        .map_err(|_| "Could not write to upgrade file".to_string())?;
-    upgrade_file
-        .flush()
-        .map_err(|_| "Could not flush upgrade file".to_string())?;
-
-    Ok(())
-}
-
-fn verify_hash(target_hash: &str) -> Result<bool, String> {
-    let mut upgrade_file = match std::fs::File::open(UPGRADE_FILE_PATH) {
-        Ok(upgrade_file) => upgrade_file,
-        Err(err) => return Err(err.to_string()),
-    };
-
-    let mut hasher = sha2::Sha256::new();
-    let mut buffer = [0; 65536];
-
-    loop {
-        let bytes_read = upgrade_file.read(&mut buffer).unwrap();
-        if bytes_read == 0 {
-            break;
-        }
-        hasher.update(&buffer[..bytes_read]);
-    }
-
-    let computed_hash = format!("{:x}", hasher.finalize());
-
-    if computed_hash == target_hash {
-        Ok(true)
-    } else {
-        Err(format!(
-            "Target hash does not equal computed hash.
-Target hash: {target_hash}
-Computed hash: {computed_hash}"
-        ))
-    }
-}
-
-fn run_upgrade() -> Response {
-    let command_output = std::process::Command::new(INSTALL_UPGRADE_FILE_PATH)
-        .arg(UPGRADE_FILE_PATH)
-        .output();
-
-    handle_command_output(command_output)?;
-
-    let command_output = std::process::Command::new("reboot").output();
-
-    handle_command_output(command_output)
-}
-
-fn upgrade_hostos(upgrade_data: &UpgradeData) -> Response {
-    println!("Creating hostos upgrade file...");
-    create_hostos_upgrade_file(&upgrade_data.url)?;
-
-    println!("Verifying hostos upgrade file hash...");
-    verify_hash(&upgrade_data.target_hash)?;
-
-    println!("Starting upgrade...");
-    run_upgrade()
-}
-
-pub mod tests {
-    #[test]
-    fn create_hostos_upgrade_file_and_verify_hash() {
-        use super::*;
-
-        let upgrade_url = std::env::var("URL").unwrap_or_else(|_| "dummy url".to_string());
-        let hash = std::env::var("HASH").unwrap_or_else(|_| "dummy hash".to_string());
-
-        create_hostos_upgrade_file(&upgrade_url).unwrap();
-        assert!(verify_hash(&hash).unwrap())
-    }
-}
@@ -0,0 +1,125 @@
+use crate::host::backend::Backend;
+use crate::protocol::{AttestationData, HostAttestation, Payload, PcrValue, Response};
+
+/// The PCRs covering the measured boot of the HostOS: the firmware and its
+/// configuration (0-7), and the kernel, initrd and command line measured by
+/// grub (8, 9).
+const QUOTED_PCRS: [u32; 10] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+
+const MAX_NONCE_BYTES: usize = 32;
+
+/// Returns a TPM quote over the measured boot state of the host, covering the
+/// nonce of the guest.
+///
+/// There is no SEV-SNP report: the host is the hypervisor, and only a SEV-SNP
+/// guest can request reports, through its own `/dev/sev-guest`. The GuestOS
+/// produces its report itself and can put the nonce in its report data.
+pub fn get_attestation(attestation_data: &AttestationData, backend: &Backend) -> Response {
+    let nonce = from_hex(&attestation_data.nonce)
+        .ok_or_else(|| format!("Invalid nonce: {}", attestation_data.nonce))?;
+    if nonce.is_empty() || nonce.len() > MAX_NONCE_BYTES {
+        return Err(format!(
+            "The nonce must have 1 to {} bytes, got {}",
+            MAX_NONCE_BYTES,
+            nonce.len()
+        ));
+    }
+
+    let tpm_quote = backend.attester.tpm_quote(&nonce, &QUOTED_PCRS)?;
+
+    Ok(Payload::Attestation(HostAttestation { tpm_quote }))
+}
+
+/// Parses the PCR values of the SHA-256 bank from the output of
+/// `tpm2_quote`, which lists them like:
+/// ```text
+/// pcrs:
+///   sha256:
+///     0 : 0x3D458CFE55CC03EA1F443F1562BEEC8DF51C75E14A9FCF9A7234A13F198E7969
+/// ```
+pub(crate) fn parse_quoted_pcrs(output: &str) -> Result<Vec<PcrValue>, String> {
+    let lines = output
+        .lines()
+        .skip_while(|line| line.trim() != "pcrs:")
+        .skip_while(|line| line.trim() != "sha256:")
+        .skip(1);
+    let mut pcrs = Vec::new();
+    for line in lines {
+        let (index, value) = match line.split_once(':') {
+            Some((index, value)) => (index.trim(), value.trim()),
+            None => break,
+        };
+        let index = match index.parse() {
+            Ok(index) => index,
+            // The next bank or section.
+            Err(_) => break,
+        };
+        let value = value.strip_prefix("0x").unwrap_or(value).to_lowercase();
+        if value.len() != 64 || from_hex(&value).is_none() {
+            return Err(format!("Invalid value of PCR {}: {}", index, value));
+        }
+        pcrs.push(PcrValue {
+            index,
+            sha256: value,
+        });
+    }
+    if pcrs.is_empty() {
+        return Err("No SHA-256 PCR values in the output of tpm2_quote".to_string());
+    }
+    Ok(pcrs)
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_quoted_pcrs() {
+        let output = "quoted: ff54434780180022000b\n\
+                      signature:\n  alg: rsassa\n  sig: 9a0b\n\
+                      pcrs:\n  sha1:\n    0 : 0x0123456789ABCDEF0123456789ABCDEF01234567\n  \
+                      sha256:\n    \
+                      0 : 0x3D458CFE55CC03EA1F443F1562BEEC8DF51C75E14A9FCF9A7234A13F198E7969\n    \
+                      7 : 0x65CAF8DD1E0EA7A6347B635D2B379C93B9A1351EDC2AFC3ECDA700E534EB3068\n\
+                      calcDigest: 0f3a\n";
+        assert_eq!(
+            parse_quoted_pcrs(output),
+            Ok(vec![
+                PcrValue {
+                    index: 0,
+                    sha256: "3d458cfe55cc03ea1f443f1562beec8df51c75e14a9fcf9a7234a13f198e7969"
+                        .to_string(),
+                },
+                PcrValue {
+                    index: 7,
+                    sha256: "65caf8dd1e0ea7a6347b635d2b379c93b9a1351edc2afc3ecda700e534eb3068"
+                        .to_string(),
+                },
+            ])
+        );
+        assert!(parse_quoted_pcrs("pcrs:\n  sha256:\n    0 : 0x1234\n").is_err());
+        assert!(parse_quoted_pcrs("quoted: ff54\n").is_err());
+    }
+
+    #[test]
+    fn hex_roundtrip() {
+        assert_eq!(to_hex(&[0, 0xab, 0x10]), "00ab10");
+        assert_eq!(from_hex("00AB10"), Some(vec![0, 0xab, 0x10]));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+}
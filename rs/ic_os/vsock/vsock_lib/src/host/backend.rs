@@ -0,0 +1,417 @@
+use crate::host::attestation::{parse_quoted_pcrs, to_hex};
+use crate::host::command_utilities::handle_command_output;
+use crate::protocol::{Response, TpmQuote, UpgradeStage};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tempfile::NamedTempFile;
+
+const UPGRADE_FILE_PATH: &str = "/tmp/upgrade.tar.gz";
+const INSTALL_UPGRADE_FILE_PATH: &str = "/opt/ic/bin/install-upgrade.sh";
+// The boot slot and boot cycle, written by manageboot.sh and read by grub.
+const GRUBENV_PATH: &str = "/boot/grub/grubenv";
+// The serial console of the guestos domain is logged to this file, see guestos.xml.template.
+const GUEST_CONSOLE_LOG_PATH: &str = "/var/log/libvirt/qemu/guestos-serial.log";
+// The last firewall ruleset applied on request of the guest, and the log of all requests.
+const FIREWALL_RULESET_PATH: &str = "/var/lib/vsock/firewall_allowlist.nft";
+const FIREWALL_AUDIT_LOG_PATH: &str = "/var/log/vsock-firewall-audit.log";
+// The disks attached to the guest on its request.
+const GUEST_DISKS_PATH: &str = "/var/lib/vsock/guest_disks.json";
+// The persistent handle of the attestation key of the TPM. The host agent
+// does not create the key, it must be provisioned beforehand.
+const ATTESTATION_KEY_HANDLE: &str = "0x81010002";
+
+/// A USB device as seen by the host.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UsbDevice {
+    pub bus_number: u8,
+    pub address: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    /// The serial number string descriptor, if the device has one and it
+    /// could be read.
+    pub serial_number: Option<String>,
+}
+
+/// State the host agent keeps across requests.
+#[derive(Default)]
+pub struct HostState {
+    /// The HSM currently attached to the guest, if any.
+    pub attached_hsm: Option<UsbDevice>,
+    /// The progress of the last upgrade requested since the HostOS booted.
+    pub upgrade_stage: UpgradeStage,
+    /// Whether the guest is uploading an upgrade image. Uploads and upgrades
+    /// exclude each other, since both write the upgrade file.
+    pub upload_in_progress: bool,
+}
+
+/// Enumerates the devices physically attached to the host.
+pub trait DeviceEnumerator: Send + Sync {
+    fn usb_devices(&self) -> Result<Vec<UsbDevice>, String>;
+}
+
+/// Attaches devices to and detaches them from a libvirt domain.
+pub trait DomainManager: Send + Sync {
+    fn attach_device(&self, domain: &str, device_xml: &str) -> Response;
+    fn detach_device(&self, domain: &str, device_xml: &str) -> Response;
+    /// Like `attach_device`, but also adds the device to the persistent
+    /// definition of the domain, so that it is still attached after the
+    /// domain restarts.
+    fn attach_device_persistent(&self, domain: &str, device_xml: &str) -> Response;
+    fn detach_device_persistent(&self, domain: &str, device_xml: &str) -> Response;
+}
+
+/// Inspects and creates the host volumes and files backing guest disks.
+pub trait DiskStorage: Send + Sync {
+    /// The size of the block device or file at `path`, `None` if it doesn't
+    /// exist.
+    fn disk_size(&self, path: &Path) -> Result<Option<u64>, String>;
+    /// Creates a sparse raw image file of `size_bytes` at `path`.
+    fn create_raw_file(&self, path: &Path, size_bytes: u64) -> Result<(), String>;
+    /// The bytes available on the file system that holds `dir`.
+    fn available_bytes(&self, dir: &Path) -> Result<u64, String>;
+}
+
+/// Downloads and installs HostOS upgrade images.
+pub trait Upgrader: Send + Sync {
+    fn download(&self, url: &str, target: &Path) -> Result<(), String>;
+    fn install(&self, image: &Path) -> Response;
+    fn reboot(&self) -> Response;
+    /// The grub environment block, which records the boot slot and whether
+    /// its boot was confirmed.
+    fn read_grubenv(&self) -> Result<String, String>;
+}
+
+/// Produces attestation evidence of the host.
+pub trait Attester: Send + Sync {
+    /// A quote by the TPM over the PCRs `pcrs` of the SHA-256 bank,
+    /// qualified by `nonce`.
+    fn tpm_quote(&self, nonce: &[u8], pcrs: &[u32]) -> Result<TpmQuote, String>;
+}
+
+/// Loads nftables scripts.
+pub trait Firewall: Send + Sync {
+    /// Applies `nft_script` atomically: either all of it takes effect or, if
+    /// it fails, none of it.
+    fn apply(&self, nft_script: &str) -> Response;
+}
+
+/// Everything the host agent needs to interact with the host system. The
+/// production backend talks to libusb, virsh and the HostOS upgrade scripts,
+/// while tests can provide in-memory implementations.
+pub struct Backend {
+    pub devices: Box<dyn DeviceEnumerator>,
+    pub domain: Box<dyn DomainManager>,
+    pub upgrader: Box<dyn Upgrader>,
+    pub firewall: Box<dyn Firewall>,
+    pub disks: Box<dyn DiskStorage>,
+    pub attester: Box<dyn Attester>,
+    pub upgrade_file_path: PathBuf,
+    pub guest_console_log_path: PathBuf,
+    pub firewall_ruleset_path: PathBuf,
+    pub firewall_audit_log_path: PathBuf,
+    pub guest_disks_path: PathBuf,
+    pub state: Mutex<HostState>,
+}
+
+impl Backend {
+    pub fn system() -> Self {
+        Self {
+            devices: Box::new(LibusbDeviceEnumerator),
+            domain: Box::new(VirshDomainManager),
+            upgrader: Box::new(SystemUpgrader),
+            firewall: Box::new(NftFirewall),
+            disks: Box::new(SystemDiskStorage),
+            attester: Box::new(Tpm2ToolsAttester),
+            upgrade_file_path: PathBuf::from(UPGRADE_FILE_PATH),
+            guest_console_log_path: PathBuf::from(GUEST_CONSOLE_LOG_PATH),
+            firewall_ruleset_path: PathBuf::from(FIREWALL_RULESET_PATH),
+            firewall_audit_log_path: PathBuf::from(FIREWALL_AUDIT_LOG_PATH),
+            guest_disks_path: PathBuf::from(GUEST_DISKS_PATH),
+            state: Mutex::new(HostState::default()),
+        }
+    }
+}
+
+pub struct LibusbDeviceEnumerator;
+
+impl DeviceEnumerator for LibusbDeviceEnumerator {
+    fn usb_devices(&self) -> Result<Vec<UsbDevice>, String> {
+        let context = libusb::Context::new().map_err(|e| e.to_string())?;
+        let usb_devices = context.devices().map_err(|e| e.to_string())?;
+
+        Ok(usb_devices
+            .iter()
+            .filter_map(|device| {
+                let device_descriptor = match device.device_descriptor() {
+                    Ok(device_descriptor) => device_descriptor,
+                    Err(_) => {
+                        println!("Error: device.device_descriptor() returned error");
+                        return None;
+                    }
+                };
+                println!(
+                    "Bus {:03} Device {:03} ID {:04x}:{:04x}",
+                    device.bus_number(),
+                    device.address(),
+                    device_descriptor.vendor_id(),
+                    device_descriptor.product_id()
+                );
+                // Reading the serial number requires opening the device, which
+                // may fail for devices that are in use or lack permissions.
+                let serial_number = device.open().ok().and_then(|handle| {
+                    handle
+                        .read_serial_number_string_ascii(&device_descriptor)
+                        .ok()
+                });
+                Some(UsbDevice {
+                    bus_number: device.bus_number(),
+                    address: device.address(),
+                    vendor_id: device_descriptor.vendor_id(),
+                    product_id: device_descriptor.product_id(),
+                    serial_number,
+                })
+            })
+            .collect())
+    }
+}
+
+pub struct VirshDomainManager;
+
+impl VirshDomainManager {
+    fn run(&self, command: &str, flags: &[&str], domain: &str, device_xml: &str) -> Response {
+        let xml_file = write_to_temp_file(device_xml)?;
+
+        println!("Sending virsh command: {command}");
+        let command_output = std::process::Command::new("virsh")
+            .arg(command)
+            .arg(domain)
+            .arg("--file")
+            .arg(xml_file.path())
+            .args(flags)
+            .output();
+
+        handle_command_output(command_output)
+    }
+}
+
+impl DomainManager for VirshDomainManager {
+    fn attach_device(&self, domain: &str, device_xml: &str) -> Response {
+        self.run("attach-device", &[], domain, device_xml)
+    }
+
+    fn detach_device(&self, domain: &str, device_xml: &str) -> Response {
+        self.run("detach-device", &[], domain, device_xml)
+    }
+
+    fn attach_device_persistent(&self, domain: &str, device_xml: &str) -> Response {
+        self.run("attach-device", &["--persistent"], domain, device_xml)
+    }
+
+    fn detach_device_persistent(&self, domain: &str, device_xml: &str) -> Response {
+        self.run("detach-device", &["--persistent"], domain, device_xml)
+    }
+}
+
+pub struct SystemDiskStorage;
+
+impl DiskStorage for SystemDiskStorage {
+    fn disk_size(&self, path: &Path) -> Result<Option<u64>, String> {
+        let metadata = match std::fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(format!("Could not inspect {:?}: {}", path, err)),
+        };
+        if !metadata.file_type().is_block_device() {
+            return Ok(Some(metadata.len()));
+        }
+        // The metadata of block devices has no size.
+        let output = std::process::Command::new("blockdev")
+            .arg("--getsize64")
+            .arg(path)
+            .output()
+            .map_err(|err| format!("Could not run blockdev --getsize64: {}", err))?;
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).to_string());
+        }
+        let size = String::from_utf8_lossy(&output.stdout);
+        size.trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("Could not read the size of {:?}: {}", path, size.trim()))
+    }
+
+    fn create_raw_file(&self, path: &Path, size_bytes: u64) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|err| format!("Could not create {:?}: {}", path, err))?;
+        file.set_len(size_bytes)
+            .map_err(|err| format!("Could not resize {:?}: {}", path, err))
+    }
+
+    fn available_bytes(&self, dir: &Path) -> Result<u64, String> {
+        // The directory of the raw files is only created with the first of
+        // them, so ask for the file system of its closest existing ancestor.
+        let existing = dir
+            .ancestors()
+            .find(|ancestor| ancestor.exists())
+            .unwrap_or_else(|| Path::new("/"));
+        let output = std::process::Command::new("df")
+            .arg("--output=avail")
+            .arg("--block-size=1")
+            .arg(existing)
+            .output()
+            .map_err(|err| format!("Could not run df: {}", err))?;
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).to_string());
+        }
+        // The first line is the header of the column.
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let available = stdout.lines().nth(1).unwrap_or_default().trim();
+        available.parse().map_err(|_| {
+            format!(
+                "Could not read the available space of {:?}: {}",
+                existing, available
+            )
+        })
+    }
+}
+
+pub struct SystemUpgrader;
+
+impl Upgrader for SystemUpgrader {
+    fn download(&self, url: &str, target: &Path) -> Result<(), String> {
+        let response =
+            reqwest::blocking::get(url).map_err(|_| "Could not download url".to_string())?;
+
+        let hostos_upgrade_contents = response
+            .bytes()
+            .map_err(|_| "Could not read downloaded contents".to_string())?;
+
+        let mut upgrade_file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(target)
+            .map_err(|_| "Could not open upgrade file".to_string())?;
+        upgrade_file
+            .write_all(&hostos_upgrade_contents)
+            .map_err(|_| "Could not write to upgrade file".to_string())?;
+        upgrade_file
+            .flush()
+            .map_err(|_| "Could not flush upgrade file".to_string())?;
+
+        Ok(())
+    }
+
+    fn install(&self, image: &Path) -> Response {
+        let command_output = std::process::Command::new(INSTALL_UPGRADE_FILE_PATH)
+            .arg(image)
+            .output();
+
+        handle_command_output(command_output)
+    }
+
+    fn reboot(&self) -> Response {
+        let command_output = std::process::Command::new("reboot").output();
+
+        handle_command_output(command_output)
+    }
+
+    fn read_grubenv(&self) -> Result<String, String> {
+        std::fs::read_to_string(GRUBENV_PATH)
+            .map_err(|err| format!("Could not read {}: {}", GRUBENV_PATH, err))
+    }
+}
+
+/// Produces the evidence with `tpm2-tools`.
+pub struct Tpm2ToolsAttester;
+
+impl Attester for Tpm2ToolsAttester {
+    fn tpm_quote(&self, nonce: &[u8], pcrs: &[u32]) -> Result<TpmQuote, String> {
+        let dir = tempfile::tempdir().map_err(|_| "Could not create temp dir".to_string())?;
+        let pcr_list: Vec<String> = pcrs.iter().map(|pcr| pcr.to_string()).collect();
+        let message_path = dir.path().join("quote.msg");
+        let signature_path = dir.path().join("quote.sig");
+        let attestation_key_path = dir.path().join("ak.pem");
+
+        println!("Quoting PCRs {}", pcr_list.join(","));
+        let output = run_tpm2_tool(
+            std::process::Command::new("tpm2_quote")
+                .arg("--key-context")
+                .arg(ATTESTATION_KEY_HANDLE)
+                .arg("--pcr-list")
+                .arg(format!("sha256:{}", pcr_list.join(",")))
+                .arg("--qualification")
+                .arg(to_hex(nonce))
+                .arg("--hash-algorithm")
+                .arg("sha256")
+                .arg("--message")
+                .arg(&message_path)
+                .arg("--signature")
+                .arg(&signature_path),
+        )?;
+        run_tpm2_tool(
+            std::process::Command::new("tpm2_readpublic")
+                .arg("--object-context")
+                .arg(ATTESTATION_KEY_HANDLE)
+                .arg("--format")
+                .arg("pem")
+                .arg("--output")
+                .arg(&attestation_key_path),
+        )?;
+
+        let read = |path: &Path| {
+            std::fs::read(path).map_err(|err| format!("Could not read {:?}: {}", path, err))
+        };
+        Ok(TpmQuote {
+            message: to_hex(&read(&message_path)?),
+            signature: to_hex(&read(&signature_path)?),
+            pcrs: parse_quoted_pcrs(&output)?,
+            attestation_key_pem: String::from_utf8_lossy(&read(&attestation_key_path)?).to_string(),
+        })
+    }
+}
+
+fn run_tpm2_tool(command: &mut std::process::Command) -> Result<String, String> {
+    let output = command
+        .output()
+        .map_err(|err| format!("Could not run {:?}: {}", command, err))?;
+    if !output.status.success() {
+        return Err(format!(
+            "Command {:?} failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+pub struct NftFirewall;
+
+impl Firewall for NftFirewall {
+    fn apply(&self, nft_script: &str) -> Response {
+        let script_file = write_to_temp_file(nft_script)?;
+
+        println!("Applying nftables script");
+        let command_output = std::process::Command::new("nft")
+            .arg("-f")
+            .arg(script_file.path())
+            .output();
+
+        handle_command_output(command_output)
+    }
+}
+
+fn write_to_temp_file(content: &str) -> Result<NamedTempFile, String> {
+    let mut file = NamedTempFile::new().map_err(|_| "Could not create temp file".to_string())?;
+    write!(file, "{content}").map_err(|_| "Could not write to temp file".to_string())?;
+    Ok(file)
+}
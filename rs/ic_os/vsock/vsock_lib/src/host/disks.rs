@@ -0,0 +1,252 @@
+use crate::host::backend::Backend;
+use crate::host::hsm::DOMAIN_NAME;
+use crate::protocol::{DiskData, DiskSource, Payload, Response};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+const VOLUME_GROUP_DIR: &str = "/dev/hostlvm";
+// The other volumes of the volume group hold the HostOS and the disk of the
+// guest itself, which must not be attached a second time.
+const VOLUME_NAME_PREFIX: &str = "guestos_extra_";
+const RAW_FILE_DIR: &str = "/var/lib/libvirt/images/guestos-disks";
+
+const MIN_DISK_SIZE_BYTES: u64 = 1 << 30;
+const MAX_DISK_SIZE_BYTES: u64 = 16 << 40;
+const MAX_NAME_LENGTH: usize = 64;
+
+/// A disk attached to the guest, as recorded by the host.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct AttachedDisk {
+    source: DiskSource,
+    size_bytes: u64,
+    /// The virtio device of the disk in the guest, e.g. `vdb`.
+    target: String,
+}
+
+/// Attaches a host volume or raw file to the guest as a virtio disk. The disk
+/// is added to the persistent definition of the guest domain, so that it
+/// stays attached when the guest reboots, and recorded, so that it can be
+/// detached again by source.
+pub fn attach_disk(disk_data: &DiskData, backend: &Backend) -> Response {
+    // Serializes concurrent changes of the recorded disks.
+    let _state = backend
+        .state
+        .lock()
+        .map_err(|_| "Could not lock host state".to_string())?;
+
+    let path = source_path(&disk_data.source)?;
+    let mut disks = load_attached_disks(&backend.guest_disks_path)?;
+    if disks.iter().any(|disk| disk.source == disk_data.source) {
+        return Err(format!("Disk already attached: {}", disk_data.source));
+    }
+    if !(MIN_DISK_SIZE_BYTES..=MAX_DISK_SIZE_BYTES).contains(&disk_data.size_bytes) {
+        return Err(format!(
+            "Disk size of {} bytes is out of range [{}, {}]",
+            disk_data.size_bytes, MIN_DISK_SIZE_BYTES, MAX_DISK_SIZE_BYTES
+        ));
+    }
+    match backend.disks.disk_size(&path)? {
+        Some(size) if size == disk_data.size_bytes => {}
+        Some(size) => {
+            return Err(format!(
+                "Disk {} has {} bytes, expected {}",
+                disk_data.source, size, disk_data.size_bytes
+            ))
+        }
+        None => match &disk_data.source {
+            DiskSource::RawFile(_) => {
+                // Raw files are sparse, so a file larger than the free space
+                // would only fail once the guest fills it, and take the
+                // HostOS down with it.
+                let available = backend.disks.available_bytes(Path::new(RAW_FILE_DIR))?;
+                if disk_data.size_bytes > available {
+                    return Err(format!(
+                        "Disk size of {} bytes exceeds the {} bytes available for raw files",
+                        disk_data.size_bytes, available
+                    ));
+                }
+                println!("Creating raw file {:?}", path);
+                backend.disks.create_raw_file(&path, disk_data.size_bytes)?;
+            }
+            DiskSource::LvmVolume(_) => {
+                return Err(format!("No such logical volume: {:?}", path));
+            }
+        },
+    }
+
+    let disk = AttachedDisk {
+        source: disk_data.source.clone(),
+        size_bytes: disk_data.size_bytes,
+        target: free_target(&disks).ok_or_else(|| "No free disk target left".to_string())?,
+    };
+    println!("Attaching disk {} as {}", disk.source, disk.target);
+    let disk_xml = get_disk_xml_string(&path, &disk);
+    backend
+        .domain
+        .attach_device_persistent(DOMAIN_NAME, &disk_xml)?;
+
+    disks.push(disk);
+    if let Err(err) = save_attached_disks(&backend.guest_disks_path, &disks) {
+        // An unrecorded disk couldn't be detached by source, so don't keep it.
+        let rollback = match backend
+            .domain
+            .detach_device_persistent(DOMAIN_NAME, &disk_xml)
+        {
+            Ok(_) => "detached it again".to_string(),
+            Err(detach_err) => format!("could not detach it again: {}", detach_err),
+        };
+        return Err(format!(
+            "Could not record the attached disk, {}: {}",
+            rollback, err
+        ));
+    }
+
+    Ok(Payload::NoPayload)
+}
+
+/// Detaches a disk attached by `attach_disk` from the guest.
+pub fn detach_disk(source: &DiskSource, backend: &Backend) -> Response {
+    let _state = backend
+        .state
+        .lock()
+        .map_err(|_| "Could not lock host state".to_string())?;
+
+    let path = source_path(source)?;
+    let mut disks = load_attached_disks(&backend.guest_disks_path)?;
+    let index = disks
+        .iter()
+        .position(|disk| &disk.source == source)
+        .ok_or_else(|| format!("Disk not attached: {}", source))?;
+    println!("Detaching disk {} from {}", source, disks[index].target);
+    backend
+        .domain
+        .detach_device_persistent(DOMAIN_NAME, &get_disk_xml_string(&path, &disks[index]))?;
+
+    disks.remove(index);
+    save_attached_disks(&backend.guest_disks_path, &disks)?;
+
+    Ok(Payload::NoPayload)
+}
+
+/// Returns the host path of `source`, refusing names that would escape the
+/// volume group or directory of guest disks.
+fn source_path(source: &DiskSource) -> Result<PathBuf, String> {
+    let name = match source {
+        DiskSource::LvmVolume(name) | DiskSource::RawFile(name) => name,
+    };
+    let valid_name = !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && !name.starts_with(['-', '.'])
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+    if !valid_name {
+        return Err(format!("Invalid disk name: {:?}", name));
+    }
+    match source {
+        DiskSource::LvmVolume(name) if name.starts_with(VOLUME_NAME_PREFIX) => {
+            Ok(Path::new(VOLUME_GROUP_DIR).join(name))
+        }
+        DiskSource::LvmVolume(name) => Err(format!(
+            "Only logical volumes named {}* can be attached, not {}",
+            VOLUME_NAME_PREFIX, name
+        )),
+        DiskSource::RawFile(name) => Ok(Path::new(RAW_FILE_DIR).join(name)),
+    }
+}
+
+/// The first virtio device not used by `disks`. `vda` is the disk of the
+/// guest itself.
+fn free_target(disks: &[AttachedDisk]) -> Option<String> {
+    ('b'..='z')
+        .map(|letter| format!("vd{}", letter))
+        .find(|target| disks.iter().all(|disk| &disk.target != target))
+}
+
+fn load_attached_disks(path: &Path) -> Result<Vec<AttachedDisk>, String> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .map_err(|err| format!("Could not parse the attached disks {:?}: {}", path, err)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(err) => Err(format!(
+            "Could not read the attached disks {:?}: {}",
+            path, err
+        )),
+    }
+}
+
+/// Writes `disks` to `path`, replacing the file atomically.
+fn save_attached_disks(path: &Path, disks: &[AttachedDisk]) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+    }
+    let contents = serde_json::to_string_pretty(disks).map_err(|err| err.to_string())?;
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, contents).map_err(|err| err.to_string())?;
+    std::fs::rename(&tmp_path, path).map_err(|err| err.to_string())
+}
+
+fn get_disk_xml_string(path: &Path, disk: &AttachedDisk) -> String {
+    let (disk_type, source) = match disk.source {
+        DiskSource::LvmVolume(_) => ("block", format!("dev='{}'", path.display())),
+        DiskSource::RawFile(_) => ("file", format!("file='{}'", path.display())),
+    };
+    format!(
+        "
+<disk type='{0}' device='disk'>
+    <driver name='qemu' type='raw'/>
+    <source {1}/>
+    <target dev='{2}' bus='virtio'/>
+</disk>
+",
+        disk_type, source, disk.target
+    )
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_sources() {
+        assert_eq!(
+            source_path(&DiskSource::LvmVolume("guestos_extra_data".to_string())),
+            Ok(PathBuf::from("/dev/hostlvm/guestos_extra_data"))
+        );
+        assert_eq!(
+            source_path(&DiskSource::RawFile("data-1.img".to_string())),
+            Ok(PathBuf::from(
+                "/var/lib/libvirt/images/guestos-disks/data-1.img"
+            ))
+        );
+        for invalid in [
+            DiskSource::LvmVolume("guestos".to_string()),
+            DiskSource::LvmVolume("A_var".to_string()),
+            DiskSource::RawFile("../../../dev/hostlvm/guestos".to_string()),
+            DiskSource::RawFile("sub/data.img".to_string()),
+            DiskSource::RawFile("".to_string()),
+        ] {
+            assert!(source_path(&invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn get_disk_xml_string() {
+        let disk = AttachedDisk {
+            source: DiskSource::LvmVolume("guestos_extra_data".to_string()),
+            size_bytes: 1 << 40,
+            target: "vdb".to_string(),
+        };
+        let actual =
+            super::get_disk_xml_string(Path::new("/dev/hostlvm/guestos_extra_data"), &disk);
+
+        let expected = "
+<disk type='block' device='disk'>
+    <driver name='qemu' type='raw'/>
+    <source dev='/dev/hostlvm/guestos_extra_data'/>
+    <target dev='vdb' bus='virtio'/>
+</disk>
+";
+        assert_eq!(actual, expected)
+    }
+}
@@ -0,0 +1,225 @@
+use crate::host::backend::Backend;
+use crate::protocol::{FirewallRule, FirewallRulesetData, Payload, Response};
+use sha2::Digest;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// The chain of the filter tables that INPUT jumps to, see /etc/nftables.conf.
+const ALLOWLIST_CHAIN: &str = "REGISTRY_ALLOWLIST";
+
+const MAX_RULES: usize = 2000;
+// nftables limits comments to 128 bytes.
+const MAX_COMMENT_LENGTH: usize = 128;
+
+/// Replaces the allowlist of the HostOS firewall by `ruleset`. The ruleset is
+/// validated, then loaded in a single nftables transaction, so that a ruleset
+/// that fails to load leaves the previous one in place. Every request is
+/// recorded in the audit log, whether it succeeds or not.
+pub fn update_firewall(ruleset: &FirewallRulesetData, backend: &Backend) -> Response {
+    // Serializes concurrent updates.
+    let _state = backend
+        .state
+        .lock()
+        .map_err(|_| "Could not lock host state".to_string())?;
+
+    let result = validate(ruleset).and_then(|_| apply(&render(ruleset), backend));
+    audit(&backend.firewall_audit_log_path, ruleset, &result);
+    result
+}
+
+fn apply(nft_script: &str, backend: &Backend) -> Response {
+    let previous_script = std::fs::read_to_string(&backend.firewall_ruleset_path).ok();
+    backend.firewall.apply(nft_script)?;
+
+    if let Err(err) = persist(&backend.firewall_ruleset_path, nft_script) {
+        // Roll back, so that the loaded ruleset is the persisted one.
+        let previous_script =
+            previous_script.unwrap_or_else(|| render(&FirewallRulesetData::default()));
+        let rollback = match backend.firewall.apply(&previous_script) {
+            Ok(_) => "rolled back".to_string(),
+            Err(rollback_err) => format!("could not roll back: {}", rollback_err),
+        };
+        return Err(format!(
+            "Could not persist the firewall ruleset, {}: {}",
+            rollback, err
+        ));
+    }
+
+    println!("Firewall ruleset applied");
+    Ok(Payload::NoPayload)
+}
+
+/// Writes `nft_script` to `path`, replacing the file atomically.
+fn persist(path: &Path, nft_script: &str) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+    }
+    let tmp_path = path.with_extension("nft.tmp");
+    std::fs::write(&tmp_path, nft_script).map_err(|err| err.to_string())?;
+    std::fs::rename(&tmp_path, path).map_err(|err| err.to_string())
+}
+
+fn audit(audit_log_path: &Path, ruleset: &FirewallRulesetData, result: &Response) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let ruleset_hash = format!(
+        "{:x}",
+        sha2::Sha256::digest(serde_json::to_vec(ruleset).unwrap_or_default())
+    );
+    let outcome = match result {
+        Ok(_) => "applied".to_string(),
+        Err(err) => format!("rejected: {}", err.replace('\n', " ")),
+    };
+    let line = format!(
+        "{} update-firewall registry_version={} rules={} sha256={} {}\n",
+        timestamp,
+        ruleset.registry_version,
+        ruleset.rules.len(),
+        ruleset_hash,
+        outcome
+    );
+    print!("{}", line);
+
+    let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(audit_log_path)
+        .and_then(|mut file| file.write_all(line.as_bytes()));
+    if let Err(err) = written {
+        println!("Error writing the firewall audit log: {}", err);
+    }
+}
+
+fn validate(ruleset: &FirewallRulesetData) -> Result<(), String> {
+    if ruleset.rules.len() > MAX_RULES {
+        return Err(format!(
+            "Too many firewall rules: {} > {}",
+            ruleset.rules.len(),
+            MAX_RULES
+        ));
+    }
+    for rule in &ruleset.rules {
+        validate_rule(rule).map_err(|err| format!("Invalid firewall rule {:?}: {}", rule, err))?;
+    }
+    Ok(())
+}
+
+fn validate_rule(rule: &FirewallRule) -> Result<(), String> {
+    parse_source(&rule.source)?;
+    if rule.ports.is_empty() {
+        return Err("no ports".to_string());
+    }
+    if rule.ports.contains(&0) {
+        return Err("port 0".to_string());
+    }
+    if rule.comment.len() > MAX_COMMENT_LENGTH {
+        return Err("comment too long".to_string());
+    }
+    // The comment is quoted in the nftables script.
+    if !rule
+        .comment
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || " -_.:/".contains(c))
+    {
+        return Err("comment with unsupported characters".to_string());
+    }
+    Ok(())
+}
+
+/// Parses an address or a prefix. Prefixes matching all addresses are
+/// refused, the allowlist is not meant to open ports to everyone.
+fn parse_source(source: &str) -> Result<IpAddr, String> {
+    let (addr, prefix_length) = match source.split_once('/') {
+        Some((addr, prefix_length)) => (addr, Some(prefix_length)),
+        None => (source, None),
+    };
+    let addr: IpAddr = addr
+        .parse()
+        .map_err(|_| format!("invalid address {}", addr))?;
+    if let Some(prefix_length) = prefix_length {
+        let max_length = if addr.is_ipv4() { 32 } else { 128 };
+        match prefix_length.parse::<u8>() {
+            Ok(length) if length > 0 && length <= max_length => {}
+            _ => return Err(format!("invalid prefix length {}", prefix_length)),
+        }
+    }
+    Ok(addr)
+}
+
+/// Renders `ruleset` as an nftables script replacing the contents of the
+/// allowlist chains of both filter tables.
+fn render(ruleset: &FirewallRulesetData) -> String {
+    let mut script = format!(
+        "flush chain ip filter {chain}\nflush chain ip6 filter {chain}\n",
+        chain = ALLOWLIST_CHAIN
+    );
+    for rule in &ruleset.rules {
+        let (family, selector) = match parse_source(&rule.source) {
+            Ok(IpAddr::V4(_)) => ("ip", "ip saddr"),
+            Ok(IpAddr::V6(_)) => ("ip6", "ip6 saddr"),
+            Err(_) => continue,
+        };
+        let mut ports = rule.ports.clone();
+        ports.sort_unstable();
+        ports.dedup();
+        let ports: Vec<String> = ports.iter().map(u16::to_string).collect();
+        script.push_str(&format!(
+            "add rule {} filter {} {} {} ct state {{ new }} tcp dport {{ {} }} accept comment \"{}\"\n",
+            family,
+            ALLOWLIST_CHAIN,
+            selector,
+            rule.source,
+            ports.join(", "),
+            rule.comment
+        ));
+    }
+    script
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn rule(source: &str, ports: Vec<u16>, comment: &str) -> FirewallRule {
+        FirewallRule {
+            source: source.to_string(),
+            ports,
+            comment: comment.to_string(),
+        }
+    }
+
+    #[test]
+    fn validates_rules() {
+        assert!(validate_rule(&rule("10.0.0.1", vec![22], "node-1")).is_ok());
+        assert!(validate_rule(&rule("2001:db8::/64", vec![9100], "dc 1")).is_ok());
+        assert!(validate_rule(&rule("2001:db8::/129", vec![9100], "")).is_err());
+        assert!(validate_rule(&rule("::/0", vec![9100], "")).is_err());
+        assert!(validate_rule(&rule("node-1", vec![22], "")).is_err());
+        assert!(validate_rule(&rule("10.0.0.1", vec![], "")).is_err());
+        assert!(validate_rule(&rule("10.0.0.1", vec![0], "")).is_err());
+        assert!(validate_rule(&rule("10.0.0.1", vec![22], "\" accept")).is_err());
+    }
+
+    #[test]
+    fn renders_rules_per_family() {
+        let ruleset = FirewallRulesetData {
+            registry_version: 1,
+            rules: vec![
+                rule("10.0.0.1", vec![22], "node-1"),
+                rule("2001:db8::/64", vec![9100, 22, 9100], "node-2"),
+            ],
+        };
+        assert_eq!(
+            render(&ruleset),
+            "flush chain ip filter REGISTRY_ALLOWLIST\n\
+             flush chain ip6 filter REGISTRY_ALLOWLIST\n\
+             add rule ip filter REGISTRY_ALLOWLIST ip saddr 10.0.0.1 ct state { new } tcp dport { 22 } accept comment \"node-1\"\n\
+             add rule ip6 filter REGISTRY_ALLOWLIST ip6 saddr 2001:db8::/64 ct state { new } tcp dport { 22, 9100 } accept comment \"node-2\"\n"
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use crate::host::backend::Backend;
+use crate::protocol::{GuestConsoleData, Payload, Response};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// The maximal size of the returned console log, such that the response stays
+/// below the size the guest reads even if most characters need escaping.
+const MAX_CONSOLE_LOG_BYTES: u64 = 32 * 1024;
+
+pub fn get_guest_console(guest_console_data: &GuestConsoleData, backend: &Backend) -> Response {
+    let log = read_tail(&backend.guest_console_log_path, MAX_CONSOLE_LOG_BYTES)?;
+    Ok(Payload::GuestConsole(last_lines(
+        &log,
+        guest_console_data.lines as usize,
+    )))
+}
+
+/// Reads at most the last `max_bytes` of the file at `path`.
+fn read_tail(path: &Path, max_bytes: u64) -> Result<String, String> {
+    let mut file = File::open(path)
+        .map_err(|err| format!("Could not open the guest console log {:?}: {}", path, err))?;
+    let size = file
+        .metadata()
+        .map_err(|err| format!("Could not read the guest console log: {}", err))?
+        .len();
+    let start = size.saturating_sub(max_bytes);
+    file.seek(SeekFrom::Start(start))
+        .map_err(|err| format!("Could not read the guest console log: {}", err))?;
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents)
+        .map_err(|err| format!("Could not read the guest console log: {}", err))?;
+    let contents = String::from_utf8_lossy(&contents);
+    // The first line is likely cut off if the log was truncated.
+    Ok(match contents.split_once('\n') {
+        Some((_, rest)) if start > 0 => rest.to_string(),
+        _ => contents.to_string(),
+    })
+}
+
+/// Returns the last `lines` lines of `log`.
+fn last_lines(log: &str, lines: usize) -> String {
+    if lines == 0 {
+        return String::new();
+    }
+    // a trailing newline does not start another line
+    let trimmed = log.strip_suffix('\n').unwrap_or(log);
+    match trimmed.rmatch_indices('\n').nth(lines - 1) {
+        Some((index, _)) => log[index + 1..].to_string(),
+        None => log.to_string(),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn returns_last_lines() {
+        let log = "one\ntwo\nthree\n";
+        assert_eq!(last_lines(log, 0), "");
+        assert_eq!(last_lines(log, 2), "two\nthree\n");
+        assert_eq!(last_lines(log, 3), log);
+        assert_eq!(last_lines(log, 10), log);
+        assert_eq!(last_lines("one\ntwo", 1), "two");
+    }
+
+    #[test]
+    fn reads_capped_tail_without_partial_line() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, "first line\nsecond\nthird\n").unwrap();
+
+        assert_eq!(
+            read_tail(file.path(), 1024).unwrap(),
+            "first line\nsecond\nthird\n"
+        );
+        // the cap cuts into "second", which is dropped
+        assert_eq!(read_tail(file.path(), 10).unwrap(), "third\n");
+    }
+}
@@ -0,0 +1,178 @@
+use crate::protocol::{
+    DiskHealth, FanReading, HardwareHealth, Payload, PowerSupplyStatus, Response,
+    TemperatureReading,
+};
+use std::process::Command;
+
+const IPMITOOL: &str = "ipmitool";
+const SMARTCTL: &str = "smartctl";
+
+pub fn get_hardware_health() -> Response {
+    let sdr_output = run_command(Command::new(IPMITOOL).arg("sdr").arg("elist"))?;
+    let mut hardware_health = parse_sdr_elist(&sdr_output);
+
+    let scan_output = run_command(Command::new(SMARTCTL).arg("--scan"))?;
+    for device in parse_smartctl_scan(&scan_output) {
+        // smartctl uses a non-zero exit code as a bit mask to report disk problems, so the
+        // output is parsed regardless of the exit status.
+        let health_output = Command::new(SMARTCTL)
+            .arg("-H")
+            .arg(&device)
+            .output()
+            .map_err(|err| format!("Could not run {} -H {}: {}", SMARTCTL, device, err))?;
+        let health_output = String::from_utf8_lossy(&health_output.stdout);
+        hardware_health
+            .disks
+            .push(parse_smartctl_health(&device, &health_output));
+    }
+
+    Ok(Payload::HardwareHealth(hardware_health))
+}
+
+fn run_command(command: &mut Command) -> Result<String, String> {
+    let output = command
+        .output()
+        .map_err(|err| format!("Could not run {:?}: {}", command, err))?;
+    if !output.status.success() {
+        return Err(format!(
+            "Command {:?} failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    String::from_utf8(output.stdout).map_err(|err| format!("Invalid command output: {}", err))
+}
+
+/// Parses the output of `ipmitool sdr elist`, whose lines look like:
+/// `Fan1             | 41h | ok  |  7.1 | 5040 RPM`
+fn parse_sdr_elist(output: &str) -> HardwareHealth {
+    let mut hardware_health = HardwareHealth::default();
+    for line in output.lines() {
+        let fields: Vec<&str> = line.split('|').map(|field| field.trim()).collect();
+        if fields.len() != 5 {
+            continue;
+        }
+        let (name, status, reading) = (fields[0], fields[2], fields[4]);
+        if reading.ends_with("RPM") || name.to_lowercase().starts_with("fan") {
+            hardware_health.fans.push(FanReading {
+                name: name.to_string(),
+                status: status.to_string(),
+                rpm: reading
+                    .strip_suffix("RPM")
+                    .and_then(parse_leading_number)
+                    .map(|rpm| rpm as u32),
+            });
+        } else if let Some(degrees) = reading.strip_suffix("degrees C") {
+            hardware_health.temperatures.push(TemperatureReading {
+                name: name.to_string(),
+                status: status.to_string(),
+                degrees_celsius: parse_leading_number(degrees).map(|degrees| degrees as i32),
+            });
+        } else if name.starts_with("PS") || name.to_lowercase().contains("power supply") {
+            hardware_health.power_supplies.push(PowerSupplyStatus {
+                name: name.to_string(),
+                status: reading.to_string(),
+                ok: status == "ok" && !reading.to_lowercase().contains("failure"),
+            });
+        }
+    }
+    hardware_health
+}
+
+fn parse_leading_number(value: &str) -> Option<f64> {
+    value.trim().parse::<f64>().ok().map(|value| value.round())
+}
+
+/// Parses the output of `smartctl --scan`, whose lines look like:
+/// `/dev/sda -d scsi # /dev/sda, SCSI device`
+fn parse_smartctl_scan(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .filter(|device| device.starts_with("/dev/"))
+        .map(|device| device.to_string())
+        .collect()
+}
+
+fn parse_smartctl_health(device: &str, output: &str) -> DiskHealth {
+    // ATA devices report the self-assessment result, SCSI and NVMe devices a health status.
+    let summary = output
+        .lines()
+        .find(|line| {
+            line.contains("self-assessment test result") || line.contains("SMART Health Status")
+        })
+        .and_then(|line| line.split(':').nth(1))
+        .map(|result| result.trim().to_string())
+        .unwrap_or_else(|| "UNKNOWN".to_string());
+    DiskHealth {
+        device: device.to_string(),
+        passed: summary == "PASSED" || summary == "OK",
+        summary,
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_sdr_elist_output() {
+        let output = "Fan1             | 41h | ok  |  7.1 | 5040 RPM
+Fan2             | 42h | ns  |  7.2 | No Reading
+Inlet Temp       | 04h | ok  |  7.1 | 23 degrees C
+PS1 Status       | 62h | ok  | 10.1 | Presence detected
+PS2 Status       | 63h | ok  | 10.2 | Presence detected, Failure detected
+Intrusion        | 73h | ok  |  7.1 |
+";
+        let health = parse_sdr_elist(output);
+        assert_eq!(
+            health.fans,
+            vec![
+                FanReading {
+                    name: "Fan1".to_string(),
+                    status: "ok".to_string(),
+                    rpm: Some(5040),
+                },
+                FanReading {
+                    name: "Fan2".to_string(),
+                    status: "ns".to_string(),
+                    rpm: None,
+                }
+            ]
+        );
+        assert_eq!(
+            health.temperatures,
+            vec![TemperatureReading {
+                name: "Inlet Temp".to_string(),
+                status: "ok".to_string(),
+                degrees_celsius: Some(23),
+            }]
+        );
+        assert_eq!(health.power_supplies.len(), 2);
+        assert!(health.power_supplies[0].ok);
+        assert!(!health.power_supplies[1].ok);
+    }
+
+    #[test]
+    fn parse_smartctl_output() {
+        let scan = "/dev/sda -d scsi # /dev/sda, SCSI device
+/dev/nvme0 -d nvme # /dev/nvme0, NVMe device
+";
+        assert_eq!(
+            parse_smartctl_scan(scan),
+            vec!["/dev/sda".to_string(), "/dev/nvme0".to_string()]
+        );
+
+        let health = parse_smartctl_health(
+            "/dev/nvme0",
+            "=== START OF SMART DATA SECTION ===
+SMART overall-health self-assessment test result: PASSED
+",
+        );
+        assert!(health.passed);
+        assert_eq!(health.summary, "PASSED");
+
+        let health = parse_smartctl_health("/dev/sda", "SMART Health Status: FAILURE\n");
+        assert!(!health.passed);
+    }
+}
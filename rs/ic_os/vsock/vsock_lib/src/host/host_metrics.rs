@@ -0,0 +1,254 @@
+use crate::protocol::{
+    CpuMetrics, FilesystemUsage, HostMetrics, MemoryMetrics, NetworkInterfaceCounters, Payload,
+    Response,
+};
+use std::process::Command;
+
+const PROC_STAT: &str = "/proc/stat";
+const PROC_LOADAVG: &str = "/proc/loadavg";
+const PROC_MEMINFO: &str = "/proc/meminfo";
+const PROC_NET_DEV: &str = "/proc/net/dev";
+// Pseudo filesystems whose usage says nothing about the disks.
+const DF_EXCLUDED_TYPES: [&str; 3] = ["tmpfs", "devtmpfs", "overlay"];
+
+pub fn get_host_metrics() -> Response {
+    let mut cpu = parse_proc_stat(&read_file(PROC_STAT)?);
+    cpu.load_averages_centi = parse_loadavg(&read_file(PROC_LOADAVG)?)?;
+
+    let mut df = Command::new("df");
+    df.arg("-B1").arg("--output=source,target,size,used,avail");
+    for fs_type in DF_EXCLUDED_TYPES {
+        df.arg("-x").arg(fs_type);
+    }
+    let df_output = df
+        .output()
+        .map_err(|err| format!("Could not run df: {}", err))?;
+    // df fails if some filesystem can't be read, but still reports the others.
+    let filesystems = parse_df(&String::from_utf8_lossy(&df_output.stdout));
+
+    Ok(Payload::HostMetrics(HostMetrics {
+        cpu,
+        memory: parse_meminfo(&read_file(PROC_MEMINFO)?),
+        filesystems,
+        network_interfaces: parse_net_dev(&read_file(PROC_NET_DEV)?),
+    }))
+}
+
+fn read_file(path: &str) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|err| format!("Could not read {}: {}", path, err))
+}
+
+/// Parses `/proc/stat`, whose aggregated first line looks like:
+/// `cpu  user nice system idle iowait irq softirq steal guest guest_nice`
+/// followed by one such line per core, e.g. `cpu0 ...`.
+fn parse_proc_stat(contents: &str) -> CpuMetrics {
+    let mut cpu = CpuMetrics::default();
+    for line in contents.lines() {
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("cpu") => {
+                let ticks: Vec<u64> = fields.map(|field| field.parse().unwrap_or(0)).collect();
+                let tick = |index: usize| ticks.get(index).copied().unwrap_or(0);
+                cpu.busy_ticks = tick(0) + tick(1) + tick(2) + tick(5) + tick(6) + tick(7);
+                cpu.idle_ticks = tick(3) + tick(4);
+            }
+            Some(name) if name.starts_with("cpu") => cpu.cores += 1,
+            _ => {}
+        }
+    }
+    cpu
+}
+
+/// Parses `/proc/loadavg`, which looks like: `0.52 0.58 0.59 1/467 12345`
+fn parse_loadavg(contents: &str) -> Result<[u32; 3], String> {
+    let mut load_averages = [0; 3];
+    let mut fields = contents.split_whitespace();
+    for load_average in load_averages.iter_mut() {
+        let field = fields
+            .next()
+            .ok_or_else(|| format!("Invalid load averages: {}", contents))?;
+        let value = field
+            .parse::<f64>()
+            .map_err(|err| format!("Invalid load average {}: {}", field, err))?;
+        *load_average = (value * 100.0).round() as u32;
+    }
+    Ok(load_averages)
+}
+
+/// Parses `/proc/meminfo`, whose lines look like: `MemTotal:  16310084 kB`
+fn parse_meminfo(contents: &str) -> MemoryMetrics {
+    let mut memory = MemoryMetrics::default();
+    for line in contents.lines() {
+        let mut fields = line.split_whitespace();
+        let (name, kilobytes) = match (fields.next(), fields.next()) {
+            (Some(name), Some(value)) => (name, value.parse::<u64>().unwrap_or(0)),
+            _ => continue,
+        };
+        let bytes = kilobytes * 1024;
+        match name {
+            "MemTotal:" => memory.total_bytes = bytes,
+            "MemAvailable:" => memory.available_bytes = bytes,
+            "SwapTotal:" => memory.swap_total_bytes = bytes,
+            "SwapFree:" => memory.swap_free_bytes = bytes,
+            _ => {}
+        }
+    }
+    memory
+}
+
+/// Parses the output of `df -B1 --output=source,target,size,used,avail`,
+/// whose lines after the header look like:
+/// `/dev/mapper/hostlvm-root  /  10726932480  3045064704  7115526144`
+fn parse_df(output: &str) -> Vec<FilesystemUsage> {
+    output
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 5 {
+                return None;
+            }
+            let number = |field: &str| field.parse::<u64>().ok();
+            let n = fields.len();
+            Some(FilesystemUsage {
+                device: fields[0].to_string(),
+                // mount points may contain spaces
+                mount_point: fields[1..n - 3].join(" "),
+                size_bytes: number(fields[n - 3])?,
+                used_bytes: number(fields[n - 2])?,
+                available_bytes: number(fields[n - 1])?,
+            })
+        })
+        .collect()
+}
+
+/// Parses `/proc/net/dev`, whose lines after the two header lines look like:
+/// `  eth0: rx_bytes rx_packets rx_errs rx_drop fifo frame compressed multicast tx_bytes tx_packets tx_errs tx_drop ...`
+/// The loopback interface is skipped.
+fn parse_net_dev(contents: &str) -> Vec<NetworkInterfaceCounters> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (name, counters) = line.split_once(':')?;
+            let name = name.trim();
+            let counters: Vec<u64> = counters
+                .split_whitespace()
+                .map(|counter| counter.parse().ok())
+                .collect::<Option<_>>()?;
+            if name == "lo" || counters.len() < 12 {
+                return None;
+            }
+            Some(NetworkInterfaceCounters {
+                name: name.to_string(),
+                rx_bytes: counters[0],
+                rx_packets: counters[1],
+                rx_errors: counters[2],
+                rx_dropped: counters[3],
+                tx_bytes: counters[8],
+                tx_packets: counters[9],
+                tx_errors: counters[10],
+                tx_dropped: counters[11],
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_cpu_metrics() {
+        let stat = "cpu  100 5 50 1000 20 3 2 1 0 0
+cpu0 50 2 25 500 10 1 1 0 0 0
+cpu1 50 3 25 500 10 2 1 1 0 0
+intr 12345
+ctxt 67890
+";
+        assert_eq!(
+            parse_proc_stat(stat),
+            CpuMetrics {
+                cores: 2,
+                load_averages_centi: [0; 3],
+                busy_ticks: 161,
+                idle_ticks: 1020,
+            }
+        );
+        assert_eq!(
+            parse_loadavg("0.52 1.5 12.04 1/467 12345\n"),
+            Ok([52, 150, 1204])
+        );
+        assert!(parse_loadavg("").is_err());
+    }
+
+    #[test]
+    fn parse_meminfo_output() {
+        let meminfo = "MemTotal:       16310084 kB
+MemFree:         1209876 kB
+MemAvailable:    8000000 kB
+SwapTotal:             0 kB
+SwapFree:              0 kB
+HugePages_Total:       0
+";
+        assert_eq!(
+            parse_meminfo(meminfo),
+            MemoryMetrics {
+                total_bytes: 16310084 * 1024,
+                available_bytes: 8000000 * 1024,
+                swap_total_bytes: 0,
+                swap_free_bytes: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_df_output() {
+        let output =
+            "Filesystem                     Mounted on          1B-blocks        Used       Avail
+/dev/mapper/hostlvm-root       /                 10726932480  3045064704  7115526144
+/dev/sda1                      /boot/my efi          1024000      512000      512000
+";
+        assert_eq!(
+            parse_df(output),
+            vec![
+                FilesystemUsage {
+                    device: "/dev/mapper/hostlvm-root".to_string(),
+                    mount_point: "/".to_string(),
+                    size_bytes: 10726932480,
+                    used_bytes: 3045064704,
+                    available_bytes: 7115526144,
+                },
+                FilesystemUsage {
+                    device: "/dev/sda1".to_string(),
+                    mount_point: "/boot/my efi".to_string(),
+                    size_bytes: 1024000,
+                    used_bytes: 512000,
+                    available_bytes: 512000,
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_net_dev_output() {
+        let net_dev = "Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo: 1000      10    0    0    0     0          0         0     1000      10    0    0    0     0       0          0
+enp1s0: 123456789 1000  2    3    0     0          0         7 987654321     2000    4    5    0     0       0          0
+";
+        assert_eq!(
+            parse_net_dev(net_dev),
+            vec![NetworkInterfaceCounters {
+                name: "enp1s0".to_string(),
+                rx_bytes: 123456789,
+                rx_packets: 1000,
+                rx_errors: 2,
+                rx_dropped: 3,
+                tx_bytes: 987654321,
+                tx_packets: 2000,
+                tx_errors: 4,
+                tx_dropped: 5,
+            }]
+        );
+    }
+}
@@ -1,15 +1,12 @@
-use crate::host::command_utilities::handle_command_output;
-use crate::protocol::Response;
-use libusb::Device;
-use std::io::{Error, ErrorKind, Write};
-use tempfile::NamedTempFile;
+use crate::host::backend::{Backend, DeviceEnumerator, UsbDevice};
+use crate::protocol::{Payload, Response};
 
 // nitrokey:
 const HSM_VENDOR: u16 = 8352;
 const HSM_PRODUCT: u16 = 16944;
 
 // the hard-coded domain name defined in the xml file for starting guestOS in virsh
-const DOMAIN_NAME: &str = "guestos";
+pub(crate) const DOMAIN_NAME: &str = "guestos";
 
 #[derive(Debug)]
 struct HSMInfo {
@@ -27,74 +24,98 @@ impl std::fmt::Display for HSMInfo {
     }
 }
 
-pub fn attach_hsm() -> Response {
-    hsm_helper("attach-device")
+impl From<&UsbDevice> for HSMInfo {
+    fn from(device: &UsbDevice) -> Self {
+        HSMInfo {
+            hsm_bus_num: device.bus_number,
+            hsm_address: device.address,
+        }
+    }
 }
 
-pub fn detach_hsm() -> Response {
-    hsm_helper("detach-device")
-}
+/// Attaches an HSM to the guest. If `serial` is given, the HSM with that USB
+/// serial number is attached, otherwise the host must have exactly one HSM.
+pub fn attach_hsm(backend: &Backend, serial: Option<&str>) -> Response {
+    let mut state = backend
+        .state
+        .lock()
+        .map_err(|_| "Could not lock host state".to_string())?;
+    if let Some(attached_hsm) = &state.attached_hsm {
+        return Err(format!(
+            "HSM already attached: {}",
+            HSMInfo::from(attached_hsm)
+        ));
+    }
 
-fn hsm_helper(command: &str) -> Response {
-    let hsm_xml_file = create_hsm_xml_file()?;
+    let hsm_device = select_hsm(get_hsm_devices(backend.devices.as_ref())?, serial)?;
+    let hsm_info = HSMInfo::from(&hsm_device);
+    println!("HSM found: {}", hsm_info);
 
-    println!("Sending virsh command: {command}");
-    let command_output = std::process::Command::new("virsh")
-        .arg(command)
-        .arg(DOMAIN_NAME)
-        .arg("--file")
-        .arg(hsm_xml_file.path())
-        .output();
+    backend
+        .domain
+        .attach_device(DOMAIN_NAME, &get_hsm_xml_string(&hsm_info))?;
+    state.attached_hsm = Some(hsm_device);
 
-    handle_command_output(command_output)
+    Ok(Payload::NoPayload)
 }
 
-fn create_hsm_xml_file() -> Result<NamedTempFile, String> {
-    let hsm_info: HSMInfo = get_hsm_info().map_err(|_| "Could not get hsm info".to_string())?;
+/// Detaches the HSM recorded as attached to the guest.
+pub fn detach_hsm(backend: &Backend) -> Response {
+    let mut state = backend
+        .state
+        .lock()
+        .map_err(|_| "Could not lock host state".to_string())?;
+    let hsm_device = match &state.attached_hsm {
+        Some(attached_hsm) => attached_hsm.clone(),
+        // Nothing is recorded if the HSM was attached before the host agent
+        // (re)started, so fall back to the only HSM of the host, if unique.
+        None => select_hsm(get_hsm_devices(backend.devices.as_ref())?, None)?,
+    };
+    let hsm_info = HSMInfo::from(&hsm_device);
+    println!("Detaching HSM: {}", hsm_info);
 
-    println!("HSM found: {}", hsm_info);
+    backend
+        .domain
+        .detach_device(DOMAIN_NAME, &get_hsm_xml_string(&hsm_info))?;
+    state.attached_hsm = None;
+
+    Ok(Payload::NoPayload)
+}
 
-    let xml: String = get_hsm_xml_string(&hsm_info);
+/// Returns all HSM devices attached to the host.
+fn get_hsm_devices(devices: &dyn DeviceEnumerator) -> Result<Vec<UsbDevice>, String> {
+    let usb_devices = devices
+        .usb_devices()
+        .map_err(|_| "Could not get hsm info".to_string())?;
 
-    write_to_temp_file(&xml).map_err(|_| "Could not write to temp file".to_string())
+    println!("Iterating over attached devices to find hsm");
+    Ok(usb_devices
+        .into_iter()
+        .filter(|device| device.vendor_id == HSM_VENDOR && device.product_id == HSM_PRODUCT)
+        .collect())
 }
 
-fn get_hsm_info() -> Result<HSMInfo, Error> {
-    let context = libusb::Context::new().map_err(|e| Error::new(ErrorKind::Other, e))?;
-
-    let usb_devices = context
-        .devices()
-        .map_err(|e| Error::new(ErrorKind::Other, e))?;
-
-    fn is_hsm_device(device: &Device) -> bool {
-        println!(
-            "Bus {:03} Device {:03} ID {:04x}:{:04x}",
-            device.bus_number(),
-            device.address(),
-            device.device_descriptor().unwrap().vendor_id(),
-            device.device_descriptor().unwrap().product_id()
-        );
-
-        let device_descriptor = match device.device_descriptor() {
-            Ok(device_descriptor) => device_descriptor,
-            Err(_) => {
-                println!("Error: device.device_descriptor() returned error");
-                return false;
-            }
-        };
-        device_descriptor.vendor_id() == HSM_VENDOR && device_descriptor.product_id() == HSM_PRODUCT
+fn select_hsm(hsm_devices: Vec<UsbDevice>, serial: Option<&str>) -> Result<UsbDevice, String> {
+    if let Some(serial) = serial {
+        return hsm_devices
+            .into_iter()
+            .find(|device| device.serial_number.as_deref() == Some(serial))
+            .ok_or_else(|| format!("No HSM device found with serial {serial}"));
     }
 
-    println!("Iterating over attached devices to find hsm");
-    // return the first usb device that satisfies the is_hsm_device filter
-    let x = match usb_devices.iter().find(is_hsm_device) {
-        Some(hsm_device) => Ok(HSMInfo {
-            hsm_bus_num: hsm_device.bus_number(),
-            hsm_address: hsm_device.address(),
-        }),
-        None => return Err(Error::new(ErrorKind::Other, "No HSM device found")),
-    };
-    x
+    match hsm_devices.len() {
+        0 => Err("Could not get hsm info".to_string()),
+        1 => Ok(hsm_devices.into_iter().next().unwrap()),
+        _ => Err(format!(
+            "Found {} HSM devices, select one by serial: {}",
+            hsm_devices.len(),
+            hsm_devices
+                .iter()
+                .map(|device| device.serial_number.as_deref().unwrap_or("<unknown>"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
 }
 
 // HSM_VENDOR and HSM_PRODUCT must be converted to hexadecimal for the attach/detach hsm virsh commands
@@ -114,12 +135,6 @@ fn get_hsm_xml_string(hsm_info: &HSMInfo) -> String {
     )
 }
 
-fn write_to_temp_file(content: &str) -> Result<NamedTempFile, Error> {
-    let mut file: NamedTempFile = NamedTempFile::new()?;
-    write!(file, "{content}")?;
-    Ok(file)
-}
-
 pub mod tests {
     #[test]
     fn get_hsm_xml_string() {
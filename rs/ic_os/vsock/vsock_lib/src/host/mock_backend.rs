@@ -0,0 +1,279 @@
+//! An in-memory host backend, used to exercise the host agent without
+//! hardware, libvirt, nftables or HostOS upgrade scripts.
+use crate::host::attestation::to_hex;
+use crate::host::backend::{
+    Attester, Backend, DeviceEnumerator, DiskStorage, DomainManager, Firewall, Upgrader, UsbDevice,
+};
+use crate::host::upgrade::BootSlots;
+use crate::protocol::{Payload, PcrValue, Response, TpmQuote};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+#[derive(Default)]
+pub struct MockHostState {
+    pub usb_devices: Vec<UsbDevice>,
+    /// Device XMLs currently attached, per domain.
+    pub attached_devices: BTreeMap<String, Vec<String>>,
+    /// Device XMLs in the persistent definition of each domain.
+    pub persistent_devices: BTreeMap<String, Vec<String>>,
+    /// Sizes of the block devices and files of the host, by path.
+    pub disks: BTreeMap<PathBuf, u64>,
+    /// The bytes available for new raw files, unlimited if `None`.
+    pub available_disk_bytes: Option<u64>,
+    /// Contents served for each upgrade URL.
+    pub upgrade_images: BTreeMap<String, Vec<u8>>,
+    /// Contents of every installed upgrade image, in installation order.
+    pub installed_images: Vec<Vec<u8>>,
+    pub reboots: u32,
+    /// The grub environment block, `None` for a confirmed boot from slot A.
+    /// Installing an upgrade updates it like `manageboot.sh` does.
+    pub grubenv: Option<String>,
+    /// Every nftables script applied, in order.
+    pub firewall_scripts: Vec<String>,
+    /// Whether applying nftables scripts fails, like `nft` does for scripts
+    /// it can't load.
+    pub fail_firewall: bool,
+    /// Whether the host has a TPM with an attestation key.
+    pub no_tpm: bool,
+}
+
+/// Cloning a `MockHost` yields a handle to the same state, so that tests can
+/// inspect the state after handing a backend to the agent.
+#[derive(Clone, Default)]
+pub struct MockHost {
+    pub state: Arc<Mutex<MockHostState>>,
+}
+
+impl MockHost {
+    /// A backend keeping its files in `dir`.
+    pub fn backend(&self, dir: &Path) -> Backend {
+        Backend {
+            devices: Box::new(self.clone()),
+            domain: Box::new(self.clone()),
+            upgrader: Box::new(self.clone()),
+            firewall: Box::new(self.clone()),
+            disks: Box::new(self.clone()),
+            attester: Box::new(self.clone()),
+            upgrade_file_path: dir.join("upgrade.tar.gz"),
+            guest_console_log_path: dir.join("guestos-serial.log"),
+            firewall_ruleset_path: dir.join("firewall").join("allowlist.nft"),
+            firewall_audit_log_path: dir.join("firewall-audit.log"),
+            guest_disks_path: dir.join("guest_disks.json"),
+            state: Mutex::default(),
+        }
+    }
+
+    pub fn add_usb_device(&self, device: UsbDevice) {
+        self.state.lock().unwrap().usb_devices.push(device);
+    }
+
+    pub fn add_upgrade_image(&self, url: &str, contents: Vec<u8>) {
+        self.state
+            .lock()
+            .unwrap()
+            .upgrade_images
+            .insert(url.to_string(), contents);
+    }
+
+    pub fn set_grubenv(&self, boot_alternative: &str, boot_cycle: &str) {
+        self.state.lock().unwrap().grubenv = Some(grubenv(boot_alternative, boot_cycle));
+    }
+
+    pub fn add_disk(&self, path: &str, size_bytes: u64) {
+        self.state
+            .lock()
+            .unwrap()
+            .disks
+            .insert(PathBuf::from(path), size_bytes);
+    }
+
+    pub fn attached_devices(&self, domain: &str) -> Vec<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .attached_devices
+            .get(domain)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn persistent_devices(&self, domain: &str) -> Vec<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .persistent_devices
+            .get(domain)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+impl DeviceEnumerator for MockHost {
+    fn usb_devices(&self) -> Result<Vec<UsbDevice>, String> {
+        Ok(self.state.lock().unwrap().usb_devices.clone())
+    }
+}
+
+impl DomainManager for MockHost {
+    // Mirrors virsh, which refuses to attach a device twice or to detach a
+    // device that is not attached.
+    fn attach_device(&self, domain: &str, device_xml: &str) -> Response {
+        let mut state = self.state.lock().unwrap();
+        let attached = state
+            .attached_devices
+            .entry(domain.to_string())
+            .or_default();
+        if attached.iter().any(|xml| xml == device_xml) {
+            return Err("error: device is already attached".to_string());
+        }
+        attached.push(device_xml.to_string());
+        Ok(Payload::NoPayload)
+    }
+
+    fn detach_device(&self, domain: &str, device_xml: &str) -> Response {
+        let mut state = self.state.lock().unwrap();
+        let attached = state
+            .attached_devices
+            .entry(domain.to_string())
+            .or_default();
+        match attached.iter().position(|xml| xml == device_xml) {
+            Some(index) => {
+                attached.remove(index);
+                Ok(Payload::NoPayload)
+            }
+            None => Err("error: device not found".to_string()),
+        }
+    }
+
+    fn attach_device_persistent(&self, domain: &str, device_xml: &str) -> Response {
+        self.attach_device(domain, device_xml)?;
+        self.state
+            .lock()
+            .unwrap()
+            .persistent_devices
+            .entry(domain.to_string())
+            .or_default()
+            .push(device_xml.to_string());
+        Ok(Payload::NoPayload)
+    }
+
+    fn detach_device_persistent(&self, domain: &str, device_xml: &str) -> Response {
+        self.detach_device(domain, device_xml)?;
+        self.state
+            .lock()
+            .unwrap()
+            .persistent_devices
+            .entry(domain.to_string())
+            .or_default()
+            .retain(|xml| xml != device_xml);
+        Ok(Payload::NoPayload)
+    }
+}
+
+impl DiskStorage for MockHost {
+    fn disk_size(&self, path: &Path) -> Result<Option<u64>, String> {
+        Ok(self.state.lock().unwrap().disks.get(path).copied())
+    }
+
+    fn create_raw_file(&self, path: &Path, size_bytes: u64) -> Result<(), String> {
+        self.state
+            .lock()
+            .unwrap()
+            .disks
+            .insert(path.to_path_buf(), size_bytes);
+        Ok(())
+    }
+
+    fn available_bytes(&self, _dir: &Path) -> Result<u64, String> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .available_disk_bytes
+            .unwrap_or(u64::MAX))
+    }
+}
+
+impl Upgrader for MockHost {
+    fn download(&self, url: &str, target: &Path) -> Result<(), String> {
+        let contents = self
+            .state
+            .lock()
+            .unwrap()
+            .upgrade_images
+            .get(url)
+            .cloned()
+            .ok_or_else(|| "Could not download url".to_string())?;
+        std::fs::write(target, contents).map_err(|_| "Could not write to upgrade file".to_string())
+    }
+
+    fn install(&self, image: &Path) -> Response {
+        let contents = std::fs::read(image).map_err(|e| e.to_string())?;
+        let target = BootSlots::from_grubenv(&self.read_grubenv()?)?
+            .active
+            .other();
+        let mut state = self.state.lock().unwrap();
+        state.installed_images.push(contents);
+        state.grubenv = Some(grubenv(&target.to_string(), "first_boot"));
+        Ok(Payload::NoPayload)
+    }
+
+    fn reboot(&self) -> Response {
+        self.state.lock().unwrap().reboots += 1;
+        Ok(Payload::NoPayload)
+    }
+
+    fn read_grubenv(&self) -> Result<String, String> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .grubenv
+            .clone()
+            .unwrap_or_else(|| grubenv("A", "stable")))
+    }
+}
+
+fn grubenv(boot_alternative: &str, boot_cycle: &str) -> String {
+    format!(
+        "# GRUB Environment Block\nboot_alternative={}\nboot_cycle={}\n",
+        boot_alternative, boot_cycle
+    )
+}
+
+impl Firewall for MockHost {
+    fn apply(&self, nft_script: &str) -> Response {
+        let mut state = self.state.lock().unwrap();
+        if state.fail_firewall {
+            return Err("Error: Could not process rule".to_string());
+        }
+        state.firewall_scripts.push(nft_script.to_string());
+        Ok(Payload::NoPayload)
+    }
+}
+
+/// The value of every PCR of the mock TPM.
+pub const MOCK_PCR_VALUE: &str = "3d458cfe55cc03ea1f443f1562beec8df51c75e14a9fcf9a7234a13f198e7969";
+
+impl Attester for MockHost {
+    // The quote of the mock merely carries the nonce, in place of a structure
+    // signed by the TPM.
+    fn tpm_quote(&self, nonce: &[u8], pcrs: &[u32]) -> Result<TpmQuote, String> {
+        if self.state.lock().unwrap().no_tpm {
+            return Err("ERROR: Could not load tcti".to_string());
+        }
+        Ok(TpmQuote {
+            message: to_hex(nonce),
+            signature: "mock signature".to_string(),
+            pcrs: pcrs
+                .iter()
+                .map(|&index| PcrValue {
+                    index,
+                    sha256: MOCK_PCR_VALUE.to_string(),
+                })
+                .collect(),
+            attestation_key_pem: "mock attestation key".to_string(),
+        })
+    }
+}
@@ -1,4 +1,15 @@
 mod agent;
+mod attestation;
+pub(crate) mod backend;
 mod command_utilities;
+mod disks;
+mod firewall;
+mod guest_console;
+mod hardware_health;
+mod host_metrics;
 mod hsm;
+#[cfg(test)]
+pub(crate) mod mock_backend;
 pub(crate) mod server;
+mod transfer;
+mod upgrade;
@@ -1,13 +1,22 @@
 use crate::host::agent::dispatch;
-use crate::protocol::{parse_request, Request, Response};
+use crate::host::backend::Backend;
+use crate::host::transfer::{serve_download, serve_upload};
+use crate::protocol::{parse_request, write_control, Command, Request, Response};
 use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use vsock::{VsockAddr, VsockListener, VsockStream, VMADDR_CID_HOST};
 
 const DEFAULT_PORT: u32 = 19090;
+/// The connections served at the same time. Further connections are closed
+/// right away.
+const MAX_CONNECTIONS: usize = 16;
 
 /// Runs the vsock server and awaits incoming vsock connections.
 pub fn run_server() -> Result<()> {
     let vsock_listener: VsockListener = create_vsock_listener()?;
+    let backend = Arc::new(Backend::system());
+    let connections = Arc::new(AtomicUsize::new(0));
 
     println!("Listening for vsock connection.\n");
 
@@ -16,23 +25,68 @@ pub fn run_server() -> Result<()> {
         stream.set_write_timeout(Some(std::time::Duration::from_secs(5)))?;
         stream.set_read_timeout(Some(std::time::Duration::from_secs(5)))?;
 
+        let connection = match ConnectionSlot::acquire(&connections) {
+            Some(connection) => connection,
+            None => {
+                println!(
+                    "\n\nRefusing incoming connection: {} connections are served already",
+                    MAX_CONNECTIONS
+                );
+                continue;
+            }
+        };
         println!("\n\nReceived incoming connection. Spawning new thread...");
 
-        let thread_result =
-            std::thread::spawn(move || -> Result<()> { process_connection(&mut stream) });
-
-        handle_thread_result(thread_result);
+        // The thread is not joined, so that a long stream transfer does not
+        // hold up the connections after it.
+        let backend = Arc::clone(&backend);
+        std::thread::spawn(move || {
+            let _connection = connection;
+            let peer_cid = stream.peer_addr().map(|peer_address| peer_address.cid());
+            log_connection_result(process_connection(&mut stream, peer_cid, &backend));
+        });
     }
 
     Ok(())
 }
 
+/// One of the `MAX_CONNECTIONS` connections served at the same time, released
+/// when dropped.
+struct ConnectionSlot {
+    connections: Arc<AtomicUsize>,
+}
+
+impl ConnectionSlot {
+    fn acquire(connections: &Arc<AtomicUsize>) -> Option<Self> {
+        connections
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                (count < MAX_CONNECTIONS).then_some(count + 1)
+            })
+            .ok()?;
+        Some(Self {
+            connections: Arc::clone(connections),
+        })
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 fn create_vsock_listener() -> Result<VsockListener> {
     let addr = VsockAddr::new(VMADDR_CID_HOST, DEFAULT_PORT);
     VsockListener::bind(&addr)
 }
 
-fn process_connection(stream: &mut VsockStream) -> Result<()> {
+/// Handles a single request on the given connection. `peer_cid` is the CID of
+/// the connected peer, which must match the sender CID in the request.
+pub(crate) fn process_connection<S: Read + Write>(
+    stream: &mut S,
+    peer_cid: Result<u32>,
+    backend: &Arc<Backend>,
+) -> Result<()> {
     let request = match get_request(stream) {
         Ok(request) => request,
         Err(err) => {
@@ -43,22 +97,39 @@ fn process_connection(stream: &mut VsockStream) -> Result<()> {
     println!("Received request: {}", request);
 
     println!("Verifying sender cid");
-    match verify_sender_cid(stream, request.guest_cid) {
+    match verify_sender_cid(peer_cid, request.guest_cid) {
         Ok(_) => (),
         Err(err) => {
-            send_response(stream, &Err(err.to_string()))?;
+            // The guest expects the answer to a stream command in a frame.
+            if request.command.is_stream() {
+                write_control(stream, &Response::Err(err.to_string()))?;
+            } else {
+                send_response(stream, &Err(err.to_string()))?;
+            }
             return Err(err);
         }
     };
 
+    match &request.command {
+        Command::StreamDownload(download_data) => {
+            println!("Streaming download");
+            return serve_download(stream, download_data, backend);
+        }
+        Command::StreamUpload(upload_data) => {
+            println!("Streaming upload");
+            return serve_upload(stream, upload_data, backend);
+        }
+        _ => (),
+    }
+
     println!("Dispatching command");
-    let response: Response = dispatch(&request.command);
+    let response: Response = dispatch(&request.command, backend);
 
     println!("Returning response to guest: {:?}", response);
     send_response(stream, &response)
 }
 
-fn get_request(stream: &mut VsockStream) -> Result<Request> {
+fn get_request<S: Read>(stream: &mut S) -> Result<Request> {
     let mut buffer = [0; 4096];
     let bytes_read = stream.read(&mut buffer)?;
     let json_request: String = match std::str::from_utf8(&buffer[..bytes_read]) {
@@ -73,16 +144,16 @@ fn get_request(stream: &mut VsockStream) -> Result<Request> {
 }
 
 // As a sanity check, we request that the sender adds its own CID to the message, and that CID must match the CID in the stream peer address.
-fn verify_sender_cid(stream: &mut VsockStream, guest_cid: u32) -> Result<()> {
-    let peer_address = match stream.peer_addr() {
-        Ok(peer_address) => peer_address,
+fn verify_sender_cid(peer_cid: Result<u32>, guest_cid: u32) -> Result<()> {
+    let peer_cid = match peer_cid {
+        Ok(peer_cid) => peer_cid,
         Err(err) => {
             let error = format!("Error: could not verify the sender_cid. {}", err);
             return Err(Error::new(ErrorKind::InvalidData, error));
         }
     };
 
-    if peer_address.cid() == guest_cid {
+    if peer_cid == guest_cid {
         Ok(())
     } else {
         Err(Error::new(
@@ -92,27 +163,17 @@ fn verify_sender_cid(stream: &mut VsockStream, guest_cid: u32) -> Result<()> {
     }
 }
 
-fn send_response(stream: &mut VsockStream, response: &Response) -> Result<()> {
+fn send_response<S: Write>(stream: &mut S, response: &Response) -> Result<()> {
     let json_response = serde_json::to_string(&response)?;
     stream.write_all(json_response.as_bytes())?;
 
     Ok(())
 }
 
-fn handle_thread_result(thread_result: std::thread::JoinHandle<Result<()>>) {
-    match thread_result.join() {
-        Ok(result) => match result {
-            Ok(_) => println!("Thread completed successfully"),
-            Err(e) => println!("Thread completed with error: {}", e),
-        },
-        Err(e) => {
-            if let Some(s) = e.downcast_ref::<&'static str>() {
-                println!("Thread panicked with error: {}", s);
-            } else if let Some(s) = e.downcast_ref::<String>() {
-                println!("Thread panicked with error: {}", s);
-            } else {
-                println!("Thread panicked with an unknown error");
-            }
-        }
+// A panic of the thread is reported by the default panic hook.
+fn log_connection_result(result: Result<()>) {
+    match result {
+        Ok(_) => println!("Thread completed successfully"),
+        Err(e) => println!("Thread completed with error: {}", e),
     }
 }
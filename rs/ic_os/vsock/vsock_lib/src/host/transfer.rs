@@ -0,0 +1,246 @@
+//! The host side of the stream transfers, see [crate::protocol::send_stream].
+use crate::host::backend::Backend;
+use crate::protocol::{
+    receive_stream, send_stream, sha256_hex, write_control, Payload, Response, StreamDownloadData,
+    StreamInfo, StreamSource, StreamTarget, StreamUploadData,
+};
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// The largest upload the host accepts, well above the size of HostOS
+/// upgrade images.
+const MAX_UPLOAD_BYTES: u64 = 4 * 1024 * 1024 * 1024;
+
+pub(crate) fn serve_download<S: Read + Write>(
+    stream: &mut S,
+    download_data: &StreamDownloadData,
+    backend: &Backend,
+) -> std::io::Result<()> {
+    let path = match download_data.source {
+        StreamSource::GuestConsole => &backend.guest_console_log_path,
+    };
+    let (file, info) = accept(stream, open_download(path, download_data.offset))?;
+
+    let sent = send_stream(
+        stream,
+        &mut file.take(info.size_bytes - info.offset),
+        info.offset,
+    )?;
+    println!(
+        "Streamed {} bytes of {:?} from offset {}",
+        sent - info.offset,
+        path,
+        info.offset
+    );
+    Ok(())
+}
+
+pub(crate) fn serve_upload<S: Read + Write>(
+    stream: &mut S,
+    upload_data: &StreamUploadData,
+    backend: &Backend,
+) -> std::io::Result<()> {
+    let target = match upload_data.target {
+        StreamTarget::UpgradeImage => &backend.upgrade_file_path,
+    };
+    let transfer = start_upload(backend).and_then(|upload| {
+        let (file, info) = open_upload(target, upload_data, backend)?;
+        Ok(((upload, file), info))
+    });
+    let ((_upload, mut file), info) = accept(stream, transfer)?;
+
+    let received = receive_stream(stream, &mut file, info.offset, info.size_bytes)?;
+    println!(
+        "Received {} bytes for {:?} from offset {}",
+        received - info.offset,
+        target,
+        info.offset
+    );
+    let response = complete_upload(target, info, received);
+    println!("Returning response to guest: {:?}", response);
+    write_control(stream, &response)
+}
+
+/// Marks an upload as in progress in the host state until it is dropped.
+struct UploadInProgress<'a> {
+    backend: &'a Backend,
+}
+
+impl Drop for UploadInProgress<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.backend.state.lock() {
+            state.upload_in_progress = false;
+        }
+    }
+}
+
+/// Claims the upgrade file for an upload. Only one upload runs at a time, as
+/// they share the partial file, and none while an upgrade reads the upgrade
+/// file, which the upload would replace.
+fn start_upload(backend: &Backend) -> Result<UploadInProgress<'_>, String> {
+    let mut state = backend
+        .state
+        .lock()
+        .map_err(|_| "Could not lock host state".to_string())?;
+    if state.upgrade_stage.is_in_progress() {
+        return Err(format!(
+            "An upgrade is in progress: {}",
+            state.upgrade_stage
+        ));
+    }
+    if state.upload_in_progress {
+        return Err("Another upload is in progress".to_string());
+    }
+    state.upload_in_progress = true;
+    Ok(UploadInProgress { backend })
+}
+
+/// Answers the request with the `StreamInfo` of the transfer, or with the
+/// error preventing it.
+fn accept<S: Write, T>(
+    stream: &mut S,
+    transfer: Result<(T, StreamInfo), String>,
+) -> std::io::Result<(T, StreamInfo)> {
+    match transfer {
+        Ok((value, info)) => {
+            println!("Accepting stream transfer: {}", info);
+            write_control(stream, &Response::Ok(Payload::StreamInfo(info.clone())))?;
+            Ok((value, info))
+        }
+        Err(err) => {
+            write_control(stream, &Response::Err(err.clone()))?;
+            Err(Error::new(ErrorKind::Other, err))
+        }
+    }
+}
+
+/// Opens the file at `path` at `offset`. The transfer covers the size of the
+/// file at this point, even if it grows further.
+fn open_download(path: &Path, offset: u64) -> Result<(File, StreamInfo), String> {
+    let mut file = File::open(path).map_err(|err| format!("Could not open {:?}: {}", path, err))?;
+    let size_bytes = file
+        .metadata()
+        .map_err(|err| format!("Could not read {:?}: {}", path, err))?
+        .len();
+    if offset > size_bytes {
+        return Err(format!(
+            "Offset {} is beyond the size of {:?}, {} bytes",
+            offset, path, size_bytes
+        ));
+    }
+    let sha256 = sha256_hex((&mut file).take(size_bytes))
+        .map_err(|err| format!("Could not read {:?}: {}", path, err))?;
+    file.seek(SeekFrom::Start(offset))
+        .map_err(|err| format!("Could not read {:?}: {}", path, err))?;
+
+    Ok((
+        file,
+        StreamInfo {
+            size_bytes,
+            sha256,
+            offset,
+        },
+    ))
+}
+
+/// Opens the partial file of an upload to `target`, keeping the bytes of an
+/// interrupted upload of the same contents.
+fn open_upload(
+    target: &Path,
+    upload_data: &StreamUploadData,
+    backend: &Backend,
+) -> Result<(File, StreamInfo), String> {
+    if upload_data.size_bytes > MAX_UPLOAD_BYTES {
+        return Err(format!(
+            "Uploads are limited to {} bytes, got {}",
+            MAX_UPLOAD_BYTES, upload_data.size_bytes
+        ));
+    }
+    let sha256 = upload_data.sha256.trim().to_lowercase();
+    if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid SHA-256: {}", upload_data.sha256));
+    }
+
+    let partial_path = partial_path(target);
+    let partial_sha256_path = partial_sha256_path(target);
+    let resumed = std::fs::read_to_string(&partial_sha256_path).ok() == Some(sha256.clone());
+    let offset = match std::fs::metadata(&partial_path) {
+        Ok(metadata) if resumed && metadata.len() <= upload_data.size_bytes => metadata.len(),
+        _ => 0,
+    };
+    // Refuse the upload up front rather than fill the file system of the
+    // HostOS with it. The partial file already holds the first `offset`
+    // bytes.
+    let dir = target.parent().unwrap_or_else(|| Path::new("/"));
+    let available = backend.disks.available_bytes(dir)?;
+    if upload_data.size_bytes - offset > available {
+        return Err(format!(
+            "Upload of {} bytes exceeds the {} bytes available for {:?}",
+            upload_data.size_bytes - offset,
+            available,
+            target
+        ));
+    }
+    std::fs::write(&partial_sha256_path, &sha256)
+        .map_err(|err| format!("Could not write {:?}: {}", partial_sha256_path, err))?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .open(&partial_path)
+        .map_err(|err| format!("Could not open {:?}: {}", partial_path, err))?;
+    file.set_len(offset)
+        .and_then(|()| file.seek(SeekFrom::Start(offset)))
+        .map_err(|err| format!("Could not write {:?}: {}", partial_path, err))?;
+
+    Ok((
+        file,
+        StreamInfo {
+            size_bytes: upload_data.size_bytes,
+            sha256,
+            offset,
+        },
+    ))
+}
+
+/// Moves the partial file of a complete upload to `target` once its contents
+/// match the expected hash.
+fn complete_upload(target: &Path, info: StreamInfo, received: u64) -> Response {
+    let partial_path = partial_path(target);
+    if received != info.size_bytes {
+        // The bytes received so far are kept to resume the upload.
+        return Err(format!(
+            "The upload ended after {} of {} bytes",
+            received, info.size_bytes
+        ));
+    }
+    let sha256 = File::open(&partial_path)
+        .and_then(sha256_hex)
+        .map_err(|err| format!("Could not read {:?}: {}", partial_path, err))?;
+    let _ = std::fs::remove_file(partial_sha256_path(target));
+    if sha256 != info.sha256 {
+        let _ = std::fs::remove_file(&partial_path);
+        return Err(format!(
+            "The uploaded bytes do not match the expected hash.
+Expected hash: {}
+Computed hash: {}",
+            info.sha256, sha256
+        ));
+    }
+    std::fs::rename(&partial_path, target)
+        .map_err(|err| format!("Could not move the upload to {:?}: {}", target, err))?;
+
+    Ok(Payload::StreamInfo(info))
+}
+
+fn partial_path(target: &Path) -> PathBuf {
+    let mut path = target.as_os_str().to_owned();
+    path.push(".part");
+    PathBuf::from(path)
+}
+
+fn partial_sha256_path(target: &Path) -> PathBuf {
+    let mut path = target.as_os_str().to_owned();
+    path.push(".part.sha256");
+    PathBuf::from(path)
+}
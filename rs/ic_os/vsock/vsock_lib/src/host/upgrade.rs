@@ -0,0 +1,267 @@
+use crate::host::backend::Backend;
+use crate::protocol::{
+    BootSlot, Payload, Response, UpgradeData, UpgradeStage, UpgradeStatus, UPLOADED_UPGRADE_URL,
+};
+use sha2::Digest;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The boot slots as recorded in the grubenv, interpreted like
+/// `manageboot.sh` does.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct BootSlots {
+    pub active: BootSlot,
+    pub next_boot: BootSlot,
+    pub confirmed: bool,
+}
+
+impl BootSlots {
+    pub(crate) fn from_grubenv(grubenv: &str) -> Result<Self, String> {
+        let mut boot_alternative = None;
+        let mut boot_cycle = None;
+        for line in grubenv.lines().filter(|line| !line.starts_with('#')) {
+            match line.split_once('=') {
+                Some(("boot_alternative", value)) => boot_alternative = Some(value.trim()),
+                Some(("boot_cycle", value)) => boot_cycle = Some(value.trim()),
+                _ => (),
+            }
+        }
+        let boot_alternative = match boot_alternative {
+            Some("A") => BootSlot::A,
+            Some("B") => BootSlot::B,
+            Some(value) => return Err(format!("Invalid boot_alternative in grubenv: {}", value)),
+            None => return Err("No boot_alternative in grubenv".to_string()),
+        };
+        let boot_cycle = boot_cycle.ok_or_else(|| "No boot_cycle in grubenv".to_string())?;
+
+        Ok(match boot_cycle {
+            // The slot to boot next was installed but never booted, so the
+            // HostOS still runs from the other one.
+            "first_boot" => BootSlots {
+                active: boot_alternative.other(),
+                next_boot: boot_alternative,
+                confirmed: false,
+            },
+            // The slot booted for the first time and the bootloader falls
+            // back to the other one unless the boot is confirmed.
+            "failsafe_check" => BootSlots {
+                active: boot_alternative,
+                next_boot: boot_alternative.other(),
+                confirmed: false,
+            },
+            boot_cycle => BootSlots {
+                active: boot_alternative,
+                next_boot: boot_alternative,
+                confirmed: boot_cycle == "stable",
+            },
+        })
+    }
+}
+
+pub fn get_upgrade_status(backend: &Backend) -> Response {
+    Ok(Payload::UpgradeStatus(upgrade_status(backend)?))
+}
+
+fn upgrade_status(backend: &Backend) -> Result<UpgradeStatus, String> {
+    let slots = BootSlots::from_grubenv(&backend.upgrader.read_grubenv()?)?;
+    let stage = backend
+        .state
+        .lock()
+        .map_err(|_| "Could not lock host state".to_string())?
+        .upgrade_stage
+        .clone();
+
+    Ok(UpgradeStatus {
+        stage,
+        active_slot: slots.active,
+        target_slot: slots.active.other(),
+        next_boot_slot: slots.next_boot,
+        boot_confirmed: slots.confirmed,
+    })
+}
+
+fn set_upgrade_stage(backend: &Backend, stage: UpgradeStage) -> Result<(), String> {
+    backend
+        .state
+        .lock()
+        .map_err(|_| "Could not lock host state".to_string())?
+        .upgrade_stage = stage;
+    Ok(())
+}
+
+pub(crate) fn verify_hash(upgrade_file_path: &Path, target_hash: &str) -> Result<bool, String> {
+    let mut upgrade_file = match std::fs::File::open(upgrade_file_path) {
+        Ok(upgrade_file) => upgrade_file,
+        Err(err) => return Err(err.to_string()),
+    };
+
+    let mut hasher = sha2::Sha256::new();
+    let mut buffer = [0; 65536];
+
+    loop {
+        let bytes_read = upgrade_file
+            .read(&mut buffer)
+            .map_err(|err| format!("Could not read upgrade file: {}", err))?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+    }
+
+    let computed_hash = format!("{:x}", hasher.finalize());
+
+    if computed_hash == target_hash.trim().to_lowercase() {
+        Ok(true)
+    } else {
+        Err(format!(
+            "Target hash does not equal computed hash.
+Target hash: {target_hash}
+Computed hash: {computed_hash}"
+        ))
+    }
+}
+
+/// Starts an upgrade of the HostOS in the background, see [run_upgrade].
+/// Returns the status of the upgrade right away, so that the guest follows
+/// its progress with `GetUpgradeStatus` instead of waiting for the download
+/// and installation on a single connection.
+pub fn upgrade_hostos(upgrade_data: &UpgradeData, backend: &Arc<Backend>) -> Response {
+    let slots = BootSlots::from_grubenv(&backend.upgrader.read_grubenv()?)?;
+    // Installing over the other slot while the running one is unconfirmed
+    // would leave the bootloader nothing to fall back to.
+    if !slots.confirmed {
+        return Err(format!(
+            "The HostOS running from slot {} has not confirmed its boot yet",
+            slots.active
+        ));
+    }
+    {
+        let mut state = backend
+            .state
+            .lock()
+            .map_err(|_| "Could not lock host state".to_string())?;
+        if state.upgrade_stage.is_in_progress() {
+            return Err(format!(
+                "An upgrade is already in progress: {}",
+                state.upgrade_stage
+            ));
+        }
+        if state.upload_in_progress {
+            return Err("An upload of an upgrade image is in progress".to_string());
+        }
+        state.upgrade_stage = UpgradeStage::Downloading;
+    }
+    println!(
+        "Upgrading slot {}, running from slot {}",
+        slots.active.other(),
+        slots.active
+    );
+
+    // The status is taken before the upgrade makes progress.
+    let status = upgrade_status(backend)?;
+    let upgrade_data = upgrade_data.clone();
+    let upgrade_backend = Arc::clone(backend);
+    std::thread::spawn(move || run_upgrade(&upgrade_data, &upgrade_backend));
+
+    Ok(Payload::UpgradeStatus(status))
+}
+
+/// Downloads the upgrade image, or takes the one the guest uploaded if the
+/// URL is [UPLOADED_UPGRADE_URL], verifies it against the expected hash,
+/// installs it into the slot the HostOS is not running from and reboots into
+/// it. Each step is recorded as the stage of the upgrade.
+fn run_upgrade(upgrade_data: &UpgradeData, backend: &Backend) {
+    let result = install_upgrade(upgrade_data, backend).and_then(|()| {
+        set_upgrade_stage(backend, UpgradeStage::Rebooting)?;
+        let status = upgrade_status(backend)?;
+        println!("Rebooting into slot {}...", status.next_boot_slot);
+        backend
+            .upgrader
+            .reboot()
+            .map(|_| ())
+            .map_err(|err| format!("Could not reboot: {}", err))
+    });
+    if let Err(err) = result {
+        println!("Upgrade failed: {}", err);
+        let _ = set_upgrade_stage(backend, UpgradeStage::Failed(err));
+    }
+}
+
+fn install_upgrade(upgrade_data: &UpgradeData, backend: &Backend) -> Result<(), String> {
+    if upgrade_data.url == UPLOADED_UPGRADE_URL {
+        println!("Using the uploaded hostos upgrade file...");
+    } else {
+        println!("Creating hostos upgrade file...");
+        backend
+            .upgrader
+            .download(&upgrade_data.url, &backend.upgrade_file_path)?;
+    }
+
+    println!("Verifying hostos upgrade file hash...");
+    set_upgrade_stage(backend, UpgradeStage::Verifying)?;
+    if let Err(err) = verify_hash(&backend.upgrade_file_path, &upgrade_data.target_hash) {
+        let _ = std::fs::remove_file(&backend.upgrade_file_path);
+        return Err(err);
+    }
+
+    println!("Starting upgrade...");
+    set_upgrade_stage(backend, UpgradeStage::Installing)?;
+    backend.upgrader.install(&backend.upgrade_file_path)?;
+    Ok(())
+}
+
+pub mod tests {
+    #[test]
+    fn create_hostos_upgrade_file_and_verify_hash() {
+        use super::*;
+
+        let upgrade_url = std::env::var("URL").unwrap_or_else(|_| "dummy url".to_string());
+        let hash = std::env::var("HASH").unwrap_or_else(|_| "dummy hash".to_string());
+
+        let backend = Backend::system();
+        backend
+            .upgrader
+            .download(&upgrade_url, &backend.upgrade_file_path)
+            .unwrap();
+        assert!(verify_hash(&backend.upgrade_file_path, &hash).unwrap())
+    }
+
+    #[test]
+    fn reads_boot_slots_from_grubenv() {
+        use super::*;
+
+        let grubenv = |boot_alternative: &str, boot_cycle: &str| {
+            format!(
+                "# GRUB Environment Block\nboot_alternative={}\nboot_cycle={}\n####",
+                boot_alternative, boot_cycle
+            )
+        };
+        assert_eq!(
+            BootSlots::from_grubenv(&grubenv("A", "stable")),
+            Ok(BootSlots {
+                active: BootSlot::A,
+                next_boot: BootSlot::A,
+                confirmed: true,
+            })
+        );
+        assert_eq!(
+            BootSlots::from_grubenv(&grubenv("B", "first_boot")),
+            Ok(BootSlots {
+                active: BootSlot::A,
+                next_boot: BootSlot::B,
+                confirmed: false,
+            })
+        );
+        assert_eq!(
+            BootSlots::from_grubenv(&grubenv("B", "failsafe_check")),
+            Ok(BootSlots {
+                active: BootSlot::B,
+                next_boot: BootSlot::A,
+                confirmed: false,
+            })
+        );
+        assert!(BootSlots::from_grubenv(&grubenv("C", "stable")).is_err());
+        assert!(BootSlots::from_grubenv("boot_cycle=stable").is_err());
+    }
+}
@@ -1,9 +1,12 @@
 #![cfg(target_os = "linux")]
 
 mod guest;
-pub use guest::send_command;
+pub use guest::{download_from_host, send_command, upload_to_host};
 
 mod host;
 pub use host::server::run_server;
 
 pub mod protocol;
+
+#[cfg(test)]
+mod e2e_tests;
@@ -1,5 +1,7 @@
+mod streaming;
 mod structures;
 mod utils;
 
+pub use streaming::*;
 pub use structures::*;
 pub use utils::*;
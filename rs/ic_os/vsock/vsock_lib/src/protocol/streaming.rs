@@ -0,0 +1,428 @@
+//! Framed streaming of payloads too large for a single response, such as
+//! HostOS upgrade images or the full console log of the guest.
+//!
+//! A transfer starts like any other command, with the guest sending a JSON
+//! `Request`. From then on, both sides exchange frames of the form
+//!
+//! ```text
+//! | kind: u8 | offset: u64 | length: u32 | SHA-256 of the payload | payload |
+//! ```
+//!
+//! with the integers in big endian. The host answers the request with a
+//! `Control` frame holding a JSON `Response`, which is a `StreamInfo` if it
+//! accepts the transfer. The sending side then sends the bytes from the
+//! offset of the `StreamInfo` on in `Data` frames of at most
+//! `MAX_CHUNK_BYTES`, followed by an `End` frame, which the receiving side
+//! answers with an `End` frame of its own.
+//!
+//! The receiving side acknowledges each `Data` frame with an `Ack` frame of
+//! the offset it wrote up to, and the sending side waits for an
+//! acknowledgement whenever `WINDOW_FRAMES` frames are unacknowledged. Hence,
+//! a slow receiver slows down the sender instead of data piling up, and an
+//! interrupted transfer can be resumed from the bytes the receiver kept.
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+/// The maximal size of the payload of a frame.
+pub const MAX_CHUNK_BYTES: usize = 64 * 1024;
+/// The number of `Data` frames the sender sends ahead of the
+/// acknowledgements of the receiver.
+pub const WINDOW_FRAMES: u32 = 16;
+
+const CHECKSUM_BYTES: usize = 32;
+const HEADER_BYTES: usize = 1 + 8 + 4 + CHECKSUM_BYTES;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameKind {
+    /// A JSON message, e.g. the `Response` to the request starting the
+    /// transfer.
+    Control,
+    /// A chunk of the transferred bytes, starting at the offset of the frame.
+    Data,
+    /// The receiver wrote all bytes up to the offset of the frame.
+    Ack,
+    /// The sender sent all bytes up to the offset of the frame, or, from the
+    /// receiver, it received all of them.
+    End,
+}
+
+impl FrameKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            FrameKind::Control => 1,
+            FrameKind::Data => 2,
+            FrameKind::Ack => 3,
+            FrameKind::End => 4,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self> {
+        match byte {
+            1 => Ok(FrameKind::Control),
+            2 => Ok(FrameKind::Data),
+            3 => Ok(FrameKind::Ack),
+            4 => Ok(FrameKind::End),
+            byte => Err(invalid_data(format!("Unknown frame kind {}", byte))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    pub offset: u64,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    fn new(kind: FrameKind, offset: u64) -> Self {
+        Self {
+            kind,
+            offset,
+            payload: vec![],
+        }
+    }
+}
+
+pub fn write_frame<W: Write>(writer: &mut W, frame: &Frame) -> Result<()> {
+    if frame.payload.len() > MAX_CHUNK_BYTES {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Frame of {} bytes exceeds the maximum of {}",
+                frame.payload.len(),
+                MAX_CHUNK_BYTES
+            ),
+        ));
+    }
+    let mut header = Vec::with_capacity(HEADER_BYTES);
+    header.push(frame.kind.to_byte());
+    header.extend_from_slice(&frame.offset.to_be_bytes());
+    header.extend_from_slice(&(frame.payload.len() as u32).to_be_bytes());
+    header.extend_from_slice(&Sha256::digest(&frame.payload));
+    writer.write_all(&header)?;
+    writer.write_all(&frame.payload)?;
+    writer.flush()
+}
+
+/// Reads a frame, failing with `ErrorKind::InvalidData` if its payload does
+/// not match its checksum.
+pub fn read_frame<R: Read>(reader: &mut R) -> Result<Frame> {
+    let mut header = [0; HEADER_BYTES];
+    reader.read_exact(&mut header)?;
+    let kind = FrameKind::from_byte(header[0])?;
+    let offset = u64::from_be_bytes(header[1..9].try_into().unwrap());
+    let length = u32::from_be_bytes(header[9..13].try_into().unwrap()) as usize;
+    if length > MAX_CHUNK_BYTES {
+        return Err(invalid_data(format!(
+            "Frame of {} bytes exceeds the maximum of {}",
+            length, MAX_CHUNK_BYTES
+        )));
+    }
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload)?;
+    if Sha256::digest(&payload)[..] != header[13..] {
+        return Err(invalid_data(format!(
+            "Checksum mismatch in the {:?} frame at offset {}",
+            kind, offset
+        )));
+    }
+    Ok(Frame {
+        kind,
+        offset,
+        payload,
+    })
+}
+
+pub fn write_control<W: Write, T: Serialize>(writer: &mut W, message: &T) -> Result<()> {
+    let payload = serde_json::to_vec(message)?;
+    write_frame(
+        writer,
+        &Frame {
+            kind: FrameKind::Control,
+            offset: 0,
+            payload,
+        },
+    )
+}
+
+pub fn read_control<R: Read, T: DeserializeOwned>(reader: &mut R) -> Result<T> {
+    let frame = read_frame_of_kind(reader, FrameKind::Control)?;
+    serde_json::from_slice(&frame.payload).map_err(|err| invalid_data(err.to_string()))
+}
+
+/// Sends the bytes of `source` in `Data` frames, the first at `offset`, and
+/// waits until the receiver received all of them. Returns the offset after
+/// the last byte.
+pub fn send_stream<S: Read + Write, R: Read>(
+    stream: &mut S,
+    source: &mut R,
+    offset: u64,
+) -> Result<u64> {
+    let mut buffer = vec![0; MAX_CHUNK_BYTES];
+    let mut sent = offset;
+    let mut acknowledged = offset;
+    let mut unacknowledged_frames = 0;
+    loop {
+        let bytes_read = read_chunk(source, &mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        if unacknowledged_frames == WINDOW_FRAMES {
+            acknowledged = read_ack(stream, acknowledged, sent)?;
+            unacknowledged_frames -= 1;
+        }
+        write_frame(
+            stream,
+            &Frame {
+                kind: FrameKind::Data,
+                offset: sent,
+                payload: buffer[..bytes_read].to_vec(),
+            },
+        )?;
+        sent += bytes_read as u64;
+        unacknowledged_frames += 1;
+    }
+    write_frame(stream, &Frame::new(FrameKind::End, sent))?;
+
+    loop {
+        let frame = read_frame(stream)?;
+        match frame.kind {
+            FrameKind::Ack => {
+                acknowledged = check_ack(&frame, acknowledged, sent)?;
+            }
+            FrameKind::End if frame.offset == sent => return Ok(sent),
+            FrameKind::End => {
+                return Err(invalid_data(format!(
+                    "The receiver ended the stream at offset {} instead of {}",
+                    frame.offset, sent
+                )))
+            }
+            kind => return Err(unexpected_frame(kind)),
+        }
+    }
+}
+
+/// Writes the `Data` frames of a stream starting at `offset` into `sink`
+/// until the `End` frame, acknowledging each frame once it is written. Fails
+/// if the stream goes beyond `max_offset`. Returns the offset after the last
+/// byte.
+pub fn receive_stream<S: Read + Write, W: Write>(
+    stream: &mut S,
+    sink: &mut W,
+    offset: u64,
+    max_offset: u64,
+) -> Result<u64> {
+    let mut received = offset;
+    loop {
+        let frame = read_frame(stream)?;
+        if frame.offset != received {
+            return Err(invalid_data(format!(
+                "Expected a frame at offset {}, received one at offset {}",
+                received, frame.offset
+            )));
+        }
+        match frame.kind {
+            FrameKind::Data => {
+                let end = received + frame.payload.len() as u64;
+                if end > max_offset {
+                    return Err(invalid_data(format!(
+                        "The stream goes beyond offset {}",
+                        max_offset
+                    )));
+                }
+                sink.write_all(&frame.payload)?;
+                sink.flush()?;
+                received = end;
+                write_frame(stream, &Frame::new(FrameKind::Ack, received))?;
+            }
+            FrameKind::End => {
+                write_frame(stream, &Frame::new(FrameKind::End, received))?;
+                return Ok(received);
+            }
+            kind => return Err(unexpected_frame(kind)),
+        }
+    }
+}
+
+/// The hex encoded SHA-256 of all bytes of `reader`.
+pub fn sha256_hex<R: Read>(mut reader: R) -> Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; MAX_CHUNK_BYTES];
+    loop {
+        let bytes_read = read_chunk(&mut reader, &mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Fills `buffer` unless `source` ends before, so that frames are full.
+fn read_chunk<R: Read>(source: &mut R, buffer: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match source.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(bytes_read) => filled += bytes_read,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+fn read_ack<S: Read>(stream: &mut S, acknowledged: u64, sent: u64) -> Result<u64> {
+    let frame = read_frame_of_kind(stream, FrameKind::Ack)?;
+    check_ack(&frame, acknowledged, sent)
+}
+
+/// Acknowledgements only move forward, and not beyond what was sent.
+fn check_ack(frame: &Frame, acknowledged: u64, sent: u64) -> Result<u64> {
+    if frame.offset < acknowledged || frame.offset > sent {
+        return Err(invalid_data(format!(
+            "Acknowledgement of offset {} outside of {}..={}",
+            frame.offset, acknowledged, sent
+        )));
+    }
+    Ok(frame.offset)
+}
+
+fn read_frame_of_kind<R: Read>(reader: &mut R, kind: FrameKind) -> Result<Frame> {
+    let frame = read_frame(reader)?;
+    if frame.kind != kind {
+        return Err(invalid_data(format!(
+            "Expected a {:?} frame, received a {:?} frame",
+            kind, frame.kind
+        )));
+    }
+    Ok(frame)
+}
+
+fn unexpected_frame(kind: FrameKind) -> Error {
+    invalid_data(format!("Unexpected {:?} frame in the stream", kind))
+}
+
+fn invalid_data(error: String) -> Error {
+    Error::new(ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+
+    fn contents(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn frames_roundtrip() {
+        let frame = Frame {
+            kind: FrameKind::Data,
+            offset: 1 << 40,
+            payload: contents(1000),
+        };
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, &frame).unwrap();
+        assert_eq!(buffer.len(), HEADER_BYTES + 1000);
+        assert_eq!(read_frame(&mut buffer.as_slice()).unwrap(), frame);
+    }
+
+    #[test]
+    fn rejects_corrupted_and_oversized_frames() {
+        let mut buffer = Vec::new();
+        write_frame(
+            &mut buffer,
+            &Frame {
+                kind: FrameKind::Data,
+                offset: 0,
+                payload: contents(100),
+            },
+        )
+        .unwrap();
+        buffer[HEADER_BYTES + 10] ^= 1;
+        let err = read_frame(&mut buffer.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("Checksum mismatch"));
+
+        let oversized = Frame {
+            kind: FrameKind::Data,
+            offset: 0,
+            payload: contents(MAX_CHUNK_BYTES + 1),
+        };
+        assert!(write_frame(&mut Vec::new(), &oversized).is_err());
+        let mut header = vec![FrameKind::Data.to_byte()];
+        header.extend_from_slice(&0u64.to_be_bytes());
+        header.extend_from_slice(&(MAX_CHUNK_BYTES as u32 + 1).to_be_bytes());
+        header.extend_from_slice(&[0; CHECKSUM_BYTES]);
+        assert_eq!(
+            read_frame(&mut header.as_slice()).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn streams_multiple_windows() {
+        // More frames than fit into a window, the last one not full.
+        let source = contents(3 * WINDOW_FRAMES as usize * MAX_CHUNK_BYTES + 12345);
+        let (mut sender, mut receiver) = UnixStream::pair().unwrap();
+        let max_offset = source.len() as u64;
+        let receiving = std::thread::spawn(move || {
+            let mut sink = Vec::new();
+            let end = receive_stream(&mut receiver, &mut sink, 0, max_offset).unwrap();
+            (end, sink)
+        });
+
+        assert_eq!(
+            send_stream(&mut sender, &mut source.as_slice(), 0).unwrap(),
+            max_offset
+        );
+        let (end, sink) = receiving.join().unwrap();
+        assert_eq!(end, max_offset);
+        assert_eq!(sink, source);
+    }
+
+    #[test]
+    fn resumes_at_offset() {
+        let source = contents(3 * MAX_CHUNK_BYTES);
+        let offset = MAX_CHUNK_BYTES as u64 + 7;
+        let (mut sender, mut receiver) = UnixStream::pair().unwrap();
+        let receiving = std::thread::spawn(move || {
+            let mut sink = Vec::new();
+            receive_stream(&mut receiver, &mut sink, offset, 3 * MAX_CHUNK_BYTES as u64).unwrap();
+            sink
+        });
+
+        send_stream(&mut sender, &mut &source[offset as usize..], offset).unwrap();
+        assert_eq!(receiving.join().unwrap(), &source[offset as usize..]);
+    }
+
+    #[test]
+    fn receiver_rejects_streams_beyond_max_offset_or_out_of_order() {
+        let mut frames = Vec::new();
+        for offset in [0, 100] {
+            write_frame(
+                &mut frames,
+                &Frame {
+                    kind: FrameKind::Data,
+                    offset,
+                    payload: contents(100),
+                },
+            )
+            .unwrap();
+        }
+        let receive = |offset: u64, max_offset: u64| {
+            let (mut sender, mut receiver) = UnixStream::pair().unwrap();
+            sender.write_all(&frames).unwrap();
+            receive_stream(&mut receiver, &mut Vec::new(), offset, max_offset).unwrap_err()
+        };
+
+        assert!(receive(0, 150).to_string().contains("beyond offset 150"));
+        assert!(receive(50, 1000)
+            .to_string()
+            .contains("Expected a frame at offset 50"));
+    }
+}
@@ -6,6 +6,14 @@ pub type Response = Result<Payload, String>;
 pub enum Payload {
     HostOSVsockVersion(HostOSVsockVersion),
     HostOSVersion(String),
+    HardwareHealth(HardwareHealth),
+    HostMetrics(HostMetrics),
+    UpgradeStatus(UpgradeStatus),
+    /// The tail of the guestOS console log.
+    GuestConsole(String),
+    /// A stream transfer accepted by the host, or completed.
+    StreamInfo(StreamInfo),
+    Attestation(HostAttestation),
     NoPayload,
 }
 
@@ -14,6 +22,12 @@ impl fmt::Display for Payload {
         match self {
             Payload::HostOSVsockVersion(version) => write!(f, "HostOSVsockVersion({})", version),
             Payload::HostOSVersion(version) => write!(f, "HostOSVersion({})", version),
+            Payload::HardwareHealth(health) => write!(f, "HardwareHealth({})", health),
+            Payload::HostMetrics(metrics) => write!(f, "HostMetrics({})", metrics),
+            Payload::UpgradeStatus(status) => write!(f, "UpgradeStatus({})", status),
+            Payload::GuestConsole(log) => write!(f, "GuestConsole({} bytes)", log.len()),
+            Payload::StreamInfo(info) => write!(f, "StreamInfo({})", info),
+            Payload::Attestation(attestation) => write!(f, "Attestation({})", attestation),
             Payload::NoPayload => write!(f, "NoPayload"),
         }
     }
@@ -52,14 +66,45 @@ pub enum Command {
     SetNodeId(NodeIdData),
     #[serde(rename = "attach-hsm")]
     AttachHSM,
+    #[serde(rename = "attach-hsm-by-serial")]
+    AttachHSMBySerial(HSMSerialData),
     #[serde(rename = "detach-hsm")]
     DetachHSM,
     #[serde(rename = "upgrade")]
     Upgrade(UpgradeData),
+    #[serde(rename = "get-upgrade-status")]
+    GetUpgradeStatus,
     #[serde(rename = "notify")]
     Notify(NotifyData),
     GetVsockProtocol,
     GetHostOSVersion,
+    #[serde(rename = "get-hardware-health")]
+    GetHardwareHealth,
+    #[serde(rename = "get-host-metrics")]
+    GetHostMetrics,
+    #[serde(rename = "get-guest-console")]
+    GetGuestConsole(GuestConsoleData),
+    #[serde(rename = "update-firewall")]
+    UpdateFirewall(FirewallRulesetData),
+    #[serde(rename = "attach-disk")]
+    AttachDisk(DiskData),
+    #[serde(rename = "detach-disk")]
+    DetachDisk(DiskSource),
+    #[serde(rename = "stream-download")]
+    StreamDownload(StreamDownloadData),
+    #[serde(rename = "stream-upload")]
+    StreamUpload(StreamUploadData),
+    #[serde(rename = "get-attestation")]
+    GetAttestation(AttestationData),
+}
+
+impl Command {
+    /// Whether the command starts a stream transfer, see
+    /// [crate::protocol::send_stream], after which the host answers in frames
+    /// instead of a plain JSON response.
+    pub fn is_stream(&self) -> bool {
+        matches!(self, Command::StreamDownload(_) | Command::StreamUpload(_))
+    }
 }
 
 impl fmt::Display for Command {
@@ -69,12 +114,16 @@ impl fmt::Display for Command {
                 write!(f, "Command: Set Node ID\nNode ID: {}", node_id_data.node_id)
             }
             Command::AttachHSM => write!(f, "Command: Attach HSM"),
+            Command::AttachHSMBySerial(hsm_serial_data) => {
+                write!(f, "Command: Attach HSM\nSerial: {}", hsm_serial_data.serial)
+            }
             Command::DetachHSM => write!(f, "Command: Detach HSM"),
             Command::Upgrade(upgrade_data) => write!(
                 f,
                 "Command: Upgrade\nURL: {}\nHASH: {}",
                 upgrade_data.url, upgrade_data.target_hash
             ),
+            Command::GetUpgradeStatus => write!(f, "Command: Get Upgrade Status"),
             Command::Notify(notify_data) => write!(
                 f,
                 "Command: Notify\nMessage: {}\nCount: {}",
@@ -82,6 +131,40 @@ impl fmt::Display for Command {
             ),
             Command::GetVsockProtocol => write!(f, "Command: Get Vsock Protocol"),
             Command::GetHostOSVersion => write!(f, "Command: Get HostOS Version"),
+            Command::GetHardwareHealth => write!(f, "Command: Get Hardware Health"),
+            Command::GetHostMetrics => write!(f, "Command: Get Host Metrics"),
+            Command::GetGuestConsole(guest_console_data) => write!(
+                f,
+                "Command: Get Guest Console\nLines: {}",
+                guest_console_data.lines
+            ),
+            Command::UpdateFirewall(ruleset) => write!(
+                f,
+                "Command: Update Firewall\nRegistry version: {}\nRules: {}",
+                ruleset.registry_version,
+                ruleset.rules.len()
+            ),
+            Command::AttachDisk(disk_data) => write!(
+                f,
+                "Command: Attach Disk\nSource: {}\nSize: {} bytes",
+                disk_data.source, disk_data.size_bytes
+            ),
+            Command::DetachDisk(source) => write!(f, "Command: Detach Disk\nSource: {}", source),
+            Command::StreamDownload(download_data) => write!(
+                f,
+                "Command: Stream Download\nSource: {}\nOffset: {}",
+                download_data.source, download_data.offset
+            ),
+            Command::StreamUpload(upload_data) => write!(
+                f,
+                "Command: Stream Upload\nTarget: {}\nSize: {} bytes\nHASH: {}",
+                upload_data.target, upload_data.size_bytes, upload_data.sha256
+            ),
+            Command::GetAttestation(attestation_data) => write!(
+                f,
+                "Command: Get Attestation\nNonce: {}",
+                attestation_data.nonce
+            ),
         }
     }
 }
@@ -121,14 +204,403 @@ pub struct NodeIdData {
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct HSMSerialData {
+    pub serial: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct UpgradeData {
     pub url: String,
     #[serde(rename = "target-hash")]
     pub target_hash: String,
 }
 
+/// One of the two sets of HostOS partitions, which are alternately
+/// upgraded, see `manageboot.sh`.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum BootSlot {
+    A,
+    B,
+}
+
+impl BootSlot {
+    pub fn other(self) -> Self {
+        match self {
+            BootSlot::A => BootSlot::B,
+            BootSlot::B => BootSlot::A,
+        }
+    }
+}
+
+impl fmt::Display for BootSlot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BootSlot::A => write!(f, "A"),
+            BootSlot::B => write!(f, "B"),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+pub enum UpgradeStage {
+    /// No upgrade was requested since the HostOS booted.
+    #[default]
+    Idle,
+    /// The upgrade image is being downloaded.
+    Downloading,
+    /// The upgrade image is being verified against the expected hash.
+    Verifying,
+    /// The upgrade image is being installed into the target slot.
+    Installing,
+    /// The last upgrade was installed into the target slot and the HostOS is
+    /// rebooting into it.
+    Rebooting,
+    /// The last upgrade failed before the HostOS rebooted, for the given
+    /// reason. Nothing was installed if it failed to download or verify.
+    Failed(String),
+}
+
+impl UpgradeStage {
+    /// Whether an upgrade is running, in which case no other one is started.
+    pub fn is_in_progress(&self) -> bool {
+        matches!(
+            self,
+            UpgradeStage::Downloading
+                | UpgradeStage::Verifying
+                | UpgradeStage::Installing
+                | UpgradeStage::Rebooting
+        )
+    }
+}
+
+impl fmt::Display for UpgradeStage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UpgradeStage::Idle => write!(f, "Idle"),
+            UpgradeStage::Downloading => write!(f, "Downloading"),
+            UpgradeStage::Verifying => write!(f, "Verifying"),
+            UpgradeStage::Installing => write!(f, "Installing"),
+            UpgradeStage::Rebooting => write!(f, "Rebooting"),
+            UpgradeStage::Failed(error) => write!(f, "Failed({})", error),
+        }
+    }
+}
+
+/// The progress of the last HostOS upgrade and the state of the boot slots.
+/// After an upgrade, the HostOS boots the target slot unconfirmed and falls
+/// back to the other slot if it reboots before confirming the boot.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct UpgradeStatus {
+    pub stage: UpgradeStage,
+    /// The slot the HostOS is running from.
+    pub active_slot: BootSlot,
+    /// The slot an upgrade is written to.
+    pub target_slot: BootSlot,
+    /// The slot the bootloader boots next.
+    pub next_boot_slot: BootSlot,
+    /// Whether the running HostOS confirmed its boot. Upgrades are refused
+    /// until it did.
+    pub boot_confirmed: bool,
+}
+
+impl fmt::Display for UpgradeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{{ stage: {}, active_slot: {}, target_slot: {}, next_boot_slot: {}, boot_confirmed: {} }}",
+            self.stage, self.active_slot, self.target_slot, self.next_boot_slot, self.boot_confirmed
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct GuestConsoleData {
+    /// The number of lines to return from the end of the console log.
+    pub lines: u32,
+}
+
+/// The sources allowed to open connections to the HostOS, derived from the
+/// registry by the guest. It replaces the previously applied ruleset.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+pub struct FirewallRulesetData {
+    /// The registry version the ruleset was derived from, recorded in the
+    /// audit log of the host.
+    pub registry_version: u64,
+    pub rules: Vec<FirewallRule>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct FirewallRule {
+    /// An IPv4 or IPv6 address, or a prefix such as `2001:db8::/64`.
+    pub source: String,
+    /// The TCP ports the source may connect to.
+    pub ports: Vec<u16>,
+    /// Describes the rule, e.g. with the ID of the node it allows.
+    pub comment: String,
+}
+
+/// A host volume or file to attach to the guest as an additional virtio disk.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct DiskData {
+    pub source: DiskSource,
+    /// The size the guest expects the disk to have. The host refuses to
+    /// attach a disk of another size, and creates missing raw files with it.
+    pub size_bytes: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DiskSource {
+    /// A logical volume of the `hostlvm` volume group, by name.
+    #[serde(rename = "lvm-volume")]
+    LvmVolume(String),
+    /// A raw image file in the guest disk directory of the host, by name.
+    #[serde(rename = "raw-file")]
+    RawFile(String),
+}
+
+impl fmt::Display for DiskSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiskSource::LvmVolume(name) => write!(f, "lvm:{}", name),
+            DiskSource::RawFile(name) => write!(f, "file:{}", name),
+        }
+    }
+}
+
+/// The URL of an `upgrade` command installing the image uploaded with a
+/// `stream-upload` to `StreamTarget::UpgradeImage` instead of downloading one.
+pub const UPLOADED_UPGRADE_URL: &str = "vsock:uploaded-upgrade-image";
+
+/// What the host streams to the guest.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum StreamSource {
+    /// The whole console log of the guest, which `get-guest-console` caps.
+    #[serde(rename = "guest-console")]
+    GuestConsole,
+}
+
+impl fmt::Display for StreamSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StreamSource::GuestConsole => write!(f, "guest-console"),
+        }
+    }
+}
+
+/// Where the host stores what the guest streams to it.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum StreamTarget {
+    /// A HostOS upgrade image, installed by an `upgrade` command with the URL
+    /// [UPLOADED_UPGRADE_URL].
+    #[serde(rename = "upgrade-image")]
+    UpgradeImage,
+}
+
+impl fmt::Display for StreamTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StreamTarget::UpgradeImage => write!(f, "upgrade-image"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct StreamDownloadData {
+    pub source: StreamSource,
+    /// The number of bytes the guest kept from an interrupted download, which
+    /// the host skips.
+    pub offset: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct StreamUploadData {
+    pub target: StreamTarget,
+    pub size_bytes: u64,
+    /// The hex encoded SHA-256 of the uploaded bytes. The host resumes an
+    /// interrupted upload of the same bytes.
+    pub sha256: String,
+}
+
+/// A stream transfer, as accepted by the host.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct StreamInfo {
+    pub size_bytes: u64,
+    /// The hex encoded SHA-256 of all `size_bytes` bytes, which the receiving
+    /// side verifies once it has all of them.
+    pub sha256: String,
+    /// The offset the transfer starts at, non-zero if it resumes an
+    /// interrupted one.
+    pub offset: u64,
+}
+
+impl fmt::Display for StreamInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{{ size_bytes: {}, sha256: {}, offset: {} }}",
+            self.size_bytes, self.sha256, self.offset
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct AttestationData {
+    /// A hex encoded value of at most 32 bytes chosen by the guest, e.g. a
+    /// hash over its own attestation evidence, which the evidence of the host
+    /// covers so that it can't be replayed.
+    pub nonce: String,
+}
+
+/// Evidence of the measured boot state of the HostOS.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct HostAttestation {
+    pub tpm_quote: TpmQuote,
+}
+
+impl fmt::Display for HostAttestation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{ pcrs: {} }}", self.tpm_quote.pcrs.len())
+    }
+}
+
+/// A quote of the TPM of the host, signed by its attestation key.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct TpmQuote {
+    /// The hex encoded `TPMS_ATTEST` structure signed by the TPM, which holds
+    /// the nonce and the digest of the quoted PCRs.
+    pub message: String,
+    /// The hex encoded `TPMT_SIGNATURE` over `message`.
+    pub signature: String,
+    /// The quoted PCRs of the SHA-256 bank.
+    pub pcrs: Vec<PcrValue>,
+    /// The public part of the attestation key, in PEM format.
+    pub attestation_key_pem: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct PcrValue {
+    pub index: u32,
+    /// The hex encoded SHA-256 value of the PCR.
+    pub sha256: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct NotifyData {
     pub count: u32,
     pub message: String,
 }
+
+/// Snapshot of the host hardware health, as gathered by the HostOS from the
+/// BMC sensors and the disks' SMART data.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+pub struct HardwareHealth {
+    pub fans: Vec<FanReading>,
+    pub temperatures: Vec<TemperatureReading>,
+    pub power_supplies: Vec<PowerSupplyStatus>,
+    pub disks: Vec<DiskHealth>,
+}
+
+impl fmt::Display for HardwareHealth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{{ fans: {}, temperatures: {}, power_supplies: {}, disks: {} }}",
+            self.fans.len(),
+            self.temperatures.len(),
+            self.power_supplies.len(),
+            self.disks.len()
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct FanReading {
+    pub name: String,
+    pub status: String,
+    /// `None` if the sensor does not report a reading (e.g. fan not present).
+    pub rpm: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct TemperatureReading {
+    pub name: String,
+    pub status: String,
+    pub degrees_celsius: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct PowerSupplyStatus {
+    pub name: String,
+    pub status: String,
+    pub ok: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct DiskHealth {
+    pub device: String,
+    /// Whether the SMART overall-health self-assessment passed.
+    pub passed: bool,
+    pub summary: String,
+}
+
+/// Snapshot of the resource usage of the HostOS, as read from `/proc` and
+/// `df`. Counters are cumulative since the boot of the host.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+pub struct HostMetrics {
+    pub cpu: CpuMetrics,
+    pub memory: MemoryMetrics,
+    pub filesystems: Vec<FilesystemUsage>,
+    pub network_interfaces: Vec<NetworkInterfaceCounters>,
+}
+
+impl fmt::Display for HostMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{{ cores: {}, filesystems: {}, network_interfaces: {} }}",
+            self.cpu.cores,
+            self.filesystems.len(),
+            self.network_interfaces.len()
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+pub struct CpuMetrics {
+    pub cores: u32,
+    /// The load averages over 1, 5 and 15 minutes, multiplied by 100.
+    pub load_averages_centi: [u32; 3],
+    /// The time all cores spent busy and idle, in ticks of `USER_HZ`.
+    pub busy_ticks: u64,
+    pub idle_ticks: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+pub struct MemoryMetrics {
+    pub total_bytes: u64,
+    pub available_bytes: u64,
+    pub swap_total_bytes: u64,
+    pub swap_free_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct FilesystemUsage {
+    pub device: String,
+    pub mount_point: String,
+    pub size_bytes: u64,
+    pub used_bytes: u64,
+    pub available_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+pub struct NetworkInterfaceCounters {
+    pub name: String,
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_errors: u64,
+    pub tx_dropped: u64,
+}
@@ -111,11 +111,17 @@ pub fn parse_request(json_str: &str) -> Result<Request, String> {
 pub fn get_v0_request_vec(request: &Request) -> Result<Vec<u8>, String> {
     let message = match &request.command {
         Command::AttachHSM => "attach-hsm".to_string(),
+        Command::AttachHSMBySerial(_) => {
+            return Err("Cannot process AttachHSMBySerial command for v0".to_string())
+        }
         Command::DetachHSM => "detach-hsm".to_string(),
         Command::SetNodeId(node_id) => format!("set-node-id[{}]", node_id.node_id),
         Command::Upgrade(upgrade_data) => {
             format!("upgrade[{} {}]", upgrade_data.url, upgrade_data.target_hash)
         }
+        Command::GetUpgradeStatus => {
+            return Err("Cannot process GetUpgradeStatus command for v0".to_string())
+        }
         Command::Notify(notify_data) => {
             format!("notify[{}, {}]", notify_data.count, notify_data.message)
         }
@@ -125,6 +131,30 @@ pub fn get_v0_request_vec(request: &Request) -> Result<Vec<u8>, String> {
         Command::GetHostOSVersion => {
             return Err("Cannot process GetHostOSVersion command for v0".to_string())
         }
+        Command::GetHardwareHealth => {
+            return Err("Cannot process GetHardwareHealth command for v0".to_string())
+        }
+        Command::GetHostMetrics => {
+            return Err("Cannot process GetHostMetrics command for v0".to_string())
+        }
+        Command::GetGuestConsole(_) => {
+            return Err("Cannot process GetGuestConsole command for v0".to_string())
+        }
+        Command::UpdateFirewall(_) => {
+            return Err("Cannot process UpdateFirewall command for v0".to_string())
+        }
+        Command::AttachDisk(_) => {
+            return Err("Cannot process AttachDisk command for v0".to_string())
+        }
+        Command::DetachDisk(_) => {
+            return Err("Cannot process DetachDisk command for v0".to_string())
+        }
+        Command::StreamDownload(_) | Command::StreamUpload(_) => {
+            return Err("Cannot process stream commands for v0".to_string())
+        }
+        Command::GetAttestation(_) => {
+            return Err("Cannot process GetAttestation command for v0".to_string())
+        }
     };
 
     let request = serde_json::json!({
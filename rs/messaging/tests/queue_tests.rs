@@ -12,7 +12,7 @@ use ic_test_utilities_metrics::fetch_int_counter_vec;
 use ic_types::{messages::RequestOrResponse, xnet::StreamIndex, Cycles};
 use maplit::btreemap;
 use std::sync::Arc;
-use xnet_test::Metrics;
+use xnet_test::{FaultInjection, Metrics, PayloadSizeDistribution};
 
 const MAX_TICKS: u64 = 100;
 
@@ -89,6 +89,30 @@ impl SingleCanisterSubnetPair {
         )
     }
 
+    /// Generate the payload for the 'start' method on an XNet canister, such that
+    /// it attaches `cycles_per_call` cycles to each of its requests and has faults
+    /// injected into their handling by the receiving canister.
+    fn start_payload_with_cycles(
+        &self,
+        canister_to_subnet_rate: u64,
+        payload_size_bytes: u64,
+        fault_injection: FaultInjection,
+        cycles_per_call: u64,
+    ) -> Result<Vec<u8>, candid::Error> {
+        let network_topology = vec![
+            vec![self.local_canister_id.get().to_vec()],
+            vec![self.remote_canister_id.get().to_vec()],
+        ];
+        Encode!(
+            &network_topology,
+            &canister_to_subnet_rate,
+            &payload_size_bytes,
+            &None::<PayloadSizeDistribution>,
+            &Some(fault_injection),
+            &Some(cycles_per_call)
+        )
+    }
+
     /// Calls the 'start' method on the local canister.
     pub fn start_local_canister(
         &self,
@@ -113,11 +137,37 @@ impl SingleCanisterSubnetPair {
         call_start_on_xnet_canister(&self.remote_env, self.remote_canister_id, payload)
     }
 
+    /// Calls the 'start' method on both canisters, such that they attach
+    /// `cycles_per_call` cycles to each of their requests.
+    pub fn start_canisters_with_cycles(
+        &self,
+        canister_to_subnet_rate: u64,
+        payload_size_bytes: u64,
+        fault_injection: FaultInjection,
+        cycles_per_call: u64,
+    ) -> Result<(), UserError> {
+        let payload = self
+            .start_payload_with_cycles(
+                canister_to_subnet_rate,
+                payload_size_bytes,
+                fault_injection,
+                cycles_per_call,
+            )
+            .unwrap();
+        call_start_on_xnet_canister(&self.local_env, self.local_canister_id, payload.clone())?;
+        call_start_on_xnet_canister(&self.remote_env, self.remote_canister_id, payload)
+    }
+
     /// Calls the 'stop' method on the local canister.
     pub fn stop_local_canister(&self) -> Result<(), UserError> {
         call_stop_on_xnet_canister(&self.local_env, self.local_canister_id)
     }
 
+    /// Calls the 'stop' method on the remote canister.
+    pub fn stop_remote_canister(&self) -> Result<(), UserError> {
+        call_stop_on_xnet_canister(&self.remote_env, self.remote_canister_id)
+    }
+
     /// Queries the local canister.
     pub fn query_local_canister(
         &self,
@@ -179,6 +229,13 @@ impl SingleCanisterSubnetPair {
         Ok(())
     }
 
+    /// Inducts the messages in the streams between the two subnets that were not
+    /// inducted yet, in both directions; thereby executing a round on each subnet.
+    fn induct_pending_messages_both_ways(&self) {
+        induct_pending_messages(&self.local_env, &self.remote_env);
+        induct_pending_messages(&self.remote_env, &self.local_env);
+    }
+
     /// Generates a snapshot of the output queue on the local canister and
     /// returns it as a vector of messages; or 'None' if no output queue exists.
     fn local_output_queue_snapshot(&self) -> Option<Vec<Option<RequestOrResponse>>> {
@@ -210,6 +267,48 @@ fn get_output_queue_iter(
         })
 }
 
+/// Inducts the messages in the stream from `from_env` to `into_env` that `into_env`
+/// has not inducted yet (i.e. starting at its signals end); or just executes a round
+/// on `into_env` if there is no such stream yet.
+fn induct_pending_messages(from_env: &StateMachine, into_env: &StateMachine) {
+    let signals_end = into_env
+        .get_latest_state()
+        .get_stream(&from_env.get_subnet_id())
+        .map(|stream| stream.signals_end())
+        .unwrap_or_else(|| StreamIndex::from(0));
+    match from_env.generate_xnet_payload(
+        into_env.get_subnet_id(),
+        Some(signals_end), // witness_begin
+        Some(signals_end), // msg_begin
+        None,              // msg_limit
+        None,              // byte_limit
+    ) {
+        Ok(xnet_payload) => into_env.execute_block_with_xnet_payload(xnet_payload),
+        Err(EncodeStreamError::NoStreamForSubnet(_)) => into_env.tick(),
+        Err(err) => panic!("Failed to generate XNet payload: {:?}", err),
+    }
+}
+
+/// Queries the metrics of an XNet canister.
+fn query_metrics(env: &StateMachine, canister_id: CanisterId) -> Metrics {
+    let reply = env.query(canister_id, "metrics", Vec::new()).unwrap();
+    Decode!(&reply.bytes(), Metrics).unwrap()
+}
+
+/// Returns the cycles balance of a canister and the cycles it consumed (i.e. burned)
+/// since the replica started.
+fn cycles_balance_and_consumption(env: &StateMachine, canister_id: CanisterId) -> (u128, u128) {
+    let state = env.get_latest_state();
+    let system_state = &state.canister_state(&canister_id).unwrap().system_state;
+    (
+        system_state.balance().get(),
+        system_state
+            .canister_metrics
+            .consumed_cycles_since_replica_started
+            .get(),
+    )
+}
+
 /// Calls the 'start' method on a canister in the state machine (assumed to be an XNet canister).
 fn call_start_on_xnet_canister(
     env: &StateMachine,
@@ -463,3 +562,108 @@ fn test_reservations_do_not_inhibit_xnet_induction_of_requests() {
 
     assert_eq!(metrics.requests_sent, *requests_inducted.unwrap() as usize);
 }
+
+/// Test the cycles attached to XNet calls are accounted for exactly on both subnets.
+/// Two canisters on different subnets send each other requests with cycles attached;
+/// the receiving canister accepts half of them unless it traps or rejects the request
+/// (as injected by the sender), and the rest is refunded with the response.
+/// Once all the responses are in, the cycles sent by either canister must equal the
+/// cycles accepted by the other one plus the cycles refunded to it; and the balance of
+/// either canister must have changed by exactly the cycles it accepted and was refunded,
+/// minus the cycles it sent and the cycles it burned (for execution, messaging, etc.).
+#[test]
+fn test_cycles_attached_to_xnet_calls_are_accounted_for() {
+    let subnets = SingleCanisterSubnetPair::new();
+
+    let canister_to_subnet_rate = 10;
+    let payload_size_bytes = 128;
+    let fault_injection = FaultInjection {
+        trap_percentage: 10,
+        reject_percentage: 10,
+        delay_percentage: 10,
+    };
+    let cycles_per_call = 1_000_000_000;
+    let rounds = 20;
+
+    let (local_balance_before, local_consumed_before) =
+        cycles_balance_and_consumption(&subnets.local_env, subnets.local_canister_id);
+    let (remote_balance_before, remote_consumed_before) =
+        cycles_balance_and_consumption(&subnets.remote_env, subnets.remote_canister_id);
+
+    // Exchange requests in both directions for a number of rounds, then stop sending
+    // requests and keep inducting messages until all requests got a response.
+    subnets
+        .start_canisters_with_cycles(
+            canister_to_subnet_rate,
+            payload_size_bytes,
+            fault_injection,
+            cycles_per_call,
+        )
+        .unwrap();
+    for _ in 0..rounds {
+        subnets.induct_pending_messages_both_ways();
+    }
+    subnets.stop_local_canister().unwrap();
+    subnets.stop_remote_canister().unwrap();
+    do_until_or_panic(MAX_TICKS, || {
+        subnets.induct_pending_messages_both_ways();
+        [
+            query_metrics(&subnets.local_env, subnets.local_canister_id),
+            query_metrics(&subnets.remote_env, subnets.remote_canister_id),
+        ]
+        .iter()
+        .all(|metrics| metrics.requests_sent == metrics.reply_responses + metrics.reject_responses)
+    });
+
+    let local_metrics = query_metrics(&subnets.local_env, subnets.local_canister_id);
+    let remote_metrics = query_metrics(&subnets.remote_env, subnets.remote_canister_id);
+
+    // Both canisters sent cycles, had some of them refunded and accepted some of the others.
+    for metrics in [&local_metrics, &remote_metrics] {
+        assert_eq!(
+            metrics.requests_sent as u64 * cycles_per_call,
+            metrics.cycles_sent
+        );
+        assert!(metrics.cycles_refunded > 0);
+        assert!(metrics.cycles_accepted > 0);
+    }
+
+    // The cycles sent are either accepted by the receiving canister or refunded.
+    assert_eq!(
+        local_metrics.cycles_sent,
+        remote_metrics.cycles_accepted + local_metrics.cycles_refunded
+    );
+    assert_eq!(
+        remote_metrics.cycles_sent,
+        local_metrics.cycles_accepted + remote_metrics.cycles_refunded
+    );
+
+    // The balances changed by the cycles transferred and burned, and by nothing else.
+    for (env, canister_id, metrics, balance_before, consumed_before) in [
+        (
+            &subnets.local_env,
+            subnets.local_canister_id,
+            &local_metrics,
+            local_balance_before,
+            local_consumed_before,
+        ),
+        (
+            &subnets.remote_env,
+            subnets.remote_canister_id,
+            &remote_metrics,
+            remote_balance_before,
+            remote_consumed_before,
+        ),
+    ] {
+        let (balance_after, consumed_after) = cycles_balance_and_consumption(env, canister_id);
+        assert!(consumed_after > consumed_before);
+        // The balances are close to `u128::MAX / 2`, so both sides only add
+        // the (small) cycles that went in or out.
+        assert_eq!(
+            balance_after + metrics.cycles_sent as u128 + (consumed_after - consumed_before),
+            balance_before + metrics.cycles_accepted as u128 + metrics.cycles_refunded as u128,
+            "Unaccounted cycles on canister {}",
+            canister_id
+        );
+    }
+}
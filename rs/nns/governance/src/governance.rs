@@ -8441,6 +8441,8 @@ fn is_information_about_swap_from_different_sources_consistent(
                 icp_ledger_canister_id: _,
                 transaction_fee_e8s: _,
                 neuron_minimum_stake_e8s: _,
+                allow_canister_participants: _,
+                archive_canister_id: _,
             } = swap_init;
 
             (
@@ -58,6 +58,10 @@ const PARAMS: sns_swap_pb::Params = sns_swap_pb::Params {
         },
     ),
     sale_delay_seconds: None,
+    early_participation_bonus: None,
+    max_direct_participants: None,
+    dutch_auction: None,
+    pro_rata_cutback: None,
 };
 
 type CanisterMethodCallResult = Result<Vec<u8>, (Option<i32>, String)>;
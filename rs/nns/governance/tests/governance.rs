@@ -7529,6 +7529,10 @@ fn test_default_followees() {
                     dissolve_delay_interval_seconds: 30 * ONE_DAY_SECONDS,
                 }),
                 sale_delay_seconds: None,
+                early_participation_bonus: None,
+                max_direct_participants: None,
+                dutch_auction: None,
+                pro_rata_cutback: None,
             }),
             community_fund_investment_e8s: Some(0),
         }),
@@ -11282,6 +11286,8 @@ lazy_static! {
         // standard values by code under test.
         transaction_fee_e8s: Some(12_345),
         neuron_minimum_stake_e8s: Some(123_456_789),
+        allow_canister_participants: None,
+        archive_canister_id: None,
     };
 }
 
@@ -11303,6 +11309,10 @@ lazy_static! {
             },
         ),
         sale_delay_seconds: None,
+        early_participation_bonus: None,
+        max_direct_participants: None,
+        dutch_auction: None,
+        pro_rata_cutback: None,
     };
 
     // Collectively, the Community Fund neurons have 100e-8 ICP in maturity.
@@ -154,6 +154,10 @@ fn make_open_sns_token_swap_proposal(
             dissolve_delay_interval_seconds: 7890000, // 3 months
         }),
         sale_delay_seconds: None,
+        early_participation_bonus: None,
+        max_direct_participants: None,
+        dutch_auction: None,
+        pro_rata_cutback: None,
     };
 
     nns_governance_make_proposal(
@@ -532,11 +532,10 @@ pub(crate) fn http_config_to_endpoint(
     http_config: &HttpConfig,
 ) -> OrchestratorResult<String> {
     info!(log, "Reading http config for registration");
-    get_endpoint(
-        log,
-        http_config.listen_addr.ip().to_string(),
-        http_config.listen_addr.port(),
-    )
+    let listen_addr = http_config.tcp_listen_addr().ok_or_else(|| {
+        OrchestratorError::invalid_configuration_error("The http config has no TCP listener")
+    })?;
+    get_endpoint(log, listen_addr.ip().to_string(), listen_addr.port())
 }
 
 pub(crate) fn msg_routing_config_to_endpoint(
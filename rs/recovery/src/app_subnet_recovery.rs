@@ -27,7 +27,7 @@ pub enum StepType {
     DownloadCertifications,
     /// In this step we will merge all found certifications and determine whether it is safe to continue without a manual intervention. In most cases, when a subnet happened due to a replica bug and not due to malicious actors, this step should not reveal any problems.
     MergeCertificationPools,
-    /// In this step we will download the latest persisted subnet state and all finalized consensus artifacts. For that we should use a node, that is up to date with the highest certification and finalization height because this node should contain all we need for the recovery.
+    /// In this step we will download the latest persisted subnet state and all finalized consensus artifacts. For that we should use a node, that is up to date with the highest certification and finalization height because this node should contain all we need for the recovery. If no node of the subnet is reachable, the state and the artifacts can be downloaded from the spool of a backup host instead.
     DownloadState,
     /// In this step we will take the latest persisted subnet state downloaded in the previous step and apply the finalized consensus artifacts on it via the deterministic state machine part of the replica to hopefully obtain the exact state which existed in the memory of all subnet nodes at the moment when a subnet issue has occurred.
     ICReplay,
@@ -72,6 +72,12 @@ pub struct AppSubnetRecoveryArgs {
     #[clap(long)]
     pub download_node: Option<IpAddr>,
 
+    /// Location of the root directory of a backup host, like
+    /// `user@host:/var/lib/ic-backup`, to download the state and the
+    /// artifacts of the subnet from instead of a node
+    #[clap(long)]
+    pub backup_source: Option<String>,
+
     /// If the downloaded state should be backed up locally
     #[clap(long)]
     pub keep_downloaded_state: Option<bool>,
@@ -163,7 +169,7 @@ impl RecoveryIterator<StepType, StepTypeIter> for AppSubnetRecovery {
                 wait_for_confirmation(&self.logger);
             }
 
-            StepType::DownloadState => {
+            StepType::DownloadState if self.params.backup_source.is_none() => {
                 // We could pick a node with highest finalization height automatically,
                 // but we might have a preference between nodes of the same finalization height.
                 print_height_info(
@@ -232,7 +238,7 @@ impl RecoveryIterator<StepType, StepTypeIter> for AppSubnetRecovery {
             }
 
             StepType::DownloadCertifications => {
-                if self.params.pub_key.is_some() {
+                if self.params.pub_key.is_some() && self.params.backup_source.is_none() {
                     Ok(Box::new(
                         self.recovery
                             .get_download_certs_step(self.params.subnet_id, false),
@@ -243,7 +249,7 @@ impl RecoveryIterator<StepType, StepTypeIter> for AppSubnetRecovery {
             }
 
             StepType::MergeCertificationPools => {
-                if self.params.pub_key.is_some() {
+                if self.params.pub_key.is_some() && self.params.backup_source.is_none() {
                     Ok(Box::new(self.recovery.get_merge_certification_pools_step()))
                 } else {
                     Err(RecoveryError::StepSkipped)
@@ -251,7 +257,12 @@ impl RecoveryIterator<StepType, StepTypeIter> for AppSubnetRecovery {
             }
 
             StepType::DownloadState => {
-                if let Some(node_ip) = self.params.download_node {
+                if let Some(backup_source) = &self.params.backup_source {
+                    Ok(Box::new(self.recovery.get_download_backup_step(
+                        self.params.subnet_id,
+                        backup_source.clone(),
+                    )))
+                } else if let Some(node_ip) = self.params.download_node {
                     Ok(Box::new(self.recovery.get_download_state_step(
                         node_ip,
                         self.params.pub_key.is_some(),
@@ -262,11 +273,20 @@ impl RecoveryIterator<StepType, StepTypeIter> for AppSubnetRecovery {
                 }
             }
 
-            StepType::ICReplay => Ok(Box::new(self.recovery.get_replay_step(
-                self.params.subnet_id,
-                None,
-                None,
-            ))),
+            StepType::ICReplay => {
+                if self.params.backup_source.is_some() {
+                    Ok(Box::new(
+                        self.recovery
+                            .get_replay_from_backup_step(self.params.subnet_id)?,
+                    ))
+                } else {
+                    Ok(Box::new(self.recovery.get_replay_step(
+                        self.params.subnet_id,
+                        None,
+                        None,
+                    )))
+                }
+            }
 
             StepType::ValidateReplayOutput => Ok(Box::new(
                 self.recovery
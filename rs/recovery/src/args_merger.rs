@@ -65,6 +65,8 @@ mod tests {
             replica_version: None,
             key_file: Some(PathBuf::from("/dir1/key_file")),
             test_mode: true,
+            download_parallelism: None,
+            non_interactive: false,
         };
         let args2 = RecoveryArgs {
             dir: PathBuf::from("/dir2/"),
@@ -72,6 +74,8 @@ mod tests {
             replica_version: None,
             key_file: None,
             test_mode: false,
+            download_parallelism: None,
+            non_interactive: false,
         };
 
         let expected = RecoveryArgs {
@@ -80,6 +84,8 @@ mod tests {
             replica_version: args2.replica_version.clone(),
             key_file: args1.key_file.clone(),
             test_mode: args2.test_mode,
+            download_parallelism: None,
+            non_interactive: false,
         };
 
         assert_eq!(expected, merge(&logger, "test", &args1, &args2).unwrap());
@@ -0,0 +1,311 @@
+//! Downloads replica binaries from a list of mirrors. Every binary is
+//! verified against the `SHA256SUMS` file published next to it on the same
+//! mirror; if a mirror is unreachable or serves a corrupted file, the next
+//! mirror is tried.
+use crate::command_helper::exec_cmd;
+use crate::error::RecoveryResult;
+use ic_http_utils::file_downloader::compute_sha256_hex;
+use ic_types::ReplicaVersion;
+use slog::{info, warn, Logger};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+/// The mirrors binaries are downloaded from by default. Binaries of version
+/// `<v>` are expected under `<mirror>/<v>/release/`.
+pub const DEFAULT_BINARY_MIRRORS: &[&str] = &["https://download.dfinity.systems/ic"];
+
+/// The progress of a download, reported after every received chunk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DownloadProgress {
+    pub url: String,
+    pub downloaded_bytes: u64,
+    /// The size of the file, if the mirror reported it.
+    pub total_bytes: Option<u64>,
+}
+
+/// Why downloading a binary from a single mirror failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MirrorError {
+    /// The checksum of the binary could not be fetched from the mirror.
+    Checksum(String),
+    /// The binary could not be downloaded.
+    Download(String),
+    /// The downloaded binary does not match the published checksum.
+    ChecksumMismatch { expected: String, computed: String },
+}
+
+impl fmt::Display for MirrorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MirrorError::Checksum(msg) => write!(f, "failed to get the checksum: {}", msg),
+            MirrorError::Download(msg) => write!(f, "failed to download: {}", msg),
+            MirrorError::ChecksumMismatch { expected, computed } => write!(
+                f,
+                "checksum mismatch, expected {}, computed {}",
+                expected, computed
+            ),
+        }
+    }
+}
+
+/// Downloading a binary failed on every mirror.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BinaryDownloadError {
+    pub binary_name: String,
+    pub replica_version: ReplicaVersion,
+    /// The mirrors that were tried, in order, with the reason they failed.
+    pub attempts: Vec<(String, MirrorError)>,
+}
+
+impl fmt::Display for BinaryDownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Failed to download {} of version {}",
+            self.binary_name, self.replica_version
+        )?;
+        if self.attempts.is_empty() {
+            return write!(f, ": no mirrors configured");
+        }
+        for (mirror, err) in &self.attempts {
+            write!(f, "; {}: {}", mirror, err)?;
+        }
+        Ok(())
+    }
+}
+
+type ProgressCallback = Arc<dyn Fn(&DownloadProgress) + Send + Sync>;
+
+/// Downloads, verifies and unpacks replica binaries.
+#[derive(Clone)]
+pub struct BinaryDownloader {
+    logger: Logger,
+    mirrors: Vec<String>,
+    progress: Option<ProgressCallback>,
+}
+
+impl BinaryDownloader {
+    /// Creates a downloader using the [DEFAULT_BINARY_MIRRORS].
+    pub fn new(logger: Logger) -> Self {
+        Self {
+            logger,
+            mirrors: DEFAULT_BINARY_MIRRORS
+                .iter()
+                .map(|mirror| mirror.to_string())
+                .collect(),
+            progress: None,
+        }
+    }
+
+    /// Replaces the mirrors, which are tried in the given order.
+    pub fn with_mirrors(mut self, mirrors: Vec<String>) -> Self {
+        self.mirrors = mirrors;
+        self
+    }
+
+    /// Calls `progress` after every chunk received from a mirror.
+    pub fn with_progress(
+        mut self,
+        progress: impl Fn(&DownloadProgress) + Send + Sync + 'static,
+    ) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    /// Downloads the binary of the given name and replica version to the
+    /// target directory, verifies its checksum, unzips it, and adds
+    /// executable permissions. Returns a [PathBuf] to the downloaded binary.
+    pub async fn download(
+        &self,
+        replica_version: &ReplicaVersion,
+        binary_name: &str,
+        target_dir: &Path,
+    ) -> RecoveryResult<PathBuf> {
+        let file = target_dir.join(format!("{}.gz", binary_name));
+        self.download_from_mirrors(replica_version, binary_name, &file)
+            .await?;
+
+        info!(self.logger, "Unzipping file...");
+        let mut gunzip = Command::new("gunzip");
+        gunzip.arg(&file);
+        if let Some(out) = exec_cmd(&mut gunzip)? {
+            info!(self.logger, "{}", out);
+        }
+
+        let file = target_dir.join(binary_name);
+        info!(self.logger, "Adding permissions...");
+        let mut chmod = Command::new("chmod");
+        chmod.arg("+x").arg(&file);
+        if let Some(out) = exec_cmd(&mut chmod)? {
+            info!(self.logger, "{}", out);
+        }
+
+        Ok(file)
+    }
+
+    /// Downloads `<binary_name>.gz` to `file` from the first mirror that
+    /// serves it with the published checksum.
+    async fn download_from_mirrors(
+        &self,
+        replica_version: &ReplicaVersion,
+        binary_name: &str,
+        file: &Path,
+    ) -> Result<(), BinaryDownloadError> {
+        let archive_name = format!("{}.gz", binary_name);
+        let mut attempts = Vec::new();
+        for mirror in &self.mirrors {
+            let base_url = format!(
+                "{}/{}/release",
+                mirror.trim_end_matches('/'),
+                replica_version
+            );
+            info!(
+                self.logger,
+                "Downloading {} from {}...", archive_name, base_url
+            );
+            match self.download_verified(&base_url, &archive_name, file).await {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    warn!(self.logger, "Mirror {} failed: {}", mirror, err);
+                    attempts.push((mirror.clone(), err));
+                }
+            }
+        }
+        Err(BinaryDownloadError {
+            binary_name: binary_name.to_string(),
+            replica_version: replica_version.clone(),
+            attempts,
+        })
+    }
+
+    async fn download_verified(
+        &self,
+        base_url: &str,
+        archive_name: &str,
+        file: &Path,
+    ) -> Result<(), MirrorError> {
+        let checksums_url = format!("{}/SHA256SUMS", base_url);
+        let checksums = fetch_text(&checksums_url)
+            .await
+            .map_err(MirrorError::Checksum)?;
+        let expected = find_checksum(&checksums, archive_name).ok_or_else(|| {
+            MirrorError::Checksum(format!(
+                "{} is not listed in {}",
+                archive_name, checksums_url
+            ))
+        })?;
+
+        let url = format!("{}/{}", base_url, archive_name);
+        self.download_file(&url, file)
+            .await
+            .map_err(MirrorError::Download)?;
+        let computed =
+            compute_sha256_hex(file).map_err(|e| MirrorError::Download(e.to_string()))?;
+        if computed != expected {
+            // Don't leave a corrupted file behind for the next mirror or run.
+            let _ = fs::remove_file(file);
+            return Err(MirrorError::ChecksumMismatch { expected, computed });
+        }
+        Ok(())
+    }
+
+    async fn download_file(&self, url: &str, file: &Path) -> Result<(), String> {
+        let mut response = reqwest::get(url)
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| e.to_string())?;
+        let mut progress = DownloadProgress {
+            url: url.to_string(),
+            downloaded_bytes: 0,
+            total_bytes: response.content_length(),
+        };
+        let mut output =
+            File::create(file).map_err(|e| format!("Failed to create {:?}: {}", file, e))?;
+        while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+            output
+                .write_all(&chunk)
+                .map_err(|e| format!("Failed to write {:?}: {}", file, e))?;
+            progress.downloaded_bytes += chunk.len() as u64;
+            if let Some(callback) = &self.progress {
+                callback(&progress);
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn fetch_text(url: &str) -> Result<String, String> {
+    reqwest::get(url)
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| e.to_string())?
+        .text()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Returns the checksum of `file_name` in the content of a `SHA256SUMS` file,
+/// whose lines are formatted as `<sha256> *<file_name>` or
+/// `<sha256>  <file_name>`.
+fn find_checksum(checksums: &str, file_name: &str) -> Option<String> {
+    checksums.lines().find_map(|line| {
+        let mut parts = line.split_whitespace();
+        let checksum = parts.next()?;
+        let name = parts.next()?;
+        (name.trim_start_matches('*') == file_name).then(|| checksum.to_lowercase())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::o;
+
+    #[test]
+    fn finds_checksums_in_both_formats() {
+        let checksums = "\
+            0a1b  ic-admin.gz\n\
+            2C3D *ic-replay.gz\n\
+            4e5f *canister_sandbox.gz\n";
+        assert_eq!(
+            find_checksum(checksums, "ic-admin.gz"),
+            Some("0a1b".to_string())
+        );
+        assert_eq!(
+            find_checksum(checksums, "ic-replay.gz"),
+            Some("2c3d".to_string())
+        );
+        assert_eq!(find_checksum(checksums, "ic-replay"), None);
+    }
+
+    #[tokio::test]
+    async fn reports_every_mirror_that_was_tried() {
+        let tmp = tempfile::tempdir().unwrap();
+        let downloader =
+            BinaryDownloader::new(Logger::root(slog::Discard, o!())).with_mirrors(vec![
+                "http://127.0.0.1:1/ic".to_string(),
+                "not a url".to_string(),
+            ]);
+        let version = ReplicaVersion::try_from("0.1.0").unwrap();
+        let err = downloader
+            .download_from_mirrors(&version, "ic-replay", &tmp.path().join("ic-replay.gz"))
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.binary_name, "ic-replay");
+        let mirrors: Vec<_> = err
+            .attempts
+            .iter()
+            .map(|(mirror, _)| mirror.as_str())
+            .collect();
+        assert_eq!(mirrors, vec!["http://127.0.0.1:1/ic", "not a url"]);
+        assert!(err
+            .attempts
+            .iter()
+            .all(|(_, err)| matches!(err, MirrorError::Checksum(_))));
+    }
+}
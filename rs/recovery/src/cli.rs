@@ -1,10 +1,12 @@
 //! Calls the recovery library.
 use crate::app_subnet_recovery::{AppSubnetRecovery, AppSubnetRecoveryArgs};
+use crate::cmd::SubCommand;
 use crate::get_node_heights_from_metrics;
 use crate::nns_recovery_failover_nodes::{NNSRecoveryFailoverNodes, NNSRecoveryFailoverNodesArgs};
 use crate::nns_recovery_same_nodes::{NNSRecoverySameNodes, NNSRecoverySameNodesArgs};
 use crate::recovery_iterator::RecoveryIterator;
 use crate::recovery_state::HasRecoveryState;
+use crate::runbook::{ExpectedOutcome, Runbook, RunbookSummary, StepReport};
 use crate::steps::Step;
 use crate::util;
 use crate::util::subnet_id_from_str;
@@ -13,6 +15,7 @@ use core::fmt::Debug;
 use ic_registry_client::client::RegistryClientImpl;
 use ic_types::{NodeId, ReplicaVersion, SubnetId};
 use slog::{info, warn, Logger};
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::io::{stdin, stdout, Write};
 use std::net::IpAddr;
@@ -45,13 +48,16 @@ pub fn app_subnet_recovery(
     args: RecoveryArgs,
     subnet_recovery_args: AppSubnetRecoveryArgs,
     mut neuron_args: Option<NeuronArgs>,
+    dry_run: bool,
 ) {
     print_step(&logger, "App Subnet Recovery");
     info!(logger, "\n{}\n", SUMMARY);
     print_summary(&logger, &args, subnet_recovery_args.subnet_id);
-    wait_for_confirmation(&logger);
+    if !dry_run {
+        wait_for_confirmation(&logger);
+    }
 
-    if neuron_args.is_none() && !args.test_mode {
+    if neuron_args.is_none() && !args.test_mode && !dry_run {
         neuron_args = Some(read_neuron_args(&logger));
     }
 
@@ -60,10 +66,14 @@ pub fn app_subnet_recovery(
         args,
         neuron_args,
         subnet_recovery_args,
-        /*interactive=*/ true,
+        /*interactive=*/ !dry_run,
     );
 
-    execute_steps(&logger, subnet_recovery);
+    if dry_run {
+        print_plan(&logger, subnet_recovery);
+    } else {
+        execute_steps(&logger, subnet_recovery);
+    }
 }
 
 /// NNS is recovered on same nodes by:
@@ -81,19 +91,26 @@ pub fn nns_recovery_same_nodes(
     logger: Logger,
     args: RecoveryArgs,
     nns_recovery_args: NNSRecoverySameNodesArgs,
+    dry_run: bool,
 ) {
     print_step(&logger, "NNS Recovery Same Nodes");
     print_summary(&logger, &args, nns_recovery_args.subnet_id);
-    wait_for_confirmation(&logger);
+    if !dry_run {
+        wait_for_confirmation(&logger);
+    }
 
     let nns_recovery = NNSRecoverySameNodes::new(
         logger.clone(),
         args,
         nns_recovery_args,
-        /*interactive=*/ true,
+        /*interactive=*/ !dry_run,
     );
 
-    execute_steps(&logger, nns_recovery);
+    if dry_run {
+        print_plan(&logger, nns_recovery);
+    } else {
+        execute_steps(&logger, nns_recovery);
+    }
 }
 
 /// NNS is recovered on failover nodes by:
@@ -112,12 +129,15 @@ pub fn nns_recovery_failover_nodes(
     args: RecoveryArgs,
     nns_recovery_args: NNSRecoveryFailoverNodesArgs,
     mut neuron_args: Option<NeuronArgs>,
+    dry_run: bool,
 ) {
     print_step(&logger, "NNS Recovery Failover Nodes");
     print_summary(&logger, &args, nns_recovery_args.subnet_id);
-    wait_for_confirmation(&logger);
+    if !dry_run {
+        wait_for_confirmation(&logger);
+    }
 
-    if neuron_args.is_none() && !args.test_mode {
+    if neuron_args.is_none() && !args.test_mode && !dry_run {
         neuron_args = Some(read_neuron_args(&logger));
     }
 
@@ -126,10 +146,14 @@ pub fn nns_recovery_failover_nodes(
         args,
         neuron_args,
         nns_recovery_args,
-        /*interactive=*/ true,
+        /*interactive=*/ !dry_run,
     );
 
-    execute_steps(&logger, nns_recovery);
+    if dry_run {
+        print_plan(&logger, nns_recovery);
+    } else {
+        execute_steps(&logger, nns_recovery);
+    }
 }
 
 fn execute_steps<
@@ -154,6 +178,142 @@ fn execute_steps<
     }
 }
 
+/// Runs the recovery declared by the runbook without asking for any input,
+/// and prints a summary of all steps as JSON to stdout. Destructive steps are
+/// only executed if `confirmed` is set. Returns whether all steps met the
+/// expectations of the runbook.
+pub fn run_runbook(logger: Logger, mut runbook: Runbook, confirmed: bool) -> bool {
+    print_step(&logger, "Runbook");
+    // Runbooks run unattended, so the commands executed by the steps are not
+    // confirmed one by one.
+    runbook.state.recovery_args.non_interactive = true;
+    let recovery_args = runbook.state.recovery_args;
+    let neuron_args = runbook.state.neuron_args;
+    let expect = runbook.expect;
+
+    let summary = match runbook.state.subcommand_args {
+        SubCommand::AppSubnetRecovery(subnet_recovery_args) => {
+            print_summary(&logger, &recovery_args, subnet_recovery_args.subnet_id);
+            let subnet_recovery = AppSubnetRecovery::new(
+                logger.clone(),
+                recovery_args,
+                neuron_args,
+                subnet_recovery_args,
+                /*interactive=*/ false,
+            );
+            execute_runbook_steps(&logger, subnet_recovery, expect, confirmed)
+        }
+        SubCommand::NNSRecoverySameNodes(nns_recovery_args) => {
+            print_summary(&logger, &recovery_args, nns_recovery_args.subnet_id);
+            let nns_recovery = NNSRecoverySameNodes::new(
+                logger.clone(),
+                recovery_args,
+                nns_recovery_args,
+                /*interactive=*/ false,
+            );
+            execute_runbook_steps(&logger, nns_recovery, expect, confirmed)
+        }
+        SubCommand::NNSRecoveryFailoverNodes(nns_recovery_args) => {
+            print_summary(&logger, &recovery_args, nns_recovery_args.subnet_id);
+            let nns_recovery = NNSRecoveryFailoverNodes::new(
+                logger.clone(),
+                recovery_args,
+                neuron_args,
+                nns_recovery_args,
+                /*interactive=*/ false,
+            );
+            execute_runbook_steps(&logger, nns_recovery, expect, confirmed)
+        }
+    };
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&summary).expect("Failed to stringify the runbook summary")
+    );
+    summary.success
+}
+
+/// Runs the remaining steps until one of them doesn't meet the expectation
+/// of the runbook, saving the recovery state after each step.
+fn execute_runbook_steps<
+    StepType: Copy + Debug + PartialEq + EnumMessage,
+    I: Iterator<Item = StepType>,
+    Steps: HasRecoveryState<StepType = StepType> + RecoveryIterator<StepType, I>,
+>(
+    logger: &Logger,
+    mut steps: Steps,
+    mut expect: BTreeMap<String, ExpectedOutcome>,
+    confirmed: bool,
+) -> RunbookSummary {
+    if let Some(next_step) = steps.get_next_step() {
+        steps.resume(next_step);
+    }
+
+    let mut summary = RunbookSummary {
+        success: true,
+        ..Default::default()
+    };
+    while let Some((step_type, outcome)) = steps.run_next_step(confirmed) {
+        if let Err(e) = steps.get_state().save() {
+            warn!(logger, "Failed to save the recovery state: {}", e);
+        }
+        let step = format!("{:?}", step_type);
+        let expected = expect.remove(&step);
+        let met = outcome.meets(expected);
+        if !met {
+            warn!(
+                logger,
+                "Step {} doesn't meet the expectation {:?}: {:?}", step, expected, outcome
+            );
+        }
+        summary.steps.push(StepReport {
+            step,
+            expected,
+            outcome,
+        });
+        if !met {
+            summary.success = false;
+            break;
+        }
+    }
+
+    summary.unmet_expectations = expect.into_keys().collect();
+    if !summary.unmet_expectations.is_empty() {
+        warn!(
+            logger,
+            "Steps expected by the runbook were never reached: {:?}", summary.unmet_expectations
+        );
+        summary.success = false;
+    }
+    summary
+}
+
+/// Prints the plan of the remaining steps as JSON to stdout, without
+/// executing any of them.
+fn print_plan<
+    StepType: Copy + Debug + PartialEq + EnumMessage,
+    I: Iterator<Item = StepType>,
+    Steps: HasRecoveryState<StepType = StepType> + RecoveryIterator<StepType, I>,
+>(
+    logger: &Logger,
+    mut steps: Steps,
+) {
+    if let Some(next_step) = steps.get_next_step() {
+        steps.resume(next_step);
+    }
+
+    let plan = steps.plan();
+    info!(
+        logger,
+        "Planned {} steps, nothing was executed.",
+        plan.steps.len()
+    );
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&plan).expect("Failed to stringify the recovery plan")
+    );
+}
+
 pub fn execute_step_after_consent(logger: &Logger, step: Box<dyn Step>) {
     info!(logger, "{}", step.descr());
     if consent_given(logger, "Execute now?") {
@@ -50,6 +50,23 @@ pub struct RecoveryToolArgs {
     #[clap(long)]
     pub test: bool,
 
+    /// The number of parallel transfers when downloading a node's state
+    #[clap(long)]
+    pub download_parallelism: Option<usize>,
+
+    /// Print the plan of the recovery as JSON instead of executing it
+    #[clap(long)]
+    pub dry_run: bool,
+
+    /// Run the recovery declared by the given YAML runbook, without asking
+    /// for any input, and print a summary of all steps as JSON
+    #[clap(long, parse(from_os_str))]
+    pub runbook: Option<PathBuf>,
+
+    /// Execute destructive steps of a runbook, like proposals and uploads
+    #[clap(long)]
+    pub yes: bool,
+
     #[clap(subcommand)]
     pub subcmd: Option<SubCommand>,
 }
@@ -1,8 +1,15 @@
 //! Various helper methods enabling execution and piping of system commands.
 use crate::error::{RecoveryError, RecoveryResult};
 use std::convert::TryInto;
+use std::io::{BufRead, BufReader, Read, Write};
 use std::process::Command;
-use std::process::Stdio;
+use std::process::{ExitStatus, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a command whose output pipes are closed is polled for its exit.
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
 
 /// Execute ALL given commands in a blocking manner by creating pipes between
 /// them. Execution will fail if ANY [Command] fails. Optionally return the
@@ -98,3 +105,241 @@ pub fn exec_cmd(command: &mut Command) -> RecoveryResult<Option<String>> {
 
     Ok(Some(stdout).filter(|s| !s.is_empty()))
 }
+
+/// The output stream a command printed a line to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// The result of a [Command] executed by [exec_cmd_with_timeout].
+#[derive(Clone, Debug)]
+pub struct CommandOutput {
+    pub status: ExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+    pub duration: Duration,
+}
+
+/// Execute the given system [Command] in a blocking manner, killing it if it
+/// does not finish within `timeout`. Every line the command prints is passed
+/// to `on_line` as soon as it is read, and captured in the returned
+/// [CommandOutput]. Execution fails if the command exits unsuccessfully, in
+/// which case the error contains both stdout and stderr.
+pub fn exec_cmd_with_timeout(
+    command: &mut Command,
+    timeout: Duration,
+    on_line: impl FnMut(OutputStream, &str),
+) -> RecoveryResult<CommandOutput> {
+    exec_cmd_with_optional_input(command, None, timeout, on_line)
+}
+
+/// Like [exec_cmd_with_timeout], but writes `input` to the stdin of the
+/// command, e.g. to answer its prompts when there is no terminal.
+pub fn exec_cmd_with_input_and_timeout(
+    command: &mut Command,
+    input: &[u8],
+    timeout: Duration,
+    on_line: impl FnMut(OutputStream, &str),
+) -> RecoveryResult<CommandOutput> {
+    exec_cmd_with_optional_input(command, Some(input), timeout, on_line)
+}
+
+fn exec_cmd_with_optional_input(
+    command: &mut Command,
+    input: Option<&[u8]>,
+    timeout: Duration,
+    mut on_line: impl FnMut(OutputStream, &str),
+) -> RecoveryResult<CommandOutput> {
+    let start = Instant::now();
+    if input.is_some() {
+        command.stdin(Stdio::piped());
+    }
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            RecoveryError::cmd_error(command, None, format!("Could not spawn: {:?}", e))
+        })?;
+
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        // Written on a separate thread, as the command may not read all of
+        // it, and stdin is closed once written.
+        let input = input.to_vec();
+        thread::spawn(move || {
+            let _ = stdin.write_all(&input);
+        });
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    spawn_line_reader(child.stdout.take(), OutputStream::Stdout, sender.clone());
+    spawn_line_reader(child.stderr.take(), OutputStream::Stderr, sender);
+
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+    let timed_out = loop {
+        match receiver.recv_timeout(timeout.saturating_sub(start.elapsed())) {
+            Ok((stream, line)) => {
+                on_line(stream, &line);
+                let buffer = match stream {
+                    OutputStream::Stdout => &mut stdout,
+                    OutputStream::Stderr => &mut stderr,
+                };
+                buffer.push_str(&line);
+                buffer.push('\n');
+            }
+            // Both pipes are closed, the command is about to exit.
+            Err(RecvTimeoutError::Disconnected) => break false,
+            Err(RecvTimeoutError::Timeout) => break true,
+        }
+    };
+
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) if !timed_out => break status,
+            Ok(None) if !timed_out && start.elapsed() < timeout => {
+                thread::sleep(EXIT_POLL_INTERVAL)
+            }
+            Ok(_) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(RecoveryError::cmd_error(
+                    command,
+                    None,
+                    format!("Timed out after {:?}\n{}{}", timeout, stdout, stderr),
+                ));
+            }
+            Err(e) => {
+                return Err(RecoveryError::cmd_error(
+                    command,
+                    None,
+                    format!("Failed to execute command: {:?}", e),
+                ))
+            }
+        }
+    };
+
+    if !status.success() {
+        return Err(RecoveryError::cmd_error(
+            command,
+            status.code(),
+            format!("{}\n{}", stdout, stderr),
+        ));
+    }
+
+    Ok(CommandOutput {
+        status,
+        stdout,
+        stderr,
+        duration: start.elapsed(),
+    })
+}
+
+/// Forward the lines read from `pipe` to `sender` on a separate thread, so
+/// that neither of the pipes of a command can fill up and block it.
+fn spawn_line_reader(
+    pipe: Option<impl Read + Send + 'static>,
+    stream: OutputStream,
+    sender: Sender<(OutputStream, String)>,
+) {
+    let pipe = match pipe {
+        Some(pipe) => pipe,
+        None => return,
+    };
+    thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        let mut line = Vec::new();
+        while matches!(reader.read_until(b'\n', &mut line), Ok(n) if n > 0) {
+            let text = String::from_utf8_lossy(&line);
+            let text = text.trim_end_matches(&['\n', '\r'][..]).to_string();
+            if sender.send((stream, text)).is_err() {
+                return;
+            }
+            line.clear();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::RecoveryError;
+
+    fn sh(script: &str) -> Command {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(script);
+        cmd
+    }
+
+    #[test]
+    fn captures_and_streams_stdout_and_stderr() {
+        let mut lines = Vec::new();
+        let output = exec_cmd_with_timeout(
+            &mut sh("echo out; echo err >&2; echo done"),
+            Duration::from_secs(10),
+            |stream, line| lines.push((stream, line.to_string())),
+        )
+        .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, "out\ndone\n");
+        assert_eq!(output.stderr, "err\n");
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec![
+                (OutputStream::Stdout, "done".to_string()),
+                (OutputStream::Stdout, "out".to_string()),
+                (OutputStream::Stderr, "err".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn fails_with_exit_code_and_stderr() {
+        let err = exec_cmd_with_timeout(
+            &mut sh("echo oops >&2; exit 3"),
+            Duration::from_secs(10),
+            |_, _| {},
+        )
+        .unwrap_err();
+        match err {
+            RecoveryError::CommandError(code, msg) => {
+                assert_eq!(code, Some(3));
+                assert!(msg.contains("oops"), "{}", msg);
+            }
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn writes_the_input_to_stdin() {
+        let output = exec_cmd_with_input_and_timeout(
+            &mut sh("read answer; echo \"answer: $answer\""),
+            b"y\n",
+            Duration::from_secs(10),
+            |_, _| {},
+        )
+        .unwrap();
+        assert_eq!(output.stdout, "answer: y\n");
+    }
+
+    #[test]
+    fn kills_commands_that_time_out() {
+        let start = Instant::now();
+        let err = exec_cmd_with_timeout(
+            &mut sh("exec sleep 30"),
+            Duration::from_millis(200),
+            |_, _| {},
+        )
+        .unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(
+            matches!(&err, RecoveryError::CommandError(None, msg) if msg.contains("Timed out")),
+            "{}",
+            err
+        );
+    }
+}
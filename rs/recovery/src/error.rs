@@ -3,7 +3,7 @@ use std::fmt;
 use std::io;
 use std::path::Path;
 
-use ic_http_utils::file_downloader::FileDownloadError;
+use crate::binary_downloader::BinaryDownloadError;
 use std::process::Command;
 
 pub type RecoveryResult<T> = Result<T, RecoveryError>;
@@ -15,7 +15,7 @@ pub enum RecoveryError {
     IoError(String, io::Error),
     CommandError(Option<i32>, String),
     OutputError(String),
-    DownloadError(String, FileDownloadError),
+    BinaryDownloadError(BinaryDownloadError),
     ParsingError(serde_json::Error),
     SerializationError(serde_json::Error),
     UnexpectedError(String),
@@ -47,12 +47,6 @@ impl RecoveryError {
     pub(crate) fn serialization_error(e: serde_json::Error) -> Self {
         RecoveryError::SerializationError(e)
     }
-    pub(crate) fn download_error(url: String, target: &Path, e: FileDownloadError) -> Self {
-        RecoveryError::DownloadError(
-            format!("Failed to download from {} to {:?}", url, target),
-            e,
-        )
-    }
 }
 
 impl fmt::Display for RecoveryError {
@@ -67,8 +61,8 @@ impl fmt::Display for RecoveryError {
             RecoveryError::OutputError(msg) => {
                 write!(f, "Output error, message: {:?}", msg)
             }
-            RecoveryError::DownloadError(msg, e) => {
-                write!(f, "Download error, message: {:?}, error: {:?}", msg, e)
+            RecoveryError::BinaryDownloadError(e) => {
+                write!(f, "Binary download error: {}", e)
             }
             RecoveryError::UnexpectedError(msg) => {
                 write!(f, "Unexpected error, message: {:?}", msg)
@@ -87,3 +81,9 @@ impl fmt::Display for RecoveryError {
 }
 
 impl Error for RecoveryError {}
+
+impl From<BinaryDownloadError> for RecoveryError {
+    fn from(e: BinaryDownloadError) -> Self {
+        RecoveryError::BinaryDownloadError(e)
+    }
+}
@@ -1,59 +1,38 @@
+use crate::binary_downloader::BinaryDownloader;
 use crate::cli::wait_for_confirmation;
 use crate::command_helper::exec_cmd;
 use crate::error::{RecoveryError, RecoveryResult};
 use crate::ssh_helper;
 use core::time;
-use ic_http_utils::file_downloader::FileDownloader;
+use ic_http_utils::file_downloader::compute_sha256_hex;
 use ic_types::ReplicaVersion;
 use slog::{info, warn, Logger};
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs::{self, File, ReadDir};
 use std::io::Write;
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::thread;
 
+const QUORUM_STAGING_DIR: &str = ".quorum";
+
 /// Given the name and replica version of a binary, download the artifact to the
-/// target directory, unzip it, and add executable permissions.
+/// target directory, verify its checksum, unzip it, and add executable permissions.
 /// Returns a [PathBuf] to the downloaded binary.
+///
+/// Uses the default mirrors; see [BinaryDownloader] for other mirrors and
+/// progress reporting.
 pub async fn download_binary(
     logger: &Logger,
     replica_version: ReplicaVersion,
     binary_name: String,
     target_dir: PathBuf,
 ) -> RecoveryResult<PathBuf> {
-    let binary_url = format!(
-        "https://download.dfinity.systems/ic/{}/release/{}.gz",
-        replica_version, binary_name
-    );
-
-    let mut file = target_dir.join(format!("{}.gz", binary_name));
-
-    info!(logger, "Downloading {} to {:?}...", binary_name, file);
-    let file_downloader = FileDownloader::new(None);
-    file_downloader
-        .download_file(&binary_url, &file, None)
+    BinaryDownloader::new(logger.clone())
+        .download(&replica_version, &binary_name, &target_dir)
         .await
-        .map_err(|e| RecoveryError::download_error(binary_url, &file, e))?;
-
-    info!(logger, "Unzipping file...");
-    let mut gunzip = Command::new("gunzip");
-    gunzip.arg(file);
-
-    if let Some(out) = exec_cmd(&mut gunzip)? {
-        info!(logger, "{}", out);
-    }
-
-    file = target_dir.join(binary_name);
-
-    info!(logger, "Adding permissions...");
-    let mut chmod = Command::new("chmod");
-    chmod.arg("+x").arg(file.clone());
-
-    if let Some(out) = exec_cmd(&mut chmod)? {
-        info!(logger, "{}", out);
-    }
-
-    Ok(file)
 }
 
 pub fn rsync_with_retries(
@@ -125,6 +104,185 @@ pub fn rsync(
     }
 }
 
+/// The checksums of the files fetched from a host, by path relative to the
+/// fetched directory.
+pub type Checksums = BTreeMap<PathBuf, String>;
+
+/// The outcome of fetching the same files from several hosts with
+/// [fetch_with_quorum].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QuorumReport {
+    /// The checksums of the version served by the majority of the hosts.
+    pub checksums: Checksums,
+    /// The hosts that served the majority version.
+    pub agreeing_hosts: Vec<IpAddr>,
+    /// The hosts that served a different version, with its checksums.
+    pub divergent_hosts: Vec<(IpAddr, Checksums)>,
+    /// The hosts the files could not be fetched from.
+    pub failed_hosts: Vec<(IpAddr, String)>,
+}
+
+impl fmt::Display for QuorumReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} hosts agree", self.agreeing_hosts.len())?;
+        for (host, checksums) in &self.divergent_hosts {
+            write!(f, "; {} diverges: {:?}", host, checksums)?;
+        }
+        for (host, err) in &self.failed_hosts {
+            write!(f, "; {} failed: {}", host, err)?;
+        }
+        Ok(())
+    }
+}
+
+/// Fetches the same files from all `hosts` concurrently and moves the version
+/// served by at least `quorum` hosts into `target`. `fetch` is called with a
+/// host and an empty staging directory it should fetch the files into.
+/// Fails if no version reaches the quorum, or if two versions tie.
+pub fn fetch_with_quorum<F>(
+    logger: &Logger,
+    hosts: &[IpAddr],
+    quorum: usize,
+    target: &Path,
+    fetch: F,
+) -> RecoveryResult<QuorumReport>
+where
+    F: Fn(IpAddr, &Path) -> Result<(), String> + Sync,
+{
+    let staging = target.join(QUORUM_STAGING_DIR);
+    remove_dir(&staging)?;
+    let fetched: Vec<(IpAddr, Result<Checksums, String>)> = thread::scope(|scope| {
+        let handles: Vec<_> = hosts
+            .iter()
+            .map(|host| {
+                let host_dir = staging.join(host.to_string());
+                let fetch = &fetch;
+                let handle = scope.spawn(move || {
+                    create_dir(&host_dir).map_err(|e| e.to_string())?;
+                    fetch(*host, &host_dir)?;
+                    checksums(&host_dir).map_err(|e| e.to_string())
+                });
+                (*host, handle)
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|(host, handle)| {
+                let result = handle
+                    .join()
+                    .unwrap_or_else(|_| Err("Fetching panicked".to_string()));
+                (host, result)
+            })
+            .collect()
+    });
+
+    let mut versions: Vec<(Checksums, Vec<IpAddr>)> = Vec::new();
+    let mut failed_hosts = Vec::new();
+    for (host, result) in fetched {
+        match result {
+            Ok(checksums) => match versions.iter_mut().find(|(v, _)| *v == checksums) {
+                Some((_, hosts)) => hosts.push(host),
+                None => versions.push((checksums, vec![host])),
+            },
+            Err(err) => {
+                warn!(logger, "Failed to fetch from {}: {}", host, err);
+                failed_hosts.push((host, err));
+            }
+        }
+    }
+    versions.sort_by_key(|(_, hosts)| std::cmp::Reverse(hosts.len()));
+
+    let mut versions = versions.into_iter();
+    let (checksums, agreeing_hosts) = versions.next().unwrap_or_default();
+    let divergent_hosts = versions
+        .flat_map(|(checksums, hosts)| hosts.into_iter().map(move |h| (h, checksums.clone())))
+        .collect::<Vec<_>>();
+    let report = QuorumReport {
+        checksums,
+        agreeing_hosts,
+        divergent_hosts,
+        failed_hosts,
+    };
+
+    let tied = report
+        .divergent_hosts
+        .iter()
+        .filter(|(_, checksums)| *checksums == report.divergent_hosts[0].1)
+        .count()
+        >= report.agreeing_hosts.len();
+    if report.agreeing_hosts.len() < quorum.max(1) || tied {
+        remove_dir(&staging)?;
+        return Err(RecoveryError::invalid_output_error(format!(
+            "No version reached the quorum of {}: {}",
+            quorum, report
+        )));
+    }
+    if !report.divergent_hosts.is_empty() {
+        warn!(logger, "Hosts served diverging versions: {}", report);
+    }
+
+    let majority_dir = staging.join(report.agreeing_hosts[0].to_string());
+    for entry in read_dir(&majority_dir)? {
+        let entry = entry.map_err(|e| RecoveryError::dir_error(&majority_dir, e))?;
+        let destination = target.join(entry.file_name());
+        if destination.is_dir() {
+            remove_dir(&destination)?;
+        }
+        fs::rename(entry.path(), &destination)
+            .map_err(|e| RecoveryError::file_error(&destination, e))?;
+    }
+    remove_dir(&staging)?;
+    Ok(report)
+}
+
+/// Fetches `remote_path` from all `hosts` with [rsync], see
+/// [fetch_with_quorum].
+pub fn rsync_with_quorum(
+    logger: &Logger,
+    account: &str,
+    hosts: &[IpAddr],
+    remote_path: &str,
+    quorum: usize,
+    target: &Path,
+    key_file: Option<&PathBuf>,
+) -> RecoveryResult<QuorumReport> {
+    fetch_with_quorum(logger, hosts, quorum, target, |host, dir| {
+        rsync(
+            logger,
+            vec![],
+            &format!("{}@[{}]:{}", account, host, remote_path),
+            &dir.display().to_string(),
+            false,
+            key_file,
+        )
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+    })
+}
+
+/// Computes the checksums of all files below `dir`.
+fn checksums(dir: &Path) -> RecoveryResult<Checksums> {
+    let mut checksums = Checksums::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(current) = dirs.pop() {
+        for entry in read_dir(&current)? {
+            let path = entry
+                .map_err(|e| RecoveryError::dir_error(&current, e))?
+                .path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                let sha256 = compute_sha256_hex(&path).map_err(|e| {
+                    RecoveryError::invalid_output_error(format!("{:?}: {}", path, e))
+                })?;
+                let relative = path.strip_prefix(dir).unwrap_or(&path).to_path_buf();
+                checksums.insert(relative, sha256);
+            }
+        }
+    }
+    Ok(checksums)
+}
+
 pub fn write_file(file: &Path, content: String) -> RecoveryResult<()> {
     let mut f = File::create(file).map_err(|e| RecoveryError::file_error(file, e))?;
     write!(f, "{}", content).map_err(|e| RecoveryError::file_error(file, e))?;
@@ -180,4 +338,58 @@ mod tests {
 
         assert!(!path_exists(&non_existing_path).unwrap());
     }
+
+    fn fetch_content(
+        contents: &'static [(&'static str, Option<&'static str>)],
+    ) -> impl Fn(IpAddr, &Path) -> Result<(), String> + Sync {
+        move |host, dir| {
+            let (_, content) = contents
+                .iter()
+                .find(|(ip, _)| ip.parse::<IpAddr>().unwrap() == host)
+                .unwrap();
+            let content = content.ok_or_else(|| "unreachable".to_string())?;
+            fs::write(dir.join("ic.json5"), content).map_err(|e| e.to_string())
+        }
+    }
+
+    #[test]
+    fn fetch_with_quorum_returns_majority_version() {
+        let tmp = tempdir().expect("Couldn't create a temp test directory");
+        let logger = crate::util::make_logger();
+        let contents: &[(&str, Option<&str>)] = &[
+            ("::1", Some("good")),
+            ("::2", Some("bad")),
+            ("::3", Some("good")),
+            ("::4", None),
+        ];
+        let hosts: Vec<IpAddr> = contents.iter().map(|(ip, _)| ip.parse().unwrap()).collect();
+
+        let report =
+            fetch_with_quorum(&logger, &hosts, 2, tmp.path(), fetch_content(contents)).unwrap();
+
+        assert_eq!(report.agreeing_hosts, vec![hosts[0], hosts[2]]);
+        assert_eq!(report.divergent_hosts.len(), 1);
+        assert_eq!(report.divergent_hosts[0].0, hosts[1]);
+        assert_eq!(report.failed_hosts.len(), 1);
+        assert_eq!(report.failed_hosts[0].0, hosts[3]);
+        assert_eq!(
+            fs::read_to_string(tmp.path().join("ic.json5")).unwrap(),
+            "good"
+        );
+        assert!(!tmp.path().join(QUORUM_STAGING_DIR).exists());
+    }
+
+    #[test]
+    fn fetch_with_quorum_fails_without_majority() {
+        let tmp = tempdir().expect("Couldn't create a temp test directory");
+        let logger = crate::util::make_logger();
+        let contents: &[(&str, Option<&str>)] =
+            &[("::1", Some("a")), ("::2", Some("b")), ("::3", None)];
+        let hosts: Vec<IpAddr> = contents.iter().map(|(ip, _)| ip.parse().unwrap()).collect();
+
+        assert!(
+            fetch_with_quorum(&logger, &hosts, 1, tmp.path(), fetch_content(contents)).is_err()
+        );
+        assert!(!tmp.path().join("ic.json5").exists());
+    }
 }
@@ -10,6 +10,7 @@ use command_helper::exec_cmd;
 use error::{RecoveryError, RecoveryResult};
 use file_sync_helper::{create_dir, download_binary, read_dir, write_bytes};
 use futures::future::join_all;
+use ic_backup_spool::SubnetSpool;
 use ic_base_types::{CanisterId, NodeId, PrincipalId};
 use ic_crypto_utils_threshold_sig_der::{parse_threshold_sig_key, public_key_to_der};
 use ic_cup_explorer::get_catchup_content;
@@ -23,7 +24,9 @@ use ic_registry_local_store::LocalStoreImpl;
 use ic_registry_nns_data_provider::registry::RegistryCanister;
 use ic_registry_replicator::RegistryReplicator;
 use ic_registry_subnet_features::EcdsaConfig;
-use ic_replay::cmd::{AddAndBlessReplicaVersionCmd, AddRegistryContentCmd, SubCommand};
+use ic_replay::cmd::{
+    AddAndBlessReplicaVersionCmd, AddRegistryContentCmd, RestoreFromBackupCmd, SubCommand,
+};
 use ic_replay::player::StateParams;
 use ic_types::messages::HttpStatusResponse;
 use ic_types::{Height, ReplicaVersion, SubnetId};
@@ -31,6 +34,7 @@ use prost::Message;
 use serde::{Deserialize, Serialize};
 use slog::{info, warn, Logger};
 use ssh_helper::SshHelper;
+use state_downloader::DEFAULT_DOWNLOAD_PARALLELISM;
 use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 use std::process::Command;
@@ -48,6 +52,7 @@ use crate::file_sync_helper::read_file;
 pub mod admin_helper;
 pub mod app_subnet_recovery;
 pub mod args_merger;
+pub mod binary_downloader;
 pub mod cli;
 pub mod cmd;
 pub mod command_helper;
@@ -55,10 +60,13 @@ pub mod error;
 pub mod file_sync_helper;
 pub mod nns_recovery_failover_nodes;
 pub mod nns_recovery_same_nodes;
+pub mod plan;
 pub mod recovery_iterator;
 pub mod recovery_state;
 pub mod replay_helper;
+pub mod runbook;
 pub(crate) mod ssh_helper;
+pub mod state_downloader;
 pub mod steps;
 pub mod util;
 
@@ -80,6 +88,7 @@ pub const IC_STATE_EXCLUDES: &[&str] = &[
 pub const IC_STATE: &str = "ic_state";
 pub const NEW_IC_STATE: &str = "new_ic_state";
 pub const IC_REGISTRY_LOCAL_STORE: &str = "ic_registry_local_store";
+pub const BACKUP_SPOOL: &str = "spool";
 pub const CHECKPOINTS: &str = "checkpoints";
 pub const ADMIN: &str = "admin";
 pub const READONLY: &str = "readonly";
@@ -106,6 +115,13 @@ pub struct RecoveryArgs {
     pub replica_version: Option<ReplicaVersion>,
     pub key_file: Option<PathBuf>,
     pub test_mode: bool,
+    /// The number of parallel transfers when downloading a node's state.
+    pub download_parallelism: Option<usize>,
+    /// If true, the commands executed by the steps are not confirmed one by
+    /// one, as when running a runbook. Unlike in test mode, proposals are
+    /// still made by the neuron of the operator.
+    #[serde(default)]
+    pub non_interactive: bool,
 }
 
 /// The recovery struct comprises working directories for the recovery of a
@@ -130,6 +146,7 @@ pub struct Recovery {
 
     pub key_file: Option<PathBuf>,
     ssh_confirmation: bool,
+    download_parallelism: usize,
 
     logger: Logger,
 }
@@ -142,7 +159,7 @@ impl Recovery {
         args: RecoveryArgs,
         neuron_args: Option<NeuronArgs>,
     ) -> RecoveryResult<Self> {
-        let ssh_confirmation = !args.test_mode;
+        let ssh_confirmation = !args.test_mode && !args.non_interactive;
         let recovery_dir = args.dir.join(RECOVERY_DIRECTORY_NAME);
         let binary_dir = recovery_dir.join("binaries");
         let data_dir = recovery_dir.join("original_data");
@@ -163,6 +180,9 @@ impl Recovery {
             local_store,
             key_file: args.key_file,
             ssh_confirmation,
+            download_parallelism: args
+                .download_parallelism
+                .unwrap_or(DEFAULT_DOWNLOAD_PARALLELISM),
             logger,
         };
 
@@ -432,6 +452,7 @@ impl Recovery {
             working_dir: self.work_dir.display().to_string(),
             require_confirmation: self.ssh_confirmation,
             key_file: self.key_file.clone(),
+            download_parallelism: self.download_parallelism,
         }
     }
 
@@ -514,6 +535,54 @@ impl Recovery {
         ))
     }
 
+    /// Return a [DownloadBackupStep] downloading the state, the spooled
+    /// artifacts and the replica config of the given subnet from the backup
+    /// at `backup_source`, instead of from a node.
+    pub fn get_download_backup_step(
+        &self,
+        subnet_id: SubnetId,
+        backup_source: String,
+    ) -> impl Step {
+        DownloadBackupStep {
+            logger: self.logger.clone(),
+            subnet_id,
+            backup_source,
+            work_dir: self.work_dir.clone(),
+            require_confirmation: self.ssh_confirmation,
+            key_file: self.key_file.clone(),
+        }
+    }
+
+    /// Return a [ReplayStep] to restore the state downloaded from a backup,
+    /// by replaying the spooled artifacts on top of its latest checkpoint
+    /// with [SubCommand::RestoreFromBackup].
+    pub fn get_replay_from_backup_step(&self, subnet_id: SubnetId) -> RecoveryResult<impl Step> {
+        let spool_path = self.work_dir.join(BACKUP_SPOOL);
+        let (replica_version, start_height) = find_backup_replay_start(
+            &SubnetSpool::new(spool_path.join(subnet_id.to_string())),
+            &self.work_dir.join("data").join(IC_CHECKPOINTS_PATH),
+        )?;
+        Ok(self.get_replay_step(
+            subnet_id,
+            Some(ReplaySubCmd {
+                cmd: SubCommand::RestoreFromBackup(RestoreFromBackupCmd {
+                    registry_local_store_path: self.local_store_path.clone(),
+                    backup_spool_path: spool_path.clone(),
+                    replica_version: replica_version.to_string(),
+                    start_height: start_height.get(),
+                }),
+                descr: format!(
+                    r#" restore-from-backup "{}" "{}" {} {}"#,
+                    self.local_store_path.display(),
+                    spool_path.display(),
+                    replica_version,
+                    start_height
+                ),
+            }),
+            None,
+        ))
+    }
+
     /// Get names of all checkpoints currently on disk
     pub fn get_checkpoint_names(path: &Path) -> RecoveryResult<Vec<String>> {
         let res = read_dir(path)?
@@ -1041,6 +1110,44 @@ pub fn get_node_heights_from_metrics(
     Ok(metrics)
 }
 
+/// Finds where to restore a backup from: the height of the latest checkpoint
+/// in `checkpoints_path`, and the replica version whose spool contains the CUP
+/// at that height and reaches the highest height.
+pub fn find_backup_replay_start(
+    spool: &SubnetSpool,
+    checkpoints_path: &Path,
+) -> RecoveryResult<(ReplicaVersion, Height)> {
+    let height = Recovery::get_checkpoint_names(checkpoints_path)?
+        .iter()
+        .map(|c| util::parse_hex_str(c))
+        .collect::<RecoveryResult<Vec<u64>>>()?
+        .into_iter()
+        .max()
+        .map(Height::from)
+        .ok_or_else(|| {
+            RecoveryError::invalid_output_error("Did not find any checkpoints".to_string())
+        })?;
+    spool
+        .versions()
+        .map_err(|e| RecoveryError::dir_error(spool.path(), e))?
+        .into_iter()
+        .filter(|version_spool| version_spool.contains_cup(height))
+        .filter_map(|version_spool| {
+            let top_height = version_spool.top_height();
+            version_spool
+                .replica_version()
+                .map(|version| (top_height, version))
+        })
+        .max()
+        .map(|(_, version)| (version, height))
+        .ok_or_else(|| {
+            RecoveryError::invalid_output_error(format!(
+                "The backup spool doesn't contain the CUP at the checkpoint height {}",
+                height
+            ))
+        })
+}
+
 /// Lookup IP addresses of all members of the given subnet
 pub fn get_member_ips(
     registry_client: Arc<RegistryClientImpl>,
@@ -1083,3 +1190,42 @@ pub fn get_member_ips(
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn add_cup(spool: &SubnetSpool, version: &str, height: u64) {
+        let version_spool = spool.version(&ReplicaVersion::try_from(version).unwrap());
+        let cup_path = version_spool.cup_path(Height::from(height));
+        fs::create_dir_all(cup_path.parent().unwrap()).unwrap();
+        fs::write(cup_path, b"cup").unwrap();
+    }
+
+    #[test]
+    fn finds_backup_replay_start_at_latest_checkpoint() {
+        let tmp = tempdir().expect("Couldn't create a temp test directory");
+        let checkpoints = tmp.path().join(IC_CHECKPOINTS_PATH);
+        for height in [500_u64, 1000] {
+            fs::create_dir_all(checkpoints.join(format!("{:016x}", height))).unwrap();
+        }
+        let spool = SubnetSpool::new(tmp.path().join(BACKUP_SPOOL));
+
+        assert!(find_backup_replay_start(&spool, &checkpoints).is_err());
+
+        add_cup(&spool, "old_version", 500);
+        add_cup(&spool, "old_version", 1000);
+        add_cup(&spool, "new_version", 1000);
+        add_cup(&spool, "new_version", 1500);
+
+        assert_eq!(
+            find_backup_replay_start(&spool, &checkpoints).unwrap(),
+            (
+                ReplicaVersion::try_from("new_version").unwrap(),
+                Height::from(1000)
+            )
+        );
+    }
+}
@@ -4,6 +4,7 @@ use clap::Parser;
 use ic_recovery::args_merger::merge;
 use ic_recovery::cmd::{RecoveryToolArgs, SubCommand};
 use ic_recovery::recovery_state::RecoveryState;
+use ic_recovery::runbook::Runbook;
 use ic_recovery::RecoveryArgs;
 use ic_recovery::{cli, util};
 use slog::{info, warn, Logger};
@@ -12,12 +13,23 @@ fn main() {
     let logger = util::make_logger();
 
     let args = RecoveryToolArgs::parse();
+
+    if let Some(path) = args.runbook {
+        let runbook = Runbook::read(&path).expect("Failed to read the runbook");
+        if !cli::run_runbook(logger, runbook, args.yes) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let mut recovery_args = RecoveryArgs {
         dir: args.dir,
         nns_url: args.nns_url,
         replica_version: args.replica_version,
         key_file: args.key_file,
         test_mode: args.test,
+        download_parallelism: args.download_parallelism,
+        non_interactive: false,
     };
     let mut neuron_args = None;
     let mut subcommand_args = args.subcmd;
@@ -25,7 +37,9 @@ fn main() {
     let state =
         RecoveryState::read(&recovery_args.dir).expect("Failed to read the recovery state file");
 
-    if let Some(state) = state {
+    // A dry run plans the recovery with the given arguments and leaves the
+    // state of a previously started recovery untouched.
+    if let Some(state) = state.filter(|_| !args.dry_run) {
         info!(
             &logger,
             "Recovery state file found with parameters {}",
@@ -51,16 +65,21 @@ fn main() {
             recovery_args,
             subnet_recovery_args,
             neuron_args,
+            args.dry_run,
+        ),
+        SubCommand::NNSRecoverySameNodes(nns_recovery_args) => cli::nns_recovery_same_nodes(
+            logger.clone(),
+            recovery_args,
+            nns_recovery_args,
+            args.dry_run,
         ),
-        SubCommand::NNSRecoverySameNodes(nns_recovery_args) => {
-            cli::nns_recovery_same_nodes(logger.clone(), recovery_args, nns_recovery_args)
-        }
         SubCommand::NNSRecoveryFailoverNodes(nns_recovery_args) => {
             cli::nns_recovery_failover_nodes(
                 logger.clone(),
                 recovery_args,
                 nns_recovery_args,
                 neuron_args,
+                args.dry_run,
             )
         }
     }
@@ -0,0 +1,170 @@
+//! Dry runs of a recovery. A [RecoveryPlan] lists the steps a recovery with
+//! the given parameters would take, including the ic-admin commands, the
+//! nodes data is downloaded from or uploaded to, and the height and state hash
+//! of the recovery CUP, without executing any of them. Operators can review
+//! and share the plan before running the real recovery.
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+/// What a step would do if it was executed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PlannedAction {
+    /// An ic-admin proposal that mutates the registry.
+    RegistryMutation,
+    /// A recovery CUP is proposed or created for the given height and state.
+    CupCreation {
+        recovery_height: u64,
+        state_hash: String,
+    },
+    /// Data is downloaded from a node, or from all nodes of the subnet if
+    /// none is given.
+    Download { node: Option<IpAddr> },
+    /// Data is downloaded from the given backup instead of from the nodes.
+    BackupDownload { source: String },
+    /// Data is uploaded to a node, or to all nodes of the subnet if none is
+    /// given.
+    Upload { node: Option<IpAddr> },
+    /// A command is executed on a node via SSH.
+    NodeCommand { node: IpAddr },
+    /// Finalized blocks are replayed on the downloaded state.
+    Replay,
+    /// The outcome of earlier steps is checked, without changing anything.
+    Verification,
+    /// Files in the recovery directory are changed.
+    Local,
+}
+
+impl PlannedAction {
+    /// Whether the action changes the subnet, its nodes or the registry, as
+    /// opposed to only reading from them or changing local files.
+    pub fn is_destructive(&self) -> bool {
+        match self {
+            PlannedAction::RegistryMutation
+            | PlannedAction::CupCreation { .. }
+            | PlannedAction::Upload { .. }
+            | PlannedAction::NodeCommand { .. } => true,
+            PlannedAction::Download { .. }
+            | PlannedAction::BackupDownload { .. }
+            | PlannedAction::Replay
+            | PlannedAction::Verification
+            | PlannedAction::Local => false,
+        }
+    }
+
+    /// Classifies an ic-admin command: recovery CUP proposals with the
+    /// height and state hash they contain, other commands as registry
+    /// mutations.
+    pub fn from_ic_admin_cmd(ic_admin_cmd: &[String]) -> Self {
+        let option = |name: &str| {
+            ic_admin_cmd
+                .iter()
+                .position(|arg| arg == name)
+                .and_then(|i| ic_admin_cmd.get(i + 1))
+        };
+        if ic_admin_cmd
+            .iter()
+            .any(|arg| arg == "propose-to-update-recovery-cup")
+        {
+            if let (Some(height), Some(state_hash)) = (
+                option("--height").and_then(|height| height.parse().ok()),
+                option("--state-hash"),
+            ) {
+                return PlannedAction::CupCreation {
+                    recovery_height: height,
+                    state_hash: state_hash.clone(),
+                };
+            }
+        }
+        PlannedAction::RegistryMutation
+    }
+}
+
+/// Whether a step would be executed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum PlannedStatus {
+    /// The step would be executed as described.
+    Execute {
+        description: String,
+        action: PlannedAction,
+    },
+    /// The step would be skipped with the given parameters.
+    Skip,
+    /// The step cannot be planned before earlier steps were executed, e.g.
+    /// because it depends on the output of the replay.
+    Blocked { reason: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedStep {
+    pub step: String,
+    pub explanation: Option<String>,
+    pub status: PlannedStatus,
+}
+
+/// The remaining steps of a recovery, in the order they would be executed.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecoveryPlan {
+    pub steps: Vec<PlannedStep>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmd(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn classifies_ic_admin_commands() {
+        assert_eq!(
+            PlannedAction::from_ic_admin_cmd(&cmd(&[
+                "ic-admin",
+                "propose-to-update-subnet",
+                "--subnet",
+                "abc",
+                "--is-halted",
+                "true"
+            ])),
+            PlannedAction::RegistryMutation
+        );
+        assert_eq!(
+            PlannedAction::from_ic_admin_cmd(&cmd(&[
+                "ic-admin",
+                "propose-to-update-recovery-cup",
+                "--subnet-index",
+                "abc",
+                "--height",
+                "1500",
+                "--state-hash",
+                "deadbeef"
+            ])),
+            PlannedAction::CupCreation {
+                recovery_height: 1500,
+                state_hash: "deadbeef".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn serializes_tagged_statuses() {
+        let plan = RecoveryPlan {
+            steps: vec![PlannedStep {
+                step: "DownloadState".to_string(),
+                explanation: None,
+                status: PlannedStatus::Execute {
+                    description: "rsync".to_string(),
+                    action: PlannedAction::Download {
+                        node: Some("::1".parse().unwrap()),
+                    },
+                },
+            }],
+        };
+        let json = serde_json::to_value(&plan).unwrap();
+        assert_eq!(json["steps"][0]["status"]["status"], "execute");
+        assert_eq!(json["steps"][0]["status"]["action"]["kind"], "download");
+        assert_eq!(serde_json::from_value::<RecoveryPlan>(json).unwrap(), plan);
+    }
+}
@@ -3,9 +3,14 @@ use std::iter::Peekable;
 
 use crate::{app_subnet_recovery, nns_recovery_failover_nodes, nns_recovery_same_nodes};
 use crate::{
-    app_subnet_recovery::AppSubnetRecovery, error::RecoveryError,
+    app_subnet_recovery::AppSubnetRecovery,
+    error::RecoveryError,
     nns_recovery_failover_nodes::NNSRecoveryFailoverNodes,
-    nns_recovery_same_nodes::NNSRecoverySameNodes, steps::Step, RecoveryResult,
+    nns_recovery_same_nodes::NNSRecoverySameNodes,
+    plan::{PlannedStatus, PlannedStep, RecoveryPlan},
+    runbook::StepOutcome,
+    steps::Step,
+    RecoveryResult,
 };
 use slog::{info, warn, Logger};
 use strum::EnumMessage;
@@ -39,6 +44,68 @@ pub trait RecoveryIterator<
         }
     }
 
+    /// Plans the remaining steps without executing them or asking for
+    /// parameters, consuming the iterator.
+    fn plan(&mut self) -> RecoveryPlan {
+        let mut steps = Vec::new();
+        while let Some(step_type) = self.get_step_iterator().next() {
+            let status = match self.get_step_impl(step_type) {
+                Ok(step) => PlannedStatus::Execute {
+                    description: step.descr(),
+                    action: step.plan(),
+                },
+                Err(RecoveryError::StepSkipped) => PlannedStatus::Skip,
+                Err(e) => PlannedStatus::Blocked {
+                    reason: e.to_string(),
+                },
+            };
+            steps.push(PlannedStep {
+                step: format!("{:?}", step_type),
+                explanation: step_type.get_documentation().map(String::from),
+                status,
+            });
+        }
+        RecoveryPlan { steps }
+    }
+
+    /// Runs the next step without asking for parameters or consent. Steps
+    /// with a destructive action are refused, unless `confirmed` is set.
+    /// The next step is only stored once this one was executed or skipped,
+    /// so that resuming the recovery retries a failed or refused step.
+    fn run_next_step(&mut self, confirmed: bool) -> Option<(StepType, StepOutcome)> {
+        let step_type = self.get_step_iterator().next()?;
+        super::cli::print_step(self.get_logger(), &format!("{:?}", step_type));
+        let outcome = match self.get_step_impl(step_type) {
+            Ok(step) if step.plan().is_destructive() && !confirmed => {
+                warn!(
+                    self.get_logger(),
+                    "Refusing to execute the destructive step {:?} without --yes", step_type
+                );
+                StepOutcome::Refused
+            }
+            Ok(step) => {
+                info!(self.get_logger(), "{}", step.descr());
+                match step.exec() {
+                    Ok(()) => StepOutcome::Executed,
+                    Err(e) => StepOutcome::Failed {
+                        error: e.to_string(),
+                    },
+                }
+            }
+            Err(RecoveryError::StepSkipped) => StepOutcome::Skipped,
+            Err(e) => StepOutcome::Failed {
+                error: e.to_string(),
+            },
+        };
+        if matches!(outcome, StepOutcome::Executed | StepOutcome::Skipped) {
+            let next_step = self.get_step_iterator().peek().copied();
+            self.store_next_step(next_step);
+        } else {
+            self.store_next_step(Some(step_type));
+        }
+        Some((step_type, outcome))
+    }
+
     fn next_step(&mut self) -> Option<(StepType, Box<dyn Step>)> {
         let result = if let Some(current_step) = self.get_step_iterator().next() {
             super::cli::print_step(self.get_logger(), &format!("{:?}", current_step));
@@ -232,6 +299,35 @@ mod tests {
         assert_eq!(Some(FakeStep::P2), fake_recovery_iterator.next_step);
     }
 
+    #[test]
+    fn plan_covers_remaining_steps_without_reading_params() {
+        let mut fake_recovery_iterator = FakeRecoveryIterator::new(/*interactive=*/ true);
+        fake_recovery_iterator.resume(FakeStep::P8);
+
+        let plan = fake_recovery_iterator.plan();
+
+        let steps: Vec<_> = plan.steps.iter().map(|step| step.step.as_str()).collect();
+        assert_eq!(steps, vec!["P8", "P9"]);
+        assert!(plan.steps.iter().all(|step| step.status
+            == PlannedStatus::Execute {
+                description: "Fake Step Description".to_string(),
+                action: crate::plan::PlannedAction::Local,
+            }));
+        assert!(!fake_recovery_iterator.read_step_params_called);
+    }
+
+    #[test]
+    fn run_next_step_executes_and_stores_next_step() {
+        let mut fake_recovery_iterator = FakeRecoveryIterator::new(/*interactive=*/ true);
+
+        assert_eq!(
+            fake_recovery_iterator.run_next_step(/*confirmed=*/ false),
+            Some((FakeStep::P0, StepOutcome::Executed))
+        );
+        assert_eq!(Some(FakeStep::P1), fake_recovery_iterator.next_step);
+        assert!(!fake_recovery_iterator.read_step_params_called);
+    }
+
     #[test]
     fn next_step_reads_params_only_when_interactive() {
         for &interactive in &[false, true] {
@@ -169,6 +169,8 @@ mod tests {
                 replica_version: None,
                 key_file: Some(PathBuf::from(dir)),
                 test_mode: true,
+                download_parallelism: None,
+                non_interactive: false,
             },
             subcommand_args: SubCommand::AppSubnetRecovery(AppSubnetRecoveryArgs {
                 subnet_id: fake_subnet_id(),
@@ -176,6 +178,7 @@ mod tests {
                 replacement_nodes: None,
                 pub_key: Some(String::from("Fake public key")),
                 download_node: None,
+                backup_source: None,
                 keep_downloaded_state: Some(false),
                 upload_node: None,
                 ecdsa_subnet_id: Some(fake_subnet_id()),
@@ -0,0 +1,204 @@
+//! Scripted recoveries. A [Runbook] is a YAML file declaring a full recovery:
+//! the arguments of the tool and of the subcommand, in the format of the
+//! recovery state file, and the expected outcome of its steps. A runbook is
+//! executed without asking the operator for any input, so that recoveries can
+//! be rehearsed and automated in tests. Destructive steps are only executed if
+//! the operator passed `--yes`. At the end, a [RunbookSummary] of all steps is
+//! printed as JSON.
+//!
+//! As the neuron can't be asked for, runbooks of recoveries that make
+//! proposals must declare the `neuron_args`, unless they run in test mode.
+use crate::cmd::SubCommand;
+use crate::error::{RecoveryError, RecoveryResult};
+use crate::file_sync_helper::read_file;
+use crate::recovery_state::RecoveryState;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The outcome a runbook expects from a step.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpectedOutcome {
+    Executed,
+    Skipped,
+}
+
+/// What happened to a step when running a runbook.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum StepOutcome {
+    /// The step was executed successfully.
+    Executed,
+    /// The step was skipped with the given parameters.
+    Skipped,
+    /// The step is destructive and `--yes` was not given.
+    Refused,
+    /// The step could not be generated or its execution failed.
+    Failed { error: String },
+}
+
+impl StepOutcome {
+    /// Whether the outcome meets the expectation. Without an expectation,
+    /// the step must not fail, nor be refused.
+    pub fn meets(&self, expected: Option<ExpectedOutcome>) -> bool {
+        matches!(
+            (self, expected),
+            (
+                StepOutcome::Executed,
+                None | Some(ExpectedOutcome::Executed)
+            ) | (StepOutcome::Skipped, None | Some(ExpectedOutcome::Skipped))
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Runbook {
+    #[serde(flatten)]
+    pub state: RecoveryState,
+    /// The expected outcome of steps, by step name.
+    #[serde(default)]
+    pub expect: BTreeMap<String, ExpectedOutcome>,
+}
+
+impl Runbook {
+    /// Reads the runbook from the YAML file at `path`.
+    pub fn read(path: &Path) -> RecoveryResult<Self> {
+        let content = read_file(path)?;
+        Self::parse(&content)
+    }
+
+    /// The arguments are deserialized from JSON values, like the recovery
+    /// state file, so that the ids and versions have the same format in both.
+    fn parse(content: &str) -> RecoveryResult<Self> {
+        let value: serde_json::Value = serde_yaml::from_str(content).map_err(|e| {
+            RecoveryError::UnexpectedError(format!("Failed to parse the runbook: {}", e))
+        })?;
+        let runbook: Self = serde_json::from_value(value).map_err(RecoveryError::parsing_error)?;
+        runbook.validate()?;
+        Ok(runbook)
+    }
+
+    /// Outside of test mode, proposals without neuron arguments would be
+    /// made by the test neuron, so they are required by the recoveries that
+    /// make proposals.
+    fn validate(&self) -> RecoveryResult<()> {
+        let makes_proposals = !matches!(
+            self.state.subcommand_args,
+            SubCommand::NNSRecoverySameNodes(_)
+        );
+        if makes_proposals
+            && self.state.neuron_args.is_none()
+            && !self.state.recovery_args.test_mode
+        {
+            return Err(RecoveryError::UnexpectedError(
+                "The runbook must declare the neuron_args to make proposals outside of test mode"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StepReport {
+    pub step: String,
+    pub expected: Option<ExpectedOutcome>,
+    #[serde(flatten)]
+    pub outcome: StepOutcome,
+}
+
+/// The machine-readable result of running a runbook. The run stops at the
+/// first step that doesn't meet its expectation.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunbookSummary {
+    pub success: bool,
+    pub steps: Vec<StepReport>,
+    /// Steps with an expected outcome that were never reached.
+    pub unmet_expectations: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUNBOOK: &str = r#"
+recovery_args:
+  dir: /var/lib/ic/data
+  nns_url: https://ic0.app
+  test_mode: true
+subcommand_args:
+  AppSubnetRecovery:
+    subnet_id: gpvux-2ejnk-3hgmh-cegwf-iekfc-b7rzs-hrvep-5euo2-3ywz3-k3hcb-cqe
+    download_node: "::1"
+    keep_downloaded_state: false
+expect:
+  Halt: executed
+  BlessVersion: skipped
+"#;
+
+    #[test]
+    fn parses_runbook() {
+        let runbook = Runbook::parse(RUNBOOK).unwrap();
+
+        assert!(runbook.state.recovery_args.test_mode);
+        assert_eq!(runbook.state.neuron_args, None);
+        match runbook.state.subcommand_args {
+            SubCommand::AppSubnetRecovery(args) => {
+                assert_eq!(
+                    args.subnet_id.to_string(),
+                    "gpvux-2ejnk-3hgmh-cegwf-iekfc-b7rzs-hrvep-5euo2-3ywz3-k3hcb-cqe"
+                );
+                assert_eq!(args.download_node, Some("::1".parse().unwrap()));
+                assert_eq!(args.keep_downloaded_state, Some(false));
+                assert_eq!(args.upload_node, None);
+            }
+            other => panic!("Unexpected subcommand: {:?}", other),
+        }
+        assert_eq!(runbook.expect.get("Halt"), Some(&ExpectedOutcome::Executed));
+        assert_eq!(
+            runbook.expect.get("BlessVersion"),
+            Some(&ExpectedOutcome::Skipped)
+        );
+    }
+
+    #[test]
+    fn requires_neuron_args_outside_of_test_mode() {
+        let without_test_mode = RUNBOOK.replace("test_mode: true", "test_mode: false");
+        assert!(Runbook::parse(&without_test_mode).is_err());
+
+        let with_neuron_args = format!(
+            "{}neuron_args:\n  dfx_hsm_pin: \"1234\"\n  slot: \"0\"\n  neuron_id: \"49\"\n  key_id: \"01\"\n",
+            without_test_mode
+        );
+        let runbook = Runbook::parse(&with_neuron_args).unwrap();
+        assert!(!runbook.state.recovery_args.test_mode);
+        assert!(runbook.state.neuron_args.is_some());
+
+        // Recoveries on the same nodes don't make proposals.
+        let same_nodes = r#"
+recovery_args:
+  dir: /var/lib/ic/data
+  nns_url: https://ic0.app
+  test_mode: false
+subcommand_args:
+  NNSRecoverySameNodes:
+    subnet_id: tdb26-jop6k-aogll-7ltgs-eruif-6kk7m-qpktf-gdiqx-mxtrf-vb5e6-eqe
+"#;
+        assert!(Runbook::parse(same_nodes).is_ok());
+    }
+
+    #[test]
+    fn outcomes_meet_expectations() {
+        assert!(StepOutcome::Executed.meets(None));
+        assert!(StepOutcome::Skipped.meets(None));
+        assert!(StepOutcome::Executed.meets(Some(ExpectedOutcome::Executed)));
+        assert!(!StepOutcome::Executed.meets(Some(ExpectedOutcome::Skipped)));
+        assert!(!StepOutcome::Skipped.meets(Some(ExpectedOutcome::Executed)));
+        assert!(!StepOutcome::Refused.meets(None));
+        assert!(!StepOutcome::Failed {
+            error: "error".to_string()
+        }
+        .meets(Some(ExpectedOutcome::Executed)));
+    }
+}
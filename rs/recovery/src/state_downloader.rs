@@ -0,0 +1,504 @@
+//! Resumable, chunked downloads of a node's state. The files to download are
+//! split into chunks of roughly equal size, which are fetched in parallel with
+//! rsync and verified against checksums computed on the node. Verified files
+//! are recorded in a manifest next to the downloaded state, so that an
+//! interrupted download only fetches the missing chunks when it is restarted.
+use crate::command_helper::exec_cmd;
+use crate::error::{RecoveryError, RecoveryResult};
+use crate::ssh_helper::{self, SshHelper};
+use ic_http_utils::file_downloader::compute_sha256_hex;
+use serde::{Deserialize, Serialize};
+use slog::{info, warn, Logger};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// The number of chunks downloaded at the same time by default.
+pub const DEFAULT_DOWNLOAD_PARALLELISM: usize = 4;
+/// Files are grouped into chunks of at most this size, unless a single file
+/// is larger.
+pub const DEFAULT_CHUNK_SIZE_BYTES: u64 = 4 << 30;
+const CHUNK_RETRIES: usize = 5;
+const MANIFEST_FILE: &str = ".state_download_manifest.json";
+
+/// A file on the node, with its path relative to the downloaded directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RemoteFile {
+    pub path: String,
+    pub size: u64,
+}
+
+/// The progress of a download, reported after every verified chunk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StateDownloadProgress {
+    pub downloaded_bytes: u64,
+    pub total_bytes: u64,
+    pub completed_chunks: usize,
+    pub total_chunks: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct VerifiedFile {
+    size: u64,
+    sha256: String,
+}
+
+/// The files that were downloaded and verified so far, by relative path.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Manifest {
+    verified: BTreeMap<String, VerifiedFile>,
+}
+
+impl Manifest {
+    fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, path: &Path) -> RecoveryResult<()> {
+        let tmp = path.with_extension("tmp");
+        let content = serde_json::to_string(self).map_err(RecoveryError::serialization_error)?;
+        fs::write(&tmp, content).map_err(|e| RecoveryError::file_error(&tmp, e))?;
+        fs::rename(&tmp, path).map_err(|e| RecoveryError::file_error(path, e))
+    }
+
+    /// Returns the files that still need to be downloaded into `target_dir`.
+    fn pending(&self, files: Vec<RemoteFile>, target_dir: &Path) -> Vec<RemoteFile> {
+        files
+            .into_iter()
+            .filter(|file| {
+                let verified = self
+                    .verified
+                    .get(&file.path)
+                    .map_or(false, |verified| verified.size == file.size);
+                let present = fs::metadata(target_dir.join(&file.path))
+                    .map_or(false, |metadata| metadata.len() == file.size);
+                !(verified && present)
+            })
+            .collect()
+    }
+}
+
+type ProgressCallback = Arc<dyn Fn(&StateDownloadProgress) + Send + Sync>;
+
+/// Downloads a directory from a node in verified, resumable chunks.
+pub struct StateDownloader {
+    logger: Logger,
+    ssh_helper: SshHelper,
+    remote_dir: String,
+    target_dir: PathBuf,
+    excludes: Vec<String>,
+    parallelism: usize,
+    chunk_size_bytes: u64,
+    progress: Option<ProgressCallback>,
+}
+
+impl StateDownloader {
+    /// Creates a downloader of `remote_dir` on the node `ssh_helper` connects
+    /// to, into `target_dir`. Files with a path component in `excludes` are
+    /// not downloaded.
+    pub(crate) fn new(
+        logger: Logger,
+        ssh_helper: SshHelper,
+        remote_dir: &str,
+        target_dir: PathBuf,
+        excludes: Vec<String>,
+    ) -> Self {
+        Self {
+            logger,
+            ssh_helper,
+            remote_dir: remote_dir.trim_end_matches('/').to_string(),
+            target_dir,
+            excludes,
+            parallelism: DEFAULT_DOWNLOAD_PARALLELISM,
+            chunk_size_bytes: DEFAULT_CHUNK_SIZE_BYTES,
+            progress: None,
+        }
+    }
+
+    /// Sets the number of chunks downloaded at the same time.
+    pub fn with_parallelism(mut self, parallelism: usize) -> Self {
+        self.parallelism = parallelism.max(1);
+        self
+    }
+
+    pub fn with_chunk_size_bytes(mut self, chunk_size_bytes: u64) -> Self {
+        self.chunk_size_bytes = chunk_size_bytes.max(1);
+        self
+    }
+
+    /// Calls `progress` after every verified chunk.
+    pub fn with_progress(
+        mut self,
+        progress: impl Fn(&StateDownloadProgress) + Send + Sync + 'static,
+    ) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    /// Downloads all files that were not downloaded and verified by a
+    /// previous, interrupted run. Chunks are retried on failure; if a chunk
+    /// still fails, the verified chunks are kept so that the next run can
+    /// resume from them.
+    pub fn download(&self) -> RecoveryResult<()> {
+        fs::create_dir_all(&self.target_dir)
+            .map_err(|e| RecoveryError::dir_error(&self.target_dir, e))?;
+        let manifest_path = self.target_dir.join(MANIFEST_FILE);
+        let manifest = Manifest::load(&manifest_path);
+
+        let files: Vec<_> = self
+            .list_remote_files()?
+            .into_iter()
+            .filter(|file| !is_excluded(&file.path, &self.excludes))
+            .collect();
+        let total_bytes = files.iter().map(|file| file.size).sum();
+        let pending = manifest.pending(files, &self.target_dir);
+        let pending_bytes: u64 = pending.iter().map(|file| file.size).sum();
+        let chunks = partition(pending, self.chunk_size_bytes);
+        info!(
+            self.logger,
+            "Downloading {} bytes in {} chunks, {} of {} bytes were already downloaded.",
+            pending_bytes,
+            chunks.len(),
+            total_bytes - pending_bytes,
+            total_bytes
+        );
+
+        let progress = Mutex::new(StateDownloadProgress {
+            downloaded_bytes: total_bytes - pending_bytes,
+            total_bytes,
+            completed_chunks: 0,
+            total_chunks: chunks.len(),
+        });
+        let queue = Mutex::new(chunks.into_iter().enumerate().collect::<VecDeque<_>>());
+        let manifest = Mutex::new(manifest);
+        let failures = Mutex::new(Vec::new());
+
+        thread::scope(|scope| {
+            for _ in 0..self.parallelism {
+                scope.spawn(|| loop {
+                    let next = queue.lock().unwrap().pop_front();
+                    let (index, chunk) = match next {
+                        Some(next) => next,
+                        None => return,
+                    };
+                    match self.download_chunk_with_retries(index, &chunk) {
+                        Ok(verified) => {
+                            let mut manifest = manifest.lock().unwrap();
+                            manifest.verified.extend(verified);
+                            if let Err(e) = manifest.save(&manifest_path) {
+                                warn!(self.logger, "Failed to save the download manifest: {}", e);
+                            }
+                            drop(manifest);
+
+                            let mut progress = progress.lock().unwrap();
+                            progress.completed_chunks += 1;
+                            progress.downloaded_bytes += chunk.iter().map(|f| f.size).sum::<u64>();
+                            info!(
+                                self.logger,
+                                "Downloaded chunk {}/{} ({}/{} bytes)",
+                                progress.completed_chunks,
+                                progress.total_chunks,
+                                progress.downloaded_bytes,
+                                progress.total_bytes
+                            );
+                            if let Some(callback) = &self.progress {
+                                callback(&progress);
+                            }
+                        }
+                        Err(e) => {
+                            warn!(self.logger, "Failed to download chunk {}: {}", index, e);
+                            failures
+                                .lock()
+                                .unwrap()
+                                .push(format!("chunk {}: {}", index, e));
+                        }
+                    }
+                });
+            }
+        });
+
+        let failures = failures.into_inner().unwrap();
+        if !failures.is_empty() {
+            return Err(RecoveryError::UnexpectedError(format!(
+                "Failed to download {} chunks, rerun to resume the download: {}",
+                failures.len(),
+                failures.join("; ")
+            )));
+        }
+        // Everything was downloaded, don't leave the manifest in the state.
+        fs::remove_file(&manifest_path).or_else(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => Ok(()),
+            _ => Err(RecoveryError::file_error(&manifest_path, e)),
+        })
+    }
+
+    /// Lists the files in the remote directory with their sizes.
+    fn list_remote_files(&self) -> RecoveryResult<Vec<RemoteFile>> {
+        let mut find = self.ssh_helper.get_command(format!(
+            "cd {} && find . -type f -printf '%s %P\\n'",
+            self.remote_dir
+        ));
+        let listing = exec_cmd(&mut find)?.unwrap_or_default();
+        parse_listing(&listing)
+    }
+
+    fn download_chunk_with_retries(
+        &self,
+        index: usize,
+        chunk: &[RemoteFile],
+    ) -> RecoveryResult<BTreeMap<String, VerifiedFile>> {
+        let list_file = self
+            .target_dir
+            .join(format!(".state_download_chunk_{}", index));
+        let list: String = chunk
+            .iter()
+            .map(|file| format!("{}\n", file.path))
+            .collect();
+        fs::write(&list_file, list).map_err(|e| RecoveryError::file_error(&list_file, e))?;
+
+        let mut result = Err(RecoveryError::UnexpectedError("No attempts".into()));
+        for attempt in 1..=CHUNK_RETRIES {
+            result = self
+                .rsync_chunk(&list_file)
+                .and_then(|()| self.verify_chunk(&list_file, chunk));
+            match &result {
+                Ok(_) => break,
+                Err(e) => warn!(
+                    self.logger,
+                    "Attempt {}/{} of chunk {} failed: {}", attempt, CHUNK_RETRIES, index, e
+                ),
+            }
+        }
+        let _ = fs::remove_file(&list_file);
+        result
+    }
+
+    /// Downloads the files listed in `list_file`. Partially transferred files
+    /// are kept, so that a retry continues where the previous attempt failed.
+    fn rsync_chunk(&self, list_file: &Path) -> RecoveryResult<()> {
+        let mut rsync = Command::new("rsync");
+        rsync
+            .arg("-acP")
+            .arg("--no-g")
+            .arg("--partial")
+            .arg("--timeout=600")
+            .arg(format!("--files-from={}", list_file.display()))
+            .arg("-e")
+            .arg(ssh_helper::get_rsync_ssh_arg(
+                self.ssh_helper.key_file.as_ref(),
+            ))
+            .arg(format!(
+                "{}@[{}]:{}/",
+                self.ssh_helper.account, self.ssh_helper.ip, self.remote_dir
+            ))
+            .arg(&self.target_dir);
+        match exec_cmd(&mut rsync) {
+            Ok(_) => Ok(()),
+            // Files vanished on the node, which the verification reports.
+            Err(RecoveryError::CommandError(Some(24), _)) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Compares the checksums of the downloaded files to the ones computed on
+    /// the node.
+    fn verify_chunk(
+        &self,
+        list_file: &Path,
+        chunk: &[RemoteFile],
+    ) -> RecoveryResult<BTreeMap<String, VerifiedFile>> {
+        let mut sha256sum = self.ssh_helper.get_command(format!(
+            "cd {} && xargs -d '\\n' sha256sum --",
+            self.remote_dir
+        ));
+        sha256sum
+            .stdin(File::open(list_file).map_err(|e| RecoveryError::file_error(list_file, e))?);
+        let expected = parse_checksums(&exec_cmd(&mut sha256sum)?.unwrap_or_default());
+
+        let mut verified = BTreeMap::new();
+        for file in chunk {
+            let local = self.target_dir.join(&file.path);
+            let computed = compute_sha256_hex(&local).map_err(|e| {
+                RecoveryError::invalid_output_error(format!("{}: {}", file.path, e))
+            })?;
+            match expected.get(&file.path) {
+                Some(sha256) if *sha256 == computed => {
+                    verified.insert(
+                        file.path.clone(),
+                        VerifiedFile {
+                            size: file.size,
+                            sha256: computed,
+                        },
+                    );
+                }
+                Some(sha256) => {
+                    return Err(RecoveryError::invalid_output_error(format!(
+                        "Checksum mismatch of {}, expected {}, computed {}",
+                        file.path, sha256, computed
+                    )))
+                }
+                None => {
+                    return Err(RecoveryError::invalid_output_error(format!(
+                        "No checksum of {} on the node",
+                        file.path
+                    )))
+                }
+            }
+        }
+        Ok(verified)
+    }
+}
+
+/// Parses lines of `<size> <path>`, as printed by `find -printf '%s %P\n'`.
+fn parse_listing(listing: &str) -> RecoveryResult<Vec<RemoteFile>> {
+    listing
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (size, path) = line.split_once(' ').ok_or_else(|| {
+                RecoveryError::invalid_output_error(format!("Invalid file listing: {}", line))
+            })?;
+            let size = size.parse().map_err(|_| {
+                RecoveryError::invalid_output_error(format!("Invalid file size: {}", line))
+            })?;
+            Ok(RemoteFile {
+                path: path.to_string(),
+                size,
+            })
+        })
+        .collect()
+}
+
+/// Parses the output of `sha256sum` into checksums by path.
+fn parse_checksums(output: &str) -> BTreeMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (sha256, path) = line.split_once(' ')?;
+            Some((
+                path.trim_start_matches([' ', '*']).to_string(),
+                sha256.to_lowercase(),
+            ))
+        })
+        .collect()
+}
+
+/// Whether any component of `path` is excluded, the same way rsync treats
+/// `--exclude` patterns without slashes.
+fn is_excluded(path: &str, excludes: &[String]) -> bool {
+    path.split('/')
+        .any(|component| excludes.iter().any(|exclude| exclude == component))
+}
+
+/// Groups files into chunks of at most `max_bytes`, keeping files that are
+/// larger on their own.
+fn partition(mut files: Vec<RemoteFile>, max_bytes: u64) -> Vec<Vec<RemoteFile>> {
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut chunk_bytes = 0;
+    for file in files {
+        if !chunk.is_empty() && chunk_bytes + file.size > max_bytes {
+            chunks.push(std::mem::take(&mut chunk));
+            chunk_bytes = 0;
+        }
+        chunk_bytes += file.size;
+        chunk.push(file);
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn file(path: &str, size: u64) -> RemoteFile {
+        RemoteFile {
+            path: path.to_string(),
+            size,
+        }
+    }
+
+    #[test]
+    fn parses_listings_and_checksums() {
+        assert_eq!(
+            parse_listing("12 ic_state/checkpoints/0a/file one\n0 empty\n").unwrap(),
+            vec![
+                file("ic_state/checkpoints/0a/file one", 12),
+                file("empty", 0)
+            ]
+        );
+        assert!(parse_listing("twelve file").is_err());
+
+        let checksums = parse_checksums("AB12  ic_state/file one\ncd34 *empty\n");
+        assert_eq!(checksums["ic_state/file one"], "ab12");
+        assert_eq!(checksums["empty"], "cd34");
+    }
+
+    #[test]
+    fn excludes_path_components() {
+        let excludes = vec!["tip".to_string(), "certification".to_string()];
+        assert!(is_excluded("ic_state/tip/file", &excludes));
+        assert!(is_excluded("ic_consensus_pool/certification", &excludes));
+        assert!(!is_excluded("ic_state/tipping/file", &excludes));
+    }
+
+    #[test]
+    fn partitions_files_into_chunks() {
+        let chunks = partition(
+            vec![file("d", 3), file("a", 4), file("c", 20), file("b", 5)],
+            10,
+        );
+        assert_eq!(
+            chunks,
+            vec![
+                vec![file("a", 4), file("b", 5)],
+                vec![file("c", 20)],
+                vec![file("d", 3)],
+            ]
+        );
+    }
+
+    #[test]
+    fn resumes_from_verified_files() {
+        let tmp = tempdir().unwrap();
+        fs::write(tmp.path().join("done"), "12345").unwrap();
+        fs::write(tmp.path().join("truncated"), "12").unwrap();
+        let mut manifest = Manifest::default();
+        for (path, size) in [("done", 5), ("truncated", 5), ("missing", 5)] {
+            manifest.verified.insert(
+                path.to_string(),
+                VerifiedFile {
+                    size,
+                    sha256: String::new(),
+                },
+            );
+        }
+        manifest.save(&tmp.path().join(MANIFEST_FILE)).unwrap();
+
+        let manifest = Manifest::load(&tmp.path().join(MANIFEST_FILE));
+        let pending = manifest.pending(
+            vec![
+                file("done", 5),
+                file("truncated", 5),
+                file("missing", 5),
+                file("new", 1),
+            ],
+            tmp.path(),
+        );
+        assert_eq!(
+            pending,
+            vec![file("truncated", 5), file("missing", 5), file("new", 1)]
+        );
+    }
+}
@@ -1,18 +1,22 @@
 use crate::admin_helper::IcAdmin;
+use crate::cli::wait_for_confirmation;
 use crate::command_helper::exec_cmd;
 use crate::error::{RecoveryError, RecoveryResult};
 use crate::file_sync_helper::{create_dir, read_dir, remove_dir, rsync, rsync_with_retries};
+use crate::plan::PlannedAction;
 use crate::ssh_helper::SshHelper;
+use crate::state_downloader::StateDownloader;
 use crate::util::{block_on, parse_hex_str};
 use crate::{
-    get_member_ips, get_node_heights_from_metrics, replay_helper, ADMIN, CHECKPOINTS,
-    IC_CERTIFICATIONS_PATH, IC_STATE, NEW_IC_STATE, READONLY,
+    find_backup_replay_start, get_member_ips, get_node_heights_from_metrics, replay_helper, ADMIN,
+    BACKUP_SPOOL, CHECKPOINTS, IC_CERTIFICATIONS_PATH, IC_STATE, NEW_IC_STATE, READONLY,
 };
 use crate::{
     Recovery, IC_CHECKPOINTS_PATH, IC_DATA_PATH, IC_JSON5_PATH, IC_REGISTRY_LOCAL_STORE,
     IC_STATE_EXCLUDES,
 };
 use ic_artifact_pool::certification_pool::CertificationPoolImpl;
+use ic_backup_spool::SubnetSpool;
 use ic_base_types::CanisterId;
 use ic_config::artifact_pool::ArtifactPoolConfig;
 use ic_interfaces::certification::CertificationPool;
@@ -39,6 +43,12 @@ use std::{thread, time};
 pub trait Step {
     fn descr(&self) -> String;
     fn exec(&self) -> RecoveryResult<()>;
+
+    /// What executing the step would do, used to plan a recovery without
+    /// executing it.
+    fn plan(&self) -> PlannedAction {
+        PlannedAction::Local
+    }
 }
 
 /// A step containing an ic-admin proposal or query to be executed.
@@ -55,6 +65,10 @@ impl Step for AdminStep {
         self.ic_admin_cmd.join(" ")
     }
 
+    fn plan(&self) -> PlannedAction {
+        PlannedAction::from_ic_admin_cmd(&self.ic_admin_cmd)
+    }
+
     // Execute the ic-admin CLI string as a system command
     fn exec(&self) -> RecoveryResult<()> {
         Recovery::exec_admin_cmd(&self.logger, &self.ic_admin_cmd)
@@ -79,6 +93,10 @@ impl Step for DownloadCertificationsStep {
         )
     }
 
+    fn plan(&self) -> PlannedAction {
+        PlannedAction::Download { node: None }
+    }
+
     fn exec(&self) -> RecoveryResult<()> {
         let user = if self.admin { ADMIN } else { READONLY };
         let cert_path = format!("{IC_DATA_PATH}/{IC_CERTIFICATIONS_PATH}");
@@ -133,6 +151,10 @@ impl Step for MergeCertificationPoolsStep {
         )
     }
 
+    fn plan(&self) -> PlannedAction {
+        PlannedAction::Verification
+    }
+
     fn exec(&self) -> RecoveryResult<()> {
         let pools = read_dir(&self.work_dir.join("certifications"))?
             .flat_map(|r| r.map_err(|e| warn!(self.logger, "Failed to read dir: {:?}", e)))
@@ -265,6 +287,7 @@ pub struct DownloadIcStateStep {
     pub keep_downloaded_state: bool,
     pub require_confirmation: bool,
     pub key_file: Option<PathBuf>,
+    pub download_parallelism: usize,
 }
 
 impl Step for DownloadIcStateStep {
@@ -284,6 +307,12 @@ impl Step for DownloadIcStateStep {
         }
     }
 
+    fn plan(&self) -> PlannedAction {
+        PlannedAction::Download {
+            node: Some(self.node_ip),
+        }
+    }
+
     fn exec(&self) -> RecoveryResult<()> {
         let account = if self.try_readonly {
             READONLY.to_string()
@@ -312,7 +341,6 @@ impl Step for DownloadIcStateStep {
             "Continuing with account: {}", ssh_helper.account
         );
 
-        let data_src = format!("{}@[{}]:{}", ssh_helper.account, self.node_ip, IC_DATA_PATH);
         let config_src = format!(
             "{}@[{}]:{}",
             ssh_helper.account, self.node_ip, IC_JSON5_PATH
@@ -345,14 +373,32 @@ impl Step for DownloadIcStateStep {
             &self.working_dir
         };
 
-        rsync(
-            &self.logger,
-            excludes.clone(),
-            &data_src,
+        let state_downloader = StateDownloader::new(
+            self.logger.clone(),
+            SshHelper::new(
+                self.logger.clone(),
+                ssh_helper.account.clone(),
+                self.node_ip,
+                false,
+                self.key_file.clone(),
+            ),
+            IC_DATA_PATH,
+            PathBuf::from(target).join("data"),
+            excludes.iter().map(|e| e.to_string()).collect(),
+        )
+        .with_parallelism(self.download_parallelism);
+        info!(
+            self.logger,
+            "About to download {} from {} to {} using {} parallel transfers.",
+            IC_DATA_PATH,
+            self.node_ip,
             target,
-            self.require_confirmation,
-            self.key_file.as_ref(),
-        )?;
+            self.download_parallelism
+        );
+        if self.require_confirmation {
+            wait_for_confirmation(&self.logger);
+        }
+        state_downloader.download()?;
 
         rsync(
             &self.logger,
@@ -378,6 +424,94 @@ impl Step for DownloadIcStateStep {
     }
 }
 
+/// Downloads the latest state, the spooled artifacts and the replica config
+/// of a subnet from the root directory of a backup, local or remote.
+pub struct DownloadBackupStep {
+    pub logger: Logger,
+    pub subnet_id: SubnetId,
+    pub backup_source: String,
+    pub work_dir: PathBuf,
+    pub require_confirmation: bool,
+    pub key_file: Option<PathBuf>,
+}
+
+impl Step for DownloadBackupStep {
+    fn descr(&self) -> String {
+        format!(
+            "Copy the state of subnet {} from {}/data/{}/{}, its spooled artifacts from {}/spool/{} \
+            and the config of the replica version to restore from {}/binaries/<version>/ic.json5 to {}.",
+            self.subnet_id,
+            self.backup_source,
+            self.subnet_id,
+            IC_STATE,
+            self.backup_source,
+            self.subnet_id,
+            self.backup_source,
+            self.work_dir.display()
+        )
+    }
+
+    fn plan(&self) -> PlannedAction {
+        PlannedAction::BackupDownload {
+            source: self.backup_source.clone(),
+        }
+    }
+
+    fn exec(&self) -> RecoveryResult<()> {
+        let state_dir = self.work_dir.join("data").join(IC_STATE);
+        let spool_dir = self
+            .work_dir
+            .join(BACKUP_SPOOL)
+            .join(self.subnet_id.to_string());
+        create_dir(&state_dir)?;
+        create_dir(&spool_dir)?;
+
+        rsync(
+            &self.logger,
+            IC_STATE_EXCLUDES.to_vec(),
+            &format!(
+                "{}/data/{}/{}/",
+                self.backup_source, self.subnet_id, IC_STATE
+            ),
+            &format!("{}/", state_dir.display()),
+            self.require_confirmation,
+            self.key_file.as_ref(),
+        )?;
+        rsync(
+            &self.logger,
+            vec![],
+            &format!("{}/spool/{}/", self.backup_source, self.subnet_id),
+            &format!("{}/", spool_dir.display()),
+            self.require_confirmation,
+            self.key_file.as_ref(),
+        )?;
+
+        let (replica_version, height) = find_backup_replay_start(
+            &SubnetSpool::new(spool_dir),
+            &self.work_dir.join("data").join(IC_CHECKPOINTS_PATH),
+        )?;
+        info!(
+            self.logger,
+            "Restoring the checkpoint at height {} with replica version {}",
+            height,
+            replica_version
+        );
+        rsync(
+            &self.logger,
+            vec![],
+            &format!(
+                "{}/binaries/{}/ic.json5",
+                self.backup_source, replica_version
+            ),
+            &self.work_dir.join("ic.json5").display().to_string(),
+            self.require_confirmation,
+            self.key_file.as_ref(),
+        )?;
+
+        Ok(())
+    }
+}
+
 pub struct ReplaySubCmd {
     pub cmd: SubCommand,
     pub descr: String,
@@ -408,6 +542,10 @@ impl Step for ReplayStep {
         base
     }
 
+    fn plan(&self) -> PlannedAction {
+        PlannedAction::Replay
+    }
+
     fn exec(&self) -> RecoveryResult<()> {
         let checkpoint_path = self.work_dir.join("data").join(IC_CHECKPOINTS_PATH);
 
@@ -481,6 +619,10 @@ impl Step for ValidateReplayStep {
         "Compare height after replay to certification and finalization heights of subnet as reported by individual nodes.".to_string()
     }
 
+    fn plan(&self) -> PlannedAction {
+        PlannedAction::Verification
+    }
+
     fn exec(&self) -> RecoveryResult<()> {
         let latest_height =
             replay_helper::read_output(self.work_dir.join(replay_helper::OUTPUT_FILE_NAME))?.height;
@@ -549,6 +691,12 @@ impl Step for UploadAndRestartStep {
         format!("Stopping replica {}, uploading and replacing state from {}, set access rights, restart replica.", self.node_ip, self.data_src.display())
     }
 
+    fn plan(&self) -> PlannedAction {
+        PlannedAction::Upload {
+            node: Some(self.node_ip),
+        }
+    }
+
     fn exec(&self) -> RecoveryResult<()> {
         let account = ADMIN;
         let ssh_helper = SshHelper::new(
@@ -664,6 +812,10 @@ impl Step for WaitForCUPStep {
         )
     }
 
+    fn plan(&self) -> PlannedAction {
+        PlannedAction::Verification
+    }
+
     fn exec(&self) -> RecoveryResult<()> {
         let state_params =
             replay_helper::read_output(self.work_dir.join(replay_helper::OUTPUT_FILE_NAME))?;
@@ -704,6 +856,10 @@ impl Step for StopReplicaStep {
         format!("Stopping replica on {}.", self.node_ip)
     }
 
+    fn plan(&self) -> PlannedAction {
+        PlannedAction::NodeCommand { node: self.node_ip }
+    }
+
     fn exec(&self) -> RecoveryResult<()> {
         let ssh_helper = SshHelper::new(
             self.logger.clone(),
@@ -754,6 +910,13 @@ impl Step for GetRecoveryCUPStep {
         format!("Set recovery CUP by executing:\nic-replay {:?} --subnet-id {:?} get-recovery-cup {:?} {:?} cup.proto", self.config, self.subnet_id, self.state_hash, self.recovery_height)
     }
 
+    fn plan(&self) -> PlannedAction {
+        PlannedAction::CupCreation {
+            recovery_height: self.recovery_height.get(),
+            state_hash: self.state_hash.clone(),
+        }
+    }
+
     fn exec(&self) -> RecoveryResult<()> {
         block_on(replay_helper::replay(
             self.subnet_id,
@@ -860,6 +1023,10 @@ impl Step for UploadCUPAndTar {
         format!("Uploading CUP and registry to {} on ALL nodes with admin access. Then execute on those nodes:\n{}", UploadCUPAndTar::get_upload_dir_name(), self.get_restart_commands())
     }
 
+    fn plan(&self) -> PlannedAction {
+        PlannedAction::Upload { node: None }
+    }
+
     fn exec(&self) -> RecoveryResult<()> {
         let ips = get_member_ips(self.registry_client.clone(), self.subnet_id)?;
 
@@ -935,6 +1102,12 @@ impl Step for DownloadRegistryStoreStep {
         )
     }
 
+    fn plan(&self) -> PlannedAction {
+        PlannedAction::Download {
+            node: Some(self.node_ip),
+        }
+    }
+
     fn exec(&self) -> RecoveryResult<()> {
         let account = ADMIN.to_string();
         let ssh_helper = SshHelper::new(
@@ -1008,6 +1181,12 @@ impl Step for UploadAndHostTarStep {
         )
     }
 
+    fn plan(&self) -> PlannedAction {
+        PlannedAction::Upload {
+            node: Some(self.aux_ip),
+        }
+    }
+
     fn exec(&self) -> RecoveryResult<()> {
         let ssh_helper = SshHelper::new(
             self.logger.clone(),
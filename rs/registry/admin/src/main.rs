@@ -2391,6 +2391,10 @@ impl From<&ProposeToOpenSnsTokenSwap> for OpenSnsTokenSwap {
                     dissolve_delay_interval_seconds: neuron_basket_dissolve_delay_interval_seconds,
                 }),
                 sale_delay_seconds,
+                early_participation_bonus: None,
+                max_direct_participants: None,
+                dutch_auction: None,
+                pro_rata_cutback: None,
             }),
             community_fund_investment_e8s,
         }
@@ -1,5 +1,5 @@
 use ic_artifact_pool::consensus_pool::ConsensusPoolImpl;
-use ic_config::artifact_pool::BACKUP_GROUP_SIZE;
+use ic_backup_spool::VersionSpool;
 use ic_consensus::consensus::dkg_key_manager::DkgKeyManager;
 use ic_consensus_utils::pool_reader::PoolReader;
 use ic_crypto_for_verification_only::CryptoComponentForVerificationOnly;
@@ -85,11 +85,7 @@ pub(crate) fn insert_cup_at_height(
 
 /// Deserializes the CUP at the given height and returns it.
 pub(crate) fn read_cup_at_height(backup_dir: &Path, height: Height) -> CatchUpPackage {
-    let group_key = (height.get() / BACKUP_GROUP_SIZE) * BACKUP_GROUP_SIZE;
-    let file = &backup_dir
-        .join(group_key.to_string())
-        .join(height.to_string())
-        .join("catch_up_package.bin");
+    let file = &VersionSpool::new(backup_dir).cup_path(height);
     let buffer = read_file(file);
 
     let protobuf = ic_protobuf::types::v1::CatchUpPackage::decode(buffer.as_slice())
@@ -105,55 +101,46 @@ pub(super) fn heights_to_artifacts_metadata(
     backup_dir: &Path,
     start_height: Height,
 ) -> Result<BTreeMap<Height, HeightArtifacts>, std::io::Error> {
-    let mut results = Vec::new();
-    for group_dir in fs::read_dir(backup_dir)? {
-        for height_dir in fs::read_dir(group_dir?.path())? {
-            let path = height_dir?.path();
-            let height = Height::from(
-                path.file_name()
+    let spool = VersionSpool::new(backup_dir);
+    let mut results = BTreeMap::new();
+    // Skip all height folders below the start height.
+    for height in spool
+        .heights()?
+        .into_iter()
+        .filter(|height| *height >= start_height)
+    {
+        let path = spool.height_dir(height);
+        let mut files = Vec::new();
+        for file in fs::read_dir(&path)? {
+            let file_path = file?.path();
+            files.push(
+                file_path
+                    .file_name()
                     .unwrap_or_default()
                     .to_str()
                     .unwrap_or_default()
-                    .parse::<u64>()
-                    .expect("Couldn't parse the height directory name"),
+                    .to_string(),
             );
-
-            // Skip all height folders below the start height,
-            if height < start_height {
-                continue;
-            }
-            let mut files = Vec::new();
-            for file in fs::read_dir(&path)? {
-                let file_path = file?.path();
-                files.push(
-                    file_path
-                        .file_name()
-                        .unwrap_or_default()
-                        .to_str()
-                        .unwrap_or_default()
-                        .to_string(),
-                );
-            }
-            let get_files = |s| {
-                files
-                    .iter()
-                    .filter(|file| file.starts_with(s))
-                    .cloned()
-                    .collect::<Vec<_>>()
-            };
-            results.push((
-                height,
-                HeightArtifacts {
-                    path,
-                    contains_cup: !get_files("catch_up_package").is_empty(),
-                    proposals: get_files("block_proposal"),
-                    finalizations: get_files("finalization"),
-                    notarizations: get_files("notarization"),
-                },
-            ));
         }
+        let get_files = |s| {
+            files
+                .iter()
+                .filter(|file| file.starts_with(s))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        results.insert(
+            height,
+            HeightArtifacts {
+                path,
+                contains_cup: !get_files("catch_up_package").is_empty(),
+                proposals: get_files("block_proposal"),
+                finalizations: get_files("finalization"),
+                notarizations: get_files("notarization"),
+            },
+        );
     }
-    Ok(results.into_iter().collect())
+    Ok(results)
 }
 
 /// Deserializes consensus artifacts, reading them from the backup spool height
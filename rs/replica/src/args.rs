@@ -1,5 +1,5 @@
 use clap::Parser;
-use ic_config::ConfigSource;
+use ic_config::{ConfigOverride, ConfigProfile, ConfigSource};
 use ic_types::ReplicaVersion;
 use std::convert::TryFrom;
 use std::path::PathBuf;
@@ -23,6 +23,25 @@ pub struct ReplicaArgs {
     #[clap(long)]
     pub config_literal: Option<String>,
 
+    /// Override a single config value, e.g.
+    /// `--config-override http_handler.max_tcp_connections=100`. Takes
+    /// precedence over the config file and `IC_<SECTION>__<FIELD>`
+    /// environment variables. Can be given multiple times.
+    #[clap(long = "config-override", value_name = "PATH=VALUE")]
+    pub config_overrides: Vec<ConfigOverride>,
+
+    /// The config profile (mainnet, testnet or local-dev) setting the values
+    /// the config file leaves out. Takes precedence over the `profile` of
+    /// the config file.
+    #[clap(long, value_name = "PROFILE")]
+    pub config_profile: Option<ConfigProfile>,
+
+    /// Write the effective config, with all defaults and overrides resolved,
+    /// to this path as JSON at startup, e.g. to diff it against the expected
+    /// config when debugging
+    #[clap(long, parse(from_os_str), value_name = "PATH")]
+    pub dump_effective_config: Option<PathBuf>,
+
     /// A path to a CBOR-encoded catch-up package to seed the Replica with
     #[clap(long, parse(from_os_str))]
     pub catch_up_package: Option<PathBuf>,
@@ -0,0 +1,100 @@
+//! Reloads the replica config on `SIGHUP` and applies the fields that can be
+//! changed at runtime, so that e.g. HTTP timeouts and rate limits can be
+//! tuned without restarting the replica. Reloads that change other fields
+//! are rejected as a whole.
+use ic_config::{Config, ConfigOverride, ConfigProfile, ConfigSource, LayeredConfig};
+use ic_http_endpoints_public::HttpConfigReloader;
+use ic_logger::{info, warn, ReplicaLogger};
+use ic_metrics::MetricsRegistry;
+use prometheus::IntCounterVec;
+use std::path::PathBuf;
+use tokio::signal::unix::{signal, SignalKind};
+
+const STATUS_APPLIED: &str = "applied";
+const STATUS_UNCHANGED: &str = "unchanged";
+const STATUS_REJECTED: &str = "rejected";
+
+/// Loads the config the same way as on startup.
+pub struct ConfigReloader {
+    source: ConfigSource,
+    tmpdir: PathBuf,
+    profile: Option<ConfigProfile>,
+    cli_overrides: Vec<ConfigOverride>,
+    http_config_reloader: HttpConfigReloader,
+    reloads_total: IntCounterVec,
+    log: ReplicaLogger,
+}
+
+impl ConfigReloader {
+    pub fn new(
+        source: ConfigSource,
+        tmpdir: PathBuf,
+        profile: Option<ConfigProfile>,
+        cli_overrides: Vec<ConfigOverride>,
+        http_config_reloader: HttpConfigReloader,
+        metrics_registry: &MetricsRegistry,
+        log: ReplicaLogger,
+    ) -> Self {
+        Self {
+            source,
+            tmpdir,
+            profile,
+            cli_overrides,
+            http_config_reloader,
+            reloads_total: metrics_registry.int_counter_vec(
+                "replica_config_reloads_total",
+                "Config reloads by status: applied, unchanged or rejected.",
+                &["status"],
+            ),
+            log,
+        }
+    }
+
+    /// Reloads the config and applies its reloadable fields. Returns the
+    /// status the reload is counted as.
+    pub fn reload(&self) -> &'static str {
+        let status = match LayeredConfig::load(
+            &self.source,
+            Config::new(self.tmpdir.clone()),
+            self.profile,
+            std::env::vars(),
+            &self.cli_overrides,
+        ) {
+            Ok(layered_config) => match self
+                .http_config_reloader
+                .reload(layered_config.config.http_handler)
+            {
+                Ok(true) => {
+                    info!(self.log, "Applied the reloaded http_handler config.");
+                    STATUS_APPLIED
+                }
+                Ok(false) => STATUS_UNCHANGED,
+                Err(err) => {
+                    warn!(
+                        self.log,
+                        "Rejected the reloaded http_handler config: {}", err
+                    );
+                    STATUS_REJECTED
+                }
+            },
+            Err(err) => {
+                warn!(self.log, "Failed to reload the config: {}", err);
+                STATUS_REJECTED
+            }
+        };
+        self.reloads_total.with_label_values(&[status]).inc();
+        status
+    }
+
+    /// Reloads the config whenever the process receives `SIGHUP`. Must be
+    /// called within a tokio runtime.
+    pub fn reload_on_sighup(self) {
+        let mut hangup = signal(SignalKind::hangup()).expect("failed to install SIGHUP handler");
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                info!(self.log, "Received SIGHUP, reloading the config.");
+                self.reload();
+            }
+        });
+    }
+}
@@ -1,3 +1,4 @@
 pub mod args;
+pub mod config_reload;
 pub mod setup;
 pub mod setup_ic_stack;
@@ -1,16 +1,16 @@
 //! Replica -- Internet Computer
 
 use ic_async_utils::{abort_on_panic, shutdown_signal};
-use ic_config::{subnet_config::SubnetConfigs, Config};
+use ic_config::{subnet_config::SubnetConfigs, LayeredConfig};
 use ic_crypto_sha::Sha256;
 use ic_crypto_tls_interfaces::TlsHandshake;
 use ic_http_endpoints_metrics::MetricsHttpEndpoint;
 use ic_interfaces_registry::{LocalStoreCertifiedTimeReader, RegistryClient};
-use ic_logger::{info, new_replica_logger_from_config};
+use ic_logger::{info, new_replica_logger_from_config, warn};
 use ic_metrics::MetricsRegistry;
 use ic_onchain_observability_server::spawn_onchain_observability_grpc_server_and_register_metrics;
 use ic_registry_client_helpers::subnet::SubnetRegistry;
-use ic_replica::setup;
+use ic_replica::{config_reload::ConfigReloader, setup};
 use ic_sys::PAGE_SIZE;
 use ic_types::{replica_version::REPLICA_BINARY_HASH, PrincipalId, ReplicaVersion, SubnetId};
 use nix::unistd::{setpgid, Pid};
@@ -144,9 +144,52 @@ fn main() -> io::Result<()> {
         .prefix("ic_config")
         .tempdir()
         .unwrap();
-    let config = Config::load_with_tmpdir(config_source, tmpdir.path().to_path_buf());
+    let config_overrides = replica_args
+        .as_ref()
+        .map(|args| args.config_overrides.clone())
+        .unwrap_or_default();
+    let config_profile = replica_args
+        .as_ref()
+        .ok()
+        .and_then(|args| args.config_profile);
+    let layered_config = LayeredConfig::load_with_tmpdir(
+        config_source.clone(),
+        tmpdir.path().to_path_buf(),
+        config_profile,
+        &config_overrides,
+    );
+    let config = layered_config.config.clone();
+    // Reject invalid configs before any sockets are bound or directories created.
+    if let Err(defects) = config.validate_all() {
+        eprintln!("Invalid replica config:");
+        for defect in defects {
+            eprintln!("  {}", defect);
+        }
+        std::process::exit(1);
+    }
 
     let (logger, async_log_guard) = new_replica_logger_from_config(&config.logger);
+    info!(
+        logger,
+        "Effective replica config:\n{}",
+        layered_config.dump()
+    );
+    for deprecation in &layered_config.deprecations {
+        warn!(logger, "Deprecated replica config field: {}", deprecation);
+    }
+    if let Some(path) = replica_args
+        .as_ref()
+        .ok()
+        .and_then(|args| args.dump_effective_config.as_ref())
+    {
+        match config.dump_effective(path) {
+            Ok(()) => info!(logger, "Wrote the effective replica config to {:?}", path),
+            Err(err) => warn!(
+                logger,
+                "Failed to write the effective replica config to {:?}: {}", path, err
+            ),
+        }
+    }
 
     let metrics_registry = MetricsRegistry::global();
 
@@ -263,7 +306,7 @@ fn main() -> io::Result<()> {
     );
 
     info!(logger, "Constructing IC stack");
-    let (_, _, _p2p_thread_joiner, _, _xnet_endpoint) =
+    let (_, _, _p2p_thread_joiner, _, _xnet_endpoint, http_server) =
         ic_replica::setup_ic_stack::construct_ic_stack(
             &logger,
             &metrics_registry,
@@ -283,6 +326,18 @@ fn main() -> io::Result<()> {
         )?;
     info!(logger, "Constructed IC stack");
 
+    // Apply the reloadable config fields on SIGHUP.
+    let config_reloader = ConfigReloader::new(
+        config_source,
+        tmpdir.path().to_path_buf(),
+        config_profile,
+        config_overrides,
+        http_server.config_reloader(),
+        &metrics_registry,
+        logger.clone(),
+    );
+    rt_main.block_on(async move { config_reloader.reload_on_sighup() });
+
     // TODO(NET-1366) - remove this flag once confident that starting gRPC is stable
     if config
         .adapters_config
@@ -325,6 +380,8 @@ fn main() -> io::Result<()> {
         shutdown_signal(logger.inner_logger.root.clone()).await
     });
     info!(save_logger, "IC Replica Terminating");
+    // Let in-flight requests complete before the runtimes are dropped.
+    rt_http.block_on(http_server.shutdown());
 
     #[cfg(feature = "profiler")]
     finalize_report(&guard);
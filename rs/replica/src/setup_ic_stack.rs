@@ -5,6 +5,7 @@ use ic_consensus::certification::VerifierImpl;
 use ic_crypto::CryptoComponent;
 use ic_cycles_account_manager::CyclesAccountManager;
 use ic_execution_environment::ExecutionServices;
+use ic_http_endpoints_public::HttpServerHandle;
 use ic_https_outcalls_adapter_client::setup_canister_http_client;
 use ic_icos_sev::Sev;
 use ic_interfaces::execution_environment::QueryHandler;
@@ -52,6 +53,7 @@ pub fn construct_ic_stack(
     // TODO: remove this return value since it is used only in tests
     IngressIngestionService,
     XNetEndpoint,
+    HttpServerHandle,
 )> {
     // ---------- ARTIFACT POOLS DEPS FOLLOW ----------
     // Determine the correct catch-up package.
@@ -256,7 +258,7 @@ pub fn construct_ic_stack(
         config.nns_registry_replicator.poll_delay_duration_ms,
     );
     // ---------- PUBLIC ENDPOINT DEPS FOLLOW ----------
-    ic_http_endpoints_public::start_server(
+    let http_server = ic_http_endpoints_public::start_server(
         rt_handle_http,
         metrics_registry,
         config.http_handler.clone(),
@@ -281,5 +283,6 @@ pub fn construct_ic_stack(
         p2p_runner,
         ingress_ingestion_service,
         xnet_endpoint,
+        http_server,
     ))
 }
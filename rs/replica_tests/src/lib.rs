@@ -394,7 +394,7 @@ where
             ..Default::default()
         };
         let temp_node = node_id;
-        let (state_manager, query_handler, _p2p_thread_joiner, ingress_ingestion_service, _) =
+        let (state_manager, query_handler, _p2p_thread_joiner, ingress_ingestion_service, _, _) =
             ic_replica::setup_ic_stack::construct_ic_stack(
                 &logger,
                 &metrics_registry,
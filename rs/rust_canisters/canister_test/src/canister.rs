@@ -1,12 +1,14 @@
 use backoff::backoff::Backoff;
 use core::future::Future;
 use dfn_candid::{candid, candid_multi_arity};
+use futures::stream::{self, StreamExt};
 use ic_canister_client::{Agent, Sender};
 use ic_config::{subnet_config::SubnetConfig, Config};
 use ic_ic00_types::CanisterStatusType::Stopped;
 pub use ic_ic00_types::{
     self as ic00, CanisterIdRecord, CanisterInstallMode, CanisterStatusResult, InstallCodeArgs,
-    ProvisionalCreateCanisterWithCyclesArgs, SetControllerArgs, IC_00,
+    ProvisionalCreateCanisterWithCyclesArgs, ProvisionalTopUpCanisterArgs, SetControllerArgs,
+    IC_00,
 };
 use ic_registry_transport::pb::v1::RegistryMutation;
 use ic_replica_tests::*;
@@ -29,6 +31,10 @@ const MAX_BACKOFF_INTERVAL: Duration = Duration::from_secs(10);
 const BACKOFF_INTERVAL_MULTIPLIER: f64 = 1.1;
 const MAX_ELAPSED_TIME: Duration = Duration::from_secs(60 * 5); // 5 minutes
 
+// The maximum number of management canister calls in flight for the batch
+// operations on canisters.
+const MAX_CONCURRENT_BATCH_CALLS: usize = 50;
+
 pub fn get_backoff_policy() -> backoff::ExponentialBackoff {
     backoff::ExponentialBackoff {
         initial_interval: MIN_BACKOFF_INTERVAL,
@@ -232,6 +238,69 @@ impl Wasm {
     }
 }
 
+/// Returns the statuses of `canisters`, in the same order. At most
+/// `MAX_CONCURRENT_BATCH_CALLS` status calls are in flight at any time.
+pub async fn canister_statuses(
+    canisters: &[Canister<'_>],
+) -> Vec<Result<CanisterStatusResultV2, String>> {
+    stream::iter(canisters)
+        .map(|canister| canister.status())
+        .buffered(MAX_CONCURRENT_BATCH_CALLS)
+        .collect()
+        .await
+}
+
+/// Adds `amount` cycles to the balance of each of `canisters`. Returns an
+/// error listing the canisters that could not be topped up, if any.
+pub async fn top_up_canisters(canisters: &[Canister<'_>], amount: u128) -> Result<(), String> {
+    for_each_canister(canisters, |canister| canister.top_up(amount))
+        .await
+        .map_err(|errors| format!("Failed to top up canisters: {}", errors))
+}
+
+/// Sets the controllers of each of `canisters` to `new_controllers`. Returns
+/// an error listing the canisters whose controllers could not be set, if any.
+pub async fn set_controllers_of_canisters(
+    canisters: &[Canister<'_>],
+    new_controllers: Vec<PrincipalId>,
+) -> Result<(), String> {
+    for_each_canister(canisters, |canister| {
+        canister.set_controllers(new_controllers.clone())
+    })
+    .await
+    .map_err(|errors| format!("Failed to set the controllers of canisters: {}", errors))
+}
+
+/// Calls `f` on each of `canisters`, with at most `MAX_CONCURRENT_BATCH_CALLS`
+/// calls in flight at any time. Returns the errors of the failed calls, by
+/// canister, if any.
+async fn for_each_canister<'b, 'c, F, Fut>(
+    canisters: &'b [Canister<'c>],
+    f: F,
+) -> Result<(), String>
+where
+    F: Fn(&'b Canister<'c>) -> Fut,
+    Fut: Future<Output = Result<(), String>>,
+{
+    let errors: Vec<String> = stream::iter(canisters)
+        .map(|canister| {
+            let call = f(canister);
+            async move {
+                call.await
+                    .map_err(|e| format!("{}: {}", canister.canister_id(), e))
+            }
+        })
+        .buffer_unordered(MAX_CONCURRENT_BATCH_CALLS)
+        .filter_map(|res| async move { res.err() })
+        .collect()
+        .await;
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("; "))
+    }
+}
+
 /// Tries calling function `f` a few times with exponential backoff until it
 /// results an `Ok`. If after a while `f` has returned only `Err`s, gives up and
 /// returns the last error.
@@ -756,6 +825,30 @@ impl<'a> Canister<'a> {
             .await
     }
 
+    /// Returns the status of this canister, as seen by the management canister.
+    pub async fn status(&self) -> Result<CanisterStatusResultV2, String> {
+        self.runtime
+            .get_management_canister_with_effective_canister_id(self.canister_id().into())
+            .update_("canister_status", candid, (self.as_record(),))
+            .await
+    }
+
+    /// Adds `amount` cycles to the balance of this canister.
+    ///
+    /// Note that this calls ic00::Method::ProvisionalTopUpCanister, which is
+    /// protected by a whitelist of callers, like the creation of canisters
+    /// with cycles.
+    pub async fn top_up(&self, amount: u128) -> Result<(), String> {
+        self.runtime
+            .get_management_canister_with_effective_canister_id(self.canister_id().into())
+            .update_(
+                ic00::Method::ProvisionalTopUpCanister.to_string(),
+                candid_multi_arity,
+                (ProvisionalTopUpCanisterArgs::new(self.canister_id, amount),),
+            )
+            .await
+    }
+
     pub async fn set_controller_with_retries(
         &self,
         new_controller: PrincipalId,
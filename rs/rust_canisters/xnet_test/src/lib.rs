@@ -1,4 +1,5 @@
 use candid::{CandidType, Deserialize};
+use rand::Rng;
 use std::time::Duration;
 
 /// Id of a canister is an opaque blob.
@@ -10,6 +11,86 @@ pub type CanisterId = Vec<u8>;
 /// This message is used as request payload for "start" call.
 pub type NetworkTopology = Vec<Vec<CanisterId>>;
 
+/// Distribution of the sizes (in bytes) of the payloads to pad messages to.
+///
+/// This message is used as the optional fourth argument of the "start" call,
+/// specifying the sizes of the responses to the requests sent by the canister.
+#[derive(Clone, CandidType, Deserialize, Debug, PartialEq, Eq)]
+pub enum PayloadSizeDistribution {
+    /// All payloads have the same size.
+    Fixed(u64),
+    /// Payload sizes are uniformly distributed between `min` and `max`
+    /// (inclusive).
+    Uniform { min: u64, max: u64 },
+    /// Payloads are `large` with a probability of `large_percentage` percent
+    /// and `small` otherwise.
+    Bimodal {
+        small: u64,
+        large: u64,
+        large_percentage: u8,
+    },
+}
+
+impl PayloadSizeDistribution {
+    /// Draws a payload size from this distribution.
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> u64 {
+        match self {
+            Self::Fixed(size) => *size,
+            Self::Uniform { min, max } => rng.gen_range(*min..=(*max).max(*min)),
+            Self::Bimodal {
+                small,
+                large,
+                large_percentage,
+            } => {
+                if rng.gen_range(0..100) < *large_percentage {
+                    *large
+                } else {
+                    *small
+                }
+            }
+        }
+    }
+
+    /// Returns the mean payload size of this distribution.
+    pub fn mean(&self) -> u64 {
+        match self {
+            Self::Fixed(size) => *size,
+            Self::Uniform { min, max } => (min + (*max).max(*min)) / 2,
+            Self::Bimodal {
+                small,
+                large,
+                large_percentage,
+            } => {
+                let large_percentage = (*large_percentage).min(100) as u64;
+                (large * large_percentage + small * (100 - large_percentage)) / 100
+            }
+        }
+    }
+}
+
+/// Percentages of the requests sent by a canister that the receiving canister
+/// is asked to handle by other means than an immediate reply.
+///
+/// This message is used as the optional fifth argument of the "start" call.
+#[derive(Clone, Default, CandidType, Deserialize, Debug, PartialEq, Eq)]
+pub struct FaultInjection {
+    /// Percentage of requests the receiving canister traps on.
+    pub trap_percentage: u8,
+
+    /// Percentage of requests the receiving canister rejects explicitly.
+    pub reject_percentage: u8,
+
+    /// Percentage of requests the receiving canister only replies to after a
+    /// roundtrip call to itself.
+    pub delay_percentage: u8,
+}
+
+/// Reject code of an explicit reject by a canister.
+pub const REJECT_CODE_CANISTER_REJECT: i32 = 4;
+
+/// Reject code of a canister trap.
+pub const REJECT_CODE_CANISTER_ERROR: i32 = 5;
+
 /// Metrics observed by this canister.
 ///
 /// This message is used as reply payload for "metrics" query.
@@ -26,9 +107,52 @@ pub struct Metrics {
     /// canister input queue).
     pub reject_responses: usize,
 
+    /// Number of replies received.
+    pub reply_responses: usize,
+
     /// Number of sequence number errors.
     pub seq_errors: usize,
 
+    /// Number of requests sent asking the receiving canister to trap.
+    pub traps_injected: usize,
+
+    /// Number of requests sent asking the receiving canister to reject.
+    pub rejects_injected: usize,
+
+    /// Number of requests sent asking the receiving canister to delay its
+    /// reply.
+    pub delays_injected: usize,
+
+    /// Number of reject responses caused by a trap of the remote canister
+    /// (included in `reject_responses`).
+    pub canister_error_responses: usize,
+
+    /// Number of explicit reject responses of the remote canister (included
+    /// in `reject_responses`).
+    pub canister_reject_responses: usize,
+
+    /// Total size in bytes of the requests sent.
+    pub request_bytes_sent: usize,
+
+    /// Total size in bytes of the requests received.
+    pub request_bytes_received: usize,
+
+    /// Total size in bytes of the responses sent.
+    pub response_bytes_sent: usize,
+
+    /// Total size in bytes of the responses received (excluding reject
+    /// responses).
+    pub response_bytes_received: usize,
+
+    /// Cycles attached to the requests sent.
+    pub cycles_sent: u64,
+
+    /// Cycles refunded with the responses received.
+    pub cycles_refunded: u64,
+
+    /// Cycles accepted from the requests received.
+    pub cycles_accepted: u64,
+
     /// Observed message rountrip latencies.
     pub latency_distribution: LatencyDistribution,
 
@@ -42,7 +166,20 @@ impl Metrics {
         self.requests_sent += other.requests_sent;
         self.call_errors += other.call_errors;
         self.reject_responses += other.reject_responses;
+        self.reply_responses += other.reply_responses;
         self.seq_errors += other.seq_errors;
+        self.traps_injected += other.traps_injected;
+        self.rejects_injected += other.rejects_injected;
+        self.delays_injected += other.delays_injected;
+        self.canister_error_responses += other.canister_error_responses;
+        self.canister_reject_responses += other.canister_reject_responses;
+        self.request_bytes_sent += other.request_bytes_sent;
+        self.request_bytes_received += other.request_bytes_received;
+        self.response_bytes_sent += other.response_bytes_sent;
+        self.response_bytes_received += other.response_bytes_received;
+        self.cycles_sent += other.cycles_sent;
+        self.cycles_refunded += other.cycles_refunded;
+        self.cycles_accepted += other.cycles_accepted;
         self.latency_distribution.merge(&other.latency_distribution);
         self.log.push_str("-----\n");
         self.log.push_str(&other.log);
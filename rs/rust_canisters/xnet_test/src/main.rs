@@ -7,14 +7,17 @@
 //! ```
 use candid::{CandidType, Decode, Deserialize, Encode};
 use dfn_core::api;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
 use rand_pcg::Lcg64Xsh32;
 use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::str::FromStr;
 use std::time::Duration;
-use xnet_test::{CanisterId, Metrics, NetworkTopology};
+use xnet_test::{
+    CanisterId, FaultInjection, Metrics, NetworkTopology, PayloadSizeDistribution,
+    REJECT_CODE_CANISTER_ERROR, REJECT_CODE_CANISTER_REJECT,
+};
 
 thread_local! {
     /// Whether this canister is generating traffic.
@@ -30,9 +33,22 @@ thread_local! {
     /// Number of requests to send to each subnet (other than ours) every round.
     static PER_SUBNET_RATE: RefCell<u64> = RefCell::new(1);
 
-    /// Pad requests AND responses to this size (in bytes) if smaller.
+    /// Pad requests to this size (in bytes) if smaller. Also applies to
+    /// responses, unless `RESPONSE_PAYLOAD_SIZE` is set.
     static PAYLOAD_SIZE: RefCell<u64> = RefCell::new(1024);
 
+    /// Distribution of the sizes (in bytes) to pad the responses to our
+    /// requests to, if set.
+    static RESPONSE_PAYLOAD_SIZE: RefCell<Option<PayloadSizeDistribution>> = RefCell::new(None);
+
+    /// Percentages of our requests that the receiving canister should trap on,
+    /// reject or reply to with a delay.
+    static FAULT_INJECTION: RefCell<FaultInjection> = RefCell::new(Default::default());
+
+    /// Cycles to attach to each of our requests. The receiving canister
+    /// accepts half of them, unless it traps or rejects the request.
+    static CYCLES_PER_CALL: RefCell<u64> = RefCell::new(0);
+
     /// State of the messaging that we use to check invariants (e.g., sequence
     /// numbers).
     static STATE: RefCell<MessagingState> = RefCell::new(Default::default());
@@ -45,8 +61,19 @@ thread_local! {
     /// The default values for state and stream parameters come from the official documentation:
     /// https://rust-random.github.io/rand/rand_pcg/struct.Lcg64Xsh32.html
     static RNG: RefCell<Lcg64Xsh32> = RefCell::new(Lcg64Xsh32::new(0xcafe_f00d_d15e_a5e5, 0x0a02_bdbf_7bb3_c0a7));
+
+    /// Heap memory allocated by "grow_state", only held to make the state of
+    /// this canister large.
+    static BALLAST: RefCell<Vec<Vec<u8>>> = RefCell::new(Vec::new());
 }
 
+/// Size of a WebAssembly page, the unit "grow_state" grows the heap by.
+const WASM_PAGE_SIZE: usize = 64 * 1024;
+
+/// Size of an OS page: "grow_state" writes one byte into each, so that all of
+/// them end up in the canister state.
+const OS_PAGE_SIZE: usize = 4 * 1024;
+
 /// Request sent by the "fanout" method.
 #[derive(CandidType, Deserialize)]
 struct Request {
@@ -55,6 +82,23 @@ struct Request {
     seq_no: u64,
     /// Local time observed in the round when this message was sent.
     time_nanos: u64,
+    /// Size (in bytes) to pad the reply to.
+    response_size: u64,
+    /// How the receiving canister should handle this request.
+    fault: Fault,
+}
+
+/// How "handle_request" should handle a `Request`.
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq)]
+enum Fault {
+    /// Reply immediately.
+    None,
+    /// Trap.
+    Trap,
+    /// Reject explicitly.
+    Reject,
+    /// Reply after a roundtrip call to self.
+    Delay,
 }
 
 /// A `Reply` to the `Request` message, sent from the "handle_request" method.
@@ -98,11 +142,11 @@ fn candid_reply<T: CandidType>(t: &T) {
     api::reply(&msg[..])
 }
 
-/// Encodes `t` as Candid, padded to `PAYLOAD_SIZE`.
-fn candid_encode_padded<T: CandidType>(t: &T) -> Vec<u8> {
+/// Encodes `t` as Candid, padded to `payload_size`.
+fn candid_encode_padded<T: CandidType>(t: &T, payload_size: u64) -> Vec<u8> {
     let msg = candid::Encode!(t, &vec![13u8; 1]).expect("failed to encode message");
 
-    let payload_size = PAYLOAD_SIZE.with(|p| *p.borrow()) as usize;
+    let payload_size = payload_size as usize;
     if msg.len() < payload_size {
         candid::Encode!(t, &vec![13u8; payload_size - msg.len() + 1])
             .expect("failed to encode message")
@@ -118,15 +162,32 @@ fn time_nanos() -> u64 {
 
 /// Callback for handling replies from "handle_request".
 fn on_reply(_env: *mut ()) {
+    let arg_data = api::arg_data();
     let (reply, _) =
-        candid::Decode!(&api::arg_data()[..], Reply, Vec<u8>).expect("failed to decode response");
+        candid::Decode!(&arg_data[..], Reply, Vec<u8>).expect("failed to decode response");
     let elapsed = Duration::from_nanos(time_nanos() - reply.time_nanos);
-    METRICS.with(|m| m.borrow_mut().latency_distribution.observe(elapsed));
+    METRICS.with(|m| {
+        let mut m = m.borrow_mut();
+        m.latency_distribution.observe(elapsed);
+        m.reply_responses += 1;
+        m.response_bytes_received += arg_data.len();
+        m.cycles_refunded += api::msg_cycles_refunded();
+    });
 }
 
 /// Callback for handling reject responses from "handle_request".
 fn on_reject(_env: *mut ()) {
-    METRICS.with(|m| m.borrow_mut().reject_responses += 1);
+    let reject_code = api::reject_code();
+    METRICS.with(|m| {
+        let mut m = m.borrow_mut();
+        m.reject_responses += 1;
+        m.cycles_refunded += api::msg_cycles_refunded();
+        match reject_code {
+            REJECT_CODE_CANISTER_ERROR => m.canister_error_responses += 1,
+            REJECT_CODE_CANISTER_REJECT => m.canister_reject_responses += 1,
+            _ => {}
+        }
+    });
 }
 
 /// Returns true if this canister should continue generating traffic.
@@ -162,12 +223,37 @@ fn log(message: &str) {
 
 /// Initializes network topology and instructs this canister to start sending
 /// requests to other canisters.
+///
+/// The optional fourth argument specifies the distribution of response sizes;
+/// if missing, responses are padded to the same size as requests. The optional
+/// fifth argument specifies the faults to inject into the handling of our
+/// requests; if missing, all requests are replied to immediately. The optional
+/// sixth argument specifies the cycles to attach to each request; if missing,
+/// none are attached. The optional seventh argument seeds the generator that
+/// picks the next canister to talk to and the sizes of the payloads; if
+/// missing, all canisters use the same fixed seed.
 #[export_name = "canister_update start"]
 fn start() {
     dfn_core::printer::hook();
-    let (network_topology, rate, payload_size) =
-        candid::Decode!(&api::arg_data()[..], NetworkTopology, u64, u64)
-            .expect("failed to decode subnet canister ids");
+    let (
+        network_topology,
+        rate,
+        payload_size,
+        response_payload_size,
+        fault_injection,
+        cycles_per_call,
+        rng_seed,
+    ) = candid::Decode!(
+        &api::arg_data()[..],
+        NetworkTopology,
+        u64,
+        u64,
+        Option<PayloadSizeDistribution>,
+        Option<FaultInjection>,
+        Option<u64>,
+        Option<u64>
+    )
+    .expect("failed to decode subnet canister ids");
 
     NETWORK_TOPOLOGY.with(move |canisters| {
         *canisters.borrow_mut() = network_topology;
@@ -175,6 +261,12 @@ fn start() {
 
     PER_SUBNET_RATE.with(|r| *r.borrow_mut() = rate);
     PAYLOAD_SIZE.with(|r| *r.borrow_mut() = payload_size);
+    RESPONSE_PAYLOAD_SIZE.with(|r| *r.borrow_mut() = response_payload_size);
+    FAULT_INJECTION.with(|f| *f.borrow_mut() = fault_injection.unwrap_or_default());
+    CYCLES_PER_CALL.with(|c| *c.borrow_mut() = cycles_per_call.unwrap_or_default());
+    if let Some(seed) = rng_seed {
+        RNG.with(|rng| *rng.borrow_mut() = Lcg64Xsh32::seed_from_u64(seed));
+    }
 
     RUNNING.with(|r| *r.borrow_mut() = true);
 
@@ -188,6 +280,26 @@ fn stop() {
     candid_reply(&"stopped");
 }
 
+/// Picks how the receiving canister should handle the next request, according
+/// to `FAULT_INJECTION`.
+fn next_fault() -> Fault {
+    let fault_injection = FAULT_INJECTION.with(|f| f.borrow().clone());
+    let roll = RNG.with(|rng| rng.borrow_mut().gen_range(0..100u16));
+
+    let trap_until = fault_injection.trap_percentage as u16;
+    let reject_until = trap_until + fault_injection.reject_percentage as u16;
+    let delay_until = reject_until + fault_injection.delay_percentage as u16;
+    if roll < trap_until {
+        Fault::Trap
+    } else if roll < reject_until {
+        Fault::Reject
+    } else if roll < delay_until {
+        Fault::Delay
+    } else {
+        Fault::None
+    }
+}
+
 /// Sends `PER_SUBNET_RATE` messages to random canisters on the remote subnets.
 /// Invoked by the canister heartbeat handler as long as `RUNNING` is `true`
 /// (`start()` was and `stop()` was not yet called).
@@ -196,6 +308,9 @@ fn fanout() {
 
     let network_topology =
         NETWORK_TOPOLOGY.with(|network_topology| network_topology.borrow().clone());
+    let payload_size = PAYLOAD_SIZE.with(|p| *p.borrow());
+    let response_payload_size = RESPONSE_PAYLOAD_SIZE.with(|r| r.borrow().clone());
+    let cycles_per_call = CYCLES_PER_CALL.with(|c| *c.borrow());
 
     for canisters in network_topology {
         if canisters.is_empty() {
@@ -213,10 +328,22 @@ fn fanout() {
 
             let seq_no = STATE.with(|s| s.borrow_mut().next_out_seq_no(canister.clone()));
 
-            let msg = candid_encode_padded(&Request {
-                seq_no,
-                time_nanos: time_nanos(),
-            });
+            let response_size = match &response_payload_size {
+                Some(distribution) => RNG.with(|rng| distribution.sample(&mut *rng.borrow_mut())),
+                None => payload_size,
+            };
+
+            let fault = next_fault();
+
+            let msg = candid_encode_padded(
+                &Request {
+                    seq_no,
+                    time_nanos: time_nanos(),
+                    response_size,
+                    fault,
+                },
+                payload_size,
+            );
 
             let err_code = api::call_raw(
                 api::CanisterId::try_from(canister.clone()).unwrap(),
@@ -226,7 +353,9 @@ fn fanout() {
                 on_reject,
                 None,
                 std::ptr::null_mut(),
-                api::Funds::zero(),
+                api::Funds {
+                    cycles: cycles_per_call,
+                },
             );
 
             if err_code != 0 {
@@ -237,7 +366,18 @@ fn fanout() {
                 ));
                 METRICS.with(|m| m.borrow_mut().call_errors += 1);
             } else {
-                METRICS.with(move |m| m.borrow_mut().requests_sent += 1);
+                METRICS.with(|m| {
+                    let mut m = m.borrow_mut();
+                    m.requests_sent += 1;
+                    m.request_bytes_sent += msg.len();
+                    m.cycles_sent += cycles_per_call;
+                    match fault {
+                        Fault::None => {}
+                        Fault::Trap => m.traps_injected += 1,
+                        Fault::Reject => m.rejects_injected += 1,
+                        Fault::Delay => m.delays_injected += 1,
+                    }
+                });
             }
         }
     }
@@ -246,8 +386,12 @@ fn fanout() {
 /// Endpoint that handles requests from canisters located on remote subnets.
 #[export_name = "canister_update handle_request"]
 fn handle_request() {
+    let arg_data = api::arg_data();
     let (req, _) =
-        candid::Decode!(&api::arg_data()[..], Request, Vec<u8>).expect("failed to decode request");
+        candid::Decode!(&arg_data[..], Request, Vec<u8>).expect("failed to decode request");
+    if req.fault == Fault::Trap {
+        api::trap_with("injected trap");
+    }
     let caller = api::caller();
     let in_seq_no = STATE.with(|s| s.borrow_mut().set_in_seq_no(caller.into_vec(), req.seq_no));
 
@@ -255,10 +399,79 @@ fn handle_request() {
         METRICS.with(|m| m.borrow_mut().seq_errors += 1);
     }
 
-    let msg = candid_encode_padded(&Reply {
-        time_nanos: req.time_nanos,
+    let msg = candid_encode_padded(
+        &Reply {
+            time_nanos: req.time_nanos,
+        },
+        req.response_size,
+    );
+    METRICS.with(|m| {
+        let mut m = m.borrow_mut();
+        m.request_bytes_received += arg_data.len();
+        if req.fault != Fault::Reject {
+            m.response_bytes_sent += msg.len();
+            // The cycles not accepted are refunded with the reply.
+            m.cycles_accepted += api::msg_cycles_accept(api::msg_cycles_available() / 2);
+        }
+    });
+
+    match req.fault {
+        Fault::Reject => api::reject("injected reject"),
+        Fault::Delay => {
+            let on_reply_msg = msg.clone();
+            let on_reject_msg = msg.clone();
+            let err_code = api::call_with_callbacks(
+                api::id(),
+                "noop",
+                &candid::Encode!().expect("failed to encode noop call"),
+                move || api::reply(&on_reply_msg[..]),
+                move || api::reply(&on_reject_msg[..]),
+            );
+            if err_code != 0 {
+                // Could not delay the reply, reply immediately instead.
+                log(&format!(
+                    "{} delay call failed with {}",
+                    time_nanos() / 1_000_000,
+                    err_code
+                ));
+                api::reply(&msg[..]);
+            }
+        }
+        _ => api::reply(&msg[..]),
+    }
+}
+
+/// Replies immediately. Called by "handle_request" to delay its reply.
+#[export_name = "canister_update noop"]
+fn noop() {
+    candid_reply(&());
+}
+
+/// Grows the heap of this canister by the given number of WebAssembly pages
+/// (64 KiB each), in addition to whatever it already holds, and fills every
+/// OS page of the new memory with a pseudo-random byte, such that the pages
+/// are part of the state (and differ from each other, for state sync).
+///
+/// Replies with the total number of pages allocated by "grow_state" so far.
+/// Traps if the memory cannot be allocated.
+#[export_name = "canister_update grow_state"]
+fn grow_state() {
+    let pages = candid::Decode!(&api::arg_data()[..], u64).expect("failed to decode pages");
+
+    let mut ballast = vec![0u8; pages as usize * WASM_PAGE_SIZE];
+    RNG.with(|rng| {
+        let mut rng = rng.borrow_mut();
+        for index in (0..ballast.len()).step_by(OS_PAGE_SIZE) {
+            ballast[index] = rng.gen();
+        }
     });
-    api::reply(&msg[..]);
+
+    let total_pages = BALLAST.with(|b| {
+        let mut b = b.borrow_mut();
+        b.push(ballast);
+        b.iter().map(|v| v.len() / WASM_PAGE_SIZE).sum::<usize>() as u64
+    });
+    candid_reply(&total_pages);
 }
 
 /// Deposits the cycles this canister has minus 1T according to the given
@@ -302,6 +302,8 @@ impl SnsInitPayload {
 
             transaction_fee_e8s: self.transaction_fee_e8s,
             neuron_minimum_stake_e8s: self.neuron_minimum_stake_e8s,
+            allow_canister_participants: None,
+            archive_canister_id: None,
         }
     }
 
@@ -65,6 +65,10 @@ lazy_static! {
             dissolve_delay_interval_seconds: 1,
         }),
         sale_delay_seconds: None,
+        early_participation_bonus: None,
+        max_direct_participants: None,
+        dutch_auction: None,
+        pro_rata_cutback: None,
     };
     pub static ref DEFAULT_ICRC1_ARCHIVE_OPTIONS: ArchiveOptions = ArchiveOptions {
         trigger_threshold: 1,
@@ -193,6 +197,8 @@ impl PaymentProtocolTestSetup {
                 .collect(),
             transaction_fee_e8s: Some(DEFAULT_TRANSFER_FEE.get_e8s()),
             neuron_minimum_stake_e8s: Some(*DEFAULT_NEURON_MINIMUM_STAKE),
+            allow_canister_participants: None,
+            archive_canister_id: None,
         }
     }
 
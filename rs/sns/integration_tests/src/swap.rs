@@ -506,6 +506,10 @@ fn begin_swap(
                     dissolve_delay_interval_seconds: 7890000, // 3 months,
                 }),
                 sale_delay_seconds: None,
+                early_participation_bonus: None,
+                max_direct_participants: None,
+                dutch_auction: None,
+                pro_rata_cutback: None,
             }),
             // This is not sufficient to make the swap an automatic success.
             community_fund_investment_e8s: Some(
@@ -2703,6 +2707,8 @@ fn test_upgrade() {
         fallback_controller_principal_ids: vec![Principal::anonymous().to_string()],
         transaction_fee_e8s: Some(10_000),
         neuron_minimum_stake_e8s: Some(1_000_000),
+        allow_canister_participants: None,
+        archive_canister_id: None,
     })
     .unwrap();
     let canister_id = state_machine
@@ -2809,11 +2815,16 @@ fn test_deletion_of_sale_ticket() {
         &sns_canister_ids.swap(),
         &TEST_USER1_PRINCIPAL,
     );
+    let refresh_response = refresh_response.unwrap();
+    let receipt = refresh_response.participation_increment.clone().unwrap();
+    assert_eq!(receipt.amount_icp_e8s, ticket.amount_icp_e8s);
+    assert_eq!(receipt.ticket_id, Some(ticket.ticket_id));
     assert_eq!(
-        refresh_response.unwrap(),
+        refresh_response,
         RefreshBuyerTokensResponse {
             icp_accepted_participation_e8s: ticket.amount_icp_e8s,
-            icp_ledger_account_balance_e8s: ticket.amount_icp_e8s
+            icp_ledger_account_balance_e8s: ticket.amount_icp_e8s,
+            participation_increment: Some(receipt),
         }
     );
 
@@ -2902,11 +2913,16 @@ fn test_deletion_of_sale_ticket() {
         &sns_canister_ids.swap(),
         &TEST_USER1_PRINCIPAL,
     );
+    let refresh_response = refresh_response.unwrap();
+    let receipt = refresh_response.participation_increment.clone().unwrap();
+    assert_eq!(receipt.amount_icp_e8s, ticket_new.amount_icp_e8s);
+    assert_eq!(receipt.ticket_id, Some(ticket_new.ticket_id));
     assert_eq!(
-        refresh_response.unwrap(),
+        refresh_response,
         RefreshBuyerTokensResponse {
             icp_accepted_participation_e8s: ticket.amount_icp_e8s + ticket_new.amount_icp_e8s,
-            icp_ledger_account_balance_e8s: ticket.amount_icp_e8s + ticket_new.amount_icp_e8s
+            icp_ledger_account_balance_e8s: ticket.amount_icp_e8s + ticket_new.amount_icp_e8s,
+            participation_increment: Some(receipt),
         }
     );
 
@@ -2945,13 +2961,18 @@ fn test_deletion_of_sale_ticket() {
         &sns_canister_ids.swap(),
         &TEST_USER1_PRINCIPAL,
     );
+    let refresh_response = refresh_response.unwrap();
+    let receipt = refresh_response.participation_increment.clone().unwrap();
+    assert_eq!(receipt.amount_icp_e8s, E8 * 5 / 4);
+    assert_eq!(receipt.ticket_id, None);
     assert_eq!(
-        refresh_response.unwrap(),
+        refresh_response,
         RefreshBuyerTokensResponse {
             icp_accepted_participation_e8s: ticket.amount_icp_e8s - 1
                 + ticket_new.amount_icp_e8s * 2,
             icp_ledger_account_balance_e8s: ticket.amount_icp_e8s - 1
-                + ticket_new.amount_icp_e8s * 2
+                + ticket_new.amount_icp_e8s * 2,
+            participation_increment: Some(receipt),
         }
     );
 }
@@ -3088,6 +3109,8 @@ fn test_last_man_less_than_min() {
         fallback_controller_principal_ids: vec![Principal::anonymous().to_string()],
         transaction_fee_e8s: Some(10_000),
         neuron_minimum_stake_e8s: Some(1_000_000),
+        allow_canister_participants: None,
+        archive_canister_id: None,
     })
     .unwrap();
     state_machine
@@ -3114,6 +3137,10 @@ fn test_last_man_less_than_min() {
                 dissolve_delay_interval_seconds: 1,
             }),
             sale_delay_seconds: None,
+            early_participation_bonus: None,
+            max_direct_participants: None,
+            dutch_auction: None,
+            pro_rata_cutback: None,
         }),
         cf_participants: vec![],
         open_sns_token_swap_proposal_id: Some(0),
@@ -3250,11 +3277,16 @@ fn test_refresh_buyer_token() {
             &TEST_USER1_PRINCIPAL,
         );
 
+        let refresh_response = refresh_response.unwrap();
+        let receipt = refresh_response.participation_increment.clone().unwrap();
+        assert_eq!(receipt.amount_icp_e8s, ticket.amount_icp_e8s);
+        assert_eq!(receipt.ticket_id, Some(ticket.ticket_id));
         assert_eq!(
-            refresh_response.unwrap(),
+            refresh_response,
             RefreshBuyerTokensResponse {
                 icp_accepted_participation_e8s: ticket.amount_icp_e8s,
-                icp_ledger_account_balance_e8s: ticket.amount_icp_e8s
+                icp_ledger_account_balance_e8s: ticket.amount_icp_e8s,
+                participation_increment: Some(receipt),
             }
         );
 
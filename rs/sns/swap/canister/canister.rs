@@ -15,24 +15,31 @@ use ic_sns_governance::ledger::LedgerCanister;
 use ic_sns_swap::{
     clients::{
         ManagementCanister, ProdManagementCanister, RealNnsGovernanceClient,
-        RealSnsGovernanceClient, RealSnsRootClient,
+        RealSnsGovernanceClient, RealSnsLedgerClient, RealSnsRootClient, RealSwapArchiveClient,
     },
     logs::{ERROR, INFO},
     memory::UPGRADES_MEMORY,
     pb::v1::{
-        ErrorRefundIcpRequest, ErrorRefundIcpResponse, FinalizeSwapRequest, FinalizeSwapResponse,
-        GetBuyerStateRequest, GetBuyerStateResponse, GetBuyersTotalRequest, GetBuyersTotalResponse,
-        GetCanisterStatusRequest, GetDerivedStateRequest, GetDerivedStateResponse, GetInitRequest,
-        GetInitResponse, GetLifecycleRequest, GetLifecycleResponse, GetOpenTicketRequest,
-        GetOpenTicketResponse, GetSaleParametersRequest, GetSaleParametersResponse,
-        GetStateRequest, GetStateResponse, Init, ListCommunityFundParticipantsRequest,
-        ListCommunityFundParticipantsResponse, ListDirectParticipantsRequest,
-        ListDirectParticipantsResponse, ListSnsNeuronRecipesRequest, ListSnsNeuronRecipesResponse,
+        ArchiveFinalizedStateRequest, ArchiveFinalizedStateResponse, ErrorRefundIcpRequest,
+        ErrorRefundIcpResponse, ExportStateRequest, ExportStateResponse, FinalizeSwapPhase,
+        FinalizeSwapPhaseRequest, FinalizeSwapRequest, FinalizeSwapResponse,
+        GetAuctionPriceRequest, GetAuctionPriceResponse, GetBuyerParticipationHistoryRequest,
+        GetBuyerParticipationHistoryResponse, GetBuyerStateRequest, GetBuyerStateResponse,
+        GetBuyersTotalRequest, GetBuyersTotalResponse, GetCanisterStatusRequest,
+        GetDerivedStateRequest, GetDerivedStateResponse, GetInitRequest, GetInitResponse,
+        GetLifecycleRequest, GetLifecycleResponse, GetOpenTicketRequest, GetOpenTicketResponse,
+        GetSaleParametersRequest, GetSaleParametersResponse, GetSaleStatusRequest,
+        GetSaleStatusResponse, GetStateChunkRequest, GetStateChunkResponse, GetStateRequest,
+        GetStateResponse, GetTransferMemoSchemeRequest, GetTransferMemoSchemeResponse, Init,
+        ListCommunityFundParticipantsRequest, ListCommunityFundParticipantsResponse,
+        ListDirectParticipantsRequest, ListDirectParticipantsResponse, ListSnsNeuronRecipesRequest,
+        ListSnsNeuronRecipesResponse, ListSwapRoundsRequest, ListSwapRoundsResponse,
         NewSaleTicketRequest, NewSaleTicketResponse, NotifyPaymentFailureRequest,
         NotifyPaymentFailureResponse, OpenRequest, OpenResponse, RefreshBuyerTokensRequest,
         RefreshBuyerTokensResponse, RestoreDappControllersRequest, RestoreDappControllersResponse,
         Swap,
     },
+    periodic_tasks::TaskRegistry,
 };
 use ic_stable_structures::{writer::Writer, Memory};
 use prost::Message;
@@ -100,6 +107,21 @@ fn get_buyer_state_(request: GetBuyerStateRequest) -> GetBuyerStateResponse {
     swap().get_buyer_state(&request)
 }
 
+/// Get the increments of the participation of a buyer, the caller by default.
+#[export_name = "canister_query get_buyer_participation_history"]
+fn get_buyer_participation_history() {
+    over(candid_one, get_buyer_participation_history_)
+}
+
+/// Get the increments of the participation of a buyer, the caller by default.
+#[candid_method(query, rename = "get_buyer_participation_history")]
+fn get_buyer_participation_history_(
+    request: GetBuyerParticipationHistoryRequest,
+) -> GetBuyerParticipationHistoryResponse {
+    log!(INFO, "get_buyer_participation_history");
+    swap().get_buyer_participation_history(&request, caller())
+}
+
 /// Get Params.
 #[export_name = "canister_query get_sale_parameters"]
 fn get_sale_parameters() {
@@ -147,11 +169,25 @@ async fn open_(req: OpenRequest) -> OpenResponse {
             allowed_canister
         );
     }
+    let mut nns_governance_client = RealNnsGovernanceClient::new(allowed_canister);
+    if let Err(msg) = req.validate_cf_neurons(&mut nns_governance_client).await {
+        panic!("{}", msg);
+    }
     let sns_ledger = create_real_icrc1_ledger(swap().init_or_panic().sns_ledger_or_panic());
-    match swap_mut().open(id(), &sns_ledger, now_seconds(), req).await {
+    let res = match swap_mut().open(id(), &sns_ledger, now_seconds(), req).await {
         Ok(res) => res,
         Err(msg) => panic!("{}", msg),
+    };
+    let mut sns_ledger_client =
+        RealSnsLedgerClient::new(swap().init_or_panic().sns_ledger_or_panic());
+    if let Err(msg) = swap_mut()
+        .check_sns_ledger_fee(&mut sns_ledger_client, now_seconds())
+        .await
+    {
+        // Retried before the swap is finalized.
+        log!(ERROR, "{}", msg);
     }
+    res
 }
 
 /// See `Swap.refresh_buyer_token_e8`.
@@ -171,7 +207,7 @@ async fn refresh_buyer_tokens_(arg: RefreshBuyerTokensRequest) -> RefreshBuyerTo
     };
     let icp_ledger = create_real_icp_ledger(swap().init_or_panic().icp_ledger_or_panic());
     match swap_mut()
-        .refresh_buyer_token_e8s(p, id(), &icp_ledger)
+        .refresh_buyer_token_e8s(p, id(), now_seconds(), &icp_ledger)
         .await
     {
         Ok(r) => r,
@@ -200,6 +236,16 @@ async fn finalize_swap_(_arg: FinalizeSwapRequest) -> FinalizeSwapResponse {
     let sns_ledger = create_real_icrc1_ledger(swap().init_or_panic().sns_ledger_or_panic());
     let mut nns_governance_client =
         RealNnsGovernanceClient::new(swap().init_or_panic().nns_governance_or_panic());
+    let mut sns_ledger_client =
+        RealSnsLedgerClient::new(swap().init_or_panic().sns_ledger_or_panic());
+
+    // Before any funds are moved.
+    if let Err(error_message) = swap_mut()
+        .check_sns_ledger_fee(&mut sns_ledger_client, now_seconds())
+        .await
+    {
+        return FinalizeSwapResponse::with_error(error_message);
+    }
 
     swap_mut()
         .finalize(
@@ -213,6 +259,77 @@ async fn finalize_swap_(_arg: FinalizeSwapRequest) -> FinalizeSwapResponse {
         .await
 }
 
+/// See Swap.finalize_phase.
+#[export_name = "canister_update finalize_swap_phase"]
+fn finalize_swap_phase() {
+    over_async(candid_one, finalize_swap_phase_)
+}
+
+/// See Swap.finalize_phase.
+#[candid_method(update, rename = "finalize_swap_phase")]
+async fn finalize_swap_phase_(request: FinalizeSwapPhaseRequest) -> FinalizeSwapResponse {
+    let phase =
+        FinalizeSwapPhase::from_i32(request.phase).unwrap_or(FinalizeSwapPhase::Unspecified);
+    log!(INFO, "finalize_swap_phase {:?}", phase);
+    let mut sns_root_client = RealSnsRootClient::new(swap().init_or_panic().sns_root_or_panic());
+    let mut sns_governance_client =
+        RealSnsGovernanceClient::new(swap().init_or_panic().sns_governance_or_panic());
+    let icp_ledger = create_real_icp_ledger(swap().init_or_panic().icp_ledger_or_panic());
+    let sns_ledger = create_real_icrc1_ledger(swap().init_or_panic().sns_ledger_or_panic());
+    let mut nns_governance_client =
+        RealNnsGovernanceClient::new(swap().init_or_panic().nns_governance_or_panic());
+    let mut sns_ledger_client =
+        RealSnsLedgerClient::new(swap().init_or_panic().sns_ledger_or_panic());
+
+    // Before any funds are moved.
+    if let Err(error_message) = swap_mut()
+        .check_sns_ledger_fee(&mut sns_ledger_client, now_seconds())
+        .await
+    {
+        return FinalizeSwapResponse::with_error(error_message);
+    }
+
+    swap_mut()
+        .finalize_phase(
+            phase,
+            now_fn,
+            &mut sns_root_client,
+            &mut sns_governance_client,
+            &icp_ledger,
+            &sns_ledger,
+            &mut nns_governance_client,
+        )
+        .await
+}
+
+/// See Swap.archive_finalized_state.
+#[export_name = "canister_update archive_finalized_state"]
+fn archive_finalized_state() {
+    over_async(candid_one, archive_finalized_state_)
+}
+
+/// See Swap.archive_finalized_state.
+#[candid_method(update, rename = "archive_finalized_state")]
+async fn archive_finalized_state_(
+    _arg: ArchiveFinalizedStateRequest,
+) -> ArchiveFinalizedStateResponse {
+    log!(INFO, "archive_finalized_state");
+    let archive_canister_id = match swap().init_or_panic().archive_canister() {
+        Ok(archive_canister_id) => archive_canister_id,
+        Err(err) => {
+            return ArchiveFinalizedStateResponse {
+                archive: swap().archive.clone(),
+                error_message: Some(format!("Cannot archive the finalized swap: {}", err)),
+            }
+        }
+    };
+    let mut archive_client = RealSwapArchiveClient::new(archive_canister_id);
+
+    swap_mut()
+        .archive_finalized_state(now_seconds(), archive_canister_id, &mut archive_client)
+        .await
+}
+
 #[export_name = "canister_update error_refund_icp"]
 fn error_refund_icp() {
     over_async(candid_one, error_refund_icp_)
@@ -271,7 +388,7 @@ async fn restore_dapp_controllers_(
     log!(INFO, "restore_dapp_controllers");
     let mut sns_root_client = RealSnsRootClient::new(swap().init_or_panic().sns_root_or_panic());
     swap_mut()
-        .restore_dapp_controllers(&mut sns_root_client, caller())
+        .restore_dapp_controllers(&mut sns_root_client, caller(), now_seconds())
         .await
 }
 
@@ -315,6 +432,32 @@ async fn get_derived_state_(_request: GetDerivedStateRequest) -> GetDerivedState
     swap().derived_state().into()
 }
 
+/// Return the current price of the dutch auction of the Sale
+#[export_name = "canister_query get_auction_price"]
+fn get_auction_price() {
+    over(candid_one, get_auction_price_)
+}
+
+/// Return the current price of the dutch auction of the Sale
+#[candid_method(query, rename = "get_auction_price")]
+fn get_auction_price_(request: GetAuctionPriceRequest) -> GetAuctionPriceResponse {
+    log!(INFO, "get_auction_price");
+    swap().get_auction_price(&request, now_seconds())
+}
+
+/// Return the remaining capacity of the Sale and whether it closes
+#[export_name = "canister_query get_sale_status"]
+fn get_sale_status() {
+    over(candid_one, get_sale_status_)
+}
+
+/// Return the remaining capacity of the Sale and whether it closes
+#[candid_method(query, rename = "get_sale_status")]
+fn get_sale_status_(request: GetSaleStatusRequest) -> GetSaleStatusResponse {
+    log!(INFO, "get_sale_status");
+    swap().get_sale_status(&request, now_seconds())
+}
+
 #[export_name = "canister_query get_open_ticket"]
 fn get_open_ticket() {
     over_async(candid_one, get_open_ticket_)
@@ -363,6 +506,66 @@ fn list_sns_neuron_recipes_(request: ListSnsNeuronRecipesRequest) -> ListSnsNeur
     swap().list_sns_neuron_recipes(request)
 }
 
+/// Lists the summaries of the rounds of the swap, the current one last.
+#[export_name = "canister_query list_swap_rounds"]
+fn list_swap_rounds() {
+    over(candid_one, list_swap_rounds_)
+}
+
+/// Lists the summaries of the rounds of the swap, the current one last.
+#[candid_method(query, rename = "list_swap_rounds")]
+fn list_swap_rounds_(request: ListSwapRoundsRequest) -> ListSwapRoundsResponse {
+    log!(INFO, "list_swap_rounds");
+    swap().list_swap_rounds(&request)
+}
+
+/// Exports the state relevant to audits, paging over its buyers, Community
+/// Fund participants and neuron recipes.
+#[export_name = "canister_query export_state"]
+fn export_state() {
+    over(candid_one, export_state_)
+}
+
+/// Exports the state relevant to audits, paging over its buyers, Community
+/// Fund participants and neuron recipes.
+#[candid_method(query, rename = "export_state")]
+fn export_state_(request: ExportStateRequest) -> ExportStateResponse {
+    log!(INFO, "export_state");
+    swap().export_state(request)
+}
+
+/// Returns a chunk of the protobuf encoding of the full state export, along
+/// with the SHA-256 of the whole encoding to verify the reassembled chunks.
+#[export_name = "canister_query get_state_chunk"]
+fn get_state_chunk() {
+    over(candid_one, get_state_chunk_)
+}
+
+/// Returns a chunk of the protobuf encoding of the full state export, along
+/// with the SHA-256 of the whole encoding to verify the reassembled chunks.
+#[candid_method(query, rename = "get_state_chunk")]
+fn get_state_chunk_(request: GetStateChunkRequest) -> GetStateChunkResponse {
+    log!(INFO, "get_state_chunk");
+    swap().get_state_chunk(request)
+}
+
+/// Describes how the memos of the ledger transfers initiated by the swap
+/// canister are composed, so that they can be labeled.
+#[export_name = "canister_query get_transfer_memo_scheme"]
+fn get_transfer_memo_scheme() {
+    over(candid_one, get_transfer_memo_scheme_)
+}
+
+/// Describes how the memos of the ledger transfers initiated by the swap
+/// canister are composed, so that they can be labeled.
+#[candid_method(query, rename = "get_transfer_memo_scheme")]
+fn get_transfer_memo_scheme_(
+    _request: GetTransferMemoSchemeRequest,
+) -> GetTransferMemoSchemeResponse {
+    log!(INFO, "get_transfer_memo_scheme");
+    swap().get_transfer_memo_scheme()
+}
+
 #[export_name = "canister_update notify_payment_failure"]
 fn notify_payment_failure() {
     over(candid_one, notify_payment_failure_)
@@ -381,23 +584,7 @@ fn notify_payment_failure_(_request: NotifyPaymentFailureRequest) -> NotifyPayme
 /// Tries to commit or abort the swap if the parameters have been satisfied.
 #[export_name = "canister_heartbeat"]
 fn canister_heartbeat() {
-    const NUMBER_OF_TICKETS_THRESHOLD: u64 = 100_000_000; // 100M * ~size(ticket) = ~25GB
-    const TWO_DAYS_IN_NANOSECONDS: u64 = 60 * 60 * 24 * 2 * 1_000_000_000;
-    const MAX_NUMBER_OF_PRINCIPALS_TO_INSPECT: u64 = 100_000;
-
-    swap_mut().try_purge_old_tickets(
-        dfn_core::api::time_nanos,
-        NUMBER_OF_TICKETS_THRESHOLD,
-        TWO_DAYS_IN_NANOSECONDS,
-        MAX_NUMBER_OF_PRINCIPALS_TO_INSPECT,
-    );
-    let now = now_seconds();
-    if swap_mut().try_open_after_delay(now) {
-        log!(INFO, "Sale opened at timestamp {}", now);
-    }
-    if swap_mut().try_commit_or_abort(now) {
-        log!(INFO, "Swap committed/aborted at timestamp {}", now);
-    }
+    TaskRegistry::swap_tasks().run_due_tasks(swap_mut(), dfn_core::api::time_nanos());
 }
 
 fn now_seconds() -> u64 {
@@ -620,6 +807,24 @@ fn encode_metrics(w: &mut ic_metrics_encoder::MetricsEncoder<Vec<u8>>) -> std::i
         "The total amount of ICP contributed by the Community Fund",
     )?;
 
+    let periodic_tasks = &swap().periodic_tasks;
+    let mut errors = w.counter_vec(
+        "sale_periodic_task_errors",
+        "The number of failed runs of each periodic task.",
+    )?;
+    for (task, state) in periodic_tasks {
+        errors = errors.value(&[("task", task)], state.error_count as f64)?;
+    }
+    let mut last_runs = w.gauge_vec(
+        "sale_periodic_task_last_run_timestamp_seconds",
+        "The last time each periodic task was run.",
+    )?;
+    for (task, state) in periodic_tasks {
+        if let Some(last_run) = state.last_run_timestamp_seconds {
+            last_runs = last_runs.value(&[("task", task)], last_run as f64)?;
+        }
+    }
+
     Ok(())
 }
 
@@ -0,0 +1,426 @@
+//! A typed client of the SNS swap canister, for off-chain tooling (launch
+//! scripts, dashboards) and tests, so that they don't need to encode the
+//! calls to the canister by hand.
+//!
+//! The client is independent of the agent making the calls: implement
+//! [Runtime] in terms of the agent at hand.
+
+use async_trait::async_trait;
+use candid::utils::{ArgumentDecoder, ArgumentEncoder};
+use candid::Principal;
+use ic_ic00_types::CanisterStatusResultV2;
+use ic_sns_swap::pb::v1::{
+    CfParticipant, ErrorRefundIcpRequest, ErrorRefundIcpResponse, ExportStateRequest,
+    ExportStateResponse, FinalizeSwapPhase, FinalizeSwapPhaseRequest, FinalizeSwapRequest,
+    FinalizeSwapResponse, GetAuctionPriceRequest, GetAuctionPriceResponse,
+    GetBuyerParticipationHistoryRequest, GetBuyerParticipationHistoryResponse,
+    GetBuyerStateRequest, GetBuyerStateResponse, GetBuyersTotalRequest, GetBuyersTotalResponse,
+    GetCanisterStatusRequest, GetDerivedStateRequest, GetDerivedStateResponse, GetInitRequest,
+    GetInitResponse, GetLifecycleRequest, GetLifecycleResponse, GetOpenTicketRequest,
+    GetOpenTicketResponse, GetSaleParametersRequest, GetSaleParametersResponse,
+    GetSaleStatusRequest, GetSaleStatusResponse, GetStateChunkRequest, GetStateChunkResponse,
+    GetStateRequest, GetStateResponse, GetTransferMemoSchemeRequest, GetTransferMemoSchemeResponse,
+    ListCommunityFundParticipantsRequest, ListCommunityFundParticipantsResponse,
+    ListDirectParticipantsRequest, ListDirectParticipantsResponse, ListSnsNeuronRecipesRequest,
+    ListSnsNeuronRecipesResponse, NewSaleTicketRequest, NewSaleTicketResponse,
+    NotifyPaymentFailureRequest, NotifyPaymentFailureResponse, OpenRequest, OpenResponse,
+    Participant, RefreshBuyerTokensRequest, RefreshBuyerTokensResponse,
+    RestoreDappControllersRequest, RestoreDappControllersResponse, SnsNeuronRecipe,
+    SwapStateExport,
+};
+use prost::Message;
+use std::future::Future;
+
+/// The reject code and message of a failed call.
+pub type CallError = (i32, String);
+
+/// The reject code of transient errors, after which a call may succeed if
+/// retried. Runtimes should report errors of the transport with it, so that
+/// queries failing with them are retried.
+pub const SYS_TRANSIENT: i32 = 2;
+
+/// The number of attempts of a query, unless set otherwise.
+pub const DEFAULT_MAX_QUERY_ATTEMPTS: usize = 3;
+
+/// The page size of the pagination helpers, unless set otherwise.
+pub const DEFAULT_PAGE_SIZE: u32 = 1_000;
+
+// Abstraction over the agent making the calls, like the `Runtime` of the
+// ICRC-1 client, but distinguishing queries from updates.
+#[async_trait]
+pub trait Runtime {
+    async fn query<In, Out>(&self, id: Principal, method: &str, args: In) -> Result<Out, CallError>
+    where
+        In: ArgumentEncoder + Send,
+        Out: for<'a> ArgumentDecoder<'a>;
+
+    async fn update<In, Out>(
+        &self,
+        id: Principal,
+        method: &str,
+        args: In,
+    ) -> Result<Out, CallError>
+    where
+        In: ArgumentEncoder + Send,
+        Out: for<'a> ArgumentDecoder<'a>;
+}
+
+/// The failure to download the state of the swap with
+/// [SwapClient::get_state_export].
+#[derive(Debug, PartialEq, Eq)]
+pub enum StateDownloadError {
+    Call(CallError),
+    /// The state changed while it was downloaded, in every attempt.
+    Changed,
+    /// The reassembled chunks don't decode to a state export.
+    Decode(String),
+}
+
+impl From<CallError> for StateDownloadError {
+    fn from(err: CallError) -> Self {
+        StateDownloadError::Call(err)
+    }
+}
+
+pub struct SwapClient<R: Runtime> {
+    pub runtime: R,
+    pub swap_canister_id: Principal,
+    /// How often a query, or a read-only update, failing with
+    /// [SYS_TRANSIENT] is attempted. Other updates are never retried, as they
+    /// may have been executed.
+    pub max_query_attempts: usize,
+    /// The number of elements requested per call by the pagination helpers.
+    /// The swap canister may return fewer.
+    pub page_size: u32,
+}
+
+impl<R: Runtime> SwapClient<R> {
+    pub fn new(runtime: R, swap_canister_id: Principal) -> Self {
+        Self {
+            runtime,
+            swap_canister_id,
+            max_query_attempts: DEFAULT_MAX_QUERY_ATTEMPTS,
+            page_size: DEFAULT_PAGE_SIZE,
+        }
+    }
+
+    async fn query<In, Out>(&self, method: &str, request: In) -> Result<Out, CallError>
+    where
+        In: candid::CandidType + Clone + Send,
+        Out: for<'a> candid::Deserialize<'a> + candid::CandidType,
+    {
+        let mut attempt = 1;
+        loop {
+            let result = self
+                .runtime
+                .query(self.swap_canister_id, method, (request.clone(),))
+                .await
+                .map(untuple);
+            match result {
+                Err((SYS_TRANSIENT, _)) if attempt < self.max_query_attempts => attempt += 1,
+                result => return result,
+            }
+        }
+    }
+
+    async fn update<In, Out>(&self, method: &str, request: In) -> Result<Out, CallError>
+    where
+        In: candid::CandidType + Send,
+        Out: for<'a> candid::Deserialize<'a> + candid::CandidType,
+    {
+        self.runtime
+            .update(self.swap_canister_id, method, (request,))
+            .await
+            .map(untuple)
+    }
+
+    // Queries.
+
+    pub async fn get_state(&self) -> Result<GetStateResponse, CallError> {
+        self.query("get_state", GetStateRequest {}).await
+    }
+
+    pub async fn get_buyer_state(
+        &self,
+        request: GetBuyerStateRequest,
+    ) -> Result<GetBuyerStateResponse, CallError> {
+        self.query("get_buyer_state", request).await
+    }
+
+    /// The increments of the participation of a buyer, the caller by default.
+    pub async fn get_buyer_participation_history(
+        &self,
+        request: GetBuyerParticipationHistoryRequest,
+    ) -> Result<GetBuyerParticipationHistoryResponse, CallError> {
+        self.query("get_buyer_participation_history", request).await
+    }
+
+    pub async fn get_sale_parameters(&self) -> Result<GetSaleParametersResponse, CallError> {
+        self.query("get_sale_parameters", GetSaleParametersRequest {})
+            .await
+    }
+
+    pub async fn get_lifecycle(&self) -> Result<GetLifecycleResponse, CallError> {
+        self.query("get_lifecycle", GetLifecycleRequest {}).await
+    }
+
+    pub async fn get_init(&self) -> Result<GetInitResponse, CallError> {
+        self.query("get_init", GetInitRequest {}).await
+    }
+
+    pub async fn get_derived_state(&self) -> Result<GetDerivedStateResponse, CallError> {
+        self.query("get_derived_state", GetDerivedStateRequest {})
+            .await
+    }
+
+    pub async fn get_auction_price(&self) -> Result<GetAuctionPriceResponse, CallError> {
+        self.query("get_auction_price", GetAuctionPriceRequest {})
+            .await
+    }
+
+    /// The remaining capacity of the swap and whether it commits or aborts.
+    pub async fn get_sale_status(&self) -> Result<GetSaleStatusResponse, CallError> {
+        self.query("get_sale_status", GetSaleStatusRequest {}).await
+    }
+
+    /// The open ticket of the caller.
+    pub async fn get_open_ticket(&self) -> Result<GetOpenTicketResponse, CallError> {
+        self.query("get_open_ticket", GetOpenTicketRequest {}).await
+    }
+
+    pub async fn get_transfer_memo_scheme(
+        &self,
+    ) -> Result<GetTransferMemoSchemeResponse, CallError> {
+        self.query("get_transfer_memo_scheme", GetTransferMemoSchemeRequest {})
+            .await
+    }
+
+    pub async fn get_state_chunk(
+        &self,
+        request: GetStateChunkRequest,
+    ) -> Result<GetStateChunkResponse, CallError> {
+        self.query("get_state_chunk", request).await
+    }
+
+    // Read-only updates, retried like queries.
+
+    pub async fn get_buyers_total(&self) -> Result<GetBuyersTotalResponse, CallError> {
+        self.retried_update("get_buyers_total", GetBuyersTotalRequest {})
+            .await
+    }
+
+    pub async fn get_canister_status(&self) -> Result<CanisterStatusResultV2, CallError> {
+        self.retried_update("get_canister_status", GetCanisterStatusRequest {})
+            .await
+    }
+
+    async fn retried_update<In, Out>(&self, method: &str, request: In) -> Result<Out, CallError>
+    where
+        In: candid::CandidType + Clone + Send,
+        Out: for<'a> candid::Deserialize<'a> + candid::CandidType,
+    {
+        let mut attempt = 1;
+        loop {
+            match self.update(method, request.clone()).await {
+                Err((SYS_TRANSIENT, _)) if attempt < self.max_query_attempts => attempt += 1,
+                result => return result,
+            }
+        }
+    }
+
+    // Updates.
+
+    pub async fn open(&self, request: OpenRequest) -> Result<OpenResponse, CallError> {
+        self.update("open", request).await
+    }
+
+    pub async fn refresh_buyer_tokens(
+        &self,
+        request: RefreshBuyerTokensRequest,
+    ) -> Result<RefreshBuyerTokensResponse, CallError> {
+        self.update("refresh_buyer_tokens", request).await
+    }
+
+    pub async fn new_sale_ticket(
+        &self,
+        request: NewSaleTicketRequest,
+    ) -> Result<NewSaleTicketResponse, CallError> {
+        self.update("new_sale_ticket", request).await
+    }
+
+    /// Deletes the open ticket of the caller.
+    pub async fn notify_payment_failure(&self) -> Result<NotifyPaymentFailureResponse, CallError> {
+        self.update("notify_payment_failure", NotifyPaymentFailureRequest {})
+            .await
+    }
+
+    pub async fn finalize_swap(&self) -> Result<FinalizeSwapResponse, CallError> {
+        self.update("finalize_swap", FinalizeSwapRequest {}).await
+    }
+
+    /// Runs a single phase of the finalization, e.g. to retry the phase a
+    /// finalization is stuck at.
+    pub async fn finalize_swap_phase(
+        &self,
+        phase: FinalizeSwapPhase,
+    ) -> Result<FinalizeSwapResponse, CallError> {
+        self.update(
+            "finalize_swap_phase",
+            FinalizeSwapPhaseRequest {
+                phase: phase as i32,
+            },
+        )
+        .await
+    }
+
+    pub async fn error_refund_icp(
+        &self,
+        request: ErrorRefundIcpRequest,
+    ) -> Result<ErrorRefundIcpResponse, CallError> {
+        self.update("error_refund_icp", request).await
+    }
+
+    pub async fn restore_dapp_controllers(
+        &self,
+    ) -> Result<RestoreDappControllersResponse, CallError> {
+        self.update("restore_dapp_controllers", RestoreDappControllersRequest {})
+            .await
+    }
+
+    // Pagination helpers.
+
+    /// All direct participants, fetched in pages of [Self::page_size].
+    pub async fn list_all_direct_participants(&self) -> Result<Vec<Participant>, CallError> {
+        paginate(self.page_size, |offset, limit| async move {
+            let request = ListDirectParticipantsRequest {
+                offset: Some(offset as u32),
+                limit: Some(limit),
+            };
+            self.query("list_direct_participants", request)
+                .await
+                .map(|response: ListDirectParticipantsResponse| response.participants)
+        })
+        .await
+    }
+
+    /// All community fund participants, fetched in pages of
+    /// [Self::page_size].
+    pub async fn list_all_community_fund_participants(
+        &self,
+    ) -> Result<Vec<CfParticipant>, CallError> {
+        paginate(self.page_size, |offset, limit| async move {
+            let request = ListCommunityFundParticipantsRequest {
+                offset: Some(offset),
+                limit: Some(limit),
+            };
+            self.query("list_community_fund_participants", request)
+                .await
+                .map(|response: ListCommunityFundParticipantsResponse| response.cf_participants)
+        })
+        .await
+    }
+
+    /// All SNS neuron recipes, fetched in pages of [Self::page_size].
+    pub async fn list_all_sns_neuron_recipes(&self) -> Result<Vec<SnsNeuronRecipe>, CallError> {
+        paginate(self.page_size, |offset, limit| async move {
+            let request = ListSnsNeuronRecipesRequest {
+                offset: Some(offset),
+                limit: Some(limit),
+            };
+            self.query("list_sns_neuron_recipes", request)
+                .await
+                .map(|response: ListSnsNeuronRecipesResponse| response.sns_neuron_recipes)
+        })
+        .await
+    }
+
+    /// The full state export, with the elements of all pages of
+    /// `export_state`. The pages aren't guaranteed to be consistent with each
+    /// other if the state changes in between, use [Self::get_state_export]
+    /// for a consistent snapshot.
+    pub async fn export_full_state(&self) -> Result<SwapStateExport, CallError> {
+        let mut offset = 0;
+        let mut export: Option<SwapStateExport> = None;
+        loop {
+            let request = ExportStateRequest {
+                offset: Some(offset),
+                limit: Some(self.page_size),
+            };
+            let response: ExportStateResponse = self.query("export_state", request).await?;
+            if let Some(page) = response.state {
+                match export.as_mut() {
+                    None => export = Some(page),
+                    Some(export) => {
+                        export.buyers.extend(page.buyers);
+                        export.cf_participants.extend(page.cf_participants);
+                        export.neuron_recipes.extend(page.neuron_recipes);
+                    }
+                }
+            }
+            match response.next_offset {
+                Some(next_offset) if next_offset > offset => offset = next_offset,
+                _ => return Ok(export.unwrap_or_default()),
+            }
+        }
+    }
+
+    /// A consistent snapshot of the full state export, reassembled from the
+    /// chunks of `get_state_chunk`. The download is restarted if the state
+    /// changes in between, up to [Self::max_query_attempts] times.
+    pub async fn get_state_export(&self) -> Result<SwapStateExport, StateDownloadError> {
+        for _ in 0..self.max_query_attempts.max(1) {
+            let first = self
+                .get_state_chunk(GetStateChunkRequest {
+                    offset: 0,
+                    length: None,
+                })
+                .await?;
+            let mut encoded = first.chunk;
+            let mut changed = false;
+            while (encoded.len() as u64) < first.total_size_bytes {
+                let next = self
+                    .get_state_chunk(GetStateChunkRequest {
+                        offset: encoded.len() as u64,
+                        length: None,
+                    })
+                    .await?;
+                if next.sha256_hex != first.sha256_hex || next.chunk.is_empty() {
+                    changed = true;
+                    break;
+                }
+                encoded.extend(next.chunk);
+            }
+            if !changed {
+                return SwapStateExport::decode(encoded.as_slice())
+                    .map_err(|err| StateDownloadError::Decode(err.to_string()));
+            }
+        }
+        Err(StateDownloadError::Changed)
+    }
+}
+
+/// Collects the pages returned by `fetch` for the offset of the next element
+/// and `page_size`, until a page is empty. A page shorter than `page_size`
+/// isn't the last one in general, as the canister caps the page size.
+async fn paginate<T, F, Fut>(page_size: u32, mut fetch: F) -> Result<Vec<T>, CallError>
+where
+    F: FnMut(u64, u32) -> Fut,
+    Fut: Future<Output = Result<Vec<T>, CallError>>,
+{
+    let mut elements = Vec::new();
+    loop {
+        let page = fetch(elements.len() as u64, page_size).await?;
+        if page.is_empty() {
+            return Ok(elements);
+        }
+        elements.extend(page);
+    }
+}
+
+// extract the element from an unary tuple
+fn untuple<T>(t: (T,)) -> T {
+    t.0
+}
+
+#[cfg(test)]
+mod tests;
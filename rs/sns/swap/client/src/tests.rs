@@ -0,0 +1,187 @@
+use super::*;
+use candid::{decode_args, decode_one, encode_args, encode_one};
+use ic_base_types::PrincipalId;
+use std::sync::Mutex;
+
+/// Serves the direct participants and the state chunks of a swap, capping
+/// pages at `max_page_size`, after failing the first `transient_failures`
+/// calls. If `changing_state` is set, the hash of the state differs in
+/// consecutive calls.
+#[derive(Default)]
+struct FakeSwap {
+    participants: Vec<Participant>,
+    max_page_size: u32,
+    state_chunks: Vec<GetStateChunkResponse>,
+    chunk_size: usize,
+    changing_state: bool,
+    transient_failures: Mutex<usize>,
+    calls: Mutex<Vec<String>>,
+}
+
+impl FakeSwap {
+    fn respond(&self, method: &str, args: &[u8]) -> Result<Vec<u8>, CallError> {
+        self.calls.lock().unwrap().push(method.to_string());
+        {
+            let mut transient_failures = self.transient_failures.lock().unwrap();
+            if *transient_failures > 0 {
+                *transient_failures -= 1;
+                return Err((SYS_TRANSIENT, "replica unavailable".to_string()));
+            }
+        }
+        let response = match method {
+            "list_direct_participants" => {
+                let request: ListDirectParticipantsRequest = decode_one(args).unwrap();
+                let offset = request.offset.unwrap() as usize;
+                let limit = request.limit.unwrap().min(self.max_page_size) as usize;
+                encode_one(ListDirectParticipantsResponse {
+                    participants: self
+                        .participants
+                        .iter()
+                        .skip(offset)
+                        .take(limit)
+                        .cloned()
+                        .collect(),
+                })
+            }
+            "get_state_chunk" => {
+                let request: GetStateChunkRequest = decode_one(args).unwrap();
+                let mut chunk =
+                    self.state_chunks[request.offset as usize / self.chunk_size].clone();
+                if self.changing_state {
+                    chunk.sha256_hex += &self.calls.lock().unwrap().len().to_string();
+                }
+                encode_one(chunk)
+            }
+            "finalize_swap" => encode_one(FinalizeSwapResponse::default()),
+            _ => return Err((3, format!("no method {}", method))),
+        };
+        Ok(response.unwrap())
+    }
+}
+
+#[async_trait]
+impl Runtime for FakeSwap {
+    async fn query<In, Out>(&self, _id: Principal, method: &str, args: In) -> Result<Out, CallError>
+    where
+        In: ArgumentEncoder + Send,
+        Out: for<'a> ArgumentDecoder<'a>,
+    {
+        let response = self.respond(method, &encode_args(args).unwrap())?;
+        Ok(decode_args(&response).unwrap())
+    }
+
+    async fn update<In, Out>(&self, id: Principal, method: &str, args: In) -> Result<Out, CallError>
+    where
+        In: ArgumentEncoder + Send,
+        Out: for<'a> ArgumentDecoder<'a>,
+    {
+        self.query(id, method, args).await
+    }
+}
+
+fn client(swap: FakeSwap) -> SwapClient<FakeSwap> {
+    SwapClient::new(swap, Principal::anonymous())
+}
+
+fn participants(count: u64) -> Vec<Participant> {
+    (0..count)
+        .map(|i| Participant {
+            participant_id: Some(PrincipalId::new_user_test_id(i)),
+            participation: None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn retries_transient_query_failures() {
+    let client = client(FakeSwap {
+        participants: participants(3),
+        max_page_size: 10,
+        transient_failures: Mutex::new(DEFAULT_MAX_QUERY_ATTEMPTS - 1),
+        ..Default::default()
+    });
+    assert_eq!(
+        client.list_all_direct_participants().await,
+        Ok(participants(3))
+    );
+
+    *client.runtime.transient_failures.lock().unwrap() = DEFAULT_MAX_QUERY_ATTEMPTS;
+    assert_eq!(
+        client.list_all_direct_participants().await.unwrap_err().0,
+        SYS_TRANSIENT
+    );
+}
+
+#[tokio::test]
+async fn does_not_retry_updates() {
+    let client = client(FakeSwap {
+        transient_failures: Mutex::new(1),
+        ..Default::default()
+    });
+    assert!(client.finalize_swap().await.is_err());
+    assert_eq!(*client.runtime.calls.lock().unwrap(), vec!["finalize_swap"]);
+}
+
+#[tokio::test]
+async fn paginates_beyond_the_page_size_cap_of_the_canister() {
+    let mut client = client(FakeSwap {
+        participants: participants(25),
+        max_page_size: 10,
+        ..Default::default()
+    });
+    client.page_size = 20;
+    assert_eq!(
+        client.list_all_direct_participants().await,
+        Ok(participants(25))
+    );
+    // Three pages and an empty one.
+    assert_eq!(client.runtime.calls.lock().unwrap().len(), 4);
+}
+
+fn chunks(state: &SwapStateExport, chunk_size: usize) -> Vec<GetStateChunkResponse> {
+    let encoded = state.encode_to_vec();
+    encoded
+        .chunks(chunk_size)
+        .map(|chunk| GetStateChunkResponse {
+            chunk: chunk.to_vec(),
+            total_size_bytes: encoded.len() as u64,
+            sha256_hex: "hash".to_string(),
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn reassembles_the_state_chunks() {
+    let state = SwapStateExport {
+        buyers: participants(10),
+        ..Default::default()
+    };
+    let client = client(FakeSwap {
+        state_chunks: chunks(&state, 50),
+        chunk_size: 50,
+        ..Default::default()
+    });
+    assert_eq!(client.get_state_export().await, Ok(state));
+}
+
+#[tokio::test]
+async fn fails_if_the_state_keeps_changing() {
+    let state = SwapStateExport {
+        buyers: participants(10),
+        ..Default::default()
+    };
+    let client = client(FakeSwap {
+        state_chunks: chunks(&state, 50),
+        chunk_size: 50,
+        changing_state: true,
+        ..Default::default()
+    });
+    assert_eq!(
+        client.get_state_export().await,
+        Err(StateDownloadError::Changed)
+    );
+    assert_eq!(
+        client.runtime.calls.lock().unwrap().len(),
+        2 * DEFAULT_MAX_QUERY_ATTEMPTS
+    );
+}
@@ -1,8 +1,10 @@
 use crate::pb::v1::{
-    CanisterCallError, GovernanceError, SetDappControllersRequest, SetDappControllersResponse,
+    ArchiveSwapRecordsRequest, ArchiveSwapRecordsResponse, CanisterCallError, GovernanceError,
+    ListNeurons, ListNeuronsResponse, SetDappControllersRequest, SetDappControllersResponse,
     SettleCommunityFundParticipation,
 };
 use async_trait::async_trait;
+use candid::Nat;
 use ic_base_types::CanisterId;
 use ic_ic00_types::CanisterStatusResultV2;
 use ic_sns_governance::pb::v1::{
@@ -121,6 +123,11 @@ pub trait NnsGovernanceClient {
         &mut self,
         request: SettleCommunityFundParticipation,
     ) -> Result<Result<(), GovernanceError>, CanisterCallError>;
+
+    async fn list_neurons(
+        &mut self,
+        request: ListNeurons,
+    ) -> Result<ListNeuronsResponse, CanisterCallError>;
 }
 
 pub struct RealNnsGovernanceClient {
@@ -148,6 +155,86 @@ impl NnsGovernanceClient for RealNnsGovernanceClient {
         .await
         .map_err(CanisterCallError::from)
     }
+
+    async fn list_neurons(
+        &mut self,
+        request: ListNeurons,
+    ) -> Result<ListNeuronsResponse, CanisterCallError> {
+        dfn_core::api::call(
+            self.canister_id,
+            "list_neurons",
+            dfn_candid::candid_one,
+            request,
+        )
+        .await
+        .map_err(CanisterCallError::from)
+    }
+}
+
+#[async_trait]
+pub trait SnsLedgerClient {
+    /// Returns the transaction fee of the ledger, in e8s.
+    async fn transfer_fee(&mut self) -> Result<u64, CanisterCallError>;
+}
+
+pub struct RealSnsLedgerClient {
+    canister_id: CanisterId,
+}
+
+impl RealSnsLedgerClient {
+    pub fn new(canister_id: CanisterId) -> Self {
+        Self { canister_id }
+    }
+}
+
+#[async_trait]
+impl SnsLedgerClient for RealSnsLedgerClient {
+    async fn transfer_fee(&mut self) -> Result<u64, CanisterCallError> {
+        let fee: Nat = dfn_core::api::call(self.canister_id, "icrc1_fee", dfn_candid::candid, ())
+            .await
+            .map_err(CanisterCallError::from)?;
+        u64::try_from(&fee.0).map_err(|_| CanisterCallError {
+            code: None,
+            description: format!("The fee {} does not fit into u64", fee),
+        })
+    }
+}
+
+/// The canister the records of a finalized swap are archived to, see
+/// `Swap::archive_finalized_state`.
+#[async_trait]
+pub trait SwapArchiveClient {
+    async fn archive_swap_records(
+        &mut self,
+        request: ArchiveSwapRecordsRequest,
+    ) -> Result<ArchiveSwapRecordsResponse, CanisterCallError>;
+}
+
+pub struct RealSwapArchiveClient {
+    canister_id: CanisterId,
+}
+
+impl RealSwapArchiveClient {
+    pub fn new(canister_id: CanisterId) -> Self {
+        Self { canister_id }
+    }
+}
+
+#[async_trait]
+impl SwapArchiveClient for RealSwapArchiveClient {
+    async fn archive_swap_records(
+        &mut self,
+        request: ArchiveSwapRecordsRequest,
+    ) -> Result<ArchiveSwapRecordsResponse, CanisterCallError> {
+        dfn_core::api::call(
+            self.canister_id,
+            "archive_swap_records",
+            dfn_candid::candid_one,
+            request,
+        )
+        .await
+        .map_err(CanisterCallError::from)
+    }
 }
 
 /// A trait that wraps calls to the IC's Management Canister. More details on the management
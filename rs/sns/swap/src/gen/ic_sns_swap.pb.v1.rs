@@ -202,6 +202,179 @@ pub struct Swap {
     /// running purge_old_tickets routine.
     #[prost(bytes = "vec", optional, tag = "14")]
     pub purge_old_tickets_next_principal: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
+    /// The transitions of the lifecycle, in the order they happened. Only
+    /// transitions made since this field was introduced are recorded.
+    #[prost(message, repeated, tag = "15")]
+    pub lifecycle_history: ::prost::alloc::vec::Vec<LifecycleTransition>,
+    /// Set if the configuration of the swap is inconsistent with the canisters
+    /// it interacts with, e.g., if the transaction fee of the SNS ledger differs
+    /// from `init.transaction_fee_e8s`. The swap is aborted when the error is
+    /// detected, such that `finalize_swap` refunds the participants. While set,
+    /// `finalize_swap` does not distribute SNS tokens, as the neurons of the
+    /// participants would be under-funded.
+    #[prost(string, optional, tag = "16")]
+    pub configuration_error: ::core::option::Option<::prost::alloc::string::String>,
+    /// The bookkeeping of the periodic tasks run by the heartbeat, by the name
+    /// of the task. See `TaskRegistry`.
+    #[prost(btree_map = "string, message", tag = "17")]
+    pub periodic_tasks:
+        ::prost::alloc::collections::BTreeMap<::prost::alloc::string::String, PeriodicTaskState>,
+    /// Set once `archive_finalized_state` started to move the buyers and
+    /// neuron recipes of the finalized swap to the archive canister.
+    #[prost(message, optional, tag = "18")]
+    pub archive: ::core::option::Option<SwapArchive>,
+    /// The earlier rounds of the swap, in the order they were opened. The
+    /// fields above describe the current round; once it is finalized and
+    /// archived, `open` can start another one, which moves the summary of the
+    /// current round here.
+    #[prost(message, repeated, tag = "19")]
+    pub completed_rounds: ::prost::alloc::vec::Vec<SwapRound>,
+    /// The memo of the first neuron of the baskets created by the next commit.
+    /// The memos of the neurons of a round follow the ones of the earlier
+    /// rounds, so that the neurons of a participant of several rounds don't
+    /// collide. If not set, the baskets start at `SALE_NEURON_MEMO_RANGE_START`.
+    #[prost(uint64, optional, tag = "20")]
+    pub next_sale_neuron_memo: ::core::option::Option<u64>,
+    /// Set once the transaction fee of the SNS ledger was checked after the
+    /// current round committed, before `finalize_swap` moved any funds. See
+    /// `configuration_error`.
+    #[prost(bool, optional, tag = "21")]
+    pub sns_ledger_fee_checked: ::core::option::Option<bool>,
+    /// The SNS tokens of the current round that the participants did not buy
+    /// at the price reached by the dutch auction, see `Params.dutch_auction`.
+    /// Set when the round commits, and returned to the treasury of SNS
+    /// governance by `sweep_sns`.
+    #[prost(message, optional, tag = "22")]
+    pub unsold_sns: ::core::option::Option<TransferableAmount>,
+    /// Set once NNS governance settled the Community Fund participation of the
+    /// current round, see `settle_community_fund_participation`. SNS tokens are
+    /// only distributed once it is set.
+    #[prost(bool, optional, tag = "23")]
+    pub community_fund_participation_settled: ::core::option::Option<bool>,
+}
+/// The summary of a round of the swap, see `Swap.completed_rounds`.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct SwapRound {
+    /// The ID of the proposal that opened the round, which identifies it, see
+    /// `Swap.open_sns_token_swap_proposal_id`.
+    #[prost(uint64, optional, tag = "1")]
+    pub open_sns_token_swap_proposal_id: ::core::option::Option<u64>,
+    /// The lifecycle the round ended in.
+    #[prost(enumeration = "Lifecycle", tag = "2")]
+    pub lifecycle: i32,
+    #[prost(message, optional, tag = "3")]
+    pub params: ::core::option::Option<Params>,
+    #[prost(uint64, optional, tag = "4")]
+    pub decentralization_sale_open_timestamp_seconds: ::core::option::Option<u64>,
+    /// The time the round committed or aborted.
+    #[prost(uint64, optional, tag = "5")]
+    pub end_timestamp_seconds: ::core::option::Option<u64>,
+    #[prost(uint64, tag = "6")]
+    pub direct_participants: u64,
+    #[prost(uint64, tag = "7")]
+    pub direct_participant_icp_e8s: u64,
+    #[prost(uint64, tag = "8")]
+    pub cf_participant_icp_e8s: u64,
+    #[prost(uint64, tag = "9")]
+    pub neuron_recipes: u64,
+    /// Where the buyers and neuron recipes of the round were archived to.
+    #[prost(message, optional, tag = "10")]
+    pub archive: ::core::option::Option<SwapArchive>,
+}
+/// The archival of the buyers and neuron recipes of a finalized swap. The
+/// records are pushed to the archive canister in batches, and removed from
+/// the swap once the archive acknowledged them. The totals of the archived
+/// buyers are kept, so that the derived state of the swap does not change.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct SwapArchive {
+    /// The canister the records are archived to.
+    #[prost(string, tag = "1")]
+    pub archive_canister_id: ::prost::alloc::string::String,
+    /// The number of batches acknowledged by the archive canister.
+    #[prost(uint64, tag = "2")]
+    pub batches: u64,
+    /// The number of buyers removed from `Swap.buyers`.
+    #[prost(uint64, tag = "3")]
+    pub archived_buyers: u64,
+    /// The number of neuron recipes removed from `Swap.neuron_recipes`.
+    #[prost(uint64, tag = "4")]
+    pub archived_neuron_recipes: u64,
+    /// The ICP contributed by the archived buyers.
+    #[prost(uint64, tag = "5")]
+    pub archived_buyer_icp_e8s: u64,
+    /// The participation weight of the archived buyers, see
+    /// `Params.early_participation_bonus`.
+    #[prost(uint64, tag = "6")]
+    pub archived_buyer_weight_e8s: u64,
+    /// The time all buyers and neuron recipes were archived.
+    #[prost(uint64, optional, tag = "7")]
+    pub completed_timestamp_seconds: ::core::option::Option<u64>,
+}
+/// The bookkeeping of a periodic task.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct PeriodicTaskState {
+    /// The last time the task was run, whether it succeeded or not.
+    #[prost(uint64, optional, tag = "1")]
+    pub last_run_timestamp_seconds: ::core::option::Option<u64>,
+    /// The number of runs of the task that failed.
+    #[prost(uint64, tag = "2")]
+    pub error_count: u64,
+    /// The error of the last run that failed.
+    #[prost(string, optional, tag = "3")]
+    pub last_error: ::core::option::Option<::prost::alloc::string::String>,
+}
+/// A transition of the lifecycle of the swap.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct LifecycleTransition {
+    /// The lifecycle before the transition.
+    #[prost(enumeration = "Lifecycle", tag = "1")]
+    pub from_lifecycle: i32,
+    /// The lifecycle after the transition.
+    #[prost(enumeration = "Lifecycle", tag = "2")]
+    pub to_lifecycle: i32,
+    /// The time of the transition.
+    #[prost(uint64, tag = "3")]
+    pub timestamp_seconds: u64,
+    /// The round of the swap the transition belongs to, see
+    /// `SwapRound.open_sns_token_swap_proposal_id`.
+    #[prost(uint64, optional, tag = "4")]
+    pub swap_round: ::core::option::Option<u64>,
+    /// Why the transition was made, if not in the regular course of the swap,
+    /// e.g., the configuration error the swap was aborted for.
+    #[prost(string, optional, tag = "5")]
+    pub reason: ::core::option::Option<::prost::alloc::string::String>,
 }
 /// The initialisation data of the canister. Always specified on
 /// canister creation, and cannot be modified afterwards.
@@ -250,6 +423,16 @@ pub struct Init {
     /// the values match is not checked. If they don't match things will break.
     #[prost(uint64, optional, tag = "14")]
     pub neuron_minimum_stake_e8s: ::core::option::Option<u64>,
+    /// Whether canisters can participate directly in the swap. Canisters can
+    /// wrap the participation of many principals, bypassing the per-principal
+    /// limits, so by default (if not set) they are refused.
+    #[prost(bool, optional, tag = "15")]
+    pub allow_canister_participants: ::core::option::Option<bool>,
+    /// The canister the buyers and neuron recipes are archived to once the swap
+    /// is finalized, see `archive_finalized_state`. It must implement
+    /// `archive_swap_records`; if not set, the records are not archived.
+    #[prost(string, optional, tag = "16")]
+    pub archive_canister_id: ::core::option::Option<::prost::alloc::string::String>,
 }
 /// Represents one NNS neuron from the community fund participating in this swap.
 #[derive(
@@ -364,6 +547,32 @@ pub struct Params {
     /// after the adoption of the sale proposal.
     #[prost(uint64, optional, tag = "9")]
     pub sale_delay_seconds: ::core::option::Option<u64>,
+    /// See `EarlyParticipationBonus`. If not set, all ICP buys SNS tokens at
+    /// the same rate.
+    #[prost(message, optional, tag = "10")]
+    pub early_participation_bonus: ::core::option::Option<params::EarlyParticipationBonus>,
+    /// The maximum number of direct participants (buyers). Once it is reached,
+    /// only existing buyers can increase their participation. Must be at least
+    /// `min_participants` if set. If not set, the number of buyers is not
+    /// limited.
+    #[prost(uint32, optional, tag = "11")]
+    pub max_direct_participants: ::core::option::Option<u32>,
+    /// See `DutchAuction`. If not set, the SNS tokens are apportioned among
+    /// the participants pro rata when the swap commits.
+    #[prost(message, optional, tag = "12")]
+    pub dutch_auction: ::core::option::Option<params::DutchAuction>,
+    /// If true, direct participations that would take the ICP committed
+    /// above `max_icp_e8s` are still accepted while the swap is open (up to
+    /// `max_participant_icp_e8s` each), as long as every direct participant
+    /// can keep at least `min_participant_icp_e8s` after the cutback. When the
+    /// swap commits, the excess over `max_icp_e8s` is cut back from the
+    /// direct participants pro rata to their participation above
+    /// `min_participant_icp_e8s`, rounding down and handing the e8s left over
+    /// to the largest remainders (ties broken by principal), and refunded to
+    /// them when the ICP is swept. See `BuyerState.cutback_icp`. If not set,
+    /// participations are refused once `max_icp_e8s` is reached.
+    #[prost(bool, optional, tag = "13")]
+    pub pro_rata_cutback: ::core::option::Option<bool>,
 }
 /// Nested message and enum types in `Params`.
 pub mod params {
@@ -388,6 +597,57 @@ pub mod params {
         #[prost(uint64, tag = "2")]
         pub dissolve_delay_interval_seconds: u64,
     }
+    /// An optional bonus for early participants. ICP committed within
+    /// `window_seconds` of the opening of the sale buys
+    /// `bonus_basis_points / 100` percent more SNS tokens than ICP committed
+    /// later on. The bonus is paid out of `sns_token_e8s`, i.e., it does not
+    /// increase the total number of SNS tokens being offered.
+    #[derive(
+        candid::CandidType,
+        candid::Deserialize,
+        serde::Serialize,
+        comparable::Comparable,
+        Clone,
+        PartialEq,
+        ::prost::Message,
+    )]
+    pub struct EarlyParticipationBonus {
+        /// The length of the bonus window, counted from
+        /// `decentralization_sale_open_timestamp_seconds`. Must be greater
+        /// than zero and must end before `swap_due_timestamp_seconds`.
+        #[prost(uint64, tag = "1")]
+        pub window_seconds: u64,
+        /// The bonus, in basis points (1/100 of a percent). Must be greater
+        /// than zero and at most `MAX_EARLY_PARTICIPATION_BONUS_BASIS_POINTS`.
+        #[prost(uint32, tag = "2")]
+        pub bonus_basis_points: u32,
+    }
+    /// An optional descending-price ("dutch") auction. The price of an SNS
+    /// token starts at `start_icp_e8s_per_sns_token` when the sale opens and
+    /// decreases linearly to `floor_icp_e8s_per_sns_token` at
+    /// `swap_due_timestamp_seconds`. When the swap commits, all participants
+    /// pay the same price: the higher of the auction price at that time and
+    /// the price at which the ICP committed buys all of `sns_token_e8s`. SNS
+    /// tokens that are not sold at that price remain in the swap canister.
+    #[derive(
+        candid::CandidType,
+        candid::Deserialize,
+        serde::Serialize,
+        comparable::Comparable,
+        Clone,
+        PartialEq,
+        ::prost::Message,
+    )]
+    pub struct DutchAuction {
+        /// The price, in ICP e8s per SNS token (10^8 SNS e8s), when the sale
+        /// opens. Must be greater than `floor_icp_e8s_per_sns_token`.
+        #[prost(uint64, tag = "1")]
+        pub start_icp_e8s_per_sns_token: u64,
+        /// The price, in ICP e8s per SNS token, at `swap_due_timestamp_seconds`.
+        /// Must be greater than zero.
+        #[prost(uint64, tag = "2")]
+        pub floor_icp_e8s_per_sns_token: u64,
+    }
 }
 #[derive(
     candid::CandidType,
@@ -450,6 +710,65 @@ pub struct BuyerState {
     /// * ABORTED - owned by the buyer, can be transferred out
     #[prost(message, optional, tag = "5")]
     pub icp: ::core::option::Option<TransferableAmount>,
+    /// The part of `icp.amount_e8s` that was accepted during the window of
+    /// `params.early_participation_bonus`, i.e., that is eligible for the
+    /// early participation bonus. Always at most `icp.amount_e8s`.
+    #[prost(uint64, optional, tag = "6")]
+    pub early_participation_icp_e8s: ::core::option::Option<u64>,
+    /// The ICP cut back from this buyer's participation when the swap
+    /// committed with more than `max_icp_e8s`, see `Params.pro_rata_cutback`.
+    /// It is not part of `icp.amount_e8s` and is refunded to the buyer when
+    /// the ICP is swept. Not set if nothing was cut back, or if the cutback
+    /// does not exceed the transfer fee, in which case it can be reclaimed
+    /// with `error_refund_icp`.
+    #[prost(message, optional, tag = "7")]
+    pub cutback_icp: ::core::option::Option<TransferableAmount>,
+}
+/// The increments of a buyer's participation. Kept in stable memory apart
+/// from `BuyerState`, so that they don't weigh on the state returned by
+/// `get_state` or `list_direct_participants`, and only returned by
+/// `get_buyer_participation_history`.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct BuyerParticipationHistory {
+    /// The increments, oldest first. At most `MAX_PARTICIPATION_HISTORY_LEN`
+    /// increments are kept, the older ones are summed up in
+    /// `pruned_participation_icp_e8s`.
+    #[prost(message, repeated, tag = "1")]
+    pub increments: ::prost::alloc::vec::Vec<ParticipationIncrement>,
+    /// The sum of the increments pruned from `increments`, including the
+    /// participation of the buyer from before the history was recorded.
+    #[prost(uint64, optional, tag = "2")]
+    pub pruned_participation_icp_e8s: ::core::option::Option<u64>,
+}
+/// An increase of a buyer's participation, accepted by a call of
+/// `refresh_buyer_tokens`.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct ParticipationIncrement {
+    /// The ICP accepted by the call.
+    #[prost(uint64, tag = "1")]
+    pub amount_icp_e8s: u64,
+    /// When the call accepted the ICP.
+    #[prost(uint64, tag = "2")]
+    pub timestamp_seconds: u64,
+    /// The ticket executed by the call, if any.
+    #[prost(uint64, optional, tag = "3")]
+    pub ticket_id: ::core::option::Option<u64>,
 }
 /// Information about a direct investor.
 #[derive(
@@ -714,6 +1033,58 @@ pub struct GetBuyerStateResponse {
     #[prost(message, optional, tag = "1")]
     pub buyer_state: ::core::option::Option<BuyerState>,
 }
+/// Request struct for the method `get_buyer_participation_history`
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct GetBuyerParticipationHistoryRequest {
+    /// The buyer whose history is returned. If not specified, the caller is
+    /// used.
+    #[prost(message, optional, tag = "1")]
+    pub principal_id: ::core::option::Option<::ic_base_types::PrincipalId>,
+}
+/// Response struct for the method `get_buyer_participation_history`. Empty if
+/// the principal is not a buyer.
+///
+/// Audits the refunds of the buyer: the increments of the history add up to
+/// `amount_icp_e8s` plus `cutback_icp_e8s`, and `refunded_icp_e8s` is what was
+/// transferred back of them.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct GetBuyerParticipationHistoryResponse {
+    /// See `BuyerParticipationHistory.increments`.
+    #[prost(message, repeated, tag = "1")]
+    pub participation_history: ::prost::alloc::vec::Vec<ParticipationIncrement>,
+    /// See `BuyerParticipationHistory.pruned_participation_icp_e8s`.
+    #[prost(uint64, optional, tag = "2")]
+    pub pruned_participation_icp_e8s: ::core::option::Option<u64>,
+    /// The ICP currently accepted from the buyer, i.e.,
+    /// `BuyerState.icp.amount_e8s`.
+    #[prost(uint64, tag = "3")]
+    pub amount_icp_e8s: u64,
+    /// The ICP cut back from the participation of the buyer, i.e.,
+    /// `BuyerState.cutback_icp.amount_e8s`.
+    #[prost(uint64, tag = "4")]
+    pub cutback_icp_e8s: u64,
+    /// The ICP of the participation transferred back to the buyer (minus
+    /// fees): the cutback once it is refunded, and all of it once an aborted
+    /// swap refunded it.
+    #[prost(uint64, tag = "5")]
+    pub refunded_icp_e8s: u64,
+}
 #[derive(
     candid::CandidType,
     candid::Deserialize,
@@ -753,6 +1124,15 @@ pub struct DerivedState {
     /// Current approximate rate SNS tokens per ICP.
     #[prost(float, tag = "2")]
     pub sns_tokens_per_icp: f32,
+    /// Current approximate rate SNS tokens per ICP committed within the window
+    /// of `params.early_participation_bonus`. Only set if the swap has such a
+    /// bonus.
+    #[prost(float, optional, tag = "3")]
+    pub early_participation_sns_tokens_per_icp: ::core::option::Option<f32>,
+    /// The number of additional direct participants the swap can accept. Only
+    /// set if `params.max_direct_participants` is set.
+    #[prost(uint64, optional, tag = "4")]
+    pub remaining_direct_participants: ::core::option::Option<u64>,
 }
 #[derive(
     candid::CandidType,
@@ -813,6 +1193,11 @@ pub struct RefreshBuyerTokensResponse {
     pub icp_accepted_participation_e8s: u64,
     #[prost(uint64, tag = "2")]
     pub icp_ledger_account_balance_e8s: u64,
+    /// The receipt of the call: the increment of the buyer's participation it
+    /// accepted, as recorded in the participation history. Not set if the call
+    /// accepted no ICP.
+    #[prost(message, optional, tag = "3")]
+    pub participation_increment: ::core::option::Option<ParticipationIncrement>,
 }
 /// Once a swap is committed or aborted, the tokens need to be
 /// distributed, and, if the swap was committed, neurons created.
@@ -826,6 +1211,21 @@ pub struct RefreshBuyerTokensResponse {
     ::prost::Message,
 )]
 pub struct FinalizeSwapRequest {}
+/// Runs a single phase of `finalize_swap`. The response only has the result
+/// of that phase.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct FinalizeSwapPhaseRequest {
+    #[prost(enumeration = "FinalizeSwapPhase", tag = "1")]
+    pub phase: i32,
+}
 /// Response from the `finalize_swap` canister API.
 #[derive(
     candid::CandidType,
@@ -854,6 +1254,80 @@ pub struct FinalizeSwapResponse {
     #[prost(string, optional, tag = "7")]
     pub error_message: ::core::option::Option<::prost::alloc::string::String>,
 }
+/// Request struct for the method `archive_finalized_state`.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct ArchiveFinalizedStateRequest {}
+/// Response from the `archive_finalized_state` canister API.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct ArchiveFinalizedStateResponse {
+    /// The progress of the archival after the call.
+    #[prost(message, optional, tag = "1")]
+    pub archive: ::core::option::Option<SwapArchive>,
+    /// Explains why the archival did not complete, if it didn't. Calling
+    /// `archive_finalized_state` again resumes it.
+    #[prost(string, optional, tag = "2")]
+    pub error_message: ::core::option::Option<::prost::alloc::string::String>,
+}
+/// A batch of records of a finalized swap, sent by the swap to the method
+/// `archive_swap_records` of its archive canister.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct ArchiveSwapRecordsRequest {
+    /// The position of the batch among the batches of the swap, starting at 0.
+    /// A batch is sent again if the swap did not receive its acknowledgement.
+    #[prost(uint64, tag = "1")]
+    pub batch_index: u64,
+    #[prost(message, repeated, tag = "2")]
+    pub buyers: ::prost::alloc::vec::Vec<Participant>,
+    #[prost(message, repeated, tag = "3")]
+    pub neuron_recipes: ::prost::alloc::vec::Vec<SnsNeuronRecipe>,
+    /// The round of the swap the buyers and neuron recipes belong to, see
+    /// `SwapRound.open_sns_token_swap_proposal_id`.
+    #[prost(uint64, optional, tag = "4")]
+    pub swap_round: ::core::option::Option<u64>,
+}
+/// The acknowledgement of a batch by the archive canister.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct ArchiveSwapRecordsResponse {
+    #[prost(uint64, tag = "1")]
+    pub batch_index: u64,
+    /// The SHA-256 of the protobuf encoding of the request, as received by the
+    /// archive canister. The swap only removes the records of the batch if it
+    /// matches.
+    #[prost(string, tag = "2")]
+    pub sha256_hex: ::prost::alloc::string::String,
+}
 #[derive(
     candid::CandidType,
     candid::Deserialize,
@@ -1322,6 +1796,65 @@ pub mod settle_community_fund_participation {
         Aborted(Aborted),
     }
 }
+/// Copied from nns governance.proto.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct ListNeurons {
+    /// The neurons to get information about. The "requested list"
+    /// contains all of these neuron IDs.
+    #[prost(fixed64, repeated, tag = "1")]
+    pub neuron_ids: ::prost::alloc::vec::Vec<u64>,
+    /// If true, the "requested list" also contains the neuron ID of the
+    /// neurons that the calling principal is authorized to read.
+    #[prost(bool, tag = "2")]
+    pub include_neurons_readable_by_caller: bool,
+}
+/// Copied from nns governance.proto, without the full neurons, which the
+/// swap canister is not authorized to read.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct ListNeuronsResponse {
+    /// For each neuron ID in the "requested list", if this neuron exists,
+    /// its `NeuronInfo` at the time of the call will be in this map.
+    #[prost(btree_map = "fixed64, message", tag = "1")]
+    pub neuron_infos: ::prost::alloc::collections::BTreeMap<u64, NeuronInfo>,
+}
+/// Copied from nns governance.proto, with only the fields the swap canister
+/// inspects.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct NeuronInfo {
+    /// The exact time at which this data was computed.
+    #[prost(uint64, tag = "1")]
+    pub retrieved_at_timestamp_seconds: u64,
+    /// The current state of the neuron.
+    #[prost(enumeration = "NeuronState", tag = "2")]
+    pub state: i32,
+    /// The current dissolve delay of the neuron.
+    #[prost(uint64, tag = "4")]
+    pub dissolve_delay_seconds: u64,
+}
 /// The id of a specific neuron, which equals the neuron's subaccount on the ledger canister
 /// (the account that holds the neuron's staked tokens).
 #[derive(
@@ -1561,6 +2094,91 @@ pub struct GetDerivedStateResponse {
     pub buyer_total_icp_e8s: ::core::option::Option<u64>,
     #[prost(double, optional, tag = "2")]
     pub sns_tokens_per_icp: ::core::option::Option<f64>,
+    #[prost(double, optional, tag = "3")]
+    pub early_participation_sns_tokens_per_icp: ::core::option::Option<f64>,
+    #[prost(uint64, optional, tag = "4")]
+    pub remaining_direct_participants: ::core::option::Option<u64>,
+}
+/// Request struct for the method `get_auction_price`
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct GetAuctionPriceRequest {}
+/// Response struct for the method `get_auction_price`. The fields are only
+/// set if the sale has a dutch auction and is open.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct GetAuctionPriceResponse {
+    /// The current price of the dutch auction, in ICP e8s per SNS token.
+    #[prost(uint64, optional, tag = "1")]
+    pub auction_icp_e8s_per_sns_token: ::core::option::Option<u64>,
+    /// The price, in ICP e8s per SNS token, that the participants would pay if
+    /// the swap committed now.
+    #[prost(uint64, optional, tag = "2")]
+    pub clearing_icp_e8s_per_sns_token: ::core::option::Option<u64>,
+    /// The number of SNS tokens (in e8s) that would be sold if the swap
+    /// committed now.
+    #[prost(uint64, optional, tag = "3")]
+    pub sns_token_e8s_sold: ::core::option::Option<u64>,
+}
+/// Request struct for the method `get_sale_status`
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct GetSaleStatusRequest {}
+/// Response struct for the method `get_sale_status`. The fields are only set
+/// if the sale is open.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct GetSaleStatusResponse {
+    /// The ICP (in e8s) the swap can still accept before reaching
+    /// `params.max_icp_e8s`.
+    #[prost(uint64, optional, tag = "1")]
+    pub remaining_icp_e8s: ::core::option::Option<u64>,
+    /// Whether the participants committed `params.max_icp_e8s`, which closes
+    /// the swap.
+    #[prost(bool, optional, tag = "2")]
+    pub max_icp_reached: ::core::option::Option<bool>,
+    /// The time until `params.swap_due_timestamp_seconds`, at which the swap
+    /// closes at the latest.
+    #[prost(uint64, optional, tag = "3")]
+    pub seconds_remaining: ::core::option::Option<u64>,
+    /// Whether the swap currently has `params.min_participants` and
+    /// `params.min_icp_e8s`, i.e., whether it would commit if it closed now.
+    #[prost(bool, optional, tag = "4")]
+    pub min_participation_reached: ::core::option::Option<bool>,
+    /// Whether the swap is closing and commits at the next heartbeat.
+    #[prost(bool, optional, tag = "5")]
+    pub commit_due: ::core::option::Option<bool>,
+    /// Whether the swap is closing and aborts at the next heartbeat.
+    #[prost(bool, optional, tag = "6")]
+    pub abort_due: ::core::option::Option<bool>,
 }
 /// ICRC-1 Account. See <https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-1>
 #[derive(
@@ -1846,6 +2464,17 @@ pub mod new_sale_ticket_response {
             InvalidSubaccount = 5,
             /// The specified principal is forbidden from creating tickets.
             InvalidPrincipal = 6,
+            /// The swap already has `params.max_direct_participants` direct
+            /// participants, and the caller is not one of them.
+            SwapParticipantLimitReached = 7,
+            /// The caller is a canister, and `init.allow_canister_participants` is
+            /// not set.
+            CanisterPrincipalNotAllowed = 8,
+            /// With `params.pro_rata_cutback`, the swap could not cut back the
+            /// participations to `params.max_icp_e8s` while leaving every direct
+            /// participant with `params.min_participant_icp_e8s` if the caller
+            /// joined, and the caller is not a participant yet.
+            CutbackCapacityReached = 9,
         }
         impl Type {
             /// String value of the enum field names used in the ProtoBuf definition.
@@ -1861,6 +2490,9 @@ pub mod new_sale_ticket_response {
                     Type::InvalidUserAmount => "TYPE_INVALID_USER_AMOUNT",
                     Type::InvalidSubaccount => "TYPE_INVALID_SUBACCOUNT",
                     Type::InvalidPrincipal => "TYPE_INVALID_PRINCIPAL",
+                    Type::SwapParticipantLimitReached => "TYPE_SWAP_PARTICIPANT_LIMIT_REACHED",
+                    Type::CanisterPrincipalNotAllowed => "TYPE_CANISTER_PRINCIPAL_NOT_ALLOWED",
+                    Type::CutbackCapacityReached => "TYPE_CUTBACK_CAPACITY_REACHED",
                 }
             }
         }
@@ -2037,6 +2669,210 @@ pub struct ListSnsNeuronRecipesResponse {
     #[prost(message, repeated, tag = "1")]
     pub sns_neuron_recipes: ::prost::alloc::vec::Vec<SnsNeuronRecipe>,
 }
+/// Request struct for the method `list_swap_rounds`.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct ListSwapRoundsRequest {}
+/// Response struct for the method `list_swap_rounds`.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct ListSwapRoundsResponse {
+    /// The rounds of the swap in the order they were opened, the current one
+    /// last.
+    #[prost(message, repeated, tag = "1")]
+    pub rounds: ::prost::alloc::vec::Vec<SwapRound>,
+}
+/// The state of the swap relevant to audits, in a deterministic
+/// representation: the buyers are ordered by the textual representation of
+/// their principal, and the community fund participants and neuron recipes are
+/// in the order in which the swap keeps them.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct SwapStateExport {
+    #[prost(enumeration = "Lifecycle", tag = "1")]
+    pub lifecycle: i32,
+    #[prost(message, optional, tag = "2")]
+    pub init: ::core::option::Option<Init>,
+    #[prost(message, optional, tag = "3")]
+    pub params: ::core::option::Option<Params>,
+    #[prost(uint64, optional, tag = "4")]
+    pub open_sns_token_swap_proposal_id: ::core::option::Option<u64>,
+    #[prost(uint64, optional, tag = "5")]
+    pub decentralization_sale_open_timestamp_seconds: ::core::option::Option<u64>,
+    #[prost(message, repeated, tag = "6")]
+    pub lifecycle_history: ::prost::alloc::vec::Vec<LifecycleTransition>,
+    #[prost(message, repeated, tag = "7")]
+    pub buyers: ::prost::alloc::vec::Vec<Participant>,
+    #[prost(message, repeated, tag = "8")]
+    pub cf_participants: ::prost::alloc::vec::Vec<CfParticipant>,
+    #[prost(message, repeated, tag = "9")]
+    pub neuron_recipes: ::prost::alloc::vec::Vec<SnsNeuronRecipe>,
+}
+/// Request struct for the method `export_state`. The method paginates over the
+/// buyers, community fund participants and neuron recipes of the exported
+/// state, in that order, as if they were a single list.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct ExportStateRequest {
+    /// Skip the first `offset` elements when constructing the response.
+    #[prost(uint64, optional, tag = "1")]
+    pub offset: ::core::option::Option<u64>,
+    /// The maximum number of elements that will be in the response.
+    /// This is capped at 10_000.
+    #[prost(uint32, optional, tag = "2")]
+    pub limit: ::core::option::Option<u32>,
+}
+/// Response struct for the method `export_state`.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct ExportStateResponse {
+    /// The exported state with a page of its buyers, community fund participants
+    /// and neuron recipes. Concatenating these over all pages yields the full
+    /// export.
+    #[prost(message, optional, tag = "1")]
+    pub state: ::core::option::Option<SwapStateExport>,
+    /// The total number of buyers, community fund participants and neuron
+    /// recipes.
+    #[prost(uint64, tag = "2")]
+    pub total_elements: u64,
+    /// The offset of the next page, if there is one.
+    #[prost(uint64, optional, tag = "3")]
+    pub next_offset: ::core::option::Option<u64>,
+}
+/// Request struct for the method `get_state_chunk`.
+///
+/// Queries can't keep state across calls, so every call encodes and hashes the
+/// full export: reassembling it costs the size of the state times the number
+/// of chunks. Callers should thus leave `length` unset, so that the export
+/// takes as few calls as possible.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct GetStateChunkRequest {
+    /// The position of the first byte of the chunk.
+    #[prost(uint64, tag = "1")]
+    pub offset: u64,
+    /// The maximum length of the chunk. This is capped at 1_000_000.
+    #[prost(uint32, optional, tag = "2")]
+    pub length: ::core::option::Option<u32>,
+}
+/// Response struct for the method `get_state_chunk`. The chunk is a part of the
+/// protobuf encoding of the full `SwapStateExport`.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct GetStateChunkResponse {
+    #[prost(bytes = "vec", tag = "1")]
+    pub chunk: ::prost::alloc::vec::Vec<u8>,
+    /// The size of the full encoding.
+    #[prost(uint64, tag = "2")]
+    pub total_size_bytes: u64,
+    /// The SHA-256 of the full encoding, to check it once reassembled.
+    #[prost(string, tag = "3")]
+    pub sha256_hex: ::prost::alloc::string::String,
+}
+/// Request struct for the method `get_transfer_memo_scheme`.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct GetTransferMemoSchemeRequest {}
+/// Response struct for the method `get_transfer_memo_scheme`. The memo of
+/// every ledger transfer initiated by the swap canister encodes the swap round
+/// in its low `swap_round_bits` bits, and the `TransferPurpose` of the transfer
+/// in the remaining high bits, so that ledger explorers can label the
+/// transfers of a swap.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct GetTransferMemoSchemeResponse {
+    /// The swap round, i.e., the ID of the proposal that opened the swap, or 0
+    /// if the swap has not been opened by a proposal.
+    #[prost(uint64, tag = "1")]
+    pub swap_round: u64,
+    /// The number of low bits of the memo holding the swap round.
+    #[prost(uint32, tag = "2")]
+    pub swap_round_bits: u32,
+    /// The memo of the transfers of this swap, for each purpose.
+    #[prost(message, repeated, tag = "3")]
+    pub memos: ::prost::alloc::vec::Vec<TransferMemo>,
+}
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    PartialEq,
+    ::prost::Message,
+)]
+pub struct TransferMemo {
+    #[prost(enumeration = "TransferPurpose", tag = "1")]
+    pub purpose: i32,
+    /// The name of the purpose, e.g., TRANSFER_PURPOSE_COMMIT_ICP.
+    #[prost(string, tag = "2")]
+    pub purpose_name: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "3")]
+    pub memo: u64,
+}
 /// Request struct for the method `notfiy_payment_failure`
 #[derive(
     candid::CandidType,
@@ -2124,3 +2960,153 @@ impl Lifecycle {
         }
     }
 }
+/// The purpose of a ledger transfer initiated by the swap canister. It is
+/// encoded in the memo of the transfer, see `GetTransferMemoSchemeResponse`.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    ::prost::Enumeration,
+)]
+#[repr(i32)]
+pub enum TransferPurpose {
+    Unspecified = 0,
+    /// ICP of a buyer swept to the SNS governance canister once the swap is
+    /// committed.
+    CommitIcp = 1,
+    /// ICP of a buyer swept back to the buyer once the swap is aborted.
+    RefundIcp = 2,
+    /// SNS tokens swept to the account of a neuron of a participant once the
+    /// swap is committed.
+    DistributeSns = 3,
+    /// ICP returned to its owner by `error_refund_icp`.
+    ErrorRefundIcp = 4,
+}
+impl TransferPurpose {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            TransferPurpose::Unspecified => "TRANSFER_PURPOSE_UNSPECIFIED",
+            TransferPurpose::CommitIcp => "TRANSFER_PURPOSE_COMMIT_ICP",
+            TransferPurpose::RefundIcp => "TRANSFER_PURPOSE_REFUND_ICP",
+            TransferPurpose::DistributeSns => "TRANSFER_PURPOSE_DISTRIBUTE_SNS",
+            TransferPurpose::ErrorRefundIcp => "TRANSFER_PURPOSE_ERROR_REFUND_ICP",
+        }
+    }
+}
+/// The phases of `finalize_swap`, in the order it runs them. Each of them can
+/// also be run on its own by `finalize_swap_phase`, e.g. to retry the phase a
+/// finalization is stuck at. Running a phase again only retries what did not
+/// succeed before.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    ::prost::Enumeration,
+)]
+#[repr(i32)]
+pub enum FinalizeSwapPhase {
+    Unspecified = 0,
+    /// Sweeps the ICP of the buyers to SNS governance if the swap committed, or
+    /// back to the buyers if it aborted.
+    SweepIcp = 1,
+    /// Settles the participation of the Community Fund with NNS governance.
+    SettleCommunityFund = 2,
+    /// Returns the dapp canisters to their fallback controllers, if the swap
+    /// aborted in its first round.
+    SetDappControllers = 3,
+    /// Sweeps the SNS tokens of the neuron recipes to their neuron accounts.
+    SweepSns = 4,
+    /// Claims the neurons of the neuron recipes with SNS governance.
+    ClaimNeurons = 5,
+    /// Sets SNS governance to normal mode.
+    SetMode = 6,
+}
+impl FinalizeSwapPhase {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            FinalizeSwapPhase::Unspecified => "FINALIZE_SWAP_PHASE_UNSPECIFIED",
+            FinalizeSwapPhase::SweepIcp => "FINALIZE_SWAP_PHASE_SWEEP_ICP",
+            FinalizeSwapPhase::SettleCommunityFund => "FINALIZE_SWAP_PHASE_SETTLE_COMMUNITY_FUND",
+            FinalizeSwapPhase::SetDappControllers => "FINALIZE_SWAP_PHASE_SET_DAPP_CONTROLLERS",
+            FinalizeSwapPhase::SweepSns => "FINALIZE_SWAP_PHASE_SWEEP_SNS",
+            FinalizeSwapPhase::ClaimNeurons => "FINALIZE_SWAP_PHASE_CLAIM_NEURONS",
+            FinalizeSwapPhase::SetMode => "FINALIZE_SWAP_PHASE_SET_MODE",
+        }
+    }
+}
+/// Copied from nns governance.proto.
+#[derive(
+    candid::CandidType,
+    candid::Deserialize,
+    serde::Serialize,
+    comparable::Comparable,
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    ::prost::Enumeration,
+)]
+#[repr(i32)]
+pub enum NeuronState {
+    /// Not a valid state. Required by Protobufs.
+    Unspecified = 0,
+    /// In this state, the neuron is not dissolving and has a specific
+    /// `dissolve_delay`. It accrues `age` by the passage of time and it
+    /// can vote if `dissolve_delay` is at least six months.
+    NotDissolving = 1,
+    /// In this state, the neuron's `dissolve_delay` decreases with the
+    /// passage of time. While dissolving it has zero age and can vote
+    /// if `dissolve_delay` is at least six months.
+    Dissolving = 2,
+    /// In this state, the neuron has no `dissolve_delay` and cannot
+    /// vote. Its stake can be disbursed.
+    Dissolved = 3,
+    /// The neuron is in spawning state, meaning it's maturity will be
+    /// converted to ICP according to <https://wiki.internetcomputer.org/wiki/Maturity_modulation.>
+    Spawning = 4,
+}
+impl NeuronState {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            NeuronState::Unspecified => "NEURON_STATE_UNSPECIFIED",
+            NeuronState::NotDissolving => "NEURON_STATE_NOT_DISSOLVING",
+            NeuronState::Dissolving => "NEURON_STATE_DISSOLVING",
+            NeuronState::Dissolved => "NEURON_STATE_DISSOLVED",
+            NeuronState::Spawning => "NEURON_STATE_SPAWNING",
+        }
+    }
+}
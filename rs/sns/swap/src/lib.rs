@@ -2,5 +2,6 @@ pub mod clients;
 pub mod logs;
 pub mod memory;
 pub mod pb;
+pub mod periodic_tasks;
 pub mod swap;
 pub mod types;
@@ -6,11 +6,12 @@ use ic_stable_structures::{
 };
 use std::cell::RefCell;
 
-use crate::pb::v1::Ticket;
+use crate::pb::v1::{BuyerParticipationHistory, Ticket};
 
 const UPGRADES_MEMORY_ID: MemoryId = MemoryId::new(0);
 const OPEN_TICKETS_MEMORY_ID: MemoryId = MemoryId::new(1);
 const BUYERS_INDEX_LIST_MEMORY_ID: MemoryId = MemoryId::new(2);
+const PARTICIPATION_HISTORIES_MEMORY_ID: MemoryId = MemoryId::new(3);
 
 thread_local! {
 
@@ -38,4 +39,9 @@ thread_local! {
                 .expect("Expected to initialize the BUYERS_LIST_INDEX without error")
             )
         );
+
+    /// The participation histories of the buyers, by Principal. Kept apart from Swap::buyers, see
+    /// `BuyerParticipationHistory`.
+    pub static PARTICIPATION_HISTORIES: RefCell<StableBTreeMap<Blob<{PrincipalId::MAX_LENGTH_IN_BYTES}>, BuyerParticipationHistory, VirtualMemory<DefaultMemoryImpl>>> =
+        MEMORY_MANAGER.with(|memory_manager| RefCell::new(StableBTreeMap::init(memory_manager.borrow().get(PARTICIPATION_HISTORIES_MEMORY_ID))));
 }
@@ -0,0 +1,184 @@
+//! The periodic work of the swap canister, run by the heartbeat.
+//!
+//! Tasks are registered in a [TaskRegistry] with the interval at which they
+//! should run. The time of their last run and their errors are recorded in
+//! `Swap.periodic_tasks`, so that the intervals survive upgrades and the
+//! errors can be exposed as metrics.
+
+use crate::logs::{ERROR, INFO};
+use crate::pb::v1::Swap;
+use ic_canister_log::log;
+
+const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
+
+/// The work of a periodic task, given the current time in nanoseconds since
+/// the Unix epoch.
+pub type TaskFn = fn(&mut Swap, u64) -> Result<(), String>;
+
+pub struct PeriodicTask {
+    /// Identifies the task in `Swap.periodic_tasks` and in the metrics.
+    pub name: &'static str,
+    /// The minimum time between the starts of two runs of the task. If zero,
+    /// the task runs on every heartbeat.
+    pub interval_seconds: u64,
+    pub run: TaskFn,
+}
+
+#[derive(Default)]
+pub struct TaskRegistry {
+    tasks: Vec<PeriodicTask>,
+}
+
+impl TaskRegistry {
+    /// The periodic tasks of the swap canister.
+    pub fn swap_tasks() -> Self {
+        let mut registry = Self::default();
+        registry
+            .register("purge_old_tickets", 0, purge_old_tickets)
+            .register("open_after_delay", 0, open_after_delay)
+            .register("commit_or_abort", 0, commit_or_abort);
+        registry
+    }
+
+    /// Registers a task. Tasks run in the order they are registered in.
+    ///
+    /// Panics if a task with the same name is already registered.
+    pub fn register(
+        &mut self,
+        name: &'static str,
+        interval_seconds: u64,
+        run: TaskFn,
+    ) -> &mut Self {
+        assert!(
+            self.tasks.iter().all(|task| task.name != name),
+            "Periodic task {} is registered twice",
+            name
+        );
+        self.tasks.push(PeriodicTask {
+            name,
+            interval_seconds,
+            run,
+        });
+        self
+    }
+
+    pub fn tasks(&self) -> &[PeriodicTask] {
+        &self.tasks
+    }
+
+    /// Runs the tasks that are due at `now_nanoseconds` and records their
+    /// runs in `swap.periodic_tasks`.
+    pub fn run_due_tasks(&self, swap: &mut Swap, now_nanoseconds: u64) {
+        let now_seconds = now_nanoseconds / NANOSECONDS_PER_SECOND;
+        for task in &self.tasks {
+            let last_run_timestamp_seconds = swap
+                .periodic_tasks
+                .get(task.name)
+                .and_then(|state| state.last_run_timestamp_seconds);
+            let due = last_run_timestamp_seconds.map_or(true, |last_run| {
+                last_run + task.interval_seconds <= now_seconds
+            });
+            if !due {
+                continue;
+            }
+
+            let result = (task.run)(swap, now_nanoseconds);
+            let state = swap
+                .periodic_tasks
+                .entry(task.name.to_string())
+                .or_default();
+            state.last_run_timestamp_seconds = Some(now_seconds);
+            if let Err(err) = result {
+                log!(ERROR, "Periodic task {} failed: {}", task.name, err);
+                state.error_count += 1;
+                state.last_error = Some(err);
+            }
+        }
+    }
+}
+
+fn purge_old_tickets(swap: &mut Swap, now_nanoseconds: u64) -> Result<(), String> {
+    const NUMBER_OF_TICKETS_THRESHOLD: u64 = 100_000_000; // 100M * ~size(ticket) = ~25GB
+    const TWO_DAYS_IN_NANOSECONDS: u64 = 60 * 60 * 24 * 2 * NANOSECONDS_PER_SECOND;
+    const MAX_NUMBER_OF_PRINCIPALS_TO_INSPECT: u64 = 100_000;
+
+    swap.try_purge_old_tickets(
+        || now_nanoseconds,
+        NUMBER_OF_TICKETS_THRESHOLD,
+        TWO_DAYS_IN_NANOSECONDS,
+        MAX_NUMBER_OF_PRINCIPALS_TO_INSPECT,
+    );
+    Ok(())
+}
+
+fn open_after_delay(swap: &mut Swap, now_nanoseconds: u64) -> Result<(), String> {
+    let now_seconds = now_nanoseconds / NANOSECONDS_PER_SECOND;
+    if swap.try_open_after_delay(now_seconds) {
+        log!(INFO, "Sale opened at timestamp {}", now_seconds);
+    }
+    Ok(())
+}
+
+fn commit_or_abort(swap: &mut Swap, now_nanoseconds: u64) -> Result<(), String> {
+    let now_seconds = now_nanoseconds / NANOSECONDS_PER_SECOND;
+    if swap.try_commit_or_abort(now_seconds) {
+        log!(INFO, "Swap committed/aborted at timestamp {}", now_seconds);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pb::v1::PeriodicTaskState;
+
+    fn succeed(swap: &mut Swap, _now_nanoseconds: u64) -> Result<(), String> {
+        swap.next_ticket_id = Some(swap.next_ticket_id.unwrap_or_default() + 1);
+        Ok(())
+    }
+
+    fn fail(_swap: &mut Swap, now_nanoseconds: u64) -> Result<(), String> {
+        Err(format!("failed at {}", now_nanoseconds))
+    }
+
+    #[test]
+    fn runs_due_tasks_and_records_their_runs() {
+        let mut registry = TaskRegistry::default();
+        registry
+            .register("succeed", 10, succeed)
+            .register("fail", 0, fail);
+        let mut swap = Swap::default();
+
+        registry.run_due_tasks(&mut swap, 100 * NANOSECONDS_PER_SECOND);
+        registry.run_due_tasks(&mut swap, 105 * NANOSECONDS_PER_SECOND + 1);
+        assert_eq!(swap.next_ticket_id, Some(1));
+        registry.run_due_tasks(&mut swap, 110 * NANOSECONDS_PER_SECOND + 1);
+        assert_eq!(swap.next_ticket_id, Some(2));
+
+        assert_eq!(
+            swap.periodic_tasks.get("succeed"),
+            Some(&PeriodicTaskState {
+                last_run_timestamp_seconds: Some(110),
+                error_count: 0,
+                last_error: None,
+            })
+        );
+        assert_eq!(
+            swap.periodic_tasks.get("fail"),
+            Some(&PeriodicTaskState {
+                last_run_timestamp_seconds: Some(110),
+                error_count: 3,
+                last_error: Some("failed at 110000000001".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "registered twice")]
+    fn rejects_duplicate_task_names() {
+        let mut registry = TaskRegistry::default();
+        registry
+            .register("fail", 0, fail)
+            .register("fail", 10, fail);
+    }
+}
@@ -1,4 +1,6 @@
-use crate::clients::{NnsGovernanceClient, SnsGovernanceClient, SnsRootClient};
+use crate::clients::{
+    NnsGovernanceClient, SnsGovernanceClient, SnsLedgerClient, SnsRootClient, SwapArchiveClient,
+};
 use crate::logs::{ERROR, INFO};
 use crate::memory;
 use crate::pb::v1::{
@@ -9,18 +11,24 @@ use crate::pb::v1::{
     settle_community_fund_participation_result,
     sns_neuron_recipe::Investor,
     sns_neuron_recipe::{ClaimedStatus, NeuronAttributes},
+    ArchiveFinalizedStateResponse, ArchiveSwapRecordsRequest, BuyerParticipationHistory,
     BuyerState, CanisterCallError, CfInvestment, DerivedState, DirectInvestment,
-    ErrorRefundIcpRequest, ErrorRefundIcpResponse, FinalizeSwapResponse, GetBuyerStateRequest,
-    GetBuyerStateResponse, GetBuyersTotalResponse, GetDerivedStateResponse, GetLifecycleRequest,
-    GetLifecycleResponse, GetOpenTicketRequest, GetOpenTicketResponse, GetSaleParametersRequest,
-    GetSaleParametersResponse, GetStateResponse, Init, Lifecycle,
+    ErrorRefundIcpRequest, ErrorRefundIcpResponse, ExportStateRequest, ExportStateResponse,
+    FinalizeSwapPhase, FinalizeSwapResponse, GetAuctionPriceRequest, GetAuctionPriceResponse,
+    GetBuyerParticipationHistoryRequest, GetBuyerParticipationHistoryResponse,
+    GetBuyerStateRequest, GetBuyerStateResponse, GetBuyersTotalResponse, GetDerivedStateResponse,
+    GetLifecycleRequest, GetLifecycleResponse, GetOpenTicketRequest, GetOpenTicketResponse,
+    GetSaleParametersRequest, GetSaleParametersResponse, GetSaleStatusRequest,
+    GetSaleStatusResponse, GetStateChunkRequest, GetStateChunkResponse, GetStateResponse,
+    GetTransferMemoSchemeResponse, Init, Lifecycle, LifecycleTransition,
     ListCommunityFundParticipantsRequest, ListCommunityFundParticipantsResponse,
     ListDirectParticipantsRequest, ListDirectParticipantsResponse, ListSnsNeuronRecipesRequest,
-    ListSnsNeuronRecipesResponse, NeuronId as SaleNeuronId, NewSaleTicketRequest,
-    NewSaleTicketResponse, OpenRequest, OpenResponse, Participant, RefreshBuyerTokensResponse,
+    ListSnsNeuronRecipesResponse, ListSwapRoundsRequest, ListSwapRoundsResponse,
+    NeuronId as SaleNeuronId, NewSaleTicketRequest, NewSaleTicketResponse, OpenRequest,
+    OpenResponse, Participant, ParticipationIncrement, RefreshBuyerTokensResponse,
     RestoreDappControllersResponse, SetDappControllersCallResult, SetModeCallResult,
-    SettleCommunityFundParticipationResult, SnsNeuronRecipe, Swap, SweepResult, Ticket,
-    TransferableAmount,
+    SettleCommunityFundParticipationResult, SnsNeuronRecipe, Swap, SwapArchive, SwapRound,
+    SwapStateExport, SweepResult, Ticket, TransferMemo, TransferPurpose, TransferableAmount,
 };
 use crate::types::{ScheduledVestingEvent, TransferResult};
 #[cfg(target_arch = "wasm32")]
@@ -28,9 +36,15 @@ use dfn_core::println;
 use dfn_core::CanisterId;
 use ic_base_types::PrincipalId;
 use ic_canister_log::log;
+use ic_crypto_sha::Sha256;
 use ic_ledger_core::Tokens;
-use ic_nervous_system_common::{i2d, ledger::compute_neuron_staking_subaccount_bytes};
+use ic_nervous_system_common::{
+    i2d,
+    ledger::{compute_distribution_subaccount_bytes, compute_neuron_staking_subaccount_bytes},
+    E8,
+};
 use ic_sns_governance::{
+    governance::TREASURY_SUBACCOUNT_NONCE,
     ledger::ICRC1Ledger,
     pb::v1::{
         claim_swap_neurons_request::NeuronParameters,
@@ -78,6 +92,26 @@ const LIST_COMMUNITY_FUND_PARTICIPANTS_LIMIT_CAP: u32 = 10_000;
 /// by ListSnsNeuronRecipes
 const DEFAULT_LIST_SNS_NEURON_RECIPES_LIMIT: u32 = 10_000;
 
+/// The maximum count of elements that can be returned by ExportState
+const EXPORT_STATE_LIMIT_CAP: u32 = 10_000;
+
+/// The maximum length of a chunk returned by GetStateChunk, well below the
+/// limit on the size of a response
+const GET_STATE_CHUNK_LENGTH_CAP: u32 = 1_000_000;
+
+/// The number of low bits of the memo of a ledger transfer initiated by the
+/// swap canister that hold the swap round. The high bits hold the
+/// `TransferPurpose` of the transfer.
+pub const TRANSFER_MEMO_SWAP_ROUND_BITS: u32 = 56;
+
+/// The purposes of the ledger transfers initiated by the swap canister.
+const TRANSFER_PURPOSES: [TransferPurpose; 4] = [
+    TransferPurpose::CommitIcp,
+    TransferPurpose::RefundIcp,
+    TransferPurpose::DistributeSns,
+    TransferPurpose::ErrorRefundIcp,
+];
+
 /// Range of allowed memos for neurons distributed via an SNS sale. This range is used to choose
 /// the memos of Sale neurons, and to enforce that other memos (e.g. for Airdrop neurons) do not
 /// conflict with the memos of Sale neurons.
@@ -96,6 +130,12 @@ pub const FIRST_PRINCIPAL_BYTES: [u8; PrincipalId::MAX_LENGTH_IN_BYTES] =
 /// 2. Avoid having the SNS Governance canister hit the instruction limit per message.
 pub const CLAIM_SWAP_NEURONS_BATCH_SIZE: usize = 500;
 
+/// The maximum number of buyers and neuron recipes, together, sent to the
+/// archive canister in one batch by `archive_finalized_state`. A neuron
+/// recipe is encoded in less than 300 bytes, so a batch stays well below the
+/// XNET message size limit of 2mb.
+pub const ARCHIVE_BATCH_SIZE: usize = 1_000;
+
 impl From<(Option<i32>, String)> for CanisterCallError {
     fn from((code, description): (Option<i32>, String)) -> Self {
         Self { code, description }
@@ -167,6 +207,10 @@ impl From<DerivedState> for GetDerivedStateResponse {
         GetDerivedStateResponse {
             buyer_total_icp_e8s: Some(state.buyer_total_icp_e8s),
             sns_tokens_per_icp: Some(state.sns_tokens_per_icp as f64),
+            early_participation_sns_tokens_per_icp: state
+                .early_participation_sns_tokens_per_icp
+                .map(|rate| rate as f64),
+            remaining_direct_participants: state.remaining_direct_participants,
         }
     }
 }
@@ -175,6 +219,10 @@ impl NeuronBasketConstructionParameters {
     /// Chops `total_amount_e8s` into `self.count` pieces. Each gets doled out
     /// every `self.dissolve_delay_seconds`, starting from 0.
     ///
+    /// The dissolve delays are exact multiples of the interval, without any
+    /// jitter, so the baskets of the neuron recipes can be reproduced from the
+    /// parameters of the swap alone.
+    ///
     /// # Arguments
     /// * `total_amount_e8s` - The total amount of tokens (in e8s) to be chopped up.
     fn generate_vesting_schedule(&self, total_amount_e8s: u64) -> Vec<ScheduledVestingEvent> {
@@ -219,6 +267,43 @@ pub fn apportion_approximately_equally(total: u64, len: u64) -> Vec<u64> {
     result
 }
 
+/// Apportions `excess_e8s` among participations that can each be cut back
+/// by at most the corresponding element of `cuttable_e8s`, pro rata to
+/// `cuttable_e8s`.
+///
+/// Each cutback is rounded down, and the e8s left over go one each to the
+/// cutbacks with the largest remainders of the division, the earliest one
+/// first on ties. Hence, the result sums up to `excess_e8s`, unless
+/// `cuttable_e8s` sums up to less, in which case everything is cut back.
+pub fn apportion_cutback(excess_e8s: u64, cuttable_e8s: &[u64]) -> Vec<u64> {
+    let total_cuttable_e8s: u128 = cuttable_e8s.iter().map(|&e8s| e8s as u128).sum();
+    if total_cuttable_e8s <= excess_e8s as u128 {
+        return cuttable_e8s.to_vec();
+    }
+
+    let mut cutbacks_e8s = Vec::with_capacity(cuttable_e8s.len());
+    let mut remainders = Vec::with_capacity(cuttable_e8s.len());
+    for (index, &cuttable) in cuttable_e8s.iter().enumerate() {
+        // Cannot overflow as both factors fit in 64 bits.
+        let product = excess_e8s as u128 * cuttable as u128;
+        // Fits in 64 bits as excess_e8s < total_cuttable_e8s.
+        cutbacks_e8s.push((product / total_cuttable_e8s) as u64);
+        remainders.push((product % total_cuttable_e8s, index));
+    }
+
+    // Less than cuttable_e8s.len(), as each remainder is less than
+    // total_cuttable_e8s.
+    let left_over_e8s = excess_e8s - cutbacks_e8s.iter().sum::<u64>();
+    remainders.sort_by(|(remainder_1, index_1), (remainder_2, index_2)| {
+        remainder_2.cmp(remainder_1).then(index_1.cmp(index_2))
+    });
+    for (_, index) in remainders.into_iter().take(left_over_e8s as usize) {
+        cutbacks_e8s[index] += 1;
+    }
+
+    cutbacks_e8s
+}
+
 // High level documentation in the corresponding Protobuf message.
 impl Swap {
     /// Create state from an `Init` object.
@@ -241,6 +326,15 @@ impl Swap {
             next_ticket_id: Some(0),
             purge_old_tickets_last_completion_timestamp_nanoseconds: Some(0),
             purge_old_tickets_next_principal: Some(FIRST_PRINCIPAL_BYTES.to_vec()),
+            lifecycle_history: vec![],
+            configuration_error: None,
+            periodic_tasks: BTreeMap::new(),
+            archive: None,
+            completed_rounds: vec![],
+            next_sale_neuron_memo: None,
+            sns_ledger_fee_checked: None,
+            unsold_sns: None,
+            community_fund_participation_settled: None,
         }
     }
 
@@ -292,10 +386,155 @@ impl Swap {
 
     /// The total amount of ICP contributed by direct investors.
     pub fn direct_investor_total_icp_e8s(&self) -> u64 {
+        let archived_icp_e8s = self
+            .archive
+            .as_ref()
+            .map_or(0, |archive| archive.archived_buyer_icp_e8s);
         self.buyers
             .values()
             .map(|x| x.amount_icp_e8s())
-            .fold(0, |sum, v| sum.saturating_add(v))
+            .fold(archived_icp_e8s, |sum, v| sum.saturating_add(v))
+    }
+
+    /// Returns true if ICP accepted at `now_seconds` is eligible for the
+    /// early participation bonus, i.e., if the swap has such a bonus and
+    /// its window has not yet elapsed.
+    pub fn is_early_participation(&self, now_seconds: u64) -> bool {
+        let window_seconds = match self
+            .params
+            .as_ref()
+            .and_then(|params| params.early_participation_bonus.as_ref())
+        {
+            Some(bonus) => bonus.window_seconds,
+            None => return false,
+        };
+        match self.decentralization_sale_open_timestamp_seconds {
+            Some(open_timestamp_seconds) => {
+                now_seconds < open_timestamp_seconds.saturating_add(window_seconds)
+            }
+            None => false,
+        }
+    }
+
+    /// The number of additional direct participants the swap can accept, or
+    /// `None` if `params.max_direct_participants` is not set.
+    pub fn remaining_direct_participants(&self) -> Option<u64> {
+        let max_direct_participants = self
+            .params
+            .as_ref()
+            .and_then(|params| params.max_direct_participants)?;
+        let archived_buyers = self
+            .archive
+            .as_ref()
+            .map_or(0, |archive| archive.archived_buyers);
+        Some(
+            (max_direct_participants as u64)
+                .saturating_sub(self.buyers.len() as u64)
+                .saturating_sub(archived_buyers),
+        )
+    }
+
+    /// Returns true if `buyer` cannot participate because the swap has
+    /// reached `params.max_direct_participants` and `buyer` is not one of
+    /// the existing direct participants.
+    fn is_direct_participant_limit_reached_for(&self, buyer: &PrincipalId) -> bool {
+        self.remaining_direct_participants() == Some(0)
+            && !self.buyers.contains_key(&buyer.to_string())
+    }
+
+    /// Returns true if `buyer` cannot participate because, with
+    /// `params.pro_rata_cutback`, the swap could not cut back the
+    /// participations to `max_icp_e8s` while leaving every direct
+    /// participant, `buyer` included, with `min_participant_icp_e8s`.
+    fn is_cutback_capacity_reached_for(&self, buyer: &PrincipalId) -> bool {
+        let params = match &self.params {
+            Some(params) if params.has_pro_rata_cutback() => params,
+            _ => return false,
+        };
+        if self.buyers.contains_key(&buyer.to_string()) {
+            return false;
+        }
+        (self.buyers.len() as u64 + 1)
+            .saturating_mul(params.min_participant_icp_e8s)
+            .saturating_add(self.cf_total_icp_e8s())
+            > params.max_icp_e8s
+    }
+
+    fn has_pro_rata_cutback(&self) -> bool {
+        self.params
+            .as_ref()
+            .map_or(false, |params| params.has_pro_rata_cutback())
+    }
+
+    /// Returns true if `buyer` cannot participate because it is a canister
+    /// and `init.allow_canister_participants` is not set.
+    fn is_canister_participant_refused(&self, buyer: &PrincipalId) -> bool {
+        is_canister_principal(buyer)
+            && !self
+                .init
+                .as_ref()
+                .map_or(false, |init| init.allows_canister_participants())
+    }
+
+    /// The weight of a direct participant when apportioning the SNS tokens
+    /// being offered, i.e., its ICP plus the early participation bonus on
+    /// the part of it that is eligible for the bonus.
+    fn buyer_participation_weight_e8s(&self, buyer_state: &BuyerState) -> u64 {
+        match &self.params {
+            Some(params) => params.participation_weight_e8s(
+                buyer_state.amount_icp_e8s(),
+                buyer_state.early_participation_icp_e8s(),
+            ),
+            None => buyer_state.amount_icp_e8s(),
+        }
+    }
+
+    /// The sum of the weights of all participants. The community fund is
+    /// not eligible for the early participation bonus, so its weight is
+    /// the ICP it contributed. Equal to `participant_total_icp_e8s` if the
+    /// swap has no early participation bonus.
+    pub fn participant_total_weight_e8s(&self) -> u64 {
+        let archived_weight_e8s = self
+            .archive
+            .as_ref()
+            .map_or(0, |archive| archive.archived_buyer_weight_e8s);
+        self.buyers
+            .values()
+            .map(|x| self.buyer_participation_weight_e8s(x))
+            .fold(
+                self.cf_total_icp_e8s().saturating_add(archived_weight_e8s),
+                |sum, v| sum.saturating_add(v),
+            )
+    }
+
+    /// The current price of the dutch auction, in ICP e8s per SNS token, or
+    /// `None` if the sale has no dutch auction or has not been opened.
+    fn dutch_auction_icp_e8s_per_sns_token(&self, now_seconds: u64) -> Option<u64> {
+        let open_timestamp_seconds = self.decentralization_sale_open_timestamp_seconds?;
+        self.params
+            .as_ref()?
+            .dutch_auction_icp_e8s_per_sns_token(open_timestamp_seconds, now_seconds)
+    }
+
+    /// The number of SNS tokens distributed among the participants if the
+    /// swap commits at `now_seconds`. This is all of `params.sns_token_e8s`,
+    /// unless the ICP committed does not buy them all at the price of the
+    /// dutch auction.
+    pub fn sns_token_e8s_sold(&self, now_seconds: u64) -> Result<u64, String> {
+        let sns_token_e8s = self.sns_token_e8s()?;
+        Ok(
+            match self.dutch_auction_icp_e8s_per_sns_token(now_seconds) {
+                Some(icp_e8s_per_sns_token) => self
+                    .params
+                    .as_ref()
+                    .expect("Expected params to be set")
+                    .sns_token_e8s_sold_at(
+                        self.participant_total_weight_e8s(),
+                        icp_e8s_per_sns_token,
+                    ),
+                None => sns_token_e8s,
+            },
+        )
     }
 
     /// The count of unique CommunityFund Neurons.
@@ -316,6 +555,30 @@ impl Swap {
     // --- state transition functions ------------------------------------------
     //
 
+    /// Sets the lifecycle and records the transition in the lifecycle
+    /// history.
+    fn transition_lifecycle(&mut self, lifecycle: Lifecycle, now_seconds: u64) {
+        self.transition_lifecycle_for_reason(lifecycle, now_seconds, None);
+    }
+
+    /// Like `transition_lifecycle`, but records why the transition was made
+    /// outside the regular course of the swap.
+    fn transition_lifecycle_for_reason(
+        &mut self,
+        lifecycle: Lifecycle,
+        now_seconds: u64,
+        reason: Option<String>,
+    ) {
+        self.lifecycle_history.push(LifecycleTransition {
+            from_lifecycle: self.lifecycle,
+            to_lifecycle: lifecycle as i32,
+            timestamp_seconds: now_seconds,
+            swap_round: self.open_sns_token_swap_proposal_id,
+            reason,
+        });
+        self.set_lifecycle(lifecycle);
+    }
+
     /// If the sale is ADOPTED, tries to open it (if the delay is elapsed).
     /// Returns true if a transition was made, and false otherwise.
     pub fn try_open_after_delay(&mut self, now_seconds: u64) -> bool {
@@ -323,7 +586,7 @@ impl Swap {
             // set the purge_old_ticket last principal so that the routine can start
             // in the next heartbeat
             self.purge_old_tickets_next_principal = Some(FIRST_PRINCIPAL_BYTES.to_vec());
-            self.set_lifecycle(Lifecycle::Open);
+            self.transition_lifecycle(Lifecycle::Open, now_seconds);
             return true;
         }
         false
@@ -344,14 +607,9 @@ impl Swap {
             return true;
         }
 
-        // Abort if time is up without reaching sufficient_participation.
-        if self.swap_due(now_seconds) && !self.sufficient_participation() {
-            self.abort(now_seconds);
-            return true;
-        }
-
-        // Abort if max icp is reached without reaching sufficient_participation.
-        if self.icp_target_reached() && !self.sufficient_participation() {
+        // Abort if time is up, or max icp is reached, without reaching
+        // sufficient_participation.
+        if self.can_abort(now_seconds) {
             self.abort(now_seconds);
             return true;
         }
@@ -374,8 +632,9 @@ impl Swap {
         now_seconds: u64,
         req: OpenRequest,
     ) -> Result<OpenResponse, String> {
-        if self.lifecycle() != Lifecycle::Pending {
-            return Err("Invalid lifecycle state to OPEN the swap: must be PENDING".to_string());
+        let is_next_round = self.lifecycle() != Lifecycle::Pending;
+        if is_next_round {
+            self.check_can_start_next_round(&req)?;
         }
 
         req.validate(now_seconds, self.init_or_panic())?;
@@ -397,6 +656,9 @@ impl Swap {
             ));
         }
 
+        if is_next_round {
+            self.start_next_round();
+        }
         assert!(self.params.is_none());
         self.params = req.params;
         self.cf_participants = req.cf_participants;
@@ -409,16 +671,238 @@ impl Swap {
             .unwrap_or(0);
         self.decentralization_sale_open_timestamp_seconds = Some(now_seconds + open_delay_seconds);
         if open_delay_seconds > 0 {
-            self.set_lifecycle(Lifecycle::Adopted);
+            self.transition_lifecycle(Lifecycle::Adopted, now_seconds);
         } else {
             // set the purge_old_ticket last principal so that the routine can start
             // in the next heartbeat
             self.purge_old_tickets_next_principal = Some(FIRST_PRINCIPAL_BYTES.to_vec());
-            self.set_lifecycle(Lifecycle::Open);
+            self.transition_lifecycle(Lifecycle::Open, now_seconds);
         }
         Ok(OpenResponse {})
     }
 
+    /// Checks that `open` can start the round opened by `req` after the
+    /// current one: the current round ended, it was finalized and its buyers
+    /// and neuron recipes were archived. A round can only follow a committed
+    /// round, as the dapp(s) are returned to the fallback controllers if the
+    /// first round aborts.
+    fn check_can_start_next_round(&self, req: &OpenRequest) -> Result<(), String> {
+        if !self.lifecycle_is_terminal() || !self.has_committed_round() {
+            return Err(format!(
+                "Invalid lifecycle state to OPEN the swap: must be PENDING, or COMMITTED or \
+                 ABORTED after a committed round. Current state is {:?}",
+                self.lifecycle()
+            ));
+        }
+        if self.is_finalize_swap_locked() {
+            return Err("The current round is being finalized or archived".to_string());
+        }
+        if !self.buyers.is_empty() || !self.neuron_recipes.is_empty() {
+            return Err(
+                "The buyers and neuron recipes of the current round must be archived \
+                 before the next round opens, see archive_finalized_state"
+                    .to_string(),
+            );
+        }
+        let proposal_id = req.open_sns_token_swap_proposal_id;
+        let is_known_round = self.open_sns_token_swap_proposal_id == proposal_id
+            || self
+                .completed_rounds
+                .iter()
+                .any(|round| round.open_sns_token_swap_proposal_id == proposal_id);
+        if proposal_id.is_none() || is_known_round {
+            return Err(format!(
+                "The next round must be opened by a new proposal, as its ID tells the \
+                 ledger transfers of the rounds apart. Proposal: {:?}",
+                proposal_id
+            ));
+        }
+        Ok(())
+    }
+
+    /// Moves the summary of the current round to `completed_rounds`, and
+    /// resets the state of the current round for `open`, including the
+    /// tickets and the buyers list index kept in stable memory.
+    fn start_next_round(&mut self) {
+        let round = self.current_round();
+        log!(
+            INFO,
+            "Completed round {:?} of the swap: {:?}",
+            round.open_sns_token_swap_proposal_id,
+            round
+        );
+        self.completed_rounds.push(round);
+
+        self.params = None;
+        self.cf_participants.clear();
+        self.buyers.clear();
+        self.neuron_recipes.clear();
+        self.unsold_sns = None;
+        self.archive = None;
+        self.decentralization_sale_open_timestamp_seconds = None;
+        self.purge_old_tickets_next_principal = None;
+        self.configuration_error = None;
+        self.sns_ledger_fee_checked = None;
+        self.community_fund_participation_settled = None;
+
+        memory::OPEN_TICKETS_MEMORY.with(|tickets| {
+            let mut tickets = tickets.borrow_mut();
+            let principals: Vec<_> = tickets.iter().map(|(principal, _)| principal).collect();
+            for principal in principals {
+                tickets.remove(&principal);
+            }
+        });
+        memory::BUYERS_LIST_INDEX.with(|buyer_list| {
+            let buyer_list = buyer_list.borrow_mut();
+            while buyer_list.pop().is_some() {}
+        });
+    }
+
+    /// Whether the current or an earlier round of the swap committed, after
+    /// which the dapp(s) are under the control of the SNS for good.
+    fn has_committed_round(&self) -> bool {
+        self.lifecycle() == Lifecycle::Committed
+            || self
+                .completed_rounds
+                .iter()
+                .any(|round| round.lifecycle() == Lifecycle::Committed)
+    }
+
+    /// The summary of the current round. The buyers and neuron recipes it
+    /// counts include the archived ones.
+    fn current_round(&self) -> SwapRound {
+        let (archived_buyers, archived_neuron_recipes) =
+            self.archive.as_ref().map_or((0, 0), |archive| {
+                (archive.archived_buyers, archive.archived_neuron_recipes)
+            });
+        let swap_round = self.open_sns_token_swap_proposal_id;
+        // The transitions recorded before the rounds were introduced belong
+        // to the first round.
+        let is_first_round = self.completed_rounds.is_empty();
+        let end_timestamp_seconds = self
+            .lifecycle_history
+            .iter()
+            .rev()
+            .find(|transition| {
+                (transition.swap_round == swap_round
+                    || (transition.swap_round.is_none() && is_first_round))
+                    && transition.to_lifecycle().is_terminal()
+            })
+            .map(|transition| transition.timestamp_seconds);
+        SwapRound {
+            open_sns_token_swap_proposal_id: swap_round,
+            lifecycle: self.lifecycle,
+            params: self.params.clone(),
+            decentralization_sale_open_timestamp_seconds: self
+                .decentralization_sale_open_timestamp_seconds,
+            end_timestamp_seconds,
+            direct_participants: (self.buyers.len() as u64).saturating_add(archived_buyers),
+            direct_participant_icp_e8s: self.direct_investor_total_icp_e8s(),
+            cf_participant_icp_e8s: self.cf_total_icp_e8s(),
+            neuron_recipes: (self.neuron_recipes.len() as u64)
+                .saturating_add(archived_neuron_recipes),
+            archive: self.archive.clone(),
+        }
+    }
+
+    /// Lists the rounds of the swap in the order they were opened, the
+    /// current one last.
+    pub fn list_swap_rounds(&self, _request: &ListSwapRoundsRequest) -> ListSwapRoundsResponse {
+        let mut rounds = self.completed_rounds.clone();
+        rounds.push(self.current_round());
+        ListSwapRoundsResponse { rounds }
+    }
+
+    /// Checks that the transaction fee of the SNS ledger is the one in
+    /// `init.transaction_fee_e8s`, which the swap relies on to fund the
+    /// neurons of the participants. The check runs when the swap opens, and
+    /// again before finalize moves any funds of a committed swap, as the fee
+    /// can change in between. On a mismatch, records a configuration error and
+    /// aborts the swap, such that finalize refunds the participants instead
+    /// of distributing SNS tokens.
+    ///
+    /// Returns an error if the fee could not be checked, in which case
+    /// finalize must not run, and the check is retried by the next attempt.
+    /// Once a committed swap passed the check, it is not checked again, as
+    /// finalize may have moved funds since.
+    pub async fn check_sns_ledger_fee(
+        &mut self,
+        sns_ledger_client: &mut impl SnsLedgerClient,
+        now_seconds: u64,
+    ) -> Result<(), String> {
+        if !self.needs_sns_ledger_fee_check() {
+            return Ok(());
+        }
+        let expected_fee_e8s = self.init_and_validate()?.transaction_fee_e8s_or_panic();
+        let fee_e8s = sns_ledger_client.transfer_fee().await.map_err(|err| {
+            format!(
+                "Could not check the transaction fee of the SNS ledger: {:?}",
+                err
+            )
+        })?;
+        // The state may have changed while awaiting the fee.
+        if !self.needs_sns_ledger_fee_check() {
+            return Ok(());
+        }
+        if fee_e8s == expected_fee_e8s {
+            if self.lifecycle() == Lifecycle::Committed {
+                self.sns_ledger_fee_checked = Some(true);
+            }
+            return Ok(());
+        }
+        if self.is_finalize_swap_locked() {
+            return Err(
+                "Could not check the transaction fee of the SNS ledger while the swap is \
+                 being finalized"
+                    .to_string(),
+            );
+        }
+
+        let configuration_error = format!(
+            "The transaction fee of the SNS ledger ({} e8s) differs from \
+             init.transaction_fee_e8s ({} e8s)",
+            fee_e8s, expected_fee_e8s
+        );
+        log!(
+            ERROR,
+            "Aborting the swap due to a configuration error: {}",
+            configuration_error
+        );
+        self.transition_lifecycle_for_reason(
+            Lifecycle::Aborted,
+            now_seconds,
+            Some(configuration_error.clone()),
+        );
+        self.configuration_error = Some(configuration_error);
+        Ok(())
+    }
+
+    /// Whether the fee of the SNS ledger must be checked before the swap can
+    /// proceed, see `check_sns_ledger_fee`.
+    fn needs_sns_ledger_fee_check(&self) -> bool {
+        match self.lifecycle() {
+            Lifecycle::Open => true,
+            Lifecycle::Committed => {
+                self.sns_ledger_fee_checked != Some(true)
+                    && self.configuration_error.is_none()
+                    && !self.has_started_icp_transfers()
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether finalize started to transfer the ICP of the buyers, e.g. before
+    /// `sns_ledger_fee_checked` was introduced.
+    fn has_started_icp_transfers(&self) -> bool {
+        self.archive.is_some()
+            || self.buyers.values().any(|buyer_state| {
+                buyer_state
+                    .icp
+                    .as_ref()
+                    .map_or(false, |icp| icp.transfer_start_timestamp_seconds != 0)
+            })
+    }
+
     /// Computes `amount_icp_e8s` scaled by (`total_sns_e8s` divided by
     /// `total_icp_e8s`), but perform the computation in integer space
     /// by computing `(amount_icp_e8s * total_sns_e8s) /
@@ -434,6 +918,56 @@ impl Swap {
         r as u64
     }
 
+    /// With `params.pro_rata_cutback`, cuts back the direct participations
+    /// so that the ICP committed does not exceed `max_icp_e8s`. Only the
+    /// part of each participation above `min_participant_icp_e8s` is cut
+    /// back, see `apportion_cutback`, and the buyers are considered in the
+    /// order of their principals.
+    fn cut_back_participations(&mut self) {
+        let params = self.params.as_ref().expect("Expected params to be set");
+        let max_icp_e8s = params.max_icp_e8s;
+        let min_participant_icp_e8s = params.min_participant_icp_e8s;
+        let excess_e8s = self.participant_total_icp_e8s().saturating_sub(max_icp_e8s);
+        if !params.has_pro_rata_cutback() || excess_e8s == 0 {
+            return;
+        }
+
+        let cuttable_e8s = self
+            .buyers
+            .values()
+            .map(|buyer_state| {
+                buyer_state
+                    .amount_icp_e8s()
+                    .saturating_sub(min_participant_icp_e8s)
+            })
+            .collect::<Vec<_>>();
+        let cutbacks_e8s = apportion_cutback(excess_e8s, &cuttable_e8s);
+        let total_cutback_e8s = cutbacks_e8s.iter().sum::<u64>();
+        if total_cutback_e8s < excess_e8s {
+            log!(
+                ERROR,
+                "Only {} of the {} e8s committed above max_icp_e8s ({}) can be cut back",
+                total_cutback_e8s,
+                excess_e8s,
+                max_icp_e8s
+            );
+        }
+
+        for ((buyer, buyer_state), cutback_e8s) in self.buyers.iter_mut().zip(cutbacks_e8s) {
+            if cutback_e8s == 0 {
+                continue;
+            }
+            buyer_state.cut_back_icp_e8s(cutback_e8s, DEFAULT_TRANSFER_FEE.get_e8s());
+            log!(
+                INFO,
+                "Cut back the participation of buyer {} by {} e8s to {} e8s",
+                buyer,
+                cutback_e8s,
+                buyer_state.amount_icp_e8s()
+            );
+        }
+    }
+
     /// Precondition: lifecycle == OPEN && sufficient_participation && (swap_due || icp_target_reached)
     ///
     /// Postcondition: lifecycle == COMMITTED
@@ -441,6 +975,7 @@ impl Swap {
         assert_eq!(self.lifecycle(), Lifecycle::Open);
         assert!(self.sufficient_participation());
         assert!(self.swap_due(now_seconds) || self.icp_target_reached());
+        self.cut_back_participations();
         // Safe as `params` must be specified in call to `open`.
         let params = self.params.as_ref().expect("Expected params to be set");
 
@@ -462,9 +997,22 @@ impl Swap {
         // OPEN without transferring tokens being offered to the swap canister.
         assert!(sns_being_offered_e8s > 0);
         // Note that this value has to be > 0 as we have > 0
-        // participants each with > 0 ICP contributed.
-        let total_participant_icp_e8s = NonZeroU64::try_from(self.participant_total_icp_e8s())
-            .expect("participant_total_icp_e8s must be greater than 0");
+        // participants each with > 0 ICP contributed. Without an early
+        // participation bonus, the weight of a participant is the ICP it
+        // contributed; with one, early ICP weighs more.
+        let total_participant_weight_e8s =
+            NonZeroU64::try_from(self.participant_total_weight_e8s())
+                .expect("participant_total_weight_e8s must be greater than 0");
+        // With a dutch auction, the participants may not buy all of the SNS
+        // tokens being offered at the price reached by the auction.
+        let sns_being_sold_e8s = self
+            .sns_token_e8s_sold(now_seconds)
+            .expect("Expected params to be set");
+
+        // The neurons of this round follow the ones of the earlier rounds.
+        let memo_offset = self
+            .next_sale_neuron_memo
+            .unwrap_or(SALE_NEURON_MEMO_RANGE_START);
 
         // Keep track of SNS tokens sold just to check that the amount
         // is correct at the end.
@@ -476,9 +1024,9 @@ impl Swap {
         // =====================================================================
         for (buyer_principal, buyer_state) in self.buyers.iter() {
             let amount_sns_e8s = Swap::scale(
-                buyer_state.amount_icp_e8s(),
-                sns_being_offered_e8s,
-                total_participant_icp_e8s,
+                self.buyer_participation_weight_e8s(buyer_state),
+                sns_being_sold_e8s,
+                total_participant_weight_e8s,
             );
 
             let parsed_principal = string_to_principal(buyer_principal).unwrap();
@@ -487,7 +1035,7 @@ impl Swap {
                     &parsed_principal,
                     amount_sns_e8s,
                     neuron_basket_construction_parameters,
-                    SALE_NEURON_MEMO_RANGE_START,
+                    memo_offset,
                 );
             neurons.extend(direct_participant_sns_neuron_recipes);
 
@@ -500,13 +1048,13 @@ impl Swap {
         // investors in the swap use the NNS Governance principal_id, there can be
         // neuron id collisions, so there must be a global memo used for all baskets
         // for all CF investors.
-        let mut global_cf_memo: u64 = SALE_NEURON_MEMO_RANGE_START;
+        let mut global_cf_memo: u64 = memo_offset;
         for cf_participant in self.cf_participants.iter() {
             for cf_neuron in cf_participant.cf_neurons.iter() {
                 let amount_sns_e8s = Swap::scale(
                     cf_neuron.amount_icp_e8s,
-                    sns_being_offered_e8s,
-                    total_participant_icp_e8s,
+                    sns_being_sold_e8s,
+                    total_participant_weight_e8s,
                 );
 
                 let parsed_principal =
@@ -541,8 +1089,27 @@ impl Swap {
 		    params.sns_token_e8s,
 		    params.sns_token_e8s - total_sns_tokens_sold_e8s
         );
+        if let Some(last_memo) = neurons
+            .iter()
+            .filter_map(|recipe| recipe.neuron_attributes.as_ref())
+            .map(|attributes| attributes.memo)
+            .max()
+        {
+            self.next_sale_neuron_memo = Some(last_memo + 1);
+        }
         self.neuron_recipes = neurons;
-        self.set_lifecycle(Lifecycle::Committed);
+        // The SNS tokens not bought at the price of the dutch auction are
+        // returned to the treasury by `sweep_sns`. Rounding leftovers are not.
+        let unsold_sns_e8s = sns_being_offered_e8s.saturating_sub(sns_being_sold_e8s);
+        self.unsold_sns = if unsold_sns_e8s > 0 {
+            Some(TransferableAmount {
+                amount_e8s: unsold_sns_e8s,
+                ..Default::default()
+            })
+        } else {
+            None
+        };
+        self.transition_lifecycle(Lifecycle::Committed, now_seconds);
     }
 
     /// Precondition:
@@ -555,7 +1122,7 @@ impl Swap {
         assert_eq!(self.lifecycle(), Lifecycle::Open);
         assert!(self.swap_due(now_seconds) || self.icp_target_reached());
         assert!(!self.sufficient_participation());
-        self.set_lifecycle(Lifecycle::Aborted);
+        self.transition_lifecycle(Lifecycle::Aborted, now_seconds);
     }
 
     /// Retrieves the balance of 'this' canister on the SNS token
@@ -610,11 +1177,24 @@ impl Swap {
     /// to the subaccount can be reclaimed using `error_refund_icp`
     /// once this swap is closed (committed or aborted).
     ///
+    /// ICP accepted within the window of the early participation bonus
+    /// (see `Params.early_participation_bonus`), as determined by
+    /// `now_seconds`, is recorded as eligible for the bonus.
+    ///
+    /// With `Params.pro_rata_cutback`, ICP is still accepted after
+    /// `max_icp_e8s` is reached, and the excess is cut back when the swap
+    /// commits.
+    ///
+    /// The ICP accepted by a call is recorded in the buyer's participation
+    /// history, see `BuyerParticipationHistory`, and returned as the receipt
+    /// of the call.
+    ///
     /// TODO(NNS1-1682): attempt to refund ICP that cannot be accepted.
     pub async fn refresh_buyer_token_e8s(
         &mut self,
         buyer: PrincipalId,
         this_canister: CanisterId,
+        now_seconds: u64,
         icp_ledger: &dyn ICRC1Ledger,
     ) -> Result<RefreshBuyerTokensResponse, String> {
         if self.lifecycle() != Lifecycle::Open {
@@ -623,9 +1203,15 @@ impl Swap {
                     .to_string(),
             );
         }
-        if self.icp_target_reached() {
+        if self.icp_target_reached() && !self.has_pro_rata_cutback() {
             return Err("The ICP target for this token swap has already been reached.".to_string());
         }
+        if self.is_canister_participant_refused(&buyer) {
+            return Err(format!(
+                "The buyer {} is a canister, and canisters are not allowed to participate in this swap",
+                buyer
+            ));
+        }
 
         // Look for the token balance of the specified principal's subaccount on 'this' canister.
         let account = Account {
@@ -652,7 +1238,7 @@ impl Swap {
         let participant_total_icp_e8s = self.participant_total_icp_e8s();
         let params = &self.params.as_ref().expect("Expected params to be set"); // Safe as lifecycle is OPEN.
         let max_icp_e8s = params.max_icp_e8s;
-        if participant_total_icp_e8s >= max_icp_e8s {
+        if participant_total_icp_e8s >= max_icp_e8s && !params.has_pro_rata_cutback() {
             if participant_total_icp_e8s > max_icp_e8s {
                 log!(
                     ERROR,
@@ -664,8 +1250,24 @@ impl Swap {
             // Nothing we can do for this buyer.
             return Err("The swap has already reached its target".to_string());
         }
-        // Subtraction safe because of the preceding if-statement.
-        let max_increment_e8s = max_icp_e8s - participant_total_icp_e8s;
+        if self.is_direct_participant_limit_reached_for(&buyer) {
+            return Err(format!(
+                "The swap has already reached its limit of {} direct participants",
+                params.max_direct_participants.unwrap_or_default()
+            ));
+        }
+        if self.is_cutback_capacity_reached_for(&buyer) {
+            return Err(
+                "The swap is oversubscribed and cannot accept more direct participants".to_string(),
+            );
+        }
+        let max_increment_e8s = if params.has_pro_rata_cutback() {
+            // The excess over max_icp_e8s is cut back when the swap commits.
+            u64::MAX
+        } else {
+            // Subtraction safe because of the preceding if-statement.
+            max_icp_e8s - participant_total_icp_e8s
+        };
 
         // Check that the minimum amount has been transferred before
         // actually creating an entry for the buyer.
@@ -687,6 +1289,7 @@ impl Swap {
             return Ok(RefreshBuyerTokensResponse {
                 icp_accepted_participation_e8s: e8s,
                 icp_ledger_account_balance_e8s: e8s,
+                participation_increment: None,
             });
         }
         // Subtraction safe because of the preceding if-statement.
@@ -717,6 +1320,7 @@ impl Swap {
 
         // Try to fetch the current ticket of the buyer
         let principal = Blob::from_bytes(buyer.as_slice().into());
+        let mut ticket_id = None;
         if let Some(ticket_sns_sale_canister) =
             memory::OPEN_TICKETS_MEMORY.with(|m| m.borrow().get(&principal))
         {
@@ -738,6 +1342,7 @@ impl Swap {
             // The requested balance in the ticket matches the balance to be topped up in the sale
             // --> Delete fully executed ticket, if it exists and proceed with the top up
             memory::OPEN_TICKETS_MEMORY.with(|m| m.borrow_mut().remove(&principal));
+            ticket_id = Some(ticket_sns_sale_canister.ticket_id);
             // If there exists no ticket for the buyer, the payment flow will simply ignore the ticket
         }
 
@@ -753,6 +1358,7 @@ impl Swap {
                 })?;
         }
 
+        let is_early_participation = self.is_early_participation(now_seconds);
         let buyer_state = self
             .buyers
             .entry(buyer.to_string())
@@ -761,8 +1367,32 @@ impl Swap {
                     amount_e8s: 0,
                     ..TransferableAmount::default()
                 }),
+                early_participation_icp_e8s: None,
+                cutback_icp: None,
             });
         buyer_state.set_amount_icp_e8s(new_balance_e8s);
+        let accepted_increment_e8s = new_balance_e8s.saturating_sub(old_amount_icp_e8s);
+        if is_early_participation {
+            buyer_state.add_early_participation_icp_e8s(accepted_increment_e8s);
+        }
+        let participation_increment = if accepted_increment_e8s > 0 {
+            let increment = ParticipationIncrement {
+                amount_icp_e8s: accepted_increment_e8s,
+                timestamp_seconds: now_seconds,
+                ticket_id,
+            };
+            memory::PARTICIPATION_HISTORIES.with(|histories| {
+                let mut histories = histories.borrow_mut();
+                let mut history = histories
+                    .get(&principal)
+                    .unwrap_or_else(|| BuyerParticipationHistory::new(old_amount_icp_e8s));
+                history.record(increment.clone());
+                histories.insert(principal, history);
+            });
+            Some(increment)
+        } else {
+            None
+        };
         log!(
             INFO,
             "Refresh_buyer_tokens for buyer {}; old e8s {}; new e8s {}",
@@ -777,6 +1407,7 @@ impl Swap {
         Ok(RefreshBuyerTokensResponse {
             icp_accepted_participation_e8s: buyer_state.amount_icp_e8s(),
             icp_ledger_account_balance_e8s: e8s,
+            participation_increment,
         })
     }
 
@@ -793,6 +1424,7 @@ impl Swap {
         &mut self,
         sns_root_client: &mut impl SnsRootClient,
         caller: PrincipalId,
+        now_seconds: u64,
     ) -> RestoreDappControllersResponse {
         // Require authorization.
         let nns_governance = self.init_or_panic().nns_governance_or_panic();
@@ -803,9 +1435,24 @@ impl Swap {
             );
         }
 
+        // Once a round committed, the dapp(s) stay under the control of the
+        // SNS, even if opening a later round fails.
+        if self.has_committed_round() {
+            return RestoreDappControllersResponse {
+                possibility: Some(restore_dapp_controllers_response::Possibility::Err(
+                    CanisterCallError {
+                        code: None,
+                        description: "The dapp(s) cannot be restored after a round of the swap \
+                                      committed"
+                            .to_string(),
+                    },
+                )),
+            };
+        }
+
         // With the restoration of the dapp(s) to the fallback controllers, the Sale
         // is now aborted.
-        self.set_lifecycle(Lifecycle::Aborted);
+        self.transition_lifecycle(Lifecycle::Aborted, now_seconds);
 
         let set_dapp_controllers_result = self.set_dapp_controllers(sns_root_client).await;
         match set_dapp_controllers_result {
@@ -854,7 +1501,7 @@ impl Swap {
     /// restore the dapp canisters to the fallback controller ids.
     /// The lifecycle MUST be set to Aborted via the commit method.
     pub fn should_restore_dapp_control(&self) -> bool {
-        self.lifecycle() == Lifecycle::Aborted
+        self.lifecycle() == Lifecycle::Aborted && !self.has_committed_round()
     }
 
     /// Calls SNS Root with the Sale canister's configured
@@ -954,6 +1601,9 @@ impl Swap {
     /// If the swap ended unsuccessfully (i.e. it is in the Lifecycle::Aborted
     /// phase), then ICP is send back to the buyers.
     ///
+    /// The subactions are the phases of [FinalizeSwapPhase], which can also be
+    /// run individually by `finalize_phase`.
+    ///
     /// The argument 'now_fn' a function that returns the current time
     /// for bookkeeping of transfers. For easier testing, it is given
     /// an argument that is 'false' to get the timestamp when a
@@ -1032,60 +1682,287 @@ impl Swap {
     ) -> FinalizeSwapResponse {
         let mut finalize_swap_response = FinalizeSwapResponse::default();
 
+        // The check of the first phase fails if the Sale is not in a terminal
+        // state yet.
+        for phase in self.finalize_phases() {
+            if let Err(error_message) = self.check_finalize_phase(phase) {
+                finalize_swap_response.set_error_message(error_message);
+                return finalize_swap_response;
+            }
+            self.run_finalize_phase(
+                phase,
+                &mut finalize_swap_response,
+                now_fn,
+                sns_root_client,
+                sns_governance_client,
+                icp_ledger,
+                sns_ledger,
+                nns_governance_client,
+            )
+            .await;
+            if finalize_swap_response.has_error_message() {
+                return finalize_swap_response;
+            }
+        }
+
+        finalize_swap_response
+    }
+
+    /// The phases finalize runs, in order.
+    pub fn finalize_phases(&self) -> Vec<FinalizeSwapPhase> {
+        // Transfer the ICP tokens from the Sale canister, and settle the
+        // CommunityFund's participation in the Sale (if any).
+        let mut phases = vec![
+            FinalizeSwapPhase::SweepIcp,
+            FinalizeSwapPhase::SettleCommunityFund,
+        ];
+        if self.should_restore_dapp_control() {
+            // In the case of returning control of the dapp(s) to the fallback
+            // controllers, finalize() need not do any more work.
+            phases.push(FinalizeSwapPhase::SetDappControllers);
+        } else if self.lifecycle() == Lifecycle::Committed {
+            // Once SNS tokens have been distributed to the correct accounts,
+            // claim them as neurons on behalf of the Sale participants.
+            phases.extend([
+                FinalizeSwapPhase::SweepSns,
+                FinalizeSwapPhase::ClaimNeurons,
+                FinalizeSwapPhase::SetMode,
+            ]);
+        }
+        // A later round that aborted leaves the dapp(s) under the control of
+        // the SNS, and has no SNS tokens to distribute.
+        phases
+    }
+
+    /// Checks that `phase` is one of the phases of the finalization of the
+    /// swap in its current state.
+    fn check_finalize_phase(&self, phase: FinalizeSwapPhase) -> Result<(), String> {
         if !self.lifecycle_is_terminal() {
-            finalize_swap_response.set_error_message(format!(
+            return Err(format!(
                 "The Sale can only be finalized in the COMMITTED or ABORTED states. Current state is {:?}",
                 self.lifecycle()
             ));
-            return finalize_swap_response;
         }
-
-        // Transfer the ICP tokens from the Sale canister.
-        finalize_swap_response.set_sweep_icp_result(self.sweep_icp(now_fn, icp_ledger).await);
-        if finalize_swap_response.has_error_message() {
-            return finalize_swap_response;
+        match phase {
+            FinalizeSwapPhase::Unspecified => {
+                Err("The phase of the finalization must be specified".to_string())
+            }
+            FinalizeSwapPhase::SweepIcp | FinalizeSwapPhase::SettleCommunityFund => Ok(()),
+            FinalizeSwapPhase::SetDappControllers => {
+                if !self.should_restore_dapp_control() {
+                    return Err("The dapp canisters are only returned to their fallback \
+                        controllers if the first round of the Sale aborted"
+                        .to_string());
+                }
+                Ok(())
+            }
+            FinalizeSwapPhase::SweepSns
+            | FinalizeSwapPhase::ClaimNeurons
+            | FinalizeSwapPhase::SetMode => {
+                if self.lifecycle() != Lifecycle::Committed {
+                    return Err(format!(
+                        "SNS tokens are only distributed if the Sale committed. Current state is {:?}",
+                        self.lifecycle()
+                    ));
+                }
+                // Don't distribute SNS tokens if the neurons would not be funded as
+                // expected.
+                if let Some(configuration_error) = &self.configuration_error {
+                    return Err(format!(
+                        "SNS tokens cannot be distributed due to a configuration error: {}",
+                        configuration_error
+                    ));
+                }
+                Ok(())
+            }
         }
+    }
 
-        // Settle the CommunityFund's participation in the Sale (if any).
-        finalize_swap_response.set_settle_community_fund_participation_result(
-            self.settle_community_fund_participation(nns_governance_client)
-                .await,
-        );
-        if finalize_swap_response.has_error_message() {
-            return finalize_swap_response;
+    /// Checks that the phases preceding `phase` completed, which finalize
+    /// ensures by running them in order.
+    fn check_finalize_phase_prerequisites(&self, phase: FinalizeSwapPhase) -> Result<(), String> {
+        match phase {
+            FinalizeSwapPhase::SweepSns => {
+                // The ICP of the buyers, including the refunds of the
+                // cutback, must have reached its destination before the
+                // participants get their SNS tokens.
+                let fee_e8s = DEFAULT_TRANSFER_FEE.get_e8s();
+                let unswept = self
+                    .buyers
+                    .values()
+                    .filter(|buyer_state| {
+                        let icp_swept = buyer_state
+                            .icp
+                            .as_ref()
+                            .map_or(false, |icp| icp.is_settled(fee_e8s));
+                        let cutback_refunded = buyer_state
+                            .cutback_icp
+                            .as_ref()
+                            .map_or(true, |cutback_icp| cutback_icp.is_settled(fee_e8s));
+                        !icp_swept || !cutback_refunded
+                    })
+                    .count();
+                if unswept > 0 {
+                    return Err(format!(
+                        "The ICP of {} buyer(s) has not been swept yet, \
+                        run the SWEEP_ICP phase first",
+                        unswept
+                    ));
+                }
+                if self.community_fund_participation_settled != Some(true) {
+                    return Err(
+                        "The Community Fund participation has not been settled yet, \
+                        run the SETTLE_COMMUNITY_FUND phase first"
+                            .to_string(),
+                    );
+                }
+                Ok(())
+            }
+            FinalizeSwapPhase::ClaimNeurons => {
+                let sns_fee_e8s = self.init_and_validate()?.transaction_fee_e8s_or_panic();
+                let unswept = self
+                    .neuron_recipes
+                    .iter()
+                    .filter(|recipe| {
+                        !recipe
+                            .sns
+                            .as_ref()
+                            .map_or(false, |sns| sns.is_settled(sns_fee_e8s))
+                    })
+                    .count();
+                if unswept > 0 {
+                    return Err(format!(
+                        "The SNS tokens of {} neuron recipe(s) have not been swept yet, \
+                        run the SWEEP_SNS phase first",
+                        unswept
+                    ));
+                }
+                Ok(())
+            }
+            FinalizeSwapPhase::SetMode => {
+                let unclaimed = self
+                    .neuron_recipes
+                    .iter()
+                    .filter(|recipe| recipe.claimed_status != Some(ClaimedStatus::Success as i32))
+                    .count();
+                if unclaimed > 0 {
+                    return Err(format!(
+                        "{} neuron(s) have not been claimed yet, run the CLAIM_NEURONS phase first",
+                        unclaimed
+                    ));
+                }
+                Ok(())
+            }
+            _ => Ok(()),
         }
+    }
 
-        if self.should_restore_dapp_control() {
+    /// Runs `phase` and records its result in `finalize_swap_response`.
+    #[allow(clippy::too_many_arguments)]
+    async fn run_finalize_phase(
+        &mut self,
+        phase: FinalizeSwapPhase,
+        finalize_swap_response: &mut FinalizeSwapResponse,
+        now_fn: fn(bool) -> u64,
+        sns_root_client: &mut impl SnsRootClient,
+        sns_governance_client: &mut impl SnsGovernanceClient,
+        icp_ledger: &dyn ICRC1Ledger,
+        sns_ledger: &dyn ICRC1Ledger,
+        nns_governance_client: &mut impl NnsGovernanceClient,
+    ) {
+        match phase {
+            FinalizeSwapPhase::Unspecified => {}
+            FinalizeSwapPhase::SweepIcp => finalize_swap_response
+                .set_sweep_icp_result(self.sweep_icp(now_fn, icp_ledger).await),
+            FinalizeSwapPhase::SettleCommunityFund => {
+                let result = self
+                    .settle_community_fund_participation(nns_governance_client)
+                    .await;
+                if result.is_successful_settlement() {
+                    self.community_fund_participation_settled = Some(true);
+                }
+                finalize_swap_response.set_settle_community_fund_participation_result(result)
+            }
             // Restore controllers of dapp canisters to their original
             // owners (i.e. self.init.fallback_controller_principal_ids).
-            finalize_swap_response.set_set_dapp_controllers_result(
-                self.set_dapp_controllers_for_finalize(sns_root_client)
-                    .await,
-            );
+            FinalizeSwapPhase::SetDappControllers => finalize_swap_response
+                .set_set_dapp_controllers_result(
+                    self.set_dapp_controllers_for_finalize(sns_root_client)
+                        .await,
+                ),
+            FinalizeSwapPhase::SweepSns => finalize_swap_response
+                .set_sweep_sns_result(self.sweep_sns(now_fn, sns_ledger).await),
+            FinalizeSwapPhase::ClaimNeurons => finalize_swap_response
+                .set_claim_neuron_result(self.claim_swap_neurons(sns_governance_client).await),
+            FinalizeSwapPhase::SetMode => finalize_swap_response.set_set_mode_call_result(
+                Self::set_sns_governance_to_normal_mode(sns_governance_client).await,
+            ),
+        }
+    }
 
-            // In the case of returning control of the dapp(s) to the fallback
-            // controllers, finalize() need not do any more work, so always return
-            // and end execution.
-            return finalize_swap_response;
+    /// Runs a single phase of finalize, e.g. to retry the phase a
+    /// finalization is stuck at. Returns the result of the phase only.
+    ///
+    /// Like finalize, it is only allowed to run if the Sale is in a terminal
+    /// state and no finalization is in progress. Additionally, a phase only
+    /// runs once the phases it depends on completed, e.g. SNS tokens are
+    /// only swept once the ICP was swept and the Community Fund participation
+    /// settled, and neurons are only claimed once their SNS tokens were
+    /// swept. All phases are idempotent: running one again only retries the
+    /// transfers and claims that did not succeed before.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn finalize_phase(
+        &mut self,
+        phase: FinalizeSwapPhase,
+        now_fn: fn(bool) -> u64,
+        sns_root_client: &mut impl SnsRootClient,
+        sns_governance_client: &mut impl SnsGovernanceClient,
+        icp_ledger: &dyn ICRC1Ledger,
+        sns_ledger: &dyn ICRC1Ledger,
+        nns_governance_client: &mut impl NnsGovernanceClient,
+    ) -> FinalizeSwapResponse {
+        if let Err(error_message) = self.lock_finalize_swap() {
+            return FinalizeSwapResponse::with_error(error_message);
         }
 
-        // Transfer the SNS tokens from the Sale canister.
-        finalize_swap_response.set_sweep_sns_result(self.sweep_sns(now_fn, sns_ledger).await);
-        if finalize_swap_response.has_error_message() {
-            return finalize_swap_response;
+        let mut finalize_swap_response = FinalizeSwapResponse::default();
+        match self
+            .check_finalize_phase(phase)
+            .and_then(|()| self.check_finalize_phase_prerequisites(phase))
+        {
+            Ok(()) => {
+                self.run_finalize_phase(
+                    phase,
+                    &mut finalize_swap_response,
+                    now_fn,
+                    sns_root_client,
+                    sns_governance_client,
+                    icp_ledger,
+                    sns_ledger,
+                    nns_governance_client,
+                )
+                .await
+            }
+            Err(error_message) => finalize_swap_response.set_error_message(error_message),
         }
 
-        // Once SNS tokens have been distributed to the correct accounts, claim
-        // them as neurons on behalf of the Sale participants.
-        finalize_swap_response
-            .set_claim_neuron_result(self.claim_swap_neurons(sns_governance_client).await);
         if finalize_swap_response.has_error_message() {
-            return finalize_swap_response;
+            log!(
+                ERROR,
+                "The finalization phase {:?} did not complete successfully. \n\
+                finalize_swap_response: {finalize_swap_response:?}",
+                phase
+            );
+        } else {
+            log!(
+                INFO,
+                "The finalization phase {:?} completed successfully. \n\
+                finalize_swap_response: {finalize_swap_response:?}",
+                phase
+            );
         }
 
-        finalize_swap_response.set_set_mode_call_result(
-            Self::set_sns_governance_to_normal_mode(sns_governance_client).await,
-        );
+        self.unlock_finalize_swap();
 
         finalize_swap_response
     }
@@ -1521,6 +2398,16 @@ impl Swap {
                     ));
                 }
             }
+            if let Some(cutback_icp) = &buyer_state.cutback_icp {
+                if cutback_icp.transfer_success_timestamp_seconds == 0 {
+                    // The cutback is refunded using the normal mechanism.
+                    return ErrorRefundIcpResponse::new_precondition_error(format!(
+                        "ICP cannot be refunded as principal {} has {} ICP (e8s) cut back \
+                         from its participation that are yet to be refunded",
+                        source_principal_id, cutback_icp.amount_e8s
+                    ));
+                }
+            }
             // This buyer has participated in the swap, but all ICP
             // has already been disbursed, either back to the buyer
             // (aborted) or to the SNS Governance canister
@@ -1563,7 +2450,7 @@ impl Swap {
                 DEFAULT_TRANSFER_FEE.get_e8s(),
                 Some(source_subaccount),
                 dst,
-                0, // memo
+                self.transfer_memo(TransferPurpose::ErrorRefundIcp),
             )
             .await;
 
@@ -1629,6 +2516,12 @@ impl Swap {
 
         // The following methods are safe to call since we validated Init in the above block
         let sns_governance = init.sns_governance_or_panic();
+        let refund_memo = self.transfer_memo(TransferPurpose::RefundIcp);
+        let transfer_memo = if lifecycle == Lifecycle::Committed {
+            self.transfer_memo(TransferPurpose::CommitIcp)
+        } else {
+            refund_memo
+        };
 
         let mut sweep_result = SweepResult::default();
 
@@ -1681,45 +2574,42 @@ impl Swap {
                     DEFAULT_TRANSFER_FEE,
                     Some(subaccount),
                     &dst,
+                    transfer_memo,
                     icp_ledger,
                 )
                 .await;
-            match result {
-                // AmountToSmall should never happen as the amount contributed is checked in
-                // `refresh_buyer_tokens`. In the case of a bug due to programmer error,
-                // increment the invalid field. This will require a manual intervention
-                // via an upgrade to correct
-                TransferResult::AmountTooSmall => {
-                    sweep_result.invalid += 1;
-                }
-                TransferResult::AlreadyStarted => {
-                    sweep_result.skipped += 1;
-                }
-                TransferResult::Success(_) => {
-                    sweep_result.success += 1;
-                }
-                TransferResult::Failure(_) => {
-                    sweep_result.failure += 1;
+            record_icp_sweep(&mut sweep_result, icp_transferable_amount, &result);
+
+            // Refund the ICP cut back from the participation of the buyer
+            // when the swap committed, see `Params.pro_rata_cutback`.
+            if lifecycle == Lifecycle::Committed {
+                if let Some(cutback_icp) = buyer_state.cutback_icp.as_mut() {
+                    let buyer_account = Account {
+                        owner: principal.0,
+                        subaccount: None,
+                    };
+                    let result = cutback_icp
+                        .transfer_helper(
+                            now_fn,
+                            DEFAULT_TRANSFER_FEE,
+                            Some(subaccount),
+                            &buyer_account,
+                            refund_memo,
+                            icp_ledger,
+                        )
+                        .await;
+                    record_icp_sweep(&mut sweep_result, cutback_icp, &result);
                 }
             }
-
-            // Update the buyer state to indicate funds that have been successfully committed or refunded.
-            if result.is_success() {
-                // Record transfer fee
-                icp_transferable_amount.transfer_fee_paid_e8s =
-                    Some(DEFAULT_TRANSFER_FEE.get_e8s());
-                // Record the amount minus transfer fee that was refunded or committed.
-                let amount_transferred_e8s =
-                    Some(icp_transferable_amount.amount_e8s - DEFAULT_TRANSFER_FEE.get_e8s());
-                icp_transferable_amount.amount_transferred_e8s = amount_transferred_e8s;
-            }
         }
 
         sweep_result
     }
 
     /// In state COMMITTED. Transfers SNS tokens from the swap
-    /// canister to each buyer.
+    /// canister to each buyer, and returns the SNS tokens not sold by a
+    /// dutch auction (see `Swap.unsold_sns`) to the treasury of SNS
+    /// governance.
     ///
     /// Returns the following values:
     /// - the number of skipped buyers due balance less than fee or operation already in progress
@@ -1758,6 +2648,7 @@ impl Swap {
         let sns_governance = init.sns_governance_or_panic();
         let nns_governance = init.nns_governance_or_panic();
         let sns_transaction_fee_tokens = Tokens::from_e8s(init.transaction_fee_e8s_or_panic());
+        let transfer_memo = self.transfer_memo(TransferPurpose::DistributeSns);
 
         let mut sweep_result = SweepResult::default();
 
@@ -1835,6 +2726,7 @@ impl Swap {
                     sns_transaction_fee_tokens,
                     /* src_subaccount= */ None,
                     &dst,
+                    transfer_memo,
                     sns_ledger,
                 )
                 .await;
@@ -1863,6 +2755,42 @@ impl Swap {
             }
         }
 
+        if let Some(unsold_sns) = self.unsold_sns.as_mut() {
+            let dst = Account {
+                owner: sns_governance.get().0,
+                subaccount: Some(compute_distribution_subaccount_bytes(
+                    sns_governance.get(),
+                    TREASURY_SUBACCOUNT_NONCE,
+                )),
+            };
+            let result = unsold_sns
+                .transfer_helper(
+                    now_fn,
+                    sns_transaction_fee_tokens,
+                    /* src_subaccount= */ None,
+                    &dst,
+                    transfer_memo,
+                    sns_ledger,
+                )
+                .await;
+            match result {
+                // Too few unsold SNS tokens to pay the fee are left behind.
+                TransferResult::AmountTooSmall | TransferResult::AlreadyStarted => {
+                    sweep_result.skipped += 1;
+                }
+                TransferResult::Success(_) => {
+                    let fee_e8s = sns_transaction_fee_tokens.get_e8s();
+                    unsold_sns.transfer_fee_paid_e8s = Some(fee_e8s);
+                    unsold_sns.amount_transferred_e8s = Some(unsold_sns.amount_e8s - fee_e8s);
+
+                    sweep_result.success += 1;
+                }
+                TransferResult::Failure(_) => {
+                    sweep_result.failure += 1;
+                }
+            }
+        }
+
         sweep_result
     }
 
@@ -1923,6 +2851,9 @@ impl Swap {
         if caller.is_anonymous() {
             return NewSaleTicketResponse::err_invalid_principal();
         }
+        if self.is_canister_participant_refused(&caller) {
+            return NewSaleTicketResponse::err_canister_principal_not_allowed();
+        }
         // subaccounts must be 32 bytes
         if request
             .subaccount
@@ -1935,6 +2866,12 @@ impl Swap {
         if let Some(ticket) = memory::OPEN_TICKETS_MEMORY.with(|m| m.borrow().get(&principal)) {
             return NewSaleTicketResponse::err_ticket_exists(ticket);
         }
+        if self.is_direct_participant_limit_reached_for(&caller) {
+            return NewSaleTicketResponse::err_swap_participant_limit_reached();
+        }
+        if self.is_cutback_capacity_reached_for(&caller) {
+            return NewSaleTicketResponse::err_cutback_capacity_reached();
+        }
 
         // Check that there are still available tokens
         let params = self
@@ -1945,9 +2882,16 @@ impl Swap {
             .buyers
             .get(&caller.to_string())
             .map_or(0, |buyer_state| buyer_state.amount_icp_e8s());
+        // With a pro rata cutback, the excess over max_icp_e8s is cut back
+        // when the swap commits.
+        let max_tot_participation = if params.has_pro_rata_cutback() {
+            u64::MAX
+        } else {
+            params.max_icp_e8s
+        };
         let amount_icp_e8s = match compute_participation_increment(
             self.participant_total_icp_e8s(),
-            params.max_icp_e8s,
+            max_tot_participation,
             params.min_participant_icp_e8s,
             params.max_participant_icp_e8s,
             old_balance_e8s,
@@ -2233,6 +3177,17 @@ impl Swap {
         self.swap_due(now_seconds) || self.icp_target_reached()
     }
 
+    /// Returns true if the swap must be aborted at the specified timestamp,
+    /// i.e., it is due or the target ICP has been reached, without
+    /// sufficient participation.
+    pub fn can_abort(&self, now_seconds: u64) -> bool {
+        if self.lifecycle() != Lifecycle::Open {
+            return false;
+        }
+        (self.swap_due(now_seconds) || self.icp_target_reached())
+            && !self.sufficient_participation()
+    }
+
     //
     // --- query methods on the state  -----------------------------------------
     //
@@ -2264,12 +3219,26 @@ impl Swap {
                 0
             }
         };
+        // Without an early participation bonus, this is the same as
+        // `participant_total_icp_e8s`.
+        let participant_total_weight_e8s = self.participant_total_weight_e8s();
+        let sns_tokens_per_icp = i2d(tokens_available_for_sale)
+            .checked_div(i2d(participant_total_weight_e8s))
+            .unwrap_or_default();
+        let early_participation_sns_tokens_per_icp = self
+            .params
+            .as_ref()
+            .and_then(|params| params.early_participation_bonus.as_ref())
+            .map(|bonus| {
+                (sns_tokens_per_icp * i2d(10_000 + bonus.bonus_basis_points as u64) / i2d(10_000))
+                    .to_f32()
+                    .unwrap_or(0.0)
+            });
         DerivedState {
             buyer_total_icp_e8s: participant_total_icp_e8s,
-            sns_tokens_per_icp: i2d(tokens_available_for_sale)
-                .checked_div(i2d(participant_total_icp_e8s))
-                .and_then(|d| d.to_f32())
-                .unwrap_or(0.0),
+            sns_tokens_per_icp: sns_tokens_per_icp.to_f32().unwrap_or(0.0),
+            early_participation_sns_tokens_per_icp,
+            remaining_direct_participants: self.remaining_direct_participants(),
         }
     }
 
@@ -2281,6 +3250,49 @@ impl Swap {
         GetBuyerStateResponse { buyer_state }
     }
 
+    /// Returns the participation history of the buyer `request.principal_id`,
+    /// or of `caller` if it is not set, together with what became of the
+    /// participation, so that the refunds of the buyer can be audited. The
+    /// amounts are 0 once the buyer was archived, see
+    /// `archive_finalized_state`.
+    pub fn get_buyer_participation_history(
+        &self,
+        request: &GetBuyerParticipationHistoryRequest,
+        caller: PrincipalId,
+    ) -> GetBuyerParticipationHistoryResponse {
+        let buyer = request.principal_id.unwrap_or(caller);
+        let principal = Blob::from_bytes(buyer.as_slice().into());
+        let history = match memory::PARTICIPATION_HISTORIES
+            .with(|histories| histories.borrow().get(&principal))
+        {
+            Some(history) => history,
+            None => return GetBuyerParticipationHistoryResponse::default(),
+        };
+        let buyer_state = self.buyers.get(&buyer.to_string());
+        let refunded_e8s = |transfer: &TransferableAmount| {
+            if transfer.transfer_success_timestamp_seconds > 0 {
+                transfer.amount_transferred_e8s.unwrap_or(0)
+            } else {
+                0
+            }
+        };
+        let refunded_icp_e8s = buyer_state.map_or(0, |buyer_state| {
+            // The ICP of a committed swap went to the SNS governance instead.
+            let icp_e8s = match self.lifecycle() {
+                Lifecycle::Aborted => buyer_state.icp.as_ref().map_or(0, refunded_e8s),
+                _ => 0,
+            };
+            icp_e8s + buyer_state.cutback_icp.as_ref().map_or(0, refunded_e8s)
+        });
+        GetBuyerParticipationHistoryResponse {
+            participation_history: history.increments,
+            pruned_participation_icp_e8s: history.pruned_participation_icp_e8s,
+            amount_icp_e8s: buyer_state.map_or(0, |buyer_state| buyer_state.amount_icp_e8s()),
+            cutback_icp_e8s: buyer_state.map_or(0, |buyer_state| buyer_state.cutback_icp_e8s()),
+            refunded_icp_e8s,
+        }
+    }
+
     /// Returns the total amount of ICP deposited by participants in the swap.
     pub fn get_buyers_total(&self) -> GetBuyersTotalResponse {
         GetBuyersTotalResponse {
@@ -2288,12 +3300,78 @@ impl Swap {
         }
     }
 
-    /// Returns the current lifecycle stage (e.g. Open, Committed, etc)
-    pub fn get_lifecycle(&self, _request: &GetLifecycleRequest) -> GetLifecycleResponse {
-        GetLifecycleResponse {
-            lifecycle: Some(self.lifecycle),
-            decentralization_sale_open_timestamp_seconds: self
-                .decentralization_sale_open_timestamp_seconds,
+    /// Returns the current lifecycle stage (e.g. Open, Committed, etc)
+    pub fn get_lifecycle(&self, _request: &GetLifecycleRequest) -> GetLifecycleResponse {
+        GetLifecycleResponse {
+            lifecycle: Some(self.lifecycle),
+            decentralization_sale_open_timestamp_seconds: self
+                .decentralization_sale_open_timestamp_seconds,
+        }
+    }
+
+    /// Returns the current price of the dutch auction, and the price paid
+    /// and the SNS tokens sold if the swap committed at `now_seconds`. The
+    /// response is empty unless the sale is open and has a dutch auction.
+    pub fn get_auction_price(
+        &self,
+        _request: &GetAuctionPriceRequest,
+        now_seconds: u64,
+    ) -> GetAuctionPriceResponse {
+        if self.lifecycle() != Lifecycle::Open {
+            return GetAuctionPriceResponse::default();
+        }
+        let (params, auction_icp_e8s_per_sns_token) = match (
+            self.params.as_ref(),
+            self.dutch_auction_icp_e8s_per_sns_token(now_seconds),
+        ) {
+            (Some(params), Some(icp_e8s_per_sns_token)) => (params, icp_e8s_per_sns_token),
+            _ => return GetAuctionPriceResponse::default(),
+        };
+        // If the ICP committed buys all SNS tokens at the auction price, the
+        // participants pay the price at which it buys exactly all of them.
+        let pro_rata_icp_e8s_per_sns_token = self.participant_total_weight_e8s() as u128
+            * E8 as u128
+            / params.sns_token_e8s.max(1) as u128;
+        GetAuctionPriceResponse {
+            auction_icp_e8s_per_sns_token: Some(auction_icp_e8s_per_sns_token),
+            clearing_icp_e8s_per_sns_token: Some(
+                pro_rata_icp_e8s_per_sns_token.max(auction_icp_e8s_per_sns_token as u128) as u64,
+            ),
+            sns_token_e8s_sold: Some(params.sns_token_e8s_sold_at(
+                self.participant_total_weight_e8s(),
+                auction_icp_e8s_per_sns_token,
+            )),
+        }
+    }
+
+    /// Returns the ICP the swap can still accept, the time until it is due,
+    /// and whether it has sufficient participation and commits or aborts at
+    /// `now_seconds`, as decided by `try_commit_or_abort`. The response is
+    /// empty unless the sale is open.
+    pub fn get_sale_status(
+        &self,
+        _request: &GetSaleStatusRequest,
+        now_seconds: u64,
+    ) -> GetSaleStatusResponse {
+        let params = match (self.lifecycle(), self.params.as_ref()) {
+            (Lifecycle::Open, Some(params)) => params,
+            _ => return GetSaleStatusResponse::default(),
+        };
+        GetSaleStatusResponse {
+            remaining_icp_e8s: Some(
+                params
+                    .max_icp_e8s
+                    .saturating_sub(self.participant_total_icp_e8s()),
+            ),
+            max_icp_reached: Some(self.icp_target_reached()),
+            seconds_remaining: Some(
+                params
+                    .swap_due_timestamp_seconds
+                    .saturating_sub(now_seconds),
+            ),
+            min_participation_reached: Some(self.sufficient_participation()),
+            commit_due: Some(self.can_commit(now_seconds)),
+            abort_due: Some(self.can_abort(now_seconds)),
         }
     }
 
@@ -2435,6 +3513,313 @@ impl Swap {
 
         ListSnsNeuronRecipesResponse { sns_neuron_recipes }
     }
+
+    /// The full state relevant to audits, see `SwapStateExport`.
+    pub fn state_export(&self) -> SwapStateExport {
+        SwapStateExport {
+            buyers: self.exported_buyers().collect(),
+            cf_participants: self.cf_participants.clone(),
+            neuron_recipes: self.neuron_recipes.clone(),
+            ..self.state_export_without_elements()
+        }
+    }
+
+    fn state_export_without_elements(&self) -> SwapStateExport {
+        SwapStateExport {
+            lifecycle: self.lifecycle,
+            init: self.init.clone(),
+            params: self.params.clone(),
+            open_sns_token_swap_proposal_id: self.open_sns_token_swap_proposal_id,
+            decentralization_sale_open_timestamp_seconds: self
+                .decentralization_sale_open_timestamp_seconds,
+            lifecycle_history: self.lifecycle_history.clone(),
+            buyers: vec![],
+            cf_participants: vec![],
+            neuron_recipes: vec![],
+        }
+    }
+
+    /// The buyers, ordered by the textual representation of their principal
+    /// as the keys of `buyers` are.
+    fn exported_buyers(&self) -> impl Iterator<Item = Participant> + '_ {
+        self.buyers
+            .iter()
+            .map(|(principal, buyer_state)| Participant {
+                participant_id: string_to_principal(principal),
+                participation: Some(buyer_state.clone()),
+            })
+    }
+
+    // Export the state with paging over its buyers, CF participants and
+    // neuron recipes
+    pub fn export_state(&self, request: ExportStateRequest) -> ExportStateResponse {
+        let limit = request
+            .limit
+            .unwrap_or(EXPORT_STATE_LIMIT_CAP)
+            .min(EXPORT_STATE_LIMIT_CAP) as usize;
+
+        let buyers_count = self.buyers.len();
+        let cf_participants_count = self.cf_participants.len();
+        let neuron_recipes_count = self.neuron_recipes.len();
+        let total = buyers_count + cf_participants_count + neuron_recipes_count;
+        let start = (request.offset.unwrap_or_default() as usize).min(total);
+        let end = (start + limit).min(total);
+
+        // The part of the page within the elements at [offset, offset + count)
+        // of the concatenation.
+        let page = |offset: usize, count: usize| {
+            let clamp = |i: usize| i.clamp(offset, offset + count) - offset;
+            clamp(start)..clamp(end)
+        };
+        let buyers = page(0, buyers_count);
+        let cf_participants = page(buyers_count, cf_participants_count);
+        let neuron_recipes = page(buyers_count + cf_participants_count, neuron_recipes_count);
+
+        let state = SwapStateExport {
+            buyers: self
+                .exported_buyers()
+                .skip(buyers.start)
+                .take(buyers.len())
+                .collect(),
+            cf_participants: self.cf_participants[cf_participants].to_vec(),
+            neuron_recipes: self.neuron_recipes[neuron_recipes].to_vec(),
+            ..self.state_export_without_elements()
+        };
+        ExportStateResponse {
+            state: Some(state),
+            total_elements: total as u64,
+            next_offset: (end < total).then_some(end as u64),
+        }
+    }
+
+    // Return a chunk of the protobuf encoding of the full state export. This
+    // is a query, so the encoding can't be cached across calls and each call
+    // costs O(size of the state), see `GetStateChunkRequest`.
+    pub fn get_state_chunk(&self, request: GetStateChunkRequest) -> GetStateChunkResponse {
+        let encoded = self.state_export().encode_to_vec();
+        let length = request
+            .length
+            .unwrap_or(GET_STATE_CHUNK_LENGTH_CAP)
+            .min(GET_STATE_CHUNK_LENGTH_CAP) as usize;
+
+        let start = (request.offset as usize).min(encoded.len());
+        let end = (start + length).min(encoded.len());
+
+        GetStateChunkResponse {
+            chunk: encoded[start..end].to_vec(),
+            total_size_bytes: encoded.len() as u64,
+            sha256_hex: hex::encode(Sha256::hash(&encoded)),
+        }
+    }
+
+    /// Moves the buyers and neuron recipes of the finalized swap to the
+    /// archive canister behind `archive_client`, whose ID is
+    /// `archive_canister_id`, keeping only their totals in `archive`.
+    ///
+    /// The records are sent in batches of `ARCHIVE_BATCH_SIZE`, and the
+    /// records of a batch are only removed once the archive acknowledged it
+    /// with the SHA-256 of its encoding. If a batch fails, the archival stops
+    /// and the next call resumes it with that batch.
+    ///
+    /// Holds the lock of `finalize` meanwhile, and fails unless `finalize`
+    /// settled every buyer and neuron recipe, as it needs them.
+    pub async fn archive_finalized_state(
+        &mut self,
+        now_seconds: u64,
+        archive_canister_id: CanisterId,
+        archive_client: &mut impl SwapArchiveClient,
+    ) -> ArchiveFinalizedStateResponse {
+        if let Err(error_message) = self.lock_finalize_swap() {
+            return ArchiveFinalizedStateResponse {
+                archive: self.archive.clone(),
+                error_message: Some(error_message),
+            };
+        }
+
+        let result = self
+            .archive_finalized_state_inner(now_seconds, archive_canister_id, archive_client)
+            .await;
+        match &result {
+            Ok(()) => log!(INFO, "Archived the finalized swap: {:?}", self.archive),
+            Err(err) => log!(ERROR, "Archiving the finalized swap failed: {}", err),
+        }
+
+        self.unlock_finalize_swap();
+
+        ArchiveFinalizedStateResponse {
+            archive: self.archive.clone(),
+            error_message: result.err(),
+        }
+    }
+
+    /// Sends the batches of `archive_finalized_state`. Like `finalize_inner`,
+    /// it MUST NOT panic, or the lock is not released.
+    async fn archive_finalized_state_inner(
+        &mut self,
+        now_seconds: u64,
+        archive_canister_id: CanisterId,
+        archive_client: &mut impl SwapArchiveClient,
+    ) -> Result<(), String> {
+        if let Some(archive) = &self.archive {
+            if archive.completed_timestamp_seconds.is_some() {
+                return Ok(());
+            }
+            if archive.archive_canister_id != archive_canister_id.to_string() {
+                return Err(format!(
+                    "The swap is being archived to canister {}, not {}",
+                    archive.archive_canister_id, archive_canister_id
+                ));
+            }
+        }
+        self.check_finalized()?;
+        self.archive.get_or_insert_with(|| SwapArchive {
+            archive_canister_id: archive_canister_id.to_string(),
+            ..Default::default()
+        });
+
+        loop {
+            let request = self.next_archive_batch();
+            if request.buyers.is_empty() && request.neuron_recipes.is_empty() {
+                break;
+            }
+            let sha256_hex = hex::encode(Sha256::hash(&request.encode_to_vec()));
+            let batch_index = request.batch_index;
+            let (buyers, neuron_recipes) = (request.buyers.len(), request.neuron_recipes.len());
+
+            let response = archive_client
+                .archive_swap_records(request)
+                .await
+                .map_err(|err| format!("Archiving batch {} failed: {:?}", batch_index, err))?;
+            if response.batch_index != batch_index || response.sha256_hex != sha256_hex {
+                return Err(format!(
+                    "The archive acknowledged batch {} with hash {}, expected batch {} with hash {}",
+                    response.batch_index, response.sha256_hex, batch_index, sha256_hex
+                ));
+            }
+            self.compact_archived_batch(buyers, neuron_recipes);
+        }
+
+        if let Some(archive) = self.archive.as_mut() {
+            archive.completed_timestamp_seconds = Some(now_seconds);
+        }
+        Ok(())
+    }
+
+    /// Fails with the first buyer or neuron recipe `finalize` has not
+    /// settled yet, as the archived records can't be finalized anymore.
+    fn check_finalized(&self) -> Result<(), String> {
+        if !self.lifecycle_is_terminal() {
+            return Err(format!(
+                "The Sale can only be archived in the COMMITTED or ABORTED states. Current state is {:?}",
+                self.lifecycle()
+            ));
+        }
+        let icp_fee_e8s = DEFAULT_TRANSFER_FEE.get_e8s();
+        for (principal, buyer_state) in &self.buyers {
+            let icp_settled = buyer_state
+                .icp
+                .as_ref()
+                .map_or(false, |icp| icp.is_settled(icp_fee_e8s));
+            let cutback_settled = buyer_state
+                .cutback_icp
+                .as_ref()
+                .map_or(true, |cutback_icp| cutback_icp.is_settled(icp_fee_e8s));
+            if !icp_settled || !cutback_settled {
+                return Err(format!(
+                    "The ICP of buyer {} has not been swept yet, finalize the swap first",
+                    principal
+                ));
+            }
+        }
+        if self.neuron_recipes.is_empty() {
+            return Ok(());
+        }
+        let sns_fee_e8s = self.init_and_validate()?.transaction_fee_e8s_or_panic();
+        for recipe in &self.neuron_recipes {
+            let sns_settled = recipe
+                .sns
+                .as_ref()
+                .map_or(false, |sns| sns.is_settled(sns_fee_e8s));
+            if !sns_settled || recipe.claimed_status != Some(ClaimedStatus::Success as i32) {
+                return Err(format!(
+                    "The neuron recipe {:?} has not been claimed yet, finalize the swap first",
+                    recipe.neuron_attributes
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The next batch of records to archive: the first buyers and, once
+    /// they are archived, the first neuron recipes.
+    fn next_archive_batch(&self) -> ArchiveSwapRecordsRequest {
+        let buyers: Vec<Participant> = self.exported_buyers().take(ARCHIVE_BATCH_SIZE).collect();
+        let neuron_recipes = self
+            .neuron_recipes
+            .iter()
+            .take(ARCHIVE_BATCH_SIZE - buyers.len())
+            .cloned()
+            .collect();
+        ArchiveSwapRecordsRequest {
+            batch_index: self.archive.as_ref().map_or(0, |archive| archive.batches),
+            buyers,
+            neuron_recipes,
+            swap_round: self.open_sns_token_swap_proposal_id,
+        }
+    }
+
+    /// Removes the records of the batch returned by `next_archive_batch`,
+    /// with `buyers` buyers and `neuron_recipes` neuron recipes, and adds
+    /// them to the totals of `archive`.
+    fn compact_archived_batch(&mut self, buyers: usize, neuron_recipes: usize) {
+        let principals: Vec<String> = self.buyers.keys().take(buyers).cloned().collect();
+        let mut icp_e8s = 0_u64;
+        let mut weight_e8s = 0_u64;
+        for principal in principals {
+            if let Some(buyer_state) = self.buyers.remove(&principal) {
+                icp_e8s = icp_e8s.saturating_add(buyer_state.amount_icp_e8s());
+                weight_e8s =
+                    weight_e8s.saturating_add(self.buyer_participation_weight_e8s(&buyer_state));
+            }
+        }
+        self.neuron_recipes.drain(..neuron_recipes);
+
+        let archive = self.archive.get_or_insert_with(Default::default);
+        archive.batches += 1;
+        archive.archived_buyers += buyers as u64;
+        archive.archived_neuron_recipes += neuron_recipes as u64;
+        archive.archived_buyer_icp_e8s = archive.archived_buyer_icp_e8s.saturating_add(icp_e8s);
+        archive.archived_buyer_weight_e8s =
+            archive.archived_buyer_weight_e8s.saturating_add(weight_e8s);
+    }
+
+    /// The swap round encoded in the memos of the ledger transfers of the
+    /// swap, i.e., the ID of the proposal that opened it.
+    fn swap_round(&self) -> u64 {
+        self.open_sns_token_swap_proposal_id.unwrap_or_default()
+    }
+
+    /// The memo of the ledger transfers of the swap for `purpose`.
+    pub fn transfer_memo(&self, purpose: TransferPurpose) -> u64 {
+        transfer_memo(purpose, self.swap_round())
+    }
+
+    // Describe how the memos of the ledger transfers of the swap are composed
+    pub fn get_transfer_memo_scheme(&self) -> GetTransferMemoSchemeResponse {
+        let memos = TRANSFER_PURPOSES
+            .iter()
+            .map(|purpose| TransferMemo {
+                purpose: *purpose as i32,
+                purpose_name: purpose.as_str_name().to_string(),
+                memo: self.transfer_memo(*purpose),
+            })
+            .collect();
+        GetTransferMemoSchemeResponse {
+            swap_round: self.swap_round(),
+            swap_round_bits: TRANSFER_MEMO_SWAP_ROUND_BITS,
+            memos,
+        }
+    }
 }
 
 /// Computes the actual participation increment for a user
@@ -2500,10 +3885,54 @@ fn compute_participation_increment(
     Ok(max_available_increment.min(requested_increment))
 }
 
+/// Counts the `result` of sweeping `transferable_amount` in `sweep_result`
+/// and, if the transfer succeeded, records it in `transferable_amount`.
+fn record_icp_sweep(
+    sweep_result: &mut SweepResult,
+    transferable_amount: &mut TransferableAmount,
+    result: &TransferResult,
+) {
+    match result {
+        // AmountToSmall should never happen as the amount contributed is checked in
+        // `refresh_buyer_tokens`. In the case of a bug due to programmer error,
+        // increment the invalid field. This will require a manual intervention
+        // via an upgrade to correct
+        TransferResult::AmountTooSmall => {
+            sweep_result.invalid += 1;
+        }
+        TransferResult::AlreadyStarted => {
+            sweep_result.skipped += 1;
+        }
+        TransferResult::Success(_) => {
+            sweep_result.success += 1;
+        }
+        TransferResult::Failure(_) => {
+            sweep_result.failure += 1;
+        }
+    }
+
+    // Update the buyer state to indicate funds that have been successfully committed or refunded.
+    if result.is_success() {
+        // Record transfer fee
+        transferable_amount.transfer_fee_paid_e8s = Some(DEFAULT_TRANSFER_FEE.get_e8s());
+        // Record the amount minus transfer fee that was refunded or committed.
+        transferable_amount.amount_transferred_e8s =
+            Some(transferable_amount.amount_e8s - DEFAULT_TRANSFER_FEE.get_e8s());
+    }
+}
+
 pub fn is_valid_principal(p: &str) -> bool {
     !p.is_empty() && PrincipalId::from_str(p).is_ok()
 }
 
+/// Returns true if `principal` is the id of a canister, i.e., an opaque
+/// principal in the layout of the canister ids allocated by the IC (see
+/// `CanisterId::from_u64`).
+pub fn is_canister_principal(principal: &PrincipalId) -> bool {
+    let bytes = principal.as_slice();
+    bytes.len() == 10 && bytes[8..] == [0x01, 0x01]
+}
+
 pub fn principal_to_subaccount(principal_id: &PrincipalId) -> Subaccount {
     let mut subaccount = [0; std::mem::size_of::<Subaccount>()];
     let principal_id = principal_id.as_slice();
@@ -2512,6 +3941,14 @@ pub fn principal_to_subaccount(principal_id: &PrincipalId) -> Subaccount {
     subaccount
 }
 
+/// The memo of a ledger transfer for `purpose` in the swap with the given
+/// round. Rounds that don't fit in `TRANSFER_MEMO_SWAP_ROUND_BITS` are
+/// truncated.
+pub fn transfer_memo(purpose: TransferPurpose, swap_round: u64) -> u64 {
+    let swap_round_mask = (1 << TRANSFER_MEMO_SWAP_ROUND_BITS) - 1;
+    ((purpose as u64) << TRANSFER_MEMO_SWAP_ROUND_BITS) | (swap_round & swap_round_mask)
+}
+
 /// A common pattern throughout the Sale canister is parsing the String
 /// representation of a PrincipalId and logging the error if any.
 fn string_to_principal(maybe_principal_id: &String) -> Option<PrincipalId> {
@@ -2679,6 +4116,34 @@ impl BoundedStorable for Ticket {
     const IS_FIXED_SIZE: bool = false;
 }
 
+impl Storable for BuyerParticipationHistory {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        self.encode_to_vec().into()
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Self::decode(&bytes[..]).expect("Cannot decode participation history")
+    }
+}
+
+impl BoundedStorable for BuyerParticipationHistory {
+    // [BuyerParticipationHistory] is stored protocol-buffer encoded. When
+    // all fields are using the max number of bytes then the size is the
+    // following
+    //
+    //   35 * MAX_PARTICIPATION_HISTORY_LEN + // 0a + 21 +
+    //        //    08 + encode_variant(u64::MAX)
+    //        //    10 + encode_variant(u64::MAX)
+    //        //    18 + encode_variant(u64::MAX)
+    //   11   // 10 + encode_variant(u64::MAX)
+    //= 3511 (rounded up)
+    const MAX_SIZE: u32 = 4096;
+
+    // The size is not fixed because of base 128 variants and the varying
+    // number of increments
+    const IS_FIXED_SIZE: bool = false;
+}
+
 impl GetOpenTicketResponse {
     pub fn ok(ticket: Option<Ticket>) -> Self {
         Self {
@@ -2760,6 +4225,30 @@ impl NewSaleTicketResponse {
         })
     }
 
+    pub fn err_canister_principal_not_allowed() -> Self {
+        Self::err(new_sale_ticket_response::Err {
+            error_type: new_sale_ticket_response::err::Type::CanisterPrincipalNotAllowed as i32,
+            invalid_user_amount: None,
+            existing_ticket: None,
+        })
+    }
+
+    pub fn err_cutback_capacity_reached() -> Self {
+        Self::err(new_sale_ticket_response::Err {
+            error_type: new_sale_ticket_response::err::Type::CutbackCapacityReached as i32,
+            invalid_user_amount: None,
+            existing_ticket: None,
+        })
+    }
+
+    pub fn err_swap_participant_limit_reached() -> Self {
+        Self::err(new_sale_ticket_response::Err {
+            error_type: new_sale_ticket_response::err::Type::SwapParticipantLimitReached as i32,
+            invalid_user_amount: None,
+            existing_ticket: None,
+        })
+    }
+
     pub fn err_ticket_exists(ticket: Ticket) -> Self {
         Self::err(new_sale_ticket_response::Err {
             error_type: new_sale_ticket_response::err::Type::TicketExists as i32,
@@ -2836,6 +4325,8 @@ mod tests {
             fallback_controller_principal_ids: vec![PrincipalId::new_user_test_id(5).to_string()],
             transaction_fee_e8s: Some(0),
             neuron_minimum_stake_e8s: Some(0),
+            allow_canister_participants: None,
+            archive_canister_id: None,
         });
     }
 
@@ -2852,6 +4343,10 @@ mod tests {
             dissolve_delay_interval_seconds: 30 * SECONDS_PER_DAY,
         }),
         sale_delay_seconds: None,
+        early_participation_bonus: None,
+        max_direct_participants: None,
+        dutch_auction: None,
+        pro_rata_cutback: None,
     };
 
     #[test]
@@ -2895,11 +4390,14 @@ mod tests {
         let derived_state = DerivedState {
             buyer_total_icp_e8s: 400_000_000,
             sns_tokens_per_icp: 2.5f32,
+            early_participation_sns_tokens_per_icp: None,
+            remaining_direct_participants: Some(7),
         };
 
         let response: GetDerivedStateResponse = derived_state.into();
         assert_eq!(response.sns_tokens_per_icp, Some(2.5f64));
         assert_eq!(response.buyer_total_icp_e8s, Some(400_000_000));
+        assert_eq!(response.remaining_direct_participants, Some(7));
     }
 
     #[test]
@@ -3338,6 +4836,28 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_apportion_cutback() {
+        // Exact division.
+        assert_eq!(apportion_cutback(30, &[20, 10, 0]), vec![20, 10, 0]);
+        assert_eq!(apportion_cutback(15, &[20, 10, 0]), vec![10, 5, 0]);
+        // Rounded down: 10 * 1/3 = 3.33 and 10 * 2/3 = 6.67, the e8 left
+        // over goes to the largest remainder.
+        assert_eq!(apportion_cutback(10, &[10, 20]), vec![3, 7]);
+        // Ties go to the earliest participation.
+        assert_eq!(apportion_cutback(1, &[5, 5]), vec![1, 0]);
+        assert_eq!(apportion_cutback(2, &[5, 5, 5]), vec![1, 1, 0]);
+        // Never more than can be cut back.
+        assert_eq!(apportion_cutback(100, &[20, 10, 0]), vec![20, 10, 0]);
+        assert_eq!(apportion_cutback(5, &[]), Vec::<u64>::new());
+        assert_eq!(apportion_cutback(0, &[20, 10]), vec![0, 0]);
+        // No overflow.
+        assert_eq!(
+            apportion_cutback(u64::MAX - 1, &[u64::MAX, u64::MAX]),
+            vec![u64::MAX / 2, u64::MAX / 2]
+        );
+    }
+
     #[test]
     fn test_list_sns_neuron_recipes() {
         let dummy_recipe = |investor_principal: PrincipalId| SnsNeuronRecipe {
@@ -3400,6 +4920,240 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_export_state() {
+        let buyers: BTreeMap<String, BuyerState> = (0..3)
+            .map(|i| {
+                (
+                    PrincipalId::new_user_test_id(i).to_string(),
+                    BuyerState::new(E8 * (i + 1)),
+                )
+            })
+            .collect();
+        let cf_participants: Vec<CfParticipant> = (0..2)
+            .map(|i| CfParticipant {
+                hotkey_principal: PrincipalId::new_user_test_id(10 + i).to_string(),
+                cf_neurons: vec![CfNeuron {
+                    nns_neuron_id: i,
+                    amount_icp_e8s: E8,
+                }],
+            })
+            .collect();
+        let neuron_recipes: Vec<SnsNeuronRecipe> = (0..4)
+            .map(|i| SnsNeuronRecipe {
+                sns: None,
+                neuron_attributes: None,
+                claimed_status: None,
+                investor: Some(Investor::Direct(DirectInvestment {
+                    buyer_principal: PrincipalId::new_user_test_id(i).to_string(),
+                })),
+            })
+            .collect();
+        let swap = Swap {
+            lifecycle: Lifecycle::Committed as i32,
+            params: Some(PARAMS),
+            buyers,
+            cf_participants: cf_participants.clone(),
+            neuron_recipes: neuron_recipes.clone(),
+            ..(SWAP.clone())
+        };
+        let full = swap.state_export();
+        assert_eq!(full.buyers.len(), 3);
+
+        // Reassembling the pages yields the full export.
+        let mut offset = None;
+        let mut pages = vec![];
+        loop {
+            let response = swap.export_state(ExportStateRequest {
+                offset,
+                limit: Some(2),
+            });
+            assert_eq!(response.total_elements, 9);
+            let state = response.state.unwrap();
+            assert_eq!(
+                state.buyers.len() + state.cf_participants.len() + state.neuron_recipes.len(),
+                2.min(9 - offset.unwrap_or_default() as usize)
+            );
+            pages.push(state);
+            offset = response.next_offset;
+            if offset.is_none() {
+                break;
+            }
+        }
+        assert_eq!(pages.len(), 5);
+        // The second page spans the last buyer and the first CF participant.
+        assert_eq!(pages[1].buyers, full.buyers[2..].to_vec());
+        assert_eq!(pages[1].cf_participants, cf_participants[..1].to_vec());
+        let mut reassembled = SwapStateExport {
+            buyers: vec![],
+            cf_participants: vec![],
+            neuron_recipes: vec![],
+            ..pages[0].clone()
+        };
+        for page in pages {
+            reassembled.buyers.extend(page.buyers);
+            reassembled.cf_participants.extend(page.cf_participants);
+            reassembled.neuron_recipes.extend(page.neuron_recipes);
+        }
+        assert_eq!(reassembled, full);
+
+        // An offset beyond the end yields an empty last page.
+        let response = swap.export_state(ExportStateRequest {
+            offset: Some(100),
+            limit: None,
+        });
+        assert_eq!(response.next_offset, None);
+        assert_eq!(response.state.unwrap().neuron_recipes, vec![]);
+    }
+
+    #[test]
+    fn test_get_state_chunk() {
+        let swap = Swap {
+            lifecycle: Lifecycle::Open as i32,
+            params: Some(PARAMS),
+            buyers: btreemap! {
+                PrincipalId::new_user_test_id(0).to_string() => BuyerState::new(E8),
+            },
+            ..(SWAP.clone())
+        };
+        let encoded = swap.state_export().encode_to_vec();
+
+        let mut chunks = vec![];
+        let mut offset = 0;
+        loop {
+            let response = swap.get_state_chunk(GetStateChunkRequest {
+                offset,
+                length: Some(10),
+            });
+            assert_eq!(response.total_size_bytes, encoded.len() as u64);
+            assert_eq!(response.sha256_hex, hex::encode(Sha256::hash(&encoded)));
+            if response.chunk.is_empty() {
+                break;
+            }
+            offset += response.chunk.len() as u64;
+            chunks.extend(response.chunk);
+        }
+        assert_eq!(chunks, encoded);
+        assert_eq!(
+            SwapStateExport::decode(chunks.as_slice()).unwrap(),
+            swap.state_export()
+        );
+    }
+
+    #[test]
+    fn test_get_state_chunk_of_a_large_state() {
+        let swap = Swap {
+            lifecycle: Lifecycle::Committed as i32,
+            params: Some(PARAMS),
+            buyers: (0..100_000)
+                .map(|i| {
+                    (
+                        PrincipalId::new_user_test_id(i).to_string(),
+                        BuyerState::new(E8 * (i + 1)),
+                    )
+                })
+                .collect(),
+            ..(SWAP.clone())
+        };
+        let encoded = swap.state_export().encode_to_vec();
+        let cap = GET_STATE_CHUNK_LENGTH_CAP as usize;
+        assert!(encoded.len() > 2 * cap);
+
+        // Chunks of the default length take the fewest calls.
+        let mut chunks = vec![];
+        let mut calls = 0;
+        while chunks.len() < encoded.len() {
+            let response = swap.get_state_chunk(GetStateChunkRequest {
+                offset: chunks.len() as u64,
+                length: None,
+            });
+            calls += 1;
+            assert_eq!(response.total_size_bytes, encoded.len() as u64);
+            assert!(!response.chunk.is_empty() && response.chunk.len() <= cap);
+            chunks.extend(response.chunk);
+        }
+        assert_eq!(calls, (encoded.len() + cap - 1) / cap);
+        assert_eq!(chunks, encoded);
+        assert_eq!(
+            swap.get_state_chunk(GetStateChunkRequest {
+                offset: 0,
+                length: Some(u32::MAX),
+            })
+            .sha256_hex,
+            hex::encode(Sha256::hash(&encoded))
+        );
+    }
+
+    #[test]
+    fn test_get_transfer_memo_scheme() {
+        let swap = Swap {
+            open_sns_token_swap_proposal_id: Some(42),
+            ..(SWAP.clone())
+        };
+        let scheme = swap.get_transfer_memo_scheme();
+        assert_eq!(scheme.swap_round, 42);
+        assert_eq!(scheme.swap_round_bits, TRANSFER_MEMO_SWAP_ROUND_BITS);
+        assert_eq!(
+            scheme.memos[0],
+            TransferMemo {
+                purpose: TransferPurpose::CommitIcp as i32,
+                purpose_name: "TRANSFER_PURPOSE_COMMIT_ICP".to_string(),
+                memo: (1 << 56) | 42,
+            }
+        );
+        assert_eq!(
+            scheme
+                .memos
+                .iter()
+                .map(|memo| memo.memo >> TRANSFER_MEMO_SWAP_ROUND_BITS)
+                .collect::<Vec<_>>(),
+            vec![1, 2, 3, 4]
+        );
+        assert!(scheme
+            .memos
+            .iter()
+            .all(|memo| memo.memo & 0xFF_FFFF_FFFF_FFFF == 42));
+
+        // The memo only keeps the low bits of a swap round that doesn't fit.
+        assert_eq!(
+            transfer_memo(TransferPurpose::RefundIcp, u64::MAX),
+            (2 << 56) | 0xFF_FFFF_FFFF_FFFF
+        );
+    }
+
+    #[test]
+    fn test_lifecycle_history() {
+        let sale_duration = 100;
+        let mut swap = Swap {
+            lifecycle: Lifecycle::Open as i32,
+            params: Some(Params {
+                min_participants: 1,
+                min_icp_e8s: 10,
+                max_icp_e8s: 20,
+                min_participant_icp_e8s: 10,
+                max_participant_icp_e8s: 20,
+                swap_due_timestamp_seconds: sale_duration,
+                ..PARAMS
+            }),
+            ..(SWAP.clone())
+        };
+        assert!(swap.try_commit_or_abort(sale_duration));
+        assert_eq!(
+            swap.lifecycle_history,
+            vec![LifecycleTransition {
+                from_lifecycle: Lifecycle::Open as i32,
+                to_lifecycle: Lifecycle::Aborted as i32,
+                timestamp_seconds: sale_duration,
+                swap_round: swap.open_sns_token_swap_proposal_id,
+                reason: None,
+            }]
+        );
+        assert_eq!(
+            swap.state_export().lifecycle_history,
+            swap.lifecycle_history
+        );
+    }
+
     proptest! {
         #[test]
         fn test_ticket_ids_unique(pids in proptest::collection::vec(0..u64::MAX, 0..1000)) {
@@ -3414,6 +5168,8 @@ mod tests {
                     fallback_controller_principal_ids: vec![Principal::anonymous().to_string()],
                     transaction_fee_e8s: Some(10_000),
                     neuron_minimum_stake_e8s: Some(10_010_000),
+                    allow_canister_participants: None,
+                    archive_canister_id: None,
                 }),
                 params: Some(Params {
                     min_participants: 1,
@@ -3428,6 +5184,10 @@ mod tests {
                         dissolve_delay_interval_seconds: 10,
                     }),
                     sale_delay_seconds: Some(10),
+                    early_participation_bonus: None,
+                    max_direct_participants: None,
+                    dutch_auction: None,
+                    pro_rata_cutback: None,
                 }),
                 cf_participants: vec![],
                 buyers: BTreeMap::new(),
@@ -3437,7 +5197,16 @@ mod tests {
                 decentralization_sale_open_timestamp_seconds: Some(1),
                 next_ticket_id: Some(0),
                 purge_old_tickets_last_completion_timestamp_nanoseconds: Some(0),
-                purge_old_tickets_next_principal: Some(FIRST_PRINCIPAL_BYTES.to_vec())
+                purge_old_tickets_next_principal: Some(FIRST_PRINCIPAL_BYTES.to_vec()),
+                lifecycle_history: vec![],
+                configuration_error: None,
+                periodic_tasks: BTreeMap::new(),
+                archive: None,
+                completed_rounds: vec![],
+                next_sale_neuron_memo: None,
+                sns_ledger_fee_checked: None,
+                unsold_sns: None,
+                community_fund_participation_settled: None,
             };
             let mut ticket_ids = HashSet::new();
             for pid in pids {
@@ -3485,6 +5254,8 @@ mod tests {
                     amount_e8s: 1,
                     ..TransferableAmount::default()
                 }),
+                early_participation_icp_e8s: None,
+                cutback_icp: None,
             },
         };
         let mut swap = Swap {
@@ -3516,6 +5287,8 @@ mod tests {
                     amount_e8s: 10,
                     ..TransferableAmount::default()
                 }),
+                early_participation_icp_e8s: None,
+                cutback_icp: None,
             },
         };
         let mut swap = Swap {
@@ -3549,6 +5322,8 @@ mod tests {
                     amount_e8s: 20,
                     ..TransferableAmount::default()
                 }),
+                early_participation_icp_e8s: None,
+                cutback_icp: None,
             },
         };
         let mut swap = Swap {
@@ -3608,6 +5383,8 @@ mod tests {
                     amount_e8s: 20,
                     ..TransferableAmount::default()
                 }),
+                early_participation_icp_e8s: None,
+                cutback_icp: None,
             },
         };
         let mut swap = Swap {
@@ -3651,6 +5428,8 @@ mod tests {
                 fallback_controller_principal_ids: vec![PrincipalId::new_anonymous().to_string()],
                 transaction_fee_e8s: Some(DEFAULT_TRANSFER_FEE.get_e8s()),
                 neuron_minimum_stake_e8s: Some(0),
+                allow_canister_participants: None,
+                archive_canister_id: None,
             }),
             params: Some(Params {
                 min_participants: 0,
@@ -3665,6 +5444,10 @@ mod tests {
                     dissolve_delay_interval_seconds: 1,
                 }),
                 sale_delay_seconds: Some(0),
+                early_participation_bonus: None,
+                max_direct_participants: None,
+                dutch_auction: None,
+                pro_rata_cutback: None,
             }),
             cf_participants: vec![],
             buyers: BTreeMap::new(),
@@ -3675,6 +5458,15 @@ mod tests {
             next_ticket_id: Some(0),
             purge_old_tickets_last_completion_timestamp_nanoseconds: Some(0),
             purge_old_tickets_next_principal: Some(FIRST_PRINCIPAL_BYTES.to_vec()),
+            lifecycle_history: vec![],
+            configuration_error: None,
+            periodic_tasks: BTreeMap::new(),
+            archive: None,
+            completed_rounds: vec![],
+            next_sale_neuron_memo: None,
+            sns_ledger_fee_checked: None,
+            unsold_sns: None,
+            community_fund_participation_settled: None,
         };
 
         let try_purge_old_tickets = |sale: &mut Swap, time: u64| loop {
@@ -3943,4 +5735,17 @@ mod tests {
 
         assert_eq!(7, swap.cf_neurons_count());
     }
+
+    #[test]
+    fn full_participation_history_fits_its_max_size() {
+        let mut history = BuyerParticipationHistory::new(u64::MAX);
+        for _ in 0..crate::types::MAX_PARTICIPATION_HISTORY_LEN {
+            history.record(ParticipationIncrement {
+                amount_icp_e8s: u64::MAX,
+                timestamp_seconds: u64::MAX,
+                ticket_id: Some(u64::MAX),
+            });
+        }
+        assert!(history.to_bytes().len() <= BuyerParticipationHistory::MAX_SIZE as usize);
+    }
 }
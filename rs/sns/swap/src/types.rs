@@ -1,23 +1,33 @@
+use crate::clients::NnsGovernanceClient;
 use crate::logs::{ERROR, INFO};
 use crate::pb::v1::{
     error_refund_icp_response, set_dapp_controllers_call_result, set_mode_call_result,
     set_mode_call_result::SetModeResult, settle_community_fund_participation_result,
-    sns_neuron_recipe::ClaimedStatus, sns_neuron_recipe::Investor, BuyerState, CfInvestment,
-    CfNeuron, CfParticipant, DirectInvestment, ErrorRefundIcpResponse, FinalizeSwapResponse, Init,
-    Lifecycle, NeuronId as SaleNeuronId, OpenRequest, Params, SetDappControllersCallResult,
-    SetModeCallResult, SettleCommunityFundParticipationResult, SnsNeuronRecipe, SweepResult,
-    TransferableAmount,
+    sns_neuron_recipe::ClaimedStatus, sns_neuron_recipe::Investor, BuyerParticipationHistory,
+    BuyerState, CfInvestment, CfNeuron, CfParticipant, DirectInvestment, ErrorRefundIcpResponse,
+    FinalizeSwapResponse, Init, Lifecycle, ListNeurons, NeuronId as SaleNeuronId, NeuronState,
+    OpenRequest, Params, ParticipationIncrement, SetDappControllersCallResult, SetModeCallResult,
+    SettleCommunityFundParticipationResult, SnsNeuronRecipe, SweepResult, TransferableAmount,
 };
 use crate::swap::is_valid_principal;
 use ic_base_types::{CanisterId, PrincipalId};
 use ic_canister_log::log;
 use ic_ledger_core::Tokens;
 use ic_nervous_system_common::ledger::ICRC1Ledger;
-use ic_nervous_system_common::SECONDS_PER_DAY;
+use ic_nervous_system_common::{E8, SECONDS_PER_DAY};
 use ic_sns_governance::pb::v1::{ClaimedSwapNeuronStatus, NeuronId};
 use icrc_ledger_types::icrc1::account::{Account, Subaccount};
 use std::str::FromStr;
 
+/// The maximum number of neurons `OpenRequest::validate_cf_neurons` looks up
+/// with one call to `list_neurons`, which keeps the response, whose neuron
+/// infos contain up to 100 recent ballots each, well below the message size
+/// limit.
+const LIST_NEURONS_BATCH_SIZE: usize = 500;
+
+/// The maximal number of increments kept in `BuyerParticipationHistory`.
+pub const MAX_PARTICIPATION_HISTORY_LEN: usize = 100;
+
 pub fn validate_principal(p: &str) -> Result<(), String> {
     let _ = PrincipalId::from_str(p).map_err(|x| {
         format!(
@@ -128,12 +138,34 @@ impl Init {
         self.transaction_fee_e8s.unwrap()
     }
 
+    /// The canister the records of the finalized swap are archived to, see
+    /// `archive_canister_id`. Fails if none is configured, as no SNS canister
+    /// implements `archive_swap_records`.
+    pub fn archive_canister(&self) -> Result<CanisterId, String> {
+        let canister_id = match &self.archive_canister_id {
+            Some(canister_id) => canister_id,
+            None => return Err("no archive canister is configured".to_string()),
+        };
+        let principal_id = PrincipalId::from_str(canister_id).map_err(|err| err.to_string())?;
+
+        CanisterId::new(principal_id).map_err(|err| err.to_string())
+    }
+
+    /// Whether canisters can participate directly, see
+    /// `allow_canister_participants`.
+    pub fn allows_canister_participants(&self) -> bool {
+        self.allow_canister_participants.unwrap_or(false)
+    }
+
     pub fn validate(&self) -> Result<(), String> {
         validate_canister_id(&self.nns_governance_canister_id)?;
         validate_canister_id(&self.sns_governance_canister_id)?;
         validate_canister_id(&self.sns_ledger_canister_id)?;
         validate_canister_id(&self.icp_ledger_canister_id)?;
         validate_canister_id(&self.sns_root_canister_id)?;
+        if let Some(archive_canister_id) = &self.archive_canister_id {
+            validate_canister_id(archive_canister_id)?;
+        }
 
         if self.fallback_controller_principal_ids.is_empty() {
             return Err("at least one fallback controller required".to_string());
@@ -159,6 +191,11 @@ impl Init {
     }
 }
 
+/// The largest early participation bonus a swap can offer, i.e., early
+/// participants receive at most twice as many SNS tokens per ICP as late
+/// participants.
+pub const MAX_EARLY_PARTICIPATION_BONUS_BASIS_POINTS: u32 = 10_000;
+
 impl Params {
     const MIN_SALE_DURATION_SECONDS: u64 = SECONDS_PER_DAY;
     const MAX_SALE_DURATION_SECONDS: u64 = 90 * SECONDS_PER_DAY;
@@ -172,6 +209,42 @@ impl Params {
             return Err("min_participants must be > 0".to_string());
         }
 
+        if let Some(max_direct_participants) = self.max_direct_participants {
+            if max_direct_participants < self.min_participants {
+                return Err(format!(
+                    "max_direct_participants ({}) must be >= min_participants ({})",
+                    max_direct_participants, self.min_participants
+                ));
+            }
+        }
+
+        if let Some(auction) = &self.dutch_auction {
+            if auction.floor_icp_e8s_per_sns_token == 0 {
+                return Err("dutch_auction.floor_icp_e8s_per_sns_token must be > 0".to_string());
+            }
+            if auction.start_icp_e8s_per_sns_token <= auction.floor_icp_e8s_per_sns_token {
+                return Err(format!(
+                    "dutch_auction.start_icp_e8s_per_sns_token ({}) must be > \
+                     dutch_auction.floor_icp_e8s_per_sns_token ({})",
+                    auction.start_icp_e8s_per_sns_token, auction.floor_icp_e8s_per_sns_token
+                ));
+            }
+        }
+
+        if let Some(bonus) = &self.early_participation_bonus {
+            if bonus.window_seconds == 0 {
+                return Err("early_participation_bonus.window_seconds must be > 0".to_string());
+            }
+            if bonus.bonus_basis_points == 0
+                || bonus.bonus_basis_points > MAX_EARLY_PARTICIPATION_BONUS_BASIS_POINTS
+            {
+                return Err(format!(
+                    "early_participation_bonus.bonus_basis_points ({}) must be > 0 and <= {}",
+                    bonus.bonus_basis_points, MAX_EARLY_PARTICIPATION_BONUS_BASIS_POINTS
+                ));
+            }
+        }
+
         let transaction_fee_e8s = init
             .transaction_fee_e8s
             .expect("transaction_fee_e8s was not supplied.");
@@ -186,8 +259,14 @@ impl Params {
             .expect("participant_neuron_basket not populated.")
             .count as u128;
 
+        // In the worst case, the early participation bonus takes up its whole
+        // budget, and a late participant only shares in the rest.
+        let sns_token_e8s_without_bonus_budget = self
+            .sns_token_e8s
+            .saturating_sub(self.early_participation_bonus_budget_e8s());
+
         let min_participant_sns_e8s = self.min_participant_icp_e8s as u128
-            * self.sns_token_e8s as u128
+            * sns_token_e8s_without_bonus_budget as u128
             / self.max_icp_e8s as u128;
 
         let min_participant_icp_e8s_big_enough = min_participant_sns_e8s
@@ -200,8 +279,9 @@ impl Params {
                  enough SNS tokens to form {} SNS neurons, each of which \
                  require at least {} SNS e8s, plus {} e8s in transaction \
                  fees. More precisely, the following inequality must hold: \
-                 min_participant_icp_e8s >= neuron_basket_count * (neuron_minimum_stake_e8s + transaction_fee_e8s) * max_icp_e8s / sns_token_e8s \
-                 (where / denotes floor division).",
+                 min_participant_icp_e8s >= neuron_basket_count * (neuron_minimum_stake_e8s + transaction_fee_e8s) * max_icp_e8s / (sns_token_e8s - early_participation_bonus_budget_e8s) \
+                 (where / denotes floor division, and early_participation_bonus_budget_e8s is zero \
+                 if there is no early participation bonus).",
                 self.min_participant_icp_e8s,
                 neuron_basket_count,
                 neuron_minimum_stake_e8s,
@@ -209,6 +289,29 @@ impl Params {
             ));
         }
 
+        // With a dutch auction, a participant can end up paying as much as
+        // the start price.
+        if let Some(auction) = &self.dutch_auction {
+            let min_participant_sns_e8s_at_start_price = self.min_participant_icp_e8s as u128
+                * E8 as u128
+                / auction.start_icp_e8s_per_sns_token as u128;
+            if min_participant_sns_e8s_at_start_price
+                < neuron_basket_count * (neuron_minimum_stake_e8s + transaction_fee_e8s) as u128
+            {
+                return Err(format!(
+                    "min_participant_icp_e8s={} is too small. At the start price of the \
+                     dutch auction ({} ICP e8s per SNS token), it needs to buy enough SNS \
+                     tokens to form {} SNS neurons, each of which require at least {} SNS \
+                     e8s, plus {} e8s in transaction fees.",
+                    self.min_participant_icp_e8s,
+                    auction.start_icp_e8s_per_sns_token,
+                    neuron_basket_count,
+                    neuron_minimum_stake_e8s,
+                    transaction_fee_e8s,
+                ));
+            }
+        }
+
         if self.sns_token_e8s == 0 {
             return Err("sns_token_e8s must be > 0".to_string());
         }
@@ -311,9 +414,86 @@ impl Params {
         if duration_seconds > Self::MAX_SALE_DURATION_SECONDS {
             return false;
         }
+        // The early participation bonus window must end before the sale does
+        if let Some(bonus) = &self.early_participation_bonus {
+            if bonus.window_seconds >= duration_seconds {
+                return false;
+            }
+        }
 
         true
     }
+
+    /// Whether the participations beyond `max_icp_e8s` are cut back pro
+    /// rata when the swap is committed, instead of being refused.
+    pub fn has_pro_rata_cutback(&self) -> bool {
+        self.pro_rata_cutback.unwrap_or(false)
+    }
+
+    /// The bonus for ICP committed early, in basis points. Zero if there is
+    /// no early participation bonus.
+    pub fn early_participation_bonus_basis_points(&self) -> u32 {
+        self.early_participation_bonus
+            .as_ref()
+            .map_or(0, |bonus| bonus.bonus_basis_points)
+    }
+
+    /// The largest number of SNS tokens (out of `sns_token_e8s`) that can go
+    /// to the early participation bonus. This is reached if all ICP is
+    /// committed within the bonus window.
+    pub fn early_participation_bonus_budget_e8s(&self) -> u64 {
+        let bonus_basis_points = self.early_participation_bonus_basis_points() as u128;
+        (self.sns_token_e8s as u128 * bonus_basis_points / (10_000 + bonus_basis_points)) as u64
+    }
+
+    /// The price of an SNS token, in ICP e8s, at `now_seconds` in the dutch
+    /// auction of a sale opened at `open_timestamp_seconds`, or `None` if
+    /// the sale has no dutch auction.
+    pub fn dutch_auction_icp_e8s_per_sns_token(
+        &self,
+        open_timestamp_seconds: u64,
+        now_seconds: u64,
+    ) -> Option<u64> {
+        let auction = self.dutch_auction.as_ref()?;
+        let duration_seconds = self
+            .swap_due_timestamp_seconds
+            .saturating_sub(open_timestamp_seconds);
+        if duration_seconds == 0 {
+            return Some(auction.floor_icp_e8s_per_sns_token);
+        }
+        let elapsed_seconds = now_seconds
+            .saturating_sub(open_timestamp_seconds)
+            .min(duration_seconds);
+        let decrease_e8s = auction
+            .start_icp_e8s_per_sns_token
+            .saturating_sub(auction.floor_icp_e8s_per_sns_token) as u128
+            * elapsed_seconds as u128
+            / duration_seconds as u128;
+        Some(auction.start_icp_e8s_per_sns_token - decrease_e8s as u64)
+    }
+
+    /// The number of SNS tokens (out of `sns_token_e8s`) that participants
+    /// with a total weight of `total_weight_e8s` buy at a price of
+    /// `icp_e8s_per_sns_token`.
+    pub fn sns_token_e8s_sold_at(&self, total_weight_e8s: u64, icp_e8s_per_sns_token: u64) -> u64 {
+        let affordable_sns_e8s =
+            total_weight_e8s as u128 * E8 as u128 / icp_e8s_per_sns_token.max(1) as u128;
+        affordable_sns_e8s.min(self.sns_token_e8s as u128) as u64
+    }
+
+    /// Returns the weight of `amount_icp_e8s` ICP (of which
+    /// `early_participation_icp_e8s` is eligible for the early participation
+    /// bonus) when apportioning `sns_token_e8s` among the participants.
+    pub fn participation_weight_e8s(
+        &self,
+        amount_icp_e8s: u64,
+        early_participation_icp_e8s: u64,
+    ) -> u64 {
+        let bonus_e8s = early_participation_icp_e8s.min(amount_icp_e8s) as u128
+            * self.early_participation_bonus_basis_points() as u128
+            / 10_000;
+        amount_icp_e8s.saturating_add(bonus_e8s as u64)
+    }
 }
 
 impl BuyerState {
@@ -326,14 +506,67 @@ impl BuyerState {
                 amount_transferred_e8s: Some(0),
                 transfer_fee_paid_e8s: Some(0),
             }),
+            early_participation_icp_e8s: None,
+            cutback_icp: None,
         }
     }
     pub fn validate(&self) -> Result<(), String> {
         if let Some(icp) = &self.icp {
-            icp.validate()
+            icp.validate()?;
         } else {
-            Err("Field 'icp' is missing but required".to_string())
+            return Err("Field 'icp' is missing but required".to_string());
+        }
+        if self.early_participation_icp_e8s() > self.amount_icp_e8s() {
+            return Err(format!(
+                "Invariant violation: early_participation_icp_e8s ({}) > icp.amount_e8s ({})",
+                self.early_participation_icp_e8s(),
+                self.amount_icp_e8s()
+            ));
+        }
+        if let Some(cutback_icp) = &self.cutback_icp {
+            cutback_icp.validate()?;
+        }
+        Ok(())
+    }
+
+    /// The ICP cut back from this buyer when the swap committed, see
+    /// `Params.pro_rata_cutback`.
+    pub fn cutback_icp_e8s(&self) -> u64 {
+        self.cutback_icp
+            .as_ref()
+            .map_or(0, |cutback_icp| cutback_icp.amount_e8s)
+    }
+
+    /// Reduces the participation of this buyer by `cutback_e8s`, taking it
+    /// out of the part eligible for the early participation bonus last. The
+    /// cutback is recorded to be refunded, unless it does not exceed
+    /// `transfer_fee_e8s`.
+    pub fn cut_back_icp_e8s(&mut self, cutback_e8s: u64, transfer_fee_e8s: u64) {
+        let new_amount_icp_e8s = self.amount_icp_e8s().saturating_sub(cutback_e8s);
+        self.set_amount_icp_e8s(new_amount_icp_e8s);
+        if self.early_participation_icp_e8s() > new_amount_icp_e8s {
+            self.early_participation_icp_e8s = Some(new_amount_icp_e8s);
         }
+        if cutback_e8s > transfer_fee_e8s {
+            self.cutback_icp = Some(TransferableAmount {
+                amount_e8s: cutback_e8s,
+                transfer_start_timestamp_seconds: 0,
+                transfer_success_timestamp_seconds: 0,
+                amount_transferred_e8s: Some(0),
+                transfer_fee_paid_e8s: Some(0),
+            });
+        }
+    }
+
+    pub fn early_participation_icp_e8s(&self) -> u64 {
+        self.early_participation_icp_e8s.unwrap_or(0)
+    }
+
+    pub fn add_early_participation_icp_e8s(&mut self, amount_icp_e8s: u64) {
+        self.early_participation_icp_e8s = Some(
+            self.early_participation_icp_e8s()
+                .saturating_add(amount_icp_e8s),
+        );
     }
 
     pub fn amount_icp_e8s(&self) -> u64 {
@@ -358,7 +591,48 @@ impl BuyerState {
     }
 }
 
+impl BuyerParticipationHistory {
+    /// The history of a buyer who participated with `earlier_icp_e8s` before
+    /// the history was recorded, which is accounted for as pruned.
+    pub fn new(earlier_icp_e8s: u64) -> Self {
+        Self {
+            increments: vec![],
+            pruned_participation_icp_e8s: (earlier_icp_e8s > 0).then_some(earlier_icp_e8s),
+        }
+    }
+
+    /// Appends `increment`, pruning the oldest increment if the history is
+    /// full.
+    pub fn record(&mut self, increment: ParticipationIncrement) {
+        self.increments.push(increment);
+        if self.increments.len() > MAX_PARTICIPATION_HISTORY_LEN {
+            let pruned = self.increments.remove(0);
+            self.pruned_participation_icp_e8s = Some(
+                self.pruned_participation_icp_e8s
+                    .unwrap_or(0)
+                    .saturating_add(pruned.amount_icp_e8s),
+            );
+        }
+    }
+
+    /// The sum of all increments of the buyer's participation, including the
+    /// pruned ones. The ICP cut back from the participation is part of it,
+    /// but no longer of `BuyerState.icp.amount_e8s`.
+    pub fn participated_icp_e8s(&self) -> u64 {
+        self.increments.iter().fold(
+            self.pruned_participation_icp_e8s.unwrap_or(0),
+            |sum, increment| sum.saturating_add(increment.amount_icp_e8s),
+        )
+    }
+}
+
 impl TransferableAmount {
+    /// Whether nothing is left to transfer, i.e., the transfer succeeded or
+    /// the amount does not cover `fee_e8s`, in which case it is never made.
+    pub fn is_settled(&self, fee_e8s: u64) -> bool {
+        self.amount_e8s <= fee_e8s || self.transfer_success_timestamp_seconds > 0
+    }
+
     pub fn validate(&self) -> Result<(), String> {
         if self.transfer_start_timestamp_seconds == 0 && self.transfer_success_timestamp_seconds > 0
         {
@@ -380,6 +654,7 @@ impl TransferableAmount {
         fee: Tokens,
         subaccount: Option<Subaccount>,
         dst: &Account,
+        memo: u64,
         ledger: &dyn ICRC1Ledger,
     ) -> TransferResult {
         let amount = Tokens::from_e8s(self.amount_e8s);
@@ -401,7 +676,7 @@ impl TransferableAmount {
                 fee.get_e8s(),
                 subaccount,
                 *dst,
-                0,
+                memo,
             )
             .await;
         if self.transfer_start_timestamp_seconds == 0 {
@@ -470,6 +745,67 @@ impl OpenRequest {
             Err(defects.join("\n"))
         }
     }
+
+    /// Checks that every CF neuron of the request exists in NNS governance
+    /// and is not dissolved, so that invalid CF neurons are rejected when
+    /// the swap opens rather than discovered at finalization. The neurons
+    /// are looked up in batches of `LIST_NEURONS_BATCH_SIZE`.
+    pub async fn validate_cf_neurons(
+        &self,
+        nns_governance_client: &mut impl NnsGovernanceClient,
+    ) -> Result<(), String> {
+        let cf_neurons = self
+            .cf_participants
+            .iter()
+            .flat_map(|participant| {
+                participant
+                    .cf_neurons
+                    .iter()
+                    .map(move |neuron| (neuron.nns_neuron_id, &participant.hotkey_principal))
+            })
+            .collect::<Vec<_>>();
+
+        let mut defects = vec![];
+        for batch in cf_neurons.chunks(LIST_NEURONS_BATCH_SIZE) {
+            let request = ListNeurons {
+                neuron_ids: batch
+                    .iter()
+                    .map(|(nns_neuron_id, _)| *nns_neuron_id)
+                    .collect(),
+                include_neurons_readable_by_caller: false,
+            };
+            let response = nns_governance_client
+                .list_neurons(request)
+                .await
+                .map_err(|err| {
+                    format!(
+                        "Could not look up the community fund neurons in NNS governance: {:?}",
+                        err
+                    )
+                })?;
+            for (nns_neuron_id, hotkey_principal) in batch {
+                match response.neuron_infos.get(nns_neuron_id) {
+                    None => defects.push(format!(
+                        "The community fund neuron {} of {} does not exist.",
+                        nns_neuron_id, hotkey_principal
+                    )),
+                    Some(neuron_info) if neuron_info.state == NeuronState::Dissolved as i32 => {
+                        defects.push(format!(
+                            "The community fund neuron {} of {} is dissolved.",
+                            nns_neuron_id, hotkey_principal
+                        ))
+                    }
+                    Some(_) => (),
+                }
+            }
+        }
+
+        if defects.is_empty() {
+            Ok(())
+        } else {
+            Err(defects.join("\n"))
+        }
+    }
 }
 
 impl DirectInvestment {
@@ -702,7 +1038,7 @@ impl SweepResult {
 }
 
 impl SettleCommunityFundParticipationResult {
-    fn is_successful_settlement(&self) -> bool {
+    pub fn is_successful_settlement(&self) -> bool {
         use settle_community_fund_participation_result::Response;
         matches!(
             &self.possibility,
@@ -782,9 +1118,11 @@ impl TryInto<NeuronId> for SaleNeuronId {
 
 #[cfg(test)]
 mod tests {
+    use super::MAX_EARLY_PARTICIPATION_BONUS_BASIS_POINTS;
     use crate::pb::v1::{
-        params::NeuronBasketConstructionParameters, CfNeuron, CfParticipant, Init,
-        ListDirectParticipantsResponse, OpenRequest, Params, Participant,
+        params::{DutchAuction, EarlyParticipationBonus, NeuronBasketConstructionParameters},
+        CfNeuron, CfParticipant, Init, ListDirectParticipantsResponse, OpenRequest, Params,
+        Participant,
     };
     use crate::swap::MAX_LIST_DIRECT_PARTICIPANTS_LIMIT;
     use ic_base_types::PrincipalId;
@@ -810,6 +1148,10 @@ mod tests {
             dissolve_delay_interval_seconds: 7890000, // 3 months
         }),
         sale_delay_seconds: None,
+        early_participation_bonus: None,
+        max_direct_participants: None,
+        dutch_auction: None,
+        pro_rata_cutback: None,
     };
 
     lazy_static! {
@@ -834,6 +1176,18 @@ mod tests {
         };
     }
 
+    #[test]
+    fn archive_canister_must_be_configured() {
+        assert_is_err!(INIT.archive_canister());
+
+        let archive_canister_id = PrincipalId::new_user_test_id(1);
+        let init = Init {
+            archive_canister_id: Some(archive_canister_id.to_string()),
+            ..INIT.clone()
+        };
+        assert_eq!(init.archive_canister().unwrap().get(), archive_canister_id);
+    }
+
     #[test]
     fn accept_iff_can_form_sns_neuron_in_the_worst_case() {
         let mut init = INIT.clone();
@@ -1129,4 +1483,126 @@ mod tests {
         };
         assert!(!params.is_valid_if_initiated_at(START_OF_2022_TIMESTAMP_SECONDS));
     }
+
+    #[test]
+    fn early_participation_bonus_validate() {
+        let params_with_bonus = |window_seconds, bonus_basis_points| Params {
+            early_participation_bonus: Some(EarlyParticipationBonus {
+                window_seconds,
+                bonus_basis_points,
+            }),
+            ..PARAMS.clone()
+        };
+
+        assert_is_ok!(params_with_bonus(SECONDS_PER_DAY, 1_000).validate(&INIT));
+        assert_is_ok!(params_with_bonus(
+            SECONDS_PER_DAY,
+            MAX_EARLY_PARTICIPATION_BONUS_BASIS_POINTS
+        )
+        .validate(&INIT));
+        assert_is_err!(params_with_bonus(0, 1_000).validate(&INIT));
+        assert_is_err!(params_with_bonus(SECONDS_PER_DAY, 0).validate(&INIT));
+        assert_is_err!(params_with_bonus(
+            SECONDS_PER_DAY,
+            MAX_EARLY_PARTICIPATION_BONUS_BASIS_POINTS + 1
+        )
+        .validate(&INIT));
+
+        // The window must end before the sale does.
+        let params = params_with_bonus(13 * SECONDS_PER_DAY, 1_000);
+        assert!(params.is_valid_if_initiated_at(START_OF_2022_TIMESTAMP_SECONDS));
+        let params = params_with_bonus(14 * SECONDS_PER_DAY, 1_000);
+        assert!(!params.is_valid_if_initiated_at(START_OF_2022_TIMESTAMP_SECONDS));
+    }
+
+    #[test]
+    fn max_direct_participants_validate() {
+        let params_with_limit = |max_direct_participants| Params {
+            max_direct_participants,
+            ..PARAMS.clone()
+        };
+
+        assert_is_ok!(params_with_limit(None).validate(&INIT));
+        assert_is_ok!(params_with_limit(Some(PARAMS.min_participants)).validate(&INIT));
+        assert_is_err!(params_with_limit(Some(PARAMS.min_participants - 1)).validate(&INIT));
+        assert_is_err!(params_with_limit(Some(0)).validate(&INIT));
+    }
+
+    #[test]
+    fn dutch_auction_validate() {
+        let params_with_auction =
+            |start_icp_e8s_per_sns_token, floor_icp_e8s_per_sns_token| Params {
+                dutch_auction: Some(DutchAuction {
+                    start_icp_e8s_per_sns_token,
+                    floor_icp_e8s_per_sns_token,
+                }),
+                ..PARAMS.clone()
+            };
+
+        assert_is_ok!(params_with_auction(E8, E8 / 10).validate(&INIT));
+        assert_is_err!(params_with_auction(E8, 0).validate(&INIT));
+        assert_is_err!(params_with_auction(E8, E8).validate(&INIT));
+        assert_is_err!(params_with_auction(E8 / 10, E8).validate(&INIT));
+        // At 2 ICP per SNS token, min_participant_icp_e8s (5 ICP) does not
+        // buy enough SNS tokens for a basket of 3 neurons.
+        assert_is_err!(params_with_auction(2 * E8, E8 / 10).validate(&INIT));
+    }
+
+    #[test]
+    fn dutch_auction_price_decreases_linearly() {
+        let params = Params {
+            dutch_auction: Some(DutchAuction {
+                start_icp_e8s_per_sns_token: 15 * E8,
+                floor_icp_e8s_per_sns_token: E8,
+            }),
+            ..PARAMS.clone()
+        };
+        let open_seconds = START_OF_2022_TIMESTAMP_SECONDS;
+        let price_at =
+            |now_seconds| params.dutch_auction_icp_e8s_per_sns_token(open_seconds, now_seconds);
+
+        assert_eq!(
+            PARAMS.dutch_auction_icp_e8s_per_sns_token(open_seconds, open_seconds),
+            None
+        );
+        assert_eq!(price_at(open_seconds - 1), Some(15 * E8));
+        assert_eq!(price_at(open_seconds), Some(15 * E8));
+        assert_eq!(price_at(open_seconds + 7 * SECONDS_PER_DAY), Some(8 * E8));
+        assert_eq!(price_at(PARAMS.swap_due_timestamp_seconds), Some(E8));
+        assert_eq!(price_at(PARAMS.swap_due_timestamp_seconds + 1), Some(E8));
+
+        // 1000 ICP buys 125 SNS tokens at 8 ICP per token, but at most the
+        // 5000 SNS tokens being offered.
+        assert_eq!(params.sns_token_e8s_sold_at(1_000 * E8, 8 * E8), 125 * E8);
+        assert_eq!(
+            params.sns_token_e8s_sold_at(1_000 * E8, E8 / 10),
+            5_000 * E8
+        );
+    }
+
+    #[test]
+    fn early_participation_bonus_budget_is_taken_into_account() {
+        // A 25% bonus can take up at most 1/5 of the SNS tokens.
+        let params = Params {
+            early_participation_bonus: Some(EarlyParticipationBonus {
+                window_seconds: SECONDS_PER_DAY,
+                bonus_basis_points: 2_500,
+            }),
+            ..PARAMS.clone()
+        };
+        assert_eq!(PARAMS.early_participation_bonus_budget_e8s(), 0);
+        assert_eq!(params.early_participation_bonus_budget_e8s(), 1_000 * E8);
+        assert_eq!(params.participation_weight_e8s(100, 40), 110);
+        assert_eq!(PARAMS.participation_weight_e8s(100, 40), 100);
+
+        // Set the bar as high as min_participant_icp_e8s can "jump" without a
+        // bonus. The bonus budget then makes it impossible to clear.
+        let mut init = INIT.clone();
+        let sns_token_e8s = PARAMS.min_participant_icp_e8s as u128 * PARAMS.sns_token_e8s as u128
+            / PARAMS.max_icp_e8s as u128;
+        init.neuron_minimum_stake_e8s =
+            Some(sns_token_e8s as u64 / 3 - init.transaction_fee_e8s.unwrap());
+        assert_is_ok!(PARAMS.validate(&init));
+        assert_is_err!(params.validate(&init));
+    }
 }
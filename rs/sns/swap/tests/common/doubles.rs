@@ -1,5 +1,6 @@
 use async_trait::async_trait;
 use ic_base_types::CanisterId;
+use ic_crypto_sha::Sha256;
 use ic_ledger_core::Tokens;
 use ic_nervous_system_common::{ledger::ICRC1Ledger, NervousSystemError};
 use ic_sns_governance::pb::v1::{
@@ -8,13 +9,17 @@ use ic_sns_governance::pb::v1::{
     SetModeResponse,
 };
 use ic_sns_swap::{
-    clients::{NnsGovernanceClient, SnsGovernanceClient, SnsRootClient},
+    clients::{
+        NnsGovernanceClient, SnsGovernanceClient, SnsLedgerClient, SnsRootClient, SwapArchiveClient,
+    },
     pb::v1::{
-        CanisterCallError, GovernanceError, SetDappControllersRequest, SetDappControllersResponse,
+        ArchiveSwapRecordsRequest, ArchiveSwapRecordsResponse, CanisterCallError, GovernanceError,
+        ListNeurons, ListNeuronsResponse, SetDappControllersRequest, SetDappControllersResponse,
         SettleCommunityFundParticipation,
     },
 };
 use icrc_ledger_types::icrc1::account::{Account, Subaccount};
+use prost::Message;
 use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
 
@@ -193,11 +198,13 @@ impl SnsGovernanceClient for SpySnsGovernanceClient {
 #[derive(Debug, PartialEq)]
 pub enum NnsGovernanceClientCall {
     SettleCommunityFundParticipation(SettleCommunityFundParticipation),
+    ListNeurons(ListNeurons),
 }
 
 #[derive(Debug, PartialEq)]
 pub enum NnsGovernanceClientReply {
     SettleCommunityFundParticipation(Result<(), GovernanceError>),
+    ListNeurons(ListNeuronsResponse),
     CanisterCallError(CanisterCallError),
 }
 
@@ -244,10 +251,48 @@ impl NnsGovernanceClient for SpyNnsGovernanceClient {
         {
             NnsGovernanceClientReply::SettleCommunityFundParticipation(reply) => Ok(reply),
             NnsGovernanceClientReply::CanisterCallError(err) => Err(err),
+            unexpected_reply => panic!(
+                "Unexpected reply in the NnsGovernanceClient queue: {:?}",
+                unexpected_reply
+            ),
+        }
+    }
+
+    async fn list_neurons(
+        &mut self,
+        request: ListNeurons,
+    ) -> Result<ListNeuronsResponse, CanisterCallError> {
+        self.calls
+            .push(NnsGovernanceClientCall::ListNeurons(request));
+
+        match self
+            .replies
+            .pop()
+            .expect("Expected there to be a reply in the NnsGovernanceClient queue")
+        {
+            NnsGovernanceClientReply::ListNeurons(reply) => Ok(reply),
+            NnsGovernanceClientReply::CanisterCallError(err) => Err(err),
+            unexpected_reply => panic!(
+                "Unexpected reply in the NnsGovernanceClient queue: {:?}",
+                unexpected_reply
+            ),
         }
     }
 }
 
+/// An SnsLedgerClient with a fixed reply to `transfer_fee`.
+#[derive(Debug)]
+pub struct StubSnsLedgerClient {
+    pub transfer_fee: Result<u64, CanisterCallError>,
+}
+
+#[async_trait]
+impl SnsLedgerClient for StubSnsLedgerClient {
+    async fn transfer_fee(&mut self) -> Result<u64, CanisterCallError> {
+        self.transfer_fee.clone()
+    }
+}
+
 /// Expectation of one call on the mock Ledger.
 #[derive(Debug, Clone, Copy)]
 pub enum LedgerExpect {
@@ -317,3 +362,33 @@ impl ICRC1Ledger for MockLedger {
         CanisterId::from_u64(1)
     }
 }
+
+/// SwapArchiveClient that records the batches it receives and acknowledges
+/// them, except for `failing_batch`, which fails once.
+#[derive(Default, Debug)]
+pub struct SpySwapArchiveClient {
+    pub requests: Vec<ArchiveSwapRecordsRequest>,
+    pub failing_batch: Option<u64>,
+}
+
+#[async_trait]
+impl SwapArchiveClient for SpySwapArchiveClient {
+    async fn archive_swap_records(
+        &mut self,
+        request: ArchiveSwapRecordsRequest,
+    ) -> Result<ArchiveSwapRecordsResponse, CanisterCallError> {
+        if self.failing_batch == Some(request.batch_index) {
+            self.failing_batch = None;
+            return Err(CanisterCallError {
+                code: None,
+                description: "Archive unavailable".to_string(),
+            });
+        }
+        let response = ArchiveSwapRecordsResponse {
+            batch_index: request.batch_index,
+            sha256_hex: hex::encode(Sha256::hash(&request.encode_to_vec())),
+        };
+        self.requests.push(request);
+        Ok(response)
+    }
+}
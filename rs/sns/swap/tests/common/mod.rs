@@ -19,9 +19,9 @@ use ic_sns_governance::{
 };
 use ic_sns_swap::pb::v1::{
     error_refund_icp_response, CfNeuron, CfParticipant, ErrorRefundIcpRequest,
-    ErrorRefundIcpResponse, OpenRequest, Params, SweepResult,
+    ErrorRefundIcpResponse, OpenRequest, Params, SweepResult, TransferPurpose,
 };
-use ic_sns_swap::swap::{principal_to_subaccount, CLAIM_SWAP_NEURONS_BATCH_SIZE};
+use ic_sns_swap::swap::{principal_to_subaccount, transfer_memo, CLAIM_SWAP_NEURONS_BATCH_SIZE};
 use ic_sns_swap::{
     memory,
     pb::v1::{
@@ -337,7 +337,7 @@ pub async fn open_swap(swap: &mut Swap, params: &Params) {
 
 pub async fn buy_token(swap: &mut Swap, user: &PrincipalId, amount: &u64, ledger: &MockLedger) {
     assert!(swap
-        .refresh_buyer_token_e8s(*user, SWAP_CANISTER_ID, ledger)
+        .refresh_buyer_token_e8s(*user, SWAP_CANISTER_ID, START_TIMESTAMP_SECONDS, ledger)
         .await
         .is_ok());
     assert_eq!(
@@ -401,6 +401,7 @@ pub fn get_transfer_and_account_balance_mock_ledger(
     amount: &u64,
     from_subaccount: &PrincipalId,
     to: &PrincipalId,
+    purpose: TransferPurpose,
     error: bool,
 ) -> Vec<LedgerExpect> {
     vec![
@@ -419,7 +420,7 @@ pub fn get_transfer_and_account_balance_mock_ledger(
                 owner: (*to).into(),
                 subaccount: None,
             },
-            0,
+            transfer_memo(purpose, OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID),
             match error {
                 false => Ok(100),
                 true => Err(101),
@@ -432,6 +433,7 @@ pub fn get_transfer_mock_ledger(
     amount: &u64,
     from_subaccount: &PrincipalId,
     to: &PrincipalId,
+    purpose: TransferPurpose,
     error: bool,
 ) -> Vec<LedgerExpect> {
     vec![LedgerExpect::TransferFunds(
@@ -442,7 +444,7 @@ pub fn get_transfer_mock_ledger(
             owner: (*to).into(),
             subaccount: None,
         },
-        0,
+        transfer_memo(purpose, OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID),
         match error {
             false => Ok(100),
             true => Err(101),
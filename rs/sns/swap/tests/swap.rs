@@ -1,7 +1,8 @@
 use crate::common::doubles::{
     ExplodingSnsRootClient, LedgerExpect, NnsGovernanceClientCall, NnsGovernanceClientReply,
     SnsGovernanceClientCall, SnsGovernanceClientReply, SnsRootClientCall, SnsRootClientReply,
-    SpyNnsGovernanceClient, SpySnsGovernanceClient, SpySnsRootClient,
+    SpyNnsGovernanceClient, SpySnsGovernanceClient, SpySnsRootClient, SpySwapArchiveClient,
+    StubSnsLedgerClient,
 };
 use crate::common::{
     buy_token, compute_multiple_successful_claim_swap_neurons_response,
@@ -20,7 +21,8 @@ use futures::{channel::mpsc, future::FutureExt, StreamExt};
 use ic_base_types::{CanisterId, PrincipalId};
 use ic_ledger_core::Tokens;
 use ic_nervous_system_common::{
-    assert_is_err, assert_is_ok, ledger::compute_neuron_staking_subaccount_bytes,
+    assert_is_err, assert_is_ok,
+    ledger::{compute_distribution_subaccount_bytes, compute_neuron_staking_subaccount_bytes},
     NervousSystemError, E8, SECONDS_PER_DAY, START_OF_2022_TIMESTAMP_SECONDS,
 };
 use ic_nervous_system_common_test_keys::{
@@ -31,6 +33,7 @@ use ic_nervous_system_common_test_utils::{
     SpyLedger,
 };
 use ic_sns_governance::{
+    governance::TREASURY_SUBACCOUNT_NONCE,
     pb::v1::{
         claim_swap_neurons_request::NeuronParameters,
         claim_swap_neurons_response::ClaimSwapNeuronsResult, governance, ClaimSwapNeuronsRequest,
@@ -39,23 +42,28 @@ use ic_sns_governance::{
     types::ONE_MONTH_SECONDS,
 };
 use ic_sns_swap::swap::{
-    CLAIM_SWAP_NEURONS_BATCH_SIZE, FIRST_PRINCIPAL_BYTES, SALE_NEURON_MEMO_RANGE_START,
+    ARCHIVE_BATCH_SIZE, CLAIM_SWAP_NEURONS_BATCH_SIZE, FIRST_PRINCIPAL_BYTES,
+    SALE_NEURON_MEMO_RANGE_START,
 };
 use ic_sns_swap::{
     memory,
     pb::v1::{
-        params::NeuronBasketConstructionParameters,
+        params::{DutchAuction, EarlyParticipationBonus, NeuronBasketConstructionParameters},
         sns_neuron_recipe::{ClaimedStatus, Investor, NeuronAttributes},
         Lifecycle::{Aborted, Adopted, Committed, Open, Pending, Unspecified},
         SetDappControllersRequest, SetDappControllersResponse, *,
     },
-    swap::{apportion_approximately_equally, principal_to_subaccount},
+    swap::{
+        apportion_approximately_equally, is_canister_principal, principal_to_subaccount,
+        transfer_memo,
+    },
+    types::MAX_PARTICIPATION_HISTORY_LEN,
 };
 use icp_ledger::DEFAULT_TRANSFER_FEE;
 use icrc_ledger_types::icrc1::account::Account;
 use maplit::btreemap;
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashSet},
     pin::Pin,
     str::FromStr,
     sync::{atomic, atomic::Ordering as AtomicOrdering},
@@ -94,6 +102,8 @@ fn init() -> Init {
         // Similar to, but different from values used in NNS.
         transaction_fee_e8s: Some(12_345),
         neuron_minimum_stake_e8s: Some(123_456_789),
+        allow_canister_participants: None,
+        archive_canister_id: None,
     };
     assert_is_ok!(result.validate());
     result
@@ -113,6 +123,10 @@ pub fn params() -> Params {
             dissolve_delay_interval_seconds: 7890000, // 3 months
         }),
         sale_delay_seconds: None,
+        early_participation_bonus: None,
+        max_direct_participants: None,
+        dutch_auction: None,
+        pro_rata_cutback: None,
     };
     assert!(result.is_valid_if_initiated_at(START_TIMESTAMP_SECONDS));
     assert!(result.validate(&init()).is_ok());
@@ -148,6 +162,15 @@ fn create_generic_committed_swap() -> Swap {
         next_ticket_id: Some(0),
         purge_old_tickets_last_completion_timestamp_nanoseconds: Some(0),
         purge_old_tickets_next_principal: Some(FIRST_PRINCIPAL_BYTES.to_vec()),
+        lifecycle_history: vec![],
+        configuration_error: None,
+        periodic_tasks: BTreeMap::new(),
+        archive: None,
+        completed_rounds: vec![],
+        next_sale_neuron_memo: None,
+        sns_ledger_fee_checked: None,
+        unsold_sns: None,
+        community_fund_participation_settled: None,
     }
 }
 
@@ -266,6 +289,109 @@ fn test_open() {
     assert_eq!(swap.lifecycle(), Open);
 }
 
+#[test]
+fn test_validate_cf_neurons() {
+    let open_request = OpenRequest {
+        params: Some(params()),
+        cf_participants: vec![
+            CfParticipant {
+                hotkey_principal: i2principal_id_string(1),
+                cf_neurons: vec![
+                    CfNeuron {
+                        nns_neuron_id: 1,
+                        amount_icp_e8s: E8,
+                    },
+                    CfNeuron {
+                        nns_neuron_id: 2,
+                        amount_icp_e8s: E8,
+                    },
+                ],
+            },
+            CfParticipant {
+                hotkey_principal: i2principal_id_string(2),
+                cf_neurons: vec![CfNeuron {
+                    nns_neuron_id: 3,
+                    amount_icp_e8s: E8,
+                }],
+            },
+        ],
+        open_sns_token_swap_proposal_id: Some(OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID),
+    };
+    let neuron_info = |state: NeuronState| NeuronInfo {
+        retrieved_at_timestamp_seconds: START_TIMESTAMP_SECONDS,
+        state: state as i32,
+        dissolve_delay_seconds: 0,
+    };
+
+    // All neurons exist and are not dissolved.
+    let mut nns_governance_client =
+        SpyNnsGovernanceClient::new(vec![NnsGovernanceClientReply::ListNeurons(
+            ListNeuronsResponse {
+                neuron_infos: btreemap! {
+                    1 => neuron_info(NeuronState::NotDissolving),
+                    2 => neuron_info(NeuronState::Dissolving),
+                    3 => neuron_info(NeuronState::NotDissolving),
+                },
+            },
+        )]);
+    assert_is_ok!(open_request
+        .validate_cf_neurons(&mut nns_governance_client)
+        .now_or_never()
+        .unwrap());
+    assert_eq!(
+        nns_governance_client.calls,
+        vec![NnsGovernanceClientCall::ListNeurons(ListNeurons {
+            neuron_ids: vec![1, 2, 3],
+            include_neurons_readable_by_caller: false,
+        })]
+    );
+
+    // Every missing or dissolved neuron is reported.
+    let mut nns_governance_client =
+        SpyNnsGovernanceClient::new(vec![NnsGovernanceClientReply::ListNeurons(
+            ListNeuronsResponse {
+                neuron_infos: btreemap! {
+                    1 => neuron_info(NeuronState::Dissolved),
+                    3 => neuron_info(NeuronState::NotDissolving),
+                },
+            },
+        )]);
+    let defects = open_request
+        .validate_cf_neurons(&mut nns_governance_client)
+        .now_or_never()
+        .unwrap()
+        .unwrap_err();
+    assert!(defects.contains("neuron 1 of"), "{}", defects);
+    assert!(defects.contains("is dissolved"), "{}", defects);
+    assert!(defects.contains("neuron 2 of"), "{}", defects);
+    assert!(defects.contains("does not exist"), "{}", defects);
+    assert!(!defects.contains("neuron 3 of"), "{}", defects);
+
+    // The lookup failing rejects the request.
+    let mut nns_governance_client =
+        SpyNnsGovernanceClient::new(vec![NnsGovernanceClientReply::CanisterCallError(
+            CanisterCallError {
+                code: None,
+                description: "NNS governance is stopped".to_string(),
+            },
+        )]);
+    assert_is_err!(open_request
+        .validate_cf_neurons(&mut nns_governance_client)
+        .now_or_never()
+        .unwrap());
+
+    // Without CF participants, NNS governance is not called.
+    let mut nns_governance_client = SpyNnsGovernanceClient::default();
+    assert_is_ok!(OpenRequest {
+        cf_participants: vec![],
+        ..open_request
+    }
+    .validate_cf_neurons(&mut nns_governance_client)
+    .now_or_never()
+    .unwrap());
+    assert!(nns_governance_client.calls.is_empty());
+}
+
 #[test]
 fn test_open_with_delay() {
     let delay_seconds = 42;
@@ -376,6 +502,7 @@ fn test_min_icp() {
         .refresh_buyer_token_e8s(
             *TEST_USER1_PRINCIPAL,
             SWAP_CANISTER_ID,
+            START_TIMESTAMP_SECONDS,
             &mock_stub(vec![LedgerExpect::AccountBalance(
                 Account {
                     owner: SWAP_CANISTER_ID.get().into(),
@@ -399,6 +526,7 @@ fn test_min_icp() {
         .refresh_buyer_token_e8s(
             *TEST_USER2_PRINCIPAL,
             SWAP_CANISTER_ID,
+            START_TIMESTAMP_SECONDS,
             &mock_stub(vec![LedgerExpect::AccountBalance(
                 Account {
                     owner: SWAP_CANISTER_ID.get().into(),
@@ -452,7 +580,7 @@ fn test_min_icp() {
                             owner: (*TEST_USER2_PRINCIPAL).into(),
                             subaccount: None,
                         },
-                        0,
+                        transfer_memo(TransferPurpose::RefundIcp, OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID),
                         Ok(1066),
                     ),
                     LedgerExpect::TransferFunds(
@@ -463,7 +591,7 @@ fn test_min_icp() {
                             owner: (*TEST_USER1_PRINCIPAL).into(),
                             subaccount: None,
                         },
-                        0,
+                        transfer_memo(TransferPurpose::RefundIcp, OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID),
                         Ok(1067),
                     ),
                 ]),
@@ -523,6 +651,7 @@ fn test_min_max_icp_per_buyer() {
             .refresh_buyer_token_e8s(
                 *TEST_USER1_PRINCIPAL,
                 SWAP_CANISTER_ID,
+                START_TIMESTAMP_SECONDS,
                 &mock_stub(vec![LedgerExpect::AccountBalance(
                     Account {
                         owner: SWAP_CANISTER_ID.get().into(),
@@ -542,6 +671,7 @@ fn test_min_max_icp_per_buyer() {
             .refresh_buyer_token_e8s(
                 *TEST_USER1_PRINCIPAL,
                 SWAP_CANISTER_ID,
+                START_TIMESTAMP_SECONDS,
                 &mock_stub(vec![LedgerExpect::AccountBalance(
                     Account {
                         owner: SWAP_CANISTER_ID.get().into(),
@@ -566,6 +696,7 @@ fn test_min_max_icp_per_buyer() {
             .refresh_buyer_token_e8s(
                 *TEST_USER1_PRINCIPAL,
                 SWAP_CANISTER_ID,
+                START_TIMESTAMP_SECONDS,
                 &mock_stub(vec![LedgerExpect::AccountBalance(
                     Account {
                         owner: SWAP_CANISTER_ID.get().into(),
@@ -636,6 +767,7 @@ fn test_max_icp() {
         .refresh_buyer_token_e8s(
             *TEST_USER1_PRINCIPAL,
             SWAP_CANISTER_ID,
+            START_TIMESTAMP_SECONDS,
             &mock_stub(vec![LedgerExpect::AccountBalance(
                 Account {
                     owner: SWAP_CANISTER_ID.get().into(),
@@ -659,6 +791,7 @@ fn test_max_icp() {
         .refresh_buyer_token_e8s(
             *TEST_USER2_PRINCIPAL,
             SWAP_CANISTER_ID,
+            START_TIMESTAMP_SECONDS,
             &mock_stub(vec![LedgerExpect::AccountBalance(
                 Account {
                     owner: SWAP_CANISTER_ID.get().into(),
@@ -759,6 +892,7 @@ fn test_scenario_happy() {
         .refresh_buyer_token_e8s(
             *TEST_USER1_PRINCIPAL,
             SWAP_CANISTER_ID,
+            START_TIMESTAMP_SECONDS,
             &mock_stub(vec![LedgerExpect::AccountBalance(
                 Account {
                     owner: SWAP_CANISTER_ID.get().into(),
@@ -784,6 +918,7 @@ fn test_scenario_happy() {
         .refresh_buyer_token_e8s(
             *TEST_USER2_PRINCIPAL,
             SWAP_CANISTER_ID,
+            START_TIMESTAMP_SECONDS,
             &mock_stub(vec![LedgerExpect::AccountBalance(
                 Account {
                     owner: SWAP_CANISTER_ID.get().into(),
@@ -814,6 +949,7 @@ fn test_scenario_happy() {
         .refresh_buyer_token_e8s(
             *TEST_USER3_PRINCIPAL,
             SWAP_CANISTER_ID,
+            START_TIMESTAMP_SECONDS,
             &mock_stub(vec![LedgerExpect::AccountBalance(
                 Account {
                     owner: SWAP_CANISTER_ID.get().into(),
@@ -883,7 +1019,7 @@ fn test_scenario_happy() {
                             owner: SNS_GOVERNANCE_CANISTER_ID.get().into(),
                             subaccount: None,
                         },
-                        0,
+                        transfer_memo(TransferPurpose::CommitIcp, OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID),
                         Err(77),
                     ),
                     LedgerExpect::TransferFunds(
@@ -894,7 +1030,7 @@ fn test_scenario_happy() {
                             owner: SNS_GOVERNANCE_CANISTER_ID.get().into(),
                             subaccount: None,
                         },
-                        0,
+                        transfer_memo(TransferPurpose::CommitIcp, OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID),
                         Ok(1066),
                     ),
                     LedgerExpect::TransferFunds(
@@ -905,7 +1041,7 @@ fn test_scenario_happy() {
                             owner: SNS_GOVERNANCE_CANISTER_ID.get().into(),
                             subaccount: None,
                         },
-                        0,
+                        transfer_memo(TransferPurpose::CommitIcp, OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID),
                         Ok(1067),
                     ),
                 ]),
@@ -934,7 +1070,7 @@ fn test_scenario_happy() {
                         owner: SNS_GOVERNANCE_CANISTER_ID.get().into(),
                         subaccount: None,
                     },
-                    0,
+                    transfer_memo(TransferPurpose::CommitIcp, OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID),
                     Ok(1068),
                 )]),
             )
@@ -991,7 +1127,10 @@ fn test_scenario_happy() {
                             /* fees */ sns_transaction_fee_e8s,
                             /* Subaccount */ None,
                             to,
-                            /* memo */ 0,
+                            transfer_memo(
+                                TransferPurpose::DistributeSns,
+                                OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID,
+                            ),
                             /* Block height */ Ok(1066),
                         )
                     })
@@ -1084,6 +1223,10 @@ async fn test_finalize_swap_ok() {
             dissolve_delay_interval_seconds: 7890000, // 3 months
         }),
         sale_delay_seconds: None,
+        early_participation_bonus: None,
+        max_direct_participants: None,
+        dutch_auction: None,
+        pro_rata_cutback: None,
     };
     let buyers = btreemap! {
         i2principal_id_string(1001) => BuyerState::new(50 * E8),
@@ -1103,6 +1246,15 @@ async fn test_finalize_swap_ok() {
         next_ticket_id: Some(0),
         purge_old_tickets_last_completion_timestamp_nanoseconds: Some(0),
         purge_old_tickets_next_principal: Some(vec![0; 32]),
+        lifecycle_history: vec![],
+        configuration_error: None,
+        periodic_tasks: BTreeMap::new(),
+        archive: None,
+        completed_rounds: vec![],
+        next_sale_neuron_memo: None,
+        sns_ledger_fee_checked: None,
+        unsold_sns: None,
+        community_fund_participation_settled: None,
     };
     assert!(swap.try_commit_or_abort(END_TIMESTAMP_SECONDS));
     assert_eq!(swap.lifecycle(), Committed);
@@ -1228,7 +1380,12 @@ async fn test_finalize_swap_ok() {
         };
 
         assert_eq!(*fee_e8s, DEFAULT_TRANSFER_FEE.get_e8s(), "{:#?}", call);
-        assert_eq!(*memo, 0, "{:#?}", call);
+        assert_eq!(
+            *memo,
+            transfer_memo(TransferPurpose::CommitIcp, OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID),
+            "{:#?}",
+            call
+        );
     }
 
     let sns_ledger_calls = sns_ledger.get_calls_snapshot();
@@ -1240,7 +1397,15 @@ async fn test_finalize_swap_ok() {
         };
 
         assert_eq!(*fee_e8s, sns_transaction_fee_e8s, "{:#?}", call);
-        assert_eq!(*memo, 0, "{:#?}", call);
+        assert_eq!(
+            *memo,
+            transfer_memo(
+                TransferPurpose::DistributeSns,
+                OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID
+            ),
+            "{:#?}",
+            call
+        );
     }
 
     // ICP should be sent to SNS governance (from various swap subaccounts.)
@@ -1261,7 +1426,7 @@ async fn test_finalize_swap_ok() {
                 fee_e8s: DEFAULT_TRANSFER_FEE.get_e8s(),
                 from_subaccount,
                 to: expected_to,
-                memo: 0,
+                memo: transfer_memo(TransferPurpose::CommitIcp, OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID),
             }
         })
         .collect::<Vec<_>>();
@@ -1287,7 +1452,10 @@ async fn test_finalize_swap_ok() {
                         fee_e8s: sns_transaction_fee_e8s,
                         from_subaccount: None,
                         to,
-                        memo: 0,
+                        memo: transfer_memo(
+                            TransferPurpose::DistributeSns,
+                            OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID,
+                        ),
                     }
                 })
                 .collect()
@@ -1345,138 +1513,572 @@ async fn test_finalize_swap_ok() {
                         transfer_success_timestamp_seconds: END_TIMESTAMP_SECONDS + 10,
                         amount_transferred_e8s: Some(expected_amount_committed_e8s),
                         transfer_fee_paid_e8s: Some(fee_e8s)
-                    })
+                    }),
+                    early_participation_icp_e8s: None,
+                    cutback_icp: None,
                 }
             );
         });
 }
 
+/// Test that a mismatch between the fee of the SNS ledger and
+/// init.transaction_fee_e8s is recorded as a configuration error, and aborts
+/// the swap.
 #[tokio::test]
-async fn test_finalize_swap_abort() {
-    // Step 1: Prepare the world.
+async fn test_check_sns_ledger_fee() {
+    let mut swap = Swap {
+        lifecycle: Open as i32,
+        init: Some(init()),
+        params: Some(params()),
+        open_sns_token_swap_proposal_id: Some(OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID),
+        ..Default::default()
+    };
 
-    let init = Init {
-        fallback_controller_principal_ids: vec![i2principal_id_string(4242)],
-        ..init()
+    // The fee matches.
+    assert_eq!(
+        swap.check_sns_ledger_fee(
+            &mut StubSnsLedgerClient {
+                transfer_fee: Ok(12_345),
+            },
+            START_TIMESTAMP_SECONDS,
+        )
+        .await,
+        Ok(())
+    );
+    assert_eq!(swap.configuration_error, None);
+    assert_eq!(swap.lifecycle(), Open);
+
+    // The fee can't be retrieved, so the check fails, to be retried.
+    assert_is_err!(
+        swap.check_sns_ledger_fee(
+            &mut StubSnsLedgerClient {
+                transfer_fee: Err(CanisterCallError {
+                    code: Some(5),
+                    description: "Canister is stopped".to_string(),
+                }),
+            },
+            START_TIMESTAMP_SECONDS,
+        )
+        .await
+    );
+    assert_eq!(swap.configuration_error, None);
+    assert_eq!(swap.lifecycle(), Open);
+
+    // The fee differs.
+    assert_eq!(
+        swap.check_sns_ledger_fee(
+            &mut StubSnsLedgerClient {
+                transfer_fee: Ok(10_000),
+            },
+            START_TIMESTAMP_SECONDS + 1,
+        )
+        .await,
+        Ok(())
+    );
+    let configuration_error = "The transaction fee of the SNS ledger (10000 e8s) differs from \
+         init.transaction_fee_e8s (12345 e8s)"
+        .to_string();
+    assert_eq!(swap.configuration_error, Some(configuration_error.clone()));
+    assert_eq!(swap.lifecycle(), Aborted);
+    assert_eq!(
+        swap.lifecycle_history,
+        vec![LifecycleTransition {
+            from_lifecycle: Open as i32,
+            to_lifecycle: Aborted as i32,
+            timestamp_seconds: START_TIMESTAMP_SECONDS + 1,
+            swap_round: Some(OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID),
+            reason: Some(configuration_error),
+        }]
+    );
+}
+
+/// Test that a committed swap is only checked once, before finalize moves
+/// any funds.
+#[tokio::test]
+async fn test_check_sns_ledger_fee_of_committed_swap() {
+    let mut swap = Swap {
+        lifecycle: Committed as i32,
+        init: Some(init()),
+        params: Some(params()),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        swap.check_sns_ledger_fee(
+            &mut StubSnsLedgerClient {
+                transfer_fee: Ok(12_345),
+            },
+            END_TIMESTAMP_SECONDS,
+        )
+        .await,
+        Ok(())
+    );
+    assert_eq!(swap.sns_ledger_fee_checked, Some(true));
+
+    // Once checked, finalize may have moved funds, so the swap can't be
+    // aborted anymore.
+    assert_eq!(
+        swap.check_sns_ledger_fee(
+            &mut StubSnsLedgerClient {
+                transfer_fee: Ok(10_000),
+            },
+            END_TIMESTAMP_SECONDS,
+        )
+        .await,
+        Ok(())
+    );
+    assert_eq!(swap.configuration_error, None);
+    assert_eq!(swap.lifecycle(), Committed);
+}
+
+/// Test that a committed swap aborted due to a mismatching fee of the SNS
+/// ledger is finalized by refunding the participants.
+#[tokio::test]
+async fn test_finalize_swap_aborted_due_to_sns_ledger_fee() {
+    // Step 1: Prepare the world.
+    let mut swap = Swap {
+        lifecycle: Committed as i32,
+        init: Some(init()),
+        params: Some(params()),
+        buyers: btreemap! {
+            i2principal_id_string(1001) => BuyerState::new(50 * E8),
+        },
+        open_sns_token_swap_proposal_id: Some(OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID),
+        ..Default::default()
     };
+    assert_eq!(
+        swap.check_sns_ledger_fee(
+            &mut StubSnsLedgerClient {
+                transfer_fee: Ok(10_000),
+            },
+            END_TIMESTAMP_SECONDS,
+        )
+        .await,
+        Ok(())
+    );
+    assert_eq!(swap.lifecycle(), Aborted);
+
+    let icp_ledger = SpyLedger::new(vec![LedgerReply::TransferFunds(Ok(1000))]);
+    let sns_ledger = SpyLedger::default();
+    let mut sns_root_client =
+        SpySnsRootClient::new(vec![SnsRootClientReply::successful_set_dapp_controllers()]);
+    let mut nns_governance_client = SpyNnsGovernanceClient::with_successful_replies();
+
+    // Step 2: Run the code under test.
+    let result = swap
+        .finalize(
+            now_fn,
+            &mut sns_root_client,
+            &mut SpySnsGovernanceClient::default(),
+            &icp_ledger,
+            &sns_ledger,
+            &mut nns_governance_client,
+        )
+        .await;
+
+    // Step 3: Inspect the results. The buyer is refunded, the community fund
+    // is told that the swap aborted, and the dapp canisters are returned.
+    assert_eq!(result.error_message, None);
+    assert_eq!(
+        result.sweep_icp_result,
+        Some(SweepResult {
+            success: 1,
+            skipped: 0,
+            failure: 0,
+            invalid: 0,
+            global_failures: 0,
+        })
+    );
+    assert_eq!(
+        result.set_dapp_controllers_call_result,
+        Some(successful_set_dapp_controllers_call_result()),
+    );
+    assert_eq!(result.sweep_sns_result, None);
+    assert_eq!(sns_ledger.get_calls_snapshot(), vec![]);
+    match nns_governance_client.calls.as_slice() {
+        [NnsGovernanceClientCall::SettleCommunityFundParticipation(request)] => assert_eq!(
+            request.result,
+            Some(settle_community_fund_participation::Result::Aborted(
+                settle_community_fund_participation::Aborted {}
+            ))
+        ),
+        calls => panic!("Unexpected calls to NNS governance: {:?}", calls),
+    }
+}
+
+/// Test that finalize sweeps the ICP but doesn't distribute SNS tokens if
+/// there is a configuration error.
+#[tokio::test]
+async fn test_finalize_swap_with_configuration_error() {
+    // Step 1: Prepare the world.
     let params = Params {
-        // This absurdly large number ensures that the swap reaches the Aborted state.
-        max_icp_e8s: E8 * E8,
-        min_icp_e8s: E8 * E8,
+        max_icp_e8s: 100,
+        min_icp_e8s: 0,
         min_participant_icp_e8s: 1,
-        max_participant_icp_e8s: E8 * E8,
-        // There will only be one participant; therefore, this also ensures that
-        // the swap reaches the Aborted state.
-        min_participants: 2,
+        max_participant_icp_e8s: 100,
+        min_participants: 1,
         sns_token_e8s: 10 * E8,
         swap_due_timestamp_seconds: END_TIMESTAMP_SECONDS,
         neuron_basket_construction_parameters: Some(NeuronBasketConstructionParameters {
-            count: 12,
+            count: 3,
             dissolve_delay_interval_seconds: 7890000, // 3 months
         }),
         sale_delay_seconds: None,
+        early_participation_bonus: None,
+        max_direct_participants: None,
+        dutch_auction: None,
+        pro_rata_cutback: None,
     };
-    let buyer_principal_id = PrincipalId::new_user_test_id(8502);
     let mut swap = Swap {
         lifecycle: Open as i32,
-        init: Some(init.clone()),
+        init: Some(init()),
         params: Some(params),
-        cf_participants: vec![],
         buyers: btreemap! {
-                i2principal_id_string(8502) => BuyerState::new(77 * E8),
+            i2principal_id_string(1001) => BuyerState::new(50 * E8),
         },
-        neuron_recipes: vec![],
         open_sns_token_swap_proposal_id: Some(OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID),
-        finalize_swap_in_progress: None,
-        decentralization_sale_open_timestamp_seconds: None,
-        next_ticket_id: Some(0),
-        purge_old_tickets_last_completion_timestamp_nanoseconds: Some(0),
-        purge_old_tickets_next_principal: Some(vec![0; 32]),
+        configuration_error: Some("The fee differs".to_string()),
+        ..Default::default()
     };
+    assert!(swap.try_commit_or_abort(END_TIMESTAMP_SECONDS));
+    assert_eq!(swap.lifecycle(), Committed);
 
-    assert!(swap.try_commit_or_abort(/* now_seconds: */ END_TIMESTAMP_SECONDS + 1));
-    assert_eq!(swap.lifecycle(), Aborted);
-    // Cannot open when aborted.
-    assert!(!swap.can_open(END_TIMESTAMP_SECONDS + 1));
-    assert!(!swap.try_open_after_delay(END_TIMESTAMP_SECONDS + 1));
-
-    // These clients should have no calls observed and therefore no calls mocked
-    let mut sns_governance_client = SpySnsGovernanceClient::default();
-    let mut nns_governance_client = SpyNnsGovernanceClient::with_successful_replies();
-    let sns_ledger: SpyLedger = SpyLedger::default();
-
-    let mut sns_root_client = SpySnsRootClient::new(vec![
-        // SNS Root will respond with zero errors
-        SnsRootClientReply::SetDappControllers(SetDappControllersResponse {
-            failed_updates: vec![],
-        }),
-    ]);
-
-    let icp_ledger: SpyLedger = SpyLedger::new(
-        // ICP Ledger should be called once and should return success
-        vec![LedgerReply::TransferFunds(Ok(1000))],
-    );
+    let icp_ledger = SpyLedger::new(vec![LedgerReply::TransferFunds(Ok(1000))]);
+    let sns_ledger = SpyLedger::new(vec![]);
 
-    // Step 2: Run the code under test. To wit, finalize_swap.
+    // Step 2: Run the code under test.
     let result = swap
         .finalize(
             now_fn,
-            &mut sns_root_client,
-            &mut sns_governance_client,
+            &mut ExplodingSnsRootClient::default(),
+            &mut SpySnsGovernanceClient::new(vec![]),
             &icp_ledger,
             &sns_ledger,
-            &mut nns_governance_client,
+            &mut SpyNnsGovernanceClient::with_successful_replies(),
         )
         .await;
 
     // Step 3: Inspect the results.
-    {
-        assert_eq!(
-            result,
-            FinalizeSwapResponse {
-                sweep_icp_result: Some(SweepResult {
-                    success: 1,
-                    failure: 0,
-                    skipped: 0,
-                    invalid: 0,
-                    global_failures: 0,
-                }),
-                sweep_sns_result: None,
-                claim_neuron_result: None,
-                set_mode_call_result: None,
-                // This is the main assertion:
-                set_dapp_controllers_call_result: Some(
-                    successful_set_dapp_controllers_call_result()
-                ),
-                settle_community_fund_participation_result: Some(
-                    successful_settle_community_fund_participation_result()
-                ),
-                error_message: None,
-            },
-        );
-    }
-
-    // Step 3.1: Assert that no neurons were created, and SNS governance was not set to normal mode.
     assert_eq!(
-        sns_governance_client.calls,
-        vec![],
-        "{:#?}",
-        sns_governance_client.calls
+        result.error_message,
+        Some(
+            "SNS tokens cannot be distributed due to a configuration error: The fee differs"
+                .to_string()
+        )
     );
+    assert_eq!(icp_ledger.get_calls_snapshot().len(), 1);
+    assert_eq!(result.sweep_sns_result, None);
+    assert_eq!(sns_ledger.get_calls_snapshot(), vec![]);
+}
+
+/// Test that the phases of finalize can be run individually and again, but
+/// only once the phases they depend on completed.
+#[tokio::test]
+async fn test_finalize_swap_phases_can_be_run_individually() {
+    // Step 1: Prepare the world.
+    let params = Params {
+        max_icp_e8s: 100,
+        min_icp_e8s: 0,
+        min_participant_icp_e8s: 1,
+        max_participant_icp_e8s: 100,
+        min_participants: 1,
+        sns_token_e8s: 10 * E8,
+        swap_due_timestamp_seconds: END_TIMESTAMP_SECONDS,
+        neuron_basket_construction_parameters: Some(NeuronBasketConstructionParameters {
+            count: 3,
+            dissolve_delay_interval_seconds: 7890000, // 3 months
+        }),
+        sale_delay_seconds: None,
+        early_participation_bonus: None,
+        max_direct_participants: None,
+        dutch_auction: None,
+        pro_rata_cutback: None,
+    };
+    let mut swap = Swap {
+        lifecycle: Open as i32,
+        init: Some(init()),
+        params: Some(params),
+        buyers: btreemap! {
+            i2principal_id_string(1001) => BuyerState::new(50 * E8),
+        },
+        open_sns_token_swap_proposal_id: Some(OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID),
+        ..Default::default()
+    };
+    assert!(swap.try_commit_or_abort(END_TIMESTAMP_SECONDS));
+    assert_eq!(swap.lifecycle(), Committed);
+    assert_eq!(swap.neuron_recipes.len(), 3);
+
+    let mut sns_governance_client = SpySnsGovernanceClient::new(vec![
+        SnsGovernanceClientReply::ClaimSwapNeurons(
+            compute_single_successful_claim_swap_neurons_response(&swap.neuron_recipes),
+        ),
+        SnsGovernanceClientReply::SetMode(SetModeResponse {}),
+    ]);
+    let icp_ledger = SpyLedger::new(vec![LedgerReply::TransferFunds(Ok(1000))]);
+    let sns_ledger = SpyLedger::new((0..3).map(|i| LedgerReply::TransferFunds(Ok(i))).collect());
+
+    // Step 2: Run the phases, some of them out of order or twice.
+    macro_rules! finalize_phase {
+        ($phase:expr) => {
+            swap.finalize_phase(
+                $phase,
+                now_fn,
+                &mut ExplodingSnsRootClient::default(),
+                &mut sns_governance_client,
+                &icp_ledger,
+                &sns_ledger,
+                &mut SpyNnsGovernanceClient::with_successful_replies(),
+            )
+            .await
+        };
+    }
+    let successful_sweep = |success, skipped| SweepResult {
+        success,
+        skipped,
+        ..Default::default()
+    };
+
+    // Step 3: Inspect the results.
+    let result = finalize_phase!(FinalizeSwapPhase::ClaimNeurons);
+    assert!(
+        result
+            .error_message
+            .as_ref()
+            .unwrap()
+            .contains("have not been swept yet"),
+        "{:?}",
+        result
+    );
+    assert_eq!(result.claim_neuron_result, None);
+
+    let result = finalize_phase!(FinalizeSwapPhase::SweepSns);
+    assert!(
+        result
+            .error_message
+            .as_ref()
+            .unwrap()
+            .contains("The ICP of 1 buyer(s) has not been swept yet"),
+        "{:?}",
+        result
+    );
+    assert_eq!(result.sweep_sns_result, None);
+
+    let result = finalize_phase!(FinalizeSwapPhase::SweepIcp);
+    assert_eq!(
+        result,
+        FinalizeSwapResponse {
+            sweep_icp_result: Some(successful_sweep(1, 0)),
+            ..Default::default()
+        }
+    );
+    // The ICP was swept already, so it is not transferred again.
+    let result = finalize_phase!(FinalizeSwapPhase::SweepIcp);
+    assert_eq!(result.sweep_icp_result, Some(successful_sweep(0, 1)));
+    assert_eq!(icp_ledger.get_calls_snapshot().len(), 1);
+
+    let result = finalize_phase!(FinalizeSwapPhase::SetDappControllers);
+    assert!(result.error_message.is_some(), "{:?}", result);
+    assert_eq!(result.set_dapp_controllers_call_result, None);
+
+    let result = finalize_phase!(FinalizeSwapPhase::SweepSns);
+    assert!(
+        result
+            .error_message
+            .as_ref()
+            .unwrap()
+            .contains("The Community Fund participation has not been settled yet"),
+        "{:?}",
+        result
+    );
+    assert_eq!(result.sweep_sns_result, None);
+    assert_eq!(sns_ledger.get_calls_snapshot(), vec![]);
+
+    let result = finalize_phase!(FinalizeSwapPhase::SettleCommunityFund);
+    assert_eq!(result.error_message, None);
+    assert_eq!(swap.community_fund_participation_settled, Some(true));
+
+    let result = finalize_phase!(FinalizeSwapPhase::SweepSns);
+    assert_eq!(result.error_message, None);
+    assert_eq!(result.sweep_sns_result, Some(successful_sweep(3, 0)));
+    assert_eq!(sns_ledger.get_calls_snapshot().len(), 3);
+
+    let result = finalize_phase!(FinalizeSwapPhase::SetMode);
+    assert!(
+        result
+            .error_message
+            .as_ref()
+            .unwrap()
+            .contains("have not been claimed yet"),
+        "{:?}",
+        result
+    );
+
+    let result = finalize_phase!(FinalizeSwapPhase::ClaimNeurons);
+    assert_eq!(result.error_message, None);
+    assert_eq!(result.claim_neuron_result, Some(successful_sweep(3, 0)));
+
+    let result = finalize_phase!(FinalizeSwapPhase::SetMode);
+    assert_eq!(
+        result,
+        FinalizeSwapResponse {
+            set_mode_call_result: Some(successful_set_mode_call_result()),
+            ..Default::default()
+        }
+    );
+
+    assert_eq!(sns_governance_client.calls.len(), 2);
+    assert!(!swap.is_finalize_swap_locked());
+    assert_eq!(
+        swap.finalize_phases(),
+        vec![
+            FinalizeSwapPhase::SweepIcp,
+            FinalizeSwapPhase::SettleCommunityFund,
+            FinalizeSwapPhase::SweepSns,
+            FinalizeSwapPhase::ClaimNeurons,
+            FinalizeSwapPhase::SetMode,
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_finalize_swap_abort() {
+    // Step 1: Prepare the world.
+
+    let init = Init {
+        fallback_controller_principal_ids: vec![i2principal_id_string(4242)],
+        ..init()
+    };
+    let params = Params {
+        // This absurdly large number ensures that the swap reaches the Aborted state.
+        max_icp_e8s: E8 * E8,
+        min_icp_e8s: E8 * E8,
+        min_participant_icp_e8s: 1,
+        max_participant_icp_e8s: E8 * E8,
+        // There will only be one participant; therefore, this also ensures that
+        // the swap reaches the Aborted state.
+        min_participants: 2,
+        sns_token_e8s: 10 * E8,
+        swap_due_timestamp_seconds: END_TIMESTAMP_SECONDS,
+        neuron_basket_construction_parameters: Some(NeuronBasketConstructionParameters {
+            count: 12,
+            dissolve_delay_interval_seconds: 7890000, // 3 months
+        }),
+        sale_delay_seconds: None,
+        early_participation_bonus: None,
+        max_direct_participants: None,
+        dutch_auction: None,
+        pro_rata_cutback: None,
+    };
+    let buyer_principal_id = PrincipalId::new_user_test_id(8502);
+    let mut swap = Swap {
+        lifecycle: Open as i32,
+        init: Some(init.clone()),
+        params: Some(params),
+        cf_participants: vec![],
+        buyers: btreemap! {
+                i2principal_id_string(8502) => BuyerState::new(77 * E8),
+        },
+        neuron_recipes: vec![],
+        open_sns_token_swap_proposal_id: Some(OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID),
+        finalize_swap_in_progress: None,
+        decentralization_sale_open_timestamp_seconds: None,
+        next_ticket_id: Some(0),
+        purge_old_tickets_last_completion_timestamp_nanoseconds: Some(0),
+        purge_old_tickets_next_principal: Some(vec![0; 32]),
+        lifecycle_history: vec![],
+        configuration_error: None,
+        periodic_tasks: BTreeMap::new(),
+        archive: None,
+        completed_rounds: vec![],
+        next_sale_neuron_memo: None,
+        sns_ledger_fee_checked: None,
+        unsold_sns: None,
+        community_fund_participation_settled: None,
+    };
+
+    assert!(swap.try_commit_or_abort(/* now_seconds: */ END_TIMESTAMP_SECONDS + 1));
+    assert_eq!(swap.lifecycle(), Aborted);
+    // Cannot open when aborted.
+    assert!(!swap.can_open(END_TIMESTAMP_SECONDS + 1));
+    assert!(!swap.try_open_after_delay(END_TIMESTAMP_SECONDS + 1));
+
+    // These clients should have no calls observed and therefore no calls mocked
+    let mut sns_governance_client = SpySnsGovernanceClient::default();
+    let mut nns_governance_client = SpyNnsGovernanceClient::with_successful_replies();
+    let sns_ledger: SpyLedger = SpyLedger::default();
+
+    let mut sns_root_client = SpySnsRootClient::new(vec![
+        // SNS Root will respond with zero errors
+        SnsRootClientReply::SetDappControllers(SetDappControllersResponse {
+            failed_updates: vec![],
+        }),
+    ]);
+
+    let icp_ledger: SpyLedger = SpyLedger::new(
+        // ICP Ledger should be called once and should return success
+        vec![LedgerReply::TransferFunds(Ok(1000))],
+    );
+
+    // Step 2: Run the code under test. To wit, finalize_swap.
+    let result = swap
+        .finalize(
+            now_fn,
+            &mut sns_root_client,
+            &mut sns_governance_client,
+            &icp_ledger,
+            &sns_ledger,
+            &mut nns_governance_client,
+        )
+        .await;
+
+    // Step 3: Inspect the results.
+    {
+        assert_eq!(
+            result,
+            FinalizeSwapResponse {
+                sweep_icp_result: Some(SweepResult {
+                    success: 1,
+                    failure: 0,
+                    skipped: 0,
+                    invalid: 0,
+                    global_failures: 0,
+                }),
+                sweep_sns_result: None,
+                claim_neuron_result: None,
+                set_mode_call_result: None,
+                // This is the main assertion:
+                set_dapp_controllers_call_result: Some(
+                    successful_set_dapp_controllers_call_result()
+                ),
+                settle_community_fund_participation_result: Some(
+                    successful_settle_community_fund_participation_result()
+                ),
+                error_message: None,
+            },
+        );
+    }
+
+    // Step 3.1: Assert that no neurons were created, and SNS governance was not set to normal mode.
+    assert_eq!(
+        sns_governance_client.calls,
+        vec![],
+        "{:#?}",
+        sns_governance_client.calls
+    );
+
+    // Step 3.2: Verify ledger calls.
+    let icp_ledger_calls = icp_ledger.get_calls_snapshot();
+    assert_eq!(
+        icp_ledger_calls,
+        vec![
+            // Refund ICP to buyer.
+            LedgerCall::TransferFundsICRC1 {
+                amount_e8s: 77 * E8 - DEFAULT_TRANSFER_FEE.get_e8s(),
 
-    // Step 3.2: Verify ledger calls.
-    let icp_ledger_calls = icp_ledger.get_calls_snapshot();
-    assert_eq!(
-        icp_ledger_calls,
-        vec![
-            // Refund ICP to buyer.
-            LedgerCall::TransferFundsICRC1 {
-                amount_e8s: 77 * E8 - DEFAULT_TRANSFER_FEE.get_e8s(),
-
                 fee_e8s: DEFAULT_TRANSFER_FEE.get_e8s(),
                 from_subaccount: Some(principal_to_subaccount(&buyer_principal_id)),
                 to: Account::from(buyer_principal_id.0),
-                memo: 0,
+                memo: transfer_memo(TransferPurpose::RefundIcp, OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID),
             }
         ],
         "{icp_ledger_calls:#?}"
@@ -1551,7 +2153,11 @@ fn test_error_refund_single_user() {
             &user1,
             &amount,
             &mock_stub(get_transfer_and_account_balance_mock_ledger(
-                &amount, &user1, &user1, false,
+                &amount,
+                &user1,
+                &user1,
+                TransferPurpose::ErrorRefundIcp,
+                false,
             )),
         )
         .now_or_never()
@@ -1607,7 +2213,11 @@ fn test_error_refund_single_user() {
             &mut swap,
             &user1,
             &mock_stub(get_transfer_and_account_balance_mock_ledger(
-                &amount, &user1, &user1, false,
+                &amount,
+                &user1,
+                &user1,
+                TransferPurpose::ErrorRefundIcp,
+                false,
             )),
         )
         .now_or_never()
@@ -1625,6 +2235,7 @@ fn test_error_refund_single_user() {
                 &(7 * E8),
                 &user1,
                 &user1,
+                TransferPurpose::ErrorRefundIcp,
                 false,
             )),
         )
@@ -1646,6 +2257,7 @@ fn test_error_refund_single_user() {
                 &amount,
                 &user1,
                 &SNS_GOVERNANCE_CANISTER_ID.into(),
+                TransferPurpose::CommitIcp,
                 false,
             )),
         )
@@ -1665,6 +2277,7 @@ fn test_error_refund_single_user() {
                 &(7 * E8),
                 &user1,
                 &user1,
+                TransferPurpose::ErrorRefundIcp,
                 false,
             )),
         )
@@ -1683,6 +2296,7 @@ fn test_error_refund_single_user() {
                 &DEFAULT_TRANSFER_FEE.get_e8s(),
                 &user1,
                 &user1,
+                TransferPurpose::ErrorRefundIcp,
                 true,
             )),
         )
@@ -1722,7 +2336,11 @@ fn test_error_refund_multiple_users() {
             &user1,
             &amount,
             &mock_stub(get_transfer_and_account_balance_mock_ledger(
-                &amount, &user1, &user1, false,
+                &amount,
+                &user1,
+                &user1,
+                TransferPurpose::ErrorRefundIcp,
+                false,
             )),
         )
         .now_or_never()
@@ -1737,15 +2355,25 @@ fn test_error_refund_multiple_users() {
         //Make sure neither user1 nor any other user can refund tokens from user1 until they are sweeped
         let mut expects = get_account_balance_mock_ledger(&amount, &user1);
         expects.extend(
-            get_transfer_mock_ledger(&amount, &user1, &user2, false)
-                .iter()
-                .copied(),
+            get_transfer_mock_ledger(
+                &amount,
+                &user1,
+                &user2,
+                TransferPurpose::ErrorRefundIcp,
+                false,
+            )
+            .iter()
+            .copied(),
         );
         let refund_err = try_error_refund_err(
             &mut swap,
             &user1,
             &mock_stub(get_transfer_and_account_balance_mock_ledger(
-                &amount, &user1, &user1, false,
+                &amount,
+                &user1,
+                &user1,
+                TransferPurpose::ErrorRefundIcp,
+                false,
             )),
         )
         .now_or_never()
@@ -1761,6 +2389,7 @@ fn test_error_refund_multiple_users() {
                 &(amount),
                 &user2,
                 &user2,
+                TransferPurpose::ErrorRefundIcp,
                 false,
             )),
         )
@@ -1777,7 +2406,13 @@ fn test_error_refund_multiple_users() {
             global_failures,
         } = sweep(
             &mut swap,
-            &mock_stub(get_transfer_mock_ledger(&amount, &user1, &user1, false)),
+            &mock_stub(get_transfer_mock_ledger(
+                &amount,
+                &user1,
+                &user1,
+                TransferPurpose::RefundIcp,
+                false,
+            )),
         )
         .now_or_never()
         .unwrap();
@@ -1796,6 +2431,7 @@ fn test_error_refund_multiple_users() {
                 &DEFAULT_TRANSFER_FEE.get_e8s(),
                 &user1,
                 &user1,
+                TransferPurpose::ErrorRefundIcp,
                 true,
             )),
         )
@@ -1838,7 +2474,11 @@ fn test_error_refund_after_close() {
             &user1,
             &amount,
             &mock_stub(get_transfer_and_account_balance_mock_ledger(
-                &amount, &user1, &user1, false,
+                &amount,
+                &user1,
+                &user1,
+                TransferPurpose::ErrorRefundIcp,
+                false,
             )),
         )
         .now_or_never()
@@ -1875,6 +2515,7 @@ fn test_error_refund_after_close() {
                 &amount,
                 &user1,
                 &SNS_GOVERNANCE_CANISTER_ID.into(),
+                TransferPurpose::CommitIcp,
                 false,
             )),
         )
@@ -1891,7 +2532,11 @@ fn test_error_refund_after_close() {
             &mut swap,
             &user2,
             &mock_stub(get_transfer_and_account_balance_mock_ledger(
-                &E8, &user2, &user2, false,
+                &E8,
+                &user2,
+                &user2,
+                TransferPurpose::ErrorRefundIcp,
+                false,
             )),
         )
         .now_or_never()
@@ -1943,6 +2588,7 @@ fn test_get_buyer_state() {
         .refresh_buyer_token_e8s(
             *TEST_USER1_PRINCIPAL,
             SWAP_CANISTER_ID,
+            START_TIMESTAMP_SECONDS,
             &mock_stub(vec![LedgerExpect::AccountBalance(
                 Account {
                     owner: SWAP_CANISTER_ID.get().into(),
@@ -1979,6 +2625,7 @@ fn test_get_buyer_state() {
         .refresh_buyer_token_e8s(
             *TEST_USER2_PRINCIPAL,
             SWAP_CANISTER_ID,
+            START_TIMESTAMP_SECONDS,
             &mock_stub(vec![LedgerExpect::AccountBalance(
                 Account {
                     owner: SWAP_CANISTER_ID.get().into(),
@@ -2302,7 +2949,9 @@ async fn test_sweep_icp_handles_ledger_transfers() {
                 icp: Some(TransferableAmount {
                     amount_e8s: DEFAULT_TRANSFER_FEE.get_e8s() - 1,
                     ..Default::default()
-                })
+                }),
+                early_participation_icp_e8s: None,
+                cutback_icp: None,
             },
             // This Buyer has already had its transfer succeed, and should result in
             // as Skipped field increment
@@ -2312,7 +2961,9 @@ async fn test_sweep_icp_handles_ledger_transfers() {
                     transfer_start_timestamp_seconds: END_TIMESTAMP_SECONDS,
                     transfer_success_timestamp_seconds: END_TIMESTAMP_SECONDS + 1,
                     ..Default::default()
-                })
+                }),
+                early_participation_icp_e8s: None,
+                cutback_icp: None,
             },
             // This buyer's state is valid, and a mock call to the ledger will allow it
             // to succeed, which should result in a success field increment
@@ -2320,7 +2971,9 @@ async fn test_sweep_icp_handles_ledger_transfers() {
                 icp: Some(TransferableAmount {
                     amount_e8s: 10 * E8,
                     ..Default::default()
-                })
+                }),
+                early_participation_icp_e8s: None,
+                cutback_icp: None,
             },
             // This buyer's state is valid, but a mock call to the ledger will fail the transfer,
             // which should result in a failure field increment.
@@ -2328,7 +2981,9 @@ async fn test_sweep_icp_handles_ledger_transfers() {
                 icp: Some(TransferableAmount {
                     amount_e8s: 10 * E8,
                     ..Default::default()
-                })
+                }),
+                early_participation_icp_e8s: None,
+                cutback_icp: None,
             },
         },
         ..Default::default()
@@ -2380,7 +3035,9 @@ async fn test_finalization_halts_when_sweep_icp_fails() {
                 icp: Some(TransferableAmount {
                     amount_e8s: DEFAULT_TRANSFER_FEE.get_e8s() - 1,
                     ..Default::default()
-                })
+                }),
+                early_participation_icp_e8s: None,
+                cutback_icp: None,
             },
             // This buyer's state is valid, but a mock call to the ledger will fail the transfer,
             // which should result in a failure field increment.
@@ -2388,7 +3045,9 @@ async fn test_finalization_halts_when_sweep_icp_fails() {
                 icp: Some(TransferableAmount {
                     amount_e8s: 10 * E8,
                     ..Default::default()
-                })
+                }),
+                early_participation_icp_e8s: None,
+                cutback_icp: None,
             },
         },
         ..Default::default()
@@ -2937,7 +3596,11 @@ async fn test_restore_dapp_controllers_happy() {
     ));
 
     let restore_dapp_controllers_response = swap
-        .restore_dapp_controllers(&mut sns_root_client, NNS_GOVERNANCE_CANISTER_ID.get())
+        .restore_dapp_controllers(
+            &mut sns_root_client,
+            NNS_GOVERNANCE_CANISTER_ID.get(),
+            END_TIMESTAMP_SECONDS,
+        )
         .await;
 
     // Step 3: Inspect results
@@ -3064,6 +3727,7 @@ async fn test_restore_dapp_controllers_rejects_unauthorized() {
     swap.restore_dapp_controllers(
         &mut ExplodingSnsRootClient::default(),
         PrincipalId::new_anonymous(),
+        END_TIMESTAMP_SECONDS,
     )
     .await;
 }
@@ -3094,6 +3758,7 @@ async fn test_restore_dapp_controllers_cannot_parse_fallback_controllers() {
         .restore_dapp_controllers(
             &mut ExplodingSnsRootClient::default(), // Should fail before using RootClient
             NNS_GOVERNANCE_CANISTER_ID.get(),
+            END_TIMESTAMP_SECONDS,
         )
         .await;
 
@@ -3142,7 +3807,11 @@ async fn test_restore_dapp_controllers_handles_external_root_failures() {
     }));
 
     let restore_dapp_controllers_response = swap
-        .restore_dapp_controllers(&mut sns_root_client, NNS_GOVERNANCE_CANISTER_ID.get())
+        .restore_dapp_controllers(
+            &mut sns_root_client,
+            NNS_GOVERNANCE_CANISTER_ID.get(),
+            END_TIMESTAMP_SECONDS,
+        )
         .await;
 
     // Step 3: Inspect results
@@ -3189,7 +3858,11 @@ async fn test_restore_dapp_controllers_handles_internal_root_failures() {
     ));
 
     let restore_dapp_controllers_response = swap
-        .restore_dapp_controllers(&mut sns_root_client, NNS_GOVERNANCE_CANISTER_ID.get())
+        .restore_dapp_controllers(
+            &mut sns_root_client,
+            NNS_GOVERNANCE_CANISTER_ID.get(),
+            END_TIMESTAMP_SECONDS,
+        )
         .await;
 
     // Step 3: Inspect results
@@ -3214,6 +3887,8 @@ fn test_derived_state() {
     let expected_derived_state1 = DerivedState {
         buyer_total_icp_e8s: 0,
         sns_tokens_per_icp: 0f32,
+        early_participation_sns_tokens_per_icp: None,
+        remaining_direct_participants: None,
     };
     let actual_derived_state1 = swap.derived_state();
     assert_eq!(expected_derived_state1, actual_derived_state1);
@@ -3224,48 +3899,627 @@ fn test_derived_state() {
     };
     swap.params = Some(params);
 
-    let expected_derived_state2 = DerivedState {
-        buyer_total_icp_e8s: 0,
-        sns_tokens_per_icp: 0f32,
-    };
-    let actual_derived_state2 = swap.derived_state();
-    assert_eq!(expected_derived_state2, actual_derived_state2);
+    let expected_derived_state2 = DerivedState {
+        buyer_total_icp_e8s: 0,
+        sns_tokens_per_icp: 0f32,
+        early_participation_sns_tokens_per_icp: None,
+        remaining_direct_participants: None,
+    };
+    let actual_derived_state2 = swap.derived_state();
+    assert_eq!(expected_derived_state2, actual_derived_state2);
+
+    let buyer_state: BuyerState = BuyerState {
+        icp: Some(TransferableAmount {
+            amount_e8s: 100_000_000,
+            transfer_start_timestamp_seconds: 10,
+            transfer_success_timestamp_seconds: 12,
+            ..Default::default()
+        }),
+        early_participation_icp_e8s: None,
+        cutback_icp: None,
+    };
+    let buyers = btreemap! {
+        "".to_string() => buyer_state,
+    };
+
+    swap.buyers = buyers;
+
+    let expected_derived_state3 = DerivedState {
+        buyer_total_icp_e8s: 100_000_000,
+        sns_tokens_per_icp: 10f32,
+        early_participation_sns_tokens_per_icp: None,
+        remaining_direct_participants: None,
+    };
+    let actual_derived_state3 = swap.derived_state();
+    assert_eq!(expected_derived_state3, actual_derived_state3);
+
+    swap.cf_participants = vec![CfParticipant {
+        hotkey_principal: "".to_string(),
+        cf_neurons: vec![CfNeuron {
+            nns_neuron_id: 0,
+            amount_icp_e8s: 300_000_000,
+        }],
+    }];
+
+    let expected_derived_state4 = DerivedState {
+        buyer_total_icp_e8s: 400_000_000,
+        sns_tokens_per_icp: 2.5f32,
+        early_participation_sns_tokens_per_icp: None,
+        remaining_direct_participants: None,
+    };
+    let actual_derived_state4 = swap.derived_state();
+    assert_eq!(expected_derived_state4, actual_derived_state4);
+}
+
+/// Tests that ICP committed within the window of the early participation
+/// bonus buys proportionally more SNS tokens than ICP committed later on.
+#[test]
+fn test_early_participation_bonus() {
+    let params = Params {
+        early_participation_bonus: Some(EarlyParticipationBonus {
+            window_seconds: SECONDS_PER_DAY,
+            bonus_basis_points: 5_000,
+        }),
+        ..params()
+    };
+    assert_is_ok!(params.validate(&init()));
+    let mut swap = Swap::new(init());
+    open_swap(&mut swap, &params).now_or_never().unwrap();
+
+    let refresh = |swap: &mut Swap, buyer: PrincipalId, now_seconds: u64, balance_e8s: u64| {
+        swap.refresh_buyer_token_e8s(
+            buyer,
+            SWAP_CANISTER_ID,
+            now_seconds,
+            &mock_stub(vec![LedgerExpect::AccountBalance(
+                Account {
+                    owner: SWAP_CANISTER_ID.get().into(),
+                    subaccount: Some(principal_to_subaccount(&buyer)),
+                },
+                Ok(Tokens::from_e8s(balance_e8s)),
+            )]),
+        )
+        .now_or_never()
+        .unwrap()
+        .unwrap();
+    };
+    let late_seconds = START_TIMESTAMP_SECONDS + SECONDS_PER_DAY;
+    // Buyer 1 participates early, buyer 2 participates early and tops up
+    // late, and buyer 3 participates late.
+    refresh(
+        &mut swap,
+        *TEST_USER1_PRINCIPAL,
+        START_TIMESTAMP_SECONDS,
+        200 * E8,
+    );
+    refresh(&mut swap, *TEST_USER2_PRINCIPAL, late_seconds - 1, 100 * E8);
+    refresh(&mut swap, *TEST_USER2_PRINCIPAL, late_seconds, 200 * E8);
+    refresh(&mut swap, *TEST_USER3_PRINCIPAL, late_seconds, 200 * E8);
+
+    let early_participation_icp_e8s = |buyer: &PrincipalId| {
+        swap.buyers
+            .get(&buyer.to_string())
+            .unwrap()
+            .early_participation_icp_e8s
+    };
+    assert_eq!(
+        early_participation_icp_e8s(&TEST_USER1_PRINCIPAL),
+        Some(200 * E8)
+    );
+    assert_eq!(
+        early_participation_icp_e8s(&TEST_USER2_PRINCIPAL),
+        Some(100 * E8)
+    );
+    assert_eq!(early_participation_icp_e8s(&TEST_USER3_PRINCIPAL), None);
+
+    // The weights are 300, 250 and 200 ICP, for a total of 750 ICP.
+    let derived_state = swap.derived_state();
+    assert_eq!(derived_state.buyer_total_icp_e8s, 600 * E8);
+    assert!((derived_state.sns_tokens_per_icp - 1_000_000.0 / 750.0).abs() < 0.01);
+    let early_participation_sns_tokens_per_icp = derived_state
+        .early_participation_sns_tokens_per_icp
+        .unwrap();
+    assert!((early_participation_sns_tokens_per_icp - 2_000.0).abs() < 0.01);
+
+    assert!(swap.try_commit_or_abort(END_TIMESTAMP_SECONDS));
+    assert_eq!(swap.lifecycle(), Committed);
+    let sns_e8s = |buyer: &PrincipalId| {
+        swap.neuron_recipes
+            .iter()
+            .filter(|recipe| {
+                recipe.investor
+                    == Some(Investor::Direct(DirectInvestment {
+                        buyer_principal: buyer.to_string(),
+                    }))
+            })
+            .map(|recipe| recipe.sns.as_ref().unwrap().amount_e8s)
+            .sum::<u64>()
+    };
+    assert_eq!(sns_e8s(&TEST_USER1_PRINCIPAL), 400_000 * E8);
+    assert_eq!(sns_e8s(&TEST_USER2_PRINCIPAL), 1_000_000 * E8 * 250 / 750);
+    assert_eq!(sns_e8s(&TEST_USER3_PRINCIPAL), 1_000_000 * E8 * 200 / 750);
+}
+
+/// Test that once `max_direct_participants` is reached, new buyers are
+/// rejected while existing buyers can still increase their participation.
+#[test]
+fn test_max_direct_participants() {
+    let params = Params {
+        min_participants: 2,
+        max_direct_participants: Some(2),
+        ..params()
+    };
+    assert_is_ok!(params.validate(&init()));
+    let mut swap = Swap::new(init());
+    open_swap(&mut swap, &params).now_or_never().unwrap();
+    assert_eq!(swap.derived_state().remaining_direct_participants, Some(2));
+
+    let refresh = |swap: &mut Swap, buyer: PrincipalId, balance_e8s: u64| {
+        swap.refresh_buyer_token_e8s(
+            buyer,
+            SWAP_CANISTER_ID,
+            START_TIMESTAMP_SECONDS,
+            &mock_stub(vec![LedgerExpect::AccountBalance(
+                Account {
+                    owner: SWAP_CANISTER_ID.get().into(),
+                    subaccount: Some(principal_to_subaccount(&buyer)),
+                },
+                Ok(Tokens::from_e8s(balance_e8s)),
+            )]),
+        )
+        .now_or_never()
+        .unwrap()
+    };
+    assert_is_ok!(refresh(&mut swap, *TEST_USER1_PRINCIPAL, 100 * E8));
+    assert_is_ok!(refresh(&mut swap, *TEST_USER2_PRINCIPAL, 100 * E8));
+    assert_eq!(swap.derived_state().remaining_direct_participants, Some(0));
+
+    let err = refresh(&mut swap, *TEST_USER3_PRINCIPAL, 100 * E8).unwrap_err();
+    assert!(err.contains("limit of 2 direct participants"), "{}", err);
+    assert!(!swap.buyers.contains_key(&TEST_USER3_PRINCIPAL.to_string()));
+    let request = NewSaleTicketRequest {
+        amount_icp_e8s: 100 * E8,
+        subaccount: None,
+    };
+    assert_eq!(
+        swap.new_sale_ticket(&request, *TEST_USER3_PRINCIPAL, 0)
+            .ticket()
+            .unwrap_err()
+            .error_type,
+        new_sale_ticket_response::err::Type::SwapParticipantLimitReached as i32
+    );
+
+    // Existing buyers are not affected by the limit.
+    assert_is_ok!(swap
+        .new_sale_ticket(&request, *TEST_USER1_PRINCIPAL, 0)
+        .ticket());
+    assert_is_ok!(refresh(&mut swap, *TEST_USER1_PRINCIPAL, 200 * E8));
+    assert_eq!(swap.buyers.len(), 2);
+}
+
+/// Test that with a pro rata cutback, participations beyond `max_icp_e8s`
+/// are accepted while the swap is open, cut back pro rata to the ICP above
+/// `min_participant_icp_e8s` when it commits, and refunded when the ICP is
+/// swept.
+#[test]
+fn test_pro_rata_cutback() {
+    let params = Params {
+        max_icp_e8s: 700 * E8,
+        min_participant_icp_e8s: 150 * E8,
+        max_participant_icp_e8s: 400 * E8,
+        pro_rata_cutback: Some(true),
+        ..params()
+    };
+    assert_is_ok!(params.validate(&init()));
+    let mut swap = Swap::new(init());
+    open_swap(&mut swap, &params).now_or_never().unwrap();
+
+    let refresh = |swap: &mut Swap, buyer: PrincipalId, balance_e8s: u64| {
+        swap.refresh_buyer_token_e8s(
+            buyer,
+            SWAP_CANISTER_ID,
+            START_TIMESTAMP_SECONDS,
+            &mock_stub(vec![LedgerExpect::AccountBalance(
+                Account {
+                    owner: SWAP_CANISTER_ID.get().into(),
+                    subaccount: Some(principal_to_subaccount(&buyer)),
+                },
+                Ok(Tokens::from_e8s(balance_e8s)),
+            )]),
+        )
+        .now_or_never()
+        .unwrap()
+    };
+    let buyer = PrincipalId::new_user_test_id;
+    assert_is_ok!(refresh(&mut swap, buyer(1), 400 * E8));
+    assert_is_ok!(refresh(&mut swap, buyer(2), 300 * E8));
+    assert!(swap.icp_target_reached());
+    // Accepted beyond max_icp_e8s, but still capped at max_participant_icp_e8s.
+    assert_eq!(
+        refresh(&mut swap, buyer(3), 500 * E8)
+            .unwrap()
+            .icp_accepted_participation_e8s,
+        400 * E8
+    );
+    assert_is_ok!(refresh(&mut swap, buyer(4), 150 * E8));
+    assert_eq!(swap.participant_total_icp_e8s(), 1250 * E8);
+
+    // A fifth buyer could not keep min_participant_icp_e8s after the cutback.
+    let err = refresh(&mut swap, buyer(5), 150 * E8).unwrap_err();
+    assert!(err.contains("oversubscribed"), "{}", err);
+    let request = NewSaleTicketRequest {
+        amount_icp_e8s: 150 * E8,
+        subaccount: None,
+    };
+    assert_eq!(
+        swap.new_sale_ticket(&request, buyer(5), 0)
+            .ticket()
+            .unwrap_err()
+            .error_type,
+        new_sale_ticket_response::err::Type::CutbackCapacityReached as i32
+    );
+
+    // The excess of 550 ICP is cut back from the 250, 150, 250 and 0 ICP
+    // above min_participant_icp_e8s, i.e., by 5/13, 3/13, 5/13 and 0 of the
+    // excess. The 2 e8s left over after rounding down go to the buyers with
+    // the largest remainders, buyers 1 and 3.
+    assert!(swap.try_commit_or_abort(START_TIMESTAMP_SECONDS + 1));
+    assert_eq!(swap.lifecycle(), Committed);
+    assert_eq!(swap.participant_total_icp_e8s(), 700 * E8);
+    let expected_cutbacks_e8s = btreemap! {
+        buyer(1).to_string() => 21_153_846_154,
+        buyer(2).to_string() => 12_692_307_692,
+        buyer(3).to_string() => 21_153_846_154,
+        buyer(4).to_string() => 0,
+    };
+    for (principal, cutback_e8s) in &expected_cutbacks_e8s {
+        let buyer_state = &swap.buyers[principal];
+        assert_eq!(buyer_state.cutback_icp_e8s(), *cutback_e8s, "{}", principal);
+        assert!(buyer_state.amount_icp_e8s() >= params.min_participant_icp_e8s);
+    }
+    assert!(swap.buyers[&buyer(4).to_string()].cutback_icp.is_none());
+
+    // The cutbacks are refunded to the buyers along with the commit.
+    let mut expected_transfers = vec![];
+    for (principal, buyer_state) in &swap.buyers {
+        let principal = PrincipalId::from_str(principal).unwrap();
+        expected_transfers.push(LedgerExpect::TransferFunds(
+            buyer_state.amount_icp_e8s() - DEFAULT_TRANSFER_FEE.get_e8s(),
+            DEFAULT_TRANSFER_FEE.get_e8s(),
+            Some(principal_to_subaccount(&principal)),
+            Account {
+                owner: SNS_GOVERNANCE_CANISTER_ID.get().into(),
+                subaccount: None,
+            },
+            transfer_memo(TransferPurpose::CommitIcp, OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID),
+            Ok(1066),
+        ));
+        if buyer_state.cutback_icp.is_some() {
+            expected_transfers.push(LedgerExpect::TransferFunds(
+                buyer_state.cutback_icp_e8s() - DEFAULT_TRANSFER_FEE.get_e8s(),
+                DEFAULT_TRANSFER_FEE.get_e8s(),
+                Some(principal_to_subaccount(&principal)),
+                Account {
+                    owner: principal.0,
+                    subaccount: None,
+                },
+                transfer_memo(TransferPurpose::RefundIcp, OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID),
+                Ok(1067),
+            ));
+        }
+    }
+    let sweep_result = swap
+        .sweep_icp(now_fn, &mock_stub(expected_transfers))
+        .now_or_never()
+        .unwrap();
+    assert_eq!(
+        sweep_result,
+        SweepResult {
+            success: 7,
+            skipped: 0,
+            failure: 0,
+            invalid: 0,
+            global_failures: 0,
+        }
+    );
+    let cutback_icp = swap.buyers[&buyer(2).to_string()]
+        .cutback_icp
+        .clone()
+        .unwrap();
+    assert_eq!(
+        cutback_icp.amount_transferred_e8s,
+        Some(12_692_307_692 - DEFAULT_TRANSFER_FEE.get_e8s())
+    );
+}
+
+/// Test that canisters are refused as direct participants, unless
+/// `init.allow_canister_participants` is set.
+#[test]
+fn test_canister_participants() {
+    let canister = CanisterId::from_u64(4242).get();
+    assert!(is_canister_principal(&canister));
+    assert!(!is_canister_principal(&TEST_USER1_PRINCIPAL));
+    assert!(!is_canister_principal(&PrincipalId::new_user_test_id(4242)));
+    assert!(!is_canister_principal(&PrincipalId::new_anonymous()));
+
+    let refresh = |swap: &mut Swap, buyer: PrincipalId, expect: Vec<LedgerExpect>| {
+        swap.refresh_buyer_token_e8s(
+            buyer,
+            SWAP_CANISTER_ID,
+            START_TIMESTAMP_SECONDS,
+            &mock_stub(expect),
+        )
+        .now_or_never()
+        .unwrap()
+    };
+    let balance = |buyer: PrincipalId| {
+        vec![LedgerExpect::AccountBalance(
+            Account {
+                owner: SWAP_CANISTER_ID.get().into(),
+                subaccount: Some(principal_to_subaccount(&buyer)),
+            },
+            Ok(Tokens::from_e8s(100 * E8)),
+        )]
+    };
+    let request = NewSaleTicketRequest {
+        amount_icp_e8s: 100 * E8,
+        subaccount: None,
+    };
+
+    // By default, canisters are refused before the ledger is queried.
+    let mut swap = Swap::new(init());
+    open_swap(&mut swap, &params()).now_or_never().unwrap();
+    let err = refresh(&mut swap, canister, vec![]).unwrap_err();
+    assert!(err.contains("canisters are not allowed"), "{}", err);
+    assert!(swap.buyers.is_empty());
+    assert_eq!(
+        swap.new_sale_ticket(&request, canister, 0)
+            .ticket()
+            .unwrap_err()
+            .error_type,
+        new_sale_ticket_response::err::Type::CanisterPrincipalNotAllowed as i32
+    );
+    assert_is_ok!(refresh(
+        &mut swap,
+        *TEST_USER1_PRINCIPAL,
+        balance(*TEST_USER1_PRINCIPAL)
+    ));
+
+    // Canisters participate like other principals if explicitly allowed.
+    let init = Init {
+        allow_canister_participants: Some(true),
+        ..init()
+    };
+    assert_is_ok!(init.validate());
+    let mut swap = Swap::new(init);
+    open_swap(&mut swap, &params()).now_or_never().unwrap();
+    assert_is_ok!(swap.new_sale_ticket(&request, canister, 0).ticket());
+    assert_is_ok!(refresh(&mut swap, canister, balance(canister)));
+    assert!(swap.buyers.contains_key(&canister.to_string()));
+}
+
+/// Test that `get_sale_status` reports the remaining capacity and whether
+/// the swap commits or aborts, consistently with `try_commit_or_abort`.
+#[test]
+fn test_get_sale_status() {
+    let mut swap = Swap::new(init());
+    assert_eq!(
+        swap.get_sale_status(&GetSaleStatusRequest {}, START_TIMESTAMP_SECONDS),
+        GetSaleStatusResponse::default()
+    );
+    let params = params();
+    open_swap(&mut swap, &params).now_or_never().unwrap();
+
+    assert_eq!(
+        swap.get_sale_status(&GetSaleStatusRequest {}, START_TIMESTAMP_SECONDS),
+        GetSaleStatusResponse {
+            remaining_icp_e8s: Some(params.max_icp_e8s),
+            max_icp_reached: Some(false),
+            seconds_remaining: Some(END_TIMESTAMP_SECONDS - START_TIMESTAMP_SECONDS),
+            min_participation_reached: Some(false),
+            commit_due: Some(false),
+            abort_due: Some(false),
+        }
+    );
+    let status = swap.get_sale_status(&GetSaleStatusRequest {}, END_TIMESTAMP_SECONDS);
+    assert_eq!(status.seconds_remaining, Some(0));
+    assert_eq!(status.abort_due, Some(true));
+    assert_eq!(status.commit_due, Some(false));
+
+    for buyer in [
+        *TEST_USER1_PRINCIPAL,
+        *TEST_USER2_PRINCIPAL,
+        *TEST_USER3_PRINCIPAL,
+    ] {
+        assert_is_ok!(swap
+            .refresh_buyer_token_e8s(
+                buyer,
+                SWAP_CANISTER_ID,
+                START_TIMESTAMP_SECONDS,
+                &mock_stub(vec![LedgerExpect::AccountBalance(
+                    Account {
+                        owner: SWAP_CANISTER_ID.get().into(),
+                        subaccount: Some(principal_to_subaccount(&buyer)),
+                    },
+                    Ok(Tokens::from_e8s(100 * E8)),
+                )]),
+            )
+            .now_or_never()
+            .unwrap());
+    }
+    let status = swap.get_sale_status(&GetSaleStatusRequest {}, START_TIMESTAMP_SECONDS);
+    assert_eq!(
+        status.remaining_icp_e8s,
+        Some(params.max_icp_e8s - 300 * E8)
+    );
+    assert_eq!(status.min_participation_reached, Some(true));
+    assert_eq!(status.commit_due, Some(false));
+    assert_eq!(status.abort_due, Some(false));
+
+    let status = swap.get_sale_status(&GetSaleStatusRequest {}, END_TIMESTAMP_SECONDS);
+    assert_eq!(status.commit_due, Some(true));
+    assert_eq!(status.abort_due, Some(false));
+    assert!(swap.try_commit_or_abort(END_TIMESTAMP_SECONDS));
+    assert_eq!(swap.lifecycle(), Committed);
+    assert_eq!(
+        swap.get_sale_status(&GetSaleStatusRequest {}, END_TIMESTAMP_SECONDS),
+        GetSaleStatusResponse::default()
+    );
+}
+
+/// Opens a swap with a dutch auction from 10 to 2 ICP per SNS token, in
+/// which each of the three test users commits 200 ICP.
+fn open_dutch_auction_swap() -> Swap {
+    let params = Params {
+        dutch_auction: Some(DutchAuction {
+            start_icp_e8s_per_sns_token: 10 * E8,
+            floor_icp_e8s_per_sns_token: 2 * E8,
+        }),
+        ..params()
+    };
+    assert_is_ok!(params.validate(&init()));
+    let mut swap = Swap::new(init());
+    open_swap(&mut swap, &params).now_or_never().unwrap();
+
+    for buyer in [
+        *TEST_USER1_PRINCIPAL,
+        *TEST_USER2_PRINCIPAL,
+        *TEST_USER3_PRINCIPAL,
+    ] {
+        swap.refresh_buyer_token_e8s(
+            buyer,
+            SWAP_CANISTER_ID,
+            START_TIMESTAMP_SECONDS,
+            &mock_stub(vec![LedgerExpect::AccountBalance(
+                Account {
+                    owner: SWAP_CANISTER_ID.get().into(),
+                    subaccount: Some(principal_to_subaccount(&buyer)),
+                },
+                Ok(Tokens::from_e8s(200 * E8)),
+            )]),
+        )
+        .now_or_never()
+        .unwrap()
+        .unwrap();
+    }
+    swap
+}
+
+/// Test that with a dutch auction, the participants pay the price reached by
+/// the auction when the swap commits, and that only the SNS tokens that they
+/// buy at that price are distributed.
+#[test]
+fn test_dutch_auction() {
+    let mut swap = open_dutch_auction_swap();
+
+    // Halfway through the sale, 600 ICP buys 100 SNS tokens at 6 ICP each.
+    let midway_seconds = (START_TIMESTAMP_SECONDS + END_TIMESTAMP_SECONDS) / 2;
+    assert_eq!(
+        swap.get_auction_price(&GetAuctionPriceRequest {}, midway_seconds),
+        GetAuctionPriceResponse {
+            auction_icp_e8s_per_sns_token: Some(6 * E8),
+            clearing_icp_e8s_per_sns_token: Some(6 * E8),
+            sns_token_e8s_sold: Some(100 * E8),
+        }
+    );
+
+    // At the floor price of 2 ICP, each buyer gets 100 SNS tokens.
+    assert!(swap.try_commit_or_abort(END_TIMESTAMP_SECONDS));
+    assert_eq!(swap.lifecycle(), Committed);
+    for buyer in [
+        *TEST_USER1_PRINCIPAL,
+        *TEST_USER2_PRINCIPAL,
+        *TEST_USER3_PRINCIPAL,
+    ] {
+        let sns_e8s = swap
+            .neuron_recipes
+            .iter()
+            .filter(|recipe| {
+                recipe.investor
+                    == Some(Investor::Direct(DirectInvestment {
+                        buyer_principal: buyer.to_string(),
+                    }))
+            })
+            .map(|recipe| recipe.sns.as_ref().unwrap().amount_e8s)
+            .sum::<u64>();
+        assert_eq!(sns_e8s, 100 * E8);
+    }
+    assert_eq!(
+        swap.get_auction_price(&GetAuctionPriceRequest {}, END_TIMESTAMP_SECONDS),
+        GetAuctionPriceResponse::default()
+    );
+}
 
-    let buyer_state: BuyerState = BuyerState {
-        icp: Some(TransferableAmount {
-            amount_e8s: 100_000_000,
-            transfer_start_timestamp_seconds: 10,
-            transfer_success_timestamp_seconds: 12,
-            ..Default::default()
-        }),
-    };
-    let buyers = btreemap! {
-        "".to_string() => buyer_state,
-    };
+/// Test that the SNS tokens not sold by a dutch auction are returned to the
+/// treasury of SNS governance when the SNS tokens are swept.
+#[tokio::test]
+async fn test_dutch_auction_returns_unsold_sns_tokens() {
+    let mut swap = open_dutch_auction_swap();
+    assert!(swap.try_commit_or_abort(END_TIMESTAMP_SECONDS));
+    assert_eq!(swap.lifecycle(), Committed);
 
-    swap.buyers = buyers;
+    // At the floor price of 2 ICP, 600 ICP buys 300 of the SNS tokens.
+    let unsold_sns_e8s = params().sns_token_e8s - 300 * E8;
+    assert_eq!(
+        swap.unsold_sns,
+        Some(TransferableAmount {
+            amount_e8s: unsold_sns_e8s,
+            ..Default::default()
+        })
+    );
 
-    let expected_derived_state3 = DerivedState {
-        buyer_total_icp_e8s: 100_000_000,
-        sns_tokens_per_icp: 10f32,
-    };
-    let actual_derived_state3 = swap.derived_state();
-    assert_eq!(expected_derived_state3, actual_derived_state3);
+    let neuron_recipes_count = swap.neuron_recipes.len();
+    let sns_ledger = SpyLedger::new(
+        (0..=neuron_recipes_count)
+            .map(|_| LedgerReply::TransferFunds(Ok(1)))
+            .collect(),
+    );
+    let sweep_result = swap.sweep_sns(now_fn, &sns_ledger).await;
+    assert_eq!(
+        sweep_result,
+        SweepResult {
+            success: neuron_recipes_count as u32 + 1,
+            ..Default::default()
+        }
+    );
 
-    swap.cf_participants = vec![CfParticipant {
-        hotkey_principal: "".to_string(),
-        cf_neurons: vec![CfNeuron {
-            nns_neuron_id: 0,
-            amount_icp_e8s: 300_000_000,
-        }],
-    }];
+    let sns_fee_e8s = init().transaction_fee_e8s();
+    let sns_governance = init().sns_governance().unwrap().get();
+    let calls = sns_ledger.get_calls_snapshot();
+    assert_eq!(calls.len(), neuron_recipes_count + 1);
+    assert_eq!(
+        calls.last().unwrap(),
+        &LedgerCall::TransferFundsICRC1 {
+            amount_e8s: unsold_sns_e8s - sns_fee_e8s,
+            fee_e8s: sns_fee_e8s,
+            from_subaccount: None,
+            to: Account {
+                owner: sns_governance.0,
+                subaccount: Some(compute_distribution_subaccount_bytes(
+                    sns_governance,
+                    TREASURY_SUBACCOUNT_NONCE
+                )),
+            },
+            memo: transfer_memo(
+                TransferPurpose::DistributeSns,
+                OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID
+            ),
+        }
+    );
+    assert_eq!(
+        swap.unsold_sns.as_ref().unwrap().amount_transferred_e8s,
+        Some(unsold_sns_e8s - sns_fee_e8s)
+    );
 
-    let expected_derived_state4 = DerivedState {
-        buyer_total_icp_e8s: 400_000_000,
-        sns_tokens_per_icp: 2.5f32,
-    };
-    let actual_derived_state4 = swap.derived_state();
-    assert_eq!(expected_derived_state4, actual_derived_state4);
+    // Sweeping again does not return the unsold SNS tokens twice.
+    let sweep_result = swap.sweep_sns(now_fn, &SpyLedger::default()).await;
+    assert_eq!(
+        sweep_result,
+        SweepResult {
+            skipped: neuron_recipes_count as u32 + 1,
+            ..Default::default()
+        }
+    );
 }
 
 /// Test that claim_swap_neurons is called with the correct preconditions
@@ -3799,6 +5053,7 @@ fn test_list_direct_participants_list_is_deterministic() {
         swap.refresh_buyer_token_e8s(
             PrincipalId::new_user_test_id(i),
             SWAP_CANISTER_ID,
+            START_TIMESTAMP_SECONDS,
             &spy_ledger,
         )
         .now_or_never()
@@ -3838,6 +5093,7 @@ fn test_list_direct_participants_paginates_all_participants() {
         swap.refresh_buyer_token_e8s(
             PrincipalId::new_user_test_id(i),
             SWAP_CANISTER_ID,
+            START_TIMESTAMP_SECONDS,
             &spy_ledger,
         )
         .now_or_never()
@@ -3925,6 +5181,7 @@ fn test_rebuild_indexes_ignores_existing_index() {
         swap.refresh_buyer_token_e8s(
             PrincipalId::new_user_test_id(i),
             SWAP_CANISTER_ID,
+            START_TIMESTAMP_SECONDS,
             &spy_ledger,
         )
         .now_or_never()
@@ -3976,10 +5233,16 @@ fn test_refresh_buyer_tokens() {
 
     let buy_token_ok =
         |swap: &mut Swap, user: &PrincipalId, balance_icp: &u64, balance_icp_accepted: &u64| {
+            let old_balance_icp_accepted = swap
+                .buyers
+                .get(&user.to_string())
+                .map(|buyer_state| buyer_state.amount_icp_e8s())
+                .unwrap_or(0);
             assert_eq!(
                 swap.refresh_buyer_token_e8s(
                     *user,
                     SWAP_CANISTER_ID,
+                    START_TIMESTAMP_SECONDS,
                     &mock_stub(vec![LedgerExpect::AccountBalance(
                         Account {
                             owner: SWAP_CANISTER_ID.get().into(),
@@ -3993,7 +5256,13 @@ fn test_refresh_buyer_tokens() {
                 .unwrap(),
                 RefreshBuyerTokensResponse {
                     icp_accepted_participation_e8s: *balance_icp_accepted,
-                    icp_ledger_account_balance_e8s: *balance_icp
+                    icp_ledger_account_balance_e8s: *balance_icp,
+                    participation_increment: (*balance_icp_accepted > old_balance_icp_accepted)
+                        .then(|| ParticipationIncrement {
+                            amount_icp_e8s: *balance_icp_accepted - old_balance_icp_accepted,
+                            timestamp_seconds: START_TIMESTAMP_SECONDS,
+                            ticket_id: None,
+                        }),
                 }
             );
         };
@@ -4004,6 +5273,7 @@ fn test_refresh_buyer_tokens() {
                 .refresh_buyer_token_e8s(
                     *user,
                     SWAP_CANISTER_ID,
+                    START_TIMESTAMP_SECONDS,
                     &mock_stub(vec![LedgerExpect::AccountBalance(
                         Account {
                             owner: SWAP_CANISTER_ID.get().into(),
@@ -4077,7 +5347,12 @@ fn test_refresh_buyer_tokens() {
 
         // Make sure tokens can only be commited once the swap is open
         assert!(swap
-            .refresh_buyer_token_e8s(user1, SWAP_CANISTER_ID, &mock_stub(vec![]))
+            .refresh_buyer_token_e8s(
+                user1,
+                SWAP_CANISTER_ID,
+                START_TIMESTAMP_SECONDS,
+                &mock_stub(vec![])
+            )
             .now_or_never()
             .unwrap()
             .unwrap_err()
@@ -4443,6 +5718,159 @@ fn test_refresh_buyer_tokens() {
     }
 }
 
+/// Test that each accepted increment of a buyer's participation is recorded,
+/// and that refreshes accepting nothing are not.
+#[test]
+fn test_refresh_buyer_tokens_records_participation_history() {
+    let user1 = PrincipalId::new_user_test_id(1);
+    let params = Params {
+        max_icp_e8s: 50 * E8,
+        min_icp_e8s: 5 * E8,
+        min_participants: 1,
+        min_participant_icp_e8s: 2 * E8,
+        max_participant_icp_e8s: 40 * E8,
+        sns_token_e8s: 100_000 * E8,
+        ..params()
+    };
+    let mut swap = Swap::new(init());
+    assert!(swap
+        .open(
+            SWAP_CANISTER_ID,
+            &mock_stub(vec![LedgerExpect::AccountBalance(
+                Account {
+                    owner: SWAP_CANISTER_ID.get().into(),
+                    subaccount: None,
+                },
+                Ok(Tokens::from_e8s(params.sns_token_e8s)),
+            )]),
+            START_TIMESTAMP_SECONDS,
+            OpenRequest {
+                params: Some(params),
+                cf_participants: vec![],
+                open_sns_token_swap_proposal_id: Some(OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID),
+            },
+        )
+        .now_or_never()
+        .unwrap()
+        .is_ok());
+
+    let refresh = |swap: &mut Swap, balance_e8s: u64, now_seconds| {
+        swap.refresh_buyer_token_e8s(
+            user1,
+            SWAP_CANISTER_ID,
+            now_seconds,
+            &mock_stub(vec![LedgerExpect::AccountBalance(
+                Account {
+                    owner: SWAP_CANISTER_ID.get().into(),
+                    subaccount: Some(principal_to_subaccount(&user1)),
+                },
+                Ok(Tokens::from_e8s(balance_e8s)),
+            )]),
+        )
+        .now_or_never()
+        .unwrap()
+        .unwrap()
+    };
+
+    let first_increment = ParticipationIncrement {
+        amount_icp_e8s: 5 * E8,
+        timestamp_seconds: START_TIMESTAMP_SECONDS,
+        ticket_id: None,
+    };
+    let second_increment = ParticipationIncrement {
+        amount_icp_e8s: 3 * E8,
+        timestamp_seconds: START_TIMESTAMP_SECONDS + 2,
+        ticket_id: None,
+    };
+    // Each refresh accepting ICP returns its increment as a receipt.
+    assert_eq!(
+        refresh(&mut swap, 5 * E8, START_TIMESTAMP_SECONDS).participation_increment,
+        Some(first_increment.clone())
+    );
+    // Nothing new was transferred.
+    assert_eq!(
+        refresh(&mut swap, 5 * E8, START_TIMESTAMP_SECONDS + 1).participation_increment,
+        None
+    );
+    assert_eq!(
+        refresh(&mut swap, 8 * E8, START_TIMESTAMP_SECONDS + 2).participation_increment,
+        Some(second_increment.clone())
+    );
+
+    let history = swap.get_buyer_participation_history(
+        &GetBuyerParticipationHistoryRequest { principal_id: None },
+        user1,
+    );
+    assert_eq!(
+        history,
+        GetBuyerParticipationHistoryResponse {
+            participation_history: vec![first_increment, second_increment],
+            pruned_participation_icp_e8s: None,
+            amount_icp_e8s: 8 * E8,
+            cutback_icp_e8s: 0,
+            refunded_icp_e8s: 0,
+        }
+    );
+
+    // Other principals can look up the history of a buyer, and get an empty
+    // history for principals that did not participate.
+    assert_eq!(
+        swap.get_buyer_participation_history(
+            &GetBuyerParticipationHistoryRequest {
+                principal_id: Some(user1)
+            },
+            PrincipalId::new_user_test_id(2),
+        ),
+        history
+    );
+    assert_eq!(
+        swap.get_buyer_participation_history(
+            &GetBuyerParticipationHistoryRequest { principal_id: None },
+            PrincipalId::new_user_test_id(2),
+        ),
+        GetBuyerParticipationHistoryResponse::default()
+    );
+
+    // The refunds of an aborted swap can be audited against the history.
+    let buyer_state = swap.buyers.get_mut(&user1.to_string()).unwrap();
+    let icp = buyer_state.icp.as_mut().unwrap();
+    icp.transfer_success_timestamp_seconds = START_TIMESTAMP_SECONDS + 3;
+    icp.amount_transferred_e8s = Some(8 * E8 - DEFAULT_TRANSFER_FEE.get_e8s());
+    swap.lifecycle = Aborted as i32;
+    assert_eq!(
+        swap.get_buyer_participation_history(
+            &GetBuyerParticipationHistoryRequest { principal_id: None },
+            user1,
+        )
+        .refunded_icp_e8s,
+        8 * E8 - DEFAULT_TRANSFER_FEE.get_e8s()
+    );
+}
+
+/// Test that the oldest increments are pruned once the history is full, and
+/// that the participation of a buyer from before the history is accounted
+/// for as pruned.
+#[test]
+fn test_participation_history_is_pruned() {
+    let increment = |amount_icp_e8s| ParticipationIncrement {
+        amount_icp_e8s,
+        timestamp_seconds: START_TIMESTAMP_SECONDS,
+        ticket_id: None,
+    };
+
+    let mut history = BuyerParticipationHistory::new(3 * E8);
+    history.record(increment(E8));
+    assert_eq!(history.pruned_participation_icp_e8s, Some(3 * E8));
+    assert_eq!(history.increments, vec![increment(E8)]);
+
+    for i in 0..MAX_PARTICIPATION_HISTORY_LEN as u64 {
+        history.record(increment(1));
+        assert_eq!(history.participated_icp_e8s(), 4 * E8 + i + 1);
+    }
+    assert_eq!(history.increments.len(), MAX_PARTICIPATION_HISTORY_LEN);
+    assert_eq!(history.pruned_participation_icp_e8s, Some(4 * E8));
+}
+
 /// Test that the get_state API bounds the dynamic data sources returned in the
 /// GetStateResponse.
 #[test]
@@ -4547,7 +5975,252 @@ async fn test_finalize_swap_abort_sets_amount_transferred_and_fees_correctly() {
                 transfer_success_timestamp_seconds: END_TIMESTAMP_SECONDS + 10,
                 amount_transferred_e8s: Some(50 * E8 - DEFAULT_TRANSFER_FEE.get_e8s()),
                 transfer_fee_paid_e8s: Some(DEFAULT_TRANSFER_FEE.get_e8s())
-            })
+            }),
+            early_participation_icp_e8s: None,
+            cutback_icp: None,
         }
     );
 }
+
+/// A committed swap with `buyers` buyers, whose ICP was swept, and one
+/// claimed neuron recipe per buyer.
+fn create_finalized_swap(buyers: u64) -> Swap {
+    let settled = |amount_e8s| TransferableAmount {
+        amount_e8s,
+        transfer_start_timestamp_seconds: END_TIMESTAMP_SECONDS + 5,
+        transfer_success_timestamp_seconds: END_TIMESTAMP_SECONDS + 10,
+        ..Default::default()
+    };
+    Swap {
+        buyers: (0..buyers)
+            .map(|i| {
+                let buyer_state = BuyerState {
+                    icp: Some(settled(10 * E8)),
+                    ..Default::default()
+                };
+                (i2principal_id_string(i), buyer_state)
+            })
+            .collect(),
+        neuron_recipes: (0..buyers)
+            .map(|i| SnsNeuronRecipe {
+                sns: Some(settled(E8)),
+                claimed_status: Some(ClaimedStatus::Success as i32),
+                ..create_single_neuron_recipe(E8, i2principal_id_string(i))
+            })
+            .collect(),
+        ..create_generic_committed_swap()
+    }
+}
+
+#[tokio::test]
+async fn test_archive_finalized_state_moves_records_in_batches() {
+    let buyers = ARCHIVE_BATCH_SIZE as u64;
+    let mut swap = create_finalized_swap(buyers);
+    let derived_state = swap.derived_state();
+    let mut archive_client = SpySwapArchiveClient {
+        failing_batch: Some(1),
+        ..Default::default()
+    };
+
+    // The second batch fails: only the records of the first one are removed.
+    let response = swap
+        .archive_finalized_state(
+            END_TIMESTAMP_SECONDS + 20,
+            SNS_GOVERNANCE_CANISTER_ID,
+            &mut archive_client,
+        )
+        .await;
+    assert!(response.error_message.is_some(), "{:?}", response);
+    assert!(!swap.is_finalize_swap_locked());
+    assert_eq!(archive_client.requests.len(), 1);
+    assert_eq!(archive_client.requests[0].buyers.len(), ARCHIVE_BATCH_SIZE);
+    assert!(swap.buyers.is_empty());
+    assert_eq!(swap.neuron_recipes.len(), buyers as usize);
+    let archive = response.archive.unwrap();
+    assert_eq!(archive.batches, 1);
+    assert_eq!(archive.archived_buyers, buyers);
+    assert_eq!(archive.archived_buyer_icp_e8s, buyers * 10 * E8);
+    assert_eq!(archive.completed_timestamp_seconds, None);
+    assert_eq!(swap.derived_state(), derived_state);
+
+    // The next call resumes with the failed batch.
+    let response = swap
+        .archive_finalized_state(
+            END_TIMESTAMP_SECONDS + 30,
+            SNS_GOVERNANCE_CANISTER_ID,
+            &mut archive_client,
+        )
+        .await;
+    assert_eq!(response.error_message, None);
+    assert_eq!(archive_client.requests.len(), 2);
+    assert_eq!(archive_client.requests[1].batch_index, 1);
+    assert_eq!(
+        archive_client.requests[1].neuron_recipes.len(),
+        ARCHIVE_BATCH_SIZE
+    );
+    assert!(swap.neuron_recipes.is_empty());
+    assert_eq!(
+        response.archive,
+        Some(SwapArchive {
+            archive_canister_id: SNS_GOVERNANCE_CANISTER_ID.to_string(),
+            batches: 2,
+            archived_buyers: buyers,
+            archived_neuron_recipes: buyers,
+            archived_buyer_icp_e8s: buyers * 10 * E8,
+            archived_buyer_weight_e8s: buyers * 10 * E8,
+            completed_timestamp_seconds: Some(END_TIMESTAMP_SECONDS + 30),
+        })
+    );
+    assert_eq!(swap.derived_state(), derived_state);
+}
+
+#[tokio::test]
+async fn test_archive_finalized_state_requires_finalized_swap() {
+    let mut swap = create_finalized_swap(3);
+    swap.neuron_recipes[1].claimed_status = Some(ClaimedStatus::Failed as i32);
+    let mut archive_client = SpySwapArchiveClient::default();
+
+    let response = swap
+        .archive_finalized_state(
+            END_TIMESTAMP_SECONDS + 20,
+            SNS_GOVERNANCE_CANISTER_ID,
+            &mut archive_client,
+        )
+        .await;
+
+    assert!(response.error_message.is_some(), "{:?}", response);
+    assert!(archive_client.requests.is_empty());
+    assert_eq!(swap.buyers.len(), 3);
+    assert_eq!(swap.neuron_recipes.len(), 3);
+    assert!(!swap.is_finalize_swap_locked());
+}
+
+/// Opens a round of the swap with the proposal `proposal_id`.
+async fn open_round(swap: &mut Swap, params: &Params, proposal_id: u64) -> Result<(), String> {
+    let account = Account {
+        owner: SWAP_CANISTER_ID.get().into(),
+        subaccount: None,
+    };
+    swap.open(
+        SWAP_CANISTER_ID,
+        &mock_stub(vec![LedgerExpect::AccountBalance(
+            account,
+            Ok(Tokens::from_e8s(params.sns_token_e8s)),
+        )]),
+        START_TIMESTAMP_SECONDS,
+        OpenRequest {
+            params: Some(params.clone()),
+            cf_participants: vec![],
+            open_sns_token_swap_proposal_id: Some(proposal_id),
+        },
+    )
+    .await
+    .map(|_| ())
+}
+
+#[tokio::test]
+async fn test_open_next_round_after_archived_round() {
+    let next_proposal_id = OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID + 1;
+    let params = Params {
+        min_participants: 1,
+        ..params()
+    };
+    let mut swap = Swap {
+        next_sale_neuron_memo: Some(SALE_NEURON_MEMO_RANGE_START + 3),
+        ..create_finalized_swap(2)
+    };
+
+    // The records of the round must be archived first.
+    assert_is_err!(open_round(&mut swap, &params, next_proposal_id).await);
+    swap.archive_finalized_state(
+        END_TIMESTAMP_SECONDS + 20,
+        SNS_GOVERNANCE_CANISTER_ID,
+        &mut SpySwapArchiveClient::default(),
+    )
+    .await;
+
+    // The next round needs a proposal of its own.
+    assert_is_err!(open_round(&mut swap, &params, OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID).await);
+    assert_is_ok!(open_round(&mut swap, &params, next_proposal_id).await);
+    assert_eq!(swap.lifecycle(), Open);
+    assert_eq!(swap.archive, None);
+    assert_eq!(
+        swap.lifecycle_history.last().unwrap().swap_round,
+        Some(next_proposal_id)
+    );
+
+    let rounds = swap.list_swap_rounds(&ListSwapRoundsRequest {}).rounds;
+    assert_eq!(rounds.len(), 2);
+    assert_eq!(
+        rounds[0].open_sns_token_swap_proposal_id,
+        Some(OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID)
+    );
+    assert_eq!(rounds[0].lifecycle(), Committed);
+    assert_eq!(rounds[0].direct_participants, 2);
+    assert_eq!(rounds[0].direct_participant_icp_e8s, 20 * E8);
+    assert_eq!(rounds[0].neuron_recipes, 2);
+    assert_eq!(
+        rounds[1].open_sns_token_swap_proposal_id,
+        Some(next_proposal_id)
+    );
+    assert_eq!(rounds[1].lifecycle(), Open);
+    assert_eq!(rounds[1].direct_participants, 0);
+
+    // The neurons of the round follow the ones of the earlier round.
+    let buyer = PrincipalId::new_user_test_id(1);
+    let amount = params.min_participant_icp_e8s;
+    buy_token(
+        &mut swap,
+        &buyer,
+        &amount,
+        &mock_stub(get_account_balance_mock_ledger(&amount, &buyer)),
+    )
+    .await;
+    assert!(swap.try_commit_or_abort(END_TIMESTAMP_SECONDS));
+    assert_eq!(swap.lifecycle(), Committed);
+    let memos: Vec<u64> = swap
+        .neuron_recipes
+        .iter()
+        .map(|recipe| recipe.neuron_attributes.as_ref().unwrap().memo)
+        .collect();
+    assert_eq!(
+        memos,
+        vec![
+            SALE_NEURON_MEMO_RANGE_START + 3,
+            SALE_NEURON_MEMO_RANGE_START + 4,
+            SALE_NEURON_MEMO_RANGE_START + 5,
+        ]
+    );
+    assert_eq!(
+        swap.next_sale_neuron_memo,
+        Some(SALE_NEURON_MEMO_RANGE_START + 6)
+    );
+}
+
+#[tokio::test]
+async fn test_aborted_later_round_keeps_dapp_controllers() {
+    let mut swap = Swap {
+        lifecycle: Aborted as i32,
+        buyers: BTreeMap::new(),
+        neuron_recipes: vec![],
+        completed_rounds: vec![SwapRound {
+            open_sns_token_swap_proposal_id: Some(OPEN_SNS_TOKEN_SWAP_PROPOSAL_ID - 1),
+            lifecycle: Committed as i32,
+            ..Default::default()
+        }],
+        ..create_generic_committed_swap()
+    };
+    assert!(!swap.should_restore_dapp_control());
+
+    let response = swap
+        .restore_dapp_controllers(
+            &mut ExplodingSnsRootClient,
+            NNS_GOVERNANCE_CANISTER_ID.get(),
+            END_TIMESTAMP_SECONDS,
+        )
+        .await;
+    assert!(extract_canister_call_error(&response)
+        .description
+        .contains("committed"));
+    assert_eq!(swap.lifecycle(), Aborted);
+}
@@ -635,6 +635,10 @@ pub fn open_sale(env: &StateMachine, swap_id: &CanisterId, params: Option<Params
                     dissolve_delay_interval_seconds: 1,
                 }),
                 sale_delay_seconds: None,
+                early_participation_bonus: None,
+                max_direct_participants: None,
+                dutch_auction: None,
+                pro_rata_cutback: None,
             }),
         ),
         cf_participants: vec![],
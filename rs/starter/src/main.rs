@@ -618,7 +618,7 @@ impl ValidatedConfig {
     fn build_replica_config(self: &ValidatedConfig) -> ReplicaConfig {
         let state_manager = Some(StateManagerConfig::new(self.state_manager_root.clone()));
         let http_handler = Some(HttpHandlerConfig {
-            listen_addr: self.http_listen_addr,
+            listeners: vec![self.http_listen_addr.into()],
             port_file_path: self.http_port_file.clone(),
             ..Default::default()
         });
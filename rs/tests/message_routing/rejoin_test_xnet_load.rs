@@ -0,0 +1,15 @@
+#[rustfmt::skip]
+
+use anyhow::Result;
+
+use ic_tests::driver::group::SystemTestGroup;
+use ic_tests::message_routing::rejoin_test_xnet_load;
+use ic_tests::systest;
+
+fn main() -> Result<()> {
+    SystemTestGroup::new()
+        .with_setup(rejoin_test_xnet_load::config)
+        .add_test(systest!(rejoin_test_xnet_load::test))
+        .execute_from_args()?;
+    Ok(())
+}
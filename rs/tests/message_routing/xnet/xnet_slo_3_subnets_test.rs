@@ -11,12 +11,18 @@ const SUBNETS: usize = 3;
 const NODES_PER_SUBNET: usize = 4;
 const RUNTIME: Duration = Duration::from_secs(600);
 const REQUEST_RATE: usize = 10;
+/// The path to the KPIs of an earlier run, which the KPIs of this run are
+/// compared to.
+const KPI_BASELINE_ENV_VAR: &str = "XNET_SLO_KPI_BASELINE";
 
 const PER_TASK_TIMEOUT: Duration = Duration::from_secs(15 * 60);
 const OVERALL_TIMEOUT: Duration = Duration::from_secs(25 * 60);
 
 fn main() -> Result<()> {
-    let config = Config::new(SUBNETS, NODES_PER_SUBNET, RUNTIME, REQUEST_RATE);
+    let mut config = Config::new(SUBNETS, NODES_PER_SUBNET, RUNTIME, REQUEST_RATE);
+    if let Ok(path) = std::env::var(KPI_BASELINE_ENV_VAR) {
+        config = config.with_kpi_baseline(path.into());
+    }
     let test = config.clone().test();
     SystemTestGroup::new()
         .with_setup(config.build())
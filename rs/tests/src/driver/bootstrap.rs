@@ -11,6 +11,7 @@ use crate::driver::test_env_api::{
     HasDependencies, HasIcDependencies, HasTopologySnapshot, IcNodeContainer, NodesInfo,
 };
 use ic_base_types::NodeId;
+use ic_config::ConfigOverride;
 use ic_prep_lib::{
     internet_computer::{IcConfig, InitializedIc, TopologyConfig},
     node::{InitializedNode, NodeConfiguration, NodeIndex},
@@ -38,6 +39,7 @@ const CONF_IMG_FNAME: &str = "config_disk.img";
 const BITCOIND_ADDR_PATH: &str = "bitcoind_addr";
 const SOCKS_PROXY_PATH: &str = "socks_proxy";
 const ONCHAIN_OBSERVABILITY_PATH: &str = "onchain_observability_overrides";
+const REPLICA_CONFIG_OVERRIDES_FNAME: &str = "replica_config_overrides.env";
 
 fn mk_compressed_img_path() -> std::string::String {
     format!("{}.gz", CONF_IMG_FNAME)
@@ -216,8 +218,16 @@ pub fn setup_and_start_vms(
         let ic_name = ic.name();
         let malicious_behaviour = ic.get_malicious_behavior_of_node(node.node_id);
         nodes_info.insert(node.node_id, malicious_behaviour.clone());
+        let replica_config_overrides = ic.get_replica_config_overrides_of_node(node.node_id);
         join_handles.push(thread::spawn(move || {
-            create_config_disk_image(&ic_name, &node, malicious_behaviour, &t_env, &group_name)?;
+            create_config_disk_image(
+                &ic_name,
+                &node,
+                malicious_behaviour,
+                &replica_config_overrides,
+                &t_env,
+                &group_name,
+            )?;
             let image_id = upload_config_disk_image(&node, &t_farm)?;
             // delete uncompressed file
             let conf_img_path = PathBuf::from(&node.node_path).join(CONF_IMG_FNAME);
@@ -259,6 +269,7 @@ pub fn create_config_disk_image(
     ic_name: &str,
     node: &InitializedNode,
     malicious_behavior: Option<MaliciousBehaviour>,
+    replica_config_overrides: &[ConfigOverride],
     test_env: &TestEnv,
     group_name: &str,
 ) -> anyhow::Result<()> {
@@ -300,6 +311,23 @@ pub fn create_config_disk_image(
             .arg(serde_json::to_string(&malicious_behavior)?);
     }
 
+    if !replica_config_overrides.is_empty() {
+        info!(
+            test_env.logger(),
+            "Node with id={} has replica config overrides={:?}",
+            node.node_id,
+            replica_config_overrides
+        );
+        // The overrides are passed to the replica as environment variables.
+        let overrides_path = PathBuf::from(&node.node_path).join(REPLICA_CONFIG_OVERRIDES_FNAME);
+        let overrides: String = replica_config_overrides
+            .iter()
+            .map(|o| format!("{}={}\n", o.env_var_name(), o.value))
+            .collect();
+        std::fs::write(&overrides_path, overrides)?;
+        cmd.arg("--replica_config_overrides").arg(overrides_path);
+    }
+
     let ssh_authorized_pub_keys_dir: PathBuf = test_env.get_path(SSH_AUTHORIZED_PUB_KEYS_DIR);
     if ssh_authorized_pub_keys_dir.exists() {
         cmd.arg("--accounts_ssh_authorized_keys")
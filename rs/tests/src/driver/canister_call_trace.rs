@@ -0,0 +1,285 @@
+//! Tracing of the canister calls of a test, such that flaky canister
+//! interactions can be debugged after the fact.
+//!
+//! Calls made through a [CanisterCallTracer] instead of directly through
+//! [Canister::update_] or [Canister::query_] are appended, one JSON object per
+//! line, to the [CANISTER_CALL_TRACE_FILE] of the test environment if the
+//! [CANISTER_CALL_TRACE_ENV_VAR] environment variable is set:
+//!
+//! ```text
+//! {"canister_id":"rwlgt-iiaaa-aaaaa-aaaaa-cai","kind":"update","method_name":"start",
+//!  "args_sha256":"5f1c…","args_bytes":120,"started_at":"2023-03-01T12:00:00.123Z",
+//!  "duration_ms":2031,"result":{"reply":{"reply_bytes":8}}}
+//! ```
+//!
+//! Otherwise, the calls are made without being recorded.
+use crate::driver::test_env::TestEnv;
+use anyhow::{Context, Result};
+use canister_test::Canister;
+use chrono::{DateTime, Utc};
+use ic_crypto_sha::Sha256;
+use on_wire::{FromWire, IntoWire, NewType};
+use serde::{Deserialize, Serialize};
+use slog::{warn, Logger};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Enables the tracing of canister calls if set (to any value).
+pub const CANISTER_CALL_TRACE_ENV_VAR: &str = "TRACE_CANISTER_CALLS";
+
+/// The file of the test environment the canister calls are appended to.
+pub const CANISTER_CALL_TRACE_FILE: &str = "canister_calls.jsonl";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CallKind {
+    Update,
+    Query,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CallResult {
+    Reply { reply_bytes: usize },
+    Reject { message: String },
+}
+
+/// A single call to a canister.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CanisterCall {
+    pub canister_id: String,
+    pub kind: CallKind,
+    pub method_name: String,
+    /// The hex-encoded SHA-256 of the encoded arguments, to tell apart calls
+    /// of the same method.
+    pub args_sha256: String,
+    pub args_bytes: usize,
+    pub started_at: DateTime<Utc>,
+    pub duration_ms: u64,
+    pub result: CallResult,
+}
+
+/// Makes canister calls and records them, if tracing is enabled. Clones
+/// append to the same trace file.
+#[derive(Clone)]
+pub struct CanisterCallTracer {
+    trace_file: Option<Arc<Mutex<File>>>,
+    log: Logger,
+}
+
+impl CanisterCallTracer {
+    /// A tracer recording to the trace file of `env` if
+    /// [CANISTER_CALL_TRACE_ENV_VAR] is set. Calls are made without being
+    /// recorded if the trace file cannot be opened.
+    pub fn new(env: &TestEnv) -> Self {
+        let log = env.logger();
+        if std::env::var_os(CANISTER_CALL_TRACE_ENV_VAR).is_none() {
+            return Self {
+                trace_file: None,
+                log,
+            };
+        }
+        let path = env.get_path(CANISTER_CALL_TRACE_FILE);
+        let trace_file = match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => Some(Arc::new(Mutex::new(file))),
+            Err(err) => {
+                warn!(
+                    log,
+                    "Not tracing canister calls, could not open {:?}: {}", path, err
+                );
+                None
+            }
+        };
+        Self { trace_file, log }
+    }
+
+    /// Like [Canister::update_], recording the call.
+    pub async fn update_<S, Input, ReturnType, Witness>(
+        &self,
+        canister: &Canister<'_>,
+        method_name: S,
+        _: Witness,
+        input: Input::Inner,
+    ) -> Result<ReturnType::Inner, String>
+    where
+        S: Into<String>,
+        Input: IntoWire + NewType,
+        Witness: FnOnce(ReturnType, Input::Inner) -> (ReturnType::Inner, Input),
+        ReturnType: FromWire + NewType,
+    {
+        let method_name = method_name.into();
+        let args = Input::from_inner(input).into_bytes()?;
+        let call = self.start(CallKind::Update, canister, &method_name, &args);
+        let result = canister.update(method_name).bytes(args).await;
+        self.finish(call, &result);
+        FromWire::from_bytes(result?).map(|r: ReturnType| r.into_inner())
+    }
+
+    /// Like [Canister::query_], recording the call.
+    pub async fn query_<S, Input, ReturnType, Witness>(
+        &self,
+        canister: &Canister<'_>,
+        method_name: S,
+        _: Witness,
+        input: Input::Inner,
+    ) -> Result<ReturnType::Inner, String>
+    where
+        S: Into<String>,
+        Input: IntoWire + NewType,
+        Witness: FnOnce(ReturnType, Input::Inner) -> (ReturnType::Inner, Input),
+        ReturnType: FromWire + NewType,
+    {
+        let method_name = method_name.into();
+        let args = Input::from_inner(input).into_bytes()?;
+        let call = self.start(CallKind::Query, canister, &method_name, &args);
+        let result = canister.query(method_name).bytes(args).await;
+        self.finish(call, &result);
+        FromWire::from_bytes(result?).map(|r: ReturnType| r.into_inner())
+    }
+
+    /// The call in progress, if tracing is enabled.
+    fn start(
+        &self,
+        kind: CallKind,
+        canister: &Canister<'_>,
+        method_name: &str,
+        args: &[u8],
+    ) -> Option<(CanisterCall, Instant)> {
+        if self.trace_file.is_none() {
+            return None;
+        }
+        let call = CanisterCall {
+            canister_id: canister.canister_id().to_string(),
+            kind,
+            method_name: method_name.to_string(),
+            args_sha256: hex::encode(Sha256::hash(args)),
+            args_bytes: args.len(),
+            started_at: Utc::now(),
+            duration_ms: 0,
+            result: CallResult::Reply { reply_bytes: 0 },
+        };
+        Some((call, Instant::now()))
+    }
+
+    fn finish(&self, call: Option<(CanisterCall, Instant)>, result: &Result<Vec<u8>, String>) {
+        if let Some((mut call, start)) = call {
+            call.duration_ms = start.elapsed().as_millis() as u64;
+            call.result = match result {
+                Ok(reply) => CallResult::Reply {
+                    reply_bytes: reply.len(),
+                },
+                Err(message) => CallResult::Reject {
+                    message: message.clone(),
+                },
+            };
+            self.record(&call);
+        }
+    }
+
+    fn record(&self, call: &CanisterCall) {
+        let trace_file = match &self.trace_file {
+            Some(trace_file) => trace_file,
+            None => return,
+        };
+        let mut line = serde_json::to_string(call).expect("Failed to serialize a canister call");
+        line.push('\n');
+        // A single write per line, such that the lines of concurrent calls do
+        // not interleave.
+        if let Err(err) = trace_file.lock().unwrap().write_all(line.as_bytes()) {
+            warn!(
+                self.log,
+                "Failed to trace the canister call {:?}: {}", call, err
+            );
+        }
+    }
+}
+
+pub trait HasCanisterCallTracer {
+    /// A tracer recording the canister calls of the running test, if
+    /// [CANISTER_CALL_TRACE_ENV_VAR] is set.
+    fn canister_call_tracer(&self) -> CanisterCallTracer;
+}
+
+impl HasCanisterCallTracer for TestEnv {
+    fn canister_call_tracer(&self) -> CanisterCallTracer {
+        CanisterCallTracer::new(self)
+    }
+}
+
+/// Reads the canister calls traced to the file at `path`, e.g. the
+/// [CANISTER_CALL_TRACE_FILE] of a test environment, in the order they
+/// completed.
+pub fn read_canister_calls<P: AsRef<Path>>(path: P) -> Result<Vec<CanisterCall>> {
+    let path = path.as_ref();
+    let file = File::open(path).with_context(|| format!("Could not open {:?}", path))?;
+    BufReader::new(file)
+        .lines()
+        .map(|line| {
+            let line = line.with_context(|| format!("Could not read {:?}", path))?;
+            serde_json::from_str(&line)
+                .with_context(|| format!("{:?}: Invalid canister call: {}", path, line))
+        })
+        .collect()
+}
+
+/// Returns the calls of `calls` that took at least `min_duration`, slowest
+/// first, which are the usual suspects of timeouts.
+pub fn slowest_canister_calls(
+    calls: &[CanisterCall],
+    min_duration: Duration,
+) -> Vec<&CanisterCall> {
+    let mut slow: Vec<&CanisterCall> = calls
+        .iter()
+        .filter(|call| call.duration_ms >= min_duration.as_millis() as u64)
+        .collect();
+    slow.sort_by(|a, b| b.duration_ms.cmp(&a.duration_ms));
+    slow
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::o;
+
+    fn call(method_name: &str, duration_ms: u64) -> CanisterCall {
+        CanisterCall {
+            canister_id: "rwlgt-iiaaa-aaaaa-aaaaa-cai".to_string(),
+            kind: CallKind::Update,
+            method_name: method_name.to_string(),
+            args_sha256: hex::encode(Sha256::hash(b"args")),
+            args_bytes: 4,
+            started_at: Utc::now(),
+            duration_ms,
+            result: CallResult::Reject {
+                message: "Canister rejected with message: out of cycles".to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn recorded_calls_can_be_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CANISTER_CALL_TRACE_FILE);
+        let tracer = CanisterCallTracer {
+            trace_file: Some(Arc::new(Mutex::new(File::create(&path).unwrap()))),
+            log: Logger::root(slog::Discard, o!()),
+        };
+        let calls = vec![call("start", 2000), call("stop", 10), call("metrics", 500)];
+        for call in &calls {
+            tracer.clone().record(call);
+        }
+
+        let read = read_canister_calls(&path).unwrap();
+        assert_eq!(read, calls);
+        assert_eq!(
+            slowest_canister_calls(&read, Duration::from_millis(500))
+                .iter()
+                .map(|call| call.method_name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["start", "metrics"]
+        );
+    }
+}
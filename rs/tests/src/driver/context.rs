@@ -2,6 +2,7 @@
 
 use crate::driver::test_env::TestEnv;
 use anyhow::{bail, Context, Result};
+use regex::Regex;
 use slog::Logger;
 use std::{
     fs,
@@ -13,11 +14,38 @@ use crate::driver::{constants, event::TaskId, subprocess_ipc::LogSender};
 
 use slog::debug;
 
+/// Selects the test functions of a group to execute. The others are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct TestFilter {
+    /// Only test functions whose names contain this substring are selected.
+    pub substring: Option<String>,
+    /// Only test functions whose names match this regex are selected.
+    pub include: Option<Regex>,
+    /// Test functions whose names match this regex are not selected.
+    pub exclude: Option<Regex>,
+}
+
+impl TestFilter {
+    pub fn is_selected(&self, test_name: &str) -> bool {
+        self.substring
+            .as_ref()
+            .map_or(true, |substring| test_name.contains(substring.as_str()))
+            && self
+                .include
+                .as_ref()
+                .map_or(true, |include| include.is_match(test_name))
+            && !self
+                .exclude
+                .as_ref()
+                .map_or(false, |exclude| exclude.is_match(test_name))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct GroupContext {
     pub exec_path: PathBuf,
     pub group_dir: PathBuf,
-    pub filter_tests: Option<String>,
+    pub filter_tests: TestFilter,
     logger: Logger,
     pub sock_id: u64,
     pub debug_keepalive: bool,
@@ -32,7 +60,7 @@ impl GroupContext {
     pub fn new(
         group_dir: PathBuf,
         subproc_info: Option<(TaskId, u64)>,
-        filter_tests: Option<String>,
+        filter_tests: TestFilter,
         debug_keepalive: bool,
     ) -> Result<Self> {
         let task_id = subproc_info.as_ref().map(|t| t.0.clone());
@@ -214,3 +242,28 @@ impl ProcessContext {
         self.group_context.logger()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_selects_matching_tests() {
+        let filter = TestFilter {
+            substring: None,
+            include: Some(Regex::new("^xnet_").unwrap()),
+            exclude: Some(Regex::new("_slow$").unwrap()),
+        };
+        assert!(filter.is_selected("xnet_small"));
+        assert!(!filter.is_selected("xnet_big_slow"));
+        assert!(!filter.is_selected("state_sync"));
+
+        let filter = TestFilter {
+            substring: Some("xnet".to_string()),
+            ..Default::default()
+        };
+        assert!(filter.is_selected("small_xnet"));
+        assert!(!filter.is_selected("state_sync"));
+        assert!(TestFilter::default().is_selected("state_sync"));
+    }
+}
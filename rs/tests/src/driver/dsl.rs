@@ -5,19 +5,29 @@ use std::{
     collections::BTreeMap,
     panic::UnwindSafe,
     path::{Path},
+    sync::Arc,
 };
 
 use crate::driver::{
-    pot_dsl::{PotSetupFn, SysTestFn},
+    pot_dsl::{PotSetupFn, RepeatableSysTestFn, SysTestFn},
     test_env::TestEnv,
 };
 use slog::Logger;
 
+/// Creates a [TestFunction] named after the given test function. Test
+/// functions that can be called more than once, e.g., plain `fn`s, are
+/// repeatable, such that the test can be retried. A closure that can only be
+/// called once and is defined in the calling function does not compile here,
+/// as its kind is not yet known; use [TestFunction::new] for it instead.
 #[macro_export]
 macro_rules! systest {
-    ($a:path) => {
-        ic_tests::driver::dsl::TestFunction::new(std::stringify!($a), $a)
-    };
+    ($a:path) => {{
+        #[allow(unused_imports)]
+        use ic_tests::driver::dsl::{IntoOnceTestFunction, IntoRepeatableTestFunction};
+        // Method resolution picks the repeatable test function, which takes
+        // the tag by value, before falling back to the one taking `&mut`.
+        ic_tests::driver::dsl::TestFunctionTag(Some($a)).into_test_function(std::stringify!($a))
+    }};
 }
 
 pub struct SystemTestGroup {
@@ -28,6 +38,7 @@ pub struct SystemTestGroup {
 pub struct TestFunction {
     name: String,
     f: Box<dyn SysTestFn>,
+    repeatable_f: Option<Arc<dyn RepeatableSysTestFn>>,
 }
 
 impl TestFunction {
@@ -35,6 +46,17 @@ impl TestFunction {
         Self {
             name: name.to_string(),
             f: Box::new(f),
+            repeatable_f: None,
+        }
+    }
+
+    /// Like `new`, but for functions that can be called more than once, such
+    /// as plain `fn`s. Only such test functions can be retried.
+    pub fn new_repeatable<F: RepeatableSysTestFn + Clone>(name: &str, f: F) -> Self {
+        Self {
+            name: name.to_string(),
+            f: Box::new(f.clone()),
+            repeatable_f: Some(Arc::new(f)),
         }
     }
 
@@ -45,6 +67,38 @@ impl TestFunction {
     pub fn f(self) -> Box<dyn SysTestFn> {
         self.f
     }
+
+    /// The test function, if it can be called more than once.
+    pub fn repeatable_f(&self) -> Option<Arc<dyn RepeatableSysTestFn>> {
+        self.repeatable_f.clone()
+    }
+}
+
+/// Holds a test function in `systest!` to select, via autoref
+/// specialization, whether it becomes a repeatable [TestFunction].
+#[doc(hidden)]
+pub struct TestFunctionTag<F>(pub Option<F>);
+
+#[doc(hidden)]
+pub trait IntoRepeatableTestFunction {
+    fn into_test_function(self, name: &str) -> TestFunction;
+}
+
+impl<F: RepeatableSysTestFn + Clone> IntoRepeatableTestFunction for TestFunctionTag<F> {
+    fn into_test_function(self, name: &str) -> TestFunction {
+        TestFunction::new_repeatable(name, self.0.expect("test function already taken"))
+    }
+}
+
+#[doc(hidden)]
+pub trait IntoOnceTestFunction {
+    fn into_test_function(self, name: &str) -> TestFunction;
+}
+
+impl<F: SysTestFn> IntoOnceTestFunction for &mut TestFunctionTag<F> {
+    fn into_test_function(self, name: &str) -> TestFunction {
+        TestFunction::new(name, self.0.take().expect("test function already taken"))
+    }
 }
 
 pub trait SubprocessFn: FnOnce() + UnwindSafe + Send + Sync + 'static {}
@@ -0,0 +1,198 @@
+//! Collection of logs and metrics from all nodes of the Internet Computer
+//! under test when a test function fails, such that tests don't need to
+//! implement their own collection.
+//!
+//! The artifacts are written gzip-compressed to the [FAILURE_ARTIFACTS_DIR]
+//! directory of the test environment of the failed test:
+//!
+//! ```text
+//! failure_artifacts/<node_id>/replica.log.gz
+//! failure_artifacts/<node_id>/orchestrator.log.gz
+//! failure_artifacts/<node_id>/replica_metrics.prom.gz
+//! failure_artifacts/<node_id>/orchestrator_metrics.prom.gz
+//! failure_artifacts/<node_id>/node_exporter_metrics.prom.gz
+//! ```
+//!
+//! Every artifact is truncated to its last [MAX_ARTIFACT_SIZE_BYTES] bytes and
+//! collection stops once [MAX_TOTAL_SIZE_BYTES] (uncompressed) have been
+//! collected.
+use crate::driver::test_env::{HasIcPrepDir, TestEnv};
+use crate::driver::test_env_api::{
+    HasTopologySnapshot, HasVmName, IcNodeContainer, IcNodeSnapshot, SshSession,
+};
+use anyhow::{bail, Result};
+use flate2::{write::GzEncoder, Compression};
+use slog::{info, warn, Logger};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+pub const FAILURE_ARTIFACTS_DIR: &str = "failure_artifacts";
+
+/// Maximum size of a single artifact (before compression).
+pub const MAX_ARTIFACT_SIZE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Maximum size of all artifacts of a test (before compression).
+pub const MAX_TOTAL_SIZE_BYTES: u64 = 1024 * 1024 * 1024;
+
+const METRICS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The logs to collect from each node: the file name of the artifact and the
+/// process whose journal entries it contains. The orchestrator and the replica
+/// both run in the `ic-replica` unit.
+const LOGS: &[(&str, &str)] = &[
+    ("replica.log.gz", "replica"),
+    ("orchestrator.log.gz", "orchestrator"),
+];
+
+/// The metrics endpoints to snapshot on each node.
+const METRICS: &[(&str, u16)] = &[
+    ("replica_metrics.prom.gz", 9090),
+    ("orchestrator_metrics.prom.gz", 9091),
+    ("node_exporter_metrics.prom.gz", 9100),
+];
+
+/// Runs `test_fn` in `env`. If it panics and `collect` is set, collects the
+/// failure artifacts before propagating the panic.
+pub(crate) fn run_collecting_failure_artifacts(
+    env: TestEnv,
+    test_fn: impl FnOnce(TestEnv),
+    collect: bool,
+) {
+    if !collect {
+        return test_fn(env);
+    }
+    let test_env = env.clone();
+    if let Err(panic) = std::panic::catch_unwind(AssertUnwindSafe(move || test_fn(test_env))) {
+        collect_failure_artifacts(&env);
+        std::panic::resume_unwind(panic);
+    }
+}
+
+/// Collects logs and metrics from all nodes of the (unnamed) Internet
+/// Computer in `env`, in parallel. Does nothing if no Internet Computer has
+/// been set up. Failures are logged rather than returned, as collection is
+/// best-effort.
+pub fn collect_failure_artifacts(env: &TestEnv) {
+    let log = env.logger();
+    if env.prep_dir("").is_none() {
+        info!(
+            log,
+            "No Internet Computer set up, skipping the collection of failure artifacts."
+        );
+        return;
+    }
+    // Reading the topology panics if the registry is not available.
+    let nodes = match std::panic::catch_unwind(AssertUnwindSafe(|| {
+        let topology = env.topology_snapshot();
+        topology
+            .subnets()
+            .flat_map(|subnet| subnet.nodes())
+            .chain(topology.unassigned_nodes())
+            .collect::<Vec<_>>()
+    })) {
+        Ok(nodes) => nodes,
+        Err(_) => {
+            warn!(
+                log,
+                "Could not read the topology, no failure artifacts collected."
+            );
+            return;
+        }
+    };
+
+    let dir = env.get_path(FAILURE_ARTIFACTS_DIR);
+    info!(
+        log,
+        "Collecting failure artifacts of {} nodes to {:?} ...",
+        nodes.len(),
+        dir
+    );
+    let collector = Collector {
+        log: log.clone(),
+        total_bytes: AtomicU64::new(0),
+    };
+    std::thread::scope(|s| {
+        for node in nodes {
+            let node_dir = dir.join(node.vm_name());
+            let collector = &collector;
+            s.spawn(move || collector.collect_node(&node, &node_dir));
+        }
+    });
+    info!(
+        log,
+        "Collected {} bytes of failure artifacts.",
+        collector.total_bytes.load(Ordering::Relaxed)
+    );
+}
+
+struct Collector {
+    log: Logger,
+    /// The number of bytes collected so far, over all nodes.
+    total_bytes: AtomicU64,
+}
+
+impl Collector {
+    fn collect_node(&self, node: &IcNodeSnapshot, dir: &Path) {
+        if let Err(e) = fs::create_dir_all(dir) {
+            warn!(self.log, "Could not create {:?}: {:?}", dir, e);
+            return;
+        }
+        for (file_name, process) in LOGS {
+            let path = dir.join(file_name);
+            if let Err(e) = self.collect_log(node, process, &path) {
+                warn!(self.log, "Could not collect {:?}: {:?}", path, e);
+            }
+        }
+        for (file_name, port) in METRICS {
+            let path = dir.join(file_name);
+            if let Err(e) = self.collect_metrics(node, *port, &path) {
+                warn!(self.log, "Could not collect {:?}: {:?}", path, e);
+            }
+        }
+    }
+
+    fn collect_log(&self, node: &IcNodeSnapshot, process: &str, path: &Path) -> Result<()> {
+        // A single attempt, as the node might be the reason of the failure.
+        let session = node.get_ssh_session()?;
+        let mut channel = session.channel_session()?;
+        channel.exec(&format!(
+            "sudo journalctl -u ic-replica.service _COMM={} --no-pager -o short-precise \
+                | tail -c {}",
+            process, MAX_ARTIFACT_SIZE_BYTES
+        ))?;
+        self.write_compressed(&mut channel, path)?;
+        channel.wait_close()?;
+        Ok(())
+    }
+
+    fn collect_metrics(&self, node: &IcNodeSnapshot, port: u16, path: &Path) -> Result<()> {
+        let url = format!("http://[{}]:{}/metrics", node.get_ip_addr(), port);
+        let client = reqwest::blocking::Client::builder()
+            .timeout(METRICS_TIMEOUT)
+            .build()?;
+        let mut response = client.get(url).send()?.error_for_status()?;
+        self.write_compressed(&mut response, path)
+    }
+
+    /// Writes at most `MAX_ARTIFACT_SIZE_BYTES` of `reader` to `path`,
+    /// compressed, unless the total limit has been reached.
+    fn write_compressed(&self, reader: &mut impl Read, path: &Path) -> Result<()> {
+        let remaining =
+            MAX_TOTAL_SIZE_BYTES.saturating_sub(self.total_bytes.load(Ordering::Relaxed));
+        if remaining == 0 {
+            bail!("The total size limit of {MAX_TOTAL_SIZE_BYTES} bytes has been reached");
+        }
+        let mut encoder = GzEncoder::new(File::create(path)?, Compression::default());
+        let bytes = io::copy(
+            &mut reader.take(remaining.min(MAX_ARTIFACT_SIZE_BYTES)),
+            &mut encoder,
+        )?;
+        encoder.finish()?;
+        self.total_bytes.fetch_add(bytes, Ordering::Relaxed);
+        Ok(())
+    }
+}
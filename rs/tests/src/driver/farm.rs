@@ -18,7 +18,7 @@ use std::io::Write;
 use thiserror::Error;
 use url::Url;
 
-use crate::driver::{ic::ImageSizeGiB, test_env::TestEnv};
+use crate::driver::{ic::ImageSizeGiB, resource::ResourceRequirements, test_env::TestEnv};
 
 pub type FarmResult<T> = Result<T, FarmError>;
 
@@ -247,6 +247,19 @@ impl Farm {
         Ok(())
     }
 
+    /// Returns the resources currently available for new groups.
+    pub fn capacity(&self) -> FarmResult<ResourceRequirements> {
+        let rb = self.get("capacity");
+        let resp = self.retry_until_success(rb)?;
+        let capacity = resp.json::<ResourceRequirements>()?;
+        Ok(capacity)
+    }
+
+    fn get(&self, path: &str) -> RequestBuilder {
+        let url = self.url_from_path(path);
+        self.client.get(url)
+    }
+
     fn post(&self, path: &str) -> RequestBuilder {
         let url = self.url_from_path(path);
         self.client.post(url)
@@ -4,12 +4,16 @@
 use std::path::PathBuf;
 
 use crate::driver::{
+    failure_artifacts,
     farm::Farm,
+    namespace::TestNamespace,
+    resource::ResourceRequirements,
+    retry::{self, Retries},
     task_scheduler::TaskScheduler,
     test_env_api::{FarmBaseUrl, HasGroupSetup},
     {
         action_graph::ActionGraph,
-        context::{GroupContext, ProcessContext},
+        context::{GroupContext, ProcessContext, TestFilter},
         dsl::{SubprocessFn, TestFunction},
         event::TaskId,
         plan::{EvalOrder, Plan},
@@ -20,7 +24,8 @@ use crate::driver::{
 };
 use crate::driver::{
     pot_dsl::{PotSetupFn, SysTestFn},
-    test_env::{TestEnv, TestEnvAttribute},
+    prometheus_vm::{HasPrometheus, PrometheusVm},
+    test_env::{TestEnv, TestEnvAttribute, TestSeed},
     test_env_api::HasIcDependencies,
     test_setup::GroupSetup,
 };
@@ -28,6 +33,7 @@ use serde::{Deserialize, Serialize};
 
 use anyhow::{bail, Result};
 use clap::Parser;
+use regex::Regex;
 use tokio::runtime::{Builder, Handle, Runtime};
 
 use crate::driver::{
@@ -38,7 +44,7 @@ use crate::driver::{
 };
 use std::{collections::BTreeMap, iter::once, time::Duration};
 
-use slog::{debug, info, trace, Logger};
+use slog::{debug, info, trace, warn, Logger};
 
 use super::report::{SystemGroupSummary, SystemTestGroupError};
 
@@ -73,11 +79,37 @@ pub struct CliArgs {
     )]
     pub filter_tests: Option<String>,
 
+    #[clap(
+        long = "include",
+        value_name = "REGEX",
+        help = r#"Execute only those test functions, whose names match the regex, and skip all the others."#
+    )]
+    pub include: Option<Regex>,
+
+    #[clap(
+        long = "exclude",
+        value_name = "REGEX",
+        help = r#"Skip those test functions, whose names match the regex."#
+    )]
+    pub exclude: Option<Regex>,
+
+    #[clap(
+        long = "list",
+        help = r#"Print the names of the test functions that would be executed, one per line, and exit without executing them."#
+    )]
+    pub list: bool,
+
     #[clap(
         long = "farm-base-url",
         help = r#"Use a custom url for the Farm webservice."#
     )]
     pub farm_base_url: Option<url::Url>,
+
+    #[clap(
+        long = "seed",
+        help = r#"Seed the random number generators of the tests (see TestEnv::rng) with this value. If not set, a random seed is chosen and reported."#
+    )]
+    pub seed: Option<u64>,
 }
 
 impl CliArgs {
@@ -86,6 +118,14 @@ impl CliArgs {
         Ok(self)
     }
 
+    fn test_filter(&self) -> TestFilter {
+        TestFilter {
+            substring: self.filter_tests.clone(),
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+        }
+    }
+
     /// A convenience method to get the task id of this subprocess, *if* it is in fact a
     /// subprocess.
     fn subproc_id(&self) -> Option<(TaskId, u64)> {
@@ -143,6 +183,10 @@ pub struct ComposeContext<'a> {
     empty_task_counter: u64,
     logger: Logger,
     timeout_per_test: Duration,
+    /// The resources available to tests running in parallel, if limited.
+    test_budget: Option<ResourceRequirements>,
+    /// Whether to collect logs and metrics from all nodes when a test fails.
+    collect_failure_artifacts: bool,
 }
 
 fn subproc(
@@ -247,6 +291,11 @@ pub enum SystemTestSubGroup {
     Singleton {
         task_fn: Box<dyn SysTestFn>,
         task_id: TaskId,
+        requirements: ResourceRequirements,
+        retries: Option<Retries>,
+        /// Set if the test is isolated, see
+        /// [SystemTestGroup::add_isolated_parallel].
+        namespace: Option<TestNamespace>,
     },
 }
 
@@ -265,11 +314,50 @@ impl SystemTestSubGroup {
     }
 
     pub fn add_test(self, test: TestFunction) -> Self {
+        self.add_test_with_requirements(test, Default::default())
+    }
+
+    /// Adds a test that needs the given resources in addition to those of
+    /// the setup.
+    pub fn add_test_with_requirements(
+        self,
+        test: TestFunction,
+        requirements: ResourceRequirements,
+    ) -> Self {
         let task_is = TaskId::Test(String::from(test.name()));
-        let singleton = Self::Singleton {
+        self.add_singleton(Self::Singleton {
             task_fn: test.f(),
             task_id: task_is,
-        };
+            requirements,
+            retries: None,
+            namespace: None,
+        })
+    }
+
+    /// Adds a test that is retried up to `max_retries` times after it
+    /// failed, see [SystemTestGroup::add_test_with_retries].
+    pub fn add_test_with_retries(self, test: TestFunction, max_retries: u32) -> Self {
+        self.add_singleton(Self::singleton_with_retries(test, max_retries))
+    }
+
+    fn singleton_with_retries(test: TestFunction, max_retries: u32) -> Self {
+        let repeatable_f = test.repeatable_f().unwrap_or_else(|| {
+            panic!(
+                "Test {} cannot be retried as it cannot be called more than once, \
+                 e.g., because it is a closure that moves out of its captures.",
+                test.name()
+            )
+        });
+        Self::Singleton {
+            task_id: TaskId::Test(String::from(test.name())),
+            task_fn: test.f(),
+            requirements: Default::default(),
+            retries: Some(Retries::new(max_retries, repeatable_f)),
+            namespace: None,
+        }
+    }
+
+    fn add_singleton(self, singleton: Self) -> Self {
         match self {
             Self::Multiple { tasks, .. } if tasks.is_empty() => {
                 // This case is only to support the builder pattern
@@ -288,8 +376,132 @@ impl SystemTestSubGroup {
         }
     }
 
+    /// The resources needed at any point in time while running this sub
+    /// group, assuming that parallel tests are not limited by a budget.
+    fn peak_requirements(&self) -> ResourceRequirements {
+        match self {
+            SystemTestSubGroup::Multiple {
+                tasks,
+                ordering: EvalOrder::Parallel,
+            } => Self::parallel_requirements(tasks),
+            SystemTestSubGroup::Multiple {
+                tasks,
+                ordering: EvalOrder::Sequential,
+            } => tasks
+                .iter()
+                .fold(ResourceRequirements::default(), |acc, t| {
+                    acc.max(&t.peak_requirements())
+                }),
+            SystemTestSubGroup::Singleton { requirements, .. } => *requirements,
+        }
+    }
+
+    /// The resources needed to run all `tasks` at the same time.
+    fn parallel_requirements(tasks: &[SystemTestSubGroup]) -> ResourceRequirements {
+        tasks
+            .iter()
+            .fold(ResourceRequirements::default(), |acc, t| {
+                acc.plus(&t.peak_requirements())
+            })
+    }
+
+    /// The tests of this sub group with their requirements.
+    fn test_requirements(&self) -> Vec<(&TaskId, &ResourceRequirements)> {
+        match self {
+            SystemTestSubGroup::Multiple { tasks, .. } => {
+                tasks.iter().flat_map(|t| t.test_requirements()).collect()
+            }
+            SystemTestSubGroup::Singleton {
+                task_id,
+                requirements,
+                ..
+            } => vec![(task_id, requirements)],
+        }
+    }
+
+    /// Gives each test of this sub group its own namespace, numbering them
+    /// from `next_index` on, out of `count` isolated tests.
+    fn isolate(self, next_index: &mut usize, count: usize) -> Self {
+        match self {
+            SystemTestSubGroup::Multiple { tasks, ordering } => SystemTestSubGroup::Multiple {
+                tasks: tasks
+                    .into_iter()
+                    .map(|t| t.isolate(next_index, count))
+                    .collect(),
+                ordering,
+            },
+            SystemTestSubGroup::Singleton {
+                task_fn,
+                task_id,
+                requirements,
+                retries,
+                ..
+            } => {
+                let namespace = TestNamespace {
+                    name: task_id.name(),
+                    index: *next_index,
+                    count,
+                };
+                *next_index += 1;
+                SystemTestSubGroup::Singleton {
+                    task_fn,
+                    task_id,
+                    requirements,
+                    retries,
+                    namespace: Some(namespace),
+                }
+            }
+        }
+    }
+
+    /// Splits parallel `tasks` into consecutive batches whose aggregate
+    /// requirements fit into `budget`, keeping the order of the tasks.
+    fn into_batches(
+        tasks: Vec<SystemTestSubGroup>,
+        budget: &ResourceRequirements,
+    ) -> Vec<Vec<SystemTestSubGroup>> {
+        let mut batches: Vec<Vec<SystemTestSubGroup>> = vec![];
+        let mut batch_requirements = ResourceRequirements::default();
+        for task in tasks {
+            let requirements = task.peak_requirements();
+            let with_task = batch_requirements.plus(&requirements);
+            match batches.last_mut() {
+                Some(batch) if with_task.fits_within(budget) => {
+                    batch.push(task);
+                    batch_requirements = with_task;
+                }
+                _ => {
+                    batches.push(vec![task]);
+                    batch_requirements = requirements;
+                }
+            }
+        }
+        batches
+    }
+
     pub fn into_plan(self, ctx: &mut ComposeContext) -> Plan<Box<dyn Task>> {
         match self {
+            SystemTestSubGroup::Multiple {
+                tasks,
+                ordering: EvalOrder::Parallel,
+            } if ctx.test_budget.map_or(false, |budget| {
+                !Self::parallel_requirements(&tasks).fits_within(&budget)
+            }) =>
+            {
+                // Run the parallel tasks in batches that fit into the budget.
+                let budget = ctx.test_budget.unwrap();
+                let batches = Self::into_batches(tasks, &budget)
+                    .into_iter()
+                    .map(|batch| {
+                        let children = batch
+                            .into_iter()
+                            .map(|sub_group| sub_group.into_plan(ctx))
+                            .collect();
+                        compose(None, EvalOrder::Parallel, children, ctx)
+                    })
+                    .collect();
+                compose(None, EvalOrder::Sequential, batches, ctx)
+            }
             SystemTestSubGroup::Multiple { tasks, ordering } => compose(
                 None,
                 ordering,
@@ -299,38 +511,76 @@ impl SystemTestSubGroup {
                     .collect(),
                 ctx,
             ),
-            // If filtering flag `--include-tests` is set, then for all
-            // skipped test function we execute a SkipTestTask
-            SystemTestSubGroup::Singleton { task_fn, task_id } => {
+            // If any of the filtering flags `--include-tests`, `--include`
+            // or `--exclude` is set, then for all skipped test functions we
+            // execute a SkipTestTask
+            SystemTestSubGroup::Singleton {
+                task_fn,
+                task_id,
+                retries,
+                namespace,
+                ..
+            } => {
                 let logger = ctx.logger.clone();
                 let group_ctx = ctx.group_ctx.clone();
-                if let Some(ref filter) = group_ctx.filter_tests {
-                    if let TaskId::Test(ref name) = task_id {
-                        if !name.contains(filter) {
-                            return Plan::Leaf {
-                                task: Box::from(SkipTestTask::new(task_id.clone())),
-                            };
-                        }
+                if let TaskId::Test(ref name) = task_id {
+                    if !group_ctx.filter_tests.is_selected(name) {
+                        return Plan::Leaf {
+                            task: Box::from(SkipTestTask::new(task_id.clone())),
+                        };
                     }
                 }
+                // Each attempt of a retried test is bounded by the timeout per
+                // test, see `Retries::run`. The timeout of the subprocess is
+                // only a backstop, and leaves time for the teardown hooks.
+                let timeout_per_test = ctx.timeout_per_test;
+                let timeout = timeout_per_test
+                    .saturating_mul(retries.as_ref().map_or(1, |r| r.max_attempts() + 1));
                 let closure = {
                     let task_id = task_id.clone();
                     let group_ctx = ctx.group_ctx.clone();
+                    let collect_failure_artifacts = ctx.collect_failure_artifacts;
                     move || {
                         debug!(logger, ">>> test_fn({})", &task_id);
-                        let env = get_or_create_env(group_ctx, task_id).unwrap();
-                        // This function will only be called after setup finishes
-                        if SetupResult::try_read_attribute(&env).is_err() {
-                            panic!("Failed to find SetupResult attribute after setup. Cancelling test function.");
+                        let test_env = |task_id: &TaskId| {
+                            let env =
+                                get_or_create_env(group_ctx.clone(), task_id.clone()).unwrap();
+                            // This function will only be called after setup finishes
+                            if SetupResult::try_read_attribute(&env).is_err() {
+                                panic!("Failed to find SetupResult attribute after setup. Cancelling test function.");
+                            }
+                            if let Some(namespace) = &namespace {
+                                info!(
+                                    env.logger(),
+                                    "Running in namespace {} ({} of {} isolated tests)",
+                                    namespace.name,
+                                    namespace.index + 1,
+                                    namespace.count
+                                );
+                                namespace.write_attribute(&env);
+                            }
+                            env
+                        };
+                        match retries {
+                            Some(retries) => retries.run(
+                                &task_id,
+                                test_env,
+                                collect_failure_artifacts,
+                                timeout_per_test,
+                            ),
+                            None => failure_artifacts::run_collecting_failure_artifacts(
+                                test_env(&task_id),
+                                task_fn,
+                                collect_failure_artifacts,
+                            ),
                         }
-                        task_fn(env)
                     }
                 };
                 timed(
                     Plan::Leaf {
                         task: Box::from(subproc(task_id, closure, ctx)),
                     },
-                    ctx.timeout_per_test,
+                    timeout,
                     None,
                     ctx,
                 )
@@ -345,6 +595,10 @@ pub struct SystemTestGroup {
     timeout_per_test: Option<Duration>,
     overall_timeout: Option<Duration>,
     with_farm: bool,
+    setup_requirements: ResourceRequirements,
+    resource_budget: Option<ResourceRequirements>,
+    collect_failure_artifacts: bool,
+    with_monitoring: bool,
 }
 
 impl Default for SystemTestGroup {
@@ -370,6 +624,10 @@ impl SystemTestGroup {
             timeout_per_test: None,
             overall_timeout: None,
             with_farm: true,
+            setup_requirements: Default::default(),
+            resource_budget: None,
+            collect_failure_artifacts: true,
+            with_monitoring: false,
         }
     }
 
@@ -386,6 +644,23 @@ impl SystemTestGroup {
         self
     }
 
+    /// Disables collecting logs and metrics from all nodes when a test fails,
+    /// e.g. for tests that collect their own.
+    pub fn without_failure_artifacts(mut self) -> Self {
+        self.collect_failure_artifacts = false;
+        self
+    }
+
+    /// Deploys a Prometheus VM, which also serves Grafana, before the setup
+    /// and configures it to scrape all nodes deployed by the setup, so that
+    /// the tests can make assertions on metrics via [HasPrometheus]. The
+    /// setup must not start a [PrometheusVm] of its own, and the test target
+    /// must depend on the Grafana dashboards.
+    pub fn with_monitoring(mut self) -> Self {
+        self.with_monitoring = true;
+        self
+    }
+
     pub fn with_overall_timeout(mut self, overall_timeout: Duration) -> Self {
         self.overall_timeout = Some(overall_timeout);
         self
@@ -396,15 +671,132 @@ impl SystemTestGroup {
         self
     }
 
-    pub fn add_test(mut self, test: TestFunction) -> Self {
+    /// Declares the resources needed by the setup, which stay allocated
+    /// while the tests run.
+    pub fn with_setup_requirements(mut self, requirements: ResourceRequirements) -> Self {
+        self.setup_requirements = requirements;
+        self
+    }
+
+    /// Limits the resources used by the group at any point in time. Parallel
+    /// tests that together exceed the budget are run in consecutive batches.
+    pub fn with_resource_budget(mut self, budget: ResourceRequirements) -> Self {
+        self.resource_budget = Some(budget);
+        self
+    }
+
+    pub fn add_test(self, test: TestFunction) -> Self {
+        self.add_test_with_requirements(test, Default::default())
+    }
+
+    /// Adds a test that needs the given resources in addition to those of
+    /// the setup.
+    pub fn add_test_with_requirements(
+        mut self,
+        test: TestFunction,
+        requirements: ResourceRequirements,
+    ) -> Self {
         let task_id = TaskId::Test(String::from(test.name()));
         self.tests.push(SystemTestSubGroup::Singleton {
             task_fn: test.f(),
             task_id,
+            requirements,
+            retries: None,
+            namespace: None,
         });
         self
     }
 
+    /// Adds a test that is known to be flaky, e.g., because of the
+    /// infrastructure, and is retried up to `max_retries` times after it
+    /// failed. Each attempt runs in a fresh test environment, after the
+    /// teardown hooks registered by the failed attempt ran (see
+    /// [retry::register_teardown]), and is bounded by the timeout per test.
+    /// Failed attempts are listed in the report, even if the test eventually
+    /// passed.
+    ///
+    /// Panics if `test` cannot be called more than once. `systest!` makes
+    /// every test function that implements `Fn` and `Clone`, e.g., a plain
+    /// `fn`, repeatable.
+    pub fn add_test_with_retries(mut self, test: TestFunction, max_retries: u32) -> Self {
+        self.tests.push(SystemTestSubGroup::singleton_with_retries(
+            test,
+            max_retries,
+        ));
+        self
+    }
+
+    /// The names of the test functions of this group, in the order in which
+    /// they were added.
+    fn test_names(&self) -> Vec<String> {
+        self.tests
+            .iter()
+            .flat_map(|sub_group| sub_group.test_requirements())
+            .map(|(task_id, _)| task_id)
+            .filter(|task_id| is_task_visible_to_user(task_id))
+            .map(|task_id| task_id.name())
+            .collect()
+    }
+
+    /// The resources available to the tests, if the group has a budget.
+    fn test_budget(&self) -> Option<ResourceRequirements> {
+        self.resource_budget
+            .map(|budget| budget.saturating_sub(&self.setup_requirements))
+    }
+
+    /// The resources needed by the group at any point in time, taking the
+    /// budget into account.
+    fn peak_requirements(&self) -> ResourceRequirements {
+        let tests = self
+            .tests
+            .iter()
+            .fold(ResourceRequirements::default(), |acc, t| {
+                acc.max(&t.peak_requirements())
+            });
+        let peak = self.setup_requirements.plus(&tests);
+        match self.resource_budget {
+            Some(budget) if !peak.fits_within(&budget) => budget,
+            _ => peak,
+        }
+    }
+
+    /// Checks that every test fits into the budget of the group, and that the
+    /// group fits into `capacity`, if known.
+    fn check_resources(&self, capacity: Option<ResourceRequirements>) -> Result<()> {
+        if let Some(test_budget) = self.test_budget() {
+            for (task_id, requirements) in self.tests.iter().flat_map(|t| t.test_requirements()) {
+                let excess = requirements.excess_over(&test_budget);
+                if !excess.is_empty() {
+                    bail!(SystemTestGroupError::PreconditionViolation {
+                        condition: "Each test must fit into the resource budget of the group \
+                            (minus the resources of the setup)"
+                            .to_string(),
+                        counterexample: format!("test {}: {}", task_id, excess.join(", ")),
+                    })
+                }
+            }
+        }
+        if let Some(capacity) = capacity {
+            let excess = self.peak_requirements().excess_over(&capacity);
+            if !excess.is_empty() {
+                bail!(SystemTestGroupError::PreconditionViolation {
+                    condition: "The resources required by the group must be available on Farm"
+                        .to_string(),
+                    counterexample: excess.join(", "),
+                })
+            }
+        }
+        Ok(())
+    }
+
+    fn has_resource_requirements(&self) -> bool {
+        self.setup_requirements != ResourceRequirements::default()
+            || self
+                .tests
+                .iter()
+                .any(|t| t.peak_requirements() != ResourceRequirements::default())
+    }
+
     fn add_group(mut self, sub_group: SystemTestSubGroup, ordering: EvalOrder) -> Self {
         self.tests.push(match sub_group {
             SystemTestSubGroup::Multiple { tasks, .. } => {
@@ -423,6 +815,19 @@ impl SystemTestGroup {
         self.add_group(sub_group, EvalOrder::Parallel)
     }
 
+    /// Adds independent tests that run in parallel against the Internet
+    /// Computer deployed by the setup, each isolated in its own
+    /// [TestNamespace], i.e., on its own share of the application subnets
+    /// (see [crate::driver::namespace::HasTestNamespace]). The setup must
+    /// deploy at least as many application subnets as `sub_group` has
+    /// tests. Like with [Self::add_parallel], the tests are run in batches
+    /// if they don't fit into the resource budget of the group together.
+    pub fn add_isolated_parallel(self, sub_group: SystemTestSubGroup) -> Self {
+        let count = sub_group.test_requirements().len();
+        let sub_group = sub_group.isolate(&mut 0, count);
+        self.add_group(sub_group, EvalOrder::Parallel)
+    }
+
     /// Add a subgroup with the specified minumal lifetime.
     ///
     /// Useful in experiments involving human interactions.
@@ -497,6 +902,8 @@ impl SystemTestGroup {
             empty_task_counter: 0,
             logger: group_ctx.logger().clone(),
             timeout_per_test: self.effective_timeout_per_test(),
+            test_budget: self.test_budget(),
+            collect_failure_artifacts: self.with_farm && self.collect_failure_artifacts,
         };
 
         // The ID of the root task is needed outside this function for awaiting when the plan execution finishes.
@@ -552,6 +959,7 @@ impl SystemTestGroup {
         let setup_plan = {
             let logger = group_ctx.logger().clone();
             let group_ctx = group_ctx.clone();
+            let with_monitoring = self.with_monitoring;
             let setup_fn = self
                 .setup
                 .unwrap_or_else(|| panic!("setup function not specified for SystemTestGroup."));
@@ -560,7 +968,15 @@ impl SystemTestGroup {
                 move || {
                     debug!(logger, ">>> setup_fn");
                     let env = get_setup_env(group_ctx);
+                    if with_monitoring {
+                        PrometheusVm::default()
+                            .start(&env)
+                            .expect("failed to start prometheus VM");
+                    }
                     setup_fn(env.clone());
+                    if with_monitoring {
+                        env.sync_prometheus_config_with_topology();
+                    }
                     SetupResult {}.write_attribute(&env);
                 },
                 &mut compose_ctx,
@@ -661,15 +1077,56 @@ impl SystemTestGroup {
         let args = CliArgs::parse().validate()?;
         let is_parent_process = matches!(args.action, SystemTestsSubcommand::Run);
 
+        let filter = args.test_filter();
+        if args.list {
+            for test_name in self.test_names() {
+                if filter.is_selected(&test_name) {
+                    println!("{}", test_name);
+                }
+            }
+            return Ok(Outcome::TestsListed);
+        }
+
         let group_ctx = GroupContext::new(
             args.group_dir.path.clone(),
             args.subproc_id(),
-            args.filter_tests,
+            filter,
             args.debug_keepalive,
         )?;
         if is_parent_process {
             let root_env = group_ctx.get_root_env().unwrap();
             FarmBaseUrl::new_or_default(args.farm_base_url).write_attribute(&root_env);
+            let seed = TestSeed::new_or_random(args.seed);
+            seed.write_attribute(&root_env);
+            info!(group_ctx.log(), "Random seed of the tests: {}", seed.0);
+            // Fail fast, before any resources are allocated.
+            let capacity = if self.with_farm && self.has_resource_requirements() {
+                let farm = Farm::new(root_env.get_farm_url()?, group_ctx.logger());
+                match farm.capacity() {
+                    Ok(capacity) => {
+                        info!(group_ctx.log(), "Farm capacity: {:?}", capacity);
+                        Some(capacity)
+                    }
+                    Err(e) => {
+                        warn!(
+                            group_ctx.log(),
+                            "Could not get Farm capacity, skipping the capacity check: {:?}", e
+                        );
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            if self.with_monitoring && !self.with_farm {
+                bail!(SystemTestGroupError::PreconditionViolation {
+                    condition: "Monitoring can only be deployed on Farm".to_string(),
+                    counterexample: "the group is configured with both with_monitoring() and \
+                        without_farm()"
+                        .to_string(),
+                })
+            }
+            self.check_resources(capacity)?;
             if self.with_farm {
                 root_env.create_group_setup();
             }
@@ -763,7 +1220,8 @@ impl SystemTestGroup {
                 //     }
                 // }
 
-                let report = task_scheduler.create_report();
+                let mut report = task_scheduler.create_report();
+                retry::report_attempts(&mut report, &group_ctx);
                 info!(group_ctx.log(), "JSON Report:\n{}", report);
                 info!(group_ctx.log(), "Report:\n{}", report.pretty_print());
 
@@ -773,6 +1231,12 @@ impl SystemTestGroup {
                 if report.failure.is_empty() {
                     Ok(Outcome::FromParentProcess(report))
                 } else {
+                    let root_env = group_ctx.get_root_env().unwrap();
+                    info!(
+                        group_ctx.log(),
+                        "To reproduce the failures, run the group with --seed {}",
+                        TestSeed::read_attribute(&root_env).0
+                    );
                     bail!(SystemTestGroupError::SystemTestFailure(report))
                 }
             }
@@ -794,6 +1258,7 @@ impl SystemTestGroup {
         match outcome {
             Ok(Outcome::FromSubProcess) => Ok(()),
             Ok(Outcome::FromParentProcess(_)) => Ok(()),
+            Ok(Outcome::TestsListed) => Ok(()),
             Err(e) => {
                 // TODO: also print Kibana link in case of failure. This requires that the dyncamic
                 // group name (e.g., distributed_mainnet_test_bin--1673213252002) is made available
@@ -825,3 +1290,105 @@ fn print_report(ctx: &GroupContext, report: &SystemGroupSummary) {
         );
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noop(_env: TestEnv) {}
+
+    fn test_with(name: &str, vms: u64) -> SystemTestSubGroup {
+        SystemTestSubGroup::new().add_test_with_requirements(
+            TestFunction::new(name, noop),
+            ResourceRequirements::default_vms(vms),
+        )
+    }
+
+    fn names(batches: &[Vec<SystemTestSubGroup>]) -> Vec<Vec<String>> {
+        batches
+            .iter()
+            .map(|batch| {
+                batch
+                    .iter()
+                    .flat_map(|t| t.test_requirements())
+                    .map(|(task_id, _)| task_id.name())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn into_batches_of_no_tasks_is_empty() {
+        let batches =
+            SystemTestSubGroup::into_batches(vec![], &ResourceRequirements::default_vms(4));
+        assert!(batches.is_empty());
+    }
+
+    #[test]
+    fn into_batches_fills_a_batch_up_to_an_exact_fit() {
+        let tasks = vec![
+            test_with("a", 1),
+            test_with("b", 3),
+            test_with("c", 2),
+            test_with("d", 2),
+        ];
+        let batches =
+            SystemTestSubGroup::into_batches(tasks, &ResourceRequirements::default_vms(4));
+        assert_eq!(names(&batches), vec![vec!["a", "b"], vec!["c", "d"]]);
+    }
+
+    #[test]
+    fn into_batches_puts_an_oversize_task_into_a_batch_of_its_own() {
+        let tasks = vec![test_with("a", 1), test_with("b", 5), test_with("c", 1)];
+        let batches =
+            SystemTestSubGroup::into_batches(tasks, &ResourceRequirements::default_vms(4));
+        assert_eq!(names(&batches), vec![vec!["a"], vec!["b"], vec!["c"]]);
+    }
+
+    #[test]
+    fn check_resources_accepts_tests_that_exactly_fit_the_budget() {
+        let group = SystemTestGroup::new()
+            .with_setup_requirements(ResourceRequirements::default_vms(2))
+            .with_resource_budget(ResourceRequirements::default_vms(6))
+            .add_test_with_requirements(
+                TestFunction::new("a", noop),
+                ResourceRequirements::default_vms(4),
+            );
+        assert!(group
+            .check_resources(Some(ResourceRequirements::default_vms(6)))
+            .is_ok());
+    }
+
+    #[test]
+    fn check_resources_rejects_a_test_exceeding_the_budget() {
+        let group = SystemTestGroup::new()
+            .with_setup_requirements(ResourceRequirements::default_vms(2))
+            .with_resource_budget(ResourceRequirements::default_vms(6))
+            .add_test_with_requirements(
+                TestFunction::new("a", noop),
+                ResourceRequirements::default_vms(5),
+            );
+        let err = group.check_resources(None).unwrap_err().to_string();
+        assert!(err.contains("test a"), "{}", err);
+    }
+
+    #[test]
+    fn check_resources_rejects_a_group_exceeding_the_capacity() {
+        let group = SystemTestGroup::new()
+            .with_setup_requirements(ResourceRequirements::default_vms(2))
+            .add_test_with_requirements(
+                TestFunction::new("a", noop),
+                ResourceRequirements::default_vms(3),
+            );
+        assert!(group
+            .check_resources(Some(ResourceRequirements::default_vms(4)))
+            .is_err());
+    }
+
+    #[test]
+    fn check_resources_accepts_an_empty_group() {
+        assert!(SystemTestGroup::new()
+            .check_resources(Some(ResourceRequirements::default()))
+            .is_ok());
+    }
+}
@@ -8,6 +8,7 @@ use crate::driver::{
     test_setup::GroupSetup,
 };
 use anyhow::Result;
+use ic_config::ConfigOverride;
 use ic_prep_lib::node::NodeSecretKeyStore;
 use ic_prep_lib::prep_state_directory::IcPrepStateDir;
 use ic_protobuf::registry::subnet::v1::GossipConfig;
@@ -281,6 +282,20 @@ impl InternetComputer {
         has_malicious_nodes || has_malicious_unassigned_nodes
     }
 
+    /// The replica config overrides of the subnet `node_id` is initially
+    /// assigned to, or none for unassigned nodes.
+    pub fn get_replica_config_overrides_of_node(&self, node_id: NodeId) -> Vec<ConfigOverride> {
+        self.subnets
+            .iter()
+            .find(|s| {
+                s.nodes
+                    .iter()
+                    .any(|n| n.secret_key_store.as_ref().unwrap().node_id == node_id)
+            })
+            .map(|s| s.replica_config_overrides.clone())
+            .unwrap_or_default()
+    }
+
     pub fn get_malicious_behavior_of_node(&self, node_id: NodeId) -> Option<MaliciousBehaviour> {
         let node_filter_map = |n: &Node| {
             if n.secret_key_store.as_ref().unwrap().node_id == node_id {
@@ -331,6 +346,10 @@ pub struct Subnet {
     pub max_number_of_canisters: Option<u64>,
     pub ssh_readonly_access: Vec<String>,
     pub ssh_backup_access: Vec<String>,
+    /// Overrides of the replica config of all nodes of the subnet, applied on
+    /// top of the config generated on the node, see
+    /// [Subnet::with_replica_config_override].
+    pub replica_config_overrides: Vec<ConfigOverride>,
 }
 
 impl Subnet {
@@ -357,6 +376,7 @@ impl Subnet {
             subnet_type,
             ssh_readonly_access: vec![],
             ssh_backup_access: vec![],
+            replica_config_overrides: vec![],
         }
     }
 
@@ -492,6 +512,27 @@ impl Subnet {
         self
     }
 
+    /// Overrides the value at `path` of the replica config of all nodes of
+    /// the subnet, e.g., `http_handler.request_timeout_seconds`. The value is
+    /// parsed like the field in the config file, so that a test can run
+    /// against a tuned configuration without a custom image.
+    ///
+    /// # Panics
+    ///
+    /// If `path` is not of the form `<section>.<field>`.
+    pub fn with_replica_config_override<V: ToString>(mut self, path: &str, value: V) -> Self {
+        let config_override: ConfigOverride = format!("{}={}", path, value.to_string())
+            .parse()
+            .unwrap_or_else(|e| panic!("invalid replica config override: {}", e));
+        assert!(
+            config_override.path.len() > 1,
+            "replica config override of a whole section: {}",
+            path
+        );
+        self.replica_config_overrides.push(config_override);
+        self
+    }
+
     pub fn add_malicious_nodes(
         mut self,
         no_of_nodes: usize,
@@ -539,6 +580,7 @@ impl Default for Subnet {
             max_number_of_canisters: None,
             ssh_readonly_access: vec![],
             ssh_backup_access: vec![],
+            replica_config_overrides: vec![],
         }
     }
 }
@@ -1,16 +1,19 @@
 pub mod action_graph;
 pub mod bootstrap;
 pub mod boundary_node;
+pub mod canister_call_trace;
 pub mod config;
 pub mod constants;
 pub mod context;
 pub mod driver_setup;
 pub mod dsl;
 pub mod event;
+pub mod failure_artifacts;
 pub mod farm;
 pub mod group;
 pub mod ic;
 pub mod logger;
+pub mod namespace;
 pub mod node_software_version;
 pub mod plan;
 pub mod port_allocator;
@@ -19,6 +22,8 @@ pub mod process;
 pub mod prometheus_vm;
 pub mod report;
 pub mod resource;
+pub mod retry;
+pub mod ssh_keys;
 pub mod subprocess_ipc;
 pub mod subprocess_task;
 pub mod task;
@@ -0,0 +1,99 @@
+//! Isolation of tests that run in parallel against the Internet Computer
+//! deployed by the setup of a group, see
+//! [crate::driver::group::SystemTestGroup::add_isolated_parallel].
+//!
+//! Each isolated test gets a [TestNamespace], which is written to its test
+//! environment before it starts. The namespace entitles the test to a
+//! disjoint share of the application subnets, on which it installs its
+//! canisters, and gives it a name to qualify the names of the resources it
+//! creates, e.g., canister names, files or metric labels. The logs of the
+//! test are tagged with its name by the driver, and its test environment,
+//! including its failure artifacts, is the one named after the test.
+//!
+//! The subnets are shared out when a test asks for them, from the topology
+//! of the setup: the `i`-th of `n` isolated tests gets every `n`-th
+//! application subnet, in the order of the registry, starting with the
+//! `i`-th. Hence, the setup must deploy at least as many application
+//! subnets as there are isolated tests, and the tests must not use the
+//! subnets of the others.
+use crate::driver::{
+    test_env::{TestEnv, TestEnvAttribute},
+    test_env_api::{HasTopologySnapshot, SubnetSnapshot},
+};
+use ic_registry_subnet_type::SubnetType;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestNamespace {
+    /// The name of the test.
+    pub name: String,
+    /// The position of the test among the isolated tests.
+    pub index: usize,
+    /// The number of isolated tests running in parallel.
+    pub count: usize,
+}
+
+impl TestEnvAttribute for TestNamespace {
+    fn attribute_name() -> String {
+        String::from("test_namespace")
+    }
+}
+
+impl TestNamespace {
+    /// The share of `items` of this namespace: every `count`-th item,
+    /// starting with the `index`-th.
+    pub fn share<T>(&self, items: impl IntoIterator<Item = T>) -> Vec<T> {
+        items
+            .into_iter()
+            .skip(self.index)
+            .step_by(self.count)
+            .collect()
+    }
+
+    /// Qualifies `name` with the name of the namespace, such that resources
+    /// created by different tests don't clash.
+    pub fn qualify(&self, name: &str) -> String {
+        format!("{}.{}", self.name, name)
+    }
+}
+
+pub trait HasTestNamespace {
+    /// The namespace of the running test, if it is isolated.
+    fn namespace(&self) -> Option<TestNamespace>;
+
+    /// The application subnets the running test may use: its share if it is
+    /// isolated, all of them otherwise.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the share of an isolated test is empty.
+    fn namespace_subnets(&self) -> Vec<SubnetSnapshot>;
+}
+
+impl HasTestNamespace for TestEnv {
+    fn namespace(&self) -> Option<TestNamespace> {
+        TestNamespace::try_read_attribute(self).ok()
+    }
+
+    fn namespace_subnets(&self) -> Vec<SubnetSnapshot> {
+        let application_subnets = self
+            .topology_snapshot()
+            .subnets()
+            .filter(|subnet| subnet.subnet_type() == SubnetType::Application)
+            .collect::<Vec<_>>();
+        let namespace = match self.namespace() {
+            Some(namespace) => namespace,
+            None => return application_subnets,
+        };
+        let available = application_subnets.len();
+        let share = namespace.share(application_subnets);
+        if share.is_empty() {
+            panic!(
+                "No application subnet for the isolated test {}: the setup deployed {} \
+                 application subnets for {} isolated tests",
+                namespace.name, available, namespace.count
+            );
+        }
+        share
+    }
+}
@@ -1,4 +1,7 @@
-use std::{fmt::Display, panic::UnwindSafe};
+use std::{
+    fmt::Display,
+    panic::{RefUnwindSafe, UnwindSafe},
+};
 
 use crate::driver::test_env::TestEnv;
 use serde::{Deserialize, Serialize};
@@ -9,6 +12,16 @@ impl<T: FnOnce(TestEnv) + UnwindSafe + Send + Sync + 'static> PotSetupFn for T {
 pub trait SysTestFn: FnOnce(TestEnv) + UnwindSafe + Send + Sync + 'static {}
 impl<T: FnOnce(TestEnv) + UnwindSafe + Send + Sync + 'static> SysTestFn for T {}
 
+/// A test function that can be called more than once, e.g., to retry it.
+pub trait RepeatableSysTestFn:
+    Fn(TestEnv) + RefUnwindSafe + UnwindSafe + Send + Sync + 'static
+{
+}
+impl<T: Fn(TestEnv) + RefUnwindSafe + UnwindSafe + Send + Sync + 'static> RepeatableSysTestFn
+    for T
+{
+}
+
 #[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct TestPath(Vec<String>);
 
@@ -5,9 +5,9 @@ use std::{
     time::Duration,
 };
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use reqwest::Url;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use slog::{debug, info};
 
@@ -54,6 +54,10 @@ const REPLICA_METRICS_PORT: u16 = 9090;
 const ORCHESTRATOR_METRICS_PORT: u16 = 9091;
 const NODE_EXPORTER_METRICS_PORT: u16 = 9100;
 
+const PROMETHEUS_WEB_PORT: u16 = 9090;
+const PROMETHEUS_QUERY_TIMEOUT: Duration = Duration::from_secs(30);
+const PROMETHEUS_QUERY_RETRY_BACKOFF: Duration = Duration::from_secs(10);
+
 const PROMETHEUS_DOMAIN_NAME: &str = "prometheus";
 const GRAFANA_DOMAIN_NAME: &str = "grafana";
 
@@ -190,6 +194,54 @@ pub trait HasPrometheus {
     /// This allows this function to be used in a finalizer where no prometheus
     /// server has been setup.
     fn download_prometheus_data_dir_if_exists(&self);
+
+    /// Evaluates the PromQL `query` on the Prometheus VM at the current time.
+    ///
+    /// Fails if the query doesn't evaluate to an instant vector; wrap scalar
+    /// expressions in `vector(...)`.
+    fn query_prometheus(&self, query: &str) -> Result<Vec<PrometheusSample>>;
+
+    /// Evaluates the PromQL `query` until `predicate` holds for its result,
+    /// and fails if it doesn't within `timeout`. Since the nodes are only
+    /// scraped every scrape interval, assertions on metrics should be made
+    /// this way rather than with a single [HasPrometheus::query_prometheus].
+    fn await_prometheus_query<P>(
+        &self,
+        query: &str,
+        timeout: Duration,
+        predicate: P,
+    ) -> Result<Vec<PrometheusSample>>
+    where
+        P: Fn(&[PrometheusSample]) -> bool;
+}
+
+/// An element of the instant vector a PromQL query evaluated to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrometheusSample {
+    pub labels: HashMap<String, String>,
+    pub value: f64,
+}
+
+#[derive(Deserialize)]
+struct PrometheusQueryResponse {
+    status: String,
+    data: Option<PrometheusQueryData>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PrometheusQueryData {
+    #[serde(rename = "resultType")]
+    result_type: String,
+    result: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct PrometheusVectorElement {
+    metric: HashMap<String, String>,
+    /// The evaluation timestamp and the value, which is a string to allow
+    /// for `NaN` and infinities.
+    value: (f64, String),
 }
 
 impl HasPrometheus for TestEnv {
@@ -286,6 +338,70 @@ sudo tar -cf "{tarball_full_path:?}" \
             "Failed to write the tarball of prometheus data directory {vm_name}:{tarball_full_path:?} to {destination:?}",
         );
     }
+
+    fn query_prometheus(&self, query: &str) -> Result<Vec<PrometheusSample>> {
+        let vm_name = PROMETHEUS_VM_NAME.to_string();
+        let prometheus_vm = self.get_deployed_universal_vm(&vm_name)?.get_vm()?;
+        let url = format!(
+            "http://[{}]:{PROMETHEUS_WEB_PORT}/api/v1/query",
+            prometheus_vm.ipv6
+        );
+        let client = reqwest::blocking::Client::builder()
+            .timeout(PROMETHEUS_QUERY_TIMEOUT)
+            .build()?;
+        let body = client.get(url).query(&[("query", query)]).send()?.text()?;
+        let response: PrometheusQueryResponse = serde_json::from_str(&body)?;
+        if response.status != "success" {
+            bail!(
+                "Query {query:?} failed: {}",
+                response.error.unwrap_or(response.status)
+            );
+        }
+        let data = match response.data {
+            Some(data) => data,
+            None => bail!("Query {query:?} returned no data"),
+        };
+        if data.result_type != "vector" {
+            bail!(
+                "Query {query:?} evaluated to a {} instead of an instant vector",
+                data.result_type
+            );
+        }
+        let elements: Vec<PrometheusVectorElement> = serde_json::from_value(data.result)?;
+        elements
+            .into_iter()
+            .map(|element| {
+                let value = element.value.1.parse::<f64>()?;
+                Ok(PrometheusSample {
+                    labels: element.metric,
+                    value,
+                })
+            })
+            .collect()
+    }
+
+    fn await_prometheus_query<P>(
+        &self,
+        query: &str,
+        timeout: Duration,
+        predicate: P,
+    ) -> Result<Vec<PrometheusSample>>
+    where
+        P: Fn(&[PrometheusSample]) -> bool,
+    {
+        retry(
+            self.logger(),
+            timeout,
+            PROMETHEUS_QUERY_RETRY_BACKOFF,
+            || {
+                let samples = self.query_prometheus(query)?;
+                if !predicate(&samples) {
+                    bail!("Query {query:?} returned unexpected samples: {samples:?}");
+                }
+                Ok(samples)
+            },
+        )
+    }
 }
 
 #[derive(Serialize)]
@@ -77,6 +77,7 @@ pub fn create_report(
         success,
         failure,
         skipped,
+        retried: vec![],
     }
 }
 
@@ -85,6 +86,9 @@ pub struct SystemGroupSummary {
     pub success: Vec<TaskReport>,
     pub failure: Vec<TaskReport>,
     pub skipped: Vec<TaskReport>,
+    /// The failed attempts of retried tests, see [crate::driver::retry].
+    #[serde(default)]
+    pub retried: Vec<TaskReport>,
 }
 
 impl Display for SystemGroupSummary {
@@ -106,6 +110,9 @@ impl SystemGroupSummary {
         for res in self.skipped.iter() {
             out_lines.append(&mut res.pretty_print(max_name_len, "SKIPPED"));
         }
+        for res in self.retried.iter() {
+            out_lines.append(&mut res.pretty_print(max_name_len, "RETRIED"));
+        }
         let mx_len = out_lines.iter().max_by_key(|x| x.len()).unwrap().len();
         let mx_len = std::cmp::min(mx_len, 200);
         let start = format!("{:=^mx_len$}", " Summary ");
@@ -122,6 +129,7 @@ impl SystemGroupSummary {
             .iter()
             .chain(self.failure.iter())
             .chain(self.skipped.iter())
+            .chain(self.retried.iter())
     }
 
     fn max_name_len(&self) -> usize {
@@ -246,4 +254,6 @@ impl std::convert::From<anyhow::Error> for SystemTestGroupError {
 pub enum Outcome {
     FromParentProcess(SystemGroupSummary),
     FromSubProcess,
+    /// The test functions were listed instead of executed.
+    TestsListed,
 }
@@ -122,6 +122,102 @@ impl ResourceGroup {
     }
 }
 
+/// The resources a test (or the setup of a group) declares to need, used to
+/// check a group against the capacity of Farm before setup, and to limit the
+/// tests that run in parallel to the budget of the group.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ResourceRequirements {
+    pub vms: u64,
+    #[serde(rename = "vCPUs")]
+    pub vcpus: u64,
+    #[serde(rename = "memoryKiB")]
+    pub memory_kibibytes: u64,
+    #[serde(rename = "diskGiB")]
+    pub disk_gibibytes: u64,
+}
+
+impl ResourceRequirements {
+    pub fn new(vms: u64, vcpus: u64, memory_kibibytes: u64, disk_gibibytes: u64) -> Self {
+        Self {
+            vms,
+            vcpus,
+            memory_kibibytes,
+            disk_gibibytes,
+        }
+    }
+
+    /// The requirements of `vms` VMs with the default number of vCPUs and
+    /// amount of memory.
+    pub fn default_vms(vms: u64) -> Self {
+        Self::new(
+            vms,
+            vms * DEFAULT_VCPUS_PER_VM.get(),
+            vms * DEFAULT_MEMORY_KIB_PER_VM.get(),
+            0,
+        )
+    }
+
+    /// The requirements of both `self` and `other`, e.g. when running at the
+    /// same time.
+    pub fn plus(&self, other: &Self) -> Self {
+        Self {
+            vms: self.vms + other.vms,
+            vcpus: self.vcpus + other.vcpus,
+            memory_kibibytes: self.memory_kibibytes + other.memory_kibibytes,
+            disk_gibibytes: self.disk_gibibytes + other.disk_gibibytes,
+        }
+    }
+
+    /// The requirements of either `self` or `other`, e.g. when running one
+    /// after the other.
+    pub fn max(&self, other: &Self) -> Self {
+        Self {
+            vms: self.vms.max(other.vms),
+            vcpus: self.vcpus.max(other.vcpus),
+            memory_kibibytes: self.memory_kibibytes.max(other.memory_kibibytes),
+            disk_gibibytes: self.disk_gibibytes.max(other.disk_gibibytes),
+        }
+    }
+
+    /// What remains of `self` after `other` is taken out of it.
+    pub fn saturating_sub(&self, other: &Self) -> Self {
+        Self {
+            vms: self.vms.saturating_sub(other.vms),
+            vcpus: self.vcpus.saturating_sub(other.vcpus),
+            memory_kibibytes: self.memory_kibibytes.saturating_sub(other.memory_kibibytes),
+            disk_gibibytes: self.disk_gibibytes.saturating_sub(other.disk_gibibytes),
+        }
+    }
+
+    /// Returns a description of each resource in which `self` exceeds
+    /// `capacity`, or an empty vector if `self` fits.
+    pub fn excess_over(&self, capacity: &Self) -> Vec<String> {
+        [
+            ("VMs", self.vms, capacity.vms),
+            ("vCPUs", self.vcpus, capacity.vcpus),
+            (
+                "memory (KiB)",
+                self.memory_kibibytes,
+                capacity.memory_kibibytes,
+            ),
+            ("disk (GiB)", self.disk_gibibytes, capacity.disk_gibibytes),
+        ]
+        .into_iter()
+        .filter(|(_, required, available)| required > available)
+        .map(|(resource, required, available)| {
+            format!(
+                "{} required {} > available {}",
+                resource, required, available
+            )
+        })
+        .collect()
+    }
+
+    pub fn fits_within(&self, capacity: &Self) -> bool {
+        self.excess_over(capacity).is_empty()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub struct AllocatedVm {
     pub name: String,
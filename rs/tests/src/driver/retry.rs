@@ -0,0 +1,280 @@
+//! Bounded retries of tests that are known to be flaky, see
+//! [crate::driver::group::SystemTestGroup::add_test_with_retries].
+//!
+//! Every attempt of a retried test runs in a fresh test environment forked
+//! from the one of the setup: the first attempt uses the environment named
+//! after the test, and attempt `n > 1` the one named `<test>_attempt_<n>`.
+//! Mutations of resources shared with the setup, e.g., canisters installed
+//! on a subnet or chaos rules applied to a node, cannot be undone by the
+//! driver. Tests that make such mutations register a teardown hook via
+//! [register_teardown], which is run before the next attempt.
+//!
+//! Each attempt is recorded in the [TestAttempts] attribute of the test
+//! environment named after the test, and failed attempts are listed in the
+//! report of the group, such that a flaky test that eventually passed is
+//! still visible.
+use crate::driver::{
+    context::GroupContext,
+    event::TaskId,
+    failure_artifacts,
+    pot_dsl::RepeatableSysTestFn,
+    report::{SystemGroupSummary, TaskReport},
+    subprocess_task::{panic_message, panic_to_result},
+    test_env::{TestEnv, TestEnvAttribute},
+};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use slog::{info, warn};
+use std::{
+    panic::AssertUnwindSafe,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+type TeardownHook = Box<dyn FnOnce(&TestEnv) + Send>;
+
+lazy_static! {
+    // A test runs in its own process, so the hooks of the running attempt
+    // can be kept in a global.
+    static ref TEARDOWN_HOOKS: Mutex<Vec<(String, TeardownHook)>> = Mutex::new(vec![]);
+}
+
+/// Registers `hook` to undo a mutation of resources shared with the setup made
+/// by the running attempt of a test. If the attempt fails and the test is
+/// retried, the hooks are run in reverse order of registration, before the
+/// next attempt. Otherwise, they are dropped without being run, such that the
+/// resources can be inspected after the last failed attempt.
+pub fn register_teardown<F: FnOnce(&TestEnv) + Send + 'static>(name: &str, hook: F) {
+    TEARDOWN_HOOKS
+        .lock()
+        .unwrap()
+        .push((name.to_string(), Box::new(hook)));
+}
+
+fn take_teardown_hooks() -> Vec<(String, TeardownHook)> {
+    std::mem::take(&mut *TEARDOWN_HOOKS.lock().unwrap())
+}
+
+/// Runs the teardown hooks of the failed attempt in `env`. A failing hook is
+/// logged, and does not prevent the other hooks from running.
+fn teardown(env: &TestEnv) {
+    for (name, hook) in take_teardown_hooks().into_iter().rev() {
+        info!(env.logger(), "Running teardown hook {}", name);
+        if let Err(e) = panic_to_result(std::panic::catch_unwind(AssertUnwindSafe(|| hook(env)))) {
+            warn!(env.logger(), "Teardown hook {} failed: {}", name, e);
+        }
+    }
+}
+
+/// The name of the test environment of attempt `attempt` (counting from 1) of
+/// the test `task_id`.
+pub(crate) fn attempt_task_id(task_id: &TaskId, attempt: u32) -> TaskId {
+    if attempt == 1 {
+        return task_id.clone();
+    }
+    TaskId::Test(format!("{}_attempt_{}", task_id.name(), attempt))
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TestAttempt {
+    /// The name of the test environment of the attempt.
+    pub env_name: String,
+    pub runtime: f64,
+    /// The panic message if the attempt failed.
+    pub failure: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TestAttempts {
+    pub max_attempts: u32,
+    pub attempts: Vec<TestAttempt>,
+}
+
+impl TestEnvAttribute for TestAttempts {
+    fn attribute_name() -> String {
+        String::from("test_attempts")
+    }
+}
+
+/// A test that is retried up to `max_retries` times after it failed.
+pub struct Retries {
+    max_retries: u32,
+    test_fn: Arc<dyn RepeatableSysTestFn>,
+}
+
+impl Retries {
+    pub(crate) fn new(max_retries: u32, test_fn: Arc<dyn RepeatableSysTestFn>) -> Self {
+        Self {
+            max_retries,
+            test_fn,
+        }
+    }
+
+    pub(crate) fn max_attempts(&self) -> u32 {
+        self.max_retries.saturating_add(1)
+    }
+
+    /// Runs the attempts of the test `task_id` until one passes or all have
+    /// failed, in which case the panic of the last attempt is propagated.
+    /// `attempt_env` returns the (fresh) test environment of an attempt.
+    ///
+    /// An attempt that does not finish within `timeout_per_attempt` fails.
+    /// As a thread cannot be killed, the attempt is abandoned rather than
+    /// stopped, and the teardown hooks it registered so far run before the
+    /// next attempt.
+    pub(crate) fn run(
+        self,
+        task_id: &TaskId,
+        attempt_env: impl Fn(&TaskId) -> TestEnv,
+        collect_failure_artifacts: bool,
+        timeout_per_attempt: Duration,
+    ) {
+        let max_attempts = self.max_attempts();
+        let record_env = attempt_env(task_id);
+        let mut record = TestAttempts {
+            max_attempts,
+            attempts: vec![],
+        };
+        for attempt in 1..=max_attempts {
+            let attempt_id = attempt_task_id(task_id, attempt);
+            let env = if attempt == 1 {
+                record_env.clone()
+            } else {
+                attempt_env(&attempt_id)
+            };
+            info!(
+                env.logger(),
+                "Attempt {} of {} of test {} (test environment {})",
+                attempt,
+                max_attempts,
+                task_id,
+                attempt_id
+            );
+            let started = Instant::now();
+            let result = self.run_attempt(
+                env.clone(),
+                &attempt_id,
+                collect_failure_artifacts,
+                timeout_per_attempt,
+            );
+            record.attempts.push(TestAttempt {
+                env_name: attempt_id.name(),
+                runtime: started.elapsed().as_secs_f64(),
+                failure: result
+                    .as_ref()
+                    .err()
+                    .map(|panic| panic_message(panic.as_ref())),
+            });
+            record.write_attribute(&record_env);
+            match result {
+                Ok(()) => {
+                    take_teardown_hooks();
+                    return;
+                }
+                Err(panic) if attempt == max_attempts => {
+                    take_teardown_hooks();
+                    std::panic::resume_unwind(panic);
+                }
+                Err(_) => {
+                    warn!(
+                        env.logger(),
+                        "Attempt {} of {} of test {} failed, retrying",
+                        attempt,
+                        max_attempts,
+                        task_id
+                    );
+                    teardown(&env);
+                }
+            }
+        }
+    }
+
+    /// Runs a single attempt in its own thread, and waits at most `timeout`
+    /// for it to finish.
+    fn run_attempt(
+        &self,
+        env: TestEnv,
+        attempt_id: &TaskId,
+        collect_failure_artifacts: bool,
+        timeout: Duration,
+    ) -> std::thread::Result<()> {
+        let test_fn = self.test_fn.clone();
+        let (sender, receiver) = mpsc::channel();
+        std::thread::Builder::new()
+            .name(attempt_id.name())
+            .spawn(move || {
+                let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                    failure_artifacts::run_collecting_failure_artifacts(
+                        env,
+                        move |env| test_fn(env),
+                        collect_failure_artifacts,
+                    )
+                }));
+                // The receiver is gone if the attempt timed out.
+                let _ = sender.send(result);
+            })
+            .expect("Failed to spawn the thread of an attempt");
+        match receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => Err(Box::new(format!(
+                "Attempt {} timed out after {:?}",
+                attempt_id, timeout
+            ))),
+            Err(RecvTimeoutError::Disconnected) => Err(Box::new(format!(
+                "Attempt {} ended without a result",
+                attempt_id
+            ))),
+        }
+    }
+}
+
+/// Adds the failed attempts of retried tests to `report`, and notes on which
+/// attempt a retried test passed.
+pub(crate) fn report_attempts(report: &mut SystemGroupSummary, group_ctx: &GroupContext) {
+    let read_attempts = |name: &str| {
+        group_ctx
+            .get_test_env(name)
+            .and_then(|env| TestAttempts::try_read_attribute(&env))
+            .ok()
+    };
+    let mut retried = vec![];
+    for (task_report, passed) in report
+        .success
+        .iter_mut()
+        .map(|r| (r, true))
+        .chain(report.failure.iter_mut().map(|r| (r, false)))
+    {
+        let record = match read_attempts(&task_report.name) {
+            Some(record) if record.attempts.len() > 1 => record,
+            _ => continue,
+        };
+        let attempts = record.attempts.len();
+        for (i, attempt) in record.attempts.iter().enumerate() {
+            // The outcome of the last attempt is the one of the test.
+            if i + 1 == attempts {
+                break;
+            }
+            retried.push(TaskReport {
+                name: format!(
+                    "{} (attempt {}/{})",
+                    task_report.name,
+                    i + 1,
+                    record.max_attempts
+                ),
+                runtime: attempt.runtime,
+                message: attempt.failure.clone(),
+            });
+        }
+        if passed {
+            let passed_on = format!("Passed on attempt {} of {}.", attempts, record.max_attempts);
+            task_report.message = Some(match task_report.message.take() {
+                Some(message) => format!("{} {}", message, passed_on),
+                None => passed_on,
+            });
+        }
+    }
+    report.retried.extend(retried);
+}
@@ -0,0 +1,216 @@
+//! SSH keys generated per test, and their installation on the deployed nodes,
+//! so that tests of e.g. backup and recovery flows can exercise the access of
+//! the `admin`, `backup` and `readonly` accounts, including the rotation and
+//! revocation of keys.
+//!
+//! The keys are stored in the test environment under [SSH_TEST_KEYS_DIR].
+//! They are distinct from the key the driver authenticates with (see
+//! [crate::driver::test_env::SshKeyGen]), which stays authorized for `admin`
+//! whatever keys a test installs.
+//!
+//! The keys are written to the node with `provision-ssh-keys.sh`, the script
+//! the orchestrator applies the SSH access of the registry with. Hence, the
+//! orchestrator replaces the keys of `backup` and `readonly` once the SSH
+//! access of the node in the registry changes.
+use crate::driver::{
+    constants::SSH_USERNAME,
+    driver_setup::SSH_AUTHORIZED_PUB_KEYS_DIR,
+    test_env::TestEnv,
+    test_env_api::{HasTestEnv, IcNodeSnapshot, SshSession},
+};
+use anyhow::{bail, Result};
+use openssh_keys::PublicKey;
+use openssl::rsa::Rsa;
+use pem::{encode, Pem};
+use ssh2::Session;
+use std::fs;
+use std::net::TcpStream;
+
+pub const SSH_TEST_KEYS_DIR: &str = "ssh/test_keys";
+
+const PROVISION_SSH_KEYS: &str = "/opt/ic/bin/provision-ssh-keys.sh";
+
+/// The accounts of a node that can be accessed over SSH.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SshAccount {
+    Admin,
+    Backup,
+    Readonly,
+}
+
+impl SshAccount {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SshAccount::Admin => "admin",
+            SshAccount::Backup => "backup",
+            SshAccount::Readonly => "readonly",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SshKeyPair {
+    pub name: String,
+    /// The private key in PEM format.
+    pub private_key: String,
+    /// The public key in the format of `authorized_keys`.
+    pub public_key: String,
+}
+
+impl SshKeyPair {
+    /// Generates a key pair whose public key has the comment `<name>@ci.ci`.
+    pub fn generate(name: &str) -> Self {
+        // Our keys are Ed25519, and not RSA. Once we figure out a direct way to
+        // encode an Ed25519 private key the SSH way, we might consider
+        // switching to it.
+        let rsa = Rsa::generate(1024).unwrap();
+        let private_key = encode(&Pem {
+            tag: String::from("RSA PRIVATE KEY"),
+            contents: rsa.private_key_to_der().unwrap(),
+        });
+        let mut public_key = PublicKey::from_rsa(rsa.e().to_vec(), rsa.n().to_vec());
+        public_key.set_comment(&format!("{}@ci.ci", name));
+        Self {
+            name: name.to_string(),
+            private_key,
+            public_key: public_key.to_string(),
+        }
+    }
+}
+
+pub trait HasTestSshKeys {
+    /// Generates a key pair named `name` and stores it, replacing the key
+    /// pair of that name generated earlier, if any.
+    fn generate_ssh_key(&self, name: &str) -> Result<SshKeyPair>;
+
+    /// The key pair named `name` generated earlier in the test.
+    fn ssh_key(&self, name: &str) -> Result<SshKeyPair>;
+}
+
+impl HasTestSshKeys for TestEnv {
+    fn generate_ssh_key(&self, name: &str) -> Result<SshKeyPair> {
+        let key = SshKeyPair::generate(name);
+        let dir = self.get_path(SSH_TEST_KEYS_DIR);
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join(name), &key.private_key)?;
+        fs::write(dir.join(name).with_extension("pub"), &key.public_key)?;
+        Ok(key)
+    }
+
+    fn ssh_key(&self, name: &str) -> Result<SshKeyPair> {
+        let dir = self.get_path(SSH_TEST_KEYS_DIR);
+        Ok(SshKeyPair {
+            name: name.to_string(),
+            private_key: fs::read_to_string(dir.join(name))?,
+            public_key: fs::read_to_string(dir.join(name).with_extension("pub"))?,
+        })
+    }
+}
+
+pub trait ManageSshKeys {
+    /// Authorizes exactly `keys` for `account`, revoking the keys installed
+    /// earlier. The key of the driver stays authorized for `admin`.
+    fn install_ssh_keys(&self, account: SshAccount, keys: &[SshKeyPair]) -> Result<()>;
+
+    /// Replaces `old` with `new` among the keys authorized for `account`.
+    fn rotate_ssh_key(&self, account: SshAccount, old: &SshKeyPair, new: &SshKeyPair)
+        -> Result<()>;
+
+    /// Revokes `key` for `account`, leaving its other keys.
+    fn revoke_ssh_key(&self, account: SshAccount, key: &SshKeyPair) -> Result<()>;
+
+    /// The public keys authorized for `account`.
+    fn authorized_ssh_keys(&self, account: SshAccount) -> Result<Vec<String>>;
+
+    /// Returns an SSH session to the node authenticated as `account` with
+    /// `key`.
+    fn ssh_login(&self, account: SshAccount, key: &SshKeyPair) -> Result<Session>;
+}
+
+impl ManageSshKeys for IcNodeSnapshot {
+    fn install_ssh_keys(&self, account: SshAccount, keys: &[SshKeyPair]) -> Result<()> {
+        let mut authorized_keys: Vec<String> =
+            keys.iter().map(|key| key.public_key.clone()).collect();
+        if account == SshAccount::Admin {
+            let driver_key = fs::read_to_string(
+                self.test_env()
+                    .get_path(SSH_AUTHORIZED_PUB_KEYS_DIR)
+                    .join(SSH_USERNAME),
+            )?;
+            authorized_keys.insert(0, driver_key.trim().to_string());
+        }
+        write_authorized_keys(self, account, &authorized_keys)
+    }
+
+    fn rotate_ssh_key(
+        &self,
+        account: SshAccount,
+        old: &SshKeyPair,
+        new: &SshKeyPair,
+    ) -> Result<()> {
+        let mut authorized_keys = self.authorized_ssh_keys(account)?;
+        match authorized_keys
+            .iter_mut()
+            .find(|key| **key == old.public_key)
+        {
+            Some(key) => *key = new.public_key.clone(),
+            None => bail!(
+                "The key {} isn't authorized for {}",
+                old.name,
+                account.name()
+            ),
+        }
+        write_authorized_keys(self, account, &authorized_keys)
+    }
+
+    fn revoke_ssh_key(&self, account: SshAccount, key: &SshKeyPair) -> Result<()> {
+        let mut authorized_keys = self.authorized_ssh_keys(account)?;
+        let count = authorized_keys.len();
+        authorized_keys.retain(|authorized_key| *authorized_key != key.public_key);
+        if authorized_keys.len() == count {
+            bail!(
+                "The key {} isn't authorized for {}",
+                key.name,
+                account.name()
+            );
+        }
+        write_authorized_keys(self, account, &authorized_keys)
+    }
+
+    fn authorized_ssh_keys(&self, account: SshAccount) -> Result<Vec<String>> {
+        let script = format!(
+            r#"sudo cat "$(getent passwd {account} | cut -d: -f6)/.ssh/authorized_keys""#,
+            account = account.name()
+        );
+        let output = self.block_on_bash_script(&script)?;
+        Ok(output
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect())
+    }
+
+    fn ssh_login(&self, account: SshAccount, key: &SshKeyPair) -> Result<Session> {
+        let tcp = TcpStream::connect((self.get_ip_addr(), 22))?;
+        let mut session = Session::new()?;
+        session.set_tcp_stream(tcp);
+        session.handshake()?;
+        session.userauth_pubkey_memory(account.name(), None, &key.private_key, None)?;
+        Ok(session)
+    }
+}
+
+fn write_authorized_keys(
+    node: &IcNodeSnapshot,
+    account: SshAccount,
+    authorized_keys: &[String],
+) -> Result<()> {
+    let script = format!(
+        "sudo {PROVISION_SSH_KEYS} {account} <<'EOF'\n{keys}\nEOF\n",
+        account = account.name(),
+        keys = authorized_keys.join("\n")
+    );
+    node.block_on_bash_script(&script)?;
+    Ok(())
+}
@@ -14,6 +14,7 @@ use crate::driver::{
 };
 use slog::{debug, error, info, Logger};
 use std::{
+    any::Any,
     panic::catch_unwind,
     process::Command,
     sync::{
@@ -253,20 +254,24 @@ impl TaskState {
     }
 }
 
-fn panic_to_result(panic_res: std::thread::Result<()>) -> Result<(), String> {
+pub(crate) fn panic_to_result(panic_res: std::thread::Result<()>) -> Result<(), String> {
     if let Err(panic_res) = panic_res {
-        if let Some(s) = panic_res.downcast_ref::<String>() {
-            Err(s.to_string())
-        } else if let Some(s) = panic_res.downcast_ref::<&str>() {
-            Err(s.to_string())
-        } else {
-            Err(format!("{:?}", panic_res))
-        }
+        Err(panic_message(panic_res.as_ref()))
     } else {
         Ok(())
     }
 }
 
+pub(crate) fn panic_message(panic_res: &(dyn Any + Send)) -> String {
+    if let Some(s) = panic_res.downcast_ref::<String>() {
+        s.to_string()
+    } else if let Some(s) = panic_res.downcast_ref::<&str>() {
+        s.to_string()
+    } else {
+        format!("{:?}", panic_res)
+    }
+}
+
 fn shared_mutex<T>(v: T) -> Arc<Mutex<T>> {
     Arc::new(Mutex::new(v))
 }
@@ -198,6 +198,7 @@ impl TaskScheduler {
             success,
             failure,
             skipped,
+            retried: vec![],
         }
     }
 }
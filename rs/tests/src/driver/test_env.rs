@@ -3,7 +3,7 @@ use ic_prep_lib::prep_state_directory::IcPrepStateDir;
 use rand::{RngCore, SeedableRng};
 use rand_chacha::ChaCha8Rng;
 use serde::de::DeserializeOwned;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use slog::{info, o, Drain, Logger};
 use slog_async::OverflowStrategy;
 use std::fs::{self, File};
@@ -213,11 +213,41 @@ pub trait HasDefaultRng {
 }
 
 impl HasDefaultRng for TestEnv {
-    /// Returns a random number generator based on a constant seed. At a later
-    /// point, the seed will be configured through the underlying test
-    /// environment.
+    /// Returns [TestEnv::rng].
     fn default_rng(&self) -> Box<dyn RngCore> {
-        Box::new(ChaCha8Rng::seed_from_u64(42))
+        Box::new(self.rng())
+    }
+}
+
+/// The seed used when the environment wasn't created by a `SystemTestGroup`.
+const DEFAULT_SEED: u64 = 42;
+
+/// The seed of the random number generators of the tests in a group, set
+/// with `--seed` or chosen randomly, and reported so that a failing run can
+/// be reproduced.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct TestSeed(pub u64);
+
+impl TestSeed {
+    pub fn new_or_random(seed: Option<u64>) -> Self {
+        Self(seed.unwrap_or_else(rand::random))
+    }
+}
+
+impl TestEnvAttribute for TestSeed {
+    fn attribute_name() -> String {
+        String::from("seed")
+    }
+}
+
+impl TestEnv {
+    /// Returns a random number generator seeded with the [TestSeed] of the
+    /// group. Every call returns a generator in the same state, so the
+    /// random choices of a test, e.g. of payloads or of the nodes to target,
+    /// are the same whenever it runs with the same seed.
+    pub fn rng(&self) -> ChaCha8Rng {
+        let seed = TestSeed::try_read_attribute(self).map_or(DEFAULT_SEED, |seed| seed.0);
+        ChaCha8Rng::seed_from_u64(seed)
     }
 }
 
@@ -138,6 +138,7 @@ use super::test_setup::GroupSetup;
 use crate::driver::constants::{self, kibana_link, SSH_USERNAME};
 use crate::driver::farm::{Farm, GroupSpec};
 use crate::driver::test_env::{HasIcPrepDir, SshKeyGen, TestEnv, TestEnvAttribute};
+use crate::nns::add_nodes_to_subnet;
 use crate::util::{create_agent, delay};
 use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
@@ -1582,6 +1583,129 @@ impl IcNodeContainer for SubnetSnapshot {
     }
 }
 
+/* ### Spare Nodes ### */
+
+/// The spare nodes that have already been taken from the pool.
+#[derive(Clone, Default, Deserialize, Serialize, Debug)]
+struct TakenSpareNodes {
+    node_ids: Vec<NodeId>,
+}
+
+impl TestEnvAttribute for TakenSpareNodes {
+    fn attribute_name() -> String {
+        "taken_spare_nodes".to_string()
+    }
+}
+
+/// A pool of warm spare nodes, i.e. the unassigned nodes provisioned at setup
+/// time (see [InternetComputer::with_unassigned_nodes]). Adding them to a
+/// subnet later on only takes a proposal, which spares tests that replace or
+/// add subnet members the latency of provisioning new VMs.
+///
+/// [InternetComputer::with_unassigned_nodes]: crate::driver::ic::InternetComputer::with_unassigned_nodes
+pub trait SpareNodes {
+    /// Takes `count` spare nodes from the pool. A node is never taken twice.
+    fn take_spare_nodes(&self, count: usize) -> Result<Vec<IcNodeSnapshot>>;
+
+    /// Takes `count` spare nodes from the pool and adds them to the subnet
+    /// `subnet_id`, by proposal. Waits until the nodes are healthy members
+    /// of the subnet and returns the snapshot of the new topology.
+    fn add_spare_nodes_to_subnet(
+        &self,
+        subnet_id: SubnetId,
+        count: usize,
+    ) -> Result<TopologySnapshot>;
+}
+
+impl SpareNodes for TestEnv {
+    fn take_spare_nodes(&self, count: usize) -> Result<Vec<IcNodeSnapshot>> {
+        let mut taken = TakenSpareNodes::try_read_attribute(self).unwrap_or_default();
+        let spare_nodes: Vec<_> = self
+            .topology_snapshot()
+            .unassigned_nodes()
+            .filter(|node| !taken.node_ids.contains(&node.node_id))
+            .take(count)
+            .collect();
+        if spare_nodes.len() < count {
+            bail!(
+                "Requested {} spare nodes, but only {} are left in the pool",
+                count,
+                spare_nodes.len()
+            );
+        }
+        taken
+            .node_ids
+            .extend(spare_nodes.iter().map(|node| node.node_id));
+        taken.write_attribute(self);
+        Ok(spare_nodes)
+    }
+
+    fn add_spare_nodes_to_subnet(
+        &self,
+        subnet_id: SubnetId,
+        count: usize,
+    ) -> Result<TopologySnapshot> {
+        let node_ids: Vec<_> = self
+            .take_spare_nodes(count)?
+            .into_iter()
+            .map(|node| node.node_id)
+            .collect();
+        info!(
+            self.logger(),
+            "Adding spare nodes {:?} to subnet {}", node_ids, subnet_id
+        );
+        let nns_node = self.get_first_healthy_nns_node_snapshot();
+        let topology = self.topology_snapshot();
+        let rt = Rt::new().expect("Could not create tokio runtime.");
+        let (topology, subnet) = rt.block_on(async {
+            add_nodes_to_subnet(nns_node.get_public_url(), subnet_id, &node_ids)
+                .await
+                .map_err(|err| anyhow!("Could not add spare nodes to subnet: {}", err))?;
+            // Other registry versions may be created before the one adding
+            // the nodes, so sync until the nodes show up in the subnet.
+            retry_async(
+                &self.logger(),
+                READY_WAIT_TIMEOUT,
+                RETRY_BACKOFF,
+                || async {
+                    topology.local_registry.sync_with_nns().await?;
+                    let latest = topology
+                        .block_for_min_registry_version(
+                            topology.local_registry.get_latest_version(),
+                        )
+                        .await?;
+                    let subnet = latest
+                        .subnets()
+                        .find(|subnet| subnet.subnet_id == subnet_id)
+                        .ok_or_else(|| anyhow!("Subnet {} not found in the registry", subnet_id))?;
+                    let members: Vec<_> = subnet.nodes().map(|node| node.node_id).collect();
+                    let missing: Vec<_> = node_ids
+                        .iter()
+                        .filter(|node_id| !members.contains(node_id))
+                        .collect();
+                    if !missing.is_empty() {
+                        bail!(
+                            "Nodes {:?} are not members of subnet {} at registry version {}",
+                            missing,
+                            subnet_id,
+                            latest.get_registry_version()
+                        );
+                    }
+                    Ok((latest, subnet))
+                },
+            )
+            .await
+        })?;
+        for node in subnet
+            .nodes()
+            .filter(|node| node_ids.contains(&node.node_id))
+        {
+            node.await_status_is_healthy()?;
+        }
+        Ok(topology)
+    }
+}
+
 /* ### VM Control ### */
 
 pub trait VmControl {
@@ -27,6 +27,7 @@ end::catalog[] */
 
 use std::time::Duration;
 
+use crate::driver::canister_call_trace::{CanisterCallTracer, HasCanisterCallTracer};
 use crate::driver::test_env::TestEnv;
 use crate::driver::test_env_api::{
     HasDependencies, HasPublicApiUrl, HasTopologySnapshot, HasVm, IcNodeContainer, IcNodeSnapshot,
@@ -90,7 +91,12 @@ pub fn test(env: TestEnv) {
     );
     // Step 2: Start all canisters (via update `start` call).
     info!(log, "Calling start() on all canisters ...");
-    start_all_canisters(&canisters, PAYLOAD_SIZE_BYTES, CANISTER_TO_SUBNET_RATE);
+    start_all_canisters(
+        &env.canister_call_tracer(),
+        &canisters,
+        PAYLOAD_SIZE_BYTES,
+        CANISTER_TO_SUBNET_RATE,
+    );
     // Step 3:  Wait 15 secs for canisters to exchange messages.
     info!(log, "Sending messages for {} secs ...", MSG_EXEC_TIME_SEC);
     block_on(async {
@@ -147,6 +153,7 @@ pub fn test(env: TestEnv) {
 }
 
 pub fn start_all_canisters(
+    tracer: &CanisterCallTracer,
     canisters: &[Vec<Canister>],
     payload_size_bytes: u64,
     canister_to_subnet_rate: u64,
@@ -162,8 +169,8 @@ pub fn start_all_canisters(
             .flat_map(|(x, v)| v.iter().enumerate().map(move |(y, v)| (x, y, v)))
         {
             let input = (&topology, canister_to_subnet_rate, payload_size_bytes);
-            let _: String = canister
-                .update_("start", candid, input)
+            let _: String = tracer
+                .update_(canister, "start", candid, input)
                 .await
                 .unwrap_or_else(|_| {
                     panic!(
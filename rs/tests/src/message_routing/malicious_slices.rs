@@ -21,6 +21,7 @@ Success::
 end::catalog[] */
 
 use super::common::{install_canisters, start_all_canisters};
+use crate::driver::canister_call_trace::HasCanisterCallTracer;
 use crate::driver::ic::{InternetComputer, Subnet};
 use crate::driver::pot_dsl::{PotSetupFn, SysTestFn};
 use crate::driver::prometheus_vm::{HasPrometheus, PrometheusVm};
@@ -135,8 +136,13 @@ pub async fn test_async(env: TestEnv, config: Config) {
     // Start all canisters (via update `start` call).
     info!(logger, "Calling start() on all canisters...");
     start_all_canisters(
-        &canisters, 1024, // send messages with 1024 byte payloads
+        &env.canister_call_tracer(),
+        &canisters,
+        1024, // send messages with 1024 byte payloads
         10,   // each canister sends 10 RPS
+        None, // responses have the same size as requests
+        None, // no injected faults
+        &mut env.rng(),
     )
     .await;
     info!(logger, "Starting chatter: 10 messages/round * 1024 bytes",);
@@ -1,23 +1,39 @@
 pub mod global_reboot_test;
 pub mod malicious_slices;
 pub mod rejoin_test;
+pub mod rejoin_test_xnet_load;
+pub mod xnet_backlog_drain;
+pub mod xnet_slice_limits;
+pub mod xnet_slo_kpis;
 pub mod xnet_slo_test;
 
 mod common {
     use canister_test::{Canister, Runtime, Wasm};
     use dfn_candid::candid;
     use futures::{future::join_all, Future};
+    use rand::Rng;
     use slog::info;
-    use xnet_test::CanisterId;
+    use xnet_test::{CanisterId, FaultInjection, PayloadSizeDistribution};
 
-    use crate::driver::{test_env::TestEnv, test_env_api::HasDependencies};
+    use crate::driver::{
+        canister_call_trace::CanisterCallTracer, test_env::TestEnv, test_env_api::HasDependencies,
+    };
 
     /// Concurrently calls `start` on all canisters in `canisters` with the
-    /// given parameters.
+    /// given parameters. Responses are padded to `payload_size_bytes` unless
+    /// `response_payload_size` is given. Faults are only injected into the
+    /// handling of requests if `fault_injection` is given. Each canister is
+    /// seeded from `rng`, e.g. [TestEnv::rng], such that its choices of
+    /// destinations and payload sizes are reproducible. The calls are
+    /// recorded by `tracer`.
     pub async fn start_all_canisters(
+        tracer: &CanisterCallTracer,
         canisters: &[Vec<Canister<'_>>],
         payload_size_bytes: u64,
         canister_to_subnet_rate: u64,
+        response_payload_size: Option<PayloadSizeDistribution>,
+        fault_injection: Option<FaultInjection>,
+        rng: &mut impl Rng,
     ) {
         let topology: Vec<Vec<CanisterId>> = canisters
             .iter()
@@ -29,10 +45,18 @@ mod common {
             .enumerate()
             .flat_map(|(x, v)| v.iter().enumerate().map(move |(y, v)| (x, y, v)))
         {
-            let input = (&topology, canister_to_subnet_rate, payload_size_bytes);
+            let input = (
+                &topology,
+                canister_to_subnet_rate,
+                payload_size_bytes,
+                response_payload_size.clone(),
+                fault_injection.clone(),
+                None::<u64>, // no cycles attached to requests
+                Some(rng.gen::<u64>()),
+            );
             futures.push(async move {
-                let _: String = canister
-                    .update_("start", candid, input)
+                let _: String = tracer
+                    .update_(canister, "start", candid, input)
                     .await
                     .unwrap_or_else(|_| {
                         panic!(
@@ -45,6 +69,46 @@ mod common {
         futures::future::join_all(futures).await;
     }
 
+    /// The number of WebAssembly pages (16 MiB) `grow_all_canisters` asks a
+    /// canister to allocate per call, in order to stay well within the
+    /// instruction and heap delta limits of a single message.
+    const GROW_STATE_PAGES_PER_CALL: u64 = 256;
+
+    /// Concurrently grows the heap of all canisters in `canisters` by `pages`
+    /// WebAssembly pages (64 KiB each), such that their state is large, e.g.
+    /// for state sync, while they generate XNet traffic. The calls are
+    /// recorded by `tracer`.
+    pub async fn grow_all_canisters(
+        tracer: &CanisterCallTracer,
+        canisters: &[Vec<Canister<'_>>],
+        pages: u64,
+    ) {
+        let mut futures = vec![];
+        for (subnet_idx, canister_idx, canister) in canisters
+            .iter()
+            .enumerate()
+            .flat_map(|(x, v)| v.iter().enumerate().map(move |(y, v)| (x, y, v)))
+        {
+            futures.push(async move {
+                let mut remaining = pages;
+                while remaining > 0 {
+                    let step = remaining.min(GROW_STATE_PAGES_PER_CALL);
+                    let _: u64 = tracer
+                        .update_(canister, "grow_state", candid, (step,))
+                        .await
+                        .unwrap_or_else(|_| {
+                            panic!(
+                                "Growing the state of canister_idx={} on subnet_idx={}",
+                                canister_idx, subnet_idx
+                            )
+                        });
+                    remaining -= step;
+                }
+            });
+        }
+        futures::future::join_all(futures).await;
+    }
+
     /// Concurrently installs `canisters_per_subnet` instances of the XNet test canister
     /// onto the subnets corresponding to the runtimes `0..subnets` in `endpoint_runtime`.
     pub async fn install_canisters(
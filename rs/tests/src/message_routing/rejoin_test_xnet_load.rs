@@ -0,0 +1,285 @@
+/* tag::catalog[]
+
+Title:: Nodes can rejoin a subnet under XNet and ingress load
+
+Runbook::
+. setup a system subnet of 3f + 1 nodes and an application subnet of a single node
+. install XNet canisters on both subnets, grow their state to STATE_PAGES_PER_CANISTER pages and start sustained XNet traffic
+. install the universal canister on the system subnet and keep sending updates to it
+. pick a random node rejoin_node of the system subnet and kill it
+. wait until the other nodes certified a few DKG intervals worth of heights
+. start the rejoin_node
+. wait for the rejoin_node to state sync and catch up
+. wait for the rejoin_node to notary-sign blocks
+. stop the load
+
+Success::
+.. rejoin_node completes a state sync and reaches the certified height the subnet had when it was restarted within STATE_SYNC_SLO
+.. rejoin_node notary-signs blocks afterwards
+.. updates to the universal canister succeeded and XNet messages were delivered in order throughout
+
+end::catalog[] */
+
+use super::common::{grow_all_canisters, install_canisters, start_all_canisters};
+use super::xnet_slo_test::{collect_metrics, stop_all_canister};
+use crate::driver::canister_call_trace::HasCanisterCallTracer;
+use crate::driver::ic::{InternetComputer, Subnet};
+use crate::driver::test_env::TestEnv;
+use crate::driver::test_env_api::{
+    retry, HasPublicApiUrl, HasTopologySnapshot, HasVm, IcNodeContainer, IcNodeSnapshot,
+    RETRY_BACKOFF,
+};
+use crate::util::{block_on, runtime_from_url, MetricsFetcher, UniversalCanister};
+use anyhow::bail;
+use canister_test::Runtime;
+use ic_registry_subnet_type::SubnetType;
+use ic_types::Height;
+use slog::{info, Logger};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use xnet_test::Metrics;
+
+const ALLOWED_FAILURES: usize = 1;
+const NODES_COUNT: usize = 3 * ALLOWED_FAILURES + 1;
+const DKG_INTERVAL: u64 = 14;
+const NOTARY_DELAY: Duration = Duration::from_millis(100);
+
+/// The number of DKG intervals the subnet progresses while the rejoin_node is
+/// down, such that it has to state sync.
+const DKG_INTERVALS_DOWN: u64 = 5;
+/// The XNet messages sent per round by each canister, with 1 KiB payloads.
+const XNET_RATE: u64 = 10;
+/// The WebAssembly pages (64 KiB each) allocated by each XNet canister, such
+/// that the rejoin_node has to state sync a large state (256 MiB per canister)
+/// while the XNet traffic goes on.
+const STATE_PAGES_PER_CANISTER: u64 = 4096;
+/// The time within which the rejoin_node must state sync and catch up after
+/// its restart.
+const STATE_SYNC_SLO: Duration = Duration::from_secs(180);
+/// The time within which the rejoin_node must notary-sign a block after it
+/// caught up.
+const CONTRIBUTION_TIMEOUT: Duration = Duration::from_secs(60);
+const PROGRESS_TIMEOUT: Duration = Duration::from_secs(300);
+
+const STATE_MANAGER_MAX_RESIDENT_HEIGHT: &str = "state_manager_max_resident_height";
+const STATE_SYNCS_COMPLETED: &str = "state_sync_duration_seconds_count{status=\"ok\"}";
+const NOTARY_SIGNATURES: &str = "consensus_time_to_notary_sign_count";
+
+pub fn config(env: TestEnv) {
+    InternetComputer::new()
+        .add_subnet(
+            Subnet::new(SubnetType::System)
+                .add_nodes(NODES_COUNT)
+                .with_dkg_interval_length(Height::from(DKG_INTERVAL))
+                .with_initial_notary_delay(NOTARY_DELAY),
+        )
+        .add_subnet(Subnet::new(SubnetType::Application).add_nodes(1))
+        .setup_and_start(&env)
+        .expect("failed to setup IC under test");
+    env.topology_snapshot().subnets().for_each(|subnet| {
+        subnet
+            .nodes()
+            .for_each(|node| node.await_status_is_healthy().unwrap())
+    });
+}
+
+pub fn test(env: TestEnv) {
+    let logger = env.logger();
+    let topology = env.topology_snapshot();
+    let root_subnet = topology.root_subnet();
+    let app_subnet = topology
+        .subnets()
+        .find(|s| s.subnet_id != root_subnet.subnet_id)
+        .expect("no application subnet");
+    let mut nodes = root_subnet.nodes();
+    let node = nodes.next().unwrap();
+    let rejoin_node = nodes.next().unwrap();
+    let healthy_nodes: Vec<IcNodeSnapshot> = root_subnet
+        .nodes()
+        .filter(|n| n.node_id != rejoin_node.node_id)
+        .collect();
+
+    info!(logger, "Starting XNet load ...");
+    let endpoints_runtime: Vec<Runtime> = [&root_subnet, &app_subnet]
+        .iter()
+        .map(|s| {
+            let node = s.nodes().next().unwrap();
+            runtime_from_url(node.get_public_url(), node.effective_canister_id())
+        })
+        .collect();
+    let canisters = block_on(install_canisters(env.clone(), &endpoints_runtime, 2, 1));
+    let tracer = env.canister_call_tracer();
+    block_on(grow_all_canisters(
+        &tracer,
+        &canisters,
+        STATE_PAGES_PER_CANISTER,
+    ));
+    block_on(start_all_canisters(
+        &tracer,
+        &canisters,
+        1024, // send messages with 1024 byte payloads
+        XNET_RATE,
+        None, // responses have the same size as requests
+        None, // no injected faults
+        &mut env.rng(),
+    ));
+
+    info!(
+        logger,
+        "Starting ingress load through node {} ...",
+        node.get_public_url()
+    );
+    let agent = node.with_default_agent(|agent| async move { agent });
+    let canister_id = block_on(UniversalCanister::new_with_retries(
+        &agent,
+        node.effective_canister_id(),
+        &logger,
+    ))
+    .canister_id();
+    let stop_ingress = Arc::new(AtomicBool::new(false));
+    let ingress_load = {
+        let stop_ingress = stop_ingress.clone();
+        std::thread::spawn(move || {
+            block_on(async move {
+                let canister = UniversalCanister::from_canister_id(&agent, canister_id);
+                let (mut succeeded, mut failed) = (0u64, 0u64);
+                while !stop_ingress.load(Ordering::Relaxed) {
+                    let message = succeeded.to_le_bytes();
+                    match canister
+                        .update(UniversalCanister::stable_writer(0, &message))
+                        .await
+                    {
+                        Ok(_) => succeeded += 1,
+                        Err(_) => failed += 1,
+                    }
+                }
+                (succeeded, failed)
+            })
+        })
+    };
+
+    info!(
+        logger,
+        "Killing a node: {} ...",
+        rejoin_node.get_public_url()
+    );
+    rejoin_node.vm().kill();
+    let height = max_height(&healthy_nodes).expect("could not fetch the certified height");
+    await_height(
+        &logger,
+        &healthy_nodes,
+        height + DKG_INTERVALS_DOWN * DKG_INTERVAL,
+        PROGRESS_TIMEOUT,
+    );
+
+    info!(logger, "Starting the killed node again ...");
+    let height_at_restart =
+        max_height(&healthy_nodes).expect("could not fetch the certified height");
+    rejoin_node.vm().start();
+    let restarted = Instant::now();
+
+    info!(
+        logger,
+        "Waiting for the node to state sync to height {} ...", height_at_restart
+    );
+    retry(logger.clone(), STATE_SYNC_SLO, RETRY_BACKOFF, || {
+        let state_syncs = fetch_metric(&rejoin_node, STATE_SYNCS_COMPLETED);
+        let height = fetch_metric(&rejoin_node, STATE_MANAGER_MAX_RESIDENT_HEIGHT);
+        match (state_syncs, height) {
+            (Some(state_syncs), Some(height)) if state_syncs > 0 && height >= height_at_restart => {
+                Ok(())
+            }
+            (state_syncs, height) => bail!(
+                "Node not caught up yet: {:?} state syncs completed, height {:?}",
+                state_syncs,
+                height
+            ),
+        }
+    })
+    .unwrap_or_else(|e| panic!("Node did not state sync within {:?}: {}", STATE_SYNC_SLO, e));
+    info!(
+        logger,
+        "Node caught up {:?} after its restart",
+        restarted.elapsed()
+    );
+
+    info!(logger, "Waiting for the node to notary-sign blocks ...");
+    let signatures = fetch_metric(&rejoin_node, NOTARY_SIGNATURES).unwrap_or_default();
+    retry(
+        logger.clone(),
+        CONTRIBUTION_TIMEOUT,
+        RETRY_BACKOFF,
+        || match fetch_metric(&rejoin_node, NOTARY_SIGNATURES) {
+            Some(s) if s > signatures => Ok(()),
+            s => bail!("No new notarization shares yet: {:?}", s),
+        },
+    )
+    .expect("Node does not contribute to consensus after catching up");
+
+    info!(logger, "Stopping the load ...");
+    stop_ingress.store(true, Ordering::Relaxed);
+    let (succeeded, failed) = ingress_load.join().expect("ingress load panicked");
+    info!(logger, "{} updates succeeded, {} failed", succeeded, failed);
+    assert!(succeeded > 0, "No updates succeeded");
+
+    block_on(stop_all_canister(&canisters));
+    for (subnet_idx, subnet_metrics) in block_on(collect_metrics(&canisters)).iter().enumerate() {
+        let mut merged = Metrics::default();
+        subnet_metrics.iter().for_each(|m| merged.merge(m));
+        info!(
+            logger,
+            "Canister metrics for subnet {}: {:?}", subnet_idx, merged
+        );
+        assert_eq!(
+            merged.seq_errors, 0,
+            "Subnet {}: sequence errors",
+            subnet_idx
+        );
+        assert!(
+            merged.requests_sent > 0,
+            "Subnet {}: no requests sent",
+            subnet_idx
+        );
+    }
+}
+
+/// Fetches the value of `metric` from `node`, summed over all label values.
+/// Returns `None` if the node cannot be reached.
+fn fetch_metric(node: &IcNodeSnapshot, metric: &str) -> Option<u64> {
+    let metrics = MetricsFetcher::new(std::iter::once(node.clone()), vec![metric.to_string()]);
+    block_on(metrics.fetch()).ok().map(|values| {
+        values
+            .iter()
+            .filter(|(name, _)| name.starts_with(metric))
+            .map(|(_, v)| v[0])
+            .sum()
+    })
+}
+
+fn max_height(nodes: &[IcNodeSnapshot]) -> Option<u64> {
+    nodes
+        .iter()
+        .filter_map(|node| fetch_metric(node, STATE_MANAGER_MAX_RESIDENT_HEIGHT))
+        .max()
+}
+
+/// Waits until all of `nodes` reached `height`.
+fn await_height(logger: &Logger, nodes: &[IcNodeSnapshot], height: u64, timeout: Duration) {
+    info!(
+        logger,
+        "Waiting for the nodes to reach height {} ...", height
+    );
+    retry(logger.clone(), timeout, RETRY_BACKOFF, || {
+        let heights: Vec<_> = nodes
+            .iter()
+            .map(|node| fetch_metric(node, STATE_MANAGER_MAX_RESIDENT_HEIGHT))
+            .collect();
+        if heights.iter().all(|h| h.map_or(false, |h| h >= height)) {
+            Ok(())
+        } else {
+            bail!("Heights {:?} below {}", heights, height)
+        }
+    })
+    .unwrap_or_else(|e| panic!("Nodes did not reach height {}: {}", height, e));
+}
@@ -0,0 +1,415 @@
+/* tag::catalog[]
+Title:: An XNet backlog built up during a partition drains after it heals.
+
+Goal:: Ensure that, after two subnets could not exchange XNet messages for a
+prolonged time, the streams between them are drained within a bounded time
+once they are reconnected, and that no state is left behind by the backlog.
+
+Runbook::
+0. Instantiate an IC with one system subnet (the NNS) and one application
+   subnet.
+1. Install Xnet canisters on each subnet.
+2. Sample the stream metrics of both subnets as the baseline.
+3. Partition the subnets by dropping all traffic to the XNet endpoints.
+4. Start all canisters (via update `start` call) and let them send for the
+   duration of the partition.
+5. Check that a backlog of messages built up in the streams.
+6. Stop all canisters (via update `stop` call) and heal the partition.
+7. Wait for the streams of both subnets to drain.
+8. Collect the canister metrics (via query `metrics` call).
+
+Success::
+1. The streams of both subnets are empty and nothing is left to induct
+   within DRAIN_TIMEOUT of the partition healing.
+2. All messages of the backlog were garbage collected, i.e. the signals for
+   them were received, and the streams shrank back to their baseline size,
+   i.e. no signals were left behind.
+3. The memory used by the canisters, including their queues, returns to its
+   baseline within DRAIN_TIMEOUT of the partition healing.
+4. The canisters saw no sequence errors.
+
+end::catalog[] */
+
+use super::common::{install_canisters, start_all_canisters};
+use super::xnet_slo_test::{collect_metrics, stop_all_canister};
+use crate::driver::canister_call_trace::HasCanisterCallTracer;
+use crate::driver::constants::DEVICE_NAME;
+use crate::driver::ic::{InternetComputer, Subnet};
+use crate::driver::pot_dsl::{PotSetupFn, SysTestFn};
+use crate::driver::test_env::TestEnv;
+use crate::driver::test_env_api::{
+    HasPublicApiUrl, HasTopologySnapshot, IcNodeContainer, SshSession, SubnetSnapshot,
+};
+use crate::util::{block_on, runtime_from_url, MetricsFetcher};
+use canister_test::Runtime;
+use futures::future::join_all;
+use ic_registry_subnet_type::SubnetType;
+use slog::{info, Logger};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use xnet_test::Metrics;
+
+/// The port of the XNet endpoint of the replica.
+const XNET_PORT: u16 = 2497;
+/// The time the streams have to drain after the partition healed.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// The stream headers may hold a few more signals than at the baseline, for
+/// the messages of the last rounds.
+const STREAM_BYTES_TOLERANCE: u64 = 10 << 10;
+/// The canisters may have grown their heaps by a few Wasm pages while
+/// sending. Leftover messages or reservations in their queues take more, as
+/// a single reservation for a response takes 2 MiB.
+const CANISTER_MEMORY_TOLERANCE: u64 = 1 << 20;
+
+const STREAM_MESSAGES: &str = "mr_stream_messages";
+const STREAM_BYTES: &str = "mr_stream_bytes";
+const XNET_MESSAGE_BACKLOG: &str = "mr_xnet_message_backlog";
+const GCED_XNET_MESSAGES: &str = "mr_gced_xnet_message_count";
+const CANISTER_MEMORY_USAGE: &str = "canister_memory_usage_bytes";
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    nodes_per_subnet: usize,
+    partition_duration: Duration,
+    canisters_per_subnet: usize,
+    canister_to_subnet_rate: u64,
+    payload_size_bytes: u64,
+    /// The backlog that must build up in each stream during the partition
+    /// for the test to be meaningful.
+    min_backlog_messages: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config {
+            nodes_per_subnet: 4,
+            partition_duration: Duration::from_secs(300),
+            canisters_per_subnet: 2,
+            canister_to_subnet_rate: 10,
+            payload_size_bytes: 1024,
+            min_backlog_messages: 1000,
+        }
+    }
+
+    /// Builds the IC instance.
+    pub fn build(self) -> impl PotSetupFn {
+        move |env: TestEnv| setup(env, self)
+    }
+
+    /// Returns a test function based on this configuration.
+    pub fn test(self) -> impl SysTestFn {
+        move |env: TestEnv| test(env, self)
+    }
+}
+
+// Generic setup
+fn setup(env: TestEnv, config: Config) {
+    InternetComputer::new()
+        .add_subnet(Subnet::new(SubnetType::System).add_nodes(config.nodes_per_subnet))
+        .add_subnet(Subnet::new(SubnetType::Application).add_nodes(config.nodes_per_subnet))
+        .setup_and_start(&env)
+        .expect("failed to setup IC under test");
+    env.topology_snapshot().subnets().for_each(|subnet| {
+        subnet
+            .nodes()
+            .for_each(|node| node.await_status_is_healthy().unwrap())
+    });
+}
+
+pub fn test(env: TestEnv, config: Config) {
+    block_on(test_async(env, config));
+}
+
+// Generic test
+pub async fn test_async(env: TestEnv, config: Config) {
+    let logger = env.logger();
+    info!(logger, "Config for the test: {:?}", config);
+    let topology = env.topology_snapshot();
+    let subnets: Vec<SubnetSnapshot> = topology.subnets().collect();
+    let endpoints_runtime: Vec<Runtime> = subnets
+        .iter()
+        .map(|s| {
+            let node = s.nodes().next().unwrap();
+            runtime_from_url(node.get_public_url(), node.effective_canister_id())
+        })
+        .collect();
+
+    // Step 1: Install Xnet canisters on each subnet.
+    info!(logger, "Installing Xnet canisters on subnets ...");
+    let canisters = install_canisters(
+        env.clone(),
+        &endpoints_runtime,
+        subnets.len(),
+        config.canisters_per_subnet,
+    )
+    .await;
+
+    // Step 2: Sample the baseline.
+    let baseline = fetch_all_stream_metrics(&logger, &subnets).await;
+
+    // Steps 3 and 4: Partition the subnets and start all canisters.
+    info!(logger, "Partitioning the subnets ...");
+    set_partitioned(&logger, &subnets, true);
+    info!(logger, "Calling start() on all canisters...");
+    start_all_canisters(
+        &env.canister_call_tracer(),
+        &canisters,
+        config.payload_size_bytes,
+        config.canister_to_subnet_rate,
+        None, // responses have the same size as requests
+        None, // no injected faults
+        &mut env.rng(),
+    )
+    .await;
+    tokio::time::sleep(config.partition_duration).await;
+
+    // Step 5: Check that a backlog built up.
+    let partitioned = fetch_all_stream_metrics(&logger, &subnets).await;
+    for (subnet, metrics) in subnets.iter().zip(partitioned.iter()) {
+        info!(
+            logger,
+            "Subnet {}: {} messages in the streams after the partition",
+            subnet.subnet_id,
+            metrics.stream_messages
+        );
+        assert!(
+            metrics.stream_messages >= config.min_backlog_messages,
+            "Subnet {}: only {} messages in the streams, expected at least {}",
+            subnet.subnet_id,
+            metrics.stream_messages,
+            config.min_backlog_messages
+        );
+    }
+
+    // Step 6: Stop all canisters and heal the partition.
+    info!(logger, "Stopping all canisters...");
+    stop_all_canister(&canisters).await;
+    info!(logger, "Healing the partition ...");
+    set_partitioned(&logger, &subnets, false);
+
+    // Step 7: Wait for the streams to drain.
+    let healed_at = Instant::now();
+    let drained = loop {
+        let metrics = fetch_all_stream_metrics(&logger, &subnets).await;
+        if metrics.iter().all(StreamMetrics::is_drained) {
+            break metrics;
+        }
+        assert!(
+            healed_at.elapsed() < DRAIN_TIMEOUT,
+            "The streams did not drain within {:?} of the partition healing: {:?}",
+            DRAIN_TIMEOUT,
+            metrics
+        );
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    };
+    info!(
+        logger,
+        "The streams drained {:?} after the partition healed",
+        healed_at.elapsed()
+    );
+
+    for (subnet, ((baseline, partitioned), drained)) in subnets
+        .iter()
+        .zip(baseline.iter().zip(partitioned.iter()).zip(drained.iter()))
+    {
+        let gced_messages = drained.gced_messages - partitioned.gced_messages;
+        info!(
+            logger,
+            "Subnet {}: {} messages garbage collected, {} stream bytes (baseline: {})",
+            subnet.subnet_id,
+            gced_messages,
+            drained.stream_bytes,
+            baseline.stream_bytes
+        );
+        assert!(
+            gced_messages >= partitioned.stream_messages,
+            "Subnet {}: {} of the {} messages of the backlog were garbage collected",
+            subnet.subnet_id,
+            gced_messages,
+            partitioned.stream_messages
+        );
+        assert!(
+            drained.stream_bytes <= baseline.stream_bytes + STREAM_BYTES_TOLERANCE,
+            "Subnet {}: the streams hold {} bytes after draining, {} at the baseline",
+            subnet.subnet_id,
+            drained.stream_bytes,
+            baseline.stream_bytes
+        );
+    }
+
+    // The canisters may still have to execute the last responses.
+    let memory_drained = loop {
+        let metrics = fetch_all_stream_metrics(&logger, &subnets).await;
+        if metrics
+            .iter()
+            .zip(baseline.iter())
+            .all(|(metrics, baseline)| {
+                metrics.canister_memory_bytes
+                    <= baseline.canister_memory_bytes + CANISTER_MEMORY_TOLERANCE
+            })
+        {
+            break metrics;
+        }
+        assert!(
+            healed_at.elapsed() < DRAIN_TIMEOUT,
+            "The memory of the canisters did not return to the baseline within {:?} of the \
+             partition healing: {:?}, baseline: {:?}",
+            DRAIN_TIMEOUT,
+            metrics,
+            baseline
+        );
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    };
+    for (subnet, (baseline, memory_drained)) in subnets
+        .iter()
+        .zip(baseline.iter().zip(memory_drained.iter()))
+    {
+        info!(
+            logger,
+            "Subnet {}: {} bytes of canister memory after draining (baseline: {})",
+            subnet.subnet_id,
+            memory_drained.canister_memory_bytes,
+            baseline.canister_memory_bytes
+        );
+    }
+
+    // Step 8: Collect the canister metrics.
+    let canister_metrics = collect_metrics(&canisters).await;
+    for (subnet_idx, subnet_metrics) in canister_metrics.iter().enumerate() {
+        let mut merged = Metrics::default();
+        subnet_metrics.iter().for_each(|m| merged.merge(m));
+        info!(
+            logger,
+            "Aggregated canister metrics for subnet {}: {:?}", subnet_idx, merged
+        );
+        assert_eq!(
+            merged.seq_errors, 0,
+            "Subnet {}: sequence errors",
+            subnet_idx
+        );
+    }
+}
+
+/// Drops (or stops dropping) all packets sent by the nodes of `subnets` to
+/// an XNet endpoint, so that no subnet can fetch streams from the others.
+/// The priority map only uses the first three bands, so the fourth one only
+/// gets the XNet traffic.
+fn set_partitioned(logger: &Logger, subnets: &[SubnetSnapshot], partitioned: bool) {
+    let script = if partitioned {
+        format!(
+            r#"set -euo pipefail
+sudo tc qdisc del dev {DEVICE_NAME} root 2> /dev/null || true
+sudo tc qdisc add dev {DEVICE_NAME} root handle 1: prio bands 4
+sudo tc qdisc add dev {DEVICE_NAME} parent 1:4 handle 40: netem loss 100%
+sudo tc filter add dev {DEVICE_NAME} protocol ipv6 parent 1:0 prio 4 u32 match ip6 dport {XNET_PORT} 0xFFFF flowid 1:4
+"#
+        )
+    } else {
+        format!(
+            r#"set -euo pipefail
+sudo tc qdisc del dev {DEVICE_NAME} root 2> /dev/null || true
+"#
+        )
+    };
+    for node in subnets.iter().flat_map(|subnet| subnet.nodes()) {
+        node.block_on_bash_script(&script).unwrap_or_else(|e| {
+            panic!(
+                "Failed to set the partition of node {} to {}: {:?}",
+                node.node_id, partitioned, e
+            )
+        });
+        info!(
+            logger,
+            "Node {}: XNet traffic {}",
+            node.node_id,
+            if partitioned { "blocked" } else { "unblocked" }
+        );
+    }
+}
+
+/// The stream metrics of a subnet, summed over its streams, and the memory
+/// used by its canisters. Each value is the largest one reported by any node
+/// of the subnet.
+#[derive(Debug, Default)]
+struct StreamMetrics {
+    stream_messages: u64,
+    stream_bytes: u64,
+    xnet_message_backlog: u64,
+    gced_messages: u64,
+    /// Includes the messages in the queues of the canisters, except on
+    /// system subnets.
+    canister_memory_bytes: u64,
+}
+
+impl StreamMetrics {
+    fn is_drained(&self) -> bool {
+        self.stream_messages == 0 && self.xnet_message_backlog == 0
+    }
+}
+
+async fn fetch_all_stream_metrics(
+    logger: &Logger,
+    subnets: &[SubnetSnapshot],
+) -> Vec<StreamMetrics> {
+    join_all(
+        subnets
+            .iter()
+            .map(|subnet| fetch_stream_metrics(logger, subnet)),
+    )
+    .await
+}
+
+/// Fetches the stream metrics of all nodes of `subnet`, retrying until they
+/// are available.
+async fn fetch_stream_metrics(logger: &Logger, subnet: &SubnetSnapshot) -> StreamMetrics {
+    const NUM_RETRIES: u32 = 200;
+    const BACKOFF_TIME_MILLIS: u64 = 500;
+
+    let metric_names = vec![
+        STREAM_MESSAGES.to_string(),
+        STREAM_BYTES.to_string(),
+        XNET_MESSAGE_BACKLOG.to_string(),
+        GCED_XNET_MESSAGES.to_string(),
+        CANISTER_MEMORY_USAGE.to_string(),
+    ];
+    let nodes = subnet.nodes().count();
+    let metrics = MetricsFetcher::new(subnet.nodes(), metric_names);
+    for _ in 0..NUM_RETRIES {
+        match metrics.fetch().await {
+            // The stream gauges only exist once there is a stream.
+            Ok(result) if result.get(GCED_XNET_MESSAGES).map(Vec::len) == Some(nodes) => {
+                return StreamMetrics {
+                    stream_messages: total(&result, STREAM_MESSAGES),
+                    stream_bytes: total(&result, STREAM_BYTES),
+                    xnet_message_backlog: total(&result, XNET_MESSAGE_BACKLOG),
+                    gced_messages: total(&result, GCED_XNET_MESSAGES),
+                    canister_memory_bytes: total(&result, CANISTER_MEMORY_USAGE),
+                };
+            }
+            Ok(_) => info!(logger, "Metrics not available yet."),
+            Err(e) => info!(logger, "Could not scrape metrics: {}.", e),
+        }
+        tokio::time::sleep(Duration::from_millis(BACKOFF_TIME_MILLIS)).await;
+    }
+    panic!(
+        "Couldn't obtain metrics of subnet {} after {} attempts.",
+        subnet.subnet_id, NUM_RETRIES
+    );
+}
+
+/// Sums the largest value reported by any node over all label values of
+/// the metric `name`.
+fn total(metrics: &BTreeMap<String, Vec<u64>>, name: &str) -> u64 {
+    metrics
+        .iter()
+        .filter(|(key, _)| key.as_str() == name || key.starts_with(&format!("{}{{", name)))
+        .map(|(_, values)| values.iter().copied().max().unwrap_or_default())
+        .sum()
+}
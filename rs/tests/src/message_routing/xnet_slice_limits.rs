@@ -0,0 +1,349 @@
+/* tag::catalog[]
+Title:: XNet slices respect the slice size limits under overload.
+
+Goal:: Ensure that, when the XNet traffic offered by canisters exceeds what fits
+into a block, slices are split at the byte and message count limits and XNet
+throughput degrades gracefully instead of stalling.
+
+Runbook::
+0. Instantiate an IC with one system subnet (the NNS) and one application
+   subnet, with a fixed maximum block payload size.
+1. Install Xnet canisters on each subnet.
+2. Start all canisters (via update `start` call), sending more bytes per round
+   to the other subnet than fit into a block.
+3. Wait for half of RUNTIME_SEC secs and fetch the XNet payload builder metrics.
+4. Wait for the other half of RUNTIME_SEC secs and fetch the metrics again.
+5. Stop all canisters and collect their metrics (via query `metrics` call).
+
+Success::
+1. No slice was larger than the block payload (at histogram bucket resolution)
+   and no slice byte size was miscounted.
+2. No slice included into a block on the NNS held more messages than the
+   system subnet stream message limit.
+3. Every node kept inducting XNet messages during the second half of the run.
+4. The canisters saw no sequence errors and received responses.
+
+end::catalog[] */
+
+use super::common::{install_canisters, start_all_canisters};
+use super::xnet_slo_test::{collect_metrics, stop_all_canister};
+use crate::driver::ic::{InternetComputer, Subnet};
+use crate::driver::pot_dsl::{PotSetupFn, SysTestFn};
+use crate::driver::test_env::TestEnv;
+use crate::driver::test_env_api::{
+    HasPublicApiUrl, HasTopologySnapshot, IcNodeContainer, SubnetSnapshot,
+};
+use crate::util::{block_on, runtime_from_url, MetricsFetcher};
+use canister_test::Runtime;
+use futures::future::join_all;
+use ic_constants::SYSTEM_SUBNET_STREAM_MSG_LIMIT;
+use ic_registry_subnet_type::SubnetType;
+use slog::{info, Logger};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use xnet_test::Metrics;
+
+/// The maximum block payload size of both subnets, which bounds the XNet
+/// payload and thus the size of any slice included into a block.
+const MAX_BLOCK_PAYLOAD_SIZE: u64 = 4 << 20;
+/// The upper bound of the smallest `xnet_builder_slice_payload_size_bytes`
+/// bucket above `MAX_BLOCK_PAYLOAD_SIZE`.
+const SLICE_PAYLOAD_SIZE_BUCKET: &str = "5000000";
+
+const SLICE_MESSAGES_SUM: &str = "xnet_builder_slice_messages_sum";
+const SLICE_MESSAGES_COUNT: &str = "xnet_builder_slice_messages_count";
+const SLICE_MESSAGES_BUCKET: &str = "xnet_builder_slice_messages_bucket";
+const SLICE_PAYLOAD_SIZE_COUNT: &str = "xnet_builder_slice_payload_size_bytes_count";
+const SLICE_PAYLOAD_SIZE_BUCKET_PREFIX: &str = "xnet_builder_slice_payload_size_bytes_bucket";
+const SLICE_COUNT_BYTES_INVALID: &str = "critical_errors{error=\"xnet_slice_count_bytes_invalid\"}";
+const SLICE_COUNT_BYTES_FAILED: &str = "critical_errors{error=\"xnet_slice_count_bytes_failed\"}";
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    nodes_per_subnet: usize,
+    runtime: Duration,
+    canisters_per_subnet: usize,
+    canister_to_subnet_rate: u64,
+    payload_size_bytes: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Config {
+    pub fn new() -> Config {
+        let config = Config {
+            nodes_per_subnet: 4,
+            runtime: Duration::from_secs(300),
+            canisters_per_subnet: 2,
+            canister_to_subnet_rate: 30,
+            payload_size_bytes: 128 << 10,
+        };
+        // The canisters must offer more XNet traffic per round than fits into
+        // a block, or the limits are never hit.
+        assert!(config.offered_bytes_per_round() > MAX_BLOCK_PAYLOAD_SIZE);
+        config
+    }
+
+    fn offered_bytes_per_round(&self) -> u64 {
+        self.canisters_per_subnet as u64 * self.canister_to_subnet_rate * self.payload_size_bytes
+    }
+
+    /// Builds the IC instance.
+    pub fn build(self) -> impl PotSetupFn {
+        move |env: TestEnv| setup(env, self)
+    }
+
+    /// Returns a test function based on this configuration.
+    pub fn test(self) -> impl SysTestFn {
+        move |env: TestEnv| test(env, self)
+    }
+}
+
+// Generic setup
+fn setup(env: TestEnv, config: Config) {
+    InternetComputer::new()
+        .add_subnet(
+            Subnet::new(SubnetType::System)
+                .add_nodes(config.nodes_per_subnet)
+                .with_max_block_payload_size(MAX_BLOCK_PAYLOAD_SIZE),
+        )
+        .add_subnet(
+            Subnet::new(SubnetType::Application)
+                .add_nodes(config.nodes_per_subnet)
+                .with_max_block_payload_size(MAX_BLOCK_PAYLOAD_SIZE),
+        )
+        .setup_and_start(&env)
+        .expect("failed to setup IC under test");
+    env.topology_snapshot().subnets().for_each(|subnet| {
+        subnet
+            .nodes()
+            .for_each(|node| node.await_status_is_healthy().unwrap())
+    });
+}
+
+pub fn test(env: TestEnv, config: Config) {
+    block_on(test_async(env, config));
+}
+
+// Generic test
+pub async fn test_async(env: TestEnv, config: Config) {
+    let logger = env.logger();
+    info!(logger, "Config for the test: {:?}", config);
+    let topology = env.topology_snapshot();
+    let subnets: Vec<SubnetSnapshot> = topology.subnets().collect();
+    let endpoints_runtime: Vec<Runtime> = subnets
+        .iter()
+        .map(|s| {
+            let node = s.nodes().next().unwrap();
+            runtime_from_url(node.get_public_url(), node.effective_canister_id())
+        })
+        .collect();
+
+    // Step 1: Install Xnet canisters on each subnet.
+    info!(logger, "Installing Xnet canisters on subnets ...");
+    let canisters = install_canisters(
+        env.clone(),
+        &endpoints_runtime,
+        subnets.len(),
+        config.canisters_per_subnet,
+    )
+    .await;
+
+    // Step 2: Start all canisters (via update `start` call).
+    info!(logger, "Calling start() on all canisters...");
+    start_all_canisters(
+        &canisters,
+        config.payload_size_bytes,
+        config.canister_to_subnet_rate,
+        None, // responses have the same size as requests
+        None, // no injected faults
+    )
+    .await;
+    info!(
+        logger,
+        "Offering {} bytes/round to each subnet, block payloads are limited to {} bytes",
+        config.offered_bytes_per_round(),
+        MAX_BLOCK_PAYLOAD_SIZE
+    );
+
+    // Steps 3 and 4: Sample the payload builder metrics halfway and at the end.
+    let half_runtime = config.runtime / 2;
+    tokio::time::sleep(half_runtime).await;
+    let halfway = join_all(
+        subnets
+            .iter()
+            .map(|subnet| fetch_slice_metrics(&logger, subnet)),
+    )
+    .await;
+    tokio::time::sleep(half_runtime).await;
+    let end = join_all(
+        subnets
+            .iter()
+            .map(|subnet| fetch_slice_metrics(&logger, subnet)),
+    )
+    .await;
+
+    // Step 5: Stop all canisters and collect their metrics.
+    info!(logger, "Stopping all canisters...");
+    stop_all_canister(&canisters).await;
+    let canister_metrics = collect_metrics(&canisters).await;
+
+    let nns_subnet_id = topology.root_subnet_id();
+    for (subnet, (halfway, end)) in subnets.iter().zip(halfway.iter().zip(end.iter())) {
+        assert_slice_limits(&logger, subnet, subnet.subnet_id == nns_subnet_id, end);
+        assert_progress(&logger, subnet, halfway, end);
+    }
+    for (subnet_idx, subnet_metrics) in canister_metrics.iter().enumerate() {
+        let mut merged = Metrics::default();
+        subnet_metrics.iter().for_each(|m| merged.merge(m));
+        info!(
+            logger,
+            "Aggregated canister metrics for subnet {}: {:?}", subnet_idx, merged
+        );
+        assert_eq!(
+            merged.seq_errors, 0,
+            "Subnet {}: sequence errors",
+            subnet_idx
+        );
+        let responses_received = merged.latency_distribution.buckets().last().unwrap().1;
+        assert!(
+            responses_received > 0,
+            "Subnet {}: no responses received for {} requests sent",
+            subnet_idx,
+            merged.requests_sent
+        );
+    }
+}
+
+/// Fetches the slice metrics of all nodes of `subnet`, retrying until they are
+/// available.
+async fn fetch_slice_metrics(
+    logger: &Logger,
+    subnet: &SubnetSnapshot,
+) -> BTreeMap<String, Vec<u64>> {
+    const NUM_RETRIES: u32 = 200;
+    const BACKOFF_TIME_MILLIS: u64 = 500;
+
+    let metric_names = vec![
+        SLICE_MESSAGES_SUM.to_string(),
+        SLICE_MESSAGES_COUNT.to_string(),
+        slice_messages_within_limit(),
+        SLICE_PAYLOAD_SIZE_COUNT.to_string(),
+        slice_payload_size_within_limit(),
+        SLICE_COUNT_BYTES_INVALID.to_string(),
+        SLICE_COUNT_BYTES_FAILED.to_string(),
+    ];
+    let nodes = subnet.nodes().count();
+    let metrics = MetricsFetcher::new(subnet.nodes(), metric_names.clone());
+    for _ in 0..NUM_RETRIES {
+        match metrics.fetch().await {
+            Ok(result)
+                if metric_names
+                    .iter()
+                    .all(|name| result.get(name).map(Vec::len) == Some(nodes)) =>
+            {
+                return result;
+            }
+            Ok(_) => info!(logger, "Metrics not available yet."),
+            Err(e) => info!(logger, "Could not scrape metrics: {}.", e),
+        }
+        tokio::time::sleep(Duration::from_millis(BACKOFF_TIME_MILLIS)).await;
+    }
+    panic!(
+        "Couldn't obtain metrics of subnet {} after {} attempts.",
+        subnet.subnet_id, NUM_RETRIES
+    );
+}
+
+/// The number of slices with at most `SYSTEM_SUBNET_STREAM_MSG_LIMIT` messages,
+/// which is one of the bucket bounds of `xnet_builder_slice_messages`.
+fn slice_messages_within_limit() -> String {
+    format!(
+        "{}{{le=\"{}\"}}",
+        SLICE_MESSAGES_BUCKET, SYSTEM_SUBNET_STREAM_MSG_LIMIT
+    )
+}
+
+fn slice_payload_size_within_limit() -> String {
+    format!(
+        "{}{{le=\"{}\"}}",
+        SLICE_PAYLOAD_SIZE_BUCKET_PREFIX, SLICE_PAYLOAD_SIZE_BUCKET
+    )
+}
+
+fn assert_slice_limits(
+    logger: &Logger,
+    subnet: &SubnetSnapshot,
+    is_nns: bool,
+    metrics: &BTreeMap<String, Vec<u64>>,
+) {
+    for node in 0..subnet.nodes().count() {
+        let slices = metrics[SLICE_PAYLOAD_SIZE_COUNT][node];
+        info!(
+            logger,
+            "Subnet {}, node {}: {} slices with {} messages",
+            subnet.subnet_id,
+            node,
+            metrics[SLICE_MESSAGES_COUNT][node],
+            metrics[SLICE_MESSAGES_SUM][node]
+        );
+        assert!(slices > 0, "Subnet {}: no slices", subnet.subnet_id);
+        assert_eq!(
+            metrics[&slice_payload_size_within_limit()][node],
+            slices,
+            "Subnet {}: slices larger than the block payload",
+            subnet.subnet_id
+        );
+        assert_eq!(
+            metrics[SLICE_COUNT_BYTES_INVALID][node], 0,
+            "Subnet {}: slices with miscounted byte sizes",
+            subnet.subnet_id
+        );
+        assert_eq!(
+            metrics[SLICE_COUNT_BYTES_FAILED][node], 0,
+            "Subnet {}: slices whose byte size could not be counted",
+            subnet.subnet_id
+        );
+        if is_nns {
+            assert_eq!(
+                metrics[&slice_messages_within_limit()][node],
+                metrics[SLICE_MESSAGES_COUNT][node],
+                "Subnet {}: slices with more than {} messages",
+                subnet.subnet_id,
+                SYSTEM_SUBNET_STREAM_MSG_LIMIT
+            );
+        }
+    }
+}
+
+/// Asserts that every node of `subnet` included XNet messages into blocks
+/// between the `halfway` and the `end` samples, i.e. XNet traffic did not
+/// stall under overload.
+fn assert_progress(
+    logger: &Logger,
+    subnet: &SubnetSnapshot,
+    halfway: &BTreeMap<String, Vec<u64>>,
+    end: &BTreeMap<String, Vec<u64>>,
+) {
+    for node in 0..subnet.nodes().count() {
+        let messages = end[SLICE_MESSAGES_SUM][node] - halfway[SLICE_MESSAGES_SUM][node];
+        let slices = end[SLICE_MESSAGES_COUNT][node] - halfway[SLICE_MESSAGES_COUNT][node];
+        info!(
+            logger,
+            "Subnet {}, node {}: {} slices with {} messages in the second half",
+            subnet.subnet_id,
+            node,
+            slices,
+            messages
+        );
+        assert!(
+            messages > 0,
+            "Subnet {}: XNet traffic stalled",
+            subnet.subnet_id
+        );
+    }
+}
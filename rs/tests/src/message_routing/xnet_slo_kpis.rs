@@ -0,0 +1,273 @@
+//! The KPIs computed by the XNet SLO test, exported as a JSON artifact so
+//! that runs can be compared with each other, e.g. to gate nightly runs on
+//! performance regressions against the KPIs of an earlier run.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::Path;
+use xnet_test::{LatencyDistribution, Metrics};
+
+/// The version of the format of [XNetSloKpis]. KPIs of different versions
+/// are not compared.
+pub const KPI_FORMAT_VERSION: u32 = 1;
+
+/// The name of the artifact written to the test environment.
+pub const KPI_ARTIFACT_NAME: &str = "xnet_slo_kpis.json";
+
+/// The KPIs of a run of the XNet SLO test, over all subnets, together with
+/// the parameters of the run they depend on.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct XNetSloKpis {
+    pub version: u32,
+    pub subnets: usize,
+    pub nodes_per_subnet: usize,
+    pub runtime_secs: u64,
+    pub payload_size_bytes: u64,
+    pub subnet_to_subnet_rate: usize,
+    /// Responses received per second.
+    pub throughput_responses_per_sec: f64,
+    /// Failed calls as a percentage of attempted calls, not counting the
+    /// rejects of injected faults.
+    pub error_percentage: f64,
+    pub mean_latency_millis: f64,
+    /// The latency percentiles, at the resolution of the latency histogram
+    /// of the canisters, i.e. the upper bound of the bucket the percentile
+    /// falls into. `None` if no responses were received.
+    pub p50_latency_millis: Option<i64>,
+    pub p90_latency_millis: Option<i64>,
+    pub p99_latency_millis: Option<i64>,
+}
+
+/// How much worse than a baseline the KPIs of a run may be.
+#[derive(Clone, Debug)]
+pub struct KpiTolerances {
+    /// The relative decrease of the throughput, e.g. 0.1 for 10%.
+    pub throughput_decrease: f64,
+    /// The increase of the error percentage, in percentage points.
+    pub error_percentage_increase: f64,
+    /// The relative increase of the mean latency and of the latency
+    /// percentiles.
+    pub latency_increase: f64,
+}
+
+impl Default for KpiTolerances {
+    fn default() -> Self {
+        Self {
+            throughput_decrease: 0.1,
+            error_percentage_increase: 1.0,
+            latency_increase: 0.25,
+        }
+    }
+}
+
+impl XNetSloKpis {
+    /// Computes the KPIs from the `metrics` of all canisters, merged over all
+    /// subnets.
+    pub fn new(
+        metrics: &Metrics,
+        subnets: usize,
+        nodes_per_subnet: usize,
+        runtime_secs: u64,
+        payload_size_bytes: u64,
+        subnet_to_subnet_rate: usize,
+        fault_injection: bool,
+    ) -> Self {
+        let responses = successful_responses(&metrics.latency_distribution);
+        let attempted_calls = metrics.requests_sent + metrics.call_errors;
+        let injected_rejects = if fault_injection {
+            metrics.canister_error_responses + metrics.canister_reject_responses
+        } else {
+            0
+        };
+        let failed_calls =
+            (metrics.call_errors + metrics.reject_responses).saturating_sub(injected_rejects);
+        Self {
+            version: KPI_FORMAT_VERSION,
+            subnets,
+            nodes_per_subnet,
+            runtime_secs,
+            payload_size_bytes,
+            subnet_to_subnet_rate,
+            throughput_responses_per_sec: (responses + metrics.reject_responses) as f64
+                / runtime_secs as f64,
+            error_percentage: if attempted_calls == 0 {
+                0.
+            } else {
+                100. * failed_calls as f64 / attempted_calls as f64
+            },
+            mean_latency_millis: if responses == 0 {
+                0.
+            } else {
+                metrics.latency_distribution.sum_millis() as f64 / responses as f64
+            },
+            p50_latency_millis: latency_percentile(&metrics.latency_distribution, 0.5),
+            p90_latency_millis: latency_percentile(&metrics.latency_distribution, 0.9),
+            p99_latency_millis: latency_percentile(&metrics.latency_distribution, 0.99),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Could not open: {:?}", path))?;
+        serde_json::from_reader(file).with_context(|| format!("{:?}: Could not read json.", path))
+    }
+
+    /// Returns the KPIs that are worse than those of `baseline` by more than
+    /// `tolerances`. Fails if the runs are not comparable.
+    pub fn regressions(
+        &self,
+        baseline: &XNetSloKpis,
+        tolerances: &KpiTolerances,
+    ) -> Result<Vec<String>> {
+        if self.version != baseline.version {
+            bail!(
+                "KPIs of version {} can't be compared to a baseline of version {}",
+                self.version,
+                baseline.version
+            );
+        }
+        let parameters = |kpis: &XNetSloKpis| {
+            (
+                kpis.subnets,
+                kpis.nodes_per_subnet,
+                kpis.runtime_secs,
+                kpis.payload_size_bytes,
+                kpis.subnet_to_subnet_rate,
+            )
+        };
+        if parameters(self) != parameters(baseline) {
+            bail!(
+                "The run can't be compared to a baseline with different parameters: {:?} vs {:?}",
+                parameters(self),
+                parameters(baseline)
+            );
+        }
+
+        let mut regressions = Vec::new();
+        let min_throughput =
+            baseline.throughput_responses_per_sec * (1. - tolerances.throughput_decrease);
+        if self.throughput_responses_per_sec < min_throughput {
+            regressions.push(format!(
+                "Throughput of {:.2} responses/s below {:.2} (baseline: {:.2})",
+                self.throughput_responses_per_sec,
+                min_throughput,
+                baseline.throughput_responses_per_sec
+            ));
+        }
+        let max_error_percentage = baseline.error_percentage + tolerances.error_percentage_increase;
+        if self.error_percentage > max_error_percentage {
+            regressions.push(format!(
+                "Error percentage of {:.2}% above {:.2}% (baseline: {:.2}%)",
+                self.error_percentage, max_error_percentage, baseline.error_percentage
+            ));
+        }
+        let max_latency = |baseline: f64| baseline * (1. + tolerances.latency_increase);
+        if self.mean_latency_millis > max_latency(baseline.mean_latency_millis) {
+            regressions.push(format!(
+                "Mean latency of {:.0}ms above {:.0}ms (baseline: {:.0}ms)",
+                self.mean_latency_millis,
+                max_latency(baseline.mean_latency_millis),
+                baseline.mean_latency_millis
+            ));
+        }
+        for (name, latency, baseline_latency) in [
+            ("p50", self.p50_latency_millis, baseline.p50_latency_millis),
+            ("p90", self.p90_latency_millis, baseline.p90_latency_millis),
+            ("p99", self.p99_latency_millis, baseline.p99_latency_millis),
+        ] {
+            if let (Some(latency), Some(baseline_latency)) = (latency, baseline_latency) {
+                if latency as f64 > max_latency(baseline_latency as f64) {
+                    regressions.push(format!(
+                        "{} latency of {}ms above {:.0}ms (baseline: {}ms)",
+                        name,
+                        latency,
+                        max_latency(baseline_latency as f64),
+                        baseline_latency
+                    ));
+                }
+            }
+        }
+        Ok(regressions)
+    }
+}
+
+/// The number of latencies observed, i.e. of successful responses.
+fn successful_responses(latencies: &LatencyDistribution) -> usize {
+    latencies.buckets().last().map_or(0, |(_, count)| *count)
+}
+
+/// The upper bound of the bucket of the cumulative histogram `latencies` the
+/// `percentile` (between 0 and 1) falls into.
+fn latency_percentile(latencies: &LatencyDistribution, percentile: f64) -> Option<i64> {
+    let total = successful_responses(latencies);
+    if total == 0 {
+        return None;
+    }
+    let rank = ((total as f64 * percentile).ceil() as usize).max(1);
+    latencies
+        .buckets()
+        .find(|(_, count)| *count >= rank)
+        .map(|(bound, _)| *bound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn kpis(latencies_millis: &[u64], call_errors: usize) -> XNetSloKpis {
+        let mut metrics = Metrics::default();
+        for latency in latencies_millis {
+            metrics
+                .latency_distribution
+                .observe(Duration::from_millis(*latency));
+        }
+        metrics.requests_sent = latencies_millis.len();
+        metrics.call_errors = call_errors;
+        XNetSloKpis::new(&metrics, 2, 4, 10, 1024, 10, false)
+    }
+
+    #[test]
+    fn computes_kpis() {
+        let latencies: Vec<u64> = (1..=100).map(|i| i * 30).collect();
+        let kpis = kpis(&latencies, 100);
+        assert_eq!(kpis.throughput_responses_per_sec, 10.);
+        assert_eq!(kpis.error_percentage, 50.);
+        assert_eq!(kpis.mean_latency_millis, 1515.);
+        assert_eq!(kpis.p50_latency_millis, Some(2000));
+        assert_eq!(kpis.p90_latency_millis, Some(5000));
+        assert_eq!(kpis.p99_latency_millis, Some(5000));
+        assert_eq!(self::kpis(&[], 0).p50_latency_millis, None);
+    }
+
+    #[test]
+    fn detects_regressions_beyond_tolerances() {
+        let baseline = kpis(&[1000; 100], 0);
+        let tolerances = KpiTolerances::default();
+        assert_eq!(
+            kpis(&[1000; 95], 0)
+                .regressions(&baseline, &tolerances)
+                .unwrap(),
+            Vec::<String>::new()
+        );
+
+        let regressions = kpis(&[3000; 50], 10)
+            .regressions(&baseline, &tolerances)
+            .unwrap();
+        assert_eq!(regressions.len(), 6, "{:?}", regressions);
+        for (regression, kpi) in regressions.iter().zip([
+            "Throughput",
+            "Error percentage",
+            "Mean latency",
+            "p50 latency",
+            "p90 latency",
+            "p99 latency",
+        ]) {
+            assert!(regression.starts_with(kpi), "{}", regression);
+        }
+
+        let mut other_runtime = baseline.clone();
+        other_runtime.runtime_secs += 1;
+        assert!(baseline.regressions(&other_runtime, &tolerances).is_err());
+    }
+}
@@ -12,7 +12,9 @@ Runbook::
 5. Collect metrics from all canisters (via query `metrics` call).
 6. Aggregate metrics for each subnet (over its canisters).
 7. Assert error_ratio < 5%, no seq_errors, send_rate >= 0.3, responses_received > threshold (calculated dynamically).
-8. Stop/delete all canisters and assert operations' success.
+8. Export the KPIs over all subnets (throughput, error ratio, latencies) as a JSON artifact and, if a
+   baseline is given, assert they are within the tolerances of the baseline KPIs.
+9. Stop/delete all canisters and assert operations' success.
 
 Success::
 1. Xnet canisters are successfully installed and started on each subnet.
@@ -23,7 +25,9 @@ If the NNS canisters are not deployed, the subnets will stop making progress aft
 
 end::catalog[] */
 
-use super::common::{install_canisters, parallel_async, start_all_canisters};
+use super::common::{grow_all_canisters, install_canisters, parallel_async, start_all_canisters};
+use super::xnet_slo_kpis::{KpiTolerances, XNetSloKpis, KPI_ARTIFACT_NAME};
+use crate::driver::canister_call_trace::HasCanisterCallTracer;
 use crate::driver::ic::{InternetComputer, Subnet};
 use crate::driver::pot_dsl::{PotSetupFn, SysTestFn};
 use crate::driver::test_env::TestEnv;
@@ -37,8 +41,9 @@ use futures::future::join_all;
 use ic_registry_subnet_type::SubnetType;
 use slog::info;
 use std::fmt::Display;
+use std::path::PathBuf;
 use std::time::Duration;
-use xnet_test::Metrics;
+use xnet_test::{FaultInjection, Metrics, PayloadSizeDistribution};
 
 // Constants for all xnet tests.
 const PAYLOAD_SIZE_BYTES: u64 = 1024;
@@ -57,12 +62,17 @@ pub struct Config {
     nodes_per_subnet: usize,
     runtime: Duration,
     payload_size_bytes: u64,
+    response_payload_size: Option<PayloadSizeDistribution>,
+    fault_injection: Option<FaultInjection>,
+    state_pages_per_canister: u64,
     send_rate_threshold: f64,
     error_percentage_threshold: f64,
     targeted_latency_seconds: u64,
     subnet_to_subnet_rate: usize,
     canisters_per_subnet: usize,
     canister_to_subnet_rate: usize,
+    kpi_baseline: Option<PathBuf>,
+    kpi_tolerances: KpiTolerances,
 }
 
 impl Config {
@@ -84,15 +94,58 @@ impl Config {
             nodes_per_subnet,
             runtime,
             payload_size_bytes: PAYLOAD_SIZE_BYTES,
+            response_payload_size: None,
+            fault_injection: None,
+            state_pages_per_canister: 0,
             send_rate_threshold: SEND_RATE_THRESHOLD,
             error_percentage_threshold: ERROR_PERCENTAGE_THRESHOLD,
             targeted_latency_seconds: TARGETED_LATENCY_SECONDS,
             subnet_to_subnet_rate,
             canisters_per_subnet,
             canister_to_subnet_rate,
+            kpi_baseline: None,
+            kpi_tolerances: KpiTolerances::default(),
         }
     }
 
+    /// Pads the responses to sizes drawn from `distribution` instead of to the
+    /// request payload size, in order to exercise the response direction of
+    /// the streams.
+    pub fn with_response_payload_size(mut self, distribution: PayloadSizeDistribution) -> Self {
+        self.response_payload_size = Some(distribution);
+        self
+    }
+
+    /// Asks the receiving canisters to trap on, reject or delay the reply to
+    /// the given percentages of requests. The resulting reject responses are
+    /// not counted as errors, but must not exceed the number of injected
+    /// faults.
+    pub fn with_fault_injection(mut self, fault_injection: FaultInjection) -> Self {
+        self.fault_injection = Some(fault_injection);
+        self
+    }
+
+    /// Grows the heap of every canister by `pages` WebAssembly pages (64 KiB
+    /// each) before starting the traffic, such that the subnets carry a large
+    /// state, e.g. to checkpoint and state sync, while under XNet load.
+    pub fn with_state_pages_per_canister(mut self, pages: u64) -> Self {
+        self.state_pages_per_canister = pages;
+        self
+    }
+
+    /// Fails the test if its KPIs are worse than those in the artifact at
+    /// `path`, written by an earlier run with the same parameters, by more
+    /// than the tolerances.
+    pub fn with_kpi_baseline(mut self, path: PathBuf) -> Self {
+        self.kpi_baseline = Some(path);
+        self
+    }
+
+    pub fn with_kpi_tolerances(mut self, tolerances: KpiTolerances) -> Self {
+        self.kpi_tolerances = tolerances;
+        self
+    }
+
     /// Builds the IC instance.
     pub fn build(self) -> impl PotSetupFn {
         move |env: TestEnv| setup(env, self)
@@ -154,7 +207,7 @@ pub async fn test_async(env: TestEnv, config: Config) {
     // Step 1: Install Xnet canisters on each subnet.
     info!(logger, "Installing Xnet canisters on subnets ...");
     let canisters = install_canisters(
-        env,
+        env.clone(),
         &endpoints_runtime,
         config.subnets,
         config.canisters_per_subnet,
@@ -169,12 +222,28 @@ pub async fn test_async(env: TestEnv, config: Config) {
         logger,
         "All {} canisters installed successfully.", canisters_count
     );
+    if config.state_pages_per_canister > 0 {
+        info!(
+            logger,
+            "Growing the state of all canisters by {} pages ...", config.state_pages_per_canister
+        );
+        grow_all_canisters(
+            &env.canister_call_tracer(),
+            &canisters,
+            config.state_pages_per_canister,
+        )
+        .await;
+    }
     // Step 2: Start all canisters (via update `start` call).
     info!(logger, "Calling start() on all canisters...");
     start_all_canisters(
+        &env.canister_call_tracer(),
         &canisters,
         config.payload_size_bytes,
         config.canister_to_subnet_rate as u64,
+        config.response_payload_size.clone(),
+        config.fault_injection.clone(),
+        &mut env.rng(),
     )
     .await;
     let msgs_per_round =
@@ -186,6 +255,14 @@ pub async fn test_async(env: TestEnv, config: Config) {
         config.payload_size_bytes,
         msgs_per_round * config.payload_size_bytes as usize
     );
+    if let Some(response_payload_size) = &config.response_payload_size {
+        info!(
+            logger,
+            "Responses padded to {:?}, mean {} bytes/round",
+            response_payload_size,
+            msgs_per_round * response_payload_size.mean() as usize
+        );
+    }
     // Step 3: Wait for canisters to exchange messages.
     info!(
         logger,
@@ -242,8 +319,29 @@ pub async fn test_async(env: TestEnv, config: Config) {
 
     for (i, m) in aggregated_metrics.iter().enumerate() {
         let attempted_calls = m.requests_sent + m.call_errors;
+        if config.fault_injection.is_some() {
+            expect(
+                m.canister_error_responses <= m.traps_injected,
+                i,
+                "Canister error responses within injected traps",
+                "More canister error responses than injected traps",
+                &format!("{}/{}", m.canister_error_responses, m.traps_injected),
+            );
+            expect(
+                m.canister_reject_responses <= m.rejects_injected,
+                i,
+                "Canister reject responses within injected rejects",
+                "More canister reject responses than injected rejects",
+                &format!("{}/{}", m.canister_reject_responses, m.rejects_injected),
+            );
+        }
+        let injected_rejects = if config.fault_injection.is_some() {
+            m.canister_error_responses + m.canister_reject_responses
+        } else {
+            0
+        };
         if attempted_calls != 0 {
-            let failed_calls = m.call_errors + m.reject_responses;
+            let failed_calls = m.call_errors + m.reject_responses - injected_rejects;
             let error_percentage = 100. * failed_calls as f64 / attempted_calls as f64;
             expect(
                 error_percentage < config.error_percentage_threshold,
@@ -327,8 +425,45 @@ pub async fn test_async(env: TestEnv, config: Config) {
             );
         }
     }
+    // Step 8: Export the KPIs and compare them to the baseline.
+    let mut merged_metrics = Metrics::default();
+    aggregated_metrics
+        .iter()
+        .for_each(|m| merged_metrics.merge(m));
+    let kpis = XNetSloKpis::new(
+        &merged_metrics,
+        config.subnets,
+        config.nodes_per_subnet,
+        config.runtime.as_secs(),
+        config.payload_size_bytes,
+        config.subnet_to_subnet_rate,
+        config.fault_injection.is_some(),
+    );
+    info!(logger, "KPIs: {:?}", kpis);
+    env.write_json_object(KPI_ARTIFACT_NAME, &kpis)
+        .expect("Failed to write the KPIs");
+    if let Some(baseline_path) = &config.kpi_baseline {
+        let regressions = XNetSloKpis::load(baseline_path)
+            .and_then(|baseline| kpis.regressions(&baseline, &config.kpi_tolerances));
+        match regressions {
+            Ok(regressions) => {
+                for regression in &regressions {
+                    info!(logger, "Failure: KPI regression: {}", regression);
+                }
+                success &= regressions.is_empty();
+            }
+            Err(e) => {
+                info!(
+                    logger,
+                    "Failure: KPIs not comparable to the baseline: {:?}", e
+                );
+                success = false;
+            }
+        }
+    }
+
     info!(logger, "Stop/delete all canisters...");
-    // Step 8: Stop all canisters.
+    // Step 9: Stop all canisters.
     let _: Vec<_> = parallel_async(
         canisters.iter().flatten(),
         |canister| {
@@ -341,7 +476,7 @@ pub async fn test_async(env: TestEnv, config: Config) {
     )
     .await;
 
-    // Step 9: Delete all canisters.
+    // Step 10: Delete all canisters.
     let _: Vec<_> = parallel_async(
         canisters.iter().flatten(),
         |canister| {
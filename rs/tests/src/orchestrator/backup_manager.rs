@@ -42,19 +42,23 @@ use crate::{
     },
     util::{block_on, get_nns_node},
 };
-use ic_backup::config::{ColdStorage, Config, SubnetConfig};
-use ic_backup::util::sleep_secs;
+use ic_backup::config::{
+    ColdStorage, Config, IpPreference, LogRotation, ReplayBudget, SubnetConfig,
+};
+use ic_backup_spool::{SubnetSpool, VersionSpool};
 use ic_base_types::SubnetId;
 use ic_recovery::file_sync_helper::{download_binary, write_file};
 use ic_registry_subnet_type::SubnetType;
 use ic_types::{Height, ReplicaVersion};
 use slog::{error, info, Logger};
+use std::collections::BTreeMap;
 use std::ffi::OsStr;
 use std::fs::{self, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::time::Duration;
 
 const DKG_INTERVAL: u64 = 9;
 const SUBNET_SIZE: usize = 4;
@@ -175,10 +179,16 @@ pub fn test(env: TestEnv) {
         replay_period_secs: 30,
         thread_id: 0,
         disable_cold_storage: false,
+        replay_sharding: None,
+        replay_budget: ReplayBudget::default(),
     };
     let cold_storage = Some(ColdStorage {
         cold_storage_dir: cold_storage_dir.clone(),
         versions_hot: 1,
+        verification_period_hours: 0,
+        verification_replay: false,
+        artifacts_chunk_size_mb: None,
+        artifacts_compression: Default::default(),
     });
     let config = Config {
         version: 1,
@@ -186,15 +196,22 @@ pub fn test(env: TestEnv) {
         metrics_urls: vec![],
         network_name: "testnet".to_string(),
         backup_instance: "backup_test_node".to_string(),
-        nns_url: Some(nns_node.get_public_url()),
+        nns_url: None,
+        nns_urls: vec![nns_node.get_public_url()],
         nns_pem: nns_public_key,
         root_dir: backup_dir.clone(),
         excluded_dirs: vec![],
         ssh_private_key: private_key_path,
         disk_threshold_warn: 75,
-        slack_token: "NO_TOKEN_IN_TESTING".to_string(),
+        slack_token: "NO_TOKEN_IN_TESTING".to_string().into(),
         cold_storage,
         blacklisted_nodes: None,
+        ip_preference: IpPreference::Ipv6,
+        node_address_overrides: BTreeMap::new(),
+        spool_stall_alert_mins: None,
+        cup_wait_timeout_mins: None,
+        log_rotation: LogRotation::default(),
+        verify_cups: true,
         subnets: vec![subnet],
     };
     let config_str =
@@ -239,16 +256,9 @@ pub fn test(env: TestEnv) {
         .join("data")
         .join(subnet_id.to_string())
         .join("ic_state/checkpoints");
-    let orig_spool_dir = backup_dir
-        .join("spool")
-        .join(subnet_id.to_string())
-        .join(replica_version)
-        .join("0");
-    let new_spool_dir = backup_dir
-        .join("spool")
-        .join(subnet_id.to_string())
-        .join(mainnet_version)
-        .join("0");
+    let subnet_spool = SubnetSpool::new(backup_dir.join("spool").join(subnet_id.to_string()));
+    let orig_spool = VersionSpool::new(subnet_spool.path().join(&replica_version));
+    let new_spool = VersionSpool::new(subnet_spool.path().join(&mainnet_version));
     let archive_dir = backup_dir.join("archive").join(subnet_id.to_string());
 
     info!(
@@ -256,8 +266,8 @@ pub fn test(env: TestEnv) {
         "Wait for backup and archive of a new version checkpoint"
     );
     loop {
-        let old_height = highest_dir_entry(&orig_spool_dir, 10);
-        let new_height = highest_dir_entry(&new_spool_dir, 10);
+        let old_height = orig_spool.top_height().get();
+        let new_height = new_spool.top_height().get();
         let good_progress = old_height + 3 * (DKG_INTERVAL + 1);
         let checkpoint = highest_dir_entry(&checkpoint_dir, 16);
         let archive_height = highest_dir_entry(&archive_dir, 10);
@@ -273,7 +283,7 @@ pub fn test(env: TestEnv) {
             info!(log, "New version was sucessfully backed up and archived");
             break;
         }
-        sleep_secs(5);
+        std::thread::sleep(Duration::from_secs(5));
     }
 
     info!(
@@ -330,7 +340,7 @@ pub fn test(env: TestEnv) {
         if hash_mismatch {
             break;
         }
-        sleep_secs(10);
+        std::thread::sleep(Duration::from_secs(10));
     }
     assert!(hash_mismatch);
     info!(log, "There was a divergence of the state");
@@ -380,7 +390,7 @@ fn cold_storage_exists(cold_storage_dir: PathBuf) -> bool {
         {
             return true;
         }
-        sleep_secs(10);
+        std::thread::sleep(Duration::from_secs(10));
     }
     false
 }
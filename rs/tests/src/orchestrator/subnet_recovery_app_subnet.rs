@@ -217,6 +217,8 @@ pub fn app_subnet_recovery_test(env: TestEnv, upgrade: bool, ecdsa: bool) {
         replica_version: Some(master_version.clone()),
         key_file: Some(ssh_authorized_priv_keys_dir.join(SSH_USERNAME)),
         test_mode: true,
+        download_parallelism: None,
+        non_interactive: false,
     };
 
     let mut unassigned_nodes = env.topology_snapshot().unassigned_nodes();
@@ -250,6 +252,7 @@ pub fn app_subnet_recovery_test(env: TestEnv, upgrade: bool, ecdsa: bool) {
         replacement_nodes: Some(unassigned_nodes_ids.clone()),
         pub_key: Some(pub_key),
         download_node: None,
+        backup_source: None,
         upload_node: Some(upload_node.get_ip_addr()),
         ecdsa_subnet_id: ecdsa.then_some(root_subnet_id),
         next_step: None,
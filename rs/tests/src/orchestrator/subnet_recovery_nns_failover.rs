@@ -159,6 +159,8 @@ pub fn test(env: TestEnv) {
         replica_version: Some(ic_version.clone()),
         key_file: Some(ssh_authorized_priv_keys_dir.join(SSH_USERNAME)),
         test_mode: true,
+        download_parallelism: None,
+        non_interactive: false,
     };
     let subnet_args = NNSRecoveryFailoverNodesArgs {
         subnet_id: topo_broken_ic.root_subnet_id(),
@@ -111,6 +111,8 @@ pub fn test(env: TestEnv) {
         replica_version: Some(ic_version),
         key_file: Some(ssh_authorized_priv_keys_dir.join(SSH_USERNAME)),
         test_mode: true,
+        download_parallelism: None,
+        non_interactive: false,
     };
 
     // unlike during a production recovery using the CLI, here we already know all of parameters
@@ -1,5 +1,6 @@
 /// SSH Key Utilities
 use crate::{
+    driver::ssh_keys::SshKeyPair,
     nns::{
         get_governance_canister, submit_external_proposal_with_test_id,
         vote_execute_proposal_assert_executed, vote_execute_proposal_assert_failed,
@@ -10,10 +11,6 @@ use crate::{
 use ic_nns_constants::REGISTRY_CANISTER_ID;
 use ic_nns_governance::pb::v1::NnsFunction;
 use ic_types::{time::current_time, SubnetId};
-use openssh_keys::PublicKey;
-use openssl::pkey::Private;
-use openssl::rsa::Rsa;
-use pem::{encode, Pem};
 use registry_canister::mutations::do_update_subnet::UpdateSubnetPayload;
 use registry_canister::mutations::do_update_unassigned_nodes_config::UpdateUnassignedNodesConfigPayload;
 use reqwest::Url;
@@ -23,30 +20,8 @@ use std::net::{IpAddr, TcpStream};
 use std::time::Duration;
 
 pub(crate) fn generate_key_strings() -> (String, String) {
-    // Our keys are Ed25519, and not RSA. Once we figure out a direct way to encode
-    // an Ed25519 private key the SSH way, we might consider switching to it.
-    let rsa = Rsa::generate(1024).unwrap();
-    let e = rsa.e();
-    let n = rsa.n();
-
-    let private_key = private_key_to_pem_string(&rsa);
-    let public_key = public_key_to_string(e.to_vec(), n.to_vec());
-    (private_key, public_key)
-}
-
-fn private_key_to_pem_string(rsa: &Rsa<Private>) -> String {
-    let private_key = rsa.private_key_to_der().unwrap();
-    let private_pem = Pem {
-        tag: String::from("RSA PRIVATE KEY"),
-        contents: private_key,
-    };
-    encode(&private_pem)
-}
-
-fn public_key_to_string(e: Vec<u8>, n: Vec<u8>) -> String {
-    let mut key = PublicKey::from_rsa(e, n);
-    key.set_comment("ci@ci.ci");
-    key.to_string()
+    let key = SshKeyPair::generate("ci");
+    (key.private_key, key.public_key)
 }
 
 pub(crate) enum AuthMean {
@@ -390,6 +390,10 @@ fn open_sns_token_swap_payload_for_tests(sns_swap_canister_id: PrincipalId) -> O
                 dissolve_delay_interval_seconds: 7_889_400,
             }),
             sale_delay_seconds: None,
+            early_participation_bonus: None,
+            max_direct_participants: None,
+            dutch_auction: None,
+            pro_rata_cutback: None,
         }),
         community_fund_investment_e8s: Some(333_333 * E8),
     }
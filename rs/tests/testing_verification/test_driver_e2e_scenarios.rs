@@ -1,5 +1,6 @@
 use anyhow::Result;
 use ic_tests::driver::group::{SystemTestGroup, SystemTestSubGroup};
+use ic_tests::driver::namespace::HasTestNamespace;
 use ic_tests::driver::test_env::TestEnv;
 use ic_tests::systest;
 use slog::info;
@@ -151,6 +152,31 @@ fn get_all_e2e_test_scenarios() -> HashMap<String, SystemTestGroup> {
                 )
                 .without_farm(),
         ),
+        (
+            "test_that_runs_2_isolated_parallel_tasks".to_string(),
+            SystemTestGroup::new()
+                .with_setup(setup_to_succeed)
+                .add_isolated_parallel(
+                    SystemTestSubGroup::new()
+                        .add_test(systest!(test_to_succeed_in_namespace))
+                        .add_test(systest!(test_to_succeed_in_namespace_2)),
+                )
+                .without_farm(),
+        ),
+        (
+            "test_with_retries_passing_on_second_attempt".to_string(),
+            SystemTestGroup::new()
+                .with_setup(setup_to_succeed)
+                .add_test_with_retries(systest!(test_to_fail_on_first_attempt), 2)
+                .without_farm(),
+        ),
+        (
+            "test_with_retries_exhausted".to_string(),
+            SystemTestGroup::new()
+                .with_setup(setup_to_succeed)
+                .add_test_with_retries(systest!(test_to_fail), 2)
+                .without_farm(),
+        ),
         (
             "test_group_timeout_in_test_task".to_string(),
             SystemTestGroup::new()
@@ -213,10 +239,43 @@ fn test_to_fail_1sec(_: TestEnv) {
     panic!("this test panics after 1 seconds");
 }
 
+fn test_to_succeed_in_namespace(env: TestEnv) {
+    let namespace = env.namespace().expect("the test is not isolated");
+    assert_eq!(namespace.count, 2);
+    // Each namespace is claimed once, next to the test environments.
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(
+            env.base_path()
+                .parent()
+                .unwrap()
+                .join(format!("namespace_{}", namespace.index)),
+        )
+        .unwrap();
+}
+
+fn test_to_succeed_in_namespace_2(env: TestEnv) {
+    test_to_succeed_in_namespace(env)
+}
+
 fn test_to_fail(_: TestEnv) {
     panic!("this test panics");
 }
 
+fn test_to_fail_on_first_attempt(env: TestEnv) {
+    // Attempts run in fresh test environments, so the marker is kept next to them.
+    let marker = env
+        .base_path()
+        .parent()
+        .unwrap()
+        .join("first_attempt_failed");
+    if !marker.exists() {
+        std::fs::write(&marker, "").unwrap();
+        panic!("this test panics on the first attempt");
+    }
+}
+
 fn never_ending_task(env: TestEnv) {
     info!(
         env.logger(),
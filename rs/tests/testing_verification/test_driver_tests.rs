@@ -1,4 +1,5 @@
 use ic_tests::driver::{
+    constants::TESTS_DIR,
     report::{SystemGroupSummary, TaskReport},
     test_env_api::FarmBaseUrl,
 };
@@ -531,3 +532,96 @@ fn test_setup_failure_file_not_written() {
     let summary = extract_report(result.stderr).expect("Failed to extract report from logs.");
     assert!(summary.failure.len() == 2);
 }
+
+#[test]
+fn test_that_runs_2_isolated_parallel_tasks() {
+    let working_dir = create_unique_working_dir();
+    let binary_path = env::current_dir().unwrap().join(BINARY_PATH);
+    let mut cmd = Command::new(binary_path);
+    cmd.env(
+        "TEST_SCENARIO_NAME",
+        "test_that_runs_2_isolated_parallel_tasks",
+    )
+    .args(["--working-dir", working_dir.to_str().unwrap(), "run"]);
+    let result = cmd.output().expect("failed to execute process");
+    assert!(
+        result.status.success(),
+        "{}",
+        String::from_utf8_lossy(&result.stderr)
+    );
+    let mut summary = extract_report(result.stderr).expect("Failed to extract report from logs.");
+    assert_test_summary_size(
+        &summary, /* successes */ 3, /* failures */ 0, /* skipped */ 0,
+    );
+    // parallel sets of tasks are not ordered, so we first sort them for assertion
+    summary.success[1..=2].sort_by(|t1, t2| t1.name.cmp(&t2.name));
+    assert_name_and_message_eq(&summary.success[0], "setup", SUCCESS);
+    assert_name_and_message_eq(&summary.success[1], "test_to_succeed_in_namespace", SUCCESS);
+    assert_name_and_message_eq(
+        &summary.success[2],
+        "test_to_succeed_in_namespace_2",
+        SUCCESS,
+    );
+    // Each test ran in a namespace of its own.
+    let tests_dir = working_dir.join(TESTS_DIR);
+    assert!(tests_dir.join("namespace_0").exists());
+    assert!(tests_dir.join("namespace_1").exists());
+}
+
+#[test]
+fn test_with_retries_passing_on_second_attempt() {
+    let result =
+        execute_test_scenario_with_default_cmd("test_with_retries_passing_on_second_attempt");
+    assert!(
+        result.status.success(),
+        "{}",
+        String::from_utf8_lossy(&result.stderr)
+    );
+    let summary = extract_report(result.stderr).expect("Failed to extract report from logs.");
+    assert_test_summary_size(
+        &summary, /* successes */ 2, /* failures */ 0, /* skipped */ 0,
+    );
+    assert_name_and_message_eq(&summary.success[0], "setup", SUCCESS);
+    assert_name_and_message_eq(
+        &summary.success[1],
+        "test_to_fail_on_first_attempt",
+        Some("Exited with code 0. Passed on attempt 2 of 3."),
+    );
+    assert_eq!(summary.retried.len(), 1);
+    assert_name_and_message_eq(
+        &summary.retried[0],
+        "test_to_fail_on_first_attempt (attempt 1/3)",
+        Some("this test panics on the first attempt"),
+    );
+}
+
+#[test]
+fn test_with_retries_exhausted() {
+    let result = execute_test_scenario_with_default_cmd("test_with_retries_exhausted");
+    assert!(
+        !result.status.success(),
+        "{}",
+        String::from_utf8_lossy(&result.stderr)
+    );
+    let summary = extract_report(result.stderr).expect("Failed to extract report from logs.");
+    assert_test_summary_size(
+        &summary, /* successes */ 1, /* failures */ 1, /* skipped */ 0,
+    );
+    assert_name_and_message_eq(&summary.success[0], "setup", SUCCESS);
+    assert_name_and_message_eq(
+        &summary.failure[0],
+        "test_to_fail",
+        Some("this test panics"),
+    );
+    assert_eq!(summary.retried.len(), 2);
+    assert_name_and_message_eq(
+        &summary.retried[0],
+        "test_to_fail (attempt 1/3)",
+        Some("this test panics"),
+    );
+    assert_name_and_message_eq(
+        &summary.retried[1],
+        "test_to_fail (attempt 2/3)",
+        Some("this test panics"),
+    );
+}